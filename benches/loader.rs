@@ -0,0 +1,49 @@
+//! Loading of large generated systems
+//!
+//! Compares serial parsing with [`loader::parse_sources`], which uses a
+//! thread pool when the `parallel` feature is enabled:
+//!
+//! ```bash
+//! cargo bench --bench loader
+//! cargo bench --bench loader --no-default-features   # serial baseline
+//! ```
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use martial_lang::loader::{self, SourceFile};
+use martial_lang::synthetic::{self, SyntheticOptions};
+use std::hint::black_box;
+
+/// A system whose sequences are spread over `files` files
+fn generate(files: usize) -> Vec<SourceFile> {
+    let options = SyntheticOptions {
+        states: 2000,
+        sequences: files * 40,
+        sequences_per_file: 40,
+        ..SyntheticOptions::default()
+    };
+    synthetic::generate("bench", &options)
+}
+
+fn parsing(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse");
+    for files in [10, 100, 400] {
+        let sources = generate(files);
+        group.bench_with_input(BenchmarkId::new("serial", files), &sources, |b, sources| {
+            b.iter(|| sources.iter().map(loader::parse_source).collect::<Vec<_>>())
+        });
+        group.bench_with_input(BenchmarkId::new("parse_sources", files), &sources, |b, sources| {
+            b.iter(|| loader::parse_sources(black_box(sources)))
+        });
+    }
+    group.finish();
+}
+
+fn loading(c: &mut Criterion) {
+    let sources = generate(100);
+    c.bench_function("load_sources/100", |b| {
+        b.iter(|| loader::load_sources("bench", black_box(&sources)).unwrap())
+    });
+}
+
+criterion_group!(benches, parsing, loading);
+criterion_main!(benches);
@@ -0,0 +1,106 @@
+//! Stages of the pipeline on a synthetic system
+//!
+//! Each stage is measured on its own, from lexing to graph analyses, on
+//! systems from [`martial_lang::synthetic`]:
+//!
+//! ```bash
+//! cargo bench --bench pipeline
+//! ```
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use martial_lang::graph::MartialGraph;
+use martial_lang::lexer::Lexer;
+use martial_lang::loader::{self, SourceFile};
+use martial_lang::parser::Parser;
+use martial_lang::synthetic::{self, SyntheticOptions};
+use std::hint::black_box;
+
+/// Sizes as (states, sequences)
+const SIZES: [(usize, usize); 3] = [(100, 500), (1000, 5000), (5000, 25000)];
+
+fn system(states: usize, sequences: usize) -> Vec<SourceFile> {
+    let options = SyntheticOptions {
+        states,
+        sequences,
+        ..SyntheticOptions::default()
+    };
+    synthetic::generate("bench", &options)
+}
+
+fn label(states: usize, sequences: usize) -> String {
+    format!("{}x{}", states, sequences)
+}
+
+fn lexing(c: &mut Criterion) {
+    let mut group = c.benchmark_group("lex");
+    for (states, sequences) in SIZES {
+        let sources = system(states, sequences);
+        group.bench_with_input(BenchmarkId::from_parameter(label(states, sequences)), &sources, |b, sources| {
+            b.iter(|| {
+                for source in sources {
+                    black_box(Lexer::new(&source.content).tokenize().unwrap());
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+fn parsing(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse");
+    for (states, sequences) in SIZES {
+        let sources = system(states, sequences);
+        let tokens: Vec<_> = sources
+            .iter()
+            .map(|s| Lexer::new(&s.content).tokenize().unwrap())
+            .collect();
+        group.bench_with_input(BenchmarkId::from_parameter(label(states, sequences)), &tokens, |b, tokens| {
+            b.iter(|| {
+                for file in tokens {
+                    black_box(Parser::new(file.clone()).parse().unwrap());
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+fn validation(c: &mut Criterion) {
+    let mut group = c.benchmark_group("validate");
+    for (states, sequences) in SIZES {
+        let sources = system(states, sequences);
+        let files: Vec<_> = sources.iter().map(|s| loader::parse_source(s).unwrap()).collect();
+        group.bench_with_input(BenchmarkId::from_parameter(label(states, sequences)), &files, |b, files| {
+            b.iter(|| loader::validate_files("bench", black_box(files)).unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn graph(c: &mut Criterion) {
+    let mut group = c.benchmark_group("graph");
+    for (states, sequences) in SIZES {
+        let system = loader::load_sources("bench", &system(states, sequences)).unwrap();
+        let graph = MartialGraph::from_system(&system);
+        let id = label(states, sequences);
+        group.bench_with_input(BenchmarkId::new("build", &id), &system, |b, system| {
+            b.iter(|| MartialGraph::from_system(black_box(system)))
+        });
+        group.bench_with_input(BenchmarkId::new("statistics", &id), &graph, |b, graph| {
+            b.iter(|| black_box(graph).statistics())
+        });
+        group.bench_with_input(BenchmarkId::new("depths", &id), &graph, |b, graph| {
+            b.iter(|| black_box(graph).depths().len())
+        });
+        group.bench_with_input(BenchmarkId::new("equivalent_states", &id), &graph, |b, graph| {
+            b.iter(|| black_box(graph).equivalent_states())
+        });
+        group.bench_with_input(BenchmarkId::new("merge_parallel_edges", &id), &graph, |b, graph| {
+            b.iter(|| black_box(graph).merge_parallel_edges())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, lexing, parsing, validation, graph);
+criterion_main!(benches);
@@ -0,0 +1,16 @@
+//! Tokenizing arbitrary text must return tokens or an error, never panic
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use martial_lang::lexer::{Lexer, Token};
+
+fuzz_target!(|input: &str| {
+    if let Ok(tokens) = Lexer::new(input).tokenize() {
+        assert_eq!(tokens.last().map(|t| &t.token), Some(&Token::Eof));
+        for token in &tokens {
+            // Spans always fall on character boundaries of the input
+            let _ = token.span.text(input);
+        }
+    }
+});
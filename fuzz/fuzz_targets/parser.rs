@@ -0,0 +1,10 @@
+//! Parsing arbitrary text, front matter included, must never panic
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use martial_lang::loader::{self, SourceFile};
+
+fuzz_target!(|input: &str| {
+    let _ = loader::parse_source(&SourceFile::new("fuzz.martial", input));
+});
@@ -0,0 +1,28 @@
+/* C API for martial-lang, built with
+ * `cargo rustc --release --lib --features ffi --crate-type cdylib` (or staticlib) */
+
+#ifndef MARTIAL_H
+#define MARTIAL_H
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/*
+ * Inputs are UTF-8 JSON arrays of { "path": ..., "content": ... } files.
+ * Returned strings must be released with martial_free_string.
+ */
+
+/* JSON array of diagnostics ("[]" when valid), or NULL on invalid input */
+char *martial_validate(const char *files_json);
+
+/* Graph JSON, or NULL if the input is invalid or the system does not validate */
+char *martial_graph_json(const char *files_json);
+
+void martial_free_string(char *s);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* MARTIAL_H */
@@ -0,0 +1,332 @@
+//! Findings drawn onto the graph
+//!
+//! Reviewing a system from a log of warnings means finding each position
+//! in a drawing by hand. [`Annotations`] attach findings to the positions
+//! they are about instead, and draw them as fill colors and tooltips in
+//! DOT, or in a standalone HTML page with an SVG drawing:
+//!
+//! - unreachable positions, which no transition leads to;
+//! - dead ends, which transitions lead to and none leaves, unless their
+//!   state is `@final`;
+//! - broken symmetry, where a state is used in some of the roles it
+//!   declares and never in the others.
+//!
+//! Positions no sequence uses only appear in a graph with its declared
+//! nodes, see [`MartialGraph::with_declared_nodes`].
+
+use crate::export::escape;
+use crate::graph::{MartialGraph, Node};
+use crate::prelude::*;
+use crate::semantic::MartialSystem;
+use alloc::collections::{BTreeMap, BTreeSet};
+use core::fmt::{self, Write};
+use serde::Serialize;
+
+/// Width of a character of a node label in the HTML drawing
+const CHAR_WIDTH: usize = 7;
+const BOX_HEIGHT: usize = 30;
+const COLUMN_GAP: usize = 90;
+const ROW_GAP: usize = 25;
+
+/// What is wrong with a position
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FindingKind {
+    Unreachable,
+    DeadEnd,
+    BrokenSymmetry,
+}
+
+impl FindingKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            FindingKind::Unreachable => "unreachable",
+            FindingKind::DeadEnd => "dead-end",
+            FindingKind::BrokenSymmetry => "broken-symmetry",
+        }
+    }
+
+    /// Fill color of the positions with the finding
+    pub fn fill(self) -> &'static str {
+        match self {
+            FindingKind::Unreachable => "#d9d9d9",
+            FindingKind::DeadEnd => "#f4b6b6",
+            FindingKind::BrokenSymmetry => "#f7e6a1",
+        }
+    }
+
+    /// Border color of the positions with the finding, and of the
+    /// transitions leading to a dead end
+    pub fn stroke(self) -> &'static str {
+        match self {
+            FindingKind::Unreachable => "#7f7f7f",
+            FindingKind::DeadEnd => "#c0392b",
+            FindingKind::BrokenSymmetry => "#b08900",
+        }
+    }
+}
+
+/// A finding about a position
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Finding {
+    pub kind: FindingKind,
+    /// Id of the position
+    pub node: String,
+    pub message: String,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.node, self.message)
+    }
+}
+
+/// Findings of a graph, by position
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Annotations {
+    /// Sorted by position, then kind
+    pub findings: Vec<Finding>,
+}
+
+impl Annotations {
+    /// The findings about the positions of `graph`, built from `system`
+    pub fn new(system: &MartialSystem, graph: &MartialGraph) -> Self {
+        let mut findings = Vec::new();
+        for node in graph.find_unreachable_nodes() {
+            findings.push(Finding {
+                kind: FindingKind::Unreachable,
+                node: node.id(),
+                message: "No transition leads here".to_string(),
+            });
+        }
+        let is_final = |node: &Node| system.states.get(node.state.as_str()).is_some_and(|state| state.is_final());
+        for node in graph.statistics().sink_nodes.into_iter().filter(|node| !is_final(node)) {
+            findings.push(Finding {
+                kind: FindingKind::DeadEnd,
+                node: node.id(),
+                message: "No transition leaves here and the state is not @final".to_string(),
+            });
+        }
+
+        let used: BTreeSet<&Node> = graph.edges.iter().flat_map(|edge| [&edge.from, &edge.to]).collect();
+        for state in system.states.values() {
+            let Some(roles) = state.allowed_roles.as_ref().filter(|roles| roles.len() > 1) else {
+                continue;
+            };
+            let (used_roles, unused_roles): (Vec<&String>, Vec<&String>) =
+                roles.iter().partition(|role| used.contains(&Node::new(state.name.as_str(), role.as_str())));
+            if used_roles.is_empty() || unused_roles.is_empty() {
+                continue;
+            }
+            let unused: Vec<String> = unused_roles.iter().map(|role| format!("{}[{}]", state.name, role)).collect();
+            for role in used_roles {
+                findings.push(Finding {
+                    kind: FindingKind::BrokenSymmetry,
+                    node: format!("{}[{}]", state.name, role),
+                    message: format!("{} is never used", unused.join(", ")),
+                });
+            }
+        }
+        findings.sort_by(|a, b| (&a.node, a.kind).cmp(&(&b.node, b.kind)));
+        Annotations { findings }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.findings.is_empty()
+    }
+
+    /// Findings about `node`
+    pub fn of(&self, node: &Node) -> Vec<&Finding> {
+        let id = node.id();
+        self.findings.iter().filter(|finding| finding.node == id).collect()
+    }
+
+    /// The most severe finding about `node`, which colors it
+    fn kind_of(&self, node: &Node) -> Option<FindingKind> {
+        self.of(node).into_iter().map(|finding| finding.kind).min()
+    }
+
+    /// Export as JSON
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string_pretty(self)
+    }
+
+    /// `graph` as DOT, see [`MartialGraph::to_dot`], with the positions that
+    /// have findings filled in the color of the most severe and listing them
+    /// in their tooltip, and the transitions leading to a dead end colored
+    pub fn to_dot(&self, graph: &MartialGraph) -> String {
+        graph.to_dot_styled(
+            |node| {
+                let Some(kind) = self.kind_of(node) else {
+                    return String::new();
+                };
+                let lines: Vec<String> = self
+                    .of(node)
+                    .into_iter()
+                    .map(|finding| format!("{}: {}", finding.kind.name(), finding.message))
+                    .chain(node.attributes.iter().map(|(key, value)| format!("{}: {}", key, value)))
+                    .map(|line| line.replace('\\', "\\\\").replace('"', "\\\""))
+                    .collect();
+                format!(
+                    "style=\"rounded,filled\", fillcolor=\"{}\", color=\"{}\", tooltip=\"{}\"",
+                    kind.fill(),
+                    kind.stroke(),
+                    lines.join("\\n")
+                )
+            },
+            |edge| {
+                let mut attributes = crate::graph::dot_edge_attributes(edge);
+                if !edge.failure && self.kind_of(&edge.to) == Some(FindingKind::DeadEnd) {
+                    attributes.push_str(&format!(", color=\"{}\"", FindingKind::DeadEnd.stroke()));
+                }
+                attributes
+            },
+        )
+    }
+
+    /// `graph` as a standalone HTML page: an SVG drawing with a column per
+    /// number of actions from an initial position, as in the TikZ export,
+    /// positions colored as in [`to_dot`](Self::to_dot) with their
+    /// findings as tooltips, then the list of findings
+    pub fn to_html(&self, graph: &MartialGraph) -> String {
+        let depths: BTreeMap<&Node, usize> = graph.depths().into_iter().collect();
+        let unreached = depths.values().max().map_or(0, |depth| depth + 1);
+        let width = graph.nodes.iter().map(|node| node.id().chars().count()).max().unwrap_or(0) * CHAR_WIDTH + 20;
+        let mut rows: BTreeMap<usize, usize> = BTreeMap::new();
+        let mut placed: BTreeMap<&Node, (usize, usize)> = BTreeMap::new();
+        for node in &graph.nodes {
+            let column = depths.get(node).copied().unwrap_or(unreached);
+            let row = rows.entry(column).or_insert(0);
+            placed.insert(node, (10 + column * (width + COLUMN_GAP), 10 + *row * (BOX_HEIGHT + ROW_GAP)));
+            *row += 1;
+        }
+
+        let mut content = String::new();
+        for edge in &graph.edges {
+            let (Some(&(x1, y1)), Some(&(x2, y2))) = (placed.get(&edge.from), placed.get(&edge.to)) else {
+                continue;
+            };
+            let color = if edge.failure {
+                "#c0392b"
+            } else if self.kind_of(&edge.to) == Some(FindingKind::DeadEnd) {
+                FindingKind::DeadEnd.stroke()
+            } else {
+                "#333"
+            };
+            let dash = if edge.reaction { " stroke-dasharray=\"4 3\"" } else { "" };
+            let sequences = if edge.sequences.is_empty() { edge.sequence.to_string() } else { edge.sequences.join(", ") };
+            let (x1, y1, x2, y2) = (x1 + width, y1 + BOX_HEIGHT / 2, x2, y2 + BOX_HEIGHT / 2);
+            let _ = writeln!(
+                content,
+                "<g><title>{} ({})</title><line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\" stroke=\"{}\"{} marker-end=\"url(#arrow)\"/>\
+                 <text x=\"{}\" y=\"{}\" font-size=\"11\">{}</text></g>",
+                escape(&edge.action),
+                escape(&sequences),
+                x1,
+                y1,
+                x2,
+                y2,
+                color,
+                dash,
+                (x1 + x2) / 2,
+                (y1 + y2) / 2 - 3,
+                escape(&edge.action)
+            );
+        }
+        for (node, &(x, y)) in &placed {
+            let kind = self.kind_of(node);
+            let mut title = node.id();
+            for finding in self.of(node) {
+                let _ = write!(title, "\n{}: {}", finding.kind.name(), finding.message);
+            }
+            let _ = writeln!(
+                content,
+                "<g><title>{}</title><rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" rx=\"4\" fill=\"{}\" stroke=\"{}\"/>\
+                 <text x=\"{}\" y=\"{}\" text-anchor=\"middle\">{}</text></g>",
+                escape(&title),
+                x,
+                y,
+                width,
+                BOX_HEIGHT,
+                kind.map_or("#eef", FindingKind::fill),
+                kind.map_or("#336", FindingKind::stroke),
+                x + width / 2,
+                y + BOX_HEIGHT / 2 + 4,
+                escape(&node.id())
+            );
+        }
+
+        let columns = placed.values().map(|&(x, _)| x).max().map_or(0, |x| x + width + 10);
+        let height = placed.values().map(|&(_, y)| y).max().map_or(0, |y| y + BOX_HEIGHT + 10);
+        let mut html = format!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{name}</title>\n\
+             <style>body{{font-family:sans-serif;margin:2em}}svg{{font-family:sans-serif;font-size:13px}}\
+             .swatch{{display:inline-block;width:1em;height:1em;margin-right:.5em;vertical-align:middle}}</style>\n\
+             </head>\n<body>\n<h1>{name}</h1>\n\
+             <svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\">\n\
+             <defs><marker id=\"arrow\" viewBox=\"0 0 10 10\" refX=\"10\" refY=\"5\" markerWidth=\"6\" markerHeight=\"6\" orient=\"auto\">\
+             <path d=\"M0,0 L10,5 L0,10 z\"/></marker></defs>\n{}</svg>\n",
+            columns,
+            height,
+            content,
+            name = escape(&graph.system_name)
+        );
+        if !self.is_empty() {
+            let _ = writeln!(html, "<h2>Findings ({})</h2>\n<ul>", self.findings.len());
+            for finding in &self.findings {
+                let _ = writeln!(
+                    html,
+                    "<li><span class=\"swatch\" style=\"background:{}\"></span>{}</li>",
+                    finding.kind.fill(),
+                    escape(&finding.to_string())
+                );
+            }
+            html.push_str("</ul>\n");
+        }
+        html.push_str("</body>\n</html>\n");
+        html
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loader::{self, SourceFile};
+
+    #[test]
+    fn test_annotations() {
+        let source = "roles { Top, Bottom }\nstate Guard roles { Top, Bottom }\nstate Mount roles { Top, Bottom }\n\
+                      state Armbar roles { Top } @final\nstate Stuck roles { Top }\nstate Lonely roles { Top }\n\
+                      sequence Sweep:\n    Hip: Guard[Bottom] -> Mount[Top]\n    Extend: Mount[Top] -> Armbar[Top]\n\
+                      sequence Wander:\n    Drift: Guard[Top] -> Stuck[Top]\n\
+                      sequence Escape:\n    Upa: Mount[Bottom] -> Guard[Top]";
+        let system = loader::load_sources("club", &[SourceFile::new("club.martial", source)]).unwrap();
+        let graph = MartialGraph::from_system(&system).with_declared_nodes(&system);
+        let annotations = Annotations::new(&system, &graph);
+        let findings: Vec<String> = annotations.findings.iter().map(|f| format!("{} {}", f.node, f.kind.name())).collect();
+        // Armbar is @final, every role of Guard and Mount is used
+        assert_eq!(findings, ["Lonely[Top] unreachable", "Stuck[Top] dead-end"]);
+
+        let source = source.replace("sequence Escape:\n    Upa: Mount[Bottom] -> Guard[Top]", "");
+        let system = loader::load_sources("club", &[SourceFile::new("club.martial", source)]).unwrap();
+        let graph = MartialGraph::from_system(&system).with_declared_nodes(&system);
+        let annotations = Annotations::new(&system, &graph);
+        assert_eq!(annotations.of(&Node::new("Mount", "Top"))[0].to_string(), "Mount[Top]: Mount[Bottom] is never used");
+        assert_eq!(annotations.of(&Node::new("Mount", "Bottom"))[0].kind, FindingKind::Unreachable);
+
+        let dot = annotations.to_dot(&graph);
+        assert!(
+            dot.contains("\"Stuck[Top]\" [label=\"Stuck\\n[Top]\", style=\"rounded,filled\", fillcolor=\"#f4b6b6\", color=\"#c0392b\", tooltip=\"dead-end: No transition leaves here and the state is not @final\"]"),
+            "{}",
+            dot
+        );
+        assert!(dot.contains("\"Guard[Top]\" -> \"Stuck[Top]\" [label=\"Drift\", color=\"#c0392b\"];"), "{}", dot);
+        assert_eq!(Annotations::default().to_dot(&graph), graph.to_dot());
+
+        let html = annotations.to_html(&graph);
+        assert!(html.starts_with("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>club</title>"));
+        assert!(html.contains("<g><title>Stuck[Top]\ndead-end: No transition leaves here and the state is not @final</title><rect"));
+        assert!(html.contains("<li><span class=\"swatch\" style=\"background:#f7e6a1\"></span>Mount[Top]: Mount[Bottom] is never used</li>"));
+        assert!(!Annotations::default().to_html(&graph).contains("<h2>Findings"));
+    }
+}
@@ -3,10 +3,75 @@
 //! These types represent the parsed structure of martial system declarations.
 //! Multiple `.martial` files can be loaded from a directory and combined.
 
+use crate::prelude::*;
+use crate::lexer::{Position, Span};
+use crate::metadata::FileMetadata;
+use crate::symbol::Symbol;
+use schemars::JsonSchema;
+use serde::Serialize;
+use alloc::collections::BTreeMap;
+use core::fmt;
+
+/// Latest version of the file format
+///
+/// A file declares its version with a `martial N` pragma before its first
+/// declaration; files without one are version 1. Version 2 gives the roles
+/// of a state in brackets, `state Guard[Top, Bottom]`, instead of
+/// `state Guard roles { Top, Bottom }`.
+pub const LATEST_VERSION: u32 = 2;
+
+/// Where a name is written in its file
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NameSpan {
+    pub position: Position,
+    pub span: Span,
+}
+
+/// Where the names of a node are written, in the order its documentation
+/// gives; empty for nodes that were not parsed from a file
+///
+/// Spans take no part in comparisons, so that nodes equal the same nodes
+/// built by hand or parsed from other text.
+#[derive(Debug, Clone, Default)]
+pub struct Spans(pub Vec<NameSpan>);
+
+impl Spans {
+    pub fn push(&mut self, span: NameSpan) {
+        self.0.push(span);
+    }
+
+    /// Where the `i`th name is written, if known
+    pub fn get(&self, i: usize) -> Option<NameSpan> {
+        self.0.get(i).copied()
+    }
+}
+
+impl PartialEq for Spans {
+    fn eq(&self, _: &Spans) -> bool {
+        true
+    }
+}
+
 /// A parsed martial file contains a list of declarations
 #[derive(Debug, Clone, PartialEq)]
 pub struct MartialFile {
+    /// Path of the file the declarations were read from, if known
+    pub source: Option<String>,
+    /// Front-matter metadata, if the file has any
+    pub metadata: Option<FileMetadata>,
+    /// Version of the file format, see [`LATEST_VERSION`]
+    pub version: u32,
     pub declarations: Vec<Declaration>,
+    /// Constructs of older versions accepted in compatibility mode
+    pub deprecations: Vec<Deprecation>,
+}
+
+/// A construct removed from the version of its file, accepted in
+/// compatibility mode
+#[derive(Debug, Clone, PartialEq)]
+pub struct Deprecation {
+    pub message: String,
+    pub position: Position,
 }
 
 /// A declaration at the top level of a file
@@ -16,6 +81,135 @@ pub enum Declaration {
     State(State),
     Sequence(Sequence),
     Group(GroupDecl),
+    Levels(LevelsDecl),
+    Term(TermDecl),
+    Ruleset(RulesetDecl),
+    Template(TemplateDecl),
+    Mirror(MirrorDecl),
+    Assert(AssertDecl),
+}
+
+impl MartialFile {
+    /// Copy of the file with the source path and all positions cleared
+    ///
+    /// Files that differ only in layout, such as a file and its printed
+    /// form, normalize to the same value.
+    pub fn normalized(&self) -> MartialFile {
+        let mut file = self.clone();
+        file.source = None;
+        file.deprecations.clear();
+        for declaration in &mut file.declarations {
+            declaration.clear_positions();
+        }
+        file
+    }
+
+    /// Whether two files declare the same things, ignoring layout and origin
+    pub fn same_declarations(&self, other: &MartialFile) -> bool {
+        self.normalized() == other.normalized()
+    }
+
+    /// Prefix the states, groups and sequences the file declares or refers
+    /// to with `namespace`, as in `wrestling.SingleLeg`
+    ///
+    /// Roles, actions, templates, terms, levels and rulesets are left as
+    /// they are, to be shared with the files around it.
+    pub fn qualify(&mut self, namespace: &str) {
+        let qualified = |name: &str| format!("{}.{}", namespace, name);
+        let qualify_ref = |r: &mut StateRef, params: &[String]| {
+            if !r.is_placeholder() && !params.iter().any(|p| *p == r.state) {
+                r.state = Symbol::new(&qualified(&r.state));
+            }
+        };
+        let qualify_steps = |steps: &mut Vec<SequenceStep>, params: &[String]| {
+            for step in steps {
+                qualify_ref(&mut step.from, params);
+                qualify_ref(&mut step.to, params);
+                if let Some(on_fail) = &mut step.on_fail {
+                    qualify_ref(on_fail, params);
+                }
+                if let Some(reaction) = &mut step.reaction {
+                    qualify_ref(&mut reaction.from, params);
+                    qualify_ref(&mut reaction.to, params);
+                }
+            }
+        };
+        for declaration in &mut self.declarations {
+            match declaration {
+                Declaration::State(state) => state.name = qualified(&state.name),
+                Declaration::Sequence(sequence) => {
+                    sequence.name = qualified(&sequence.name);
+                    if let Some(original) = &mut sequence.mirror_of {
+                        *original = qualified(original);
+                    }
+                    qualify_steps(&mut sequence.steps, &[]);
+                    for arg in sequence.invocations.iter_mut().flat_map(|i| &mut i.args) {
+                        if let TemplateArg::Ref(state_ref) = arg {
+                            qualify_ref(state_ref, &[]);
+                        }
+                    }
+                }
+                Declaration::Template(template) => qualify_steps(&mut template.steps, &template.params),
+                Declaration::Group(group) => {
+                    group.name = qualified(&group.name);
+                    for state in &mut group.states {
+                        *state = qualified(state);
+                    }
+                }
+                Declaration::Assert(assertion) => {
+                    qualify_ref(&mut assertion.from, &[]);
+                    qualify_ref(&mut assertion.to, &[]);
+                }
+                Declaration::Roles(_)
+                | Declaration::Levels(_)
+                | Declaration::Term(_)
+                | Declaration::Ruleset(_)
+                | Declaration::Mirror(_) => {}
+            }
+        }
+    }
+}
+
+impl Declaration {
+    /// Reset every position in the declaration to the default
+    fn clear_positions(&mut self) {
+        let clear_annotations = |annotations: &mut Vec<Annotation>| {
+            for annotation in annotations {
+                annotation.position = Position::default();
+            }
+        };
+        match self {
+            Declaration::Roles(roles) => roles.position = Position::default(),
+            Declaration::State(state) => {
+                state.position = Position::default();
+                clear_annotations(&mut state.annotations);
+            }
+            Declaration::Sequence(sequence) => {
+                sequence.position = Position::default();
+                clear_annotations(&mut sequence.annotations);
+                for step in &mut sequence.steps {
+                    step.position = Position::default();
+                    clear_annotations(&mut step.annotations);
+                }
+                for invocation in &mut sequence.invocations {
+                    invocation.position = Position::default();
+                }
+            }
+            Declaration::Template(template) => {
+                template.position = Position::default();
+                for step in &mut template.steps {
+                    step.position = Position::default();
+                    clear_annotations(&mut step.annotations);
+                }
+            }
+            Declaration::Group(group) => group.position = Position::default(),
+            Declaration::Levels(levels) => levels.position = Position::default(),
+            Declaration::Term(term) => term.position = Position::default(),
+            Declaration::Ruleset(ruleset) => ruleset.position = Position::default(),
+            Declaration::Mirror(mirror) => mirror.position = Position::default(),
+            Declaration::Assert(assertion) => assertion.position = Position::default(),
+        }
+    }
 }
 
 /// A roles declaration
@@ -25,25 +219,131 @@ pub enum Declaration {
 #[derive(Debug, Clone, PartialEq)]
 pub struct RolesDecl {
     pub roles: Vec<String>,
+    pub position: Position,
+    /// Spans of the roles
+    pub spans: Spans,
 }
 
 /// A state declaration
 ///
 /// Example: `state Mount roles { Top, Bottom }`
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema)]
 pub struct State {
     pub name: String,
     /// Optional role restrictions. If None, all roles are valid.
     pub allowed_roles: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub annotations: Vec<Annotation>,
+    pub position: Position,
+    /// Spans of the name, then of the allowed roles
+    #[serde(skip)]
+    pub spans: Spans,
+}
+
+impl State {
+    /// Level the state is introduced at, from its `@level` annotation
+    pub fn level(&self) -> Option<&str> {
+        level(&self.annotations)
+    }
+
+    /// Display name in `lang`, from its `@label` annotation
+    pub fn label(&self, lang: &str) -> Option<&str> {
+        label(&self.annotations, lang)
+    }
+
+    /// How dominant the position is, from its `@dominance` annotation
+    pub fn dominance(&self) -> Option<u32> {
+        annotation(&self.annotations, "dominance")?.args.first()?.parse().ok()
+    }
+
+    /// Whether the state ends sequences, from its `@final` annotation
+    pub fn is_final(&self) -> bool {
+        annotation(&self.annotations, "final").is_some()
+    }
+
+    /// Whether the state is a core position, from its `@core` annotation
+    pub fn is_core(&self) -> bool {
+        annotation(&self.annotations, "core").is_some()
+    }
+
+    /// States to teach before this one, from its `@requires` annotations
+    pub fn requires(&self) -> Vec<&str> {
+        self.annotations
+            .iter()
+            .filter(|a| a.name == "requires")
+            .flat_map(|a| a.args.iter().map(String::as_str))
+            .collect()
+    }
+
+    /// Every annotation as a key and value, see [`attributes`]
+    pub fn attributes(&self) -> BTreeMap<String, String> {
+        attributes(&self.annotations)
+    }
 }
 
 /// A state reference with a role
 ///
-/// Example: `Mount[Top]`
-#[derive(Debug, Clone, PartialEq)]
+/// Example: `Mount[Top]`, or `Triangle[Attacker, Defender, Weapon]` with a
+/// role for each of several participants
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema)]
 pub struct StateRef {
-    pub state: String,
-    pub role: String,
+    pub state: Symbol,
+    pub role: Symbol,
+    /// Roles of the further participants, in slot order
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub extra_roles: Vec<Symbol>,
+    /// Spans of the state, then of the roles in slot order
+    #[serde(skip)]
+    pub spans: Spans,
+}
+
+impl StateRef {
+    /// Roles of every slot, in order
+    pub fn roles(&self) -> impl Iterator<Item = &Symbol> {
+        core::iter::once(&self.role).chain(&self.extra_roles)
+    }
+
+    /// Where `name`, its state or one of its roles, is written, if known
+    pub fn position_of(&self, name: &str) -> Option<Position> {
+        let i = core::iter::once(&self.state).chain(self.roles()).position(|n| *n == name)?;
+        Some(self.spans.get(i)?.position)
+    }
+
+    /// Number of role slots
+    pub fn arity(&self) -> usize {
+        1 + self.extra_roles.len()
+    }
+
+    /// The role slots as written between the brackets, e.g. `Attacker, Defender`
+    pub fn role_list(&self) -> String {
+        self.roles().map(Symbol::as_str).collect::<Vec<_>>().join(", ")
+    }
+}
+
+impl StateRef {
+    /// A template parameter standing for a whole state reference
+    pub fn placeholder(parameter: impl Into<Symbol>) -> Self {
+        StateRef {
+            state: parameter.into(),
+            role: Symbol::from(""),
+            extra_roles: Vec::new(),
+            spans: Spans::default(),
+        }
+    }
+
+    /// Whether this is a bare template parameter rather than `State[Role]`
+    pub fn is_placeholder(&self) -> bool {
+        self.role.is_empty()
+    }
+}
+
+impl fmt::Display for StateRef {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_placeholder() {
+            return write!(f, "{}", self.state);
+        }
+        write!(f, "{}[{}]", self.state, self.role_list())
+    }
 }
 
 /// A sequence declaration - ordered progression of actions
@@ -54,18 +354,153 @@ pub struct StateRef {
 ///     Stack: OpenGuard[Top] -> HalfGuard[Top]
 ///     KneeSlice: HalfGuard[Top] -> SideControl[Top]
 /// ```
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema)]
 pub struct Sequence {
     pub name: String,
     pub steps: Vec<SequenceStep>,
+    /// Template invocations among the steps, until the system is validated
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub invocations: Vec<Invocation>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub annotations: Vec<Annotation>,
+    /// Whether the mirrored version of the sequence is generated too
+    #[serde(skip_serializing_if = "core::ops::Not::not")]
+    pub mirrored: bool,
+    /// Roles taking turns at the steps, given with `alternating(A, B)`; the
+    /// first one performs the first step
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub alternating: Option<(String, String)>,
+    /// Sequence this one is the generated mirror of
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mirror_of: Option<String>,
+    pub position: Position,
+    /// Spans of the name, then of the alternating roles
+    #[serde(skip)]
+    pub spans: Spans,
+}
+
+impl Sequence {
+    /// Level the sequence is taught at, from its `@level` annotation
+    pub fn level(&self) -> Option<&str> {
+        level(&self.annotations)
+    }
+
+    /// Teaching time in minutes, from its `@duration` annotation
+    pub fn duration(&self) -> Option<u32> {
+        annotation(&self.annotations, "duration")?.args.first()?.parse().ok()
+    }
+
+    /// Instructional video URLs, from its `@video` annotations
+    pub fn videos(&self) -> Vec<&str> {
+        videos(&self.annotations)
+    }
+
+    /// Whether the sequence is marked as an attack with `@attack`
+    pub fn is_attack(&self) -> bool {
+        annotation(&self.annotations, "attack").is_some()
+    }
+
+    /// Outcome of the sequence, such as `submission`, from its `@kind`
+    /// annotation
+    pub fn kind(&self) -> Option<&str> {
+        annotation(&self.annotations, "kind")?.args.first().map(String::as_str)
+    }
+
+    /// Tags, from its `@tag` annotations
+    pub fn tags(&self) -> Vec<&str> {
+        tags(&self.annotations)
+    }
+
+    /// Role whose turn step `i` is, in a sequence declared `alternating`
+    pub fn turn(&self, i: usize) -> Option<&str> {
+        let (first, second) = self.alternating.as_ref()?;
+        Some(if i.is_multiple_of(2) { first } else { second })
+    }
 }
 
 /// A single step within a sequence - an action with explicit transition
 ///
 /// Example: `KneeCut: Headquarters[Top] -> SideControl[Top]`
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema)]
 pub struct SequenceStep {
-    pub action_name: String,
+    pub action_name: Symbol,
+    pub from: StateRef,
+    pub to: StateRef,
+    /// Where the step leaves its performer when the action fails, given
+    /// with `onfail ->`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub on_fail: Option<StateRef>,
+    /// The opponent's simultaneous transition, if given
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reaction: Option<Reaction>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub annotations: Vec<Annotation>,
+    /// Template the step was expanded from; its position is then that of
+    /// the invocation
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub template: Option<String>,
+    /// Times the step is performed in a row, from an `xN` suffix
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repeat: Option<u32>,
+    /// Which of the repetitions of a repeated step this one is, from 1, once
+    /// the system is validated
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repetition: Option<u32>,
+    pub position: Position,
+    /// Span of the action
+    #[serde(skip)]
+    pub spans: Spans,
+}
+
+impl SequenceStep {
+    /// Where the step can end: its `to`, then its `onfail` outcome
+    pub fn outcomes(&self) -> impl Iterator<Item = &StateRef> {
+        core::iter::once(&self.to).chain(&self.on_fail)
+    }
+
+    /// Instructional video URLs, from its `@video` annotations
+    pub fn videos(&self) -> Vec<&str> {
+        videos(&self.annotations)
+    }
+
+    /// Display name of the action in `lang`, from its `@label` annotation
+    pub fn label(&self, lang: &str) -> Option<&str> {
+        label(&self.annotations, lang)
+    }
+
+    /// Relative weight of the transition, from its `@weight` annotation
+    pub fn weight(&self) -> Option<u32> {
+        annotation(&self.annotations, "weight")?.args.first()?.parse().ok()
+    }
+
+    /// Whether the sequence can be joined at this step, from its `@entry`
+    /// annotation
+    pub fn is_entry(&self) -> bool {
+        annotation(&self.annotations, "entry").is_some()
+    }
+
+    /// Turn made before the step, in degrees counterclockwise, from its
+    /// `@turn` annotation: `@turn(90L)` gives 90 and `@turn(90R)` -90
+    pub fn turn(&self) -> Option<i32> {
+        parse_turn(annotation(&self.annotations, "turn")?.args.first()?)
+    }
+
+    /// Steps moved forward after turning, from its `@advance` annotation,
+    /// one without argument
+    pub fn advance(&self) -> Option<u32> {
+        match annotation(&self.annotations, "advance")?.args.first() {
+            Some(steps) => steps.parse().ok(),
+            None => Some(1),
+        }
+    }
+}
+
+/// The opponent's side of a step
+///
+/// Example: the part after `/` in
+/// `Sweep: ClosedGuard[Bottom] -> Mount[Top] / ClosedGuard[Top] -> Mount[Bottom]`
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema)]
+pub struct Reaction {
     pub from: StateRef,
     pub to: StateRef,
 }
@@ -82,4 +517,454 @@ pub struct SequenceStep {
 pub struct GroupDecl {
     pub name: String,
     pub states: Vec<String>,
+    pub position: Position,
+    /// Spans of the name, then of the states
+    pub spans: Spans,
+}
+
+/// A levels declaration - ordered curriculum levels, lowest first
+///
+/// Example: `levels { WhiteBelt, BlueBelt, PurpleBelt }`
+#[derive(Debug, Clone, PartialEq)]
+pub struct LevelsDecl {
+    pub levels: Vec<String>,
+    pub position: Position,
+}
+
+/// A glossary entry defining a technique or position name
+///
+/// Example: `term OGoshi "Major hip throw"`
+#[derive(Debug, Clone, PartialEq)]
+pub struct TermDecl {
+    pub name: String,
+    pub definition: String,
+    pub position: Position,
+    /// Span of the name
+    pub spans: Spans,
+}
+
+/// A ruleset declaration - actions and sequences illegal under a set of rules
+///
+/// Example: `ruleset IBJJF { forbid HeelHookEntry, ScissorTakedown }`
+#[derive(Debug, Clone, PartialEq)]
+pub struct RulesetDecl {
+    pub name: String,
+    /// Forbidden action or sequence names
+    pub forbidden: Vec<String>,
+    pub position: Position,
+    /// Spans of the name, then of the forbidden names
+    pub spans: Spans,
+}
+
+/// A mirror declaration - pairs of names swapped when mirroring a sequence
+///
+/// Example: `mirror roles { Orthodox <-> Southpaw }` swaps the roles, and
+/// `mirror suffixes { Left <-> Right }` turns `JabLeft` into `JabRight`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MirrorDecl {
+    pub kind: MirrorKind,
+    pub pairs: Vec<(String, String)>,
+    pub position: Position,
+    /// Spans of both names of each pair
+    pub spans: Spans,
+}
+
+/// What the pairs of a mirror declaration are
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MirrorKind {
+    Roles,
+    /// Suffixes of action and sequence names
+    Suffixes,
+}
+
+/// The swaps of all mirror declarations of a system
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Mirror {
+    /// Mirror of each paired role, in both directions
+    pub roles: BTreeMap<String, String>,
+    pub suffixes: Vec<(String, String)>,
+}
+
+impl Mirror {
+    /// Mirror of a role; unpaired roles stay as they are
+    pub fn role(&self, role: &Symbol) -> Symbol {
+        match self.roles.get(role.as_str()) {
+            Some(mirror) => Symbol::from(mirror.as_str()),
+            None => role.clone(),
+        }
+    }
+
+    /// Mirror of an action or sequence name, by swapping its suffix
+    pub fn name(&self, name: &str) -> Option<String> {
+        self.suffixes.iter().find_map(|(left, right)| {
+            let swap = |suffix: &str, mirror: &str| {
+                let stem = name.strip_suffix(suffix).filter(|stem| !stem.is_empty())?;
+                Some(format!("{}{}", stem, mirror))
+            };
+            swap(left, right).or_else(|| swap(right, left))
+        })
+    }
+
+    fn state_ref(&self, state_ref: &StateRef) -> StateRef {
+        StateRef {
+            state: state_ref.state.clone(),
+            role: self.role(&state_ref.role),
+            extra_roles: state_ref.extra_roles.iter().map(|role| self.role(role)).collect(),
+            spans: Spans::default(),
+        }
+    }
+
+    /// The mirrored version of `sequence`, named by swapping its suffix or
+    /// else by appending `Mirrored`
+    pub fn sequence(&self, sequence: &Sequence) -> Sequence {
+        let steps = sequence
+            .steps
+            .iter()
+            .map(|step| SequenceStep {
+                action_name: match self.name(&step.action_name) {
+                    Some(name) => Symbol::from(name.as_str()),
+                    None => step.action_name.clone(),
+                },
+                from: self.state_ref(&step.from),
+                to: self.state_ref(&step.to),
+                on_fail: step.on_fail.as_ref().map(|on_fail| self.state_ref(on_fail)),
+                reaction: step.reaction.as_ref().map(|reaction| Reaction {
+                    from: self.state_ref(&reaction.from),
+                    to: self.state_ref(&reaction.to),
+                }),
+                ..step.clone()
+            })
+            .collect();
+        Sequence {
+            name: self.name(&sequence.name).unwrap_or_else(|| format!("{}Mirrored", sequence.name)),
+            steps,
+            invocations: Vec::new(),
+            annotations: sequence.annotations.clone(),
+            mirrored: false,
+            alternating: sequence.alternating.as_ref().map(|(first, second)| {
+                (self.role(&first.as_str().into()).to_string(), self.role(&second.as_str().into()).to_string())
+            }),
+            mirror_of: Some(sequence.name.clone()),
+            position: sequence.position,
+            spans: Spans::default(),
+        }
+    }
+}
+
+/// An assertion about the graph of the system, checked by validation
+///
+/// Example: `assert no_path Mount[Bottom] -> RearMount[Bottom]`
+#[derive(Debug, Clone, PartialEq)]
+pub struct AssertDecl {
+    pub kind: AssertKind,
+    pub from: StateRef,
+    pub to: StateRef,
+    pub position: Position,
+}
+
+/// What an assertion requires of the path between its positions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AssertKind {
+    /// Some chain of actions leads from one position to the other
+    Reachable,
+    /// No chain of actions does
+    NoPath,
+}
+
+impl AssertKind {
+    /// Name of the assertion, as written after `assert`
+    pub fn name(self) -> &'static str {
+        match self {
+            AssertKind::Reachable => "reachable",
+            AssertKind::NoPath => "no_path",
+        }
+    }
+
+    /// The assertion named `name`
+    pub fn from_name(name: &str) -> Option<AssertKind> {
+        [AssertKind::Reachable, AssertKind::NoPath].into_iter().find(|kind| kind.name() == name)
+    }
+}
+
+impl fmt::Display for AssertDecl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "assert {} {} -> {}", self.kind.name(), self.from, self.to)
+    }
+}
+
+/// A template declaration - steps with parameters, repeated by invoking
+/// the template among the steps of sequences
+///
+/// Example:
+/// ```text
+/// template Step(action, from, to) = action: from -> to
+/// template Punch(action, stance) =
+///     action: stance[Tori] -> stance[Tori]
+/// ```
+///
+/// A parameter may stand for an action, a state or role name, or a whole
+/// state reference when written bare, as `from` above.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TemplateDecl {
+    pub name: String,
+    pub params: Vec<String>,
+    pub steps: Vec<SequenceStep>,
+    pub position: Position,
+}
+
+/// An argument of a template invocation
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema)]
+#[serde(untagged)]
+pub enum TemplateArg {
+    Name(Symbol),
+    Ref(StateRef),
+}
+
+impl fmt::Display for TemplateArg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TemplateArg::Name(name) => write!(f, "{}", name),
+            TemplateArg::Ref(state_ref) => write!(f, "{}", state_ref),
+        }
+    }
+}
+
+/// Use of a template among the steps of a sequence
+///
+/// Example: `Step(Oizuki, Zenkutsu[Tori], Zenkutsu[Tori])`
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema)]
+pub struct Invocation {
+    pub template: String,
+    pub args: Vec<TemplateArg>,
+    /// Number of the sequence's own steps written before the invocation
+    pub index: usize,
+    pub position: Position,
+    /// Spans of the template, then of the first name of each argument
+    #[serde(skip)]
+    pub spans: Spans,
+}
+
+impl TemplateDecl {
+    /// The steps of the template with the arguments of `invocation`
+    /// substituted, placed at the invocation
+    pub fn instantiate(&self, invocation: &Invocation) -> Result<Vec<SequenceStep>, String> {
+        if invocation.args.len() != self.params.len() {
+            return Err(format!(
+                "Template '{}' takes {} argument(s) ({}), got {}",
+                self.name,
+                self.params.len(),
+                self.params.join(", "),
+                invocation.args.len()
+            ));
+        }
+        let arg = |name: &str| self.params.iter().position(|p| p == name).map(|i| &invocation.args[i]);
+        let symbol = |symbol: &Symbol| -> Result<Symbol, String> {
+            match arg(symbol) {
+                None => Ok(symbol.clone()),
+                Some(TemplateArg::Name(name)) => Ok(name.clone()),
+                Some(TemplateArg::Ref(state_ref)) => Err(format!(
+                    "Parameter '{}' of template '{}' stands for a name, got {}",
+                    symbol, self.name, state_ref
+                )),
+            }
+        };
+        let state_ref = |state_ref: &StateRef| -> Result<StateRef, String> {
+            if !state_ref.is_placeholder() {
+                return Ok(StateRef {
+                    state: symbol(&state_ref.state)?,
+                    role: symbol(&state_ref.role)?,
+                    extra_roles: state_ref.extra_roles.iter().map(symbol).collect::<Result<_, _>>()?,
+                    spans: Spans::default(),
+                });
+            }
+            match arg(&state_ref.state) {
+                Some(TemplateArg::Ref(state_ref)) => Ok(state_ref.clone()),
+                _ => Err(format!(
+                    "Parameter '{}' of template '{}' stands for a state like State[Role]",
+                    state_ref.state, self.name
+                )),
+            }
+        };
+
+        self.steps
+            .iter()
+            .map(|step| {
+                let mut annotations = step.annotations.clone();
+                for annotation in &mut annotations {
+                    for value in annotation.args.iter_mut().chain(annotation.named.values_mut()) {
+                        if let Some(arg) = arg(value) {
+                            *value = arg.to_string();
+                        }
+                    }
+                    annotation.position = invocation.position;
+                }
+                Ok(SequenceStep {
+                    action_name: symbol(&step.action_name)?,
+                    from: state_ref(&step.from)?,
+                    to: state_ref(&step.to)?,
+                    on_fail: step.on_fail.as_ref().map(state_ref).transpose()?,
+                    reaction: match &step.reaction {
+                        Some(reaction) => Some(Reaction {
+                            from: state_ref(&reaction.from)?,
+                            to: state_ref(&reaction.to)?,
+                        }),
+                        None => None,
+                    },
+                    annotations,
+                    template: Some(self.name.clone()),
+                    repeat: step.repeat,
+                    repetition: None,
+                    position: invocation.position,
+                    spans: Spans::default(),
+                })
+            })
+            .collect()
+    }
+}
+
+impl Sequence {
+    /// Replace the template invocations and the repeated steps by the steps
+    /// they expand to
+    ///
+    /// On error, returns the position of the faulty invocation or step.
+    pub fn expand(&mut self, templates: &BTreeMap<String, TemplateDecl>) -> Result<(), (Position, String)> {
+        let invocations = core::mem::take(&mut self.invocations);
+        if !invocations.is_empty() {
+            let own_steps = core::mem::take(&mut self.steps);
+            let mut own_steps = own_steps.into_iter().enumerate().peekable();
+            for invocation in invocations {
+                while let Some((_, step)) = own_steps.next_if(|(i, _)| *i < invocation.index) {
+                    self.steps.push(step);
+                }
+                let template = templates.get(&invocation.template).ok_or_else(|| {
+                    let message = format!("Template '{}' is not defined", invocation.template);
+                    (invocation.position, message)
+                })?;
+                let steps = template.instantiate(&invocation).map_err(|message| (invocation.position, message))?;
+                self.steps.extend(steps);
+            }
+            self.steps.extend(own_steps.map(|(_, step)| step));
+        }
+
+        for step in core::mem::take(&mut self.steps) {
+            let count = match (step.repeat, step.repetition) {
+                (Some(count), None) => count,
+                _ => {
+                    self.steps.push(step);
+                    continue;
+                }
+            };
+            // Each repetition starts where the previous one ended
+            let ends = core::iter::once((&step.from, &step.to)).chain(step.reaction.as_ref().map(|r| (&r.from, &r.to)));
+            if let Some((from, to)) = ends.filter(|_| count > 1).find(|(from, to)| from != to) {
+                let message = format!(
+                    "Step '{}' cannot be repeated: it ends at {} rather than where it starts, {}",
+                    step.action_name, to, from
+                );
+                return Err((step.position, message));
+            }
+            for i in 1..=count {
+                self.steps.push(SequenceStep {
+                    repetition: Some(i),
+                    ..step.clone()
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// An annotation attached to a state, sequence or step
+///
+/// Example: `state Mount @level(BlueBelt)`
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema)]
+pub struct Annotation {
+    pub name: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
+    /// Named arguments, as in `@label(en: "Mount")`
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub named: BTreeMap<String, String>,
+    pub position: Position,
+}
+
+/// Find an annotation by name
+pub fn annotation<'a>(annotations: &'a [Annotation], name: &str) -> Option<&'a Annotation> {
+    annotations.iter().find(|a| a.name == name)
+}
+
+/// Degrees counterclockwise of a turn such as `90L`, `45R` or `180`, in
+/// multiples of 45 degrees up to a full turn; a side is needed unless the
+/// turn is 180 degrees
+pub fn parse_turn(text: &str) -> Option<i32> {
+    let (degrees, sign) = match text.as_bytes().last()? {
+        b'L' | b'l' => (&text[..text.len() - 1], 1),
+        b'R' | b'r' => (&text[..text.len() - 1], -1),
+        _ => (text, 0),
+    };
+    let degrees: i32 = degrees.parse().ok()?;
+    if degrees <= 0 || degrees > 360 || degrees % 45 != 0 {
+        return None;
+    }
+    match sign {
+        0 if degrees == 180 => Some(180),
+        0 => None,
+        sign => Some(sign * degrees),
+    }
+}
+
+fn videos(annotations: &[Annotation]) -> Vec<&str> {
+    annotations
+        .iter()
+        .filter(|a| a.name == "video")
+        .filter_map(|a| a.args.first())
+        .map(String::as_str)
+        .collect()
+}
+
+fn tags(annotations: &[Annotation]) -> Vec<&str> {
+    annotations
+        .iter()
+        .filter(|a| a.name == "tag")
+        .flat_map(|a| &a.args)
+        .map(String::as_str)
+        .collect()
+}
+
+/// Annotations as a flat map, for exporters: `@level(Blue)` gives `level`
+/// = `Blue`, `@label(en: "Mount")` gives `label.en` = `Mount`, and an
+/// annotation without arguments, such as `@attack`, gives `true`
+///
+/// The arguments of an annotation, and of repeated ones such as `@video`,
+/// are joined with `", "`.
+pub fn attributes(annotations: &[Annotation]) -> BTreeMap<String, String> {
+    let mut attributes: BTreeMap<String, String> = BTreeMap::new();
+    let mut add = |key: String, value: &str| {
+        attributes
+            .entry(key)
+            .and_modify(|existing| {
+                existing.push_str(", ");
+                existing.push_str(value);
+            })
+            .or_insert_with(|| value.to_string());
+    };
+    for annotation in annotations {
+        for (key, value) in &annotation.named {
+            add(format!("{}.{}", annotation.name, key), value);
+        }
+        if !annotation.args.is_empty() {
+            add(annotation.name.clone(), &annotation.args.join(", "));
+        } else if annotation.named.is_empty() {
+            add(annotation.name.clone(), "true");
+        }
+    }
+    attributes
+}
+
+fn level(annotations: &[Annotation]) -> Option<&str> {
+    annotation(annotations, "level").and_then(|a| a.args.first()).map(String::as_str)
+}
+
+fn label<'a>(annotations: &'a [Annotation], lang: &str) -> Option<&'a str> {
+    annotation(annotations, "label").and_then(|a| a.named.get(lang)).map(String::as_str)
 }
@@ -0,0 +1,230 @@
+//! Balance of attacking and defending techniques
+//!
+//! A sequence attacks if its `@kind` is a sweep, submission or pass, or it
+//! is tagged `@tag(attack)`, and defends if it is an escape or tagged
+//! `@tag(defense)`; sequences of other kinds are counted apart. The report
+//! tallies them by the role and the groups of the positions they start
+//! from, and flags the positions a curriculum leaves without defense: those
+//! where a sequence attacks from the other side of the same state, such as
+//! `Mount[Bottom]` when `MountToArmbar` starts from `Mount[Top]`, and no
+//! sequence defends.
+
+use crate::ast::Sequence;
+use crate::graph::{MartialGraph, Node};
+use crate::prelude::*;
+use crate::semantic::MartialSystem;
+use alloc::collections::{BTreeMap, BTreeSet};
+use core::fmt;
+use serde::Serialize;
+
+/// Kinds of attacking sequences
+pub const ATTACK_KINDS: [&str; 3] = ["sweep", "submission", "pass"];
+
+/// Kinds of defending sequences
+pub const DEFENSE_KINDS: [&str; 1] = ["escape"];
+
+/// Which side a sequence is on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Side {
+    Attack,
+    Defense,
+    Other,
+}
+
+impl Side {
+    /// The side of `sequence`, from its kind, then its tags
+    pub fn of(sequence: &Sequence) -> Self {
+        let tags = sequence.tags();
+        match sequence.kind() {
+            Some(kind) if ATTACK_KINDS.contains(&kind) => Side::Attack,
+            Some(kind) if DEFENSE_KINDS.contains(&kind) => Side::Defense,
+            _ if tags.contains(&"attack") => Side::Attack,
+            _ if tags.contains(&"defense") => Side::Defense,
+            _ => Side::Other,
+        }
+    }
+}
+
+/// Numbers of sequences on each side
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Tally {
+    pub attack: usize,
+    pub defense: usize,
+    pub other: usize,
+}
+
+impl Tally {
+    fn add(&mut self, side: Side) {
+        match side {
+            Side::Attack => self.attack += 1,
+            Side::Defense => self.defense += 1,
+            Side::Other => self.other += 1,
+        }
+    }
+}
+
+/// A position attacked from the other side of its state with no sequence
+/// defending from it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Unescaped {
+    pub position: String,
+    /// Attacking sequences starting from the other side, sorted
+    pub attacks: Vec<String>,
+}
+
+impl fmt::Display for Unescaped {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "No escapes defined from {}, attacked by {}", self.position, self.attacks.join(", "))
+    }
+}
+
+/// Attacking and defending sequences of a system
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BalanceReport {
+    pub system_name: String,
+    /// Numbers of sequences by `@kind`, `untyped` for those without one
+    pub kinds: BTreeMap<String, usize>,
+    /// Sequences by the role they start from
+    pub roles: BTreeMap<String, Tally>,
+    /// Sequences by the groups of the states they start from
+    pub groups: BTreeMap<String, Tally>,
+    pub unescaped: Vec<Unescaped>,
+}
+
+impl BalanceReport {
+    pub fn new(system: &MartialSystem) -> Self {
+        let graph = MartialGraph::from_system(system).with_declared_nodes(system);
+
+        // Where each sequence can be started from
+        let mut starts: BTreeMap<&str, BTreeSet<&Node>> = BTreeMap::new();
+        for edge in graph.edges.iter().filter(|edge| edge.entry && !edge.reaction && !edge.failure) {
+            starts.entry(edge.sequence.as_str()).or_default().insert(&edge.from);
+        }
+
+        let mut report = BalanceReport {
+            system_name: system.name.clone(),
+            kinds: BTreeMap::new(),
+            roles: system.roles.iter().map(|role| (role.clone(), Tally::default())).collect(),
+            groups: system.groups.keys().map(|group| (group.clone(), Tally::default())).collect(),
+            unescaped: Vec::new(),
+        };
+        let mut attacked: BTreeMap<&Node, BTreeSet<&str>> = BTreeMap::new();
+        let mut defended: BTreeSet<&Node> = BTreeSet::new();
+        for (name, sequence) in &system.sequences {
+            let side = Side::of(sequence);
+            *report.kinds.entry(sequence.kind().unwrap_or("untyped").to_string()).or_default() += 1;
+            let starts = starts.get(name.as_str()).cloned().unwrap_or_default();
+            let roles: BTreeSet<&str> = starts.iter().map(|node| node.role.as_str()).collect();
+            for role in roles {
+                report.roles.entry(role.to_string()).or_default().add(side);
+            }
+            for (group, members) in &system.groups {
+                if starts.iter().any(|node| members.iter().any(|state| *state == node.state)) {
+                    report.groups.entry(group.clone()).or_default().add(side);
+                }
+            }
+            for node in starts {
+                match side {
+                    Side::Attack => {
+                        attacked.entry(node).or_default().insert(name);
+                    }
+                    Side::Defense => {
+                        defended.insert(node);
+                    }
+                    Side::Other => {}
+                }
+            }
+        }
+
+        for node in graph.nodes.iter().filter(|node| !defended.contains(node)) {
+            let attacks: BTreeSet<&str> = attacked
+                .iter()
+                .filter(|(from, _)| from.state == node.state && from.role != node.role)
+                .flat_map(|(_, sequences)| sequences.iter().copied())
+                .collect();
+            if !attacks.is_empty() {
+                report.unescaped.push(Unescaped {
+                    position: node.id(),
+                    attacks: attacks.into_iter().map(String::from).collect(),
+                });
+            }
+        }
+        report
+    }
+
+    /// Export as JSON
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string_pretty(self)
+    }
+
+    /// The tallies as tables, then the positions left without defense
+    pub fn to_text(&self) -> String {
+        let mut out = format!("Technique balance of '{}':\n", self.system_name);
+        let kinds: Vec<String> = self.kinds.iter().map(|(kind, count)| format!("{} {}", kind, count)).collect();
+        out.push_str(&format!("  Kinds: {}\n", kinds.join(", ")));
+        for (title, tallies) in [("role", &self.roles), ("group", &self.groups)] {
+            if tallies.is_empty() {
+                continue;
+            }
+            let width = tallies.keys().map(|name| name.chars().count()).max().unwrap_or(0).max(title.len() + 3);
+            out.push_str(&format!("\n  {:<width$}  attack  defense  other\n", format!("By {}", title), width = width));
+            for (name, tally) in tallies {
+                out.push_str(&format!(
+                    "  {:<width$}  {:>6}  {:>7}  {:>5}\n",
+                    name,
+                    tally.attack,
+                    tally.defense,
+                    tally.other,
+                    width = width
+                ));
+            }
+        }
+        if !self.unescaped.is_empty() {
+            out.push('\n');
+            for unescaped in &self.unescaped {
+                out.push_str(&format!("⚠ {}\n", unescaped));
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loader::{self, SourceFile};
+
+    #[test]
+    fn test_balance() {
+        let source = "roles { Top, Bottom }\nstate Guard\nstate Mount\nstate Back\n\
+                      group Dominant { Mount, Back }\n\
+                      sequence Armbar @kind(submission):\n    Extend: Mount[Top] -> Mount[Top]\n\
+                      sequence Choke @tag(attack):\n    Strangle: Back[Top] -> Back[Top]\n\
+                      sequence Bridge @kind(escape):\n    Upa: Mount[Bottom] -> Guard[Top]\n\
+                      sequence Sweep @kind(sweep):\n    Hip: Guard[Bottom] -> Mount[Top]\n\
+                      sequence Shrimp:\n    Hips: Guard[Top] -> Guard[Top]";
+        let system = loader::load_sources("club", &[SourceFile::new("club.martial", source)]).unwrap();
+        let report = BalanceReport::new(&system);
+
+        let kinds: Vec<(&str, usize)> = report.kinds.iter().map(|(k, n)| (k.as_str(), *n)).collect();
+        assert_eq!(kinds, [("escape", 1), ("submission", 1), ("sweep", 1), ("untyped", 2)]);
+        assert_eq!(report.roles["Top"], Tally { attack: 2, defense: 0, other: 1 });
+        assert_eq!(report.roles["Bottom"], Tally { attack: 1, defense: 1, other: 0 });
+        assert_eq!(report.groups["Dominant"], Tally { attack: 2, defense: 1, other: 0 });
+
+        // Mount[Bottom] has an escape, Back[Bottom] and Guard[Top] have none
+        let unescaped: Vec<String> = report.unescaped.iter().map(|u| u.to_string()).collect();
+        assert_eq!(
+            unescaped,
+            [
+                "No escapes defined from Back[Bottom], attacked by Choke",
+                "No escapes defined from Guard[Top], attacked by Sweep",
+            ]
+        );
+
+        let text = report.to_text();
+        assert!(text.contains("\n  By role  attack  defense  other\n  Bottom        1        1      0\n"), "{}", text);
+        assert!(text.ends_with("⚠ No escapes defined from Guard[Top], attacked by Sweep\n"), "{}", text);
+    }
+}
@@ -0,0 +1,245 @@
+//! Bottleneck transitions of a graph
+//!
+//! Techniques that are single points of failure of a game plan show up in
+//! two ways. A bridge is a transition whose removal splits the positions
+//! around it in two, ignoring direction: the positions on its far side are
+//! only connected to the rest through it. A minimum cut between two sets of
+//! positions is the fewest transitions whose removal leaves no path from
+//! the first to the second; a cut of one transition is a bottleneck of
+//! every route between them.
+//!
+//! Transitions are the edges of the graph with parallel edges merged, see
+//! [`MartialGraph::merge_parallel_edges`]. The opponent's side of a step is
+//! left out, as it follows the step itself.
+
+use crate::collections::{HashMap, HashSet};
+use crate::graph::{Edge, MartialGraph, Node};
+use crate::prelude::*;
+use alloc::collections::VecDeque;
+use serde::Serialize;
+
+/// A transition splitting the graph in two
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Bridge {
+    pub edge: Edge,
+    /// Positions of the smaller side, sorted
+    pub separated: Vec<Node>,
+}
+
+/// The transitions of `graph` to analyse, with their endpoints as indices
+/// into its nodes
+fn transitions(graph: &MartialGraph) -> (Vec<Edge>, Vec<(usize, usize)>) {
+    let index: HashMap<&Node, usize> = graph.nodes.iter().enumerate().map(|(i, node)| (node, i)).collect();
+    let edges: Vec<Edge> = graph
+        .merge_parallel_edges()
+        .edges
+        .into_iter()
+        .filter(|edge| !edge.reaction && edge.from != edge.to)
+        .filter(|edge| index.contains_key(&edge.from) && index.contains_key(&edge.to))
+        .collect();
+    let ends = edges.iter().map(|edge| (index[&edge.from], index[&edge.to])).collect();
+    (edges, ends)
+}
+
+/// Transitions whose removal disconnects the positions around them, in
+/// graph order
+pub fn bridges(graph: &MartialGraph) -> Vec<Bridge> {
+    let (edges, ends) = transitions(graph);
+    let count = graph.nodes.len();
+    let mut adjacent: Vec<Vec<(usize, usize)>> = vec![Vec::new(); count];
+    for (id, &(from, to)) in ends.iter().enumerate() {
+        adjacent[from].push((to, id));
+        adjacent[to].push((from, id));
+    }
+
+    // Tarjan's bridge finding, iteratively: an edge to a child is a bridge
+    // when nothing below the child reaches back above it. Skipping the edge
+    // by id rather than the parent node keeps parallel edges from counting.
+    let mut order = vec![usize::MAX; count];
+    let mut low = vec![0; count];
+    let mut is_bridge = vec![false; edges.len()];
+    let mut visited = 0;
+    for root in 0..count {
+        if order[root] != usize::MAX {
+            continue;
+        }
+        order[root] = visited;
+        low[root] = visited;
+        visited += 1;
+        // (node, edge it was entered by, next neighbor to look at)
+        let mut stack: Vec<(usize, usize, usize)> = vec![(root, usize::MAX, 0)];
+        while let Some(&mut (node, entered_by, ref mut next)) = stack.last_mut() {
+            if let Some(&(neighbor, id)) = adjacent[node].get(*next) {
+                *next += 1;
+                if id == entered_by {
+                    continue;
+                }
+                if order[neighbor] == usize::MAX {
+                    order[neighbor] = visited;
+                    low[neighbor] = visited;
+                    visited += 1;
+                    stack.push((neighbor, id, 0));
+                } else {
+                    low[node] = low[node].min(order[neighbor]);
+                }
+            } else {
+                stack.pop();
+                if let Some(&(parent, _, _)) = stack.last() {
+                    low[parent] = low[parent].min(low[node]);
+                    if low[node] > order[parent] {
+                        is_bridge[entered_by] = true;
+                    }
+                }
+            }
+        }
+    }
+
+    let side = |start: usize, without: usize| -> HashSet<usize> {
+        let mut seen: HashSet<usize> = HashSet::new();
+        let mut to_visit = vec![start];
+        while let Some(node) = to_visit.pop() {
+            if seen.insert(node) {
+                to_visit.extend(adjacent[node].iter().filter(|(_, id)| *id != without).map(|(n, _)| *n));
+            }
+        }
+        seen
+    };
+    edges
+        .into_iter()
+        .zip(ends)
+        .enumerate()
+        .filter(|(id, _)| is_bridge[*id])
+        .map(|(id, (edge, (from, to)))| {
+            let (near, far) = (side(from, id), side(to, id));
+            let smaller = if far.len() <= near.len() { far } else { near };
+            let mut separated: Vec<Node> = smaller.into_iter().map(|i| graph.nodes[i].clone()).collect();
+            separated.sort();
+            Bridge { edge, separated }
+        })
+        .collect()
+}
+
+/// Transition a node was reached by in a residual graph, and whether it was
+/// taken forward
+type ReachedBy = Option<(usize, bool)>;
+
+/// Fewest transitions whose removal leaves no path from the positions
+/// matching `from` to those matching `to`, given as for [`Node::matches`]
+///
+/// Empty if no path leads there already; `None` if a position matches both,
+/// as no cut separates them.
+pub fn min_cut(graph: &MartialGraph, from: &str, to: &str) -> Option<Vec<Edge>> {
+    let sources: Vec<usize> = (0..graph.nodes.len()).filter(|&i| graph.nodes[i].matches(from)).collect();
+    let is_target = |i: usize| graph.nodes[i].matches(to);
+    if sources.iter().any(|&i| is_target(i)) {
+        return None;
+    }
+    let (edges, ends) = transitions(graph);
+    let mut outgoing: Vec<Vec<usize>> = vec![Vec::new(); graph.nodes.len()];
+    let mut incoming: Vec<Vec<usize>> = vec![Vec::new(); graph.nodes.len()];
+    for (id, &(a, b)) in ends.iter().enumerate() {
+        outgoing[a].push(id);
+        incoming[b].push(id);
+    }
+
+    // Edmonds-Karp with a capacity of one per transition: each augmenting
+    // path takes unused transitions forward or used ones backward
+    let mut flow = vec![false; edges.len()];
+    let residual = |flow: &[bool]| -> (Vec<ReachedBy>, Vec<bool>, Option<usize>) {
+        let mut reached_by: Vec<ReachedBy> = vec![None; graph.nodes.len()];
+        let mut seen = vec![false; graph.nodes.len()];
+        let mut queue: VecDeque<usize> = sources.iter().copied().collect();
+        for &source in &sources {
+            seen[source] = true;
+        }
+        while let Some(node) = queue.pop_front() {
+            if is_target(node) {
+                return (reached_by, seen, Some(node));
+            }
+            let forward = outgoing[node].iter().filter(|&&id| !flow[id]).map(|&id| (ends[id].1, id, true));
+            let backward = incoming[node].iter().filter(|&&id| flow[id]).map(|&id| (ends[id].0, id, false));
+            for (next, id, forward) in forward.chain(backward) {
+                if !seen[next] {
+                    seen[next] = true;
+                    reached_by[next] = Some((id, forward));
+                    queue.push_back(next);
+                }
+            }
+        }
+        (reached_by, seen, None)
+    };
+    loop {
+        let (reached_by, seen, target) = residual(&flow);
+        let Some(mut node) = target else {
+            // The cut leaves the side still reached from the sources
+            return Some(
+                edges
+                    .into_iter()
+                    .zip(ends)
+                    .filter(|(_, (a, b))| seen[*a] && !seen[*b])
+                    .map(|(edge, _)| edge)
+                    .collect(),
+            );
+        };
+        while let Some((id, forward)) = reached_by[node] {
+            flow[id] = forward;
+            node = if forward { ends[id].0 } else { ends[id].1 };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loader::{self, SourceFile};
+
+    fn graph(sequences: &str) -> MartialGraph {
+        let source = format!("roles {{ Top, Bottom }}\nstate Guard\nstate Half\nstate Mount\nstate Back\nstate Armbar\n{}", sequences);
+        let system = loader::load_sources("club", &[SourceFile::new("club.martial", source)]).unwrap();
+        MartialGraph::from_system(&system)
+    }
+
+    #[test]
+    fn test_bridges() {
+        let graph = graph(
+            "sequence Sweep:\n    Hip: Guard[Bottom] -> Mount[Top]\n    Take: Mount[Top] -> Back[Top]\n\
+             sequence Lose:\n    Roll: Back[Top] -> Guard[Bottom]\n\
+             sequence Finish:\n    Extend: Mount[Top] -> Armbar[Top]\n\
+             sequence Again:\n    Extend: Mount[Top] -> Armbar[Top]",
+        );
+        // The cycle has no bridge, and the same action from two sequences is one transition
+        let found = bridges(&graph);
+        assert_eq!(found.len(), 1);
+        assert_eq!((found[0].edge.action.as_str(), found[0].edge.sequences.len()), ("Extend", 2));
+        assert_eq!(found[0].separated, [Node::new("Armbar", "Top")]);
+
+        // A second route makes it redundant
+        let graph = graph_with_route();
+        assert!(bridges(&graph).is_empty());
+    }
+
+    fn graph_with_route() -> MartialGraph {
+        graph(
+            "sequence Finish:\n    Extend: Mount[Top] -> Armbar[Top]\n\
+             sequence Scramble:\n    Grab: Armbar[Top] -> Mount[Top]",
+        )
+    }
+
+    #[test]
+    fn test_min_cut() {
+        let graph = graph(
+            "sequence A:\n    Hip: Guard[Bottom] -> Mount[Top]\n    Take: Mount[Top] -> Back[Top]\n\
+             sequence B:\n    Sit: Guard[Bottom] -> Half[Bottom]\n    Up: Half[Bottom] -> Mount[Top]",
+        );
+        // Both routes from guard go through Take
+        let cut = min_cut(&graph, "Guard", "Back").unwrap();
+        let actions: Vec<&str> = cut.iter().map(|edge| edge.action.as_str()).collect();
+        assert_eq!(actions, ["Take"]);
+        // Two routes lead to mount, cut closest to the start
+        let cut = min_cut(&graph, "Guard", "Mount[Top]").unwrap();
+        let actions: Vec<&str> = cut.iter().map(|edge| edge.action.as_str()).collect();
+        assert_eq!(actions, ["Hip", "Sit"]);
+        assert!(min_cut(&graph, "Back", "Guard").unwrap().is_empty());
+        assert!(min_cut(&graph, "Guard", "Guard[Bottom]").is_none());
+    }
+}
@@ -0,0 +1,531 @@
+//! Exports of `mat build`
+//!
+//! The project file (see [`crate::config`]) lists the exports to keep in
+//! sync with the system:
+//!
+//! ```toml
+//! # Directory of the .martial files, relative to this file (default ".")
+//! source = "system"
+//!
+//! [[export]]
+//! format = "dot"
+//! output = "docs/graph.dot"
+//!
+//! [[export]]
+//! format = "mermaid"
+//! output = "README.md"
+//! snippet = "graph"
+//! level = "Blue"
+//!
+//! [[export]]
+//! format = "json"
+//! output = "site/data.json"
+//! ```
+//!
+//! An export with a `snippet` replaces only the part of its output between
+//! `<!-- mat:NAME -->` and `<!-- /mat:NAME -->`, so generated content can
+//! live inside hand-written Markdown. `mat build` runs every export.
+//!
+//! An export with a `name` is a preset that `mat export` runs on its own.
+//! Besides `level` and `role`, presets filter the graph to what is within
+//! `depth` transitions of `start` and can `merge_edges`; `style` sets DOT
+//! graph attributes, or the `direction` of a Mermaid flowchart:
+//!
+//! ```toml
+//! [[export]]
+//! name = "guard"
+//! format = "dot"
+//! output = "docs/guard.dot"
+//! start = "ClosedGuard[Bottom]"
+//! depth = 2
+//! style = { rankdir = "TB", bgcolor = "white" }
+//! ```
+//!
+//! Formats are [`Exporter`]s found by name in an [`ExporterRegistry`];
+//! applications register their own next to the built-in ones, or add a
+//! [`GraphExporter`] with [`ExporterRegistry::register_graph`].
+//!
+//! ```
+//! use martial_lang::build::{ExportTarget, Exporter, ExporterRegistry};
+//! use martial_lang::graph::MartialGraph;
+//! use martial_lang::semantic::MartialSystem;
+//!
+//! /// One line per transition
+//! struct Lines;
+//!
+//! impl Exporter for Lines {
+//!     fn name(&self) -> &'static str {
+//!         "lines"
+//!     }
+//!
+//!     fn export(&self, _: &MartialSystem, graph: &MartialGraph, _: &ExportTarget) -> Result<String, String> {
+//!         Ok(graph.edges.iter().map(|e| format!("{} -> {}\n", e.from.id(), e.to.id())).collect())
+//!     }
+//! }
+//!
+//! let mut registry = ExporterRegistry::builtin();
+//! registry.register(Box::new(Lines));
+//! assert!(registry.get("lines").is_some());
+//! ```
+
+use crate::config::Config;
+use crate::doc;
+use crate::export::{ExportOptions, GraphExporter, GraphExporterRegistry};
+use crate::graph::MartialGraph;
+use crate::semantic::MartialSystem;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A format exports are rendered in
+pub trait Exporter: Send + Sync {
+    /// Name given as the `format` of an export
+    fn name(&self) -> &'static str;
+
+    /// Info string of a fenced code block holding the export, if it is not
+    /// Markdown itself
+    fn fence(&self) -> Option<&'static str> {
+        None
+    }
+
+    /// The export of `system`, whose graph already has the filters of
+    /// `target` applied
+    fn export(&self, system: &MartialSystem, graph: &MartialGraph, target: &ExportTarget) -> Result<String, String>;
+}
+
+/// Exporters by name
+pub struct ExporterRegistry {
+    exporters: Vec<Box<dyn Exporter>>,
+}
+
+impl ExporterRegistry {
+    /// A registry without any exporter
+    pub fn new() -> Self {
+        ExporterRegistry { exporters: Vec::new() }
+    }
+
+    /// A registry with the built-in formats: the graph formats of
+    /// [`GraphExporterRegistry::builtin`], `system` and `markdown`
+    pub fn builtin() -> Self {
+        let mut registry = ExporterRegistry::new();
+        for exporter in GraphExporterRegistry::builtin().into_exporters() {
+            registry.register_graph(exporter);
+        }
+        for format in ExportFormat::ALL {
+            registry.register(Box::new(format));
+        }
+        registry
+    }
+
+    /// Add `exporter`, replacing any exporter of the same name
+    pub fn register(&mut self, exporter: Box<dyn Exporter>) {
+        match self.exporters.iter_mut().find(|e| e.name() == exporter.name()) {
+            Some(slot) => *slot = exporter,
+            None => self.exporters.push(exporter),
+        }
+    }
+
+    /// Add a graph format, replacing any exporter of the same name
+    pub fn register_graph(&mut self, exporter: Box<dyn GraphExporter>) {
+        self.register(Box::new(GraphFormat(exporter)));
+    }
+
+    pub fn get(&self, name: &str) -> Option<&dyn Exporter> {
+        self.exporters.iter().find(|e| e.name() == name).map(|e| e.as_ref())
+    }
+
+    /// Names of the exporters, in registration order
+    pub fn names(&self) -> Vec<&'static str> {
+        self.exporters.iter().map(|e| e.name()).collect()
+    }
+}
+
+impl Default for ExporterRegistry {
+    fn default() -> Self {
+        ExporterRegistry::builtin()
+    }
+}
+
+/// Built-in exports of the whole system rather than its graph, named as
+/// the commands producing them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// The validated system, as `mat system`
+    System,
+    /// The handbook, as `mat doc`
+    Markdown,
+}
+
+impl ExportFormat {
+    pub const ALL: [ExportFormat; 2] = [ExportFormat::System, ExportFormat::Markdown];
+}
+
+impl Exporter for ExportFormat {
+    fn name(&self) -> &'static str {
+        match self {
+            ExportFormat::System => "system",
+            ExportFormat::Markdown => "markdown",
+        }
+    }
+
+    fn fence(&self) -> Option<&'static str> {
+        match self {
+            ExportFormat::System => Some("json"),
+            ExportFormat::Markdown => None,
+        }
+    }
+
+    fn export(&self, system: &MartialSystem, _: &MartialGraph, _: &ExportTarget) -> Result<String, String> {
+        Ok(match self {
+            ExportFormat::System => system.to_json().map_err(|e| e.to_string())? + "\n",
+            ExportFormat::Markdown => doc::to_markdown(system),
+        })
+    }
+}
+
+/// A [`GraphExporter`] run as an export, fenced under its name and with the
+/// `style` of the export
+struct GraphFormat(Box<dyn GraphExporter>);
+
+impl Exporter for GraphFormat {
+    fn name(&self) -> &'static str {
+        self.0.name()
+    }
+
+    fn fence(&self) -> Option<&'static str> {
+        Some(self.0.name())
+    }
+
+    fn export(&self, _: &MartialSystem, graph: &MartialGraph, target: &ExportTarget) -> Result<String, String> {
+        let options = ExportOptions {
+            style: target.style.clone(),
+        };
+        let mut content = self.0.export(graph, &options)?;
+        if !content.ends_with('\n') {
+            content.push('\n');
+        }
+        Ok(content)
+    }
+}
+
+/// One export of a project
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExportTarget {
+    /// Name of the preset, to run it alone with `mat export`
+    pub name: Option<String>,
+    /// Name of its [`Exporter`]
+    pub format: String,
+    /// File to write, relative to the project file
+    pub output: String,
+    /// Marker name of the region of `output` to replace, if not all of it
+    pub snippet: Option<String>,
+    /// Only export what is known at this level
+    pub level: Option<String>,
+    /// Export the graph from the point of view of this role
+    pub role: Option<String>,
+    /// Only export the graph reachable from this state or position
+    pub start: Option<String>,
+    /// Only export what is within this many transitions of `start`
+    pub depth: Option<usize>,
+    /// Draw parallel edges as one
+    #[serde(default)]
+    pub merge_edges: bool,
+    /// Styling handed to the exporter
+    #[serde(default)]
+    pub style: BTreeMap<String, String>,
+}
+
+impl ExportTarget {
+    /// The export of `system` in a built-in format
+    pub fn render(&self, system: &MartialSystem) -> Result<String, BuildError> {
+        self.render_with(system, &ExporterRegistry::builtin())
+    }
+
+    /// The export of `system`, with its format found in `exporters`
+    pub fn render_with(&self, system: &MartialSystem, exporters: &ExporterRegistry) -> Result<String, BuildError> {
+        let error = |message: String| BuildError::new(message, Some(&self.output));
+        let exporter = exporters.get(&self.format).ok_or_else(|| {
+            error(format!("unknown format '{}', expected one of {}", self.format, exporters.names().join(", ")))
+        })?;
+        let leveled;
+        let system = match &self.level {
+            Some(level) => {
+                leveled = system
+                    .at_level(level)
+                    .ok_or_else(|| error(format!("unknown level '{}'", level)))?;
+                &leveled
+            }
+            None => system,
+        };
+
+        let mut graph = MartialGraph::from_system(system);
+        match &self.role {
+            Some(role) if system.roles.contains(role) => graph = graph.project_role_perspective(role),
+            Some(role) => return Err(error(format!("unknown role '{}'", role))),
+            None => {}
+        }
+        match (&self.start, self.depth) {
+            (Some(start), depth) if graph.nodes.iter().any(|n| n.matches(start)) => graph = graph.within(start, depth),
+            (Some(start), _) => return Err(error(format!("unknown start '{}'", start))),
+            (None, Some(_)) => return Err(error("depth requires a start".to_string())),
+            (None, None) => {}
+        }
+        if self.merge_edges {
+            graph = graph.merge_parallel_edges();
+        }
+
+        exporter.export(system, &graph, self).map_err(error)
+    }
+}
+
+/// Error raised while running an export
+#[derive(Debug, Clone, PartialEq)]
+pub struct BuildError {
+    pub message: String,
+    /// Output of the export the error refers to, if any
+    pub output: Option<String>,
+}
+
+impl BuildError {
+    fn new(message: impl Into<String>, output: Option<&str>) -> Self {
+        BuildError {
+            message: message.into(),
+            output: output.map(String::from),
+        }
+    }
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.output {
+            Some(output) => write!(f, "Build error in {}: {}", output, self.message),
+            None => write!(f, "Build error: {}", self.message),
+        }
+    }
+}
+
+impl core::error::Error for BuildError {}
+
+/// Replace the region of `document` marked `name` with `content`
+pub fn splice(document: &str, name: &str, content: &str) -> Option<String> {
+    let open = format!("<!-- mat:{} -->", name);
+    let close = format!("<!-- /mat:{} -->", name);
+    let start = document.find(&open)? + open.len();
+    let end = start + document[start..].find(&close)?;
+
+    let mut spliced = String::with_capacity(document.len() + content.len());
+    spliced.push_str(&document[..start]);
+    spliced.push('\n');
+    spliced.push_str(content);
+    if !content.ends_with('\n') {
+        spliced.push('\n');
+    }
+    spliced.push_str(&document[end..]);
+    Some(spliced)
+}
+
+/// A file produced by a build
+#[derive(Debug, Clone, PartialEq)]
+pub struct Artifact {
+    pub path: PathBuf,
+    pub content: String,
+    /// Whether `content` differs from what is on disk
+    pub changed: bool,
+}
+
+impl Artifact {
+    /// Write the file, creating its directory if needed
+    pub fn write(&self) -> std::io::Result<()> {
+        if let Some(parent) = self.path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&self.path, &self.content)
+    }
+}
+
+/// Render every export of `config`, with outputs relative to `root`
+///
+/// Nothing is written; exports into a snippet read the current file.
+pub fn build(root: &Path, config: &Config, system: &MartialSystem) -> Result<Vec<Artifact>, BuildError> {
+    build_with(root, &config.exports, system, &ExporterRegistry::builtin())
+}
+
+/// Render `targets`, with their formats found in `exporters`
+pub fn build_with<'a>(
+    root: &Path,
+    targets: impl IntoIterator<Item = &'a ExportTarget>,
+    system: &MartialSystem,
+    exporters: &ExporterRegistry,
+) -> Result<Vec<Artifact>, BuildError> {
+    let mut artifacts = Vec::new();
+    for target in targets {
+        let path = root.join(&target.output);
+        let current = fs::read_to_string(&path).ok();
+        let mut content = target.render_with(system, exporters)?;
+
+        if let Some(name) = &target.snippet {
+            let document = current
+                .as_deref()
+                .ok_or_else(|| BuildError::new("cannot read the file holding the snippet", Some(&target.output)))?;
+            if let Some(fence) = exporters.get(&target.format).and_then(|e| e.fence()) {
+                content = format!("```{}\n{}```", fence, content);
+            }
+            content = splice(document, name, &content).ok_or_else(|| {
+                let message = format!("no <!-- mat:{0} --> ... <!-- /mat:{0} --> markers", name);
+                BuildError::new(message, Some(&target.output))
+            })?;
+        }
+
+        artifacts.push(Artifact {
+            changed: current.as_deref() != Some(content.as_str()),
+            path,
+            content,
+        });
+    }
+    Ok(artifacts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loader::{load_sources, SourceFile};
+
+    fn system() -> MartialSystem {
+        let source = "roles { Top, Bottom }\nlevels { White, Blue }\n\
+                      state Guard\nstate Mount @level(Blue)\n\
+                      sequence Sweep:\n    Hip: Guard[Bottom] -> Mount[Top]";
+        load_sources("club", &[SourceFile::new("a.martial", source)]).unwrap()
+    }
+
+    #[test]
+    fn test_parse_exports() {
+        let config = Config::parse(
+            "[[export]]\nformat = \"dot\"\noutput = \"docs/graph.dot\"\n\n\
+             [[export]]\nformat = \"mermaid\"\noutput = \"README.md\"\nsnippet = \"graph\"\nrole = \"Top\"\n",
+        )
+        .unwrap();
+        assert_eq!(config.exports.len(), 2);
+        assert_eq!(config.exports[0].format, "dot");
+        assert_eq!(config.exports[1].snippet.as_deref(), Some("graph"));
+        assert_eq!(config.exports[1].role.as_deref(), Some("Top"));
+
+        let error = Config::parse("[[export]]\nformat = \"png\"\noutput = \"a.png\"\n").unwrap().exports[0]
+            .render(&system())
+            .unwrap_err();
+        assert!(error.message.contains("unknown format 'png'"), "{}", error);
+        assert!(Config::parse("[[export]]\nformat = \"dot\"\n").is_err());
+    }
+
+    #[test]
+    fn test_presets() {
+        let config = Config::parse(
+            "[[export]]\nname = \"guard\"\nformat = \"dot\"\noutput = \"guard.dot\"\n\
+             start = \"Guard\"\ndepth = 0\nstyle = { rankdir = \"TB\", bgcolor = \"white\" }\n\n\
+             [[export]]\nformat = \"mermaid\"\noutput = \"graph.md\"\nstyle = { direction = \"TD\" }\n",
+        )
+        .unwrap();
+        let guard = config.preset("guard").unwrap();
+        let dot = guard.render(&system()).unwrap();
+        assert!(dot.starts_with("digraph \"club\" {\n  bgcolor=\"white\";\n  rankdir=\"TB\";\n  node"), "{}", dot);
+        assert!(!dot.contains("Hip"), "{}", dot);
+        assert!(config.exports[1].render(&system()).unwrap().starts_with("flowchart TD\n"));
+        assert!(config.preset("graph").is_none());
+
+        let duplicate = "[[export]]\nname = \"a\"\nformat = \"dot\"\noutput = \"a\"\n\n\
+                         [[export]]\nname = \"a\"\nformat = \"json\"\noutput = \"b\"\n";
+        assert!(Config::parse(duplicate).unwrap_err().message.contains("'a' is declared more than once"));
+
+        let mut unknown = guard.clone();
+        unknown.start = Some("Back".to_string());
+        assert_eq!(unknown.render(&system()).unwrap_err().message, "unknown start 'Back'");
+    }
+
+    #[test]
+    fn test_custom_exporter() {
+        struct Count;
+
+        impl Exporter for Count {
+            fn name(&self) -> &'static str {
+                "count"
+            }
+
+            fn export(&self, _: &MartialSystem, graph: &MartialGraph, _: &ExportTarget) -> Result<String, String> {
+                Ok(graph.edges.len().to_string())
+            }
+        }
+
+        let mut exporters = ExporterRegistry::builtin();
+        exporters.register(Box::new(Count));
+        let target = ExportTarget {
+            format: "count".to_string(),
+            output: "count.txt".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(target.render_with(&system(), &exporters).unwrap(), "1");
+        assert!(target.render(&system()).is_err());
+        assert_eq!(exporters.names().last(), Some(&"count"));
+    }
+
+    #[test]
+    fn test_render() {
+        let system = system();
+        let target = |format: &str, level: Option<&str>| ExportTarget {
+            format: format.to_string(),
+            output: "out".to_string(),
+            level: level.map(String::from),
+            ..Default::default()
+        };
+        assert!(target("dot", None).render(&system).unwrap().contains("Hip"));
+        assert!(target("mermaid", None).render(&system).unwrap().contains("-->|Hip|"));
+        assert!(!target("json", Some("White")).render(&system).unwrap().contains("Hip"));
+
+        let error = target("dot", Some("Black")).render(&system).unwrap_err();
+        assert_eq!(error.to_string(), "Build error in out: unknown level 'Black'");
+        let mut by_role = target("json", None);
+        by_role.role = Some("Referee".to_string());
+        assert!(by_role.render(&system).is_err());
+    }
+
+    #[test]
+    fn test_splice() {
+        let document = "# Club\n<!-- mat:graph -->\nold\n<!-- /mat:graph -->\nmore\n";
+        assert_eq!(
+            splice(document, "graph", "new").unwrap(),
+            "# Club\n<!-- mat:graph -->\nnew\n<!-- /mat:graph -->\nmore\n"
+        );
+        assert_eq!(splice(document, "stats", "new"), None);
+        assert_eq!(splice("<!-- /mat:graph --><!-- mat:graph -->", "graph", "new"), None);
+    }
+
+    #[test]
+    fn test_build() {
+        let root = std::env::temp_dir().join(format!("martial-build-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("README.md"), "# Club\n<!-- mat:graph -->\n<!-- /mat:graph -->\n").unwrap();
+        let config = Config::parse(
+            "[[export]]\nformat = \"dot\"\noutput = \"docs/graph.dot\"\n\n\
+             [[export]]\nformat = \"mermaid\"\noutput = \"README.md\"\nsnippet = \"graph\"\n",
+        )
+        .unwrap();
+
+        let artifacts = build(&root, &config, &system()).unwrap();
+        assert!(artifacts.iter().all(|a| a.changed));
+        assert!(artifacts[1].content.starts_with("# Club\n<!-- mat:graph -->\n```mermaid\nflowchart LR\n"));
+        assert!(artifacts[1].content.ends_with("```\n<!-- /mat:graph -->\n"), "{}", artifacts[1].content);
+        for artifact in &artifacts {
+            artifact.write().unwrap();
+        }
+        assert!(root.join("docs/graph.dot").exists());
+
+        let rebuilt = build(&root, &config, &system()).unwrap();
+        assert!(!rebuilt[1].changed);
+
+        fs::write(root.join("README.md"), "# Club\n").unwrap();
+        let error = build(&root, &config, &system()).unwrap_err();
+        assert_eq!(error.output.as_deref(), Some("README.md"));
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,228 @@
+//! Structural comparison of two systems
+//!
+//! `mat compare` matches the graphs of two versions of a style, such as
+//! those of two coaches, by position and by transition rather than by the
+//! text of their files. A transition is an action from one position to
+//! another, whichever sequence declares it. The comparison counts what the
+//! graphs share and what only one of them has, with the Jaccard similarity
+//! of their positions and of their transitions, and draws both graphs
+//! merged with each node and edge colored by where it comes from.
+
+use crate::graph::{Edge, MartialGraph, Node};
+use crate::prelude::*;
+use alloc::collections::BTreeSet;
+use serde::Serialize;
+
+/// Color of what only the first graph has
+pub const LEFT_COLOR: &str = "blue";
+
+/// Color of what only the second graph has
+pub const RIGHT_COLOR: &str = "red";
+
+/// An action from one position to another, as `Guard[Bottom] -> Mount[Top] via Sweep`
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub struct Transition {
+    pub from: String,
+    pub to: String,
+    pub action: String,
+}
+
+impl Transition {
+    fn of(edge: &Edge) -> Self {
+        Transition {
+            from: edge.from.id(),
+            to: edge.to.id(),
+            action: edge.action.to_string(),
+        }
+    }
+}
+
+impl core::fmt::Display for Transition {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "{} -> {} via {}", self.from, self.to, self.action)
+    }
+}
+
+/// How the items of two graphs overlap
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Overlap<T> {
+    pub shared: Vec<T>,
+    /// Items of the first graph only
+    pub left: Vec<T>,
+    /// Items of the second graph only
+    pub right: Vec<T>,
+    /// Shared items over all items, 1 if neither graph has any
+    pub jaccard: f64,
+}
+
+impl<T: Ord + Clone> Overlap<T> {
+    fn new(left: BTreeSet<T>, right: BTreeSet<T>) -> Self {
+        let shared: Vec<T> = left.intersection(&right).cloned().collect();
+        let union = left.union(&right).count();
+        Overlap {
+            jaccard: if union == 0 { 1.0 } else { shared.len() as f64 / union as f64 },
+            left: left.difference(&right).cloned().collect(),
+            right: right.difference(&left).cloned().collect(),
+            shared,
+        }
+    }
+}
+
+/// Comparison of two graphs
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Comparison {
+    /// Names of the systems compared
+    pub left: String,
+    pub right: String,
+    /// Positions, by id
+    pub nodes: Overlap<String>,
+    pub transitions: Overlap<Transition>,
+}
+
+impl Comparison {
+    pub fn new(left: &MartialGraph, right: &MartialGraph) -> Self {
+        let nodes = |graph: &MartialGraph| graph.nodes.iter().map(Node::id).collect();
+        let transitions = |graph: &MartialGraph| graph.edges.iter().map(Transition::of).collect();
+        Comparison {
+            left: left.system_name.clone(),
+            right: right.system_name.clone(),
+            nodes: Overlap::new(nodes(left), nodes(right)),
+            transitions: Overlap::new(transitions(left), transitions(right)),
+        }
+    }
+
+    /// Export as JSON
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string_pretty(self)
+    }
+
+    /// Counts and similarities, then what each graph has alone
+    pub fn to_text(&self) -> String {
+        let mut out = format!("Comparing '{}' with '{}'\n", self.left, self.right);
+        let counts = |kind: &str, shared: usize, left: usize, right: usize, jaccard: f64| {
+            format!(
+                "  {}: {} shared, {} only in {}, {} only in {} (Jaccard {:.2})\n",
+                kind, shared, left, self.left, right, self.right, jaccard
+            )
+        };
+        let (nodes, transitions) = (&self.nodes, &self.transitions);
+        out.push_str(&counts("Positions", nodes.shared.len(), nodes.left.len(), nodes.right.len(), nodes.jaccard));
+        out.push_str(&counts(
+            "Transitions",
+            transitions.shared.len(),
+            transitions.left.len(),
+            transitions.right.len(),
+            transitions.jaccard,
+        ));
+        for (name, nodes, transitions) in [
+            (&self.left, &nodes.left, &transitions.left),
+            (&self.right, &nodes.right, &transitions.right),
+        ] {
+            if nodes.is_empty() && transitions.is_empty() {
+                continue;
+            }
+            out.push_str(&format!("\nOnly in {}:\n", name));
+            for node in nodes {
+                out.push_str(&format!("  - {}\n", node));
+            }
+            for transition in transitions {
+                out.push_str(&format!("  - {}\n", transition));
+            }
+        }
+        out
+    }
+
+    /// Both graphs merged as DOT: what the first has alone is drawn in
+    /// [`LEFT_COLOR`], what the second has alone in [`RIGHT_COLOR`], and what
+    /// they share in black
+    ///
+    /// Nodes, edges and groups of the first graph come first; an edge of the
+    /// second is added if no edge of the first has its transition.
+    pub fn to_dot(&self, left: &MartialGraph, right: &MartialGraph) -> String {
+        let mut merged = left.clone();
+        merged.system_name = format!("{} vs {}", self.left, self.right);
+        for node in &right.nodes {
+            if !merged.nodes.contains(node) {
+                merged.nodes.push(node.clone());
+            }
+        }
+        let known: BTreeSet<Transition> = left.edges.iter().map(Transition::of).collect();
+        merged
+            .edges
+            .extend(right.edges.iter().filter(|edge| !known.contains(&Transition::of(edge))).cloned());
+        for (group, states) in &right.groups {
+            merged.groups.entry(group.clone()).or_insert_with(|| states.clone());
+        }
+
+        let color = |left: bool, right: bool| match (left, right) {
+            (true, false) => format!("color={}, fontcolor={}", LEFT_COLOR, LEFT_COLOR),
+            (false, true) => format!("color={}, fontcolor={}", RIGHT_COLOR, RIGHT_COLOR),
+            _ => String::new(),
+        };
+        merged.to_dot_styled(
+            |node| {
+                let id = node.id();
+                color(self.nodes.left.contains(&id), self.nodes.right.contains(&id))
+            },
+            |edge| {
+                let transition = Transition::of(edge);
+                let provenance = color(
+                    self.transitions.left.binary_search(&transition).is_ok(),
+                    self.transitions.right.binary_search(&transition).is_ok(),
+                );
+                let mut attributes = format!("label=\"{}\"", edge.action);
+                if !provenance.is_empty() {
+                    attributes.push_str(", ");
+                    attributes.push_str(&provenance);
+                }
+                if edge.reaction {
+                    attributes.push_str(", style=dashed");
+                }
+                attributes
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loader::{self, SourceFile};
+
+    fn graph(name: &str, sequences: &str) -> MartialGraph {
+        let content = format!("roles {{ Top, Bottom }}\nstate Guard\nstate Mount\nstate Back\n{}", sequences);
+        let system = loader::load_sources(name, &[SourceFile::new("club.martial", content)]).unwrap();
+        MartialGraph::from_system(&system)
+    }
+
+    #[test]
+    fn test_compare() {
+        let left = graph("ana", "sequence Sweep:\n    Hip: Guard[Bottom] -> Mount[Top]");
+        let right = graph(
+            "ben",
+            "sequence HipSweep:\n    Hip: Guard[Bottom] -> Mount[Top]\n\
+             sequence Take:\n    Hook: Mount[Top] -> Back[Top]",
+        );
+        let comparison = Comparison::new(&left, &right);
+        // The same action between the same positions is shared, whatever the sequence
+        assert_eq!(comparison.transitions.shared.len(), 1);
+        assert_eq!(comparison.transitions.right[0].to_string(), "Mount[Top] -> Back[Top] via Hook");
+        assert_eq!(comparison.nodes.right, ["Back[Top]"]);
+        assert!(comparison.nodes.left.is_empty());
+        assert_eq!((comparison.nodes.jaccard, comparison.transitions.jaccard), (2.0 / 3.0, 0.5));
+
+        let text = comparison.to_text();
+        assert!(text.contains("  Transitions: 1 shared, 0 only in ana, 1 only in ben (Jaccard 0.50)\n"), "{}", text);
+        assert!(text.ends_with("Only in ben:\n  - Back[Top]\n  - Mount[Top] -> Back[Top] via Hook\n"), "{}", text);
+
+        let dot = comparison.to_dot(&left, &right);
+        assert!(dot.starts_with("digraph \"ana vs ben\" {"));
+        assert!(dot.contains("\"Back[Top]\" [label=\"Back\\n[Top]\", color=red, fontcolor=red];"), "{}", dot);
+        assert!(dot.contains("\"Guard[Bottom]\" -> \"Mount[Top]\" [label=\"Hip\"];"), "{}", dot);
+        assert!(dot.contains("\"Mount[Top]\" -> \"Back[Top]\" [label=\"Hook\", color=red, fontcolor=red];"));
+        assert_eq!(dot.matches("[label=\"Hip\"").count(), 1);
+
+        let same = Comparison::new(&left, &left);
+        assert_eq!((same.nodes.jaccard, same.transitions.jaccard), (1.0, 1.0));
+    }
+}
@@ -0,0 +1,379 @@
+//! Completion suggestions for editor integrations
+//!
+//! Given the sources of a system (including the partially edited one) and a
+//! cursor position, suggests the keywords and identifiers that are valid at
+//! that point. Declarations are collected from tokens rather than the AST so
+//! that files which do not parse yet still contribute.
+
+use crate::ast::AssertKind;
+use crate::lexer::{Lexer, Position, PositionedToken, Token};
+use crate::loader::SourceFile;
+use serde::Serialize;
+
+/// Keywords that can start a declaration
+pub const KEYWORDS: [&str; 12] = [
+    "roles", "state", "sequence", "group", "levels", "term", "ruleset", "template", "mirror", "mirrored",
+    "alternating", "assert",
+];
+
+/// What a completion item refers to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CompletionKind {
+    Keyword,
+    Role,
+    State,
+    Sequence,
+    Group,
+    Action,
+}
+
+/// A single completion suggestion
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CompletionItem {
+    pub label: String,
+    pub kind: CompletionKind,
+}
+
+/// Declarations found in a set of sources
+#[derive(Debug, Default)]
+struct Symbols {
+    roles: Vec<String>,
+    /// States with their role restrictions, if any
+    states: Vec<(String, Option<Vec<String>>)>,
+    actions: Vec<String>,
+    sequences: Vec<String>,
+    groups: Vec<String>,
+}
+
+impl Symbols {
+    fn collect(sources: &[SourceFile]) -> Self {
+        let mut symbols = Symbols::default();
+
+        for source in sources {
+            let tokens = match Lexer::new(&source.content).tokenize() {
+                Ok(tokens) => tokens,
+                Err(_) => continue,
+            };
+
+            let mut i = 0;
+            while i < tokens.len() {
+                match (&tokens[i].token, tokens.get(i + 1).map(|t| &t.token)) {
+                    (Token::Roles, Some(Token::LeftBrace)) => {
+                        let (roles, next) = identifier_list(&tokens, i + 2);
+                        for role in roles {
+                            push_unique(&mut symbols.roles, role);
+                        }
+                        i = next;
+                        continue;
+                    }
+                    (Token::State, Some(Token::Identifier(name))) => {
+                        let allowed = match tokens.get(i + 2).map(|t| &t.token) {
+                            Some(Token::Roles) => Some(identifier_list(&tokens, i + 4).0),
+                            _ => None,
+                        };
+                        if !symbols.states.iter().any(|(s, _)| s == name) {
+                            symbols.states.push((name.to_string(), allowed));
+                        }
+                    }
+                    (Token::Sequence, Some(Token::Identifier(name))) => {
+                        push_unique(&mut symbols.sequences, name.to_string());
+                        i += 3; // skip the header so its colon is not taken for a step
+                        continue;
+                    }
+                    (Token::Group, Some(Token::Identifier(name))) => {
+                        push_unique(&mut symbols.groups, name.to_string());
+                    }
+                    (Token::Identifier(action), Some(Token::Colon)) => {
+                        push_unique(&mut symbols.actions, action.to_string());
+                    }
+                    _ => {}
+                }
+                i += 1;
+            }
+        }
+
+        symbols
+    }
+}
+
+/// Identifiers of a `{ A, B, ... }` list starting at `start`, and the index after it
+fn identifier_list(tokens: &[PositionedToken], start: usize) -> (Vec<String>, usize) {
+    let mut names = Vec::new();
+    let mut i = start;
+    while let Some(token) = tokens.get(i) {
+        match &token.token {
+            Token::Identifier(name) => names.push(name.to_string()),
+            Token::Comma => {}
+            _ => break,
+        }
+        i += 1;
+    }
+    (names, i)
+}
+
+fn push_unique(list: &mut Vec<String>, name: String) {
+    if !list.contains(&name) {
+        list.push(name);
+    }
+}
+
+/// Syntactic slot the cursor is in
+#[derive(Debug, PartialEq)]
+enum Slot {
+    /// Start of a declaration
+    Declaration,
+    /// A new name is being declared; nothing to suggest
+    NewName,
+    /// Inside `State[...]`, with the state name if known
+    Role(Option<String>),
+    /// Inside the role list of a state declaration
+    RoleList,
+    /// A state position (group member, step target or assertion)
+    State,
+    /// Start of a step, which may be a state or a group
+    Source,
+    /// Start of a sequence step
+    Action,
+    /// Inside a ruleset body
+    Forbidden,
+    /// Kind of an assertion, after `assert`
+    Assertion,
+}
+
+/// Determine the slot from the tokens preceding the cursor
+fn slot(tokens: &[PositionedToken]) -> Slot {
+    let mut open: Option<Slot> = None;
+    let mut in_sequence = false;
+
+    for (i, token) in tokens.iter().enumerate() {
+        let prev = |n: usize| i.checked_sub(n).map(|j| &tokens[j].token);
+        match &token.token {
+            Token::LeftBracket => {
+                let state = match prev(1) {
+                    Some(Token::Identifier(name)) => Some(name.to_string()),
+                    _ => None,
+                };
+                open = Some(Slot::Role(state));
+            }
+            Token::LeftBrace => {
+                open = match (prev(1), prev(2), prev(3)) {
+                    (Some(Token::Roles), Some(Token::Identifier(_)), Some(Token::State)) => Some(Slot::RoleList),
+                    (Some(Token::Roles), Some(Token::Mirror), _) => Some(Slot::RoleList),
+                    (Some(Token::Roles | Token::Levels | Token::Suffixes), _, _) => Some(Slot::NewName),
+                    (Some(Token::Identifier(_)), Some(Token::Group), _) => Some(Slot::State),
+                    (Some(Token::Identifier(_)), Some(Token::Ruleset), _) => Some(Slot::Forbidden),
+                    _ => None,
+                };
+            }
+            Token::RightBracket | Token::RightBrace => open = None,
+            Token::Sequence | Token::Template => in_sequence = true,
+            Token::Roles | Token::State | Token::Group | Token::Levels | Token::Term | Token::Ruleset | Token::Mirror | Token::Assert => {
+                in_sequence = false
+            }
+            _ => {}
+        }
+    }
+
+    if let Some(slot) = open {
+        return slot;
+    }
+
+    let last = tokens.last().map(|t| &t.token);
+    let before_last = tokens.len().checked_sub(2).map(|i| &tokens[i].token);
+    let third_last = tokens.len().checked_sub(3).map(|i| &tokens[i].token);
+
+    match (last, before_last, third_last) {
+        (
+            Some(
+                Token::State
+                | Token::Sequence
+                | Token::Group
+                | Token::Term
+                | Token::Ruleset
+                | Token::Template
+                | Token::At
+                | Token::LeftParen,
+            ),
+            _,
+            _,
+        ) => Slot::NewName,
+        (Some(Token::Assert), _, _) => Slot::Assertion,
+        (Some(Token::Arrow | Token::Slash), _, _) | (Some(Token::Identifier(_)), Some(Token::Assert), _) => Slot::State,
+        (Some(Token::Colon), Some(Token::Identifier(_)), Some(Token::Sequence)) => Slot::Action,
+        (Some(Token::Colon), _, _) => Slot::Source,
+        (Some(Token::RightBracket | Token::RightParen), _, _) if in_sequence => Slot::Action,
+        _ => Slot::Declaration,
+    }
+}
+
+/// Suggest completions at `position` (1-based) in the source at `path`
+///
+/// `sources` should contain every file of the system, including the
+/// partially edited one.
+pub fn completions(sources: &[SourceFile], path: &str, position: Position) -> Vec<CompletionItem> {
+    let content = match sources.iter().find(|s| s.path == path) {
+        Some(source) => source.content.as_str(),
+        None => return Vec::new(),
+    };
+
+    // Text before the cursor, minus the identifier being typed
+    let mut prefix = String::new();
+    for (i, line) in content.split('\n').enumerate() {
+        if i + 1 < position.line {
+            prefix.push_str(line);
+            prefix.push('\n');
+        } else {
+            prefix.extend(line.chars().take(position.column.saturating_sub(1)));
+            break;
+        }
+    }
+    let typed_start = prefix
+        .trim_end_matches(|c: char| c.is_alphanumeric() || c == '_')
+        .len();
+    let typed = prefix[typed_start..].to_string();
+    prefix.truncate(typed_start);
+
+    let tokens = match Lexer::new(&prefix).tokenize() {
+        Ok(mut tokens) => {
+            tokens.pop(); // Eof
+            tokens
+        }
+        Err(_) => Vec::new(),
+    };
+
+    let symbols = Symbols::collect(sources);
+    let mut items = Vec::new();
+    let mut add = |names: &[String], kind: CompletionKind| {
+        for name in names {
+            if name.starts_with(&typed) && !items.iter().any(|i: &CompletionItem| &i.label == name) {
+                items.push(CompletionItem {
+                    label: name.clone(),
+                    kind,
+                });
+            }
+        }
+    };
+
+    match slot(&tokens) {
+        Slot::Declaration => {
+            let keywords: Vec<String> = KEYWORDS.iter().map(|k| k.to_string()).collect();
+            add(&keywords, CompletionKind::Keyword);
+        }
+        Slot::NewName => {}
+        Slot::Role(state) => {
+            let allowed = state
+                .and_then(|state| symbols.states.iter().find(|(s, _)| *s == state).cloned())
+                .and_then(|(_, allowed)| allowed);
+            add(allowed.as_ref().unwrap_or(&symbols.roles), CompletionKind::Role);
+        }
+        Slot::RoleList => add(&symbols.roles, CompletionKind::Role),
+        Slot::State => {
+            let states: Vec<String> = symbols.states.iter().map(|(s, _)| s.clone()).collect();
+            add(&states, CompletionKind::State);
+        }
+        Slot::Source => {
+            let states: Vec<String> = symbols.states.iter().map(|(s, _)| s.clone()).collect();
+            add(&states, CompletionKind::State);
+            add(&symbols.groups, CompletionKind::Group);
+        }
+        Slot::Forbidden => {
+            add(&["forbid".to_string()], CompletionKind::Keyword);
+            add(&symbols.actions, CompletionKind::Action);
+            add(&symbols.sequences, CompletionKind::Sequence);
+        }
+        Slot::Assertion => {
+            let kinds = [AssertKind::Reachable, AssertKind::NoPath].map(|kind| kind.name().to_string());
+            add(&kinds, CompletionKind::Keyword);
+        }
+        Slot::Action => {
+            add(&symbols.actions, CompletionKind::Action);
+            let keywords: Vec<String> = KEYWORDS.iter().map(|k| k.to_string()).collect();
+            add(&keywords, CompletionKind::Keyword);
+        }
+    }
+
+    items
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn labels(content: &str, line: usize, column: usize) -> Vec<String> {
+        let sources = vec![
+            SourceFile::new("roles.martial", "roles { Top, Bottom, Neutral }"),
+            SourceFile::new(
+                "states.martial",
+                "state Standing roles { Neutral }\nstate Mount roles { Top, Bottom }",
+            ),
+            SourceFile::new("edit.martial", content),
+        ];
+        completions(&sources, "edit.martial", Position { line, column })
+            .into_iter()
+            .map(|item| item.label)
+            .collect()
+    }
+
+    #[test]
+    fn test_keywords_at_top_level() {
+        assert_eq!(labels("", 1, 1), KEYWORDS);
+        assert_eq!(labels("se", 1, 3), vec!["sequence"]);
+        assert_eq!(labels("mi", 1, 3), vec!["mirror", "mirrored"]);
+    }
+
+    #[test]
+    fn test_roles_filtered_by_state() {
+        assert_eq!(labels("sequence S:\n    Go: Mount[", 2, 15), vec!["Top", "Bottom"]);
+        assert_eq!(labels("sequence S:\n    Go: Standing[N", 2, 19), vec!["Neutral"]);
+    }
+
+    #[test]
+    fn test_states_after_arrow_and_in_groups() {
+        assert_eq!(labels("sequence S:\n    Go: Mount[Top] -> ", 2, 24), vec!["Standing", "Mount"]);
+        assert_eq!(labels("group G { M", 1, 12), vec!["Mount"]);
+        assert_eq!(labels("sequence S:\n    Go: Mount[Top] -> Mount[Top] / M", 2, 37), vec!["Mount"]);
+    }
+
+    #[test]
+    fn test_groups_at_step_source() {
+        let content = "group Mounted { Mount }
+sequence S:
+    Go: ";
+        assert_eq!(labels(content, 3, 9), vec!["Standing", "Mount", "Mounted"]);
+        assert_eq!(labels(&format!("{}Mount[Top] -> ", content), 3, 24), vec!["Standing", "Mount"]);
+    }
+
+    #[test]
+    fn test_mirror_roles() {
+        assert_eq!(labels("mirror roles { Top <-> B", 1, 25), vec!["Bottom"]);
+        assert!(labels("mirror suffixes { ", 1, 19).is_empty());
+    }
+
+    #[test]
+    fn test_assertions() {
+        assert_eq!(labels("assert ", 1, 8), vec!["reachable", "no_path"]);
+        assert_eq!(labels("assert no", 1, 10), vec!["no_path"]);
+        assert_eq!(labels("assert reachable M", 1, 19), vec!["Mount"]);
+        assert_eq!(labels("assert reachable Mount[Top] -> S", 1, 33), vec!["Standing"]);
+    }
+
+    #[test]
+    fn test_ruleset_body() {
+        let sequences = SourceFile::new("seq.martial", "sequence Pin:\n    Settle: Mount[Top] -> Mount[Top]");
+        let sources = vec![sequences, SourceFile::new("edit.martial", "ruleset IBJJF { forbid ")];
+        let labels: Vec<String> = completions(&sources, "edit.martial", Position { line: 1, column: 24 })
+            .into_iter()
+            .map(|item| item.label)
+            .collect();
+        assert_eq!(labels, vec!["forbid", "Settle", "Pin"]);
+    }
+
+    #[test]
+    fn test_no_suggestions_for_new_names() {
+        assert!(labels("state ", 1, 7).is_empty());
+        assert!(labels("roles { ", 1, 9).is_empty());
+        assert!(labels("levels { ", 1, 10).is_empty());
+        assert!(labels("state Mount @", 1, 14).is_empty());
+    }
+}
@@ -0,0 +1,176 @@
+//! Conditional sections of `.martial` files
+//!
+//! Lines between `#if flag` and `#endif` are kept only when `flag` is
+//! defined at load time, such as by `mat graph --define nogi`; `#if !flag`
+//! keeps them when it is not. An `#else` line switches to the other case,
+//! and sections nest:
+//!
+//! ```text
+//! #if nogi
+//! sequence HeelHook:
+//!     Hook: Ashi[Top] -> Finish[Top]
+//! #else
+//! sequence BowAndArrow:
+//!     Collar: Back[Top] -> Finish[Top]
+//! #endif
+//! ```
+//!
+//! The loader evaluates the sections before parsing, blanking the lines it
+//! leaves out so that positions in the rest of the file do not change. The
+//! lexer skips directive lines, so tools reading the whole file see every
+//! section.
+
+use crate::prelude::*;
+use core::fmt;
+use serde::Serialize;
+
+/// Error raised by a malformed conditional section
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ConditionalError {
+    pub message: String,
+    pub line: usize,
+}
+
+impl fmt::Display for ConditionalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at line {}", self.message, self.line)
+    }
+}
+
+impl core::error::Error for ConditionalError {}
+
+/// An open `#if`: whether its lines are kept, whether the enclosing
+/// section's are, and the line it starts on
+struct Section {
+    kept: bool,
+    outer: bool,
+    else_seen: bool,
+    line: usize,
+}
+
+/// Whether `content` has any conditional section
+pub fn has_sections(content: &str) -> bool {
+    split_front_matter(content).1.lines().any(|line| directive(line).is_some())
+}
+
+/// The front-matter block of `content`, where `#` starts TOML comments, and
+/// the rest
+fn split_front_matter(content: &str) -> (&str, &str) {
+    let head = crate::metadata::front_matter(content).map_or("", |block| block.raw);
+    content.split_at(head.len())
+}
+
+/// The directive of a line, with its argument, if it is one
+fn directive(line: &str) -> Option<(&str, &str)> {
+    let rest = line.trim_start().strip_prefix('#')?;
+    let (name, argument) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+    Some((name, argument.trim()))
+}
+
+/// `content` with the sections that `defines` leave out, and every
+/// directive line, blanked
+pub fn evaluate(content: &str, defines: &[String]) -> Result<String, ConditionalError> {
+    if !has_sections(content) {
+        return Ok(content.to_string());
+    }
+    let (head, body) = split_front_matter(content);
+    let mut sections: Vec<Section> = Vec::new();
+    let mut out = String::with_capacity(content.len());
+    out.push_str(head);
+    for (index, line) in body.split_inclusive('\n').enumerate() {
+        let number = head.matches('\n').count() + index + 1;
+        let error = |message: String| ConditionalError { message, line: number };
+        let kept = sections.last().is_none_or(|section| section.kept);
+        match directive(line) {
+            Some(("if", condition)) => {
+                let (negated, flag) = match condition.strip_prefix('!') {
+                    Some(flag) => (true, flag.trim()),
+                    None => (false, condition),
+                };
+                if flag.is_empty() || !flag.chars().all(|ch| ch.is_alphanumeric() || ch == '_' || ch == '-') {
+                    return Err(error(format!("Expected a flag after #if, got '{}'", condition)));
+                }
+                let defined = defines.iter().any(|define| define == flag);
+                sections.push(Section {
+                    kept: kept && defined != negated,
+                    outer: kept,
+                    else_seen: false,
+                    line: number,
+                });
+            }
+            Some(("else", "")) => match sections.last_mut() {
+                Some(section) if !section.else_seen => {
+                    section.kept = section.outer && !section.kept;
+                    section.else_seen = true;
+                }
+                Some(_) => return Err(error("Second #else in the same section".to_string())),
+                None => return Err(error("#else without #if".to_string())),
+            },
+            Some(("endif", "")) => {
+                sections.pop().ok_or_else(|| error("#endif without #if".to_string()))?;
+            }
+            Some((name, _)) => return Err(error(format!("Unknown directive '#{}'", name.trim()))),
+            None if kept => {
+                out.push_str(line);
+                continue;
+            }
+            None => {}
+        }
+        if line.ends_with('\n') {
+            out.push('\n');
+        }
+    }
+    match sections.last() {
+        Some(section) => Err(ConditionalError {
+            message: "#if without #endif".to_string(),
+            line: section.line,
+        }),
+        None => Ok(out),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = "state Guard\n#if nogi\nstate Ashi\n#else\nstate Collar\n#endif\nstate Mount\n";
+
+    fn defines(flags: &[&str]) -> Vec<String> {
+        flags.iter().map(|flag| flag.to_string()).collect()
+    }
+
+    #[test]
+    fn test_sections() {
+        assert_eq!(evaluate(SOURCE, &defines(&["nogi"])).unwrap(), "state Guard\n\nstate Ashi\n\n\n\nstate Mount\n");
+        assert_eq!(evaluate(SOURCE, &[]).unwrap(), "state Guard\n\n\n\nstate Collar\n\nstate Mount\n");
+        let negated = "#if !nogi\nstate Collar\n#endif";
+        assert_eq!(evaluate(negated, &defines(&["nogi"])).unwrap(), "\n\n");
+        assert_eq!(evaluate("state Mount", &[]).unwrap(), "state Mount");
+        // `#` starts comments in TOML front matter
+        let fronted = "+++\n# reviewed by the coach\n+++\n#if nogi\nstate Ashi\n#endif\n";
+        assert_eq!(evaluate(fronted, &[]).unwrap(), "+++\n# reviewed by the coach\n+++\n\n\n\n");
+        assert_eq!(evaluate("+++\n+++\n#if nogi\n", &[]).unwrap_err().line, 3);
+    }
+
+    #[test]
+    fn test_nested_sections() {
+        let source = "#if gi\n#if kids\nA\n#else\nB\n#endif\n#else\nC\n#endif\n";
+        let kept = |flags: &[&str]| -> String {
+            evaluate(source, &defines(flags)).unwrap().split_whitespace().collect()
+        };
+        assert_eq!(kept(&["gi", "kids"]), "A");
+        assert_eq!(kept(&["gi"]), "B");
+        // An #else within a section left out stays left out
+        assert_eq!(kept(&["kids"]), "C");
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |source: &str| evaluate(source, &[]).unwrap_err();
+        assert_eq!(error("state A\n#if nogi\nstate B").line, 2);
+        assert_eq!(error("#endif").message, "#endif without #if");
+        assert_eq!(error("#if a\n#else\n#else\n#endif").line, 3);
+        assert_eq!(error("#ifdef a").message, "Unknown directive '#ifdef'");
+        assert!(error("#if\n#endif").message.starts_with("Expected a flag"));
+    }
+}
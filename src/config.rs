@@ -0,0 +1,689 @@
+//! Project configuration
+//!
+//! A `martial.toml` (or `.martialrc`, in the same TOML syntax) at the root
+//! of a system sets defaults for the CLI:
+//!
+//! ```toml
+//! strict = true                        # as `mat validate --strict`
+//! compat = true                        # accept deprecated syntax, as --compat
+//! duplicates = "merge-roles"           # or "error" (default), "first-wins"
+//! kinds = ["takedown"]                 # @kind values besides the built-in ones
+//! lints = ["dominance-drop"]           # warnings to report, all by default
+//! deny = ["unused-state"]              # lints whose findings fail validation
+//! ruleset = "IBJJF"                    # default --ruleset for graph and dot
+//! include = ["*.martial", "drills/**/*.martial"]
+//! exclude = ["*.draft.martial"]
+//! shared = ["../shared"]               # libraries of common declarations
+//! defines = ["nogi"]                   # flags of conditional sections
+//! locked = true                        # check martial.lock, as --locked
+//!
+//! [dependencies]                       # packages added by `mat add`
+//! wrestling = "https://example.com/wrestling-core.martialpkg"
+//! ```
+//!
+//! The same file declares the exports run by `mat build` and the presets of
+//! `mat export`, see [`crate::build`], and the shared libraries of a
+//! workspace, see [`Config::layers`]. [`Resolved`] layers `MAT_*`
+//! environment variables and command-line flags over it, and remembers
+//! where each value came from.
+
+use crate::build::ExportTarget;
+use crate::lint::LintRegistry;
+use crate::selector;
+use crate::semantic::{DuplicatePolicy, SemanticValidator};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/// Names of the project file, in order of precedence
+pub const CONFIG_FILES: [&str; 2] = ["martial.toml", ".martialrc"];
+
+/// Directory of a system, relative to it, that dependencies are unpacked into
+pub const VENDOR_DIR: &str = "vendor";
+
+/// Settings of a project
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Directory of the `.martial` files built by `mat build`, relative to
+    /// the project file
+    pub source: String,
+    /// Whether actions without a glossary entry fail validation
+    pub strict: bool,
+    /// Whether syntax removed from the version of a file is accepted with a
+    /// warning, see [`Parser::compat`](crate::parser::Parser::compat)
+    pub compat: bool,
+    /// What to do with a state declared more than once
+    pub duplicates: DuplicatePolicy,
+    /// Sequence kinds accepted by `@kind` besides
+    /// [`SEQUENCE_KINDS`](crate::semantic::SEQUENCE_KINDS)
+    pub kinds: Vec<String>,
+    /// Names of the lints run by `mat validate`; those not at
+    /// [`Level::Allow`](crate::lint::Level::Allow) if unset
+    pub lints: Option<Vec<String>>,
+    /// Names of the lints run at [`Level::Deny`](crate::lint::Level::Deny),
+    /// whose findings fail `mat validate`
+    pub deny: Vec<String>,
+    /// Ruleset applied when none is given on the command line
+    pub ruleset: Option<String>,
+    /// Globs of the files making up the system, relative to its directory;
+    /// the `.martial` files at its top level if empty
+    pub include: Vec<String>,
+    /// Globs of files to leave out
+    pub exclude: Vec<String>,
+    /// Directories of shared libraries, relative to the system directory,
+    /// whose `.martial` files are part of the system, see [`Config::layers`]
+    pub shared: Vec<String>,
+    /// Flags whose conditional sections are kept, see [`crate::conditional`]
+    pub defines: Vec<String>,
+    /// Packages the system depends on, by namespace, with the path or URL
+    /// they are fetched from into [`VENDOR_DIR`] by `mat add`
+    pub dependencies: BTreeMap<String, String>,
+    /// Whether commands check the sources against the lockfile, see
+    /// [`crate::lock`]
+    pub locked: bool,
+    #[serde(rename = "export")]
+    pub exports: Vec<ExportTarget>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            source: ".".to_string(),
+            strict: false,
+            compat: false,
+            duplicates: DuplicatePolicy::Error,
+            kinds: Vec::new(),
+            lints: None,
+            deny: Vec::new(),
+            ruleset: None,
+            include: Vec::new(),
+            exclude: Vec::new(),
+            shared: Vec::new(),
+            defines: Vec::new(),
+            dependencies: BTreeMap::new(),
+            locked: false,
+            exports: Vec::new(),
+        }
+    }
+}
+
+/// Error raised while reading a project file
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigError {
+    pub message: String,
+    /// File the error refers to, if any
+    pub path: Option<String>,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.path {
+            Some(path) => write!(f, "Config error in {}: {}", path, self.message),
+            None => write!(f, "Config error: {}", self.message),
+        }
+    }
+}
+
+impl core::error::Error for ConfigError {}
+
+impl Config {
+    pub fn parse(content: &str) -> Result<Self, ConfigError> {
+        let config: Config = toml::from_str(content).map_err(|e: toml::de::Error| ConfigError {
+            message: e.message().to_string(),
+            path: None,
+        })?;
+        let mut names = std::collections::BTreeSet::new();
+        if let Some(name) = config.exports.iter().filter_map(|e| e.name.as_deref()).find(|name| !names.insert(*name)) {
+            return Err(ConfigError {
+                message: format!("export preset '{}' is declared more than once", name),
+                path: None,
+            });
+        }
+        Ok(config)
+    }
+
+    /// The export preset named `name`
+    pub fn preset(&self, name: &str) -> Option<&ExportTarget> {
+        self.exports.iter().find(|e| e.name.as_deref() == Some(name))
+    }
+
+    /// Path of the project file of `dir`, if there is one
+    pub fn find(dir: &str) -> Option<String> {
+        CONFIG_FILES
+            .iter()
+            .map(|name| Path::new(dir).join(name))
+            .find(|path| path.is_file())
+            .map(|path| path.to_string_lossy().into_owned())
+    }
+
+    /// Read the project file of `dir`, or the defaults if it has none
+    pub fn load(dir: &str) -> Result<Self, ConfigError> {
+        Ok(Resolved::load(dir)?.config)
+    }
+
+    /// A validator applying the settings of the project
+    pub fn validator(&self) -> SemanticValidator {
+        SemanticValidator::new()
+            .duplicates(self.duplicates)
+            .kinds(self.kinds.iter().cloned())
+            .lints(self.lints.clone())
+            .deny(self.deny.iter().cloned())
+    }
+
+    /// Whether `mat validate` reports the lint named `name`, if it runs by
+    /// default
+    pub fn lint_enabled(&self, name: &str) -> bool {
+        self.lints.as_ref().is_none_or(|lints| lints.iter().any(|lint| lint == name))
+    }
+
+    /// Whether the file at `path`, relative to the system directory, is part
+    /// of the system
+    pub fn includes(&self, path: &str) -> bool {
+        let included = if self.include.is_empty() {
+            path_glob_match("*.martial", path)
+        } else {
+            self.include.iter().any(|glob| path_glob_match(glob, path))
+        };
+        included && !self.exclude.iter().any(|glob| path_glob_match(glob, path))
+    }
+
+    /// Paths of the files of the system in `dir`, sorted
+    pub fn find_files(&self, dir: &str) -> Result<Vec<String>, std::io::Error> {
+        let mut relative = Vec::new();
+        walk(Path::new(dir), "", !self.include.is_empty(), &mut relative)?;
+
+        let mut files: Vec<String> = relative
+            .into_iter()
+            .filter(|path| self.includes(path))
+            .map(|path| Path::new(dir).join(path).to_string_lossy().into_owned())
+            .collect();
+        files.sort();
+        Ok(files)
+    }
+
+    /// The layers of the system in `dir`, in loading order: its shared
+    /// libraries, its dependencies, then the system itself
+    pub fn layers(&self, dir: &str) -> Vec<Layer> {
+        let mut layers: Vec<Layer> = self
+            .shared
+            .iter()
+            .map(|shared| {
+                let path = Path::new(dir).join(shared);
+                Layer {
+                    name: directory_name(&path),
+                    dir: path.to_string_lossy().into_owned(),
+                    kind: LayerKind::Shared,
+                }
+            })
+            .collect();
+        layers.extend(self.dependencies.keys().map(|name| Layer {
+            name: name.clone(),
+            dir: Path::new(dir).join(VENDOR_DIR).join(name).to_string_lossy().into_owned(),
+            kind: LayerKind::Dependency,
+        }));
+        layers.push(Layer {
+            name: directory_name(Path::new(dir)),
+            dir: dir.to_string(),
+            kind: LayerKind::System,
+        });
+        layers
+    }
+}
+
+/// What a [`Layer`] of a system is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LayerKind {
+    /// A library of declarations shared with other systems
+    Shared,
+    /// A package the system depends on, whose declarations are namespaced
+    Dependency,
+    /// The system itself
+    System,
+}
+
+/// A directory whose files make up part of a system
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Layer {
+    /// Name of the directory, or of the dependency
+    pub name: String,
+    pub dir: String,
+    pub kind: LayerKind,
+}
+
+impl Layer {
+    /// Whether the layer comes from outside the system
+    pub fn is_external(&self) -> bool {
+        self.kind != LayerKind::System
+    }
+
+    /// Namespace of the declarations of the layer, for a dependency
+    pub fn namespace(&self) -> Option<&str> {
+        (self.kind == LayerKind::Dependency).then_some(self.name.as_str())
+    }
+
+    /// The first of `layers`, as returned by [`Config::layers`], that holds
+    /// the file at `path`
+    ///
+    /// Shared libraries and dependencies come first as their paths may
+    /// start with the system directory.
+    pub fn of<'a>(layers: &'a [Layer], path: &str) -> Option<&'a Layer> {
+        layers
+            .iter()
+            .find(|layer| Path::new(path).parent().is_some_and(|parent| parent.starts_with(&layer.dir)))
+    }
+}
+
+impl fmt::Display for Layer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            LayerKind::Shared => write!(f, "shared library '{}' at {}", self.name, self.dir),
+            LayerKind::Dependency => write!(f, "dependency '{}' at {}", self.name, self.dir),
+            LayerKind::System => write!(f, "system '{}'", self.name),
+        }
+    }
+}
+
+/// Name of a directory, resolving a trailing `..` or `.` when possible
+fn directory_name(path: &Path) -> String {
+    let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    path.file_name().and_then(|n| n.to_str()).unwrap_or("unknown").to_string()
+}
+
+/// Where the value of a setting comes from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Origin {
+    Default,
+    /// The project file at this path
+    File(String),
+    /// This environment variable
+    Env(String),
+    /// This command-line flag
+    Flag(String),
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Origin::Default => write!(f, "default"),
+            Origin::File(path) => write!(f, "{}", path),
+            Origin::Env(var) => write!(f, "env {}", var),
+            Origin::Flag(flag) => write!(f, "flag {}", flag),
+        }
+    }
+}
+
+/// Settings that can be overridden, with their environment variables
+pub const SETTINGS: [(&str, &str); 13] = [
+    ("source", "MAT_SOURCE"),
+    ("strict", "MAT_STRICT"),
+    ("compat", "MAT_COMPAT"),
+    ("duplicates", "MAT_DUPLICATES"),
+    ("kinds", "MAT_KINDS"),
+    ("lints", "MAT_LINTS"),
+    ("deny", "MAT_DENY"),
+    ("ruleset", "MAT_RULESET"),
+    ("include", "MAT_INCLUDE"),
+    ("exclude", "MAT_EXCLUDE"),
+    ("shared", "MAT_SHARED"),
+    ("defines", "MAT_DEFINES"),
+    ("locked", "MAT_LOCKED"),
+];
+
+/// A configuration resolved from layers, each overriding the previous
+/// one: defaults, the project file, `MAT_*` environment variables and
+/// command-line flags
+#[derive(Debug, Clone, PartialEq)]
+pub struct Resolved {
+    pub config: Config,
+    /// Path of the project file, if there is one
+    pub file: Option<String>,
+    origins: Vec<(&'static str, Origin)>,
+}
+
+impl Resolved {
+    /// The project file of `dir` over the defaults
+    pub fn load(dir: &str) -> Result<Self, ConfigError> {
+        let mut resolved = Resolved {
+            config: Config::default(),
+            file: None,
+            origins: SETTINGS.iter().map(|&(key, _)| (key, Origin::Default)).collect(),
+        };
+        let Some(path) = Config::find(dir) else {
+            return Ok(resolved);
+        };
+        let error = |message: String| ConfigError {
+            message,
+            path: Some(path.clone()),
+        };
+        let content = fs::read_to_string(&path).map_err(|e| error(e.to_string()))?;
+        resolved.config = Config::parse(&content).map_err(|e| error(e.message))?;
+        let table: toml::Table = toml::from_str(&content).map_err(|e: toml::de::Error| error(e.message().to_string()))?;
+        for (key, origin) in &mut resolved.origins {
+            if table.contains_key(*key) {
+                *origin = Origin::File(path.clone());
+            }
+        }
+        resolved.file = Some(path);
+        Ok(resolved)
+    }
+
+    /// Apply the `MAT_*` variables among `vars`
+    pub fn apply_env(&mut self, vars: impl IntoIterator<Item = (String, String)>) -> Result<(), ConfigError> {
+        for (var, value) in vars {
+            if let Some(&(key, _)) = SETTINGS.iter().find(|(_, name)| *name == var) {
+                self.set(key, &value, Origin::Env(var))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Set `key` from its text form: `true`/`false` for `strict`, `compat`
+    /// and `locked`, and comma-separated items for lists
+    pub fn set(&mut self, key: &str, value: &str, origin: Origin) -> Result<(), ConfigError> {
+        let error = |message: String| ConfigError {
+            message: format!("{} ({})", message, origin),
+            path: None,
+        };
+        let list = || -> Vec<String> {
+            value.split(',').map(str::trim).filter(|item| !item.is_empty()).map(String::from).collect()
+        };
+        let config = &mut self.config;
+        match key {
+            "source" => config.source = value.to_string(),
+            "strict" | "compat" | "locked" => {
+                let flag = match value.trim().to_lowercase().as_str() {
+                    "true" | "1" | "yes" => true,
+                    "false" | "0" | "no" | "" => false,
+                    _ => return Err(error(format!("{} expects true or false, not '{}'", key, value))),
+                };
+                match key {
+                    "strict" => config.strict = flag,
+                    "compat" => config.compat = flag,
+                    _ => config.locked = flag,
+                }
+            }
+            "duplicates" => {
+                config.duplicates = DuplicatePolicy::from_name(value.trim()).ok_or_else(|| {
+                    let names: Vec<&str> = DuplicatePolicy::ALL.iter().map(|p| p.name()).collect();
+                    error(format!("duplicates expects one of {}, not '{}'", names.join(", "), value))
+                })?
+            }
+            "kinds" => config.kinds = list(),
+            "lints" => config.lints = Some(list()),
+            "deny" => config.deny = list(),
+            "ruleset" => config.ruleset = Some(value.to_string()).filter(|r| !r.is_empty()),
+            "include" => config.include = list(),
+            "exclude" => config.exclude = list(),
+            "shared" => config.shared = list(),
+            "defines" => config.defines = list(),
+            _ => return Err(error(format!("unknown setting '{}'", key))),
+        }
+        if let Some((_, slot)) = self.origins.iter_mut().find(|(k, _)| *k == key) {
+            *slot = origin;
+        }
+        Ok(())
+    }
+
+    /// Where the value of `key` comes from
+    pub fn origin(&self, key: &str) -> Option<&Origin> {
+        self.origins.iter().find(|(k, _)| *k == key).map(|(_, origin)| origin)
+    }
+
+    /// The effective settings as TOML, each commented with its origin
+    pub fn show(&self) -> String {
+        let config = &self.config;
+        let strings = |items: &[String]| toml::Value::Array(items.iter().cloned().map(toml::Value::String).collect());
+        let mut out = String::new();
+        for &(key, _) in &SETTINGS {
+            let value = match key {
+                "source" => Some(toml::Value::String(config.source.clone())),
+                "strict" => Some(toml::Value::Boolean(config.strict)),
+                "compat" => Some(toml::Value::Boolean(config.compat)),
+                "duplicates" => Some(toml::Value::String(config.duplicates.name().to_string())),
+                "kinds" => Some(strings(&config.kinds)),
+                "lints" => {
+                    let lints = config.lints.clone().unwrap_or_else(|| {
+                        LintRegistry::builtin().default_names().into_iter().map(String::from).collect()
+                    });
+                    Some(strings(&lints))
+                }
+                "deny" => Some(strings(&config.deny)),
+                "ruleset" => config.ruleset.clone().map(toml::Value::String),
+                "include" if config.include.is_empty() => Some(strings(&["*.martial".to_string()])),
+                "include" => Some(strings(&config.include)),
+                "exclude" => Some(strings(&config.exclude)),
+                "shared" => Some(strings(&config.shared)),
+                "defines" => Some(strings(&config.defines)),
+                _ => Some(toml::Value::Boolean(config.locked)),
+            };
+            let origin = self.origin(key).unwrap_or(&Origin::Default);
+            match value {
+                Some(value) => out.push_str(&format!("{} = {}  # {}\n", key, value, origin)),
+                None => out.push_str(&format!("# {} is unset  # {}\n", key, origin)),
+            }
+        }
+        if let Some(file) = self.file.as_ref().filter(|_| !config.exports.is_empty()) {
+            out.push_str(&format!("# {} export(s)  # {}\n", config.exports.len(), file));
+        }
+        if let Some(file) = self.file.as_ref().filter(|_| !config.dependencies.is_empty()) {
+            out.push_str(&format!("# {} dependency(ies)  # {}\n", config.dependencies.len(), file));
+        }
+        out
+    }
+}
+
+/// `content` of a project file with the dependency `name` fetched from
+/// `source`, replacing any earlier entry for it
+///
+/// The file is edited as text so that its comments and layout are kept.
+pub fn with_dependency(content: &str, name: &str, source: &str) -> String {
+    let entry = format!("{} = {}", name, toml::Value::String(source.to_string()));
+    let mut lines: Vec<String> = content.lines().map(String::from).collect();
+    let Some(header) = lines.iter().position(|line| line.trim() == "[dependencies]") else {
+        let mut out = content.to_string();
+        if !out.is_empty() && !out.ends_with('\n') {
+            out.push('\n');
+        }
+        if !out.is_empty() {
+            out.push('\n');
+        }
+        out.push_str(&format!("[dependencies]\n{}\n", entry));
+        return out;
+    };
+    let end = lines[header + 1..]
+        .iter()
+        .position(|line| line.trim_start().starts_with('['))
+        .map_or(lines.len(), |offset| header + 1 + offset);
+    let existing = (header + 1..end).find(|&index| {
+        lines[index].split_once('=').is_some_and(|(key, _)| key.trim().trim_matches('"') == name)
+    });
+    match existing {
+        Some(index) => lines[index] = entry,
+        None => lines.insert(header + 1, entry),
+    }
+    lines.join("\n") + "\n"
+}
+
+/// Collect the `.martial` files under `dir` as `/`-separated paths
+/// starting with `prefix`, descending into subdirectories if `recursive`
+fn walk(dir: &Path, prefix: &str, recursive: bool, files: &mut Vec<String>) -> Result<(), std::io::Error> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+            continue;
+        };
+        let relative = format!("{}{}", prefix, name);
+        if path.is_dir() && recursive {
+            walk(&path, &format!("{}/", relative), recursive, files)?;
+        } else if path.is_file() && path.extension().is_some_and(|ext| ext == "martial") {
+            files.push(relative);
+        }
+    }
+    Ok(())
+}
+
+/// Match a `/`-separated path against a glob
+///
+/// Each path segment is matched by [`selector::glob_match`], so `?` matches
+/// one character and `*` any run of characters within a segment; a `**`
+/// segment matches any number of whole directories.
+pub fn path_glob_match(glob: &str, path: &str) -> bool {
+    let globs: Vec<&str> = glob.split('/').collect();
+    let segments: Vec<&str> = path.split('/').collect();
+    segments_match(&globs, &segments)
+}
+
+/// Whether `segments` match the glob segments `globs`
+fn segments_match(globs: &[&str], segments: &[&str]) -> bool {
+    match globs.split_first() {
+        None => segments.is_empty(),
+        Some((&"**", rest)) => (0..=segments.len()).any(|i| segments_match(rest, &segments[i..])),
+        Some((glob, rest)) => segments
+            .split_first()
+            .is_some_and(|(segment, tail)| selector::glob_match(glob, segment) && segments_match(rest, tail)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_config() {
+        assert_eq!(Config::parse("duplicates = \"merge-roles\"\n").unwrap().duplicates, DuplicatePolicy::MergeRoles);
+        let config = Config::parse(
+            "strict = true\nlints = [\"forbidden-reach\"]\nruleset = \"Kids\"\nexclude = [\"*.draft.martial\"]\n",
+        )
+        .unwrap();
+        assert!(config.strict);
+        assert_eq!(config.ruleset.as_deref(), Some("Kids"));
+        assert!(config.lint_enabled("forbidden-reach"));
+        assert!(!config.lint_enabled("dominance-drop"));
+        assert!(Config::default().lint_enabled("dominance-drop"));
+        assert_eq!(Config::parse("").unwrap(), Config::default());
+
+        // Custom lints are only known to the registry running them
+        let config = Config::parse("lints = [\"ends-in-zanshin\"]\n").unwrap();
+        assert_eq!(config.lints, Some(vec!["ends-in-zanshin".to_string()]));
+        let config = Config::parse("deny = [\"unused-state\"]\n").unwrap();
+        assert_eq!(config.deny, ["unused-state"]);
+        assert!(Config::parse("stricter = true\n").is_err());
+    }
+
+    #[test]
+    fn test_layered_resolution() {
+        let root = std::env::temp_dir().join(format!("martial-layers-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("martial.toml"), "strict = true\nruleset = \"Kids\"\n").unwrap();
+        let file = root.join("martial.toml").to_string_lossy().into_owned();
+
+        let mut resolved = Resolved::load(root.to_str().unwrap()).unwrap();
+        assert_eq!(resolved.origin("strict"), Some(&Origin::File(file.clone())));
+        assert_eq!(resolved.origin("lints"), Some(&Origin::Default));
+
+        let env = [("MAT_RULESET", "Open"), ("MAT_LINTS", "dominance-drop"), ("HOME", "/root")];
+        resolved.apply_env(env.map(|(k, v)| (k.to_string(), v.to_string()))).unwrap();
+        assert_eq!(resolved.config.ruleset.as_deref(), Some("Open"));
+        assert_eq!(resolved.config.lints, Some(vec!["dominance-drop".to_string()]));
+        assert_eq!(resolved.origin("ruleset"), Some(&Origin::Env("MAT_RULESET".to_string())));
+
+        resolved.set("ruleset", "Pro", Origin::Flag("--ruleset".to_string())).unwrap();
+        resolved.set("exclude", "a.martial, b.martial", Origin::Flag("--exclude".to_string())).unwrap();
+        assert_eq!(resolved.config.exclude, ["a.martial", "b.martial"]);
+        assert!(resolved.config.strict);
+        resolved.set("compat", "yes", Origin::Env("MAT_COMPAT".to_string())).unwrap();
+        assert!(resolved.config.compat);
+        resolved.set("duplicates", "first-wins", Origin::Flag("--duplicates".to_string())).unwrap();
+        assert_eq!(resolved.config.duplicates, DuplicatePolicy::FirstWins);
+        assert!(resolved.set("duplicates", "last-wins", Origin::Default).is_err());
+        resolved.set("kinds", "takedown, guard-pull", Origin::Env("MAT_KINDS".to_string())).unwrap();
+        assert_eq!(resolved.config.kinds, ["takedown", "guard-pull"]);
+
+        let shown = resolved.show();
+        assert!(shown.contains(&format!("strict = true  # {}\n", file)), "{}", shown);
+        assert!(shown.contains("ruleset = \"Pro\"  # flag --ruleset\n"), "{}", shown);
+        assert!(shown.contains("lints = [\"dominance-drop\"]  # env MAT_LINTS\n"), "{}", shown);
+        assert!(shown.contains("include = [\"*.martial\"]  # default\n"), "{}", shown);
+        assert!(shown.contains("compat = true  # env MAT_COMPAT\n"), "{}", shown);
+        assert!(shown.contains("kinds = [\"takedown\", \"guard-pull\"]  # env MAT_KINDS\n"), "{}", shown);
+
+        let error = resolved.set("strict", "maybe", Origin::Env("MAT_STRICT".to_string())).unwrap_err();
+        assert_eq!(error.to_string(), "Config error: strict expects true or false, not 'maybe' (env MAT_STRICT)");
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_path_glob_match() {
+        assert!(path_glob_match("*.martial", "guard.martial"));
+        assert!(!path_glob_match("*.martial", "drills/guard.martial"));
+        assert!(path_glob_match("drills/*.martial", "drills/guard.martial"));
+        assert!(path_glob_match("**/*.martial", "guard.martial"));
+        assert!(path_glob_match("**/*.martial", "a/b/guard.martial"));
+        assert!(path_glob_match("a/**/g?ard.martial", "a/b/c/guard.martial"));
+        assert!(path_glob_match("*.draft.martial", "guard.draft.martial"));
+        assert!(!path_glob_match("*.draft.martial", "guard.martial"));
+        assert!(path_glob_match("*", "anything"));
+        assert!(!path_glob_match("?", "/"));
+    }
+
+    #[test]
+    fn test_includes() {
+        let config = Config {
+            include: vec!["*.martial".to_string(), "drills/**/*.martial".to_string()],
+            exclude: vec!["**/*.draft.martial".to_string()],
+            ..Config::default()
+        };
+        assert!(config.includes("guard.martial"));
+        assert!(config.includes("drills/kids/guard.martial"));
+        assert!(!config.includes("drills/kids/guard.draft.martial"));
+        assert!(!config.includes("archive/guard.martial"));
+        assert!(!Config::default().includes("drills/guard.martial"));
+    }
+
+    #[test]
+    fn test_load_and_find_files() {
+        let root = std::env::temp_dir().join(format!("martial-config-{}", std::process::id()));
+        fs::create_dir_all(root.join("drills")).unwrap();
+        for file in ["a.martial", "b.draft.martial", "drills/c.martial", "notes.txt"] {
+            fs::write(root.join(file), "").unwrap();
+        }
+        let dir = root.to_str().unwrap();
+        assert_eq!(Config::load(dir).unwrap(), Config::default());
+        assert_eq!(Config::default().find_files(dir).unwrap().len(), 2);
+
+        fs::write(root.join(".martialrc"), "include = [\"**/*.martial\"]\nexclude = [\"*.draft.martial\"]\n").unwrap();
+        let config = Config::load(dir).unwrap();
+        let files: Vec<String> = config
+            .find_files(dir)
+            .unwrap()
+            .iter()
+            .map(|f| Path::new(f).strip_prefix(&root).unwrap().to_string_lossy().replace('\\', "/"))
+            .collect();
+        assert_eq!(files, ["a.martial", "drills/c.martial"]);
+
+        fs::write(root.join("martial.toml"), "strict = 1\n").unwrap();
+        let error = Config::load(dir).unwrap_err();
+        assert!(error.path.unwrap().ends_with("martial.toml"));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_with_dependency() {
+        let added = with_dependency("strict = true", "wrestling", "../wrestling.martialpkg");
+        assert_eq!(added, "strict = true\n\n[dependencies]\nwrestling = \"../wrestling.martialpkg\"\n");
+        let config = Config::parse(&added).unwrap();
+        assert_eq!(config.dependencies["wrestling"], "../wrestling.martialpkg");
+        assert_eq!(config.layers("bjj")[0].namespace(), Some("wrestling"));
+
+        // An entry is replaced, and other sections and comments are kept
+        let content = "[dependencies]\n# takedowns\nwrestling = \"old\"\n\n[[export]]\nformat = \"dot\"\n";
+        let updated = with_dependency(content, "wrestling", "new");
+        assert!(updated.starts_with("[dependencies]\n# takedowns\nwrestling = \"new\"\n\n[[export]]"), "{}", updated);
+        let added = with_dependency(&updated, "judo", "judo.martialpkg");
+        assert!(added.starts_with("[dependencies]\njudo = \"judo.martialpkg\"\n# takedowns"), "{}", added);
+        assert_eq!(with_dependency("", "judo", "j"), "[dependencies]\njudo = \"j\"\n");
+    }
+}
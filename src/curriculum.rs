@@ -0,0 +1,257 @@
+//! Teaching order of the positions of a system
+//!
+//! A state depends on the states whose transitions lead to it and on those
+//! it `@requires`, except that a transition from a higher level back to a
+//! lower one does not make the lower state depend on the higher. States
+//! depending on each other, directly or not, are taught together as one
+//! unit. Units come after the units they depend on and, among those ready to
+//! be taught, lower levels come first, then the units closer to the initial
+//! positions of the graph. States without a level belong to every level, so
+//! they rank with the lowest.
+
+use crate::graph::MartialGraph;
+use crate::semantic::MartialSystem;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::Write;
+
+/// States taught at the same point of the curriculum
+#[derive(Debug, Clone, PartialEq)]
+pub struct Unit {
+    /// The states, closest to an initial position first
+    pub states: Vec<String>,
+    /// Highest level of the states, if any has one
+    pub level: Option<String>,
+    /// Fewest actions from an initial position to one of the states, if
+    /// they can be reached
+    pub depth: Option<usize>,
+}
+
+/// Every declared state, in teaching order
+pub fn order(system: &MartialSystem) -> Vec<Unit> {
+    let names: Vec<&str> = system.states.keys().map(String::as_str).collect();
+    let index: HashMap<&str, usize> = names.iter().enumerate().map(|(i, name)| (*name, i)).collect();
+    let rank: Vec<Option<usize>> = names
+        .iter()
+        .map(|name| system.states[*name].level().and_then(|level| system.level_rank(level)))
+        .collect();
+
+    let graph = MartialGraph::from_system(system);
+    let mut depth: Vec<Option<usize>> = vec![None; names.len()];
+    for (node, d) in graph.depths() {
+        if let Some(&i) = index.get(node.state.as_str()) {
+            depth[i] = Some(depth[i].map_or(d, |current| current.min(d)));
+        }
+    }
+
+    // dependents[a] holds the states depending on a
+    let mut dependents: Vec<BTreeSet<usize>> = vec![BTreeSet::new(); names.len()];
+    for edge in &graph.edges {
+        let (Some(&from), Some(&to)) = (index.get(edge.from.state.as_str()), index.get(edge.to.state.as_str())) else {
+            continue;
+        };
+        let backwards = matches!((rank[from], rank[to]), (Some(from), Some(to)) if from > to);
+        if from != to && !backwards {
+            dependents[from].insert(to);
+        }
+    }
+    for (name, state) in &system.states {
+        for required in state.requires() {
+            if let Some(&required) = index.get(required) {
+                if required != index[name.as_str()] {
+                    dependents[required].insert(index[name.as_str()]);
+                }
+            }
+        }
+    }
+
+    let components = components(&dependents);
+    let mut units: Vec<Vec<usize>> = Vec::new();
+    let mut unit_of = vec![0; names.len()];
+    for (i, &component) in components.iter().enumerate() {
+        if component == units.len() {
+            units.push(Vec::new());
+        }
+        units[component].push(i);
+        unit_of[i] = component;
+    }
+
+    // Units are ranked by level, then depth, then name; states without a
+    // level belong to the lowest one, and unreached states come last
+    let key = |unit: &[usize]| {
+        let rank = unit.iter().filter_map(|&i| rank[i]).max().unwrap_or(0);
+        let depth = unit.iter().filter_map(|&i| depth[i]).min().unwrap_or(usize::MAX);
+        (rank, depth, names[unit[0]])
+    };
+    let mut edges: Vec<BTreeSet<usize>> = vec![BTreeSet::new(); units.len()];
+    for (from, targets) in dependents.iter().enumerate() {
+        edges[unit_of[from]].extend(targets.iter().map(|&to| unit_of[to]).filter(|&to| to != unit_of[from]));
+    }
+    let mut pending = vec![0; units.len()];
+    for targets in &edges {
+        for &to in targets {
+            pending[to] += 1;
+        }
+    }
+
+    let mut ready: BTreeSet<((usize, usize, &str), usize)> =
+        (0..units.len()).filter(|&u| pending[u] == 0).map(|u| (key(&units[u]), u)).collect();
+    let mut ordered = Vec::new();
+    while let Some(entry) = ready.pop_first() {
+        let unit = entry.1;
+        for &next in &edges[unit] {
+            pending[next] -= 1;
+            if pending[next] == 0 {
+                ready.insert((key(&units[next]), next));
+            }
+        }
+        let mut states = units[unit].clone();
+        states.sort_by_key(|&i| (depth[i].is_none(), depth[i], names[i]));
+        ordered.push(Unit {
+            level: states.iter().filter_map(|&i| rank[i]).max().map(|rank| system.levels[rank].clone()),
+            depth: states.iter().filter_map(|&i| depth[i]).min(),
+            states: states.into_iter().map(|i| names[i].to_string()).collect(),
+        });
+    }
+    ordered
+}
+
+/// Strongly connected component of every vertex, numbered in order of their
+/// lowest vertex
+fn components(edges: &[BTreeSet<usize>]) -> Vec<usize> {
+    let count = edges.len();
+    let mut reversed: Vec<Vec<usize>> = vec![Vec::new(); count];
+    for (from, targets) in edges.iter().enumerate() {
+        for &to in targets {
+            reversed[to].push(from);
+        }
+    }
+
+    // Kosaraju: finish order on the graph, then components on the reversed
+    // graph, both without recursion
+    let mut visited = vec![false; count];
+    let mut finished = Vec::with_capacity(count);
+    for root in 0..count {
+        if visited[root] {
+            continue;
+        }
+        visited[root] = true;
+        let mut stack = vec![(root, edges[root].iter())];
+        while let Some((vertex, targets)) = stack.last_mut() {
+            match targets.find(|&&to| !visited[to]) {
+                Some(&to) => {
+                    visited[to] = true;
+                    stack.push((to, edges[to].iter()));
+                }
+                None => {
+                    finished.push(*vertex);
+                    stack.pop();
+                }
+            }
+        }
+    }
+
+    let mut component = vec![usize::MAX; count];
+    let mut found = 0;
+    for &root in finished.iter().rev() {
+        if component[root] != usize::MAX {
+            continue;
+        }
+        component[root] = found;
+        let mut stack = vec![root];
+        while let Some(vertex) = stack.pop() {
+            for &from in &reversed[vertex] {
+                if component[from] == usize::MAX {
+                    component[from] = found;
+                    stack.push(from);
+                }
+            }
+        }
+        found += 1;
+    }
+
+    // Renumber so that components are numbered by their lowest vertex
+    let mut renumbered: HashMap<usize, usize> = HashMap::new();
+    component
+        .into_iter()
+        .map(|c| {
+            let next = renumbered.len();
+            *renumbered.entry(c).or_insert(next)
+        })
+        .collect()
+}
+
+/// Render a teaching order as a numbered Markdown syllabus
+pub fn to_markdown(system: &MartialSystem, units: &[Unit]) -> String {
+    let mut out = format!("# Curriculum: {}\n\n", system.name);
+    for (i, unit) in units.iter().enumerate() {
+        let states: Vec<String> = unit.states.iter().map(|state| format!("**{}**", state)).collect();
+        let _ = write!(out, "{}. {}", i + 1, states.join(", "));
+        if unit.states.len() > 1 {
+            out.push_str(" (taught together)");
+        }
+        let mut details = Vec::new();
+        if let Some(level) = &unit.level {
+            details.push(level.clone());
+        }
+        details.push(match unit.depth {
+            Some(depth) => format!("depth {}", depth),
+            None => "not reached from an initial position".to_string(),
+        });
+        let _ = writeln!(out, " — {}", details.join(", "));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loader::{load_sources, SourceFile};
+
+    fn order_of(source: &str) -> Vec<Vec<String>> {
+        let system = load_sources("test", &[SourceFile::new("a.martial", source)]).unwrap();
+        order(&system).into_iter().map(|unit| unit.states).collect()
+    }
+
+    #[test]
+    fn test_order() {
+        let source = "roles { Top, Bottom }\nstate Standing\nstate Guard\nstate Mount\nstate Back\nstate Side\n\
+                      sequence Pull:\n    Sit: Standing[Top] -> Guard[Bottom]\n    Sweep: Guard[Bottom] -> Mount[Top]\n\
+                      sequence Take:\n    Roll: Mount[Top] -> Back[Top]\n    Slip: Back[Top] -> Mount[Top]\n\
+                      sequence Pass:\n    Cut: Guard[Top] -> Side[Top]";
+        assert_eq!(
+            order_of(source),
+            [vec!["Standing"], vec!["Guard"], vec!["Side"], vec!["Mount", "Back"]]
+        );
+
+        // A prerequisite comes first, even from further away
+        let required = source.replace("state Side", "state Side @requires(Back)");
+        assert_eq!(order_of(&required).last().unwrap(), &["Side"]);
+    }
+
+    #[test]
+    fn test_order_levels() {
+        let source = "roles { Top }\nlevels { White, Blue }\n\
+                      state Standing @level(White)\nstate Guard @level(White)\nstate Mount @level(White)\n\
+                      state Back @level(Blue)\nstate Lost\n\
+                      sequence Climb:\n    Pull: Standing[Top] -> Guard[Top]\n    Sweep: Guard[Top] -> Mount[Top]\n    \
+                      Take: Mount[Top] -> Back[Top]\n\
+                      sequence Reset:\n    Lose: Back[Top] -> Guard[Top]";
+        let system = load_sources("test", &[SourceFile::new("a.martial", source)]).unwrap();
+        let units = order(&system);
+        // Going back down a level does not make Guard depend on Back
+        let states: Vec<&str> = units.iter().flat_map(|u| &u.states).map(String::as_str).collect();
+        assert_eq!(states, ["Standing", "Guard", "Mount", "Lost", "Back"]);
+        assert_eq!(units[4].level.as_deref(), Some("Blue"));
+        assert_eq!(units[3].depth, None);
+
+        let markdown = to_markdown(&system, &units);
+        assert!(markdown.starts_with("# Curriculum: test\n\n1. **Standing** — White, depth 0\n"), "{}", markdown);
+        assert!(markdown.contains("4. **Lost** — not reached from an initial position\n"), "{}", markdown);
+    }
+
+    #[test]
+    fn test_components() {
+        let edges = [BTreeSet::from([1]), BTreeSet::from([2]), BTreeSet::from([1]), BTreeSet::new()];
+        assert_eq!(components(&edges), [0, 1, 1, 2]);
+    }
+}
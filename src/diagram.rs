@@ -0,0 +1,256 @@
+//! Box-and-arrow drawings of the graph for the terminal
+//!
+//! Every position is a box, the initial positions first, with a line per
+//! action leaving it. Arrows run in lanes on the left, from the line of an
+//! action to the title of the box it leads to; an action that stays in the
+//! same position has no arrow. [`Charset::Ascii`] draws without box-drawing
+//! characters, for terminals that lack them.
+//!
+//! ```text
+//!    ┌──────────────────┐
+//!    │ Guard[Bottom]    │
+//! ┌──┤ Hip → Mount[Top] │
+//! │  └──────────────────┘
+//! │  ┌──────────────────┐
+//! └─▶│ Mount[Top]       │
+//!    │ Grip (stays)     │
+//!    └──────────────────┘
+//! ```
+
+use crate::graph::{Edge, MartialGraph, Node};
+use crate::prelude::*;
+use alloc::collections::BTreeMap;
+
+/// Characters a drawing is made of
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Charset {
+    /// Box-drawing characters
+    Unicode,
+    /// `+`, `-`, `|` and `>`
+    Ascii,
+}
+
+const UP: u8 = 1;
+const DOWN: u8 = 2;
+const LEFT: u8 = 4;
+const RIGHT: u8 = 8;
+
+impl Charset {
+    /// The character joining the neighbours of a cell given by `mask`
+    fn line(self, mask: u8) -> char {
+        let sides = (mask & UP != 0, mask & DOWN != 0, mask & LEFT != 0, mask & RIGHT != 0);
+        if self == Charset::Ascii {
+            return match sides {
+                (false, false, false, false) => ' ',
+                (false, false, _, _) => '-',
+                (_, _, false, false) => '|',
+                _ => '+',
+            };
+        }
+        match sides {
+            (false, false, false, false) => ' ',
+            (false, false, _, _) => '─',
+            (_, _, false, false) => '│',
+            (false, true, false, true) => '┌',
+            (true, false, false, true) => '└',
+            (false, true, true, false) => '┐',
+            (true, false, true, false) => '┘',
+            (true, true, false, true) => '├',
+            (true, true, true, false) => '┤',
+            (false, true, true, true) => '┬',
+            (true, false, true, true) => '┴',
+            (true, true, true, true) => '┼',
+        }
+    }
+
+    fn arrow(self) -> char {
+        match self {
+            Charset::Unicode => '▶',
+            Charset::Ascii => '>',
+        }
+    }
+
+    fn to(self) -> &'static str {
+        match self {
+            Charset::Unicode => "→",
+            Charset::Ascii => "->",
+        }
+    }
+}
+
+/// A line of the drawing
+enum Row {
+    Top,
+    Title(String),
+    /// An action, and whether an arrow leaves from it
+    Action(String, bool),
+    Bottom,
+}
+
+/// Draw `graph`, or nothing if it has no nodes
+pub fn render(graph: &MartialGraph, charset: Charset) -> String {
+    let depths: BTreeMap<&Node, usize> = graph.depths().into_iter().collect();
+    let mut order: Vec<&Node> = graph.nodes.iter().collect();
+    order.sort_by_key(|node| depths.get(node).copied().unwrap_or(usize::MAX));
+
+    // The rows of the boxes, and the arrows as (from, to) row indices
+    let mut rows = Vec::new();
+    let mut titles = Vec::new();
+    let mut pending: Vec<(usize, &Node)> = Vec::new();
+    for node in &order {
+        rows.push(Row::Top);
+        titles.push(rows.len());
+        rows.push(Row::Title(node.id()));
+        for edge in graph.edges.iter().filter(|e| e.from == **node) {
+            let looped = edge.to == edge.from;
+            if !looped {
+                pending.push((rows.len(), &edge.to));
+            }
+            rows.push(Row::Action(action(edge, charset), !looped));
+        }
+        rows.push(Row::Bottom);
+    }
+    let mut arrows: Vec<(usize, usize)> = pending
+        .into_iter()
+        .filter_map(|(from, to)| Some((from, titles[order.iter().position(|n| *n == to)?])))
+        .collect();
+    // Short arrows get the lanes closest to the boxes
+    arrows.sort_by_key(|&(from, to)| from.abs_diff(to));
+
+    let mut lanes: Vec<Vec<(usize, usize)>> = Vec::new();
+    let mut placed = Vec::new();
+    for &(from, to) in &arrows {
+        let span = (from.min(to), from.max(to));
+        let free = lanes.iter().position(|lane| lane.iter().all(|&(lo, hi)| hi < span.0 || span.1 < lo));
+        let lane = free.unwrap_or_else(|| {
+            lanes.push(Vec::new());
+            lanes.len() - 1
+        });
+        lanes[lane].push(span);
+        placed.push((from, to, lane));
+    }
+
+    let width = if lanes.is_empty() { 0 } else { 2 * lanes.len() + 1 };
+    let mut cells = vec![vec![0u8; width]; rows.len()];
+    let mut heads = vec![false; rows.len()];
+    for (from, to, lane) in placed {
+        let x = 2 * (lanes.len() - 1 - lane);
+        let down = to > from;
+        for row in cells.iter_mut().take(from.max(to)).skip(from.min(to) + 1) {
+            row[x] |= UP | DOWN;
+        }
+        cells[from][x] |= RIGHT | if down { DOWN } else { UP };
+        cells[to][x] |= RIGHT | if down { UP } else { DOWN };
+        for cell in &mut cells[from][x + 1..] {
+            *cell |= LEFT | RIGHT;
+        }
+        for cell in &mut cells[to][x + 1..width - 1] {
+            *cell |= LEFT | RIGHT;
+        }
+        heads[to] = true;
+    }
+
+    let inner = rows
+        .iter()
+        .map(|row| match row {
+            Row::Title(text) | Row::Action(text, _) => text.chars().count(),
+            _ => 0,
+        })
+        .max()
+        .unwrap_or(0)
+        + 2;
+    let (corners, horizontal, vertical, port) = match charset {
+        Charset::Unicode => (['┌', '┐', '└', '┘'], '─', '│', '┤'),
+        Charset::Ascii => (['+'; 4], '-', '|', '+'),
+    };
+    let mut out = String::new();
+    for (i, row) in rows.iter().enumerate() {
+        for (x, &mask) in cells[i].iter().enumerate() {
+            out.push(if heads[i] && x == width - 1 { charset.arrow() } else { charset.line(mask) });
+        }
+        let text = |border: char, text: &str| {
+            let padding = inner - 1 - text.chars().count();
+            format!("{} {}{}{}", border, text, " ".repeat(padding), vertical)
+        };
+        let line = match row {
+            Row::Top => format!("{}{}{}", corners[0], horizontal.to_string().repeat(inner), corners[1]),
+            Row::Bottom => format!("{}{}{}", corners[2], horizontal.to_string().repeat(inner), corners[3]),
+            Row::Title(title) => text(vertical, title),
+            Row::Action(action, arrow) => text(if *arrow { port } else { vertical }, action),
+        };
+        out.push_str(&line);
+        out.push('\n');
+    }
+    out
+}
+
+/// The line of an action in the box it leaves
+fn action(edge: &Edge, charset: Charset) -> String {
+    let mut text = if edge.to == edge.from {
+        format!("{} (stays)", edge.action)
+    } else {
+        format!("{} {} {}", edge.action, charset.to(), edge.to.id())
+    };
+    if edge.failure {
+        text.push_str(" (on failure)");
+    }
+    if edge.reaction {
+        text.push_str(" (reaction)");
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loader::{load_sources, SourceFile};
+
+    fn graph(source: &str) -> MartialGraph {
+        MartialGraph::from_system(&load_sources("club", &[SourceFile::new("a.martial", source)]).unwrap())
+    }
+
+    #[test]
+    fn test_render() {
+        let graph = graph(
+            "roles { Top, Bottom }\nstate Guard\nstate Mount\n\
+             sequence Sweep:\n    Hip: Guard[Bottom] -> Mount[Top]\n    Grip: Mount[Top] -> Mount[Top]",
+        );
+        assert_eq!(
+            render(&graph, Charset::Unicode),
+            "   ┌──────────────────┐\n\
+             \x20  │ Guard[Bottom]    │\n\
+             ┌──┤ Hip → Mount[Top] │\n\
+             │  └──────────────────┘\n\
+             │  ┌──────────────────┐\n\
+             └─▶│ Mount[Top]       │\n\
+             \x20  │ Grip (stays)     │\n\
+             \x20  └──────────────────┘\n"
+        );
+        assert!(render(&graph, Charset::Ascii).contains("+--+ Hip -> Mount[Top] |\n|  +-"));
+        assert_eq!(render(&graph.sequence("Nothing"), Charset::Unicode), "");
+    }
+
+    #[test]
+    fn test_lanes() {
+        // Going back up crosses the lane of the shorter arrow
+        let graph = graph(
+            "roles { Top }\nstate A\nstate B\nstate C\n\
+             sequence S:\n    Go: A[Top] -> B[Top]\n    On: B[Top] -> C[Top]\n    Back: C[Top] -> A[Top]",
+        );
+        assert_eq!(
+            render(&graph, Charset::Unicode),
+            "     ┌───────────────┐\n\
+             ┌───▶│ A[Top]        │\n\
+             │ ┌──┤ Go → B[Top]   │\n\
+             │ │  └───────────────┘\n\
+             │ │  ┌───────────────┐\n\
+             │ └─▶│ B[Top]        │\n\
+             │ ┌──┤ On → C[Top]   │\n\
+             │ │  └───────────────┘\n\
+             │ │  ┌───────────────┐\n\
+             │ └─▶│ C[Top]        │\n\
+             └────┤ Back → A[Top] │\n\
+             \x20    └───────────────┘\n"
+        );
+    }
+}
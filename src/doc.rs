@@ -0,0 +1,266 @@
+//! Markdown handbook generation
+//!
+//! Renders a validated system as a printable document: the roles, a section
+//! per group with the techniques entering and leaving each of its states,
+//! and a step table per sequence. Everything is sorted by name so that the
+//! output is stable across runs.
+
+use crate::ast::{SequenceStep, StateRef};
+use crate::semantic::MartialSystem;
+use std::collections::BTreeSet;
+use std::fmt::Write;
+use std::path::Path;
+
+fn state_ref(r: &StateRef) -> String {
+    r.to_string()
+}
+
+/// Steps of every sequence, with the sequence name, sorted by sequence
+pub(crate) fn all_steps(system: &MartialSystem) -> Vec<(&str, &SequenceStep)> {
+    let names: Vec<&String> = system.sequences.keys().collect();
+    names
+        .into_iter()
+        .flat_map(|name| {
+            system.sequences[name]
+                .steps
+                .iter()
+                .map(move |step| (name.as_str(), step))
+        })
+        .collect()
+}
+
+fn write_state(out: &mut String, system: &MartialSystem, name: &str) {
+    let state = &system.states[name];
+    let _ = writeln!(out, "### {}\n", name);
+    if let Some(roles) = &state.allowed_roles {
+        let _ = writeln!(out, "Roles: {}\n", roles.join(", "));
+    }
+
+    let steps = all_steps(system);
+    let incoming: Vec<_> = steps.iter().filter(|(_, s)| s.to.state == name).collect();
+    let outgoing: Vec<_> = steps.iter().filter(|(_, s)| s.from.state == name).collect();
+
+    if incoming.is_empty() && outgoing.is_empty() {
+        out.push_str("No techniques lead to or from this state.\n\n");
+        return;
+    }
+    write_steps(out, "Entered by", &incoming);
+    write_steps(out, "Leads to", &outgoing);
+}
+
+fn write_steps(out: &mut String, title: &str, steps: &[&(&str, &SequenceStep)]) {
+    if steps.is_empty() {
+        return;
+    }
+    let _ = writeln!(out, "{}:\n", title);
+    for (sequence, step) in steps {
+        let _ = writeln!(
+            out,
+            "- **{}** from {} to {} _({})_",
+            step.action_name,
+            state_ref(&step.from),
+            state_ref(&step.to),
+            sequence
+        );
+    }
+    out.push('\n');
+}
+
+/// Render a system as a Markdown handbook
+pub fn to_markdown(system: &MartialSystem) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "# {}\n", system.name);
+
+    // Roles
+    let roles: BTreeSet<&String> = system.roles.iter().collect();
+    out.push_str("## Roles\n\n");
+    for role in roles {
+        let _ = writeln!(out, "- {}", role);
+    }
+    out.push('\n');
+
+    // Sources with front-matter metadata
+    if !system.metadata.is_empty() {
+        out.push_str("## Sources\n\n");
+        out.push_str("| File | Author | Level | Reviewed |\n");
+        out.push_str("|------|--------|-------|----------|\n");
+        for (file, metadata) in &system.metadata {
+            let cell = |value: &Option<String>| value.clone().unwrap_or_default();
+            let name = Path::new(file).file_name().and_then(|n| n.to_str()).unwrap_or(file);
+            let _ = writeln!(
+                out,
+                "| {} | {} | {} | {} |",
+                name,
+                cell(&metadata.author),
+                cell(&metadata.level),
+                cell(&metadata.reviewed)
+            );
+        }
+        out.push('\n');
+    }
+
+    // States, grouped
+    let group_names: Vec<&String> = system.groups.keys().collect();
+    let mut grouped = BTreeSet::new();
+    for group in group_names {
+        let _ = writeln!(out, "## Group: {}\n", group);
+        for state in &system.groups[group] {
+            write_state(&mut out, system, state);
+            grouped.insert(state.as_str());
+        }
+    }
+
+    let ungrouped: Vec<&String> = system
+        .states
+        .keys()
+        .filter(|s| !grouped.contains(s.as_str()))
+        .collect();
+    if !ungrouped.is_empty() {
+        out.push_str(if grouped.is_empty() { "## States\n\n" } else { "## Other States\n\n" });
+        for state in ungrouped {
+            write_state(&mut out, system, state);
+        }
+    }
+
+    // Sequences
+    let sequence_names: Vec<&String> = system.sequences.keys().collect();
+    if !sequence_names.is_empty() {
+        out.push_str("## Sequences\n\n");
+    }
+    for name in sequence_names {
+        let sequence = &system.sequences[name];
+        let _ = writeln!(out, "### {}\n", name);
+        for url in sequence.videos() {
+            let _ = writeln!(out, "Video: <{}>\n", url);
+        }
+        out.push_str("| # | Action | From | To |\n");
+        out.push_str("|---|--------|------|----|\n");
+        for (i, step) in sequence.steps.iter().enumerate() {
+            let links: String = step.videos().iter().map(|url| format!(" [▶]({})", url)).collect();
+            let on_fail = match &step.on_fail {
+                Some(on_fail) => format!(" (on failure: {})", state_ref(on_fail)),
+                None => String::new(),
+            };
+            let _ = writeln!(
+                out,
+                "| {} | {}{} | {} | {}{} |",
+                i + 1,
+                step.action_name,
+                links,
+                state_ref(&step.from),
+                state_ref(&step.to),
+                on_fail
+            );
+        }
+        out.push('\n');
+    }
+
+    // Glossary
+    if !system.terms.is_empty() {
+        out.push_str("## Glossary\n\n");
+        for (term, definition) in &system.terms {
+            let _ = writeln!(out, "- **{}**: {}", term, definition);
+        }
+        out.push('\n');
+    }
+
+    out.truncate(out.trim_end().len());
+    out.push('\n');
+    out
+}
+
+/// Render the glossary as tab-separated flashcards, one `term<TAB>definition`
+/// per line, as accepted by Anki and most flashcard apps
+///
+/// The back of each card also names the sequences using the term as an action.
+pub fn flashcards(system: &MartialSystem) -> String {
+    let clean = |text: &str| text.replace(['\t', '\n'], " ");
+    let mut out = String::new();
+    for (term, definition) in &system.terms {
+        let sequences: Vec<&str> = system
+            .sequences
+            .iter()
+            .filter(|(_, s)| s.steps.iter().any(|step| step.action_name == *term))
+            .map(|(name, _)| name.as_str())
+            .collect();
+        let _ = write!(out, "{}\t{}", clean(term), clean(definition));
+        if !sequences.is_empty() {
+            let _ = write!(out, " (used in {})", sequences.join(", "));
+        }
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loader::{load_sources, SourceFile};
+
+    fn system() -> MartialSystem {
+        let sources = vec![SourceFile::new(
+            "system.martial",
+            "roles { Top, Bottom }\n\
+             state Mount roles { Top, Bottom }\n\
+             state Back\n\
+             state Guard\n\
+             group Dominant { Mount, Back }\n\
+             sequence TakeBack:\n    \
+                 Roll: Mount[Top] -> Back[Top]",
+        )];
+        load_sources("handbook", &sources).unwrap()
+    }
+
+    #[test]
+    fn test_sections() {
+        let markdown = to_markdown(&system());
+        assert!(markdown.starts_with("# handbook\n\n## Roles\n\n- Bottom\n- Top\n"));
+        assert!(markdown.contains("## Group: Dominant\n\n### Mount\n\nRoles: Top, Bottom\n"));
+        assert!(markdown.contains("## Other States\n\n### Guard\n\nNo techniques"));
+        assert!(markdown.contains("| 1 | Roll | Mount[Top] | Back[Top] |"));
+        assert!(!markdown.contains("## Sources"));
+    }
+
+    #[test]
+    fn test_video_links() {
+        let sources = vec![SourceFile::new(
+            "system.martial",
+            "roles { Top }\nstate Mount\n\
+             sequence Hold @video(\"https://videos.test/hold\"):\n    \
+             Settle: Mount[Top] -> Mount[Top] @video(\"https://videos.test/settle\")",
+        )];
+        let markdown = to_markdown(&load_sources("handbook", &sources).unwrap());
+        assert!(markdown.contains("### Hold\n\nVideo: <https://videos.test/hold>\n\n| # |"));
+        assert!(markdown.contains("| 1 | Settle [▶](https://videos.test/settle) | Mount[Top] | Mount[Top] |"));
+    }
+
+    #[test]
+    fn test_glossary() {
+        let sources = vec![SourceFile::new(
+            "system.martial",
+            "roles { Top }\nstate Mount\nterm Kesa \"Scarf hold\"\nterm OGoshi \"Major\thip throw\"\n\
+             sequence Hold:\n    Kesa: Mount[Top] -> Mount[Top]",
+        )];
+        let system = load_sources("handbook", &sources).unwrap();
+        assert!(to_markdown(&system).ends_with("## Glossary\n\n- **Kesa**: Scarf hold\n- **OGoshi**: Major\thip throw\n"));
+        assert_eq!(flashcards(&system), "Kesa\tScarf hold (used in Hold)\nOGoshi\tMajor hip throw\n");
+    }
+
+    #[test]
+    fn test_sources_metadata() {
+        let sources = vec![
+            SourceFile::new("dir/roles.martial", "roles { Top }"),
+            SourceFile::new("dir/states.martial", "---\nauthor: Coach\nreviewed: 2024-03-01\n---\nstate Mount"),
+        ];
+        let markdown = to_markdown(&load_sources("handbook", &sources).unwrap());
+        assert!(markdown.contains("## Sources\n\n| File | Author | Level | Reviewed |\n"));
+        assert!(markdown.contains("| states.martial | Coach |  | 2024-03-01 |"));
+    }
+
+    #[test]
+    fn test_incoming_and_outgoing() {
+        let markdown = to_markdown(&system());
+        assert!(markdown.contains("### Mount\n\nRoles: Top, Bottom\n\nLeads to:\n\n- **Roll** from Mount[Top] to Back[Top] _(TakeBack)_"));
+        assert!(markdown.contains("### Back\n\nEntered by:\n\n- **Roll**"));
+    }
+}
@@ -0,0 +1,197 @@
+//! Floor patterns of kata
+//!
+//! The embusen of a kata is the line its performer moves along, and a kata
+//! is expected to end where it started. Steps encode it with two
+//! annotations: `@turn(90L)` turns the performer left or right before the
+//! step, by a multiple of 45 degrees, and `@advance` or `@advance(N)` then
+//! moves them one or `N` steps forward in the direction they face.
+//!
+//! Positions on the floor are counted in steps from the starting point,
+//! `y` growing in the starting facing and `x` to its right.
+
+use crate::ast::SequenceStep;
+use crate::prelude::*;
+use crate::semantic::MartialSystem;
+use serde::Serialize;
+
+/// Arrows of the eight facings, counterclockwise from the starting one
+const ARROWS: [char; 8] = ['↑', '↖', '←', '↙', '↓', '↘', '→', '↗'];
+
+/// A point of the floor, in steps from the starting point
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Point {
+    pub x: i32,
+    pub y: i32,
+}
+
+/// Where the performer stands and which way they face
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Place {
+    pub point: Point,
+    /// Degrees counterclockwise from the starting facing, below 360
+    pub facing: u32,
+}
+
+impl Place {
+    /// The place after turning by `turn` degrees counterclockwise then
+    /// advancing `steps`
+    pub fn moved(self, turn: i32, steps: u32) -> Place {
+        let facing = (self.facing as i32 + turn).rem_euclid(360) as u32;
+        // Turns are multiples of 45 degrees, so each facing is one of eight
+        let (dx, dy) = match facing / 45 {
+            0 => (0, 1),
+            1 => (-1, 1),
+            2 => (-1, 0),
+            3 => (-1, -1),
+            4 => (0, -1),
+            5 => (1, -1),
+            6 => (1, 0),
+            _ => (1, 1),
+        };
+        let steps = steps as i32;
+        Place {
+            point: Point {
+                x: self.point.x + dx * steps,
+                y: self.point.y + dy * steps,
+            },
+            facing,
+        }
+    }
+
+    /// An arrow pointing the way the performer faces, `↑` for the starting
+    /// facing
+    pub fn arrow(self) -> char {
+        ARROWS[(self.facing / 45) as usize % 8]
+    }
+}
+
+/// The starting place followed by the place after each step
+pub fn walk<'a>(steps: impl IntoIterator<Item = &'a SequenceStep>) -> Vec<Place> {
+    let mut places = vec![Place::default()];
+    for step in steps {
+        let last = places[places.len() - 1];
+        places.push(last.moved(step.turn().unwrap_or(0), step.advance().unwrap_or(0)));
+    }
+    places
+}
+
+/// Whether a step moves the performer on the floor
+pub fn has_direction(step: &SequenceStep) -> bool {
+    step.turn().is_some() || step.advance().is_some()
+}
+
+/// Where a step leaves the performer
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Mark {
+    pub sequence: String,
+    pub action: String,
+    #[serde(flatten)]
+    pub place: Place,
+}
+
+/// The floor pattern of sequences performed one after the other
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Embusen {
+    pub system_name: String,
+    pub sequences: Vec<String>,
+    pub marks: Vec<Mark>,
+    /// Whether the pattern ends at its starting point
+    pub returns: bool,
+}
+
+impl Embusen {
+    /// The floor pattern of the `sequences` of `system`, in order; names the
+    /// system does not declare are skipped
+    pub fn new(system: &MartialSystem, sequences: &[&str]) -> Self {
+        let sequences: Vec<_> = sequences.iter().filter_map(|name| system.sequences.get(*name)).collect();
+        let places = walk(sequences.iter().flat_map(|sequence| &sequence.steps));
+        let marks: Vec<Mark> = sequences
+            .iter()
+            .flat_map(|sequence| sequence.steps.iter().map(move |step| (sequence, step)))
+            .zip(&places[1..])
+            .map(|((sequence, step), place)| Mark {
+                sequence: sequence.name.clone(),
+                action: step.action_name.to_string(),
+                place: *place,
+            })
+            .collect();
+        Embusen {
+            system_name: system.name.clone(),
+            sequences: sequences.iter().map(|sequence| sequence.name.clone()).collect(),
+            returns: places[places.len() - 1].point == Point::default(),
+            marks,
+        }
+    }
+
+    /// Export as JSON
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string_pretty(self)
+    }
+
+    /// One line per step with where it leaves the performer, then whether
+    /// the pattern returns to its starting point
+    pub fn to_text(&self) -> String {
+        let mut out = format!("Embusen of {}:\n", self.sequences.join(", "));
+        let width = self.marks.iter().map(|mark| mark.action.len()).max().unwrap_or(0);
+        let mut sequence = "";
+        for (i, mark) in self.marks.iter().enumerate() {
+            if mark.sequence != sequence {
+                sequence = &mark.sequence;
+                out.push_str(&format!("  {}\n", sequence));
+            }
+            out.push_str(&format!(
+                "    {:>2}. {:<width$}  {} ({}, {})\n",
+                i + 1,
+                mark.action,
+                mark.place.arrow(),
+                mark.place.point.x,
+                mark.place.point.y,
+                width = width
+            ));
+        }
+        let end = self.marks.last().map_or(Point::default(), |mark| mark.place.point);
+        if self.returns {
+            out.push_str("✓ Returns to its starting point\n");
+        } else {
+            out.push_str(&format!("✗ Ends at ({}, {}), not at its starting point\n", end.x, end.y));
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loader::{self, SourceFile};
+
+    #[test]
+    fn test_embusen() {
+        let source = "roles { Migi, Hidari }\nstate Yoi\nstate Zenkutsu\n\
+                      sequence FirstLine:\n    GedanBarai: Yoi[Migi] -> Zenkutsu[Hidari] @turn(90L) @advance\n    \
+                      OiZuki: Zenkutsu[Hidari] -> Zenkutsu[Migi] @advance\n\
+                      sequence SecondLine:\n    GedanBarai: Zenkutsu[Migi] -> Zenkutsu[Migi] @turn(180)\n    \
+                      OiZuki: Zenkutsu[Migi] -> Zenkutsu[Hidari] @advance(2)\n\
+                      sequence Close:\n    Yame: Zenkutsu[Hidari] -> Yoi[Migi] @turn(90L) @advance(0)";
+        let system = loader::load_sources("heian", &[SourceFile::new("heian.martial", source)]);
+        let error = system.unwrap_err().to_string();
+        assert!(error.contains("Annotation '@advance' takes a positive number of steps"), "{}", error);
+
+        let source = source.replace(" @advance(0)", "");
+        let system = loader::load_sources("heian", &[SourceFile::new("heian.martial", &source)]).unwrap();
+        let embusen = Embusen::new(&system, &["FirstLine", "SecondLine", "Close"]);
+        let places: Vec<(i32, i32, char)> =
+            embusen.marks.iter().map(|mark| (mark.place.point.x, mark.place.point.y, mark.place.arrow())).collect();
+        assert_eq!(places, [(-1, 0, '←'), (-2, 0, '←'), (-2, 0, '→'), (0, 0, '→'), (0, 0, '↑')]);
+        assert!(embusen.returns);
+        assert!(embusen.to_text().ends_with("  Close\n     5. Yame        ↑ (0, 0)\n✓ Returns to its starting point\n"));
+
+        let first = Embusen::new(&system, &["FirstLine"]);
+        assert!(!first.returns);
+        assert!(first.to_text().ends_with("✗ Ends at (-2, 0), not at its starting point\n"), "{}", first.to_text());
+
+        assert_eq!(crate::ast::parse_turn("45R"), Some(-45));
+        assert_eq!(crate::ast::parse_turn("90"), None);
+        assert_eq!(crate::ast::parse_turn("100L"), None);
+        assert_eq!(Place::default().moved(-45, 2).point, Point { x: 2, y: 2 });
+    }
+}
@@ -0,0 +1,145 @@
+//! Crate-level error type
+//!
+//! Each stage keeps its own error type, with the details callers match on.
+//! [`Error`] wraps any of them, so that applications using several stages
+//! can propagate them with `?` and hand them to `anyhow` or `thiserror`.
+//! Its message and source are those of the wrapped error.
+
+use crate::lexer::LexError;
+use crate::loader::LoadError;
+use crate::metadata::MetadataError;
+use crate::parser::ParseError;
+use crate::semantic::SemanticError;
+use core::fmt;
+
+#[cfg(feature = "std")]
+use crate::{build::BuildError, config::ConfigError, import::ImportError, refactor::RefactorError, sparring::LogError};
+
+/// Any error raised by the crate
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum Error {
+    Lex(LexError),
+    Parse(ParseError),
+    Metadata(MetadataError),
+    Semantic(SemanticError),
+    Load(LoadError),
+    #[cfg(feature = "std")]
+    Config(ConfigError),
+    #[cfg(feature = "std")]
+    Import(ImportError),
+    #[cfg(feature = "std")]
+    Refactor(RefactorError),
+    #[cfg(feature = "std")]
+    Build(BuildError),
+    #[cfg(feature = "std")]
+    Log(LogError),
+}
+
+/// Result with the crate-level [`Error`]
+pub type Result<T, E = Error> = core::result::Result<T, E>;
+
+impl Error {
+    /// The wrapped error
+    fn inner(&self) -> &(dyn core::error::Error + 'static) {
+        match self {
+            Error::Lex(error) => error,
+            Error::Parse(error) => error,
+            Error::Metadata(error) => error,
+            Error::Semantic(error) => error,
+            Error::Load(error) => error,
+            #[cfg(feature = "std")]
+            Error::Config(error) => error,
+            #[cfg(feature = "std")]
+            Error::Import(error) => error,
+            #[cfg(feature = "std")]
+            Error::Refactor(error) => error,
+            #[cfg(feature = "std")]
+            Error::Build(error) => error,
+            #[cfg(feature = "std")]
+            Error::Log(error) => error,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self.inner(), f)
+    }
+}
+
+impl core::error::Error for Error {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        self.inner().source()
+    }
+}
+
+macro_rules! from_error {
+    ($($(#[$attr:meta])* $variant:ident($error:ty)),* $(,)?) => {
+        $(
+            $(#[$attr])*
+            impl From<$error> for Error {
+                fn from(error: $error) -> Self {
+                    Error::$variant(error)
+                }
+            }
+        )*
+    };
+}
+
+from_error! {
+    Lex(LexError),
+    Parse(ParseError),
+    Metadata(MetadataError),
+    Semantic(SemanticError),
+    Load(LoadError),
+    #[cfg(feature = "std")]
+    Config(ConfigError),
+    #[cfg(feature = "std")]
+    Import(ImportError),
+    #[cfg(feature = "std")]
+    Refactor(RefactorError),
+    #[cfg(feature = "std")]
+    Build(BuildError),
+    #[cfg(feature = "std")]
+    Log(LogError),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Position;
+    use crate::loader::{load_sources, SourceFile};
+    use core::error::Error as _;
+
+    fn load(content: &str) -> Result<usize> {
+        let system = load_sources("test", &[SourceFile::new("a.martial", content)])?;
+        Ok(system.states.len())
+    }
+
+    #[test]
+    fn test_source_chain() {
+        let error = load("roles { Top }\nstate").unwrap_err();
+        assert!(matches!(error, Error::Load(LoadError::Parse { .. })));
+        assert_eq!(error.to_string(), "Parse error in a.martial: Parse error at line 2, column 6: Expected identifier, got EOF");
+        let source = error.source().unwrap();
+        assert_eq!(source.to_string(), "Parse error at line 2, column 6: Expected identifier, got EOF");
+        assert!(source.source().is_none());
+
+        let error = Error::from(SemanticError::new("Role 'Top' is not defined", "state Mount"));
+        assert_eq!(error.to_string(), "Semantic error in state Mount: Role 'Top' is not defined");
+        assert!(error.source().is_none());
+        assert!(Error::from(LoadError::NoSources { path: "x".to_string() }).source().is_none());
+    }
+
+    #[test]
+    fn test_boxed() {
+        let boxed: Box<dyn std::error::Error + Send + Sync> = Error::from(LexError {
+            message: "Unexpected character '$'".to_string(),
+            position: Position { line: 1, column: 3 },
+        })
+        .into();
+        assert_eq!(boxed.to_string(), "Lexer error at line 1, column 3: Unexpected character '$'");
+        assert_eq!(load("roles { Top }\nstate Mount").unwrap(), 1);
+    }
+}
@@ -0,0 +1,508 @@
+//! Graph export formats
+//!
+//! Every format of the graph implements [`GraphExporter`] and is found by
+//! name in a [`GraphExporterRegistry`]. `mat graph --format`, `mat dot` and
+//! the exports of `mat build` go through the registry, so a format added by
+//! a downstream crate works everywhere a built-in one does.
+//!
+//! ```
+//! use martial_lang::export::{ExportOptions, GraphExporter, GraphExporterRegistry};
+//! use martial_lang::graph::MartialGraph;
+//!
+//! /// One line per transition
+//! struct EdgeList;
+//!
+//! impl GraphExporter for EdgeList {
+//!     fn name(&self) -> &'static str {
+//!         "edges"
+//!     }
+//!
+//!     fn extension(&self) -> &'static str {
+//!         "txt"
+//!     }
+//!
+//!     fn export(&self, graph: &MartialGraph, _: &ExportOptions) -> Result<String, String> {
+//!         Ok(graph.edges.iter().map(|e| format!("{} -> {}\n", e.from.id(), e.to.id())).collect())
+//!     }
+//! }
+//!
+//! let mut registry = GraphExporterRegistry::builtin();
+//! registry.register(Box::new(EdgeList));
+//! assert_eq!(registry.get("edges").map(|e| e.extension()), Some("txt"));
+//! ```
+use crate::annotate::Annotations;
+use crate::diagram::{self, Charset};
+use crate::graph::{Edge, MartialGraph, Node};
+use crate::prelude::*;
+use alloc::collections::BTreeMap;
+use core::fmt::Write;
+
+/// Settings of an export beyond the graph itself
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ExportOptions {
+    /// Styling, by attribute name; each format picks those it understands
+    pub style: BTreeMap<String, String>,
+}
+
+/// A format the graph can be exported in
+pub trait GraphExporter: Send + Sync {
+    /// Name the format is chosen by
+    fn name(&self) -> &'static str;
+
+    /// Extension of the files holding the export, without the dot
+    fn extension(&self) -> &'static str;
+
+    /// The export of `graph`
+    fn export(&self, graph: &MartialGraph, options: &ExportOptions) -> Result<String, String>;
+}
+
+/// Graph exporters by name
+pub struct GraphExporterRegistry {
+    exporters: Vec<Box<dyn GraphExporter>>,
+}
+
+impl GraphExporterRegistry {
+    /// A registry without any exporter
+    pub fn new() -> Self {
+        GraphExporterRegistry { exporters: Vec::new() }
+    }
+
+    /// A registry with the built-in formats
+    pub fn builtin() -> Self {
+        let mut registry = GraphExporterRegistry::new();
+        registry.register(Box::new(JsonExporter));
+        registry.register(Box::new(DotExporter));
+        registry.register(Box::new(MermaidExporter));
+        registry.register(Box::new(TextExporter));
+        registry.register(Box::new(TikzExporter { per_sequence: false }));
+        registry.register(Box::new(TikzExporter { per_sequence: true }));
+        registry.register(Box::new(FlowsExporter));
+        registry.register(Box::new(HtmlExporter));
+        registry
+    }
+
+    /// Add `exporter`, replacing any exporter of the same name
+    pub fn register(&mut self, exporter: Box<dyn GraphExporter>) {
+        match self.exporters.iter_mut().find(|e| e.name() == exporter.name()) {
+            Some(slot) => *slot = exporter,
+            None => self.exporters.push(exporter),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&dyn GraphExporter> {
+        self.exporters.iter().find(|e| e.name() == name).map(|e| e.as_ref())
+    }
+
+    /// Names of the exporters, in registration order
+    pub fn names(&self) -> Vec<&'static str> {
+        self.exporters.iter().map(|e| e.name()).collect()
+    }
+
+    /// Remove the exporters, in registration order
+    pub fn into_exporters(self) -> Vec<Box<dyn GraphExporter>> {
+        self.exporters
+    }
+}
+
+impl Default for GraphExporterRegistry {
+    fn default() -> Self {
+        GraphExporterRegistry::builtin()
+    }
+}
+
+/// The graph as JSON, see [`MartialGraph::to_json`]
+pub struct JsonExporter;
+
+impl GraphExporter for JsonExporter {
+    fn name(&self) -> &'static str {
+        "json"
+    }
+
+    fn extension(&self) -> &'static str {
+        "json"
+    }
+
+    fn export(&self, graph: &MartialGraph, _: &ExportOptions) -> Result<String, String> {
+        graph.to_json().map_err(|e| e.to_string())
+    }
+}
+
+/// The graph as Graphviz DOT, see [`MartialGraph::to_dot`]; the style is
+/// added as graph attributes, `rankdir` replacing the default
+pub struct DotExporter;
+
+impl GraphExporter for DotExporter {
+    fn name(&self) -> &'static str {
+        "dot"
+    }
+
+    fn extension(&self) -> &'static str {
+        "dot"
+    }
+
+    fn export(&self, graph: &MartialGraph, options: &ExportOptions) -> Result<String, String> {
+        let dot = graph.to_dot();
+        if options.style.is_empty() {
+            return Ok(dot);
+        }
+        let attributes: String =
+            options.style.iter().map(|(key, value)| format!("  {}=\"{}\";\n", key, value)).collect();
+        let dot = if options.style.contains_key("rankdir") { dot.replacen("  rankdir=LR;\n", "", 1) } else { dot };
+        Ok(match dot.find('\n') {
+            Some(end) => format!("{}{}{}", &dot[..=end], attributes, &dot[end + 1..]),
+            None => dot,
+        })
+    }
+}
+
+/// The graph as a Mermaid flowchart, see [`MartialGraph::to_mermaid`]; the
+/// `direction` style replaces `LR`
+pub struct MermaidExporter;
+
+impl GraphExporter for MermaidExporter {
+    fn name(&self) -> &'static str {
+        "mermaid"
+    }
+
+    fn extension(&self) -> &'static str {
+        "mmd"
+    }
+
+    fn export(&self, graph: &MartialGraph, options: &ExportOptions) -> Result<String, String> {
+        let mermaid = graph.to_mermaid();
+        Ok(match options.style.get("direction") {
+            Some(direction) => mermaid.replacen("flowchart LR", &format!("flowchart {}", direction), 1),
+            None => mermaid,
+        })
+    }
+}
+
+/// The graph as boxes and arrows for the terminal, see [`diagram::render`];
+/// the `charset = "ascii"` style draws without box-drawing characters
+pub struct TextExporter;
+
+impl GraphExporter for TextExporter {
+    fn name(&self) -> &'static str {
+        "text"
+    }
+
+    fn extension(&self) -> &'static str {
+        "txt"
+    }
+
+    fn export(&self, graph: &MartialGraph, options: &ExportOptions) -> Result<String, String> {
+        let charset = match options.style.get("charset").map(String::as_str) {
+            Some("ascii") => Charset::Ascii,
+            Some("unicode") | None => Charset::Unicode,
+            Some(other) => return Err(format!("unknown charset '{}', expected ascii or unicode", other)),
+        };
+        Ok(diagram::render(graph, charset))
+    }
+}
+
+/// How sequences lead into each other, as Graphviz DOT, see
+/// [`MartialGraph::flows_to_dot`]
+pub struct FlowsExporter;
+
+impl GraphExporter for FlowsExporter {
+    fn name(&self) -> &'static str {
+        "flows"
+    }
+
+    fn extension(&self) -> &'static str {
+        "dot"
+    }
+
+    fn export(&self, graph: &MartialGraph, _: &ExportOptions) -> Result<String, String> {
+        Ok(graph.flows_to_dot())
+    }
+}
+
+/// The graph as a standalone HTML page with an SVG drawing, see
+/// [`Annotations::to_html`]; `mat graph --annotate` adds the findings
+pub struct HtmlExporter;
+
+impl GraphExporter for HtmlExporter {
+    fn name(&self) -> &'static str {
+        "html"
+    }
+
+    fn extension(&self) -> &'static str {
+        "html"
+    }
+
+    fn export(&self, graph: &MartialGraph, _: &ExportOptions) -> Result<String, String> {
+        Ok(Annotations::default().to_html(graph))
+    }
+}
+
+/// The graph as a standalone LaTeX document of TikZ pictures, to embed in
+/// printed material with `\includegraphics` or `\input`
+///
+/// `tikz` draws the whole graph, its states laid out left to right by their
+/// distance from an initial position; `tikz-sequences` draws each sequence
+/// as a chain of its own, one page per sequence. The style is added to the
+/// options of every picture, such as `scale = "0.8"`.
+pub struct TikzExporter {
+    /// Whether each sequence gets its own picture
+    pub per_sequence: bool,
+}
+
+impl GraphExporter for TikzExporter {
+    fn name(&self) -> &'static str {
+        if self.per_sequence {
+            "tikz-sequences"
+        } else {
+            "tikz"
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        "tex"
+    }
+
+    fn export(&self, graph: &MartialGraph, options: &ExportOptions) -> Result<String, String> {
+        let mut out = String::from("\\documentclass[tikz, border=10pt]{standalone}\n\\usetikzlibrary{arrows.meta}\n");
+        out.push_str("\\begin{document}\n");
+        if !self.per_sequence {
+            let depths: BTreeMap<&Node, usize> = graph.depths().into_iter().collect();
+            let unreached = depths.values().max().map_or(0, |d| d + 1);
+            let mut rows: BTreeMap<usize, usize> = BTreeMap::new();
+            let placed: Vec<(&Node, usize, usize)> = graph
+                .nodes
+                .iter()
+                .map(|node| {
+                    let column = depths.get(node).copied().unwrap_or(unreached);
+                    let row = rows.entry(column).or_insert(0);
+                    *row += 1;
+                    (node, column, *row - 1)
+                })
+                .collect();
+            let edges: Vec<&Edge> = graph.edges.iter().collect();
+            tikz_picture(&mut out, &graph.system_name, None, &placed, &edges, options);
+        }
+
+        let mut sequences: Vec<&str> = Vec::new();
+        for edge in graph.edges.iter().filter(|_| self.per_sequence) {
+            for sequence in core::iter::once(&edge.sequence).chain(&edge.sequences) {
+                if !sequences.contains(&sequence.as_str()) {
+                    sequences.push(sequence.as_str());
+                }
+            }
+        }
+        for sequence in sequences {
+            // The performer's side of the steps, their states in order
+            let edges: Vec<&Edge> = graph
+                .edges
+                .iter()
+                .filter(|e| !e.reaction && (e.sequence == sequence || e.sequences.iter().any(|s| *s == sequence)))
+                .collect();
+            let mut placed: Vec<(&Node, usize, usize)> = Vec::new();
+            for node in edges.iter().flat_map(|e| [&e.from, &e.to]) {
+                if !placed.iter().any(|(n, _, _)| *n == node) {
+                    placed.push((node, placed.len(), 0));
+                }
+            }
+            tikz_picture(&mut out, &format!("Sequence {}", sequence), Some(sequence), &placed, &edges, options);
+        }
+        out.push_str("\\end{document}\n");
+        Ok(out)
+    }
+}
+
+/// Append a picture of `edges` between the `placed` nodes, each at a column
+/// and a row, under `title` if any
+fn tikz_picture(
+    out: &mut String,
+    comment: &str,
+    title: Option<&str>,
+    placed: &[(&Node, usize, usize)],
+    edges: &[&Edge],
+    options: &ExportOptions,
+) {
+    let index = |node: &Node| placed.iter().position(|(n, _, _)| *n == node);
+    let mut settings = String::from("x=4cm, y=1.5cm, >=Stealth, state/.style={draw, rounded corners, align=center}");
+    for (key, value) in &options.style {
+        let _ = write!(settings, ", {}={}", key, value);
+    }
+    let _ = writeln!(out, "% {}\n\\begin{{tikzpicture}}[{}]", comment, settings);
+    if let Some(title) = title {
+        let _ = writeln!(out, "  \\node[font=\\bfseries, anchor=west] at (0, 1) {{{}}};", tex(title));
+    }
+    for (i, (node, column, row)) in placed.iter().enumerate() {
+        let _ = writeln!(
+            out,
+            "  \\node[state] (n{}) at ({}, {}) {{{}\\\\ {{\\small [{}]}}}};",
+            i,
+            column,
+            -(*row as i64),
+            tex(&node.state),
+            tex(&node.role)
+        );
+    }
+
+    // Parallel edges share one arrow, labelled with all of their actions
+    let mut arrows: Vec<((usize, usize), Vec<&Edge>)> = Vec::new();
+    for edge in edges {
+        let (Some(from), Some(to)) = (index(&edge.from), index(&edge.to)) else {
+            continue;
+        };
+        match arrows.iter_mut().find(|(ends, _)| *ends == (from, to)) {
+            Some((_, parallel)) => parallel.push(edge),
+            None => arrows.push(((from, to), vec![edge])),
+        }
+    }
+    for ((from, to), parallel) in &arrows {
+        let mut style = String::from("->");
+        if parallel.iter().all(|e| e.reaction) {
+            style.push_str(", dashed");
+        }
+        if parallel.iter().all(|e| e.failure) {
+            style.push_str(", red");
+        }
+        let path = if from == to {
+            "to[loop above]"
+        } else if arrows.iter().any(|(ends, _)| *ends == (*to, *from)) || placed[*to].1 <= placed[*from].1 {
+            "to[bend left=20]"
+        } else {
+            "to"
+        };
+        let labels: Vec<String> = parallel.iter().map(|e| tex(&e.action)).collect();
+        let _ = writeln!(
+            out,
+            "  \\draw[{}] (n{}) {} node[auto, font=\\small] {{{}}} (n{});",
+            style,
+            from,
+            path,
+            labels.join(", "),
+            to
+        );
+    }
+    out.push_str("\\end{tikzpicture}\n");
+}
+
+/// `text` with the characters special to HTML and SVG escaped, shared by
+/// the generated site and the other HTML outputs
+pub(crate) fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// `text` with the characters special to LaTeX escaped
+fn tex(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\textbackslash{}"),
+            '~' => escaped.push_str("\\textasciitilde{}"),
+            '^' => escaped.push_str("\\textasciicircum{}"),
+            '&' | '%' | '$' | '#' | '_' | '{' | '}' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loader::{load_sources, SourceFile};
+
+    fn graph() -> MartialGraph {
+        let source = "roles { Top, Bottom }\nstate Guard\nstate Mount\n\
+                      sequence Sweep:\n    Hip: Guard[Bottom] -> Mount[Top]";
+        MartialGraph::from_system(&load_sources("club", &[SourceFile::new("a.martial", source)]).unwrap())
+    }
+
+    #[test]
+    fn test_builtin() {
+        let registry = GraphExporterRegistry::builtin();
+        assert_eq!(registry.names(), ["json", "dot", "mermaid", "text", "tikz", "tikz-sequences", "flows", "html"]);
+        let graph = graph();
+        let options = ExportOptions::default();
+        for name in registry.names() {
+            let exporter = registry.get(name).unwrap();
+            // Flows are between sequences, without their actions
+            let expected = if name == "flows" { "Sweep" } else { "Hip" };
+            assert!(exporter.export(&graph, &options).unwrap().contains(expected), "{}", name);
+        }
+        assert_eq!(registry.get("dot").unwrap().export(&graph, &options).unwrap(), graph.to_dot());
+        assert_eq!(registry.get("mermaid").unwrap().extension(), "mmd");
+        assert!(registry.get("png").is_none());
+    }
+
+    #[test]
+    fn test_style() {
+        let graph = graph();
+        let mut options = ExportOptions::default();
+        options.style.insert("rankdir".to_string(), "TB".to_string());
+        options.style.insert("bgcolor".to_string(), "white".to_string());
+        let dot = DotExporter.export(&graph, &options).unwrap();
+        assert!(dot.starts_with("digraph \"club\" {\n  bgcolor=\"white\";\n  rankdir=\"TB\";\n  node"), "{}", dot);
+
+        options.style.insert("direction".to_string(), "TD".to_string());
+        assert!(MermaidExporter.export(&graph, &options).unwrap().starts_with("flowchart TD\n"));
+
+        options.style.insert("charset".to_string(), "ascii".to_string());
+        assert!(TextExporter.export(&graph, &options).unwrap().contains("+--+ Hip -> Mount[Top]"));
+        options.style.insert("charset".to_string(), "ebcdic".to_string());
+        assert!(TextExporter.export(&graph, &options).is_err());
+    }
+
+    #[test]
+    fn test_register() {
+        struct Count;
+
+        impl GraphExporter for Count {
+            fn name(&self) -> &'static str {
+                "dot"
+            }
+
+            fn extension(&self) -> &'static str {
+                "txt"
+            }
+
+            fn export(&self, graph: &MartialGraph, _: &ExportOptions) -> Result<String, String> {
+                Ok(graph.edges.len().to_string())
+            }
+        }
+
+        // A format of the same name replaces the built-in one
+        let mut registry = GraphExporterRegistry::builtin();
+        registry.register(Box::new(Count));
+        assert_eq!(registry.names(), ["json", "dot", "mermaid", "text", "tikz", "tikz-sequences", "flows", "html"]);
+        assert_eq!(registry.get("dot").unwrap().export(&graph(), &ExportOptions::default()).unwrap(), "1");
+    }
+
+    #[test]
+    fn test_tikz() {
+        let source = "roles { Top, Bottom }\nstate Guard\nstate Mount\nstate Arm_Lock\n\
+                      sequence Sweep:\n    Hip: Guard[Bottom] -> Mount[Top]\n    \
+                      Lock: Mount[Top] -> Arm_Lock[Top] onfail -> Mount[Top]\n\
+                      sequence Escape:\n    Bridge: Mount[Bottom] -> Guard[Top]";
+        let graph = MartialGraph::from_system(&load_sources("club", &[SourceFile::new("a.martial", source)]).unwrap());
+        let mut options = ExportOptions::default();
+        options.style.insert("scale".to_string(), "0.8".to_string());
+
+        let tex = TikzExporter { per_sequence: false }.export(&graph, &options).unwrap();
+        assert!(tex.starts_with("\\documentclass[tikz, border=10pt]{standalone}\n"), "{}", tex);
+        assert!(tex.ends_with("\\end{tikzpicture}\n\\end{document}\n"), "{}", tex);
+        assert_eq!(tex.matches("\\begin{tikzpicture}").count(), 1);
+        assert!(tex.contains(">=Stealth, state/.style={draw, rounded corners, align=center}, scale=0.8]"), "{}", tex);
+        assert!(tex.contains("\\node[state] (n0) at (2, 0) {Arm\\_Lock\\\\ {\\small [Top]}};"), "{}", tex);
+        assert!(tex.contains("\\node[state] (n3) at (0, -1) {Mount\\\\ {\\small [Bottom]}};"), "{}", tex);
+        assert!(tex.contains("\\draw[->] (n1) to node[auto, font=\\small] {Hip} (n4);"), "{}", tex);
+        assert!(tex.contains("\\draw[->, red] (n4) to[loop above] node[auto, font=\\small] {Lock} (n4);"), "{}", tex);
+
+        let sequences = TikzExporter { per_sequence: true }.export(&graph, &options).unwrap();
+        assert_eq!(sequences.matches("\\begin{tikzpicture}").count(), 2);
+        assert!(sequences.contains("% Sequence Sweep\n"), "{}", sequences);
+        assert!(sequences.contains("\\node[font=\\bfseries, anchor=west] at (0, 1) {Escape};"), "{}", sequences);
+        assert!(!sequences.contains("dashed"), "{}", sequences);
+    }
+}
@@ -0,0 +1,108 @@
+//! C ABI for embedding the engine
+//!
+//! Mirrors the browser bindings in [`crate::wasm`] for native hosts such as
+//! Swift or C. Inputs are NUL-terminated UTF-8 JSON arrays of
+//! `{ "path": ..., "content": ... }` files. Returned strings are owned by the
+//! library and must be released with [`martial_free_string`].
+//!
+//! See `include/martial.h` for the C declarations.
+
+use crate::wasm;
+use std::ffi::{CStr, CString};
+use std::os::raw::c_char;
+
+/// Read a borrowed C string, or `None` if it is null or not UTF-8
+unsafe fn read_str<'a>(ptr: *const c_char) -> Option<&'a str> {
+    if ptr.is_null() {
+        return None;
+    }
+    CStr::from_ptr(ptr).to_str().ok()
+}
+
+/// Hand a string over to the caller
+fn into_raw(s: String) -> *mut c_char {
+    CString::new(s).map(CString::into_raw).unwrap_or(std::ptr::null_mut())
+}
+
+/// Validate a set of files and return a JSON array of diagnostics
+///
+/// An empty array means the system is valid. Returns null if the input is
+/// not a valid files array.
+///
+/// # Safety
+///
+/// `files_json` must be null or point to a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn martial_validate(files_json: *const c_char) -> *mut c_char {
+    match read_str(files_json).map(wasm::validate_source) {
+        Some(Ok(diagnostics)) => into_raw(diagnostics),
+        _ => std::ptr::null_mut(),
+    }
+}
+
+/// Build the transition graph of a set of files as JSON
+///
+/// Returns null if the input is invalid or the system does not validate;
+/// use [`martial_validate`] to find out why.
+///
+/// # Safety
+///
+/// `files_json` must be null or point to a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn martial_graph_json(files_json: *const c_char) -> *mut c_char {
+    match read_str(files_json).map(wasm::graph_json) {
+        Some(Ok(graph)) => into_raw(graph),
+        _ => std::ptr::null_mut(),
+    }
+}
+
+/// Release a string returned by this library
+///
+/// # Safety
+///
+/// `s` must be null or a pointer returned by one of the functions above that
+/// has not been freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn martial_free_string(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    unsafe fn call(f: unsafe extern "C" fn(*const c_char) -> *mut c_char, input: &str) -> Option<String> {
+        let input = CString::new(input).unwrap();
+        let output = f(input.as_ptr());
+        if output.is_null() {
+            return None;
+        }
+        let result = CStr::from_ptr(output).to_str().unwrap().to_string();
+        martial_free_string(output);
+        Some(result)
+    }
+
+    const FILES: &str = r#"[
+        { "path": "system.martial", "content": "roles { Top }\nstate Mount\nstate Back\nsequence S:\n    Take: Mount[Top] -> Back[Top]" }
+    ]"#;
+
+    #[test]
+    fn test_validate_and_graph() {
+        unsafe {
+            assert_eq!(call(martial_validate, FILES).as_deref(), Some("[]"));
+            assert!(call(martial_graph_json, FILES).unwrap().contains("\"edges\""));
+        }
+    }
+
+    #[test]
+    fn test_invalid_input_returns_null() {
+        unsafe {
+            assert!(martial_validate(std::ptr::null()).is_null());
+            assert!(call(martial_validate, "{").is_none());
+            assert!(call(martial_graph_json, r#"[{ "path": "a.martial", "content": "state" }]"#).is_none());
+            martial_free_string(std::ptr::null_mut());
+        }
+    }
+}
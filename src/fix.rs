@@ -0,0 +1,172 @@
+//! Application of the corrections suggested by semantic errors
+//!
+//! The validator works on the AST, which does not keep the position of
+//! every name, so an [`Edit`] locates its target as an identifier within the
+//! construct starting at a position: the tokens from there until the next
+//! one at or left of its column on a later line, such as the next step of a
+//! sequence or the next declaration. Action names, which are followed by a
+//! colon, are never targets. Inserted lines go before the line of their
+//! position.
+
+use crate::lexer::{Lexer, PositionedToken, Position, Span, Token};
+use crate::loader::SourceFile;
+use crate::prelude::*;
+use crate::semantic::{Edit, SemanticError, Suggestion};
+
+/// The sources changed by applying `suggestion` of `error`, with their new
+/// content, or `None` if one of its edits cannot be located
+pub fn apply(sources: &[SourceFile], error: &SemanticError, suggestion: &Suggestion) -> Option<Vec<SourceFile>> {
+    let mut changed: Vec<(SourceFile, Vec<(Span, String)>)> = Vec::new();
+    for edit in &suggestion.edits {
+        let (Edit::Replace { file, position, .. } | Edit::Insert { file, position, .. }) = edit;
+        let path = file.as_ref().or(error.file.as_ref())?;
+        let position = position.or(error.position)?;
+        let index = match changed.iter().position(|(source, _)| &source.path == path) {
+            Some(index) => index,
+            None => {
+                changed.push((sources.iter().find(|source| &source.path == path)?.clone(), Vec::new()));
+                changed.len() - 1
+            }
+        };
+        let (source, spans) = &mut changed[index];
+        match edit {
+            Edit::Replace { target, replacement, .. } => {
+                let tokens = Lexer::new(&source.content).tokenize().ok()?;
+                spans.push((locate(&tokens, position, target)?, replacement.clone()));
+            }
+            Edit::Insert { text, .. } => {
+                let start = source.content.split_inclusive('\n').take(position.line - 1).map(str::len).sum();
+                let line = source.content[start..].lines().next()?;
+                let indent = &line[..line.len() - line.trim_start().len()];
+                spans.push((Span { start, end: start }, format!("{}{}\n", indent, text)));
+            }
+        }
+    }
+    Some(
+        changed
+            .into_iter()
+            .map(|(mut source, mut spans)| {
+                spans.sort_by_key(|(span, _)| core::cmp::Reverse(span.start));
+                for (span, text) in spans {
+                    source.content.replace_range(span.start..span.end, &text);
+                }
+                source
+            })
+            .collect(),
+    )
+}
+
+/// Span of the identifier `target` in the construct starting at `position`
+fn locate(tokens: &[PositionedToken], position: Position, target: &str) -> Option<Span> {
+    let start = tokens
+        .iter()
+        .position(|t| (t.position.line, t.position.column) >= (position.line, position.column))?;
+    let construct = &tokens[start..];
+    let end = construct
+        .iter()
+        .position(|t| t.position.line > position.line && t.position.column <= position.column)
+        .unwrap_or(construct.len());
+    let construct = &construct[..end];
+    construct.iter().enumerate().find_map(|(i, t)| {
+        let is_action = construct.get(i + 1).is_some_and(|next| next.token == Token::Colon);
+        (t.token == Token::Identifier(target) && !is_action).then_some(t.span)
+    })
+}
+
+/// The lines that differ between `old` and `new`: the number of the first
+/// one, counting from 1, then the removed and the added lines
+pub fn changed_lines<'a>(old: &'a str, new: &'a str) -> (usize, Vec<&'a str>, Vec<&'a str>) {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+    let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    (
+        prefix + 1,
+        old[prefix..old.len() - suffix].to_vec(),
+        new[prefix..new.len() - suffix].to_vec(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loader::load_sources;
+
+    fn error(sources: &[SourceFile]) -> SemanticError {
+        match load_sources("test", sources).unwrap_err() {
+            crate::loader::LoadError::Semantic(error) => error,
+            other => panic!("{:?}", other),
+        }
+    }
+
+    /// The sources changed by the first suggestion of their error
+    fn fixed(sources: &[SourceFile]) -> Vec<SourceFile> {
+        let error = error(sources);
+        apply(sources, &error, &error.suggestions[0]).unwrap()
+    }
+
+    #[test]
+    fn test_fix_typos() {
+        let roles = SourceFile::new("roles.martial", "roles { Top, Bottom }\nstate Mount\nstate Guard\n");
+        let source = "sequence Mout:\n    Mout: Mount[Top] -> Mout[Tp]\n    Stay: Mount[Top] -> Mount[Top]\n";
+        let sequences = SourceFile::new("a.martial", source);
+
+        let sources = [roles.clone(), sequences];
+        let changed = fixed(&sources);
+        assert_eq!(changed.len(), 1);
+        assert_eq!(changed[0].content, source.replace("-> Mout[Tp]", "-> Mount[Tp]"));
+        let changed = fixed(&[roles.clone(), changed[0].clone()]);
+        assert_eq!(changed[0].content, source.replace("-> Mout[Tp]", "-> Mount[Top]"));
+        assert!(load_sources("test", &[roles, changed[0].clone()]).is_ok());
+    }
+
+    #[test]
+    fn test_fix_roles() {
+        let states = SourceFile::new("states.martial", "roles { Top, Bottom }\nstate Mount roles { Top }\n");
+        let source = "sequence S:\n    Roll: Mount[Top] -> Mount[Bottom]\n";
+        let sources = [states, SourceFile::new("a.martial", source)];
+        let error = error(&sources);
+        assert_eq!(error.suggestions[0].message, "Allow role 'Bottom' in state 'Mount'");
+        let changed = apply(&sources, &error, &error.suggestions[0]).unwrap();
+        assert_eq!(changed[0].path, "states.martial");
+        assert_eq!(changed[0].content, "roles { Top, Bottom }\nstate Mount roles { Top, Bottom }\n");
+
+        let sources = [SourceFile::new("a.martial", "roles { Top }\nstate Mount\nsequence S:\n    Roll: Mount[Top] -> Mount[Referee]\n")];
+        let changed = fixed(&sources);
+        assert!(changed[0].content.starts_with("roles { Top, Referee }\n"), "{}", changed[0].content);
+    }
+
+    #[test]
+    fn test_fix_chains() {
+        let declarations = "roles { Top, Bottom }\nstate Guard\nstate Mount\nstate Back\n\
+                            sequence Known:\n    Pass: Guard[Top] -> Mount[Top]\n";
+        let source = "sequence S:\n    Sweep: Back[Top] -> Guard[Top]\n    Choke: Mount[Top] -> Back[Top]\n";
+        let sources = [SourceFile::new("a.martial", declarations), SourceFile::new("b.martial", source)];
+        let error = error(&sources);
+        let messages: Vec<&str> = error.suggestions.iter().map(|s| s.message.as_str()).collect();
+        assert_eq!(messages, ["Insert step 'Pass' from sequence Known", "Start the step at Guard[Top]"]);
+
+        let inserted = apply(&sources, &error, &error.suggestions[0]).unwrap();
+        assert_eq!(
+            inserted[0].content,
+            "sequence S:\n    Sweep: Back[Top] -> Guard[Top]\n    Pass: Guard[Top] -> Mount[Top]\n    Choke: Mount[Top] -> Back[Top]\n"
+        );
+        let restarted = apply(&sources, &error, &error.suggestions[1]).unwrap();
+        assert_eq!(restarted[0].content, source.replace("Choke: Mount", "Choke: Guard"));
+        for changed in [inserted, restarted] {
+            assert!(load_sources("test", &[sources[0].clone(), changed[0].clone()]).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_changed_lines() {
+        assert_eq!(changed_lines("a\nb\nc\n", "a\nB\nc\n"), (2, vec!["b"], vec!["B"]));
+        assert_eq!(changed_lines("a\nc\n", "a\nb\nc\n"), (2, vec![], vec!["b"]));
+        assert_eq!(changed_lines("a\n", "a\n"), (2, vec![], vec![]));
+    }
+}
@@ -0,0 +1,387 @@
+//! Fixture tests of `mat test`
+//!
+//! A fixture is a `.martial` file or a directory of them, with a
+//! `NAME.expect.json` file next to it describing what loading it gives:
+//!
+//! ```json
+//! {
+//!   "roles": ["Top", "Bottom"],
+//!   "states": ["Guard", "Mount"],
+//!   "sequences": ["Sweep"],
+//!   "diagnostics": []
+//! }
+//! ```
+//!
+//! `guard.expect.json` describes `guard.martial`, or the directory `guard`
+//! if there is one. Every key is optional. Names are compared as sets.
+//! `diagnostics` lists the expected errors in the order they are reported;
+//! an empty list expects a valid system, and leaving it out accepts any
+//! errors as long as the other keys hold.
+//!
+//! A `NAME.snapshot.json` file next to the expectation holds the
+//! [`snapshot`] of the system the fixture must match. [`update_snapshot`]
+//! writes it.
+
+use crate::config::Config;
+use crate::loader::{self, LoadError, SourceFile};
+use crate::semantic::MartialSystem;
+use crate::snapshot;
+use serde::Deserialize;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Suffix of expectation files
+pub const EXPECT_SUFFIX: &str = ".expect.json";
+
+/// Suffix of snapshot files
+pub const SNAPSHOT_SUFFIX: &str = ".snapshot.json";
+
+/// Contents of an expectation file
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Expectation {
+    pub roles: Option<Vec<String>>,
+    pub states: Option<Vec<String>>,
+    pub sequences: Option<Vec<String>>,
+    pub diagnostics: Option<Vec<ExpectedDiagnostic>>,
+}
+
+/// An error a fixture is expected to report
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExpectedDiagnostic {
+    /// Text the message must contain
+    pub message: String,
+    /// File of the error, relative to the fixture directory
+    pub file: Option<String>,
+    pub line: Option<usize>,
+}
+
+impl ExpectedDiagnostic {
+    /// Whether `error`, reported by a fixture under `base`, is this one
+    fn matches(&self, error: &LoadError, base: &Path) -> bool {
+        let file = error.file().map(|file| Path::new(file).strip_prefix(base).unwrap_or(Path::new(file)));
+        error.to_string().contains(&self.message)
+            && self.file.as_ref().is_none_or(|expected| file == Some(Path::new(expected)))
+            && self.line.is_none_or(|line| error.position().map(|p| p.line) == Some(line))
+    }
+}
+
+impl fmt::Display for ExpectedDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "\"{}\"", self.message)?;
+        match (&self.file, self.line) {
+            (Some(file), Some(line)) => write!(f, " at {}:{}", file, line),
+            (Some(file), None) => write!(f, " in {}", file),
+            (None, Some(line)) => write!(f, " at line {}", line),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+/// A fixture and its expectation file
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fixture {
+    /// Path of the fixture without extension, relative to the search root
+    pub name: String,
+    /// The `.martial` file or directory
+    pub path: PathBuf,
+    pub expect: PathBuf,
+    /// Snapshot of the system, compared if the file exists
+    pub snapshot: PathBuf,
+}
+
+/// Outcome of a fixture
+#[derive(Debug, Clone, PartialEq)]
+pub struct FixtureResult {
+    pub name: String,
+    /// What did not match the expectation; empty if the fixture passed
+    pub failures: Vec<String>,
+}
+
+impl FixtureResult {
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+/// Find the fixtures under `dir`, sorted by name
+///
+/// Expectation files without a fixture next to them are reported as
+/// fixtures whose path does not exist, so that running them fails.
+pub fn discover(dir: &Path) -> io::Result<Vec<Fixture>> {
+    let mut fixtures = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(current) = pending.pop() {
+        for entry in fs::read_dir(&current)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                pending.push(path);
+                continue;
+            }
+            let Some(stem) = path.to_str().and_then(|p| p.strip_suffix(EXPECT_SUFFIX)) else {
+                continue;
+            };
+            let directory = PathBuf::from(stem);
+            let fixture = if directory.is_dir() {
+                directory
+            } else {
+                PathBuf::from(format!("{}.martial", stem))
+            };
+            let name = Path::new(stem).strip_prefix(dir).unwrap_or(Path::new(stem));
+            fixtures.push(Fixture {
+                name: name.to_string_lossy().into_owned(),
+                path: fixture,
+                snapshot: PathBuf::from(format!("{}{}", stem, SNAPSHOT_SUFFIX)),
+                expect: path,
+            });
+        }
+    }
+    fixtures.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(fixtures)
+}
+
+/// Read the sources of a fixture
+fn read_sources(path: &Path) -> Result<Vec<SourceFile>, String> {
+    let path_str = path.to_string_lossy();
+    if path.is_dir() {
+        let config = Config::load(&path_str).map_err(|e| e.to_string())?;
+        return loader::read_configured(&path_str, &config).map_err(|e| e.to_string());
+    }
+    let content = fs::read_to_string(path).map_err(|e| format!("cannot read {}: {}", path_str, e))?;
+    Ok(vec![SourceFile::new(path_str, content)])
+}
+
+/// Load the sources of a fixture, keeping every parse error
+fn load(name: &str, sources: &[SourceFile]) -> Result<MartialSystem, Vec<LoadError>> {
+    let mut files = Vec::new();
+    let mut errors = Vec::new();
+    for result in loader::parse_sources(sources) {
+        match result {
+            Ok(file) => files.push(file),
+            Err(error) => errors.push(error),
+        }
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    loader::validate_files(name, &files).map_err(|error| vec![error])
+}
+
+/// Load the system of a fixture; the outer error is about reading it
+fn load_fixture(fixture: &Fixture) -> Result<Result<MartialSystem, Vec<LoadError>>, String> {
+    let sources = read_sources(&fixture.path)?;
+    let name = fixture.path.file_stem().and_then(|n| n.to_str()).unwrap_or("unknown");
+    Ok(load(name, &sources))
+}
+
+/// Write the snapshot of a fixture's system, returning whether it changed
+///
+/// Fails if the fixture does not load without errors.
+pub fn update_snapshot(fixture: &Fixture) -> Result<bool, String> {
+    let system = match load_fixture(fixture)? {
+        Ok(system) => system,
+        Err(errors) => return Err(format!("{} has {} error(s)", fixture.name, errors.len())),
+    };
+    let content = snapshot::system(&system);
+    if fs::read_to_string(&fixture.snapshot).ok().as_deref() == Some(content.as_str()) {
+        return Ok(false);
+    }
+    fs::write(&fixture.snapshot, content).map_err(|e| format!("cannot write {}: {}", fixture.snapshot.display(), e))?;
+    Ok(true)
+}
+
+/// Compare names as sets, describing the difference
+fn compare(what: &str, expected: &[String], actual: Vec<&String>) -> Option<String> {
+    let mut expected: Vec<&String> = expected.iter().collect();
+    let mut actual = actual;
+    expected.sort();
+    expected.dedup();
+    actual.sort();
+    let missing: Vec<&str> = expected.iter().filter(|n| !actual.contains(n)).map(|n| n.as_str()).collect();
+    let unexpected: Vec<&str> = actual.iter().filter(|n| !expected.contains(n)).map(|n| n.as_str()).collect();
+    let mut differences = Vec::new();
+    if !missing.is_empty() {
+        differences.push(format!("missing {}", missing.join(", ")));
+    }
+    if !unexpected.is_empty() {
+        differences.push(format!("unexpected {}", unexpected.join(", ")));
+    }
+    (!differences.is_empty()).then(|| format!("{}: {}", what, differences.join("; ")))
+}
+
+/// Load a fixture and check it against its expectation
+pub fn run(fixture: &Fixture) -> FixtureResult {
+    let mut result = FixtureResult {
+        name: fixture.name.clone(),
+        failures: Vec::new(),
+    };
+    let expectation = match fs::read_to_string(&fixture.expect) {
+        Ok(json) => serde_json::from_str::<Expectation>(&json),
+        Err(e) => {
+            result.failures.push(format!("cannot read {}: {}", fixture.expect.display(), e));
+            return result;
+        }
+    };
+    let expectation = match expectation {
+        Ok(expectation) => expectation,
+        Err(e) => {
+            result.failures.push(format!("invalid {}: {}", fixture.expect.display(), e));
+            return result;
+        }
+    };
+    let loaded = match load_fixture(fixture) {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            result.failures.push(e);
+            return result;
+        }
+    };
+
+    let base = if fixture.path.is_dir() {
+        fixture.path.as_path()
+    } else {
+        fixture.path.parent().unwrap_or(Path::new(""))
+    };
+    let errors = loaded.as_ref().err().map(Vec::as_slice).unwrap_or_default();
+
+    if let Some(expected) = &expectation.diagnostics {
+        if expected.len() != errors.len() {
+            result.failures.push(format!("expected {} diagnostic(s), got {}", expected.len(), errors.len()));
+        }
+        for (expected, error) in expected.iter().zip(errors) {
+            if !expected.matches(error, base) {
+                result.failures.push(format!("expected diagnostic {}, got: {}", expected, error));
+            }
+        }
+    }
+
+    let system = match &loaded {
+        Ok(system) => system,
+        Err(errors) => {
+            let expects_names = expectation.roles.is_some() || expectation.states.is_some() || expectation.sequences.is_some();
+            if expects_names && expectation.diagnostics.is_none() {
+                for error in errors {
+                    result.failures.push(format!("unexpected diagnostic: {}", error));
+                }
+            }
+            if fixture.snapshot.exists() {
+                result.failures.push("cannot compare with the snapshot of a system with errors".to_string());
+            }
+            return result;
+        }
+    };
+    let names = [
+        ("roles", &expectation.roles, system.roles.iter().collect::<Vec<_>>()),
+        ("states", &expectation.states, system.states.keys().collect()),
+        ("sequences", &expectation.sequences, system.sequences.keys().collect()),
+    ];
+    for (what, expected, actual) in names {
+        if let Some(expected) = expected {
+            result.failures.extend(compare(what, expected, actual));
+        }
+    }
+    if let Ok(expected) = fs::read_to_string(&fixture.snapshot) {
+        if let Some(difference) = snapshot::diff(&expected, &snapshot::system(system)) {
+            result.failures.push(format!("snapshot differs at {}", difference));
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(root: &Path, path: &str, content: &str) {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    #[test]
+    fn test_discover_and_run() {
+        let root = std::env::temp_dir().join(format!("martial-fixture-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        write(&root, "guard.martial", "roles { Top, Bottom }\nstate Guard\nsequence Hold:\n    Grip: Guard[Top] -> Guard[Top]");
+        write(
+            &root,
+            "guard.expect.json",
+            r#"{"roles": ["Bottom", "Top"], "states": ["Guard"], "sequences": ["Hold"], "diagnostics": []}"#,
+        );
+        write(&root, "broken/roles.martial", "roles { Top }");
+        write(&root, "broken/seq.martial", "state Guard\nsequence Hold:\n    Grip: Guard[Top] -> Mount[Top]");
+        write(
+            &root,
+            "broken.expect.json",
+            r#"{"diagnostics": [{"message": "State 'Mount' is not defined", "file": "seq.martial", "line": 3}]}"#,
+        );
+        write(&root, "nested/wrong.martial", "roles { Top }\nstate Guard\nsequence Hold:\n    Grip: Guard[Top] -> Guard[Top]");
+        write(&root, "nested/wrong.expect.json", r#"{"roles": ["Top", "Bottom"], "states": ["Guard", "Top"]}"#);
+        write(&root, "orphan.expect.json", "{}");
+
+        let fixtures = discover(&root).unwrap();
+        let names: Vec<&str> = fixtures.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, ["broken", "guard", "nested/wrong", "orphan"]);
+        assert!(fixtures[0].path.is_dir());
+
+        let results: Vec<FixtureResult> = fixtures.iter().map(run).collect();
+        assert!(results[0].passed(), "{:?}", results[0]);
+        assert!(results[1].passed(), "{:?}", results[1]);
+        assert_eq!(results[2].failures, ["roles: missing Bottom", "states: missing Top"]);
+        assert!(results[3].failures[0].contains("cannot read"), "{:?}", results[3]);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_diagnostic_mismatches() {
+        let root = std::env::temp_dir().join(format!("martial-fixture-diagnostics-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        write(&root, "bad.martial", "roles { Top }\nstate Guard\nsequence Hold:\n    Grip: Guard[Top] -> Mount[Top]");
+        let fixture = Fixture {
+            name: "bad".to_string(),
+            path: root.join("bad.martial"),
+            expect: root.join("bad.expect.json"),
+            snapshot: root.join("bad.snapshot.json"),
+        };
+        let check = |expect: &str| {
+            write(&root, "bad.expect.json", expect);
+            run(&fixture).failures
+        };
+
+        assert!(check(r#"{"diagnostics": [{"message": "Mount", "file": "bad.martial", "line": 4}]}"#).is_empty());
+        let failures = check(r#"{"diagnostics": [{"message": "Mount", "line": 2}]}"#);
+        assert!(failures[0].starts_with("expected diagnostic \"Mount\" at line 2, got: "), "{:?}", failures);
+        assert_eq!(check(r#"{"diagnostics": []}"#)[0], "expected 0 diagnostic(s), got 1");
+        let failures = check(r#"{"states": ["Guard"]}"#);
+        assert!(failures[0].starts_with("unexpected diagnostic: "), "{:?}", failures);
+        assert!(check(r#"{"state": ["Guard"]}"#)[0].starts_with("invalid "));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_snapshots() {
+        let root = std::env::temp_dir().join(format!("martial-fixture-snapshot-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        write(&root, "guard.martial", "roles { Top }\nstate Guard\nsequence Hold:\n    Grip: Guard[Top] -> Guard[Top]");
+        write(&root, "guard.expect.json", "{}");
+        let fixture = discover(&root).unwrap().remove(0);
+        assert!(run(&fixture).passed());
+
+        assert!(update_snapshot(&fixture).unwrap());
+        assert!(!update_snapshot(&fixture).unwrap());
+        assert!(run(&fixture).passed());
+
+        write(&root, "guard.martial", "roles { Top }\nstate Guard\nsequence Hold:\n    Press: Guard[Top] -> Guard[Top]");
+        let failures = run(&fixture).failures;
+        assert!(failures[0].starts_with("snapshot differs at line "), "{:?}", failures);
+        assert!(failures[0].contains("Press"), "{:?}", failures);
+
+        write(&root, "guard.martial", "roles { Top }\nstate Guard roles { Referee }");
+        assert_eq!(run(&fixture).failures, ["cannot compare with the snapshot of a system with errors"]);
+        assert!(update_snapshot(&fixture).is_err());
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
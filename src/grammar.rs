@@ -0,0 +1,490 @@
+//! The grammar of the Martial DSL
+//!
+//! [`GRAMMAR`] describes the language once. `mat grammar` prints it as EBNF,
+//! `mat help syntax` shows its declarations, and the parser takes the
+//! alternatives it reports in errors from it. Tests derive sentences from
+//! every rule and check that the parser accepts them, and that the EBNF in
+//! the specification is the one printed here.
+
+use crate::prelude::*;
+use core::fmt;
+
+/// The right-hand side of a rule
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Expr {
+    /// A keyword or symbol, as written
+    Literal(&'static str),
+    /// Another rule
+    Rule(&'static str),
+    Seq(&'static [Expr]),
+    Alt(&'static [Expr]),
+    Optional(&'static Expr),
+    /// Zero or more
+    Repeat(&'static Expr),
+    /// One or more
+    OneOrMore(&'static Expr),
+    /// An alternative of a file format version only
+    Version(u32, &'static Expr),
+    /// A token class, described in prose
+    Lexical(&'static str),
+}
+
+/// A rule of the grammar
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rule {
+    pub name: &'static str,
+    pub expr: Expr,
+    /// A comment printed above the rule
+    pub note: Option<&'static str>,
+}
+
+const fn rule(name: &'static str, expr: Expr) -> Rule {
+    Rule { name, expr, note: None }
+}
+
+const IDENTIFIER: Expr = Expr::Rule("IDENTIFIER");
+const IDENTIFIERS: Expr = Expr::Seq(&[IDENTIFIER, Expr::Repeat(&Expr::Seq(&[Expr::Literal(","), IDENTIFIER]))]);
+const STATE_REF: Expr = Expr::Rule("state_ref");
+const ANNOTATIONS: Expr = Expr::Repeat(&Expr::Rule("annotation"));
+
+/// The rules, starting with `program`
+pub const GRAMMAR: &[Rule] = &[
+    rule(
+        "program",
+        Expr::Seq(&[Expr::Optional(&Expr::Rule("version_pragma")), Expr::OneOrMore(&Expr::Rule("declaration"))]),
+    ),
+    rule("version_pragma", Expr::Seq(&[Expr::Literal("martial"), Expr::Rule("NUMBER")])),
+    rule(
+        "declaration",
+        Expr::Alt(&[
+            Expr::Rule("roles_decl"),
+            Expr::Rule("state_decl"),
+            Expr::Rule("sequence_decl"),
+            Expr::Rule("group_decl"),
+            Expr::Rule("levels_decl"),
+            Expr::Rule("term_decl"),
+            Expr::Rule("ruleset_decl"),
+            Expr::Rule("template_decl"),
+            Expr::Rule("mirror_decl"),
+            Expr::Rule("assert_decl"),
+        ]),
+    ),
+    rule("roles_decl", Expr::Seq(&[Expr::Literal("roles"), Expr::Literal("{"), IDENTIFIERS, Expr::Literal("}")])),
+    rule(
+        "state_decl",
+        Expr::Seq(&[Expr::Literal("state"), IDENTIFIER, Expr::Optional(&Expr::Rule("state_roles")), ANNOTATIONS]),
+    ),
+    Rule {
+        name: "state_roles",
+        expr: Expr::Alt(&[
+            Expr::Version(1, &Expr::Seq(&[Expr::Literal("roles"), Expr::Literal("{"), IDENTIFIERS, Expr::Literal("}")])),
+            Expr::Version(2, &Expr::Seq(&[Expr::Literal("["), IDENTIFIERS, Expr::Literal("]")])),
+        ]),
+        note: Some("in martial 1, on the line of the state name"),
+    },
+    rule(
+        "sequence_decl",
+        Expr::Seq(&[
+            Expr::Optional(&Expr::Literal("mirrored")),
+            Expr::Optional(&Expr::Rule("alternating")),
+            Expr::Literal("sequence"),
+            IDENTIFIER,
+            ANNOTATIONS,
+            Expr::Literal(":"),
+            Expr::OneOrMore(&Expr::Rule("sequence_item")),
+        ]),
+    ),
+    Rule {
+        name: "alternating",
+        expr: Expr::Seq(&[
+            Expr::Literal("alternating"),
+            Expr::Literal("("),
+            IDENTIFIER,
+            Expr::Literal(","),
+            IDENTIFIER,
+            Expr::Literal(")"),
+        ]),
+        note: Some("two roles taking turns at the steps, the first one starting"),
+    },
+    rule("sequence_item", Expr::Alt(&[Expr::Rule("sequence_step"), Expr::Rule("invocation")])),
+    rule(
+        "sequence_step",
+        Expr::Seq(&[
+            IDENTIFIER,
+            Expr::Literal(":"),
+            STATE_REF,
+            Expr::Literal("->"),
+            STATE_REF,
+            Expr::Optional(&Expr::Rule("on_fail")),
+            Expr::Optional(&Expr::Rule("reaction")),
+            Expr::Optional(&Expr::Rule("REPETITION")),
+            ANNOTATIONS,
+        ]),
+    ),
+    rule("on_fail", Expr::Seq(&[Expr::Literal("onfail"), Expr::Literal("->"), STATE_REF])),
+    rule("reaction", Expr::Seq(&[Expr::Literal("/"), STATE_REF, Expr::Literal("->"), STATE_REF])),
+    rule(
+        "invocation",
+        Expr::Seq(&[
+            IDENTIFIER,
+            Expr::Rule("arguments"),
+            Expr::Repeat(&Expr::Seq(&[Expr::Literal(","), Expr::Rule("arguments")])),
+        ]),
+    ),
+    rule(
+        "arguments",
+        Expr::Seq(&[
+            Expr::Literal("("),
+            Expr::Optional(&Expr::Seq(&[
+                Expr::Rule("template_arg"),
+                Expr::Repeat(&Expr::Seq(&[Expr::Literal(","), Expr::Rule("template_arg")])),
+            ])),
+            Expr::Literal(")"),
+        ]),
+    ),
+    rule("template_arg", Expr::Alt(&[IDENTIFIER, STATE_REF])),
+    rule("state_ref", Expr::Seq(&[IDENTIFIER, Expr::Literal("["), IDENTIFIERS, Expr::Literal("]")])),
+    rule(
+        "group_decl",
+        Expr::Seq(&[Expr::Literal("group"), IDENTIFIER, Expr::Literal("{"), IDENTIFIERS, Expr::Literal("}")]),
+    ),
+    rule("levels_decl", Expr::Seq(&[Expr::Literal("levels"), Expr::Literal("{"), IDENTIFIERS, Expr::Literal("}")])),
+    rule("term_decl", Expr::Seq(&[Expr::Literal("term"), IDENTIFIER, Expr::Rule("STRING")])),
+    rule(
+        "ruleset_decl",
+        Expr::Seq(&[
+            Expr::Literal("ruleset"),
+            IDENTIFIER,
+            Expr::Literal("{"),
+            Expr::Repeat(&Expr::Rule("forbid_rule")),
+            Expr::Literal("}"),
+        ]),
+    ),
+    rule("forbid_rule", Expr::Seq(&[Expr::Literal("forbid"), IDENTIFIERS])),
+    Rule {
+        name: "template_decl",
+        expr: Expr::Seq(&[
+            Expr::Literal("template"),
+            IDENTIFIER,
+            Expr::Literal("("),
+            Expr::Optional(&IDENTIFIERS),
+            Expr::Literal(")"),
+            Expr::Literal("="),
+            Expr::OneOrMore(&Expr::Rule("sequence_step")),
+        ]),
+        note: Some("in the steps, a parameter may stand alone where a state_ref is expected"),
+    },
+    rule(
+        "mirror_decl",
+        Expr::Seq(&[
+            Expr::Literal("mirror"),
+            Expr::Rule("mirror_kind"),
+            Expr::Literal("{"),
+            Expr::Rule("pair"),
+            Expr::Repeat(&Expr::Seq(&[Expr::Literal(","), Expr::Rule("pair")])),
+            Expr::Literal("}"),
+        ]),
+    ),
+    rule("mirror_kind", Expr::Alt(&[Expr::Literal("roles"), Expr::Literal("suffixes")])),
+    rule("pair", Expr::Seq(&[IDENTIFIER, Expr::Literal("<->"), IDENTIFIER])),
+    rule(
+        "assert_decl",
+        Expr::Seq(&[Expr::Literal("assert"), Expr::Rule("assertion"), STATE_REF, Expr::Literal("->"), STATE_REF]),
+    ),
+    rule("assertion", Expr::Alt(&[Expr::Literal("reachable"), Expr::Literal("no_path")])),
+    rule(
+        "annotation",
+        Expr::Seq(&[
+            Expr::Literal("@"),
+            IDENTIFIER,
+            Expr::Optional(&Expr::Seq(&[
+                Expr::Literal("("),
+                Expr::Optional(&Expr::Seq(&[
+                    Expr::Rule("annotation_arg"),
+                    Expr::Repeat(&Expr::Seq(&[Expr::Literal(","), Expr::Rule("annotation_arg")])),
+                ])),
+                Expr::Literal(")"),
+            ])),
+        ]),
+    ),
+    rule(
+        "annotation_arg",
+        Expr::Seq(&[Expr::Optional(&Expr::Seq(&[IDENTIFIER, Expr::Literal(":")])), Expr::Rule("annotation_value")]),
+    ),
+    rule("annotation_value", Expr::Alt(&[IDENTIFIER, Expr::Rule("NUMBER"), Expr::Rule("STRING")])),
+    Rule {
+        name: "IDENTIFIER",
+        expr: Expr::Lexical("( letter | '_' ) { letter | digit | '_' } { '.' ( letter | '_' ) { letter | digit | '_' } }"),
+        note: Some("any word but a keyword; words joined by dots name declarations of dependencies"),
+    },
+    Rule {
+        name: "NUMBER",
+        expr: Expr::Lexical("digit { digit } { letter }"),
+        note: Some("the letters are a unit or a side, as in @turn(90L)"),
+    },
+    Rule {
+        name: "REPETITION",
+        expr: Expr::Lexical("'x' digit { digit }"),
+        note: Some("on the line of its step, which is performed that many times in a row, from 1 to 1000"),
+    },
+    rule("STRING", Expr::Lexical("'\"' { any character except '\"', '\\' or newline | '\\\"' | '\\\\' } '\"'")),
+];
+
+/// The rule named `name`
+pub fn rule_named(name: &str) -> Option<&'static Rule> {
+    GRAMMAR.iter().find(|rule| rule.name == name)
+}
+
+/// The alternatives of the rule `name`: the text of a literal, the name of
+/// a rule
+pub fn choices(name: &str) -> Vec<&'static str> {
+    match rule_named(name).map(|rule| rule.expr) {
+        Some(Expr::Alt(alternatives)) => alternatives
+            .iter()
+            .filter_map(|alternative| match alternative {
+                Expr::Literal(text) | Expr::Rule(text) => Some(*text),
+                _ => None,
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// The keywords of the declarations, as `state` for `state_decl`
+pub fn declarations() -> Vec<&'static str> {
+    choices("declaration")
+        .into_iter()
+        .map(|name| name.strip_suffix("_decl").unwrap_or(name))
+        .collect()
+}
+
+/// `items` as "a or b", or "a, b, or c"
+pub fn one_of(items: &[&str]) -> String {
+    match items {
+        [] => String::new(),
+        [item] => item.to_string(),
+        [first, second] => format!("{} or {}", first, second),
+        [rest @ .., last] => format!("{}, or {}", rest.join(", "), last),
+    }
+}
+
+/// The rules `rule` refers to, in order of first reference
+pub fn references(rule: &Rule) -> Vec<&'static str> {
+    fn walk(expr: &Expr, names: &mut Vec<&'static str>) {
+        match expr {
+            Expr::Rule(name) => {
+                if !names.contains(name) {
+                    names.push(name);
+                }
+            }
+            Expr::Seq(items) | Expr::Alt(items) => items.iter().for_each(|item| walk(item, names)),
+            Expr::Optional(inner) | Expr::Repeat(inner) | Expr::OneOrMore(inner) | Expr::Version(_, inner) => {
+                walk(inner, names)
+            }
+            Expr::Literal(_) | Expr::Lexical(_) => {}
+        }
+    }
+    let mut names = Vec::new();
+    walk(&rule.expr, &mut names);
+    names
+}
+
+/// The keywords and symbols of the grammar, in order of first use
+pub fn literals() -> Vec<&'static str> {
+    fn walk(expr: &Expr, out: &mut Vec<&'static str>) {
+        match expr {
+            Expr::Literal(text) => {
+                if !out.contains(text) {
+                    out.push(text);
+                }
+            }
+            Expr::Seq(items) | Expr::Alt(items) => items.iter().for_each(|item| walk(item, out)),
+            Expr::Optional(inner) | Expr::Repeat(inner) | Expr::OneOrMore(inner) | Expr::Version(_, inner) => {
+                walk(inner, out)
+            }
+            Expr::Rule(_) | Expr::Lexical(_) => {}
+        }
+    }
+    let mut out = Vec::new();
+    for rule in GRAMMAR {
+        walk(&rule.expr, &mut out);
+    }
+    out
+}
+
+/// The whole grammar as EBNF
+pub fn to_ebnf() -> String {
+    GRAMMAR.iter().map(|rule| rule.to_string()).collect::<Vec<_>>().join("\n")
+}
+
+/// Width of the rule names in EBNF
+const NAME_WIDTH: usize = 16;
+
+impl fmt::Display for Rule {
+    /// The rule as EBNF, alternatives of its right-hand side one per line
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(note) = self.note {
+            writeln!(f, "(* {} *)", note)?;
+        }
+        match self.expr {
+            Expr::Alt(alternatives) => {
+                for (i, alternative) in alternatives.iter().enumerate() {
+                    if i == 0 {
+                        write!(f, "{:<width$} ::= ", self.name, width = NAME_WIDTH)?;
+                    } else {
+                        write!(f, "{:<width$}   | ", "", width = NAME_WIDTH)?;
+                    }
+                    writeln!(f, "{}", alternative)?;
+                }
+                Ok(())
+            }
+            expr => writeln!(f, "{:<width$} ::= {}", self.name, expr, width = NAME_WIDTH),
+        }
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expr::Literal(text) => write!(f, "\"{}\"", text),
+            Expr::Rule(name) => write!(f, "{}", name),
+            Expr::Seq(items) => {
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    match item {
+                        Expr::Alt(_) => write!(f, "( {} )", item)?,
+                        _ => write!(f, "{}", item)?,
+                    }
+                }
+                Ok(())
+            }
+            Expr::Alt(items) => {
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " | ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                Ok(())
+            }
+            Expr::Optional(inner) => write!(f, "[ {} ]", inner),
+            Expr::Repeat(inner @ Expr::Seq(_)) => write!(f, "{{ {} }}", inner),
+            Expr::Repeat(inner) => write!(f, "{}*", inner),
+            Expr::OneOrMore(inner @ (Expr::Seq(_) | Expr::Alt(_))) => write!(f, "( {} )+", inner),
+            Expr::OneOrMore(inner) => write!(f, "{}+", inner),
+            Expr::Version(version, inner) => write!(f, "{}   (* martial {} *)", inner, version),
+            Expr::Lexical(text) => write!(f, "{}", text),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::{keyword, Lexer, Token, KEYWORDS};
+    use crate::parser::Parser;
+
+    #[test]
+    fn test_rules() {
+        // Every rule is defined once, and used
+        for rule in GRAMMAR {
+            assert_eq!(GRAMMAR.iter().filter(|r| r.name == rule.name).count(), 1, "{}", rule.name);
+            for name in references(rule) {
+                assert!(rule_named(name).is_some(), "{} refers to undefined {}", rule.name, name);
+            }
+            assert!(
+                rule.name == "program" || GRAMMAR.iter().any(|r| references(r).contains(&rule.name)),
+                "{} is unused",
+                rule.name
+            );
+        }
+        assert_eq!(declarations()[..3], ["roles", "state", "sequence"]);
+        assert_eq!(one_of(&choices("assertion")), "reachable or no_path");
+        assert_eq!(one_of(&["a", "b", "c"]), "a, b, or c");
+    }
+
+    #[test]
+    fn test_tokens() {
+        // Words of the grammar are the lexer's keywords, besides the ones
+        // only recognised in place
+        let words = literals();
+        for word in &words {
+            let tokens = Lexer::new(word).tokenize().unwrap();
+            assert_eq!(tokens.len(), 2, "{} is not one token", word);
+            if let Token::Identifier(word) = tokens[0].token {
+                assert!(["martial", "reachable", "no_path"].contains(&word), "{} is not a keyword", word);
+            }
+        }
+        for word in KEYWORDS {
+            assert!(keyword(word).is_some() && words.contains(&word), "keyword {} is not in the grammar", word);
+        }
+    }
+
+    /// Derives sentences from the rules, always taking martial 2 syntax
+    struct Generator {
+        seed: u64,
+        names: usize,
+    }
+
+    impl Generator {
+        fn pick(&mut self, n: usize) -> usize {
+            self.seed = self.seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (self.seed >> 33) as usize % n
+        }
+
+        fn expand(&mut self, expr: &Expr, out: &mut Vec<String>) {
+            match expr {
+                Expr::Literal(text) => out.push(text.to_string()),
+                Expr::Rule("IDENTIFIER") => {
+                    self.names += 1;
+                    out.push(format!("N{}", self.names));
+                }
+                Expr::Rule("NUMBER") => out.push("2".to_string()),
+                Expr::Rule("REPETITION") => out.push("x2".to_string()),
+                Expr::Rule("STRING") => out.push("\"a \\\"b\\\"\"".to_string()),
+                Expr::Rule(name) => self.expand(&rule_named(name).unwrap().expr, out),
+                Expr::Seq(items) => items.iter().for_each(|item| self.expand(item, out)),
+                Expr::Alt(items) => {
+                    let items: Vec<&Expr> = items.iter().filter(|item| !matches!(item, Expr::Version(1, _))).collect();
+                    let item = items[self.pick(items.len())];
+                    self.expand(item, out);
+                }
+                Expr::Optional(inner) => {
+                    if self.pick(2) == 0 {
+                        self.expand(inner, out);
+                    }
+                }
+                Expr::Repeat(inner) => (0..self.pick(3)).for_each(|_| self.expand(inner, out)),
+                Expr::OneOrMore(inner) => (0..=self.pick(2)).for_each(|_| self.expand(inner, out)),
+                Expr::Version(_, inner) => self.expand(inner, out),
+                Expr::Lexical(_) => unreachable!(),
+            }
+        }
+    }
+
+    #[test]
+    fn test_sentences_parse() {
+        let mut generator = Generator { seed: 1, names: 0 };
+        for _ in 0..500 {
+            let mut source = String::from("martial 2\n");
+            for _ in 0..4 {
+                let mut tokens = Vec::new();
+                generator.expand(&Expr::Rule("declaration"), &mut tokens);
+                source.push_str(&tokens.join(" "));
+                source.push('\n');
+            }
+            let tokens = Lexer::new(&source).tokenize().unwrap();
+            if let Err(e) = Parser::new(tokens).parse() {
+                panic!("{}\n{}", e, source);
+            }
+        }
+    }
+
+    #[test]
+    fn test_spec() {
+        let spec = include_str!("../spec/spec-1.0.md");
+        assert!(spec.contains(&format!("```\n{}```", to_ebnf())), "spec/spec-1.0.md is not the output of mat grammar");
+    }
+}
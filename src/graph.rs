@@ -3,44 +3,167 @@
 //! Converts a validated martial system into a directed graph structure
 //! for analysis and visualization.
 
+use crate::collections::{HashMap, HashSet};
+use crate::prelude::*;
+use crate::selector::{node_filter, Selector};
 use crate::semantic::MartialSystem;
-use std::collections::{HashMap, HashSet};
+use crate::symbol::Symbol;
+use alloc::collections::{BTreeMap, BTreeSet, VecDeque};
+use schemars::JsonSchema;
 use serde::{Serialize, Deserialize};
 
 /// A node in the martial graph represents a (State, Role) combination
-#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+///
+/// With several participants, `role` lists their roles, e.g. `Attacker, Defender`.
+/// Nodes are compared, hashed and ordered by state and role only; their
+/// attributes are metadata.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct Node {
-    pub state: String,
-    pub role: String,
+    pub state: Symbol,
+    pub role: Symbol,
+    /// The state's annotations, see [`crate::ast::attributes`]
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub attributes: BTreeMap<String, String>,
+    /// Sequences that can be joined here, at a step marked `@entry`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub entries: Vec<Symbol>,
+}
+
+impl PartialEq for Node {
+    fn eq(&self, other: &Self) -> bool {
+        self.state == other.state && self.role == other.role
+    }
+}
+
+impl Eq for Node {}
+
+impl core::hash::Hash for Node {
+    fn hash<H: core::hash::Hasher>(&self, hasher: &mut H) {
+        self.state.hash(hasher);
+        self.role.hash(hasher);
+    }
+}
+
+impl PartialOrd for Node {
+    fn partial_cmp(&self, other: &Self) -> Option<core::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Node {
+    fn cmp(&self, other: &Self) -> core::cmp::Ordering {
+        (&self.state, &self.role).cmp(&(&other.state, &other.role))
+    }
 }
 
 impl Node {
-    pub fn new(state: String, role: String) -> Self {
-        Node { state, role }
+    pub fn new(state: impl Into<Symbol>, role: impl Into<Symbol>) -> Self {
+        Node {
+            state: state.into(),
+            role: role.into(),
+            attributes: BTreeMap::new(),
+            entries: Vec::new(),
+        }
     }
     
+    /// The node with the attributes of its state in `system`
+    pub fn with_attributes(self, system: &MartialSystem) -> Node {
+        let attributes = system.states.get(self.state.as_str()).map(|s| s.attributes()).unwrap_or_default();
+        Node { attributes, ..self }
+    }
+
     pub fn id(&self) -> String {
         format!("{}[{}]", self.state, self.role)
     }
+
+    /// Whether the node matches the selector `endpoint`: a node id such as
+    /// `Mount[Top]`, a state name matching the state in any role, or a glob
+    /// or regular expression of them, see [`Selector`]
+    pub fn matches(&self, endpoint: &str) -> bool {
+        node_filter(endpoint)(self)
+    }
+}
+
+/// Constraints on the paths of [`MartialGraph::find_paths_with_constraints`],
+/// as selectors of positions, see [`Selector`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PathConstraints {
+    /// Positions the path passes through, in order
+    pub via: Vec<String>,
+    /// Positions the path never enters
+    pub avoid: Vec<String>,
 }
 
 /// An edge in the martial graph represents an action/transition
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct Edge {
     pub from: Node,
     pub to: Node,
-    pub action: String,
-    pub sequence: String,
+    pub action: Symbol,
+    pub sequence: Symbol,
+    /// Every sequence declaring the edge, once parallel edges are merged
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sequences: Vec<Symbol>,
+    /// Video URLs of the step, then of its sequence
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub videos: Vec<String>,
+    /// Whether this is the opponent's side of a step; it directly follows
+    /// the edge of the step itself
+    #[serde(default, skip_serializing_if = "core::ops::Not::not")]
+    pub reaction: bool,
+    /// Whether the edge leads to where the action leaves its performer when
+    /// it fails, given with `onfail ->`
+    #[serde(default, skip_serializing_if = "core::ops::Not::not")]
+    pub failure: bool,
+    /// Whether a path may take this edge without the previous step of its
+    /// sequence: the first step, or one marked `@entry`
+    #[serde(default, skip_serializing_if = "core::ops::Not::not")]
+    pub entry: bool,
+    /// Role whose turn the step is, in a sequence declared `alternating`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub turn: Option<Symbol>,
+    /// Annotations of the sequence, then of the step, which wins on a
+    /// shared key; see [`crate::ast::attributes`]
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub attributes: BTreeMap<String, String>,
+}
+
+/// How central a node is to the graph, see [`MartialGraph::centrality`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct Centrality {
+    /// Edges entering the node
+    pub in_degree: usize,
+    /// Edges leaving the node
+    pub out_degree: usize,
+    /// Between 0 and 1
+    pub betweenness: f64,
+    /// Between 0 and 1
+    pub closeness: f64,
+}
+
+/// A sequence leading into another, see [`MartialGraph::flows`]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Flow {
+    pub from: Symbol,
+    pub to: Symbol,
+    /// Where `to` can be joined
+    pub at: Node,
+    /// Whether `from` ends at `at`, rather than passing through it
+    pub ends: bool,
 }
 
+/// Steps [`MartialGraph::longest_chain`] may take before settling for the
+/// longest chain found so far
+pub const LONGEST_CHAIN_BUDGET: usize = 1_000_000;
+
 /// A directed graph representing the martial system
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct MartialGraph {
     pub system_name: String,
     pub nodes: Vec<Node>,
     pub edges: Vec<Edge>,
-    #[serde(skip_serializing_if = "HashMap::is_empty")]
-    pub groups: HashMap<String, Vec<String>>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub groups: BTreeMap<String, Vec<String>>,
 }
 
 impl MartialGraph {
@@ -49,28 +172,67 @@ impl MartialGraph {
         let mut nodes_set = HashSet::new();
         let mut edges = Vec::new();
 
+        let mut entries: BTreeMap<Node, Vec<Symbol>> = BTreeMap::new();
+
         // Extract nodes and edges from all sequences
         for (seq_name, sequence) in &system.sequences {
-            for step in &sequence.steps {
-                let from_node = Node::new(step.from.state.clone(), step.from.role.clone());
-                let to_node = Node::new(step.to.state.clone(), step.to.role.clone());
-
-                nodes_set.insert(from_node.clone());
-                nodes_set.insert(to_node.clone());
-
-                edges.push(Edge {
-                    from: from_node,
-                    to: to_node,
-                    action: step.action_name.clone(),
-                    sequence: seq_name.clone(),
-                });
+            for (i, step) in sequence.steps.iter().enumerate() {
+                let videos: Vec<String> = step
+                    .videos()
+                    .into_iter()
+                    .chain(sequence.videos())
+                    .map(String::from)
+                    .collect();
+                let mut attributes = crate::ast::attributes(&sequence.annotations);
+                attributes.extend(crate::ast::attributes(&step.annotations));
+                let on_fail = step.on_fail.as_ref().map(|to| (&step.from, to, false, true));
+                let reaction = step.reaction.as_ref().map(|r| (&r.from, &r.to, true, false));
+
+                for (from, to, is_reaction, is_failure) in [(&step.from, &step.to, false, false)]
+                    .into_iter()
+                    .chain(on_fail)
+                    .chain(reaction)
+                {
+                    let to_node = Node::new(to.state.clone(), to.role_list());
+                    nodes_set.insert(to_node.clone());
+
+                    // A step starting from a group leaves each of its states
+                    for state in system.states_of(&from.state) {
+                        let from_node = Node::new(state, from.role_list());
+                        nodes_set.insert(from_node.clone());
+                        if step.is_entry() && !is_reaction && !is_failure {
+                            entries.entry(from_node.clone()).or_default().push(seq_name.into());
+                        }
+                        edges.push(Edge {
+                            from: from_node,
+                            to: to_node.clone(),
+                            action: step.action_name.clone(),
+                            sequence: seq_name.into(),
+                            sequences: Vec::new(),
+                            videos: videos.clone(),
+                            reaction: is_reaction,
+                            failure: is_failure,
+                            entry: i == 0 || step.is_entry(),
+                            turn: sequence.turn(i).map(Symbol::from),
+                            attributes: attributes.clone(),
+                        });
+                    }
+                }
             }
         }
 
-        let mut nodes: Vec<Node> = nodes_set.into_iter().collect();
+        // Edge endpoints only identify nodes; the listed nodes carry the
+        // attributes of their state
+        let mut nodes: Vec<Node> = nodes_set
+            .into_iter()
+            .map(|node| {
+                let entries = entries.get(&node).cloned().unwrap_or_default();
+                Node { entries, ..node.with_attributes(system) }
+            })
+            .collect();
         nodes.sort_by(|a, b| {
             let cmp = a.state.cmp(&b.state);
-            if cmp == std::cmp::Ordering::Equal {
+            if cmp == core::cmp::Ordering::Equal {
                 a.role.cmp(&b.role)
             } else {
                 cmp
@@ -85,56 +247,732 @@ impl MartialGraph {
         }
     }
 
-    /// Get all nodes reachable from a given node
-    pub fn reachable_from(&self, start: &Node) -> HashSet<Node> {
+    /// The graph with a node for every declared state in every role it
+    /// allows, so that positions no sequence uses show up as isolated nodes
+    pub fn with_declared_nodes(&self, system: &MartialSystem) -> MartialGraph {
+        let mut nodes: BTreeSet<Node> = self.nodes.iter().cloned().collect();
+        for state in system.states.values() {
+            let roles: Vec<&String> = match &state.allowed_roles {
+                Some(roles) => roles.iter().collect(),
+                None => system.roles.iter().collect(),
+            };
+            nodes.extend(roles.into_iter().map(|role| Node::new(&state.name, role).with_attributes(system)));
+        }
+        MartialGraph {
+            nodes: nodes.into_iter().collect(),
+            ..self.clone()
+        }
+    }
+
+    /// The graph from the point of view of the participant in `role`: only
+    /// the transitions starting in that role, whether their own actions or
+    /// reactions to the opponent's, and the positions they involve
+    pub fn project_role_perspective(&self, role: &str) -> MartialGraph {
+        let edges: Vec<Edge> = self.edges.iter().filter(|e| e.from.role == role).cloned().collect();
+        let nodes = self
+            .nodes
+            .iter()
+            .filter(|n| n.role == role || edges.iter().any(|e| e.to == **n))
+            .cloned()
+            .collect();
+
+        MartialGraph {
+            system_name: self.system_name.clone(),
+            nodes,
+            edges,
+            groups: self.groups.clone(),
+        }
+    }
+
+    /// The part of the graph within `depth` transitions of the nodes matching
+    /// `start`, or everything reachable from them if `depth` is `None`
+    pub fn within(&self, start: &str, depth: Option<usize>) -> MartialGraph {
+        let outgoing = self.outgoing();
+        let mut distances: HashMap<&Node, usize> = HashMap::new();
+        let mut queue: VecDeque<&Node> = VecDeque::new();
+        let start = node_filter(start);
+        for node in self.nodes.iter().filter(|n| start(n)) {
+            distances.insert(node, 0);
+            queue.push_back(node);
+        }
+        while let Some(node) = queue.pop_front() {
+            let distance = distances[node];
+            if depth.is_some_and(|depth| distance >= depth) {
+                continue;
+            }
+            for edge in outgoing.get(node).into_iter().flatten() {
+                if !distances.contains_key(&edge.to) {
+                    distances.insert(&edge.to, distance + 1);
+                    queue.push_back(&edge.to);
+                }
+            }
+        }
+
+        // An edge is kept if it is taken within the first `depth` transitions
+        let edges = self
+            .edges
+            .iter()
+            .filter(|e| distances.get(&e.from).is_some_and(|&d| depth.is_none_or(|depth| d < depth)))
+            .cloned()
+            .collect();
+        let nodes: Vec<Node> = self.nodes.iter().filter(|n| distances.contains_key(n)).cloned().collect();
+        self.subgraph(nodes, edges)
+    }
+
+    /// The edges of the sequences matching the selector `name`, merged or
+    /// not, and the nodes they join
+    pub fn sequence(&self, name: &str) -> MartialGraph {
+        let Ok(selector) = Selector::parse(name) else {
+            return self.subgraph(Vec::new(), Vec::new());
+        };
+        let edges: Vec<Edge> = self
+            .edges
+            .iter()
+            .filter(|e| selector.matches_name(&e.sequence) || e.sequences.iter().any(|s| selector.matches_name(s)))
+            .cloned()
+            .collect();
+        let nodes = self
+            .nodes
+            .iter()
+            .filter(|n| edges.iter().any(|e| e.from == **n || e.to == **n))
+            .cloned()
+            .collect();
+        self.subgraph(nodes, edges)
+    }
+
+    /// A graph of `nodes` and `edges`, with the groups trimmed to the states
+    /// left and those without any dropped
+    fn subgraph(&self, nodes: Vec<Node>, edges: Vec<Edge>) -> MartialGraph {
+        let groups = self
+            .groups
+            .iter()
+            .map(|(name, states)| {
+                let kept = states.iter().filter(|s| nodes.iter().any(|n| n.state == **s)).cloned().collect();
+                (name.clone(), kept)
+            })
+            .filter(|(_, states): &(String, Vec<String>)| !states.is_empty())
+            .collect();
+        MartialGraph {
+            system_name: self.system_name.clone(),
+            nodes,
+            edges,
+            groups,
+        }
+    }
+
+    /// Merge parallel edges, which several sequences declare with the same
+    /// action between the same nodes, into one edge listing every sequence
+    ///
+    /// The merged edge keeps the first sequence name in alphabetical order as
+    /// `sequence`, and the videos of all of them. Attributes come from the
+    /// first sequence declaring each key.
+    pub fn merge_parallel_edges(&self) -> MartialGraph {
+        let mut edges: Vec<Edge> = Vec::new();
+        for edge in &self.edges {
+            let parallel = edges
+                .iter_mut()
+                .find(|e| e.from == edge.from && e.to == edge.to && e.action == edge.action && e.reaction == edge.reaction && e.failure == edge.failure);
+            match parallel {
+                Some(merged) => {
+                    merged.sequences.push(edge.sequence.clone());
+                    merged.entry |= edge.entry;
+                    for video in &edge.videos {
+                        if !merged.videos.contains(video) {
+                            merged.videos.push(video.clone());
+                        }
+                    }
+                    for (key, value) in &edge.attributes {
+                        merged.attributes.entry(key.clone()).or_insert_with(|| value.clone());
+                    }
+                }
+                None => edges.push(Edge {
+                    sequences: vec![edge.sequence.clone()],
+                    ..edge.clone()
+                }),
+            }
+        }
+        for edge in &mut edges {
+            edge.sequences.sort();
+            edge.sequences.dedup();
+            edge.sequence = edge.sequences[0].clone();
+        }
+
+        MartialGraph {
+            edges,
+            ..self.clone()
+        }
+    }
+
+    /// Outgoing edges of every node, in edge order
+    fn outgoing(&self) -> HashMap<&Node, Vec<&Edge>> {
+        let mut outgoing: HashMap<&Node, Vec<&Edge>> = HashMap::new();
+        for edge in &self.edges {
+            outgoing.entry(&edge.from).or_default().push(edge);
+        }
+        outgoing
+    }
+
+    /// Nodes reachable from any of `starts`, including themselves
+    fn reachable_from_all<'a>(&'a self, starts: impl IntoIterator<Item = &'a Node>) -> HashSet<&'a Node> {
+        let outgoing = self.outgoing();
         let mut reachable = HashSet::new();
-        let mut to_visit = vec![start.clone()];
-        
+        let mut to_visit: Vec<&Node> = starts.into_iter().collect();
+
         while let Some(current) = to_visit.pop() {
-            if !reachable.insert(current.clone()) {
+            if !reachable.insert(current) {
                 continue; // Already visited
             }
-            
-            // Find all edges from current node
-            for edge in &self.edges {
-                if edge.from == current && !reachable.contains(&edge.to) {
-                    to_visit.push(edge.to.clone());
+            for edge in outgoing.get(current).into_iter().flatten() {
+                if !reachable.contains(&edge.to) {
+                    to_visit.push(&edge.to);
                 }
             }
         }
-        
+
         reachable
     }
 
+    /// Get all nodes reachable from a given node
+    pub fn reachable_from(&self, start: &Node) -> HashSet<Node> {
+        self.reachable_from_many(core::slice::from_ref(start))
+    }
+
+    /// Nodes reachable from any of `starts`, including themselves
+    pub fn reachable_from_many(&self, starts: &[Node]) -> HashSet<Node> {
+        self.reachable_from_all(starts).into_iter().cloned().collect()
+    }
+
+    /// Sequences that can be joined, at their first step or an `@entry`
+    /// step, from the nodes matching `from`, sorted by name
+    pub fn reachable_sequences(&self, from: &str) -> Vec<&Symbol> {
+        let reachable = self.reachable_from_all(self.nodes.iter().filter(|n| n.matches(from)));
+        let mut sequences: Vec<&Symbol> = self
+            .edges
+            .iter()
+            .filter(|edge| edge.entry && reachable.contains(&edge.from))
+            .map(|edge| &edge.sequence)
+            .collect();
+        sequences.sort();
+        sequences.dedup();
+        sequences
+    }
+
+    /// Nodes that none of `starts` leads to, in graph order
+    pub fn unreachable_from(&self, starts: &[Node]) -> Vec<Node> {
+        let reachable = self.reachable_from_all(starts);
+        self.nodes.iter().filter(|node| !reachable.contains(node)).cloned().collect()
+    }
+
     /// Find all unreachable nodes (nodes with no incoming edges and not starting points)
     pub fn find_unreachable_nodes(&self) -> Vec<Node> {
-        if self.nodes.is_empty() {
+        // Every node with an outgoing edge is a starting point
+        let reachable = self.reachable_from_all(self.edges.iter().map(|e| &e.from));
+
+        // Find nodes not in reachable set
+        self.nodes
+            .iter()
+            .filter(|node| !reachable.contains(node))
+            .cloned()
+            .collect()
+    }
+
+    /// Initial positions: nodes with outgoing edges and no incoming edge from
+    /// another node
+    pub fn initial_nodes(&self) -> Vec<&Node> {
+        let leaving: HashSet<&Node> = self.edges.iter().map(|e| &e.from).collect();
+        let entered: HashSet<&Node> = self.edges.iter().filter(|e| e.from != e.to).map(|e| &e.to).collect();
+        self.nodes
+            .iter()
+            .filter(|n| leaving.contains(n) && !entered.contains(n))
+            .collect()
+    }
+
+    /// Fewest actions needed to reach each node from an initial position,
+    /// sorted by depth then id; nodes not reached are left out
+    pub fn depths(&self) -> Vec<(&Node, usize)> {
+        let mut depths: Vec<(&Node, usize)> = self.distances_from(self.initial_nodes()).into_iter().collect();
+        depths.sort_by_key(|(node, depth)| (*depth, node.id()));
+        depths
+    }
+
+    /// Fewest actions needed to reach each node from the nearest of
+    /// `starts`, which are at distance 0; nodes not reached are left out
+    pub fn distances_from<'a>(&'a self, starts: impl IntoIterator<Item = &'a Node>) -> HashMap<&'a Node, usize> {
+        let mut queue: VecDeque<&Node> = starts.into_iter().collect();
+        let mut distances: HashMap<&Node, usize> = queue.iter().map(|n| (*n, 0)).collect();
+        let outgoing = self.outgoing();
+        while let Some(node) = queue.pop_front() {
+            let distance = distances[node];
+            for edge in outgoing.get(node).into_iter().flatten() {
+                if !distances.contains_key(&edge.to) {
+                    distances.insert(&edge.to, distance + 1);
+                    queue.push_back(&edge.to);
+                }
+            }
+        }
+        distances
+    }
+
+    /// Fewest actions leading from `from` to `to`
+    ///
+    /// Endpoints are given as for [`Node::matches`]. Returns an empty path
+    /// when `from` already matches `to`, and `None` when `to` cannot be
+    /// reached.
+    pub fn shortest_path(&self, from: &str, to: &str) -> Option<Vec<&Edge>> {
+        let (from, to) = (node_filter(from), node_filter(to));
+        let starts: Vec<&Node> = self.nodes.iter().filter(|n| from(n)).collect();
+        if starts.iter().any(|n| to(n)) {
+            return Some(Vec::new());
+        }
+
+        let outgoing = self.outgoing();
+        let mut reached_by: HashMap<&Node, Option<&Edge>> = starts.iter().map(|n| (*n, None)).collect();
+        let mut queue: VecDeque<&Node> = starts.into_iter().collect();
+        while let Some(node) = queue.pop_front() {
+            for &edge in outgoing.get(node).into_iter().flatten() {
+                if reached_by.contains_key(&edge.to) {
+                    continue;
+                }
+                reached_by.insert(&edge.to, Some(edge));
+                if to(&edge.to) {
+                    let mut path = vec![edge];
+                    while let Some(Some(previous)) = reached_by.get(&path[path.len() - 1].from) {
+                        path.push(previous);
+                    }
+                    path.reverse();
+                    return Some(path);
+                }
+                queue.push_back(&edge.to);
+            }
+        }
+        None
+    }
+
+    /// Up to `k` paths from `from` to `to` that never revisit a node, fewest
+    /// actions first, by Yen's algorithm
+    ///
+    /// Endpoints are given as for [`Node::matches`]. Paths differ in at
+    /// least one edge, so parallel edges make distinct paths unless merged
+    /// first, see [`merge_parallel_edges`](Self::merge_parallel_edges).
+    /// Paths of the same length keep the order they were found in.
+    pub fn k_shortest_paths(&self, from: &str, to: &str, k: usize) -> Vec<Vec<&Edge>> {
+        let from = node_filter(from);
+        let starts: Vec<&Node> = self.nodes.iter().filter(|n| from(n)).collect();
+        self.yen(&starts, node_filter(to), |_| true, k)
+            .into_iter()
+            .map(|path| path.into_iter().map(|i| &self.edges[i]).collect())
+            .collect()
+    }
+
+    /// Up to `k` paths from `from` to `to`, fewest actions first, passing
+    /// through a position matching each pattern of `constraints.via` in
+    /// order and entering none matching a pattern of `constraints.avoid`
+    ///
+    /// Endpoints and patterns are given as for [`Node::matches`]. Each stretch between two waypoints never
+    /// revisits a node, but a path may come back to a node of an earlier
+    /// stretch. Without waypoints, this is
+    /// [`k_shortest_paths`](Self::k_shortest_paths) with the avoided
+    /// positions left out.
+    pub fn find_paths_with_constraints(&self, from: &str, to: &str, constraints: &PathConstraints, k: usize) -> Vec<Vec<&Edge>> {
+        let avoided: Vec<_> = constraints.avoid.iter().map(String::as_str).map(node_filter).collect();
+        let allowed = |node: &Node| !avoided.iter().any(|avoided| avoided(node));
+        let from = node_filter(from);
+        let starts: Vec<&Node> = self.nodes.iter().filter(|n| from(n)).collect();
+        // Waypoints, then the destination
+        let stops: Vec<_> = constraints.via.iter().map(String::as_str).chain([to]).map(node_filter).collect();
+
+        // Partial paths with the node they end at; keeping the `k` shortest
+        // ending at each node is enough, as any longer one would only lead
+        // to paths longer than `k` others
+        let mut partials: Vec<(Vec<usize>, Option<&Node>)> = vec![(Vec::new(), None)];
+        for stop in &stops {
+            let mut extended: Vec<(Vec<usize>, Option<&Node>)> = Vec::new();
+            for (path, end) in &partials {
+                let from_here: Vec<&Node> = match end {
+                    Some(node) => vec![*node],
+                    None => starts.clone(),
+                };
+                for tail in self.yen(&from_here, stop, allowed, k) {
+                    let end = tail.last().map(|&i| &self.edges[i].to).or(*end);
+                    let end = end.or_else(|| starts.iter().copied().find(|node| stop(node)));
+                    extended.push((path.iter().copied().chain(tail).collect(), end));
+                }
+            }
+            extended.sort_by_key(|(path, _)| path.len());
+            let mut per_end: HashMap<Option<&Node>, usize> = HashMap::new();
+            extended.retain(|(_, end)| {
+                let kept = per_end.entry(*end).or_default();
+                *kept += 1;
+                *kept <= k
+            });
+            partials = extended;
+        }
+        let mut paths: Vec<Vec<usize>> = Vec::new();
+        for (path, _) in partials {
+            if !paths.contains(&path) {
+                paths.push(path);
+            }
+        }
+        paths
+            .into_iter()
+            .take(k)
+            .map(|path| path.into_iter().map(|i| &self.edges[i]).collect())
+            .collect()
+    }
+
+    /// Up to `k` paths, as edge indices, from any of `starts` to a node that
+    /// `is_target`, entering only nodes that are `allowed` and never
+    /// revisiting one, fewest edges first, by Yen's algorithm
+    fn yen(&self, starts: &[&Node], is_target: impl Fn(&Node) -> bool, allowed: impl Fn(&Node) -> bool, k: usize) -> Vec<Vec<usize>> {
+        if k == 0 || starts.is_empty() {
             return Vec::new();
         }
+        if starts.iter().any(|n| is_target(n)) {
+            return vec![Vec::new()];
+        }
+        let mut outgoing: HashMap<&Node, Vec<usize>> = HashMap::new();
+        for (i, edge) in self.edges.iter().enumerate() {
+            outgoing.entry(&edge.from).or_default().push(i);
+        }
+        // Fewest edges from any of `starts` to a target, avoiding the banned
+        // edges and nodes
+        let search = |starts: &[&Node], banned_edges: &HashSet<usize>, banned_nodes: &HashSet<&Node>| {
+            let mut reached_by: HashMap<&Node, Option<usize>> = starts.iter().map(|n| (*n, None)).collect();
+            let mut queue: VecDeque<&Node> = starts.iter().copied().collect();
+            while let Some(node) = queue.pop_front() {
+                for &i in outgoing.get(node).into_iter().flatten() {
+                    let next = &self.edges[i].to;
+                    if banned_edges.contains(&i) || banned_nodes.contains(next) || reached_by.contains_key(next) || !allowed(next) {
+                        continue;
+                    }
+                    reached_by.insert(next, Some(i));
+                    if is_target(next) {
+                        let mut path = vec![i];
+                        while let Some(Some(previous)) = reached_by.get(&self.edges[path[path.len() - 1]].from) {
+                            path.push(*previous);
+                        }
+                        path.reverse();
+                        return Some(path);
+                    }
+                    queue.push_back(next);
+                }
+            }
+            None
+        };
 
-        // Nodes that have incoming edges or are sources
-        let mut reachable = HashSet::new();
-        
-        // Add all source nodes (nodes with outgoing but possibly no incoming edges)
+        let Some(first) = search(starts, &HashSet::new(), &HashSet::new()) else {
+            return Vec::new();
+        };
+        let mut paths: Vec<Vec<usize>> = vec![first];
+        let mut candidates: Vec<Vec<usize>> = Vec::new();
+        while paths.len() < k {
+            let last = &paths[paths.len() - 1];
+            for spur in 0..last.len() {
+                let root = &last[..spur];
+                // Leave the path at `spur` by another edge than the paths
+                // sharing its root, without going back through the root
+                let banned_edges: HashSet<usize> = paths
+                    .iter()
+                    .filter(|path| path.len() > spur && path[..spur] == *root)
+                    .map(|path| path[spur])
+                    .collect();
+                let banned_nodes: HashSet<&Node> = root.iter().map(|&i| &self.edges[i].from).collect();
+                let spur_starts = match root.last() {
+                    Some(&i) => vec![&self.edges[i].to],
+                    None => starts.to_vec(),
+                };
+                if let Some(tail) = search(&spur_starts, &banned_edges, &banned_nodes) {
+                    let candidate: Vec<usize> = root.iter().copied().chain(tail).collect();
+                    if !paths.contains(&candidate) && !candidates.contains(&candidate) {
+                        candidates.push(candidate);
+                    }
+                }
+            }
+            let Some(next) = candidates.iter().enumerate().min_by_key(|(_, path)| path.len()).map(|(i, _)| i) else {
+                break;
+            };
+            paths.push(candidates.remove(next));
+        }
+        paths
+    }
+
+    /// Like [`shortest_path`](Self::shortest_path), entering sequences only
+    /// at their [`entry`](Edge::entry) edges: every other edge must follow
+    /// an edge of the same sequence
+    pub fn canonical_path(&self, from: &str, to: &str) -> Option<Vec<&Edge>> {
+        let (from, to) = (node_filter(from), node_filter(to));
+        if self.nodes.iter().any(|n| from(n) && to(n)) {
+            return Some(Vec::new());
+        }
+
+        // Searches edges rather than nodes, since which edges may follow
+        // depends on the edge taken; edges are tracked by index
+        let mut outgoing: HashMap<&Node, Vec<usize>> = HashMap::new();
+        for (i, edge) in self.edges.iter().enumerate() {
+            outgoing.entry(&edge.from).or_default().push(i);
+        }
+        let mut reached_by: HashMap<usize, Option<usize>> = HashMap::new();
+        let mut queue: VecDeque<usize> = VecDeque::new();
+        for node in self.nodes.iter().filter(|n| from(n)) {
+            for &i in outgoing.get(node).into_iter().flatten() {
+                if self.edges[i].entry && !reached_by.contains_key(&i) {
+                    reached_by.insert(i, None);
+                    queue.push_back(i);
+                }
+            }
+        }
+        while let Some(current) = queue.pop_front() {
+            let edge = &self.edges[current];
+            if to(&edge.to) {
+                let mut path = vec![current];
+                while let Some(Some(previous)) = reached_by.get(&path[path.len() - 1]) {
+                    path.push(*previous);
+                }
+                return Some(path.into_iter().rev().map(|i| &self.edges[i]).collect());
+            }
+            for &next in outgoing.get(&edge.to).into_iter().flatten() {
+                let follows = self.edges[next].entry || self.edges[next].sequence == edge.sequence;
+                if follows && !reached_by.contains_key(&next) {
+                    reached_by.insert(next, Some(current));
+                    queue.push_back(next);
+                }
+            }
+        }
+        None
+    }
+
+    /// The longest chain of actions that never revisits a node
+    ///
+    /// Searches every simple path, which is exponential in the worst case but
+    /// fast for systems of the size people write by hand. On larger graphs
+    /// the search stops after [`LONGEST_CHAIN_BUDGET`] steps and returns the
+    /// longest chain found so far. Ties go to the chain found first in node
+    /// and edge order.
+    pub fn longest_chain(&self) -> Vec<&Edge> {
+        struct Search<'a> {
+            outgoing: HashMap<&'a Node, Vec<&'a Edge>>,
+            best: Vec<&'a Edge>,
+            budget: usize,
+        }
+
+        fn extend<'a>(search: &mut Search<'a>, path: &mut Vec<&'a Edge>, visited: &mut HashSet<&'a Node>) {
+            if path.len() > search.best.len() {
+                search.best = path.clone();
+            }
+            let current = path.last().map(|e| &e.to).expect("paths start with an edge");
+            let edges = search.outgoing.get(current).cloned().unwrap_or_default();
+            for edge in edges {
+                if search.budget == 0 {
+                    return;
+                }
+                if visited.insert(&edge.to) {
+                    search.budget -= 1;
+                    path.push(edge);
+                    extend(search, path, visited);
+                    path.pop();
+                    visited.remove(&edge.to);
+                }
+            }
+        }
+
+        let mut search = Search {
+            outgoing: self.outgoing(),
+            best: Vec::new(),
+            budget: LONGEST_CHAIN_BUDGET,
+        };
+        for edge in self.edges.iter().filter(|e| e.from != e.to) {
+            let mut visited: HashSet<&Node> = HashSet::from([&edge.from, &edge.to]);
+            extend(&mut search, &mut vec![edge], &mut visited);
+        }
+        let mut best = search.best;
+        if best.is_empty() {
+            // Only self-loops
+            best.extend(self.edges.first());
+        }
+        best
+    }
+
+    /// Find states with identical incoming and outgoing transitions, which
+    /// are likely the same position declared under different names
+    ///
+    /// A state's signature is the set of its edges, with the state itself
+    /// replaced by a placeholder so that self-loops compare equal. States
+    /// without edges are not reported. Each class is sorted, and classes are
+    /// sorted by their first state.
+    pub fn equivalent_states(&self) -> Vec<Vec<String>> {
+        type Signature = BTreeSet<(bool, Symbol, Symbol, Symbol, Symbol)>;
+        let mut signatures: BTreeMap<&str, Signature> = BTreeMap::new();
+
+        let other = |node: &Node, state: &str| if node.state == state { Symbol::default() } else { node.state.clone() };
         for edge in &self.edges {
-            reachable.insert(edge.from.clone());
+            let from = edge.from.state.as_str();
+            let to = edge.to.state.as_str();
+            signatures.entry(from).or_default().insert((
+                true,
+                edge.action.clone(),
+                edge.from.role.clone(),
+                other(&edge.to, from),
+                edge.to.role.clone(),
+            ));
+            signatures.entry(to).or_default().insert((
+                false,
+                edge.action.clone(),
+                edge.to.role.clone(),
+                other(&edge.from, to),
+                edge.from.role.clone(),
+            ));
+        }
+
+        let mut classes: BTreeMap<&Signature, Vec<String>> = BTreeMap::new();
+        for (state, signature) in &signatures {
+            classes.entry(signature).or_default().push(state.to_string());
         }
-        
-        // For each source, find all reachable nodes
-        let sources: Vec<Node> = reachable.iter().cloned().collect();
-        for source in sources {
-            let reached = self.reachable_from(&source);
-            for node in reached {
-                reachable.insert(node);
+        let mut classes: Vec<Vec<String>> = classes.into_values().filter(|c| c.len() > 1).collect();
+        classes.sort();
+        classes
+    }
+
+    /// How sequences lead into each other: a flow from A to B for every
+    /// sequence B that can be joined, at its first step or an `@entry` step,
+    /// at a node one of A's actions leads to; sorted by A, then B
+    ///
+    /// Only the performer's side of the steps counts, not reactions or
+    /// `onfail` outcomes. The flow is at the node A ends at if B can be
+    /// joined there, else at the first such node A passes through.
+    pub fn flows(&self) -> Vec<Flow> {
+        let mut reached: BTreeMap<Symbol, Vec<&Node>> = BTreeMap::new();
+        let mut entries: BTreeMap<&Node, BTreeSet<Symbol>> = BTreeMap::new();
+        for edge in self.edges.iter().filter(|e| !e.reaction && !e.failure) {
+            for sequence in core::iter::once(&edge.sequence).chain(&edge.sequences) {
+                let nodes = reached.entry(sequence.clone()).or_default();
+                nodes.retain(|node| *node != &edge.to);
+                nodes.push(&edge.to);
+                if edge.entry {
+                    entries.entry(&edge.from).or_default().insert(sequence.clone());
+                }
             }
         }
 
-        // Find nodes not in reachable set
-        self.nodes
-            .iter()
-            .filter(|node| !reachable.contains(node))
-            .cloned()
-            .collect()
+        let mut flows = Vec::new();
+        for (from, nodes) in &reached {
+            let end = nodes.last().copied();
+            let mut joined: BTreeMap<&Symbol, &Node> = BTreeMap::new();
+            // The end first, then the nodes passed through in order
+            for node in end.into_iter().chain(nodes.iter().copied()) {
+                for to in entries.get(node).into_iter().flatten().filter(|to| *to != from) {
+                    joined.entry(to).or_insert(node);
+                }
+            }
+            for (to, at) in joined {
+                flows.push(Flow {
+                    from: from.clone(),
+                    to: to.clone(),
+                    at: at.clone(),
+                    ends: Some(at) == end,
+                });
+            }
+        }
+        flows
+    }
+
+    /// Export [`MartialGraph::flows`] as DOT, a box per sequence and an
+    /// arrow per flow, dashed when the first sequence only passes through
+    pub fn flows_to_dot(&self) -> String {
+        let mut dot = format!("digraph \"{} flows\" {{\n", self.system_name);
+        dot.push_str("  rankdir=LR;\n");
+        dot.push_str("  node [shape=box, style=rounded];\n\n");
+        let mut sequences: Vec<&Symbol> =
+            self.edges.iter().flat_map(|e| core::iter::once(&e.sequence).chain(&e.sequences)).collect();
+        sequences.sort();
+        sequences.dedup();
+        for sequence in sequences {
+            dot.push_str(&format!("  \"{}\";\n", sequence));
+        }
+        dot.push('\n');
+        for flow in self.flows() {
+            let style = if flow.ends { "" } else { ", style=dashed" };
+            dot.push_str(&format!("  \"{}\" -> \"{}\" [label=\"{}\"{}];\n", flow.from, flow.to, flow.at.id(), style));
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// Centrality of every node, in node order
+    ///
+    /// Betweenness and closeness follow shortest paths, ignoring self-loops
+    /// and counting parallel edges once. Betweenness is the share of the
+    /// shortest paths between two other nodes that go through the node,
+    /// summed over all pairs and divided by the number of pairs. Closeness
+    /// is harmonic: the mean of `1 / distance` to every other node, 0 for
+    /// nodes out of reach, so it is defined on disconnected graphs too.
+    pub fn centrality(&self) -> Vec<(&Node, Centrality)> {
+        let index: HashMap<&Node, usize> = self.nodes.iter().enumerate().map(|(i, node)| (node, i)).collect();
+        let n = self.nodes.len();
+        let mut centrality = vec![Centrality::default(); n];
+        let mut successors: Vec<BTreeSet<usize>> = vec![BTreeSet::new(); n];
+        for edge in &self.edges {
+            let (Some(&from), Some(&to)) = (index.get(&edge.from), index.get(&edge.to)) else {
+                continue;
+            };
+            centrality[from].out_degree += 1;
+            centrality[to].in_degree += 1;
+            if from != to {
+                successors[from].insert(to);
+            }
+        }
+
+        // Brandes' algorithm: a breadth-first search from every node,
+        // then dependencies accumulated from the farthest nodes back
+        let mut betweenness = vec![0.0; n];
+        for source in 0..n {
+            let mut distance: Vec<Option<usize>> = vec![None; n];
+            let mut paths = vec![0.0_f64; n];
+            let mut predecessors: Vec<Vec<usize>> = vec![Vec::new(); n];
+            let mut order = Vec::new();
+            let mut queue = VecDeque::from([source]);
+            distance[source] = Some(0);
+            paths[source] = 1.0;
+            while let Some(node) = queue.pop_front() {
+                order.push(node);
+                let next = distance[node].map(|d| d + 1);
+                for &to in &successors[node] {
+                    if distance[to].is_none() {
+                        distance[to] = next;
+                        queue.push_back(to);
+                    }
+                    if distance[to] == next {
+                        paths[to] += paths[node];
+                        predecessors[to].push(node);
+                    }
+                }
+            }
+
+            let mut dependency = vec![0.0; n];
+            for &node in order.iter().rev() {
+                for &before in &predecessors[node] {
+                    dependency[before] += paths[before] / paths[node] * (1.0 + dependency[node]);
+                }
+                if node != source {
+                    betweenness[node] += dependency[node];
+                }
+            }
+            let reached: f64 = distance
+                .iter()
+                .enumerate()
+                .filter(|(node, _)| *node != source)
+                .filter_map(|(_, d)| d.map(|d| 1.0 / d as f64))
+                .fold(0.0, |sum, inverse| sum + inverse);
+            if n > 1 {
+                centrality[source].closeness = reached / (n - 1) as f64;
+            }
+        }
+        let pairs = n.saturating_sub(1) * n.saturating_sub(2);
+        for (node, value) in betweenness.into_iter().enumerate() {
+            if pairs > 0 {
+                centrality[node].betweenness = value / pairs as f64;
+            }
+        }
+        self.nodes.iter().zip(centrality).collect()
     }
 
     /// Export as JSON
@@ -142,8 +980,22 @@ impl MartialGraph {
         serde_json::to_string_pretty(self)
     }
 
-    /// Export as DOT format for Graphviz
+    /// Export as DOT format for Graphviz; the opponent's side of a step is
+    /// dashed, merged parallel edges are drawn thicker, and their sequences
+    /// and the attributes of edges and nodes make up tooltips
     pub fn to_dot(&self) -> String {
+        self.to_dot_with(dot_edge_attributes)
+    }
+
+    /// Export as DOT, with the attribute list of each edge given by `edge_attributes`
+    pub fn to_dot_with(&self, edge_attributes: impl Fn(&Edge) -> String) -> String {
+        self.to_dot_styled(|_| String::new(), edge_attributes)
+    }
+
+    /// Export as DOT, adding `node_attributes` to those of each node and
+    /// with the attribute list of each edge given by `edge_attributes`
+    pub fn to_dot_styled(&self, node_attributes: impl Fn(&Node) -> String, edge_attributes: impl Fn(&Edge) -> String) -> String {
+        let dot_node = |node: &Node| dot_node(node, &node_attributes(node));
         let mut dot = String::new();
         dot.push_str(&format!("digraph \"{}\" {{\n", self.system_name));
         dot.push_str("  rankdir=LR;\n");
@@ -153,8 +1005,7 @@ impl MartialGraph {
         let mut grouped_nodes: HashSet<String> = HashSet::new();
 
         // Add group subgraphs
-        let mut sorted_groups: Vec<_> = self.groups.iter().collect();
-        sorted_groups.sort_by_key(|(name, _)| (*name).clone());
+        let sorted_groups: Vec<_> = self.groups.iter().collect();
 
         for (group_name, group_states) in &sorted_groups {
             dot.push_str(&format!("  subgraph cluster_{} {{\n", group_name));
@@ -163,13 +1014,8 @@ impl MartialGraph {
             dot.push_str("    color=grey;\n");
 
             for node in &self.nodes {
-                if group_states.contains(&node.state) {
-                    dot.push_str(&format!(
-                        "    \"{}\" [label=\"{}\\n[{}]\"];\n",
-                        node.id(),
-                        node.state,
-                        node.role
-                    ));
+                if group_states.iter().any(|s| *s == node.state) {
+                    dot.push_str(&format!("    {};\n", dot_node(node)));
                     grouped_nodes.insert(node.id());
                 }
             }
@@ -180,24 +1026,19 @@ impl MartialGraph {
         // Add ungrouped nodes
         for node in &self.nodes {
             if !grouped_nodes.contains(&node.id()) {
-                dot.push_str(&format!(
-                    "  \"{}\" [label=\"{}\\n[{}]\"];\n",
-                    node.id(),
-                    node.state,
-                    node.role
-                ));
+                dot.push_str(&format!("  {};\n", dot_node(node)));
             }
         }
 
-        dot.push_str("\n");
+        dot.push('\n');
 
         // Add edges
         for edge in &self.edges {
             dot.push_str(&format!(
-                "  \"{}\" -> \"{}\" [label=\"{}\"];\n",
+                "  \"{}\" -> \"{}\" [{}];\n",
                 edge.from.id(),
                 edge.to.id(),
-                edge.action
+                edge_attributes(edge)
             ));
         }
 
@@ -205,6 +1046,50 @@ impl MartialGraph {
         dot
     }
 
+    /// Export as a Mermaid flowchart; like the DOT export, the opponent's
+    /// side of a step is dotted and groups become subgraphs
+    ///
+    /// A node appears in the first group containing its state only, as
+    /// Mermaid cannot draw overlapping subgraphs.
+    pub fn to_mermaid(&self) -> String {
+        let index: HashMap<&Node, usize> = self.nodes.iter().enumerate().map(|(i, node)| (node, i)).collect();
+        let declare = |i: usize, node: &Node| format!("n{}[\"{}<br/>[{}]\"]", i, node.state, node.role);
+
+        let mut mermaid = String::from("flowchart LR\n");
+        let sorted_groups: Vec<_> = self.groups.iter().collect();
+
+        let mut grouped = vec![false; self.nodes.len()];
+        for (group_name, group_states) in sorted_groups {
+            let members: Vec<usize> = (0..self.nodes.len())
+                .filter(|&i| !grouped[i] && group_states.iter().any(|s| *s == self.nodes[i].state))
+                .collect();
+            if members.is_empty() {
+                continue;
+            }
+            mermaid.push_str(&format!("  subgraph {}\n", group_name));
+            for i in members {
+                grouped[i] = true;
+                mermaid.push_str(&format!("    {}\n", declare(i, &self.nodes[i])));
+            }
+            mermaid.push_str("  end\n");
+        }
+        for (i, node) in self.nodes.iter().enumerate() {
+            if !grouped[i] {
+                mermaid.push_str(&format!("  {}\n", declare(i, node)));
+            }
+        }
+
+        for edge in &self.edges {
+            let arrow = if edge.reaction { "-.->" } else { "-->" };
+            let failed = if edge.failure { " (failed)" } else { "" };
+            mermaid.push_str(&format!(
+                "  n{} {}|{}{}| n{}\n",
+                index[&edge.from], arrow, edge.action, failed, index[&edge.to]
+            ));
+        }
+        mermaid
+    }
+
     /// Get statistics about the graph
     pub fn statistics(&self) -> GraphStatistics {
         let mut in_degree: HashMap<&Node, usize> = HashMap::new();
@@ -246,6 +1131,59 @@ impl MartialGraph {
     }
 }
 
+/// The attribute list of `edge` in [`MartialGraph::to_dot`]
+pub(crate) fn dot_edge_attributes(edge: &Edge) -> String {
+    let mut attributes = if edge.failure {
+        format!("label=\"{} (failed)\", color=red", edge.action)
+    } else {
+        format!("label=\"{}\"", edge.action)
+    };
+    let merged = (edge.sequences.len() > 1).then(|| edge.sequences.join(", "));
+    if let Some(tooltip) = tooltip(merged.as_deref(), &edge.attributes) {
+        attributes.push_str(&format!(", tooltip=\"{}\"", tooltip));
+    }
+    if merged.is_some() {
+        attributes.push_str(&format!(", penwidth={}", edge.sequences.len()));
+    }
+    if edge.reaction {
+        attributes.push_str(", style=dashed");
+    }
+    attributes
+}
+
+/// A DOT node statement, with the node's attributes as a tooltip unless
+/// `extra` sets one
+fn dot_node(node: &Node, extra: &str) -> String {
+    let mut statement = format!("\"{}\" [label=\"{}\\n[{}]\"", node.id(), node.state, node.role);
+    if let Some(tooltip) = tooltip(None, &node.attributes).filter(|_| !extra.contains("tooltip=")) {
+        statement.push_str(&format!(", tooltip=\"{}\"", tooltip));
+    }
+    if !node.entries.is_empty() {
+        statement.push_str(", peripheries=2");
+    }
+    if !extra.is_empty() {
+        statement.push_str(", ");
+        statement.push_str(extra);
+    }
+    statement.push(']');
+    statement
+}
+
+/// A DOT tooltip of `first`, then one `key: value` line per attribute;
+/// `None` if there is nothing to show
+fn tooltip(first: Option<&str>, attributes: &BTreeMap<String, String>) -> Option<String> {
+    let lines: Vec<String> = first
+        .map(String::from)
+        .into_iter()
+        .chain(attributes.iter().map(|(key, value)| format!("{}: {}", key, value)))
+        .collect();
+    if lines.is_empty() {
+        return None;
+    }
+    let escaped: Vec<String> = lines.iter().map(|line| line.replace('\\', "\\\\").replace('"', "\\\"")).collect();
+    Some(escaped.join("\\n"))
+}
+
 /// Graph statistics
 #[derive(Debug, Clone)]
 pub struct GraphStatistics {
@@ -261,19 +1199,22 @@ pub struct GraphStatistics {
 mod tests {
     use super::*;
     use crate::ast::*;
-    use std::collections::HashSet;
+    use crate::lexer::Position;
 
     fn make_test_system() -> MartialSystem {
-        let mut roles = HashSet::new();
+        let mut roles = BTreeSet::new();
         roles.insert("Top".to_string());
         roles.insert("Bottom".to_string());
 
-        let mut states = HashMap::new();
+        let mut states = BTreeMap::new();
         states.insert(
             "Mount".to_string(),
             State {
                 name: "Mount".to_string(),
                 allowed_roles: None,
+                annotations: Vec::new(),
+                position: Position::default(),
+                spans: Spans::default(),
             },
         );
         states.insert(
@@ -281,27 +1222,49 @@ mod tests {
             State {
                 name: "Guard".to_string(),
                 allowed_roles: None,
+                annotations: Vec::new(),
+                position: Position::default(),
+                spans: Spans::default(),
             },
         );
 
-        let mut sequences = HashMap::new();
+        let mut sequences = BTreeMap::new();
         sequences.insert(
             "Escape".to_string(),
             Sequence {
                 name: "Escape".to_string(),
                 steps: vec![
                     SequenceStep {
-                        action_name: "Shrimp".to_string(),
+                        action_name: "Shrimp".into(),
                         from: StateRef {
-                            state: "Mount".to_string(),
-                            role: "Bottom".to_string(),
+                            state: "Mount".into(),
+                            role: "Bottom".into(),
+                            extra_roles: Vec::new(),
+                            spans: Spans::default(),
                         },
                         to: StateRef {
-                            state: "Guard".to_string(),
-                            role: "Bottom".to_string(),
+                            state: "Guard".into(),
+                            role: "Bottom".into(),
+                            extra_roles: Vec::new(),
+                            spans: Spans::default(),
                         },
+                        on_fail: None,
+                        reaction: None,
+                        annotations: Vec::new(),
+                        template: None,
+                        repeat: None,
+                        repetition: None,
+                        position: Position::default(),
+                        spans: Spans::default(),
                     },
                 ],
+                invocations: Vec::new(),
+                annotations: Vec::new(),
+                mirrored: false,
+                alternating: None,
+                mirror_of: None,
+                position: Position::default(),
+                spans: Spans::default(),
             },
         );
 
@@ -310,7 +1273,15 @@ mod tests {
             roles,
             states,
             sequences,
-            groups: HashMap::new(),
+            groups: BTreeMap::new(),
+            levels: Vec::new(),
+            terms: Default::default(),
+            rulesets: Default::default(),
+            metadata: Default::default(),
+            sequence_order: Vec::new(),
+            origins: BTreeMap::new(),
+            role_positions: BTreeMap::new(),
+            name_positions: BTreeMap::new(),
         }
     }
 
@@ -329,11 +1300,34 @@ mod tests {
         let system = make_test_system();
         let graph = MartialGraph::from_system(&system);
 
-        let start = Node::new("Mount".to_string(), "Bottom".to_string());
+        let start = Node::new("Mount", "Bottom");
         let reachable = graph.reachable_from(&start);
 
         assert_eq!(reachable.len(), 2); // Mount[Bottom] and Guard[Bottom]
-        assert!(reachable.contains(&Node::new("Guard".to_string(), "Bottom".to_string())));
+        assert!(reachable.contains(&Node::new("Guard", "Bottom")));
+    }
+
+    #[test]
+    fn test_unreachable_from() {
+        let mut system = make_test_system();
+        let mut escape = system.sequences["Escape"].clone();
+        escape.name = "Roll".to_string();
+        escape.steps[0].from.state = "Guard".into();
+        escape.steps[0].to.state = "Mount".into();
+        escape.steps[0].to.role = "Top".into();
+        system.sequences.insert("Roll".to_string(), escape);
+        let graph = MartialGraph::from_system(&system);
+
+        // Every edge source is a root for find_unreachable_nodes
+        assert!(graph.find_unreachable_nodes().is_empty());
+
+        let guard = Node::new("Guard", "Bottom");
+        let reachable = graph.reachable_from_many(core::slice::from_ref(&guard));
+        assert_eq!(reachable.len(), 2);
+        assert!(reachable.contains(&Node::new("Mount", "Top")));
+        assert_eq!(graph.unreachable_from(core::slice::from_ref(&guard)), [Node::new("Mount", "Bottom")]);
+        assert!(graph.unreachable_from(&[guard, Node::new("Mount", "Bottom")]).is_empty());
+        assert_eq!(graph.unreachable_from(&[]).len(), 3);
     }
 
     #[test]
@@ -349,6 +1343,18 @@ mod tests {
         assert_eq!(stats.sink_nodes.len(), 1);
     }
 
+    #[test]
+    fn test_declared_nodes() {
+        let mut system = make_test_system();
+        system.states.get_mut("Guard").unwrap().allowed_roles = Some(vec!["Bottom".to_string()]);
+        let graph = MartialGraph::from_system(&system).with_declared_nodes(&system);
+
+        let ids: Vec<String> = graph.nodes.iter().map(Node::id).collect();
+        assert_eq!(ids, ["Guard[Bottom]", "Mount[Bottom]", "Mount[Top]"]);
+        assert_eq!(graph.edges.len(), 1);
+        assert_eq!(graph.statistics().isolated_nodes, [Node::new("Mount", "Top")]);
+    }
+
     #[test]
     fn test_dot_export() {
         let system = make_test_system();
@@ -361,6 +1367,32 @@ mod tests {
         assert!(dot.contains("Shrimp"));
     }
 
+    #[test]
+    fn test_mermaid_export() {
+        let source = "roles { Top, Bottom }\nstate Guard\nstate Mount\ngroup Pins { Mount }\n\
+                      sequence Sweep:\n    Hip: Guard[Bottom] -> Mount[Top] / Guard[Top] -> Mount[Bottom]";
+        let system = crate::loader::load_sources("test", &[crate::loader::SourceFile::new("a.martial", source)]).unwrap();
+        let mermaid = MartialGraph::from_system(&system).to_mermaid();
+
+        assert!(mermaid.starts_with("flowchart LR\n"));
+        assert!(mermaid.contains("  subgraph Pins\n    n2[\"Mount<br/>[Bottom]\"]\n    n3[\"Mount<br/>[Top]\"]\n  end\n"), "{}", mermaid);
+        assert!(mermaid.contains("  n0[\"Guard<br/>[Bottom]\"]\n"));
+        assert!(mermaid.contains("  n0 -->|Hip| n3\n"));
+        assert!(mermaid.contains("  n1 -.->|Hip| n2\n"));
+    }
+
+    #[test]
+    fn test_group_steps() {
+        let source = "roles { Top, Bottom }\nstate ClosedGuard\nstate OpenGuard\nstate HalfGuard\n\
+                      group AnyGuard { ClosedGuard, OpenGuard }\n\
+                      sequence Recover:\n    Recover: AnyGuard[Bottom] -> HalfGuard[Bottom]";
+        let system = crate::loader::load_sources("test", &[crate::loader::SourceFile::new("a.martial", source)]).unwrap();
+        let graph = MartialGraph::from_system(&system);
+        let edges: Vec<String> = graph.edges.iter().map(|e| format!("{} -> {}", e.from.id(), e.to.id())).collect();
+        assert_eq!(edges, ["ClosedGuard[Bottom] -> HalfGuard[Bottom]", "OpenGuard[Bottom] -> HalfGuard[Bottom]"]);
+        assert!(!graph.nodes.iter().any(|node| node.state == "AnyGuard"));
+    }
+
     #[test]
     fn test_json_export() {
         let system = make_test_system();
@@ -371,4 +1403,365 @@ mod tests {
         assert!(json.contains("Mount"));
         assert!(json.contains("Shrimp"));
     }
+
+    #[test]
+    fn test_edge_videos() {
+        let source = "roles { Top }\nstate Mount\n\
+                      sequence Hold @video(\"https://videos.test/hold\"):\n    \
+                      Settle: Mount[Top] -> Mount[Top] @video(\"https://videos.test/settle\")\n    \
+                      Post: Mount[Top] -> Mount[Top]";
+        let system = crate::loader::load_sources("test", &[crate::loader::SourceFile::new("a.martial", source)]).unwrap();
+        let graph = MartialGraph::from_system(&system);
+
+        assert_eq!(graph.edges[0].videos, vec!["https://videos.test/settle", "https://videos.test/hold"]);
+        assert_eq!(graph.edges[1].videos, vec!["https://videos.test/hold"]);
+        assert!(graph.to_json().unwrap().contains("\"videos\""));
+        assert!(!MartialGraph::from_system(&make_test_system()).to_json().unwrap().contains("videos"));
+    }
+
+    #[test]
+    fn test_attributes() {
+        let source = "roles { Top }\nlevels { White }\nstate Mount @tag(pin) @label(ja: \"Tate Shiho\")\nstate Guard\n\
+                      sequence Hold @level(White) @tag(control) @attack:\n    \
+                      Settle: Guard[Top] -> Mount[Top] @weight(2) @tag(\"pass \\\"fast\\\"\")";
+        let system = crate::loader::load_sources("test", &[crate::loader::SourceFile::new("a.martial", source)]).unwrap();
+        let graph = MartialGraph::from_system(&system);
+
+        let mount = graph.nodes.iter().find(|n| n.state == "Mount").unwrap();
+        assert_eq!(mount.attributes["tag"], "pin");
+        assert_eq!(mount.attributes["label.ja"], "Tate Shiho");
+        assert!(graph.nodes.iter().find(|n| n.state == "Guard").unwrap().attributes.is_empty());
+        assert_eq!(*mount, Node::new("Mount", "Top"));
+
+        let edge = &graph.edges[0];
+        let attributes: Vec<(&str, &str)> = edge.attributes.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+        assert_eq!(attributes, [("attack", "true"), ("level", "White"), ("tag", "pass \"fast\""), ("weight", "2")]);
+        assert!(edge.from.attributes.is_empty());
+
+        let json = graph.to_json().unwrap();
+        assert!(json.contains("\"label.ja\": \"Tate Shiho\""));
+        let dot = graph.to_dot();
+        assert!(dot.contains("[label=\"Mount\\n[Top]\", tooltip=\"label.ja: Tate Shiho\\ntag: pin\"]"), "{}", dot);
+        assert!(dot.contains("tooltip=\"attack: true\\nlevel: White\\ntag: pass \\\"fast\\\"\\nweight: 2\""), "{}", dot);
+    }
+
+    #[test]
+    fn test_reaction_edges() {
+        let source = "roles { Top, Bottom }\nstate Guard\nstate Mount\n\
+                      sequence Sweep:\n    Scissor: Guard[Bottom] -> Mount[Top] / Guard[Top] -> Mount[Bottom]";
+        let system = crate::loader::load_sources("test", &[crate::loader::SourceFile::new("a.martial", source)]).unwrap();
+        let graph = MartialGraph::from_system(&system);
+
+        assert_eq!(graph.nodes.len(), 4);
+        let edges: Vec<(String, String, bool)> =
+            graph.edges.iter().map(|e| (e.from.id(), e.to.id(), e.reaction)).collect();
+        assert_eq!(
+            edges,
+            vec![
+                ("Guard[Bottom]".to_string(), "Mount[Top]".to_string(), false),
+                ("Guard[Top]".to_string(), "Mount[Bottom]".to_string(), true),
+            ]
+        );
+        assert!(graph.to_dot().contains("\"Guard[Top]\" -> \"Mount[Bottom]\" [label=\"Scissor\", style=dashed];"));
+        assert!(graph.to_json().unwrap().contains("\"reaction\": true"));
+    }
+
+    #[test]
+    fn test_failure_edges() {
+        let source = "roles { Top }\nstate Mount\nstate Armbar\n\
+                      sequence Finish:\n    Armbar: Mount[Top] -> Armbar[Top] onfail -> Mount[Top]";
+        let system = crate::loader::load_sources("test", &[crate::loader::SourceFile::new("a.martial", source)]).unwrap();
+        let graph = MartialGraph::from_system(&system);
+
+        let edges: Vec<(String, String, bool)> =
+            graph.edges.iter().map(|e| (e.from.id(), e.to.id(), e.failure)).collect();
+        assert_eq!(
+            edges,
+            vec![
+                ("Mount[Top]".to_string(), "Armbar[Top]".to_string(), false),
+                ("Mount[Top]".to_string(), "Mount[Top]".to_string(), true),
+            ]
+        );
+        assert!(graph.edges.iter().all(|e| e.entry));
+        assert!(graph.to_dot().contains("\"Mount[Top]\" -> \"Mount[Top]\" [label=\"Armbar (failed)\", color=red];"));
+        assert!(graph.to_mermaid().contains("|Armbar (failed)|"));
+        assert!(graph.to_json().unwrap().contains("\"failure\": true"));
+    }
+
+    #[test]
+    fn test_merge_parallel_edges() {
+        let source = "roles { Top }\nstate Guard\nstate Mount\n\
+                      sequence B @video(\"https://videos.test/b\"):\n    Climb: Guard[Top] -> Mount[Top]\n\
+                      sequence A:\n    Climb: Guard[Top] -> Mount[Top]\n    Settle: Mount[Top] -> Mount[Top]";
+        let system = crate::loader::load_sources("test", &[crate::loader::SourceFile::new("a.martial", source)]).unwrap();
+        let graph = MartialGraph::from_system(&system);
+        assert_eq!(graph.edges.len(), 3);
+        assert!(!graph.to_json().unwrap().contains("\"sequences\""));
+
+        let merged = graph.merge_parallel_edges();
+        assert_eq!(merged.edges.len(), 2);
+        let climb = merged.edges.iter().find(|e| e.action == "Climb").unwrap();
+        assert_eq!((climb.sequence.as_str(), climb.sequences.clone()), ("A", vec!["A".into(), "B".into()]));
+        assert_eq!(climb.videos, vec!["https://videos.test/b"]);
+        assert!(merged
+            .to_dot()
+            .contains("[label=\"Climb\", tooltip=\"A, B\\nvideo: https://videos.test/b\", penwidth=2];"));
+        assert!(merged.to_dot().contains("[label=\"Settle\"];"));
+    }
+
+    #[test]
+    fn test_depth_and_longest_chain() {
+        let source = "roles { Top, Bottom }\nstate Standing\nstate Guard\nstate Mount\nstate Back\n\
+                      sequence Pull:\n    Sit: Standing[Top] -> Guard[Bottom]\n    Sweep: Guard[Bottom] -> Mount[Top]\n\
+                      sequence Take:\n    Roll: Mount[Top] -> Back[Top]\n    Reset: Back[Top] -> Mount[Top]\n\
+                      sequence Shortcut:\n    Jump: Standing[Top] -> Back[Top]\n\
+                      sequence Hold:\n    Settle: Mount[Top] -> Mount[Top]";
+        let system = crate::loader::load_sources("test", &[crate::loader::SourceFile::new("a.martial", source)]).unwrap();
+        let graph = MartialGraph::from_system(&system);
+
+        let initial: Vec<String> = graph.initial_nodes().iter().map(|n| n.id()).collect();
+        assert_eq!(initial, vec!["Standing[Top]"]);
+        let depths: Vec<(String, usize)> = graph.depths().into_iter().map(|(n, d)| (n.id(), d)).collect();
+        assert_eq!(
+            depths,
+            vec![
+                ("Standing[Top]".to_string(), 0),
+                ("Back[Top]".to_string(), 1),
+                ("Guard[Bottom]".to_string(), 1),
+                ("Mount[Top]".to_string(), 2),
+            ]
+        );
+
+        let chain: Vec<&str> = graph.longest_chain().iter().map(|e| e.action.as_str()).collect();
+        assert_eq!(chain, vec!["Sit", "Sweep", "Roll"]);
+        assert!(MartialGraph::from_system(&make_test_system()).longest_chain().len() == 1);
+    }
+
+    #[test]
+    fn test_shortest_path() {
+        let source = "roles { Top, Bottom }\nstate Standing\nstate Guard\nstate Mount\nstate Back\n\
+                      sequence Pull:\n    Sit: Standing[Top] -> Guard[Bottom]\n    Sweep: Guard[Bottom] -> Mount[Top]\n\
+                      sequence Take:\n    Roll: Mount[Top] -> Back[Top]";
+        let system = crate::loader::load_sources("test", &[crate::loader::SourceFile::new("a.martial", source)]).unwrap();
+        let graph = MartialGraph::from_system(&system);
+
+        let actions = |from: &str, to: &str| -> Option<Vec<String>> {
+            let path = graph.shortest_path(from, to)?;
+            Some(path.iter().map(|e| e.action.to_string()).collect())
+        };
+        assert_eq!(actions("Standing", "Back"), Some(vec!["Sit".into(), "Sweep".into(), "Roll".into()]));
+        assert_eq!(actions("Guard[Bottom]", "Mount"), Some(vec!["Sweep".into()]));
+        assert_eq!(actions("Mount", "Mount[Top]"), Some(Vec::new()));
+        assert_eq!(actions("Guard[Top]", "Mount"), None);
+        assert_eq!(actions("Back", "Standing"), None);
+        assert_eq!(actions("Nowhere", "Back"), None);
+    }
+
+    #[test]
+    fn test_k_shortest_paths() {
+        let source = "roles { Top, Bottom }\nstate Guard\nstate Half\nstate Side\nstate Mount\n\
+                      sequence Sweep:\n    Hip: Guard[Bottom] -> Mount[Top]\n\
+                      sequence Pass:\n    Sit: Guard[Bottom] -> Half[Bottom]\n    Up: Half[Bottom] -> Side[Top]\n    \
+                      Climb: Side[Top] -> Mount[Top]\n\
+                      sequence Scramble:\n    Roll: Half[Bottom] -> Mount[Top]\n    Back: Mount[Top] -> Guard[Bottom]";
+        let system = crate::loader::load_sources("test", &[crate::loader::SourceFile::new("a.martial", source)]).unwrap();
+        let graph = MartialGraph::from_system(&system);
+
+        let routes = |k: usize| -> Vec<String> {
+            let paths = graph.k_shortest_paths("Guard", "Mount", k);
+            paths.iter().map(|path| path.iter().map(|e| e.action.as_str()).collect::<Vec<_>>().join(" ")).collect()
+        };
+        assert_eq!(routes(5), ["Hip", "Sit Roll", "Sit Up Climb"]);
+        assert_eq!(routes(2), ["Hip", "Sit Roll"]);
+        assert_eq!(graph.k_shortest_paths("Guard", "Guard[Bottom]", 3), vec![Vec::<&Edge>::new()]);
+        assert!(graph.k_shortest_paths("Side", "Guard[Top]", 3).is_empty());
+        assert!(graph.k_shortest_paths("Guard", "Mount", 0).is_empty());
+    }
+
+    #[test]
+    fn test_paths_with_constraints() {
+        let source = "roles { Top, Bottom }\nstate Guard\nstate HalfGuard\nstate Turtle\nstate TurtleBack\nstate Back\n\
+                      sequence Direct:\n    Spin: Guard[Bottom] -> Turtle[Top]\n    Take: Turtle[Top] -> Back[Top]\n\
+                      sequence Long:\n    Sit: Guard[Bottom] -> HalfGuard[Bottom]\n    Up: HalfGuard[Bottom] -> HalfGuard[Top]\n    \
+                      Hook: HalfGuard[Top] -> Back[Top]\n\
+                      sequence Detour:\n    Roll: HalfGuard[Top] -> TurtleBack[Top]\n    Seat: TurtleBack[Top] -> Back[Top]";
+        let system = crate::loader::load_sources("test", &[crate::loader::SourceFile::new("a.martial", source)]).unwrap();
+        let graph = MartialGraph::from_system(&system);
+        let constraints = |via: &[&str], avoid: &[&str]| PathConstraints {
+            via: via.iter().map(|p| p.to_string()).collect(),
+            avoid: avoid.iter().map(|p| p.to_string()).collect(),
+        };
+        let routes = |constraints: &PathConstraints, k: usize| -> Vec<String> {
+            let paths = graph.find_paths_with_constraints("Guard", "Back", constraints, k);
+            paths.iter().map(|path| path.iter().map(|e| e.action.as_str()).collect::<Vec<_>>().join(" ")).collect()
+        };
+        assert_eq!(routes(&constraints(&[], &[]), 1), ["Spin Take"]);
+        assert_eq!(routes(&constraints(&[], &["Turtle*"]), 3), ["Sit Up Hook"]);
+        assert_eq!(routes(&constraints(&["HalfGuard[Top]"], &[]), 3), ["Sit Up Hook", "Sit Up Roll Seat"]);
+        assert_eq!(routes(&constraints(&["HalfGuard[Top]"], &["Turtle*"]), 3), ["Sit Up Hook"]);
+        assert!(routes(&constraints(&["Back", "Guard"], &[]), 3).is_empty());
+
+        assert_eq!(routes(&constraints(&["/^Half.*\\[Top\\]$/"], &["/Turtle/"]), 3), ["Sit Up Hook"]);
+    }
+
+    #[test]
+    fn test_canonical_path() {
+        let source = "roles { Top }\nstate Guard\nstate Side\nstate Mount\nstate Back\n\
+                      sequence Pass:\n    Cut: Guard[Top] -> Side[Top]\n\
+                      sequence Attack:\n    Settle: Side[Top] -> Side[Top]\n    Climb: Side[Top] -> Mount[Top]\n    \
+                      Take: Mount[Top] -> Back[Top] @entry";
+        let system = crate::loader::load_sources("test", &[crate::loader::SourceFile::new("a.martial", source)]).unwrap();
+        let graph = MartialGraph::from_system(&system);
+
+        let mut entries: Vec<&str> = graph.edges.iter().filter(|e| e.entry).map(|e| e.action.as_ref()).collect();
+        entries.sort();
+        assert_eq!(entries, ["Cut", "Settle", "Take"]);
+        let mount = graph.nodes.iter().find(|n| n.matches("Mount")).unwrap();
+        assert_eq!(mount.entries, [Symbol::from("Attack")]);
+        assert!(graph.to_dot().contains("peripheries=2"));
+
+        let actions = |path: Option<Vec<&Edge>>| -> Option<Vec<String>> {
+            Some(path?.iter().map(|e| e.action.to_string()).collect())
+        };
+        assert_eq!(actions(graph.shortest_path("Guard", "Mount")), Some(vec!["Cut".into(), "Climb".into()]));
+        assert_eq!(
+            actions(graph.canonical_path("Guard", "Mount")),
+            Some(vec!["Cut".into(), "Settle".into(), "Climb".into()])
+        );
+        assert_eq!(actions(graph.canonical_path("Mount", "Back")), Some(vec!["Take".into()]));
+        assert_eq!(actions(graph.canonical_path("Mount", "Mount")), Some(Vec::new()));
+        assert_eq!(actions(graph.canonical_path("Back", "Guard")), None);
+
+        assert_eq!(graph.reachable_sequences("Guard"), [&Symbol::from("Attack"), &Symbol::from("Pass")]);
+        // Attack is joined at its first step or at Take
+        assert_eq!(graph.reachable_sequences("Mount"), [&Symbol::from("Attack")]);
+        assert!(graph.reachable_sequences("Back").is_empty());
+    }
+
+    #[test]
+    fn test_flows() {
+        let source = "roles { Top, Bottom }\nstate Guard\nstate Side\nstate Mount\nstate Back\n\
+                      sequence Pass:\n    Cut: Guard[Top] -> Side[Top]\n    Climb: Side[Top] -> Mount[Top]\n\
+                      sequence Reset:\n    Open: Side[Top] -> Guard[Top]\n\
+                      sequence Take:\n    Grip: Guard[Top] -> Mount[Top]\n    Roll: Mount[Top] -> Back[Top] @entry\n    \
+                      Sweep: Back[Top] -> Mount[Bottom] onfail -> Guard[Top] / Back[Bottom] -> Mount[Top]";
+        let system = crate::loader::load_sources("test", &[crate::loader::SourceFile::new("a.martial", source)]).unwrap();
+        let graph = MartialGraph::from_system(&system);
+
+        let flows: Vec<(String, String, String, bool)> = graph
+            .flows()
+            .into_iter()
+            .map(|f| (f.from.to_string(), f.to.to_string(), f.at.id(), f.ends))
+            .collect();
+        assert_eq!(
+            flows,
+            [
+                // Reset starts where Pass passes through, Take where it ends
+                ("Pass".into(), "Reset".into(), "Side[Top]".into(), false),
+                ("Pass".into(), "Take".into(), "Mount[Top]".into(), true),
+                ("Reset".into(), "Pass".into(), "Guard[Top]".into(), true),
+                ("Reset".into(), "Take".into(), "Guard[Top]".into(), true),
+            ]
+        );
+        let dot = graph.flows_to_dot();
+        assert!(dot.contains("  \"Pass\" -> \"Reset\" [label=\"Side[Top]\", style=dashed];\n"));
+        assert!(dot.contains("  \"Take\";\n"));
+        assert_eq!(graph.merge_parallel_edges().flows().len(), 4);
+    }
+
+    #[test]
+    fn test_centrality() {
+        let source = "roles { Top }\nstate Guard\nstate Side\nstate Mount\nstate Back\n\
+                      sequence Pass:\n    Cut: Guard[Top] -> Side[Top]\n    Climb: Side[Top] -> Mount[Top]\n    \
+                      Settle: Mount[Top] -> Mount[Top]\n\
+                      sequence Take:\n    Step: Side[Top] -> Back[Top]";
+        let system = crate::loader::load_sources("test", &[crate::loader::SourceFile::new("a.martial", source)]).unwrap();
+        let graph = MartialGraph::from_system(&system);
+
+        let centrality: BTreeMap<String, Centrality> =
+            graph.centrality().into_iter().map(|(node, c)| (node.id(), c)).collect();
+        let side = centrality["Side[Top]"];
+        assert_eq!((side.in_degree, side.out_degree), (1, 2));
+        // Guard to Mount and Guard to Back, of the 6 ordered pairs of others
+        assert!((side.betweenness - 2.0 / 6.0).abs() < 1e-9);
+        assert!((side.closeness - 2.0 / 3.0).abs() < 1e-9);
+        let mount = centrality["Mount[Top]"];
+        assert_eq!((mount.in_degree, mount.out_degree, mount.betweenness, mount.closeness), (2, 1, 0.0, 0.0));
+        assert!((centrality["Guard[Top]"].closeness - (1.0 + 0.5 + 0.5) / 3.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_within() {
+        let source = "roles { Top, Bottom }\nstate Standing\nstate Guard\nstate Mount\nstate Back\n\
+                      sequence Pull:\n    Sit: Standing[Top] -> Guard[Bottom]\n    Sweep: Guard[Bottom] -> Mount[Top]\n    \
+                      Take: Mount[Top] -> Back[Top]\n\
+                      sequence Reset:\n    Stand: Mount[Top] -> Standing[Top]";
+        let system = crate::loader::load_sources("test", &[crate::loader::SourceFile::new("a.martial", source)]).unwrap();
+        let graph = MartialGraph::from_system(&system);
+        let ids = |graph: &MartialGraph| -> (Vec<String>, Vec<String>) {
+            let mut nodes: Vec<String> = graph.nodes.iter().map(Node::id).collect();
+            nodes.sort();
+            (nodes, graph.edges.iter().map(|e| e.action.to_string()).collect())
+        };
+
+        let (nodes, actions) = ids(&graph.within("Standing[Top]", Some(1)));
+        assert_eq!((nodes, actions), (vec!["Guard[Bottom]".to_string(), "Standing[Top]".to_string()], vec!["Sit".to_string()]));
+        // Stand closes a cycle, but only within the first two transitions
+        let (nodes, actions) = ids(&graph.within("Standing", Some(2)));
+        assert_eq!(nodes.len(), 3);
+        assert_eq!(actions, ["Sit", "Sweep"]);
+        assert_eq!(ids(&graph.within("Standing", Some(3))).1, ["Sit", "Sweep", "Take", "Stand"]);
+        assert_eq!(ids(&graph.within("Standing", None)), ids(&graph));
+        assert_eq!(ids(&graph.within("Back", Some(5))).0, ["Back[Top]"]);
+        assert!(graph.within("Nowhere", Some(5)).nodes.is_empty());
+
+        let mut graph = graph;
+        graph.groups.insert("Ground".to_string(), vec!["Guard".to_string(), "Back".to_string()]);
+        let within = graph.within("Standing", Some(1));
+        assert_eq!(within.groups.get("Ground"), Some(&vec!["Guard".to_string()]));
+        assert_eq!(graph.within("Back", None).groups["Ground"], ["Back"]);
+        assert!(graph.within("Mount", Some(0)).groups.is_empty());
+
+        let reset = graph.sequence("Reset");
+        assert_eq!(ids(&reset), (vec!["Mount[Top]".to_string(), "Standing[Top]".to_string()], vec!["Stand".to_string()]));
+        assert!(reset.groups.is_empty());
+        assert!(graph.sequence("Nothing").nodes.is_empty());
+        assert_eq!(ids(&graph.sequence("/^Res/")), ids(&reset));
+        assert_eq!(ids(&graph.sequence("*")).1, ids(&graph).1);
+        assert_eq!(ids(&graph.within("Stand*", Some(1))).1, ["Sit"]);
+    }
+
+    #[test]
+    fn test_equivalent_states() {
+        let source = "roles { Top, Bottom }\nstate Guard\nstate Mount\nstate FullMount\nstate Back\n\
+                      sequence A:\n    Climb: Guard[Top] -> Mount[Top]\n    Take: Mount[Top] -> Back[Top]\n\
+                      sequence B:\n    Climb: Guard[Top] -> FullMount[Top]\n    Take: FullMount[Top] -> Back[Top]\n\
+                      sequence C:\n    Settle: Mount[Top] -> Mount[Top]\n\
+                      sequence D:\n    Settle: FullMount[Top] -> FullMount[Top]";
+        let system = crate::loader::load_sources("test", &[crate::loader::SourceFile::new("a.martial", source)]).unwrap();
+        let graph = MartialGraph::from_system(&system);
+        assert_eq!(graph.equivalent_states(), vec![vec!["FullMount".to_string(), "Mount".to_string()]]);
+
+        let source = source.replace("sequence D:\n    Settle", "sequence D:\n    Post");
+        let system = crate::loader::load_sources("test", &[crate::loader::SourceFile::new("a.martial", &source)]).unwrap();
+        assert!(MartialGraph::from_system(&system).equivalent_states().is_empty());
+    }
+
+    #[test]
+    fn test_project_role_perspective() {
+        let source = "roles { Tori, Uke }\nstate Kamae\nstate Ikkyo\nstate Pinned\n\
+                      sequence Attack:\n    \
+                      Strike: Kamae[Uke] -> Ikkyo[Uke] / Kamae[Tori] -> Ikkyo[Tori]\n\
+                      sequence Control:\n    \
+                      Pin: Ikkyo[Tori] -> Pinned[Tori] / Ikkyo[Uke] -> Pinned[Uke]";
+        let system = crate::loader::load_sources("test", &[crate::loader::SourceFile::new("a.martial", source)]).unwrap();
+        let tori = MartialGraph::from_system(&system).project_role_perspective("Tori");
+
+        let mut edges: Vec<(&str, bool)> = tori.edges.iter().map(|e| (e.action.as_str(), e.reaction)).collect();
+        edges.sort();
+        assert_eq!(edges, vec![("Pin", false), ("Strike", true)]);
+        assert!(tori.nodes.iter().all(|n| n.role == "Tori"));
+        assert_eq!(tori.nodes.len(), 3);
+        assert!(MartialGraph::from_system(&system).project_role_perspective("Nage").edges.is_empty());
+    }
 }
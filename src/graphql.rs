@@ -0,0 +1,380 @@
+//! GraphQL schema over a validated system
+//!
+//! Lets front ends fetch exactly the slice of a system they display, e.g.
+//!
+//! ```graphql
+//! {
+//!   state(name: "Mount") { level outgoing { action to { id } } }
+//!   path(from: "ClosedGuard", to: "ArmbarPosition") { action sequence }
+//! }
+//! ```
+//!
+//! Objects resolve lazily from a shared [`SystemHandle`]. `mat serve`
+//! answers queries at `/graphql` and prints the schema at `/graphql/schema`.
+
+use crate::ast;
+use crate::graph::{Edge, Node};
+use crate::handle::SystemHandle;
+use async_graphql::{EmptyMutation, EmptySubscription, Object, SimpleObject};
+
+/// The schema type served by [`schema`]
+pub type MartialSchema = async_graphql::Schema<Query, EmptyMutation, EmptySubscription>;
+
+/// Build the schema for `handle`
+pub fn schema(handle: SystemHandle) -> MartialSchema {
+    MartialSchema::new(Query(handle), EmptyMutation, EmptySubscription)
+}
+
+/// Run a request to completion on the current thread
+pub fn execute(schema: &MartialSchema, request: impl Into<async_graphql::Request>) -> async_graphql::Response {
+    futures_executor::block_on(schema.execute(request))
+}
+
+/// Entry points of the schema
+pub struct Query(SystemHandle);
+
+#[Object]
+impl Query {
+    /// Name of the system
+    async fn name(&self) -> &str {
+        &self.0.system().name
+    }
+
+    /// Declared roles, sorted
+    async fn roles(&self) -> Vec<&str> {
+        self.0.system().roles.iter().map(String::as_str).collect()
+    }
+
+    /// Curriculum levels, lowest first
+    async fn levels(&self) -> &[String] {
+        &self.0.system().levels
+    }
+
+    /// States sorted by name, optionally only those of a group, playable in
+    /// a role or introduced at a level
+    async fn states(&self, group: Option<String>, role: Option<String>, level: Option<String>) -> Vec<StateView> {
+        let system = self.0.system();
+        let members = group.map(|g| system.groups.get(&g).cloned().unwrap_or_default());
+        system
+            .states
+            .values()
+            .filter(|s| members.as_ref().is_none_or(|m| m.contains(&s.name)))
+            .filter(|s| role.as_ref().is_none_or(|r| s.allowed_roles.as_ref().is_none_or(|a| a.contains(r))))
+            .filter(|s| level.is_none() || s.level() == level.as_deref())
+            .map(|s| StateView::new(&self.0, &s.name))
+            .collect()
+    }
+
+    async fn state(&self, name: String) -> Option<StateView> {
+        self.0.system().states.contains_key(&name).then(|| StateView::new(&self.0, &name))
+    }
+
+    /// Sequences sorted by name, optionally only those taught at a level or
+    /// passing through a state
+    async fn sequences(&self, level: Option<String>, state: Option<String>) -> Vec<SequenceView> {
+        let system = self.0.system();
+        system
+            .sequences
+            .values()
+            .filter(|q| level.is_none() || system.sequence_level(q) == level.as_deref())
+            .filter(|q| state.as_ref().is_none_or(|s| q.steps.iter().any(|t| t.from.state == *s || t.to.state == *s)))
+            .map(|q| SequenceView::new(&self.0, &q.name))
+            .collect()
+    }
+
+    async fn sequence(&self, name: String) -> Option<SequenceView> {
+        self.0.system().sequences.contains_key(&name).then(|| SequenceView::new(&self.0, &name))
+    }
+
+    /// Groups sorted by name
+    async fn groups(&self) -> Vec<Group> {
+        self.0
+            .system()
+            .groups
+            .iter()
+            .map(|(name, states)| Group {
+                name: name.clone(),
+                states: states.clone(),
+            })
+            .collect()
+    }
+
+    /// Graph edges, optionally only those leaving `from`, entering `to` or
+    /// performing `action`; endpoints are states or nodes like `Mount[Top]`
+    async fn transitions(&self, from: Option<String>, to: Option<String>, action: Option<String>) -> Vec<Transition> {
+        self.0
+            .graph()
+            .edges
+            .iter()
+            .filter(|e| from.as_ref().is_none_or(|f| e.from.matches(f)))
+            .filter(|e| to.as_ref().is_none_or(|t| e.to.matches(t)))
+            .filter(|e| action.as_ref().is_none_or(|a| e.action == *a))
+            .map(Transition::new)
+            .collect()
+    }
+
+    /// Fewest transitions from `from` to `to`, null if there is no path
+    async fn path(&self, from: String, to: String) -> Option<Vec<Transition>> {
+        let path = self.0.shortest_path(&from, &to)?;
+        Some(path.into_iter().map(Transition::new).collect())
+    }
+}
+
+/// A state of the system
+pub struct StateView {
+    handle: SystemHandle,
+    name: String,
+}
+
+impl StateView {
+    fn new(handle: &SystemHandle, name: &str) -> Self {
+        StateView {
+            handle: handle.clone(),
+            name: name.to_string(),
+        }
+    }
+
+    fn state(&self) -> &ast::State {
+        &self.handle.system().states[&self.name]
+    }
+
+    fn edges(&self, outgoing: bool) -> Vec<Transition> {
+        self.handle
+            .graph()
+            .edges
+            .iter()
+            .filter(|e| if outgoing { &e.from } else { &e.to }.state == self.name)
+            .map(Transition::new)
+            .collect()
+    }
+}
+
+#[Object(name = "State")]
+impl StateView {
+    async fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Roles the state may be played in, null if any
+    async fn allowed_roles(&self) -> Option<&[String]> {
+        self.state().allowed_roles.as_deref()
+    }
+
+    async fn level(&self) -> Option<&str> {
+        self.state().level()
+    }
+
+    async fn dominance(&self) -> Option<u32> {
+        self.state().dominance()
+    }
+
+    /// Display name in `lang`, from the `@label` annotation
+    async fn label(&self, lang: String) -> Option<&str> {
+        self.state().label(&lang)
+    }
+
+    /// Groups containing the state, sorted
+    async fn groups(&self) -> Vec<&str> {
+        self.handle
+            .system()
+            .groups
+            .iter()
+            .filter(|(_, states)| states.contains(&self.name))
+            .map(|(name, _)| name.as_str())
+            .collect()
+    }
+
+    /// Transitions leaving the state in any role
+    async fn outgoing(&self) -> Vec<Transition> {
+        self.edges(true)
+    }
+
+    /// Transitions entering the state in any role
+    async fn incoming(&self) -> Vec<Transition> {
+        self.edges(false)
+    }
+}
+
+/// A sequence of the system
+pub struct SequenceView {
+    handle: SystemHandle,
+    name: String,
+}
+
+impl SequenceView {
+    fn new(handle: &SystemHandle, name: &str) -> Self {
+        SequenceView {
+            handle: handle.clone(),
+            name: name.to_string(),
+        }
+    }
+
+    fn sequence(&self) -> &ast::Sequence {
+        &self.handle.system().sequences[&self.name]
+    }
+}
+
+#[Object(name = "Sequence")]
+impl SequenceView {
+    async fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Level the sequence is taught at, declared or inherited from its states
+    async fn level(&self) -> Option<&str> {
+        self.handle.system().sequence_level(self.sequence())
+    }
+
+    /// Teaching time in minutes
+    async fn duration(&self) -> Option<u32> {
+        self.sequence().duration()
+    }
+
+    async fn videos(&self) -> Vec<&str> {
+        self.sequence().videos()
+    }
+
+    async fn attack(&self) -> bool {
+        self.sequence().is_attack()
+    }
+
+    async fn steps(&self) -> Vec<Step> {
+        self.sequence()
+            .steps
+            .iter()
+            .map(|step| Step {
+                action: step.action_name.to_string(),
+                from: step.from.to_string(),
+                to: step.to.to_string(),
+                videos: step.videos().into_iter().map(String::from).collect(),
+            })
+            .collect()
+    }
+}
+
+/// A step of a sequence, with states written as `State[Role]`
+#[derive(SimpleObject)]
+pub struct Step {
+    pub action: String,
+    pub from: String,
+    pub to: String,
+    pub videos: Vec<String>,
+}
+
+#[derive(SimpleObject)]
+pub struct Group {
+    pub name: String,
+    pub states: Vec<String>,
+}
+
+/// A state played in a role
+#[derive(SimpleObject)]
+#[graphql(name = "Node")]
+pub struct NodeView {
+    /// `State[Role]`
+    pub id: String,
+    pub state: String,
+    pub role: String,
+}
+
+impl From<&Node> for NodeView {
+    fn from(node: &Node) -> Self {
+        NodeView {
+            id: node.id(),
+            state: node.state.to_string(),
+            role: node.role.to_string(),
+        }
+    }
+}
+
+/// An edge of the graph: an action taking one node to another
+#[derive(SimpleObject)]
+pub struct Transition {
+    pub action: String,
+    /// Sequence declaring the step
+    pub sequence: String,
+    pub from: NodeView,
+    pub to: NodeView,
+    /// Whether this is the opponent's side of a step
+    pub reaction: bool,
+    /// Whether this is where the action leads when it fails
+    pub failure: bool,
+    pub videos: Vec<String>,
+}
+
+// Not `From<&Edge>`: the derived resolver of `from` would shadow it
+impl Transition {
+    fn new(edge: &Edge) -> Self {
+        Transition {
+            action: edge.action.to_string(),
+            sequence: edge.sequence.to_string(),
+            from: NodeView::from(&edge.from),
+            to: NodeView::from(&edge.to),
+            reaction: edge.reaction,
+            failure: edge.failure,
+            videos: edge.videos.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loader::{load_sources, SourceFile};
+    use serde_json::json;
+
+    fn run(query: &str) -> serde_json::Value {
+        let source = "roles { Top, Bottom }\nlevels { White, Blue }\n\
+                      state Guard @level(White)\nstate Mount roles { Top, Bottom } @level(Blue) @dominance(8)\nstate Back\n\
+                      group Dominant { Mount, Back }\n\
+                      sequence Pass @attack:\n    Sweep: Guard[Bottom] -> Mount[Top]\n    Roll: Mount[Top] -> Back[Top]";
+        let system = load_sources("club", &[SourceFile::new("a.martial", source)]).unwrap();
+        let response = execute(&schema(SystemHandle::new(system)), query);
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        response.data.into_json().unwrap()
+    }
+
+    #[test]
+    fn test_states_and_sequences() {
+        let data = run("{ name roles states(group: \"Dominant\") { name level dominance groups } }");
+        assert_eq!(data["name"], "club");
+        assert_eq!(data["roles"], json!(["Bottom", "Top"]));
+        assert_eq!(
+            data["states"],
+            json!([
+                { "name": "Back", "level": null, "dominance": null, "groups": ["Dominant"] },
+                { "name": "Mount", "level": "Blue", "dominance": 8, "groups": ["Dominant"] },
+            ])
+        );
+
+        let data = run("{ state(name: \"Mount\") { allowedRoles outgoing { action to { id } } incoming { action } } }");
+        assert_eq!(data["state"]["allowedRoles"], json!(["Top", "Bottom"]));
+        assert_eq!(data["state"]["outgoing"], json!([{ "action": "Roll", "to": { "id": "Back[Top]" } }]));
+        assert_eq!(data["state"]["incoming"], json!([{ "action": "Sweep" }]));
+        assert_eq!(run("{ state(name: \"Nowhere\") { name } }")["state"], json!(null));
+
+        let data = run("{ sequences(state: \"Back\") { name attack level steps { action from to } } }");
+        assert_eq!(
+            data["sequences"],
+            json!([{
+                "name": "Pass",
+                "attack": true,
+                "level": "Blue",
+                "steps": [
+                    { "action": "Sweep", "from": "Guard[Bottom]", "to": "Mount[Top]" },
+                    { "action": "Roll", "from": "Mount[Top]", "to": "Back[Top]" },
+                ],
+            }])
+        );
+    }
+
+    #[test]
+    fn test_transitions_and_paths() {
+        let data = run("{ transitions(from: \"Mount[Top]\") { action sequence } }");
+        assert_eq!(data["transitions"], json!([{ "action": "Roll", "sequence": "Pass" }]));
+
+        let data = run("{ path(from: \"Guard\", to: \"Back\") { action } none: path(from: \"Back\", to: \"Guard\") { action } }");
+        assert_eq!(data["path"], json!([{ "action": "Sweep" }, { "action": "Roll" }]));
+        assert_eq!(data["none"], json!(null));
+    }
+}
@@ -0,0 +1,113 @@
+//! Shared access to a validated system
+//!
+//! A [`SystemHandle`] owns a system and its graph behind an [`Arc`]. Clones
+//! are cheap and every query takes `&self` without interior mutability, so
+//! one handle can answer queries from many threads at once, as a web
+//! service answering path queries does.
+
+use crate::graph::{Edge, GraphStatistics, MartialGraph, Node};
+use crate::prelude::*;
+use crate::semantic::MartialSystem;
+use alloc::sync::Arc;
+
+/// A validated system and its graph, shared between threads
+#[derive(Debug, Clone)]
+pub struct SystemHandle {
+    shared: Arc<Shared>,
+}
+
+#[derive(Debug)]
+struct Shared {
+    system: MartialSystem,
+    graph: MartialGraph,
+}
+
+impl SystemHandle {
+    /// Build the graph of `system` once and share both
+    pub fn new(system: MartialSystem) -> Self {
+        let graph = MartialGraph::from_system(&system);
+        SystemHandle {
+            shared: Arc::new(Shared { system, graph }),
+        }
+    }
+
+    pub fn system(&self) -> &MartialSystem {
+        &self.shared.system
+    }
+
+    pub fn graph(&self) -> &MartialGraph {
+        &self.shared.graph
+    }
+
+    pub fn statistics(&self) -> GraphStatistics {
+        self.graph().statistics()
+    }
+
+    /// Fewest actions from `from` to `to`, see [`MartialGraph::shortest_path`]
+    pub fn shortest_path(&self, from: &str, to: &str) -> Option<Vec<&Edge>> {
+        self.graph().shortest_path(from, to)
+    }
+
+    /// Fewest actions from `from` to `to` joining sequences only at their
+    /// entry points, see [`MartialGraph::canonical_path`]
+    pub fn canonical_path(&self, from: &str, to: &str) -> Option<Vec<&Edge>> {
+        self.graph().canonical_path(from, to)
+    }
+
+    /// Nodes reachable from `state` in `role`, including itself, sorted by id
+    pub fn reachable_from(&self, state: &str, role: &str) -> Vec<Node> {
+        let mut nodes: Vec<Node> = self.graph().reachable_from(&Node::new(state, role)).into_iter().collect();
+        nodes.sort_by_key(Node::id);
+        nodes
+    }
+
+    /// Whether two handles share the same system
+    pub fn ptr_eq(&self, other: &SystemHandle) -> bool {
+        Arc::ptr_eq(&self.shared, &other.shared)
+    }
+}
+
+impl From<MartialSystem> for SystemHandle {
+    fn from(system: MartialSystem) -> Self {
+        SystemHandle::new(system)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loader::{load_sources, SourceFile};
+    use std::thread;
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn test_concurrent_queries() {
+        assert_send_sync::<SystemHandle>();
+
+        let source = "roles { Top, Bottom }\nstate Guard\nstate Mount\nstate Back\n\
+                      sequence Pass:\n    Sweep: Guard[Bottom] -> Mount[Top]\n    Roll: Mount[Top] -> Back[Top]";
+        let handle = SystemHandle::new(load_sources("test", &[SourceFile::new("a.martial", source)]).unwrap());
+
+        thread::scope(|scope| {
+            let workers: Vec<_> = (0..8)
+                .map(|_| {
+                    let handle = handle.clone();
+                    scope.spawn(move || {
+                        let path = handle.shortest_path("Guard", "Back").unwrap();
+                        path.iter().map(|e| e.action.to_string()).collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+            for worker in workers {
+                assert_eq!(worker.join().unwrap(), vec!["Sweep", "Roll"]);
+            }
+        });
+
+        let copy = handle.clone();
+        assert!(copy.ptr_eq(&handle));
+        assert_eq!(copy.statistics().edge_count, 2);
+        let reachable: Vec<String> = handle.reachable_from("Mount", "Top").iter().map(Node::id).collect();
+        assert_eq!(reachable, vec!["Back[Top]", "Mount[Top]"]);
+    }
+}
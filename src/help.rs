@@ -0,0 +1,1084 @@
+//! Long-form help for the `mat` commands and the language
+//!
+//! Every command is described once, in [`COMMANDS`], and that description
+//! renders the usage summary, `mat help <command>` and the `mat(1)` man
+//! page. The syntax page shows the rules of [`crate::grammar`], which the
+//! parser follows, and the keywords of the lexer, so a new declaration
+//! cannot be parsed without being documented.
+
+use crate::grammar::{self, Rule};
+use crate::lexer::KEYWORDS;
+
+/// A `mat` command
+#[derive(Debug)]
+pub struct Command {
+    pub name: &'static str,
+    /// Arguments and flags, as in `mat path <directory> <from> <to>`
+    pub synopsis: &'static str,
+    /// One line, for the usage summary
+    pub summary: &'static str,
+    /// Paragraphs separated by blank lines
+    pub description: &'static str,
+    pub examples: &'static [Example],
+}
+
+/// A run of a command on files, with what it prints
+#[derive(Debug)]
+pub struct Example {
+    /// Files the command reads, as (path, contents)
+    pub files: &'static [(&'static str, &'static str)],
+    pub command: &'static str,
+    /// What the command prints, without the banner; "..." marks a cut
+    pub output: &'static str,
+}
+
+/// A declaration of the language
+#[derive(Debug)]
+pub struct Construct {
+    /// The keyword starting it, one of [`grammar::declarations`]
+    pub keyword: &'static str,
+    pub summary: &'static str,
+    pub example: &'static str,
+}
+
+/// The system most examples run on
+const CLUB: (&str, &str) = (
+    "club/club.martial",
+    "roles { Top, Bottom }
+state Guard
+state Mount
+sequence Sweep:
+    Hip: Guard[Bottom] -> Mount[Top]
+    Grip: Mount[Top] -> Mount[Top]
+",
+);
+
+/// The commands of `mat`, in the order of the usage summary
+pub const COMMANDS: &[Command] = &[
+    Command {
+        name: "validate",
+        synopsis: "<directory> [--strict] [--per-file] [--profile kata|sparring]",
+        summary: "Validate a martial system",
+        description: "Parses every .martial file of the directory and checks the system: undefined states \
+            and roles, role constraints, and sequences whose steps do not chain. Findings that do not make \
+            the system invalid are printed as warnings, each named by its lint.\n\n\
+            With --strict, actions without a glossary term fail validation too. --profile requires the \
+            lints of a style of practice: kata for linear sequences ending in a @final state, sparring for \
+            positions that all reach each other. With --per-file, each file is checked on its own first, \
+            and the file and line declaring every state and sequence are listed.",
+        examples: &[Example {
+            files: &[CLUB],
+            command: "mat validate --per-file club",
+            output: "Files:
+  ✓ club/club.martial
+
+Declared in:
+  state Guard — club/club.martial:2
+  state Mount — club/club.martial:3
+  sequence Sweep — club/club.martial:4
+
+✓ System 'club' is valid across 1 file(s)",
+        }],
+    },
+    Command {
+        name: "validate-all",
+        synopsis: "<root>",
+        summary: "Validate every system under a directory",
+        description: "Treats each directory under the root holding .martial files or a project file as a \
+            system of its own, directories within it being part of it, and validates them all in parallel, \
+            each with its own project config. Prints a table of the systems with their files, states, \
+            sequences, errors and warnings, then the errors, and fails if any system is invalid.",
+        examples: &[Example {
+            files: &[("styles/club/club.martial", CLUB.1), ("styles/judo/judo.martial", "roles { Tori, Uke }\nstate Standing\n")],
+            command: "mat validate-all styles",
+            output: "System  Files  States  Sequences  Errors  Warnings
+club        1       2          1       0         0
+judo        1       1          0       0         3
+
+✓ All 2 systems are valid",
+        }],
+    },
+    Command {
+        name: "lock",
+        synopsis: "<directory> [--check]",
+        summary: "Write martial.lock with the hashes of the sources",
+        description: "Validates the system and writes martial.lock in its directory, with the SHA-256 hash \
+            of every file, shared libraries included, and the fingerprint of the system they resolve to. \
+            --check compares the sources with it instead, and fails if a file was changed, added or \
+            removed, or the system resolves differently.\n\n\
+            Every command reading a system checks the lockfile first when given --locked, or when the \
+            project file sets locked = true, so an artifact is known to come from exactly the locked \
+            sources.",
+        examples: &[],
+    },
+    Command {
+        name: "package",
+        synopsis: "<directory> [-o name.martialpkg]",
+        summary: "Bundle a system into a single .martialpkg file",
+        description: "Validates the system and writes its sources, its graph and a manifest into one file, \
+            <name>.martialpkg by default, to share the system with other gyms. The manifest names the \
+            system and holds the SHA-256 hash of every file and the fingerprint of the system, as mat lock \
+            does. Files of shared libraries are bundled under shared/<library>/.",
+        examples: &[],
+    },
+    Command {
+        name: "info",
+        synopsis: "<package>",
+        summary: "Describe a .martialpkg file",
+        description: "Prints the name, fingerprint and size of the system in a package, and its files with \
+            their hashes and front-matter metadata. Fails if a file does not match the manifest.",
+        examples: &[],
+    },
+    Command {
+        name: "unpack",
+        synopsis: "<package> [-o directory]",
+        summary: "Write the sources of a .martialpkg file",
+        description: "Checks every file of the package against its manifest, then writes them into the \
+            directory, named after the system by default, which must not hold any file yet. A martial.toml \
+            with the settings the system was validated under and a martial.lock are written along, so \
+            mat validate <directory> --locked proves the sources are those packaged.",
+        examples: &[],
+    },
+    Command {
+        name: "add",
+        synopsis: "<directory> <package-or-url> [--as name]",
+        summary: "Add a packaged system as a dependency",
+        description: "Reads a .martialpkg file, fetching http(s) URLs with curl, unpacks it into \
+            vendor/<name> of the system and records it under [dependencies] in its martial.toml. The name is \
+            that of the packaged system unless --as is given. The states, sequences and groups of a dependency \
+            are loaded under its name as a namespace, as wrestling.SingleLeg; its roles are shared. Adding a \
+            dependency again replaces it.",
+        examples: &[],
+    },
+    Command {
+        name: "graph",
+        synopsis: "<directory> [--format json|dot|mermaid|text|tikz|tikz-sequences|flows|html] [--annotate] \
+            [--level L] [--ruleset R] [--role R] [--lang xx] [--merge-edges] [--start S [--depth N]]",
+        summary: "Export graph, as JSON by default",
+        description: "Prints the graph of positions and the actions between them. --level keeps what is \
+            known at a level, --ruleset drops the actions a ruleset forbids, and --role keeps the \
+            transitions starting in one role. --start keeps what can be reached from a state or position, \
+            within --depth transitions if given. --merge-edges turns the edges several sequences share \
+            into one. --annotate colors the unreachable positions, dead ends and states used in only some \
+            of their roles in the dot and html formats.",
+        examples: &[Example {
+            files: &[CLUB],
+            command: "mat graph club --format mermaid",
+            output: "flowchart LR
+  n0[\"Guard<br/>[Bottom]\"]
+  n1[\"Mount<br/>[Top]\"]
+  n0 -->|Hip| n1
+  n1 -->|Grip| n1",
+        }],
+    },
+    Command {
+        name: "tui",
+        synopsis: "<directory>",
+        summary: "Explore states, sequences and transitions in the terminal",
+        description: "Opens an explorer with panes for the positions, the sequences, and the transitions \
+            into and out of the selected position. Tab moves between panes, Enter follows a transition or \
+            marks a sequence, n and p step through the marked sequence, / filters by role:, group:, tag: \
+            or part of a name, and q quits.",
+        examples: &[],
+    },
+    Command {
+        name: "show",
+        synopsis: "<directory> [--sequence S] [--ascii] [--start S [--depth N]] [--level L] [--role R]",
+        summary: "Draw the graph in the terminal",
+        description: "Draws each position as a box listing the actions leaving it, with arrows from an \
+            action to the position it leads to. --sequence draws one sequence and --ascii avoids \
+            box-drawing characters. The filters of mat graph apply too.",
+        examples: &[Example {
+            files: &[CLUB],
+            command: "mat show club",
+            output: "   ┌──────────────────┐
+   │ Guard[Bottom]    │
+┌──┤ Hip → Mount[Top] │
+│  └──────────────────┘
+│  ┌──────────────────┐
+└─▶│ Mount[Top]       │
+   │ Grip (stays)     │
+   └──────────────────┘",
+        }],
+    },
+    Command {
+        name: "dot",
+        synopsis: "<directory> [--annotate] [--level L] [--ruleset R] [--role R] [--lang xx] [--merge-edges] \
+            [--start S [--depth N]]",
+        summary: "Export graph as DOT (Graphviz)",
+        description: "Prints the graph in the DOT language of Graphviz, restricted as with mat graph. \
+            Merged edges are drawn thicker, with their sequences as a tooltip. --annotate colors the \
+            findings of validation, as with mat graph.",
+        examples: &[Example {
+            files: &[CLUB],
+            command: "mat dot club",
+            output: "digraph \"club\" {
+  rankdir=LR;
+  node [shape=box, style=rounded];
+
+  \"Guard[Bottom]\" [label=\"Guard\\n[Bottom]\"];
+  \"Mount[Top]\" [label=\"Mount\\n[Top]\"];
+
+  \"Guard[Bottom]\" -> \"Mount[Top]\" [label=\"Hip\"];
+  \"Mount[Top]\" -> \"Mount[Top]\" [label=\"Grip\"];
+}",
+        }],
+    },
+    Command {
+        name: "stats",
+        synopsis: "<directory> [--start State[Role]]... [--used-only]",
+        summary: "Show graph statistics",
+        description: "Counts nodes, edges and self-loops, and lists source, sink and isolated nodes, the \
+            longest chain, and the depth of each position from an initial one. Every declared state \
+            counts in every role it allows; --used-only keeps the positions sequences go through. Each \
+            --start names a position a bout begins in, and the report then lists what none of them lead to.",
+        examples: &[Example {
+            files: &[CLUB],
+            command: "mat stats club --used-only",
+            output: "Graph Statistics for 'club':
+  Nodes: 2
+  Edges: 2
+  Self-loops: 1
+
+  Source nodes (no incoming edges):
+    - Guard[Bottom]
+
+  Longest chain: 1 actions
+    Guard[Bottom] -Hip-> Mount[Top]
+
+  Depth from initial positions:
+      0  Guard[Bottom]
+      1  Mount[Top]",
+        }],
+    },
+    Command {
+        name: "system",
+        synopsis: "<directory> [--lang xx]",
+        summary: "Export validated system as JSON",
+        description: "Prints the validated system, with its roles, states, sequences and groups in name \
+            order, as JSON.",
+        examples: &[Example {
+            files: &[CLUB],
+            command: "mat system club",
+            output: "{
+  \"name\": \"club\",
+  \"roles\": [
+    \"Bottom\",
+    \"Top\"
+  ],
+  ...",
+        }],
+    },
+    Command {
+        name: "schema",
+        synopsis: "<graph|system>",
+        summary: "Print the JSON Schema of an export format",
+        description: "Prints the JSON Schema of the output of mat graph or mat system, for validating \
+            third-party integrations.",
+        examples: &[],
+    },
+    Command {
+        name: "doc",
+        synopsis: "<directory> [-o file.md] [--lang xx]",
+        summary: "Render the system as a Markdown handbook",
+        description: "Renders the roles, a section per group and state with the techniques entering and \
+            leaving it, and a step table per sequence. Writes to stdout unless -o is given.",
+        examples: &[Example {
+            files: &[CLUB],
+            command: "mat doc club",
+            output: "# club
+
+## Roles
+
+- Bottom
+- Top
+
+## States
+
+### Guard
+
+Leads to:
+
+- **Hip** from Guard[Bottom] to Mount[Top] _(Sweep)_
+...",
+        }],
+    },
+    Command {
+        name: "flashcards",
+        synopsis: "<directory> [-o file.tsv]",
+        summary: "Export glossary terms as flashcards",
+        description: "Writes the glossary as tab-separated flashcards: the term, then its definition and \
+            the sequences using it, ready to import into Anki.",
+        examples: &[],
+    },
+    Command {
+        name: "site",
+        synopsis: "<directory> [-o site/] [--lang xx]",
+        summary: "Generate a static HTML site",
+        description: "Generates an index and a page per state and per sequence, with inline SVG diagrams, \
+            all cross-linked. The site goes to site/ unless -o is given.",
+        examples: &[],
+    },
+    Command {
+        name: "import",
+        synopsis: "[--format csv|dot|scxml] <file> -o <directory> [--force]",
+        summary: "Convert to .martial files",
+        description: "Converts technique chains kept elsewhere into .martial files. The format is guessed \
+            from the extension unless --format is given, and the result is validated before anything is \
+            written. Existing files are only overwritten with --force. Names such as knee slice become \
+            KneeSlice.",
+        examples: &[Example {
+            files: &[(
+                "sweeps.csv",
+                "sequence,action,from_state,from_role,to_state,to_role\nSweep,hip bump,closed guard,Bottom,mount,Top\n",
+            )],
+            command: "mat import sweeps.csv -o club",
+            output: "Wrote club/roles.martial
+Wrote club/states.martial
+Wrote club/sequences.martial
+✓ Imported system is valid",
+        }],
+    },
+    Command {
+        name: "gen-synthetic",
+        synopsis: "[--states 1000] [--sequences 5000] [--seed 1] -o <directory>",
+        summary: "Generate a large system for benchmarks",
+        description: "Generates a large valid system for benchmarks and load tests: random walks of one to \
+            four steps over numbered states, in files of 250 sequences. The same seed always produces the \
+            same files.",
+        examples: &[],
+    },
+    Command {
+        name: "rename",
+        synopsis: "<directory> <kind> <old> <new>",
+        summary: "Rename a role, state, sequence, action or group",
+        description: "Renames a role, state, sequence, action or group and every reference to it. Only the \
+            renamed names are edited, so comments and layout are kept. Renaming a role or action onto an \
+            existing one merges them. Nothing is written if the result does not validate.",
+        examples: &[Example {
+            files: &[CLUB],
+            command: "mat rename club state Mount FullMount",
+            output: "  club/club.martial (4 occurrences)
+✓ Renamed state 'Mount' to 'FullMount' in 1 files",
+        }],
+    },
+    Command {
+        name: "extract-group",
+        synopsis: "<directory> <group> [-o file]",
+        summary: "Move a group into its own file",
+        description: "Moves a group, its states and every sequence that only touches them into a new file, \
+            <group>.martial in the directory unless -o is given. Declarations are moved as written with the \
+            comments above them. Files left empty are removed, and nothing is written if the result does not \
+            validate.",
+        examples: &[],
+    },
+    Command {
+        name: "migrate",
+        synopsis: "<directory> [--check]",
+        summary: "Rewrite files to the latest file format version",
+        description: "Rewrites the constructs of older file format versions and updates the martial N \
+            pragma, keeping comments and layout elsewhere. With --check, the files that need migrating are \
+            listed and the command fails if there are any.",
+        examples: &[],
+    },
+    Command {
+        name: "lesson",
+        synopsis: "<directory> --focus State [--minutes 60] [-o file.md] [--lang xx]",
+        summary: "Generate a timed lesson plan",
+        description: "Picks the sequences closest to the focus state until the time runs out, ordered from \
+            those leading into the focus to those finishing from it, and spends any time left on \
+            positional sparring. A sequence takes its @duration, or 3 minutes per step.",
+        examples: &[Example {
+            files: &[CLUB],
+            command: "mat lesson club --focus Mount --minutes 30",
+            output: "# Lesson: Mount
+
+30 minutes
+
+1. **0:00** Sweep (6 min)
+   - Hip: Guard[Bottom] → Mount[Top]
+   - Grip: Mount[Top] → Mount[Top]
+2. **0:06** Positional sparring from Mount (24 min)",
+        }],
+    },
+    Command {
+        name: "storyboard",
+        synopsis: "<directory> <sequence> [--format svg|md] [-o file.svg|file.md] [--lang xx]",
+        summary: "Draw a sequence as a timeline of stances",
+        description: "Draws the stances of one sequence from left to right, with each action numbered on \
+            the arrow between them, as an SVG drawing or a Markdown table. The format is taken from the \
+            extension of -o unless --format is given, and is svg by default. When \
+            the steps have @turn or @advance annotations, the floor pattern is drawn too.",
+        examples: &[],
+    },
+    Command {
+        name: "embusen",
+        synopsis: "<directory> <sequence>... [--format text|json]",
+        summary: "Check that a kata's floor pattern returns to its start",
+        description: "Walks the steps of the sequences, each a selector matching sequences in declaration \
+            order, following their @turn and @advance annotations, and prints where each step leaves the \
+            performer and the way they face. Exits with an error if the pattern does not end at its \
+            starting point.",
+        examples: &[],
+    },
+    Command {
+        name: "order",
+        synopsis: "<directory> [-o file.md]",
+        summary: "Order the positions into a teaching syllabus",
+        description: "Orders the states so that each comes after the states leading to it and those it \
+            @requires, lower levels first, then the states closest to an initial position. States leading \
+            to each other are taught together.",
+        examples: &[Example {
+            files: &[CLUB],
+            command: "mat order club",
+            output: "# Curriculum: club
+
+1. **Guard** — depth 0
+2. **Mount** — depth 1",
+        }],
+    },
+    Command {
+        name: "log",
+        synopsis: "add [directory] \"From[Role] -> To[Role] via Action\"",
+        summary: "Record a sparring transition",
+        description: "Appends a transition from live sparring to the directory's sparring.log, dated today \
+            unless the entry starts with a YYYY-MM-DD date. Transitions the system does not declare are \
+            logged with a warning.",
+        examples: &[Example {
+            files: &[CLUB],
+            command: "mat log add club \"Guard[Bottom] -> Mount[Top] via Hip\"",
+            output: "✓ Logged to club/sparring.log",
+        }],
+    },
+    Command {
+        name: "heatmap",
+        synopsis: "<directory> [--format dot|json] [--log file] [-o file]",
+        summary: "Overlay sparring usage on the graph",
+        description: "Colors the edges of the graph by how often sparring.log, or --log, records them, \
+            dashing the unused ones. JSON lists each edge with its count, plus the undeclared transitions.",
+        examples: &[],
+    },
+    Command {
+        name: "balance",
+        synopsis: "<directory> [--format text|json]",
+        summary: "Report attacking versus defending sequences",
+        description: "Counts the sequences of each @kind, then tallies those attacking (sweep, submission, \
+            pass or @tag(attack)), defending (escape or @tag(defense)) and others by the role and the groups \
+            of the positions they start from. Flags every position attacked from the other side of its state \
+            that no sequence escapes from.",
+        examples: &[],
+    },
+    Command {
+        name: "matrix",
+        synopsis: "<directory> [--by group|state] [--format markdown|csv|json] [-o file]",
+        summary: "Tabulate which groups reach which",
+        description: "Prints, for every pair of groups, the fewest actions leading from a position of the \
+            first to one of the second, in any role; groups sharing a state are at 0, and a cell is empty, \
+            or — in Markdown, when no path leads there. --by state has a row and a column per state instead.",
+        examples: &[],
+    },
+    Command {
+        name: "bottlenecks",
+        synopsis: "<directory> [--from S --to S] [--format text|json]",
+        summary: "Find the transitions a game plan depends on",
+        description: "Lists the bridges of the graph: transitions whose removal splits the positions around \
+            them in two, ignoring direction, with the positions of the smaller side. With --from and --to, \
+            states or positions, prints instead the fewest transitions whose removal leaves no path from one \
+            to the other. The same action declared by several sequences counts once, and the opponent's side \
+            of a step is left out.",
+        examples: &[],
+    },
+    Command {
+        name: "compare",
+        synopsis: "<directory> <directory> [--format text|dot|json] [-o file]",
+        summary: "Compare the graphs of two systems",
+        description: "Matches the positions and transitions of two systems, a transition being an action \
+            from one position to another whichever sequence declares it. Prints how many they share and how \
+            many only one has, with the Jaccard similarity of each, then what each has alone. DOT draws both \
+            graphs merged, with what only the first has in blue and what only the second has in red.",
+        examples: &[],
+    },
+    Command {
+        name: "next",
+        synopsis: "<directory> State[Role] [--log file] [--limit 5]",
+        summary: "Rank the likely next actions",
+        description: "Ranks the actions from a position by how often the sparring log records them, with \
+            an estimated chance for each. Actions never logged keep a small chance.",
+        examples: &[Example {
+            files: &[CLUB],
+            command: "mat next club Guard[Bottom]",
+            output: "From Guard[Bottom] (0 logged transitions):
+  1. Hip -> Mount[Top]  100%  (0 logged; Sweep)",
+        }],
+    },
+    Command {
+        name: "path",
+        synopsis: "<directory> <from> <to> [--canonical | --k N] [--via P]... [--avoid P]...",
+        summary: "Show the fewest actions between two positions",
+        description: "Prints the fewest actions from one state or position to another. With --canonical, \
+            the path only joins sequences at their first step or an @entry step and otherwise continues \
+            the sequence it is in. --k lists up to N routes that never revisit a position, shortest first, \
+            then the actions every route takes. --via makes routes pass through a position matching each \
+            pattern in order and --avoid keeps them off positions matching any. Positions are selectors: \
+            a node id such as HalfGuard[Top], a state in any role, a glob of them with * and ?, or a \
+            regular expression between slashes such as /^Half/.",
+        examples: &[Example {
+            files: &[CLUB],
+            command: "mat path club Guard[Bottom] Mount[Top]",
+            output: "From Guard[Bottom] to Mount[Top] in 1 actions:
+  1. Hip (Sweep): Guard[Bottom] -> Mount[Top]",
+        }],
+    },
+    Command {
+        name: "reachable",
+        synopsis: "<directory> <from> [--kind K]",
+        summary: "List the sequences that can be joined from a position",
+        description: "Lists the sequences that can be joined, at their first step or an @entry step, from \
+            a state or position. --kind keeps those of one @kind.",
+        examples: &[Example {
+            files: &[CLUB],
+            command: "mat reachable club Guard[Bottom]",
+            output: "Sequences reachable from Guard[Bottom] (1):
+  - Sweep",
+        }],
+    },
+    Command {
+        name: "position",
+        synopsis: "<directory> <state>",
+        summary: "Profile a position: its roles, groups, actions in and out and centrality",
+        description: "Prints everything about a state when preparing a class on it: the roles it allows, \
+            the groups it belongs to and whether it is @final, then for each role it is used in, every \
+            action entering and leaving it with its sequence, whether it is an initial or terminal \
+            position, and its betweenness and closeness centrality.",
+        examples: &[Example {
+            files: &[CLUB],
+            command: "mat position club Mount",
+            output: "Position Mount
+  Roles: Bottom, Top
+
+Mount[Top] (terminal)
+  Incoming (2):
+    Hip (Sweep) from Guard[Bottom]
+    Grip (Sweep) from Mount[Top]
+  Outgoing (1):
+    Grip (Sweep) to Mount[Top]
+  Centrality: betweenness 0.00, closeness 0.00",
+        }],
+    },
+    Command {
+        name: "flows",
+        synopsis: "<directory>",
+        summary: "Show how sequences lead into each other",
+        description: "Lists, for each sequence, the sequences that can be joined at their first step or an \
+            @entry step where one of its actions leads, and where: at the position it ends at, or at one it \
+            passes through. Reactions and onfail outcomes are left out. mat graph --format flows draws the \
+            same as DOT.",
+        examples: &[Example {
+            files: &[(
+                "club/club.martial",
+                "roles { Top, Bottom }
+state Guard
+state Mount
+state Back
+sequence Sweep:
+    Hip: Guard[Bottom] -> Mount[Top]
+sequence Take:
+    Roll: Mount[Top] -> Back[Top]
+",
+            )],
+            command: "mat flows club",
+            output: "Sequence flows (1):
+  Sweep -> Take at Mount[Top]",
+        }],
+    },
+    Command {
+        name: "describe",
+        synopsis: "<directory> <name>",
+        summary: "Describe a state, role, sequence, group or action",
+        description: "Prints what a name is, where it is declared and how it is used: the roles a state \
+            allows and the groups it belongs to, the states of a role or group, the steps of a sequence or \
+            action, and the transitions into and out of the positions of a state, role or group. Language \
+            server hovers show the same description.",
+        examples: &[Example {
+            files: &[CLUB],
+            command: "mat describe club Mount",
+            output: "state Mount (club/club.martial:3)
+  Roles: Bottom, Top
+  Transitions: 2 in, 1 out
+  Steps:
+    Hip: Guard[Bottom] -> Mount[Top] (Sweep)
+    Grip: Mount[Top] -> Mount[Top] (Sweep)",
+        }],
+    },
+    Command {
+        name: "refs",
+        synopsis: "<directory> <name>",
+        summary: "List every use of a state, role, group, sequence or action",
+        description: "Lists where a name is declared and used, each with its file, line and column, how it \
+            is used and the declaration it appears in: steps starting or ending at a state or role, steps of \
+            an action, the roles a state allows, group members and rulesets. The language server answers \
+            find-references requests from the same list.",
+        examples: &[Example {
+            files: &[CLUB],
+            command: "mat refs club Mount",
+            output: "References to Mount (4):
+  club/club.martial:3:7: declaration in state Mount
+  club/club.martial:5:27: to in sequence Sweep
+  club/club.martial:6:11: from in sequence Sweep
+  club/club.martial:6:25: to in sequence Sweep",
+        }],
+    },
+    Command {
+        name: "impact",
+        synopsis: "<directory> remove <state|sequence> <name> [--format text|json]",
+        summary: "Report what removing a state or sequence would break",
+        description: "Lists, without editing any file, the sequences with a step at a removed state, the \
+            groups listing it, the rulesets forbidding a removed sequence, the positions left without any \
+            transition and the routes between the remaining positions that would disappear, then every \
+            reference with its file, line and column. Removing a sequence removes its mirror too.",
+        examples: &[],
+    },
+    Command {
+        name: "train",
+        synopsis: "<directory> --start State[Role] [--random [--steps N]] [--seed N]",
+        summary: "Walk the graph interactively",
+        description: "Shows the current position and its actions, then follows the one picked: a number, \
+            r for random, b to go back, q to quit. --random walks on its own for --steps actions, 10 by \
+            default, and --seed makes random choices repeatable.",
+        examples: &[Example {
+            files: &[CLUB],
+            command: "mat train club --start Guard[Bottom] --random --steps 2 --seed 1",
+            output: "Flow (2 actions):
+  Guard[Bottom] -> Mount[Top]: Hip
+  Mount[Top] -> Mount[Top]: Grip",
+        }],
+    },
+    Command {
+        name: "build",
+        synopsis: "[directory]",
+        summary: "Run the exports declared in martial.toml or .martialrc",
+        description: "Runs every [[export]] of the project configuration. An export with a snippet \
+            replaces the region between <!-- mat:NAME --> markers of its output. Files already up to date \
+            are left untouched.",
+        examples: &[],
+    },
+    Command {
+        name: "export",
+        synopsis: "<directory> <preset>... | --all",
+        summary: "Run named export presets",
+        description: "Runs the exports of the project configuration that have a name, each on its own or \
+            all of them with --all.",
+        examples: &[],
+    },
+    Command {
+        name: "test",
+        synopsis: "<directory> [--update]",
+        summary: "Check fixtures against their .expect.json and snapshot files",
+        description: "Checks every fixture under the directory, a .martial file or a directory of them, \
+            against the .expect.json file next to it, and against its .snapshot.json golden file if there \
+            is one. --update writes the snapshots of every fixture that loads.",
+        examples: &[],
+    },
+    Command {
+        name: "config",
+        synopsis: "show [directory] [--strict] [--compat] [--duplicates P] [--kinds a,b] [--ruleset R] \
+            [--lints a,b] [--deny a,b] [--include g] [--exclude g] [--shared d] [--define f] [--locked]",
+        summary: "Print the effective configuration",
+        description: "Prints every setting, resolved from the defaults, martial.toml or .martialrc, the \
+            MAT_* environment variables and the flags, with where its value came from.",
+        examples: &[Example {
+            files: &[CLUB],
+            command: "mat config show club",
+            output: "source = \".\"  # default
+strict = false  # default
+...",
+        }],
+    },
+    Command {
+        name: "serve",
+        synopsis: "<directory> [--port 8080] [--host 127.0.0.1]",
+        summary: "Serve the system over an HTTP JSON API",
+        description: "Validates the system once, then answers GET /graph, /stats and /paths, POST \
+            /validate, and GraphQL queries on /graphql.",
+        examples: &[],
+    },
+    Command {
+        name: "fix",
+        synopsis: "<directory> [--yes]",
+        summary: "Apply the corrections suggested for validation errors",
+        description: "Shows each validation error with its suggested corrections and the lines they would \
+            change, writes the chosen one, and validates again. With --yes, the first suggestion of every \
+            error is applied without asking.",
+        examples: &[],
+    },
+    Command {
+        name: "grammar",
+        synopsis: "[--format ebnf|textmate|tree-sitter] [-o file]",
+        summary: "Print the grammar of the language as EBNF",
+        description: "Prints the grammar the parser follows, as EBNF, for the authors of editor plugins and \
+            other parsers. It is generated from the same description the parser reports its expectations \
+            from, so it cannot fall behind the language.\n\n\
+            --format textmate prints a TextMate grammar for syntax highlighting, as JSON, and --format \
+            tree-sitter a tree-sitter grammar.js of the latest file format version.",
+        examples: &[Example {
+            files: &[],
+            command: "mat grammar",
+            output: "program          ::= [ version_pragma ] declaration+
+
+version_pragma   ::= \"martial\" NUMBER
+...",
+        }],
+    },
+    Command {
+        name: "lsp",
+        synopsis: "",
+        summary: "Run the language server over stdio",
+        description: "Runs a Language Server Protocol server over stdio, with diagnostics, go-to-definition, \
+            hover and completion for .martial files.",
+        examples: &[],
+    },
+    Command {
+        name: "help",
+        synopsis: "[command|syntax] [--man]",
+        summary: "Show the help of a command, or of the language",
+        description: "Prints the usage summary, the help of a command with examples, or with syntax the \
+            declarations of the language. --man prints the mat(1) manual page instead, in roff.",
+        examples: &[],
+    },
+];
+
+/// The declarations of the language, in the order of [`grammar::declarations`]
+pub const SYNTAX: &[Construct] = &[
+    Construct {
+        keyword: "roles",
+        summary: "Declares the roles a participant can be in. Declarations in several files are merged.",
+        example: "roles { Top, Bottom }",
+    },
+    Construct {
+        keyword: "state",
+        summary: "Declares a position, optionally restricted to some roles. Version 1 files write the \
+            roles as roles { ... } instead.",
+        example: "martial 2\nstate Mount [Top, Bottom] @dominance(8)",
+    },
+    Construct {
+        keyword: "sequence",
+        summary: "Declares a chain of actions, each from one position to the next. onfail gives where a \
+            failed action leaves its performer, / the opponent's side of the step, and xN after a step \
+            repeats it N times in a row. alternating(A, B) before sequence makes partners take turns, \
+            each step starting in the role whose turn it is and following on that role's previous step.",
+        example: "sequence Sweep @kind(sweep):\n    Hip: Guard[Bottom] -> Mount[Top] onfail -> Guard[Bottom]",
+    },
+    Construct {
+        keyword: "group",
+        summary: "Gathers related states.",
+        example: "group Guards { ClosedGuard, HalfGuard }",
+    },
+    Construct {
+        keyword: "levels",
+        summary: "Declares the grades, lowest first, that @level annotations refer to.",
+        example: "levels { White, Blue, Purple }",
+    },
+    Construct {
+        keyword: "term",
+        summary: "Defines an action in the glossary.",
+        example: "term Hip \"Bridge onto the shoulder to sweep\"",
+    },
+    Construct {
+        keyword: "ruleset",
+        summary: "Names the actions a competition ruleset forbids.",
+        example: "ruleset IBJJF {\n    forbid HeelHook, Slam\n}",
+    },
+    Construct {
+        keyword: "template",
+        summary: "Declares steps with parameters, which sequences invoke as Name(arguments).",
+        example: "template Step(action, from, to) = action: from -> to",
+    },
+    Construct {
+        keyword: "mirror",
+        summary: "Pairs roles or name suffixes, which a mirrored sequence swaps to generate its mirror.",
+        example: "mirror suffixes { Left <-> Right }",
+    },
+    Construct {
+        keyword: "assert",
+        summary: "States that a position can, or cannot, be reached from another.",
+        example: "assert reachable Guard[Bottom] -> Mount[Top]",
+    },
+];
+
+/// The annotations, with what they can follow and what they mean
+pub const ANNOTATIONS: &[(&str, &str, &str)] = &[
+    ("@level(L)", "state, sequence", "the level it is taught at"),
+    ("@requires(S, ...)", "state", "states taught before it"),
+    ("@dominance(N)", "state", "how dominant the position is"),
+    ("@final", "state", "a position a kata ends in"),
+    ("@core", "state", "a position sparring must connect"),
+    ("@kind(K)", "sequence", "sweep, submission, pass, escape or a configured kind"),
+    ("@duration(N)", "sequence", "minutes it takes in a lesson"),
+    ("@attack", "sequence", "its steps should not lose dominance"),
+    ("@video(URL)", "sequence, step", "a video showing it"),
+    ("@weight(N)", "step", "how often it is chosen by mat train"),
+    ("@entry", "step", "a step a sequence can be joined at"),
+    ("@turn(90L)", "step", "a turn before it, for the floor pattern of a kata"),
+    ("@advance(N)", "step", "steps moved forward after turning, 1 without N"),
+    ("@tag(T, ...)", "any", "free-form tags"),
+    ("@label(xx: \"...\")", "state, step", "display names by language"),
+];
+
+/// The command named `name`
+pub fn command(name: &str) -> Option<&'static Command> {
+    COMMANDS.iter().find(|command| command.name == name)
+}
+
+/// The usage summary, a line per command
+pub fn usage() -> String {
+    let mut out = String::from("Usage:\n");
+    for command in COMMANDS {
+        let line = format!("mat {} {}", command.name, command.synopsis);
+        out.push_str(&format!("  {:<28} # {}\n", line.trim_end(), command.summary));
+    }
+    out.push_str(
+        "\nCommands reading a system accept --compat to allow syntax removed from the version of a file, \
+         with a warning,\nand --duplicates error|first-wins|merge-roles to choose what a second declaration \
+         of a state does,\n--define flag, once per flag, to keep the conditional sections of flag, and --locked to check \
+         martial.lock first\n\nRun mat help <command> for the details of a command, and mat help syntax for \
+         the language\n",
+    );
+    out
+}
+
+/// The long-form help of `command`
+pub fn render(command: &Command) -> String {
+    let mut out = format!("mat {} — {}\n\nUsage:\n  {}\n\n", command.name, command.summary, invocation(command));
+    for paragraph in command.description.split("\n\n") {
+        out.push_str(&wrap(paragraph, "  "));
+        out.push('\n');
+    }
+    for example in command.examples {
+        out.push_str("Example:\n");
+        for (path, contents) in example.files {
+            out.push_str(&format!("  {}:\n", path));
+            for line in contents.lines() {
+                out.push_str(&format!("    {}\n", line).replace("    \n", "\n"));
+            }
+            out.push('\n');
+        }
+        out.push_str(&format!("  $ {}\n", example.command));
+        for line in example.output.lines() {
+            out.push_str(format!("  {}", line).trim_end());
+            out.push('\n');
+        }
+        out.push('\n');
+    }
+    out.truncate(out.trim_end().len() + 1);
+    out
+}
+
+/// The syntax page
+pub fn syntax() -> String {
+    let mut out = String::from(
+        "The Martial language\n\n\
+         A system is the .martial files of a directory, each a sequence of declarations, optionally\n\
+         preceded by a martial N pragma giving the file format version. Comments start with //.\n\n",
+    );
+    // A rule used by several declarations is shown with the first one
+    let mut shown = vec!["IDENTIFIER", "NUMBER", "STRING", "annotation", "annotation_arg", "annotation_value"];
+    for keyword in grammar::declarations() {
+        let Some(construct) = SYNTAX.iter().find(|construct| construct.keyword == keyword) else {
+            continue;
+        };
+        out.push_str(&format!("{}\n", keyword));
+        let mut rules = vec![format!("{}_decl", keyword)];
+        let mut i = 0;
+        while let Some(rule) = rules.get(i).and_then(|name| grammar::rule_named(name)) {
+            for name in grammar::references(rule) {
+                if !shown.contains(&name) && !rules.iter().any(|r| r == name) {
+                    rules.push(name.to_string());
+                }
+            }
+            out.push_str(&indented(rule));
+            i += 1;
+        }
+        shown.extend(rules.iter().filter_map(|name| grammar::rule_named(name)).map(|rule| rule.name));
+        out.push('\n');
+        out.push_str(&wrap(construct.summary, "  "));
+        out.push('\n');
+        for line in construct.example.lines() {
+            out.push_str(&format!("    {}\n", line));
+        }
+        out.push('\n');
+    }
+    out.push_str("Annotations\n");
+    for name in ["annotation", "annotation_arg", "annotation_value"] {
+        out.push_str(&indented(grammar::rule_named(name).expect("annotations are in the grammar")));
+    }
+    out.push('\n');
+    for (annotation, targets, meaning) in ANNOTATIONS {
+        out.push_str(&format!("  {:<20} {:<16} {}\n", annotation, targets, meaning));
+    }
+    out.push_str(&format!("\nKeywords\n{}", wrap(&KEYWORDS.join(", "), "  ")));
+    out.push_str("\nRun mat grammar for the complete grammar, with the tokens.\n");
+    out
+}
+
+/// `rule` as EBNF, indented
+fn indented(rule: &Rule) -> String {
+    rule.to_string().lines().map(|line| format!("  {}\n", line)).collect()
+}
+
+/// The `mat(1)` manual page, in roff
+pub fn man_page() -> String {
+    let mut out = String::from(
+        ".TH MAT 1\n.SH NAME\nmat \\- validate, explore and export martial systems\n\
+         .SH SYNOPSIS\n.B mat\n.I command\n[\\fIarguments\\fR]\n\
+         .SH DESCRIPTION\nmat reads a martial system, the .martial files of a directory, and validates, \
+         queries or exports it.\n.SH COMMANDS\n",
+    );
+    for command in COMMANDS {
+        out.push_str(&format!(".SS {}\n", roff(&invocation(command))));
+        for paragraph in command.description.split("\n\n") {
+            out.push_str(&format!("{}\n.PP\n", roff(paragraph)));
+        }
+        for example in command.examples {
+            out.push_str(".nf\n.RS\n");
+            for (path, contents) in example.files {
+                out.push_str(&format!("{}:\n", roff(path)));
+                for line in contents.lines() {
+                    out.push_str(&format!("    {}\n", roff(line)));
+                }
+                out.push('\n');
+            }
+            out.push_str(&format!("$ {}\n", roff(example.command)));
+            for line in example.output.lines() {
+                out.push_str(&format!("{}\n", roff(line)));
+            }
+            out.push_str(".RE\n.fi\n");
+        }
+    }
+    out.push_str(".SH SYNTAX\n.nf\n");
+    for line in syntax().lines() {
+        out.push_str(&format!("{}\n", roff(line)));
+    }
+    out.push_str(".fi\n");
+    out
+}
+
+fn invocation(command: &Command) -> String {
+    format!("mat {} {}", command.name, command.synopsis).trim_end().to_string()
+}
+
+/// `text` wrapped at 80 columns, each line starting with `indent`
+fn wrap(text: &str, indent: &str) -> String {
+    let mut out = String::new();
+    let mut line = String::from(indent);
+    for word in text.split_whitespace() {
+        if line.len() > indent.len() && line.chars().count() + 1 + word.chars().count() > 80 {
+            out.push_str(&line);
+            out.push('\n');
+            line = String::from(indent);
+        }
+        if line.len() > indent.len() {
+            line.push(' ');
+        }
+        line.push_str(word);
+    }
+    out.push_str(&line);
+    out.push('\n');
+    out
+}
+
+/// `text` escaped for roff
+fn roff(text: &str) -> String {
+    let text = text.replace('\\', "\\e").replace('-', "\\-");
+    if text.starts_with('.') || text.starts_with('\'') {
+        format!("\\&{}", text)
+    } else {
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::loader::{load_sources, SourceFile};
+    use crate::parser::Parser;
+
+    #[test]
+    fn test_commands() {
+        for entry in COMMANDS {
+            assert_eq!(COMMANDS.iter().filter(|c| c.name == entry.name).count(), 1, "{} is listed twice", entry.name);
+            for example in entry.examples {
+                assert!(format!("{} ", example.command).starts_with(&format!("mat {} ", entry.name)), "{}", example.command);
+                // Examples of systems read a valid one
+                let sources: Vec<SourceFile> = example
+                    .files
+                    .iter()
+                    .filter(|(path, _)| path.ends_with(".martial"))
+                    .map(|(path, contents)| SourceFile::new(*path, *contents))
+                    .collect();
+                if !sources.is_empty() {
+                    load_sources("club", &sources).unwrap();
+                }
+            }
+        }
+        assert!(usage().contains("  mat path <directory> <from> <to> [--canonical | --k N] [--via P]... [--avoid P]... # Show the fewest actions"));
+        assert!(usage().contains("  mat lsp                      # Run the language server over stdio\n"));
+    }
+
+    #[test]
+    fn test_render() {
+        assert_eq!(
+            render(command("reachable").unwrap()),
+            "mat reachable — List the sequences that can be joined from a position\n\n\
+             Usage:\n  mat reachable <directory> <from> [--kind K]\n\n\
+             \x20 Lists the sequences that can be joined, at their first step or an @entry step,\n\
+             \x20 from a state or position. --kind keeps those of one @kind.\n\n\
+             Example:\n  club/club.martial:\n    roles { Top, Bottom }\n    state Guard\n    state Mount\n\
+             \x20   sequence Sweep:\n        Hip: Guard[Bottom] -> Mount[Top]\n        Grip: Mount[Top] -> Mount[Top]\n\n\
+             \x20 $ mat reachable club Guard[Bottom]\n  Sequences reachable from Guard[Bottom] (1):\n    - Sweep\n"
+        );
+        assert!(render(command("lsp").unwrap()).starts_with("mat lsp — Run the language server over stdio\n\nUsage:\n  mat lsp\n\n"));
+    }
+
+    #[test]
+    fn test_syntax() {
+        let keywords: Vec<&str> = SYNTAX.iter().map(|construct| construct.keyword).collect();
+        assert_eq!(keywords, grammar::declarations());
+        for construct in SYNTAX {
+            let tokens = Lexer::new(construct.example).tokenize().unwrap();
+            Parser::new(tokens).parse().unwrap_or_else(|e| panic!("{}: {}", construct.keyword, e));
+        }
+        let page = syntax();
+        assert!(page.contains(
+            "assert\n  assert_decl      ::= \"assert\" assertion state_ref \"->\" state_ref\n  \
+             assertion        ::= \"reachable\"\n                     | \"no_path\"\n\n"
+        ));
+        assert!(page.contains("  @entry               step             a step a sequence can be joined at\n"));
+        assert!(page.contains("  mirror, mirrored, alternating, suffixes, assert, onfail\n"));
+    }
+
+    #[test]
+    fn test_annotations() {
+        // Every annotation of the syntax page is accepted where it says
+        let source = "martial 2\nroles { Top, Bottom }\nlevels { White }\n\
+            state Guard @level(White) @core @tag(open) @label(fr: \"Garde\")\n\
+            state Mount @dominance(8) @requires(Guard) @final\n\
+            sequence Sweep @level(White) @kind(sweep) @duration(5) @attack @video(\"https://example.com/sweep\"):\n\
+            \x20   Hip: Guard[Bottom] -> Mount[Top] @weight(2) @entry @video(\"https://example.com/hip\") @label(fr: \"Hanche\") \
+            @turn(90L) @advance";
+        load_sources("club", &[SourceFile::new("a.martial", source)]).unwrap();
+        for (annotation, _, _) in ANNOTATIONS {
+            let name = annotation.split('(').next().unwrap();
+            assert!(source.contains(name), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_man_page() {
+        let page = man_page();
+        assert!(page.starts_with(".TH MAT 1\n.SH NAME\nmat \\- validate"));
+        assert!(page.contains(".SS mat path <directory> <from> <to> [\\-\\-canonical | \\-\\-k N] [\\-\\-via P]... [\\-\\-avoid P]...\n"));
+        assert!(page.contains("  \"Guard[Bottom]\" [label=\"Guard\\en[Bottom]\"];\n"));
+        assert_eq!(roff(".hidden"), "\\&.hidden");
+    }
+}
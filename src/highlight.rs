@@ -0,0 +1,245 @@
+//! Syntax highlighting grammars for editors
+//!
+//! The TextMate grammar takes its keywords and operators from the lexer and
+//! [`crate::grammar`], and the tree-sitter grammar is a translation of the
+//! grammar's rules, so both follow the language as keywords are added.
+
+use crate::ast::LATEST_VERSION;
+use crate::grammar::{self, Expr, GRAMMAR};
+use crate::lexer::KEYWORDS;
+use serde_json::{json, Map, Value};
+
+/// Identifiers, as read by the lexer
+const IDENTIFIER: &str = r"[\p{L}_][\p{L}\p{N}_]*(?:\.[\p{L}_][\p{L}\p{N}_]*)*";
+
+/// Symbols that separate rather than operate
+const PUNCTUATION: [&str; 8] = ["{", "}", "[", "]", "(", ")", ",", ":"];
+
+/// `words` as a regular expression alternation
+fn alternation(words: &[&str]) -> String {
+    let escaped: Vec<String> = words
+        .iter()
+        .map(|word| {
+            word.chars()
+                .map(|ch| if r"\^$.|?*+()[]{}/".contains(ch) { format!("\\{}", ch) } else { ch.to_string() })
+                .collect()
+        })
+        .collect();
+    escaped.join("|")
+}
+
+/// A TextMate grammar for `.martial` files, as JSON
+pub fn textmate() -> String {
+    let declarations = grammar::declarations();
+    let (declaration_words, other_words): (Vec<&str>, Vec<&str>) =
+        KEYWORDS.iter().partition(|word| declarations.contains(word));
+    let operators: Vec<&str> = grammar::literals()
+        .into_iter()
+        .filter(|literal| !literal.starts_with(|ch: char| ch.is_alphanumeric() || ch == '_'))
+        .filter(|literal| !PUNCTUATION.contains(literal) && *literal != "@")
+        .collect();
+    let mut repository = Map::new();
+    repository.insert(
+        "comment".to_string(),
+        json!({ "name": "comment.line.double-slash.martial", "match": "//.*$" }),
+    );
+    repository.insert(
+        "pragma".to_string(),
+        json!({
+            "match": "^\\s*(martial)\\s+(\\d+)\\b",
+            "captures": {
+                "1": { "name": "keyword.other.pragma.martial" },
+                "2": { "name": "constant.numeric.version.martial" }
+            }
+        }),
+    );
+    repository.insert(
+        "string".to_string(),
+        json!({
+            "name": "string.quoted.double.martial",
+            "begin": "\"",
+            "end": "\"",
+            "patterns": [{ "name": "constant.character.escape.martial", "match": "\\\\[\"\\\\]" }]
+        }),
+    );
+    repository.insert(
+        "annotation".to_string(),
+        json!({
+            "match": format!("(@)({})", IDENTIFIER),
+            "captures": {
+                "1": { "name": "punctuation.definition.annotation.martial" },
+                "2": { "name": "entity.name.function.annotation.martial" }
+            }
+        }),
+    );
+    repository.insert(
+        "assertion".to_string(),
+        json!({
+            "match": format!("\\b(assert)\\s+({})\\b", alternation(&grammar::choices("assertion"))),
+            "captures": {
+                "1": { "name": "storage.type.declaration.martial" },
+                "2": { "name": "support.constant.assertion.martial" }
+            }
+        }),
+    );
+    repository.insert(
+        "keyword".to_string(),
+        json!({
+            "patterns": [
+                { "name": "storage.type.declaration.martial", "match": format!("\\b({})\\b", alternation(&declaration_words)) },
+                { "name": "keyword.other.martial", "match": format!("\\b({})\\b", alternation(&other_words)) }
+            ]
+        }),
+    );
+    repository.insert(
+        "state-ref".to_string(),
+        json!({
+            "begin": format!("({})\\s*(\\[)", IDENTIFIER),
+            "beginCaptures": {
+                "1": { "name": "entity.name.type.state.martial" },
+                "2": { "name": "punctuation.section.brackets.begin.martial" }
+            },
+            "end": "\\]",
+            "endCaptures": { "0": { "name": "punctuation.section.brackets.end.martial" } },
+            "patterns": [
+                { "name": "variable.other.role.martial", "match": IDENTIFIER },
+                { "name": "punctuation.separator.martial", "match": "," }
+            ]
+        }),
+    );
+    repository.insert(
+        "number".to_string(),
+        json!({ "name": "constant.numeric.martial", "match": "\\b\\d+[A-Za-z]*\\b" }),
+    );
+    repository.insert(
+        "repetition".to_string(),
+        json!({ "name": "keyword.operator.repetition.martial", "match": "\\bx\\d+\\b" }),
+    );
+    repository.insert(
+        "operator".to_string(),
+        json!({ "name": "keyword.operator.martial", "match": alternation(&operators) }),
+    );
+    repository.insert(
+        "punctuation".to_string(),
+        json!({ "name": "punctuation.separator.martial", "match": format!("[{}]", alternation(&PUNCTUATION).replace('|', "")) }),
+    );
+    let patterns: Vec<Value> = [
+        "comment", "pragma", "string", "annotation", "assertion", "keyword", "state-ref", "number", "repetition",
+        "operator", "punctuation",
+    ]
+    .iter()
+    .map(|name| json!({ "include": format!("#{}", name) }))
+    .collect();
+    let grammar = json!({
+        "$schema": "https://raw.githubusercontent.com/martinring/tmlanguage/master/tmlanguage.json",
+        "name": "Martial",
+        "scopeName": "source.martial",
+        "fileTypes": ["martial"],
+        "patterns": patterns,
+        "repository": repository,
+    });
+    let mut out = serde_json::to_string_pretty(&grammar).expect("a JSON value always serializes");
+    out.push('\n');
+    out
+}
+
+/// A tree-sitter `grammar.js` for `.martial` files
+///
+/// It follows the latest file format version: older syntax that has changed
+/// is not recognised, as after `mat migrate`.
+pub fn tree_sitter() -> String {
+    let mut out = String::from(
+        "// Generated by `mat grammar --format tree-sitter`, do not edit\n\
+         module.exports = grammar({\n  name: 'martial',\n  extras: $ => [/\\s/, $.comment],\n  word: $ => $.identifier,\n  rules: {\n",
+    );
+    for rule in GRAMMAR {
+        let body = match rule.expr {
+            Expr::Lexical(_) => match rule.name {
+                "IDENTIFIER" => format!("/{}/", IDENTIFIER),
+                "NUMBER" => "/\\d+[A-Za-z]*/".to_string(),
+                // Ahead of identifiers, which `x3` would also be
+                "REPETITION" => "token(prec(1, /x\\d+/))".to_string(),
+                "STRING" => "/\"([^\"\\\\\\n]|\\\\[\"\\\\])*\"/".to_string(),
+                name => unreachable!("no tree-sitter token for {}", name),
+            },
+            expr => tree_sitter_expr(&expr),
+        };
+        out.push_str(&format!("    {}: $ => {},\n", rule.name.to_lowercase(), body));
+    }
+    out.push_str("    comment: $ => token(seq('//', /.*/)),\n  },\n});\n");
+    out
+}
+
+fn tree_sitter_expr(expr: &Expr) -> String {
+    let list = |items: &[Expr]| items.iter().map(tree_sitter_expr).collect::<Vec<_>>().join(", ");
+    match expr {
+        Expr::Literal(text) => format!("'{}'", text.replace('\\', "\\\\").replace('\'', "\\'")),
+        Expr::Rule(name) => format!("$.{}", name.to_lowercase()),
+        Expr::Seq(items) => {
+            // A sequence within a sequence is spelled inline
+            let flat: Vec<String> = items
+                .iter()
+                .map(|item| match item {
+                    Expr::Seq(inner) => list(inner),
+                    item => tree_sitter_expr(item),
+                })
+                .collect();
+            format!("seq({})", flat.join(", "))
+        }
+        Expr::Alt(items) => {
+            let current: Vec<Expr> = items
+                .iter()
+                .filter(|item| !matches!(item, Expr::Version(version, _) if *version != LATEST_VERSION))
+                .copied()
+                .collect();
+            match current.as_slice() {
+                [only] => tree_sitter_expr(only),
+                items => format!("choice({})", list(items)),
+            }
+        }
+        Expr::Optional(inner) => format!("optional({})", tree_sitter_expr(inner)),
+        Expr::Repeat(inner) => format!("repeat({})", tree_sitter_expr(inner)),
+        Expr::OneOrMore(inner) => format!("repeat1({})", tree_sitter_expr(inner)),
+        Expr::Version(_, inner) => tree_sitter_expr(inner),
+        Expr::Lexical(_) => unreachable!("token classes are rules of their own"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_textmate() {
+        let grammar: Value = serde_json::from_str(&textmate()).unwrap();
+        assert_eq!(grammar["scopeName"], "source.martial");
+        let keywords = &grammar["repository"]["keyword"]["patterns"];
+        assert_eq!(
+            keywords[0]["match"],
+            "\\b(roles|state|sequence|group|levels|term|ruleset|template|mirror|assert)\\b"
+        );
+        assert_eq!(keywords[1]["match"], "\\b(forbid|mirrored|alternating|suffixes|onfail)\\b");
+        assert_eq!(grammar["repository"]["operator"]["match"], "->|\\/|=|<->");
+        assert_eq!(grammar["repository"]["assertion"]["match"], "\\b(assert)\\s+(reachable|no_path)\\b");
+        // Every pattern has a definition
+        for pattern in grammar["patterns"].as_array().unwrap() {
+            let name = pattern["include"].as_str().unwrap().trim_start_matches('#');
+            assert!(grammar["repository"].get(name).is_some(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_tree_sitter() {
+        let grammar = tree_sitter();
+        assert!(grammar.contains("    program: $ => seq(optional($.version_pragma), repeat1($.declaration)),\n"));
+        assert!(grammar.contains("    state_roles: $ => seq('[', $.identifier, repeat(seq(',', $.identifier)), ']'),\n"));
+        assert!(grammar.contains("    mirror_kind: $ => choice('roles', 'suffixes'),\n"));
+        assert!(grammar.contains("    repetition: $ => token(prec(1, /x\\d+/)),\n"));
+        assert!(grammar.contains("    identifier: $ => /[\\p{L}_][\\p{L}\\p{N}_]*(?:\\.[\\p{L}_][\\p{L}\\p{N}_]*)*/,\n"));
+        // Every rule it refers to is defined
+        for reference in grammar.split("$.").skip(1) {
+            let name: String = reference.chars().take_while(|ch| ch.is_alphanumeric() || *ch == '_').collect();
+            assert!(grammar.contains(&format!("    {}: $ =>", name)), "{}", name);
+        }
+    }
+}
@@ -0,0 +1,251 @@
+//! Impact of removing a state or a sequence
+//!
+//! `mat impact` reports what a removal would break before any file is
+//! edited: the steps, groups and rulesets that name the state or sequence,
+//! the positions that would leave the graph, and the routes between the
+//! remaining positions that would disappear with them. Removing a state
+//! drops every transition into or out of its positions; removing a sequence
+//! drops its transitions and those of its mirror.
+
+use crate::collections::HashSet;
+use crate::graph::{MartialGraph, Node};
+use crate::prelude::*;
+use crate::semantic::{MartialSystem, Reference, ReferenceKind};
+use alloc::collections::BTreeSet;
+use serde::Serialize;
+
+/// What is removed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Target {
+    State,
+    Sequence,
+}
+
+impl Target {
+    pub const ALL: [Target; 2] = [Target::State, Target::Sequence];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Target::State => "state",
+            Target::Sequence => "sequence",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Target::ALL.into_iter().find(|target| target.name() == name)
+    }
+}
+
+/// Positions a position would no longer lead to
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LostRoutes {
+    pub from: String,
+    pub to: Vec<String>,
+}
+
+/// Everything a removal would break
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Impact {
+    pub system_name: String,
+    pub target: Target,
+    pub name: String,
+    /// Uses of the name outside its declaration, in the order of
+    /// [`MartialSystem::references`]
+    pub references: Vec<Reference>,
+    /// Sequences with a step at a removed state, which would no longer
+    /// validate, or the mirror removed with a sequence
+    pub sequences: Vec<String>,
+    /// Groups listing a removed state
+    pub groups: Vec<String>,
+    /// Rulesets forbidding a removed sequence
+    pub rulesets: Vec<String>,
+    /// Positions left without any transition, sorted
+    pub positions: Vec<String>,
+    /// Routes between the remaining positions that would disappear, by
+    /// sorted starting position
+    pub lost_routes: Vec<LostRoutes>,
+}
+
+impl Impact {
+    /// The impact of removing the state or sequence `name` from `system`,
+    /// `None` if it declares no such state or sequence
+    pub fn new(system: &MartialSystem, target: Target, name: &str) -> Option<Self> {
+        let declared = match target {
+            Target::State => system.states.contains_key(name),
+            Target::Sequence => system.sequences.get(name).is_some_and(|sequence| sequence.mirror_of.is_none()),
+        };
+        if !declared {
+            return None;
+        }
+        // The steps of a removed sequence go with it
+        let own = format!("sequence {}", name);
+        let references: Vec<Reference> = system
+            .references(name)
+            .into_iter()
+            .filter(|reference| reference.kind != ReferenceKind::Declaration)
+            .filter(|reference| target == Target::State || reference.within != own)
+            .collect();
+        let names_of = |kind: ReferenceKind, keyword: &str| -> Vec<String> {
+            let names: BTreeSet<&str> = references
+                .iter()
+                .filter(|reference| reference.kind == kind || (kind == ReferenceKind::From && is_step(reference.kind)))
+                .filter_map(|reference| reference.within.strip_prefix(keyword))
+                .collect();
+            names.into_iter().map(String::from).collect()
+        };
+
+        let graph = MartialGraph::from_system(system);
+        let mut after = graph.clone();
+        let sequences = match target {
+            Target::State => {
+                after.edges.retain(|edge| edge.from.state != name && edge.to.state != name);
+                names_of(ReferenceKind::From, "sequence ")
+            }
+            Target::Sequence => {
+                let mirrors: Vec<String> = system
+                    .sequences
+                    .values()
+                    .filter(|sequence| sequence.mirror_of.as_deref() == Some(name))
+                    .map(|sequence| sequence.name.clone())
+                    .collect();
+                after.edges.retain(|edge| edge.sequence != name && !mirrors.iter().any(|mirror| edge.sequence == mirror.as_str()));
+                mirrors
+            }
+        };
+        let used: HashSet<&Node> = after.edges.iter().flat_map(|edge| [&edge.from, &edge.to]).collect();
+        let (kept, removed): (Vec<&Node>, Vec<&Node>) = graph.nodes.iter().partition(|node| used.contains(node));
+
+        let mut lost_routes = Vec::new();
+        for node in kept.iter().copied().collect::<BTreeSet<&Node>>() {
+            let reached = after.distances_from([node]);
+            let lost: Vec<String> = graph
+                .distances_from([node])
+                .into_keys()
+                .filter(|to| used.contains(to) && !reached.contains_key(to))
+                .map(Node::id)
+                .collect::<BTreeSet<String>>()
+                .into_iter()
+                .collect();
+            if !lost.is_empty() {
+                lost_routes.push(LostRoutes { from: node.id(), to: lost });
+            }
+        }
+
+        Some(Impact {
+            system_name: system.name.clone(),
+            target,
+            name: name.to_string(),
+            sequences,
+            groups: names_of(ReferenceKind::Member, "group "),
+            rulesets: names_of(ReferenceKind::Forbidden, "ruleset "),
+            positions: removed.into_iter().collect::<BTreeSet<&Node>>().into_iter().map(Node::id).collect(),
+            lost_routes,
+            references,
+        })
+    }
+
+    /// Whether nothing would break
+    pub fn is_empty(&self) -> bool {
+        self.references.is_empty() && self.sequences.is_empty() && self.positions.is_empty() && self.lost_routes.is_empty()
+    }
+
+    /// Export as JSON
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string_pretty(self)
+    }
+
+    /// What would break, one section per kind, with the references last
+    pub fn to_text(&self) -> String {
+        let subject = format!("{} {}", self.target.name(), self.name);
+        if self.is_empty() {
+            return format!("✓ Removing {} from '{}' breaks nothing\n", subject, self.system_name);
+        }
+        let mut out = format!("Removing {} from '{}' would break:\n", subject, self.system_name);
+        let sequences = match self.target {
+            Target::State => "Sequences no longer valid",
+            Target::Sequence => "Mirrors removed with it",
+        };
+        for (title, names) in [
+            (sequences, &self.sequences),
+            ("Groups listing it", &self.groups),
+            ("Rulesets forbidding it", &self.rulesets),
+            ("Positions removed", &self.positions),
+        ] {
+            if !names.is_empty() {
+                out.push_str(&format!("  {} ({}): {}\n", title, names.len(), names.join(", ")));
+            }
+        }
+        if !self.lost_routes.is_empty() {
+            let count: usize = self.lost_routes.iter().map(|lost| lost.to.len()).sum();
+            out.push_str(&format!("\n  Routes lost ({}):\n", count));
+            for lost in &self.lost_routes {
+                out.push_str(&format!("    {} no longer reaches {}\n", lost.from, lost.to.join(", ")));
+            }
+        }
+        if !self.references.is_empty() {
+            out.push_str(&format!("\n  References ({}):\n", self.references.len()));
+            for reference in &self.references {
+                out.push_str(&format!("    {}\n", reference));
+            }
+        }
+        out
+    }
+}
+
+/// Whether a reference is a step's use of a state
+fn is_step(kind: ReferenceKind) -> bool {
+    matches!(kind, ReferenceKind::From | ReferenceKind::To | ReferenceKind::OnFail | ReferenceKind::Reaction)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loader::{self, SourceFile};
+
+    fn system() -> MartialSystem {
+        let source = "roles { Top, Bottom }\nstate Guard\nstate Turtle\nstate Back\nstate Mount\n\
+                      group Scrambles { Turtle, Guard }\nruleset Kids { forbid Crank }\n\
+                      sequence Spin:\n    Roll: Guard[Bottom] -> Turtle[Top]\n    Take: Turtle[Top] -> Back[Top]\n\
+                      sequence Climb:\n    Hook: Back[Top] -> Mount[Top]\n\
+                      sequence Crank:\n    Crank: Mount[Top] -> Mount[Top]";
+        loader::load_sources("club", &[SourceFile::new("club.martial", source)]).unwrap()
+    }
+
+    #[test]
+    fn test_remove_state() {
+        let impact = Impact::new(&system(), Target::State, "Turtle").unwrap();
+        assert_eq!(impact.sequences, ["Spin"]);
+        assert_eq!(impact.groups, ["Scrambles"]);
+        assert_eq!(impact.positions, ["Guard[Bottom]", "Turtle[Top]"]);
+        // Guard only led anywhere through the turtle, and is left without transitions
+        assert!(impact.lost_routes.is_empty());
+        assert_eq!(impact.references.len(), 3);
+
+        let text = impact.to_text();
+        assert!(text.starts_with("Removing state Turtle from 'club' would break:\n"), "{}", text);
+        assert!(text.contains("\n  Groups listing it (1): Scrambles\n  Positions removed (2)"), "{}", text);
+        assert!(text.contains("    club.martial:10:11: from in sequence Spin\n"), "{}", text);
+        assert!(Impact::new(&system(), Target::State, "Nowhere").is_none());
+    }
+
+    #[test]
+    fn test_remove_sequence() {
+        let impact = Impact::new(&system(), Target::Sequence, "Climb").unwrap();
+        assert_eq!(impact.rulesets, Vec::<String>::new());
+        assert!(impact.positions.is_empty());
+        let lost: Vec<(&str, Vec<&str>)> =
+            impact.lost_routes.iter().map(|lost| (lost.from.as_str(), lost.to.iter().map(String::as_str).collect())).collect();
+        assert_eq!(
+            lost,
+            [("Back[Top]", vec!["Mount[Top]"]), ("Guard[Bottom]", vec!["Mount[Top]"]), ("Turtle[Top]", vec!["Mount[Top]"])]
+        );
+
+        let crank = Impact::new(&system(), Target::Sequence, "Crank").unwrap();
+        assert_eq!(crank.rulesets, ["Kids"]);
+        // The action of the same name goes with the sequence
+        assert_eq!(crank.references.len(), 1);
+        assert!(crank.lost_routes.is_empty() && crank.positions.is_empty());
+        assert_eq!(Target::from_name("sequence"), Some(Target::Sequence));
+    }
+}
@@ -0,0 +1,177 @@
+//! CSV import
+//!
+//! One row per step: `sequence, action, from_state, from_role, to_state,
+//! to_role`. A header row naming these columns is optional and may list
+//! them in any order. Rows of the same sequence are appended in order.
+
+use super::{identifier, Builder, ImportError};
+use crate::ast::*;
+use crate::lexer::Position;
+
+/// Split CSV content into records, with their line numbers
+///
+/// Supports quoted fields with `""` escapes. Blank lines are skipped.
+fn csv_records(content: &str) -> Result<Vec<(usize, Vec<String>)>, ImportError> {
+    let mut records = Vec::new();
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut line = 1;
+    let mut record_line = 1;
+    let mut chars = content.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            '\n' if !quoted => {
+                fields.push(std::mem::take(&mut field));
+                if fields.iter().any(|f| !f.trim().is_empty()) {
+                    records.push((record_line, std::mem::take(&mut fields)));
+                }
+                fields.clear();
+                line += 1;
+                record_line = line;
+            }
+            '\r' if !quoted => {}
+            _ => {
+                if c == '\n' {
+                    line += 1;
+                }
+                field.push(c);
+            }
+        }
+    }
+    if quoted {
+        return Err(ImportError::new("Unterminated quoted field", Some(record_line)));
+    }
+    fields.push(field);
+    if fields.iter().any(|f| !f.trim().is_empty()) {
+        records.push((record_line, fields));
+    }
+    Ok(records)
+}
+
+/// Column names of the CSV format, in their default order
+pub const COLUMNS: [&str; 6] = ["sequence", "action", "from_state", "from_role", "to_state", "to_role"];
+
+pub(super) fn import(content: &str) -> Result<Vec<Declaration>, ImportError> {
+    let mut records = csv_records(content)?.into_iter().peekable();
+
+    // An optional header row may reorder the columns
+    let mut columns: Vec<usize> = (0..COLUMNS.len()).collect();
+    if let Some((line, header)) = records.peek() {
+        let names: Vec<String> = header.iter().map(|h| h.trim().to_lowercase()).collect();
+        if names.iter().any(|n| COLUMNS.contains(&n.as_str())) {
+            let line = *line;
+            columns = COLUMNS
+                .iter()
+                .map(|column| {
+                    names
+                        .iter()
+                        .position(|n| n == column)
+                        .ok_or_else(|| ImportError::new(format!("Missing column '{}'", column), Some(line)))
+                })
+                .collect::<Result<_, _>>()?;
+            records.next();
+        }
+    }
+
+    let mut builder = Builder::default();
+    for (line, record) in records {
+        let mut values = Vec::new();
+        for (column, &index) in COLUMNS.iter().zip(&columns) {
+            let raw = record.get(index).map(|v| v.as_str()).unwrap_or("");
+            let value = identifier(raw).ok_or_else(|| {
+                ImportError::new(format!("Invalid {} '{}'", column, raw.trim()), Some(line))
+            })?;
+            values.push(value);
+        }
+
+        let [sequence, action, from_state, from_role, to_state, to_role]: [String; 6] =
+            values.try_into().expect("one value per column");
+        builder.step(
+            sequence,
+            SequenceStep {
+                action_name: action.into(),
+                from: StateRef {
+                    state: from_state.into(),
+                    role: from_role.into(),
+                    extra_roles: Vec::new(),
+                    spans: Spans::default(),
+                },
+                to: StateRef {
+                    state: to_state.into(),
+                    role: to_role.into(),
+                    extra_roles: Vec::new(),
+                    spans: Spans::default(),
+                },
+                on_fail: None,
+                reaction: None,
+                annotations: Vec::new(),
+                template: None,
+                repeat: None,
+                repetition: None,
+                position: Position::default(),
+                spans: Spans::default(),
+            },
+        );
+    }
+
+    Ok(builder.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{import, to_sources, ImportFormat};
+    use super::*;
+    use crate::loader::load_sources;
+
+    #[test]
+    fn test_csv_import_validates() {
+        let csv = "sequence,action,from_state,from_role,to_state,to_role\n\
+                   Escape,Bridge,Mount,Bottom,Mount,Bottom\n\
+                   \n\
+                   Escape,\"Elbow, knee\",Mount,Bottom,Half Guard,Bottom\n\
+                   Pass,Knee Slice,Half Guard,Top,Side Control,Top\n";
+        let declarations = import(ImportFormat::Csv, csv).unwrap();
+        let sources = to_sources("out", declarations);
+
+        let names: Vec<&str> = sources.iter().map(|s| s.path.as_str()).collect();
+        assert_eq!(names, vec!["out/roles.martial", "out/states.martial", "out/sequences.martial"]);
+        assert_eq!(sources[0].content, "martial 2\n\nroles { Bottom, Top }\n");
+        assert!(sources[2].content.contains("    ElbowKnee: Mount[Bottom] -> HalfGuard[Bottom]\n"));
+
+        let system = load_sources("out", &sources).unwrap();
+        assert_eq!(system.sequences["Escape"].steps.len(), 2);
+        assert_eq!(system.states.len(), 3);
+    }
+
+    #[test]
+    fn test_csv_header_reorders_columns() {
+        let csv = "from_state,from_role,to_state,to_role,action,sequence\n\
+                   Mount,Top,Back,Top,Roll,TakeBack";
+        let declarations = import(ImportFormat::Csv, csv).unwrap();
+        match &declarations[3] {
+            Declaration::Sequence(s) => {
+                assert_eq!(s.name, "TakeBack");
+                assert_eq!(s.steps[0].action_name, "Roll");
+            }
+            other => panic!("Expected sequence, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_csv_errors_report_line() {
+        let error = import(ImportFormat::Csv, "S,Go,Mount,Top,Back\nS,Go,Mount,Top,Back,Top").unwrap_err();
+        assert_eq!(error.line, Some(1));
+        assert!(error.message.contains("to_role"));
+
+        let error = import(ImportFormat::Csv, "sequence,action\nS,Go").unwrap_err();
+        assert_eq!(error.message, "Missing column 'from_state'");
+    }
+}
@@ -0,0 +1,424 @@
+//! Graphviz DOT import
+//!
+//! Reads the subset of DOT used for flowcharts: node and edge statements
+//! with attribute lists, edge chains (`a -> b -> c`) and nested subgraphs.
+//! Node labels name states, edge labels name actions and `cluster`
+//! subgraphs become groups named after their label. Ports and default
+//! attribute statements are accepted and ignored.
+
+use super::{identifier, node_ref, Builder, ImportError};
+use crate::ast::{Declaration, StateRef};
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    /// Identifier, number or quoted string
+    Id(String),
+    LeftBrace,
+    RightBrace,
+    LeftBracket,
+    RightBracket,
+    Equals,
+    Semicolon,
+    Comma,
+    Colon,
+    /// `->` or `--`
+    Edge,
+}
+
+fn tokenize(content: &str) -> Result<Vec<(Token, usize)>, ImportError> {
+    let chars: Vec<char> = content.chars().collect();
+    let mut tokens = Vec::new();
+    let mut line = 1;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        match c {
+            '\n' => {
+                line += 1;
+                i += 1;
+            }
+            _ if c.is_whitespace() => i += 1,
+            // Line comments and preprocessor lines
+            '#' => {
+                while i < chars.len() && chars[i] != '\n' {
+                    i += 1;
+                }
+            }
+            '/' if next == Some('/') => {
+                while i < chars.len() && chars[i] != '\n' {
+                    i += 1;
+                }
+            }
+            '/' if next == Some('*') => {
+                i += 2;
+                while i < chars.len() && !(chars[i] == '*' && chars.get(i + 1) == Some(&'/')) {
+                    if chars[i] == '\n' {
+                        line += 1;
+                    }
+                    i += 1;
+                }
+                i += 2;
+            }
+            '"' => {
+                let start_line = line;
+                let mut value = String::new();
+                i += 1;
+                loop {
+                    match chars.get(i) {
+                        None => return Err(ImportError::new("Unterminated string", Some(start_line))),
+                        Some('"') => break,
+                        Some('\\') => {
+                            match chars.get(i + 1) {
+                                Some('n') | Some('l') | Some('r') => value.push('\n'),
+                                Some('\n') => line += 1,
+                                Some(&escaped) => value.push(escaped),
+                                None => {}
+                            }
+                            i += 2;
+                            continue;
+                        }
+                        Some(&ch) => {
+                            if ch == '\n' {
+                                line += 1;
+                            }
+                            value.push(ch);
+                        }
+                    }
+                    i += 1;
+                }
+                i += 1;
+                tokens.push((Token::Id(value), start_line));
+            }
+            '-' if next == Some('>') || next == Some('-') => {
+                tokens.push((Token::Edge, line));
+                i += 2;
+            }
+            '{' | '}' | '[' | ']' | '=' | ';' | ',' | ':' => {
+                let token = match c {
+                    '{' => Token::LeftBrace,
+                    '}' => Token::RightBrace,
+                    '[' => Token::LeftBracket,
+                    ']' => Token::RightBracket,
+                    '=' => Token::Equals,
+                    ';' => Token::Semicolon,
+                    ',' => Token::Comma,
+                    _ => Token::Colon,
+                };
+                tokens.push((token, line));
+                i += 1;
+            }
+            _ if c.is_alphanumeric() || c == '_' || c == '.' || c == '-' => {
+                let start = i;
+                while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_' || chars[i] == '.') {
+                    i += 1;
+                }
+                if i == start {
+                    i += 1; // lone '-'
+                }
+                tokens.push((Token::Id(chars[start..i].iter().collect()), line));
+            }
+            _ => return Err(ImportError::new(format!("Unexpected character '{}'", c), Some(line))),
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// Nodes, edges and clusters read from a DOT graph
+#[derive(Default)]
+struct Graph {
+    /// Node ids in order of first appearance
+    nodes: Vec<String>,
+    labels: HashMap<String, String>,
+    /// (from, to, label)
+    edges: Vec<(String, String, Option<String>)>,
+    /// (name, member node ids)
+    clusters: Vec<(String, Vec<String>)>,
+}
+
+impl Graph {
+    fn node(&mut self, id: &str) {
+        if !self.nodes.iter().any(|n| n == id) {
+            self.nodes.push(id.to_string());
+        }
+    }
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    position: usize,
+    graph: Graph,
+    /// `label=...` of the graph or subgraph being parsed
+    label: Option<String>,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position).map(|(t, _)| t)
+    }
+
+    fn line(&self) -> Option<usize> {
+        self.tokens
+            .get(self.position)
+            .or_else(|| self.tokens.last())
+            .map(|(_, line)| *line)
+    }
+
+    fn error(&self, message: impl Into<String>) -> ImportError {
+        ImportError::new(message, self.line())
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), ImportError> {
+        match self.peek() {
+            Some(token) if *token == expected => {
+                self.position += 1;
+                Ok(())
+            }
+            other => Err(self.error(format!("Expected {:?}, got {:?}", expected, other))),
+        }
+    }
+
+    fn id(&mut self) -> Result<String, ImportError> {
+        match self.peek().cloned() {
+            Some(Token::Id(id)) => {
+                self.position += 1;
+                Ok(id)
+            }
+            other => Err(self.error(format!("Expected identifier, got {:?}", other))),
+        }
+    }
+
+    fn keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(Token::Id(id)) if id.eq_ignore_ascii_case(keyword))
+    }
+
+    /// `graph ::= [strict] (graph | digraph) [ID] '{' stmt_list '}'`
+    fn graph(&mut self) -> Result<(), ImportError> {
+        if self.keyword("strict") {
+            self.position += 1;
+        }
+        if !(self.keyword("digraph") || self.keyword("graph")) {
+            return Err(self.error("Expected 'digraph' or 'graph'"));
+        }
+        self.position += 1;
+        if let Some(Token::Id(_)) = self.peek() {
+            self.position += 1;
+        }
+        self.expect(Token::LeftBrace)?;
+        self.statements()?;
+        self.expect(Token::RightBrace)
+    }
+
+    /// Parse statements up to the closing brace, returning the nodes seen
+    fn statements(&mut self) -> Result<Vec<String>, ImportError> {
+        let mut members = Vec::new();
+        loop {
+            match self.peek() {
+                None | Some(Token::RightBrace) => return Ok(members),
+                Some(Token::Semicolon) => self.position += 1,
+                _ => members.extend(self.statement()?),
+            }
+        }
+    }
+
+    /// Attribute lists `[a=b, c=d][...]`
+    fn attributes(&mut self) -> Result<HashMap<String, String>, ImportError> {
+        let mut attributes = HashMap::new();
+        while self.peek() == Some(&Token::LeftBracket) {
+            self.position += 1;
+            while self.peek() != Some(&Token::RightBracket) {
+                let key = self.id()?;
+                self.expect(Token::Equals)?;
+                let value = self.id()?;
+                attributes.insert(key, value);
+                if matches!(self.peek(), Some(Token::Comma) | Some(Token::Semicolon)) {
+                    self.position += 1;
+                }
+            }
+            self.position += 1;
+        }
+        Ok(attributes)
+    }
+
+    /// Node id, skipping any port
+    fn node_id(&mut self) -> Result<String, ImportError> {
+        let id = self.id()?;
+        while self.peek() == Some(&Token::Colon) {
+            self.position += 1;
+            self.id()?;
+        }
+        Ok(id)
+    }
+
+    fn subgraph(&mut self) -> Result<Vec<String>, ImportError> {
+        let mut name = None;
+        if self.keyword("subgraph") {
+            self.position += 1;
+            if let Some(Token::Id(_)) = self.peek() {
+                name = Some(self.id()?);
+            }
+        }
+        self.expect(Token::LeftBrace)?;
+        let outer_label = self.label.take();
+        let members = self.statements()?;
+        self.expect(Token::RightBrace)?;
+        let label = std::mem::replace(&mut self.label, outer_label);
+
+        if let Some(name) = name.filter(|n| n.starts_with("cluster")) {
+            let name = label.unwrap_or_else(|| name.trim_start_matches("cluster").trim_start_matches('_').to_string());
+            self.graph.clusters.push((name, members.clone()));
+        }
+        Ok(members)
+    }
+
+    /// Parse one statement and return the nodes it mentions
+    fn statement(&mut self) -> Result<Vec<String>, ImportError> {
+        if self.keyword("subgraph") || self.peek() == Some(&Token::LeftBrace) {
+            return self.subgraph();
+        }
+        if self.keyword("graph") || self.keyword("node") || self.keyword("edge") {
+            self.position += 1;
+            self.attributes()?;
+            return Ok(Vec::new());
+        }
+
+        let first = self.node_id()?;
+        if self.peek() == Some(&Token::Equals) {
+            // Graph attribute such as `rankdir=LR`
+            self.position += 1;
+            let value = self.id()?;
+            if first == "label" {
+                self.label = Some(value);
+            }
+            return Ok(Vec::new());
+        }
+
+        let mut chain = vec![first];
+        while self.peek() == Some(&Token::Edge) {
+            self.position += 1;
+            if self.keyword("subgraph") || self.peek() == Some(&Token::LeftBrace) {
+                return Err(self.error("Subgraphs as edge endpoints are not supported"));
+            }
+            chain.push(self.node_id()?);
+        }
+        let attributes = self.attributes()?;
+
+        for id in &chain {
+            self.graph.node(id);
+        }
+        if chain.len() == 1 {
+            if let Some(label) = attributes.get("label") {
+                self.graph.labels.insert(chain[0].clone(), label.clone());
+            }
+        } else {
+            for pair in chain.windows(2) {
+                let label = attributes.get("label").cloned();
+                self.graph.edges.push((pair[0].clone(), pair[1].clone(), label));
+            }
+        }
+        Ok(chain)
+    }
+}
+
+pub(super) fn import(content: &str) -> Result<Vec<Declaration>, ImportError> {
+    let mut parser = Parser {
+        tokens: tokenize(content)?,
+        position: 0,
+        graph: Graph::default(),
+        label: None,
+    };
+    parser.graph()?;
+    let graph = parser.graph;
+
+    // Node ids already in `State[Role]` form (as written by `mat dot`) win
+    // over labels; otherwise the label, if any, names the state.
+    let resolve = |id: &str| -> Result<StateRef, ImportError> {
+        let name = match graph.labels.get(id) {
+            Some(label) if !id.contains('[') => label.replace('\n', " "),
+            _ => id.to_string(),
+        };
+        node_ref(&name).ok_or_else(|| ImportError::new(format!("Invalid node name '{}'", name), None))
+    };
+
+    let mut builder = Builder::default();
+    for id in &graph.nodes {
+        let state = resolve(id)?;
+        builder.role(state.role);
+        builder.state(state.state);
+    }
+    for (from, to, label) in &graph.edges {
+        builder.transition(label.as_deref(), resolve(from)?, resolve(to)?);
+    }
+    for (name, members) in &graph.clusters {
+        let group = identifier(name)
+            .ok_or_else(|| ImportError::new(format!("Invalid cluster name '{}'", name), None))?;
+        let states = members
+            .iter()
+            .map(|id| resolve(id).map(|r| r.state.into()))
+            .collect::<Result<_, _>>()?;
+        builder.group(group, states);
+    }
+
+    Ok(builder.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{import, to_sources, ImportFormat, DEFAULT_ROLE};
+    use crate::graph::MartialGraph;
+    use crate::loader::{load_sources, SourceFile};
+
+    #[test]
+    fn test_generic_digraph() {
+        let dot = r#"
+            digraph flow {
+                rankdir=LR; node [shape=box]
+                start [label="Standing"];
+                start -> guard -> mount [label="pass"];
+                /* sweep back */
+                mount -> guard;
+            }
+        "#;
+        let sources = to_sources("out", import(ImportFormat::Dot, dot).unwrap());
+        let system = load_sources("flow", &sources).unwrap();
+
+        assert_eq!(system.states.len(), 3);
+        assert!(system.states.contains_key("Standing"));
+        assert!(system.roles.contains(DEFAULT_ROLE));
+        let step = &system.sequences["GuardToMount"].steps[0];
+        assert_eq!(step.action_name, "Pass");
+        assert_eq!(system.sequences["MountToGuard"].steps[0].action_name, "Transition");
+        assert_eq!(system.sequences.len(), 3);
+    }
+
+    #[test]
+    fn test_round_trip_of_mat_dot() {
+        let original = load_sources(
+            "bjj",
+            &[SourceFile::new(
+                "system.martial",
+                "roles { Top, Bottom }\nstate Mount\nstate Back\ngroup Dominant { Mount, Back }\n\
+                 sequence TakeBack:\n    Roll: Mount[Top] -> Back[Top]\n    Hook: Back[Top] -> Back[Top]",
+            )],
+        )
+        .unwrap();
+        let dot = MartialGraph::from_system(&original).to_dot();
+
+        let sources = to_sources("out", import(ImportFormat::Dot, &dot).unwrap());
+        let system = load_sources("bjj", &sources).unwrap();
+        assert!(system.roles.contains("Top"));
+        assert!(!system.roles.contains(DEFAULT_ROLE));
+        assert_eq!(system.groups["Dominant"].len(), 2);
+        assert_eq!(system.sequences["MountToBack"].steps[0].from.role, "Top");
+        assert_eq!(system.sequences["BackToBack"].steps[0].action_name, "Hook");
+    }
+
+    #[test]
+    fn test_syntax_error_reports_line() {
+        let error = import(ImportFormat::Dot, "digraph {\n  a -> ;\n}").unwrap_err();
+        assert_eq!(error.line, Some(2));
+    }
+}
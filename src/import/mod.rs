@@ -0,0 +1,329 @@
+//! Import of techniques from other formats
+//!
+//! Converts external descriptions of technique chains into declarations
+//! that can be printed as `.martial` sources:
+//! - CSV rows of `sequence, action, from_state, from_role, to_state, to_role`
+//! - Graphviz DOT digraphs and SCXML state charts, whose edges become
+//!   single-step sequences
+//!
+//! Free-form names such as `knee slice` are turned into identifiers
+//! (`KneeSlice`). States are declared without role restrictions.
+
+mod csv;
+mod dot;
+mod scxml;
+
+pub use self::csv::COLUMNS as CSV_COLUMNS;
+
+use crate::ast::*;
+use crate::lexer::Position;
+use crate::loader::SourceFile;
+use crate::printer;
+use std::fmt;
+use std::path::Path;
+
+/// Supported input formats
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportFormat {
+    Csv,
+    Dot,
+    Scxml,
+}
+
+impl ImportFormat {
+    /// Names accepted by [`ImportFormat::from_name`]
+    pub const NAMES: [&'static str; 3] = ["csv", "dot", "scxml"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "csv" => Some(ImportFormat::Csv),
+            "dot" | "gv" => Some(ImportFormat::Dot),
+            "scxml" => Some(ImportFormat::Scxml),
+            _ => None,
+        }
+    }
+
+    /// Guess the format from a file extension
+    pub fn from_path(path: &str) -> Option<Self> {
+        Path::new(path)
+            .extension()
+            .and_then(|e| e.to_str())
+            .and_then(Self::from_name)
+    }
+}
+
+/// Error raised while reading an import
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImportError {
+    pub message: String,
+    /// Line of the input the error refers to, if any
+    pub line: Option<usize>,
+}
+
+impl ImportError {
+    fn new(message: impl Into<String>, line: Option<usize>) -> Self {
+        ImportError {
+            message: message.into(),
+            line,
+        }
+    }
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "Import error at line {}: {}", line, self.message),
+            None => write!(f, "Import error: {}", self.message),
+        }
+    }
+}
+
+impl core::error::Error for ImportError {}
+
+/// Turn a free-form name into an identifier
+///
+/// Words are capitalized and joined; anything but letters, digits and
+/// underscores is dropped. Returns `None` if nothing usable remains.
+pub fn identifier(raw: &str) -> Option<String> {
+    let name: String = raw
+        .split(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .filter(|w| !w.is_empty())
+        .map(|w| {
+            let mut chars = w.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect(),
+                None => String::new(),
+            }
+        })
+        .collect();
+
+    match name.chars().next() {
+        Some(c) if c.is_alphabetic() || c == '_' => Some(name),
+        _ => None,
+    }
+}
+
+/// Role given to states of formats that have no notion of roles
+pub const DEFAULT_ROLE: &str = "Any";
+
+/// Resolve a node name to a state reference
+///
+/// Names of the form `State[Role]`, as produced by `mat dot`, keep their
+/// role; anything else gets [`DEFAULT_ROLE`].
+fn node_ref(name: &str) -> Option<StateRef> {
+    let name = name.trim();
+    if let Some(open) = name.find('[') {
+        if let Some(inner) = name[open + 1..].strip_suffix(']') {
+            return Some(StateRef {
+                state: identifier(&name[..open])?.into(),
+                role: identifier(inner)?.into(),
+                extra_roles: Vec::new(),
+                spans: Spans::default(),
+            });
+        }
+    }
+    Some(StateRef {
+        state: identifier(name)?.into(),
+        role: DEFAULT_ROLE.into(),
+        extra_roles: Vec::new(),
+        spans: Spans::default(),
+    })
+}
+
+/// Collects declarations in order of first appearance
+#[derive(Default)]
+struct Builder {
+    roles: Vec<String>,
+    states: Vec<String>,
+    sequences: Vec<Sequence>,
+    groups: Vec<GroupDecl>,
+}
+
+impl Builder {
+    fn role(&mut self, name: impl Into<String>) {
+        let name = name.into();
+        if !self.roles.contains(&name) {
+            self.roles.push(name);
+        }
+    }
+
+    fn state(&mut self, name: impl Into<String>) {
+        let name = name.into();
+        if !self.states.contains(&name) {
+            self.states.push(name);
+        }
+    }
+
+    fn step(&mut self, sequence: String, step: SequenceStep) {
+        self.role(step.from.role.clone());
+        self.role(step.to.role.clone());
+        self.state(step.from.state.clone());
+        self.state(step.to.state.clone());
+
+        match self.sequences.iter_mut().find(|s| s.name == sequence) {
+            Some(existing) => existing.steps.push(step),
+            None => self.sequences.push(Sequence {
+                name: sequence,
+                steps: vec![step],
+                invocations: Vec::new(),
+                annotations: Vec::new(),
+                mirrored: false,
+                alternating: None,
+                mirror_of: None,
+                position: Position::default(),
+                spans: Spans::default(),
+            }),
+        }
+    }
+
+    /// Add a transition as its own sequence, named after its endpoints
+    fn transition(&mut self, action: Option<&str>, from: StateRef, to: StateRef) {
+        let action = action.and_then(identifier).unwrap_or_else(|| "Transition".to_string());
+        let base = format!("{}To{}", from.state, to.state);
+        let mut name = base.clone();
+        let mut suffix = 1;
+        while self.sequences.iter().any(|s| s.name == name) {
+            suffix += 1;
+            name = format!("{}{}", base, suffix);
+        }
+        self.step(
+            name,
+            SequenceStep {
+                action_name: action.into(),
+                from,
+                to,
+                on_fail: None,
+                reaction: None,
+                annotations: Vec::new(),
+                template: None,
+                repeat: None,
+                repetition: None,
+                position: Position::default(),
+                spans: Spans::default(),
+            },
+        );
+    }
+
+    /// Add states to a group, creating it if needed
+    fn group(&mut self, name: String, states: Vec<String>) {
+        let group = match self.groups.iter().position(|g| g.name == name) {
+            Some(index) => &mut self.groups[index],
+            None => {
+                self.groups.push(GroupDecl {
+                    name,
+                    states: Vec::new(),
+                    position: Position::default(),
+                    spans: Spans::default(),
+                });
+                self.groups.last_mut().expect("group was just added")
+            }
+        };
+        for state in states {
+            if !group.states.contains(&state) {
+                group.states.push(state);
+            }
+        }
+    }
+
+    fn finish(self) -> Vec<Declaration> {
+        let mut declarations = Vec::new();
+        if !self.roles.is_empty() {
+            declarations.push(Declaration::Roles(RolesDecl {
+                roles: self.roles,
+                position: Position::default(),
+                spans: Spans::default(),
+            }));
+        }
+        for name in self.states {
+            declarations.push(Declaration::State(State {
+                name,
+                allowed_roles: None,
+                annotations: Vec::new(),
+                position: Position::default(),
+                spans: Spans::default(),
+            }));
+        }
+        declarations.extend(self.sequences.into_iter().map(Declaration::Sequence));
+        declarations.extend(
+            self.groups
+                .into_iter()
+                .filter(|g| !g.states.is_empty())
+                .map(Declaration::Group),
+        );
+        declarations
+    }
+}
+
+/// Convert input in the given format into declarations
+pub fn import(format: ImportFormat, content: &str) -> Result<Vec<Declaration>, ImportError> {
+    match format {
+        ImportFormat::Csv => csv::import(content),
+        ImportFormat::Dot => dot::import(content),
+        ImportFormat::Scxml => scxml::import(content),
+    }
+}
+
+/// Split imported declarations into `roles.martial`, `states.martial`,
+/// `sequences.martial` and `groups.martial` under `dir`
+///
+/// Files without declarations are omitted.
+pub fn to_sources(dir: &str, declarations: Vec<Declaration>) -> Vec<SourceFile> {
+    let mut files: Vec<(&str, Vec<Declaration>)> = vec![
+        ("roles.martial", Vec::new()),
+        ("states.martial", Vec::new()),
+        ("sequences.martial", Vec::new()),
+        ("groups.martial", Vec::new()),
+    ];
+    for declaration in declarations {
+        let index = match declaration {
+            Declaration::Roles(_) | Declaration::Levels(_) | Declaration::Term(_) | Declaration::Mirror(_) => 0,
+            Declaration::State(_) => 1,
+            Declaration::Sequence(_)
+            | Declaration::Ruleset(_)
+            | Declaration::Template(_)
+            | Declaration::Assert(_) => 2,
+            Declaration::Group(_) => 3,
+        };
+        files[index].1.push(declaration);
+    }
+
+    files
+        .into_iter()
+        .filter(|(_, declarations)| !declarations.is_empty())
+        .map(|(name, declarations)| {
+            let path = Path::new(dir).join(name).to_string_lossy().into_owned();
+            let content = printer::print_file(&MartialFile {
+                source: None,
+                metadata: None,
+                version: LATEST_VERSION,
+                declarations,
+                deprecations: Vec::new(),
+            });
+            SourceFile::new(path, content)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_identifier() {
+        assert_eq!(identifier(" KneeSlice "), Some("KneeSlice".to_string()));
+        assert_eq!(identifier("knee slice"), Some("KneeSlice".to_string()));
+        assert_eq!(identifier("half-guard"), Some("HalfGuard".to_string()));
+        assert_eq!(identifier("1st"), None);
+        assert_eq!(identifier("state"), Some("State".to_string()));
+        assert_eq!(identifier(""), None);
+    }
+
+    #[test]
+    fn test_node_ref() {
+        let r = node_ref("Mount[Top]").unwrap();
+        assert_eq!((r.state.as_str(), r.role.as_str()), ("Mount", "Top"));
+        let r = node_ref("side control").unwrap();
+        assert_eq!((r.state.as_str(), r.role.as_str()), ("SideControl", DEFAULT_ROLE));
+        assert!(node_ref("[Top]").is_none());
+    }
+}
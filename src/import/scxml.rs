@@ -0,0 +1,115 @@
+//! SCXML import
+//!
+//! Every `<state>`, `<parallel>` and `<final>` with an `id` becomes a state
+//! and every `<transition>` a single-step sequence from its enclosing state
+//! to each of its targets, named after its `event`. Compound states also
+//! become a group of their direct child states.
+
+use super::{node_ref, Builder, ImportError};
+use crate::ast::{Declaration, StateRef};
+use roxmltree::{Document, Node};
+
+const STATE_ELEMENTS: [&str; 3] = ["state", "parallel", "final"];
+
+fn is_state(node: &Node) -> bool {
+    node.is_element() && STATE_ELEMENTS.contains(&node.tag_name().name())
+}
+
+fn line(document: &Document, node: &Node) -> Option<usize> {
+    Some(document.text_pos_at(node.range().start).row as usize)
+}
+
+fn state_ref(document: &Document, node: &Node, id: &str) -> Result<StateRef, ImportError> {
+    node_ref(id).ok_or_else(|| ImportError::new(format!("Invalid state id '{}'", id), line(document, node)))
+}
+
+pub(super) fn import(content: &str) -> Result<Vec<Declaration>, ImportError> {
+    let document = Document::parse(content).map_err(|e| {
+        let position = e.pos();
+        ImportError::new(e.to_string(), Some(position.row as usize))
+    })?;
+    let root = document.root_element();
+    if root.tag_name().name() != "scxml" {
+        return Err(ImportError::new("Expected an <scxml> root element", line(&document, &root)));
+    }
+
+    let mut builder = Builder::default();
+
+    // States first, so that they are declared in document order
+    for node in root.descendants().filter(is_state) {
+        if let Some(id) = node.attribute("id") {
+            let state = state_ref(&document, &node, id)?;
+            builder.role(state.role);
+            builder.state(state.state);
+        }
+    }
+
+    for node in root.descendants().filter(is_state) {
+        let id = match node.attribute("id") {
+            Some(id) => id,
+            None => continue,
+        };
+        let from = state_ref(&document, &node, id)?;
+
+        for transition in node.children().filter(|c| c.has_tag_name("transition")) {
+            // Targetless transitions are internal and do not change state
+            let targets = transition.attribute("target").unwrap_or("");
+            for target in targets.split_whitespace() {
+                let to = state_ref(&document, &transition, target)?;
+                builder.transition(transition.attribute("event"), from.clone(), to);
+            }
+        }
+
+        let children: Vec<String> = node
+            .children()
+            .filter(is_state)
+            .filter_map(|child| child.attribute("id"))
+            .filter_map(node_ref)
+            .map(|r| r.state.into())
+            .collect();
+        if !children.is_empty() {
+            builder.group(from.state.into(), children);
+        }
+    }
+
+    Ok(builder.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{import, to_sources, ImportFormat};
+    use crate::loader::load_sources;
+
+    #[test]
+    fn test_scxml_import() {
+        let scxml = r#"<?xml version="1.0"?>
+            <scxml xmlns="http://www.w3.org/2005/07/scxml" version="1.0" initial="standing">
+                <state id="standing">
+                    <transition event="pull.guard" target="guard"/>
+                </state>
+                <state id="ground">
+                    <state id="guard">
+                        <transition event="sweep" target="mount"/>
+                        <transition event="log"/>
+                    </state>
+                    <state id="mount"/>
+                </state>
+                <final id="tapped"/>
+            </scxml>"#;
+        let sources = to_sources("out", import(ImportFormat::Scxml, scxml).unwrap());
+        let system = load_sources("chart", &sources).unwrap();
+
+        assert_eq!(system.states.len(), 5);
+        assert_eq!(system.sequences["StandingToGuard"].steps[0].action_name, "PullGuard");
+        assert_eq!(system.sequences["GuardToMount"].steps[0].action_name, "Sweep");
+        assert_eq!(system.sequences.len(), 2);
+        assert_eq!(system.groups["Ground"], vec!["Guard", "Mount"]);
+    }
+
+    #[test]
+    fn test_scxml_errors() {
+        let error = import(ImportFormat::Scxml, "<scxml>\n<state id=\"a\">\n</scxml>").unwrap_err();
+        assert_eq!(error.line, Some(3));
+        assert!(import(ImportFormat::Scxml, "<machine/>").is_err());
+    }
+}
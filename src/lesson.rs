@@ -0,0 +1,214 @@
+//! Lesson plan generation
+//!
+//! Picks the sequences closest to a focus state that fit in the time
+//! available, then orders them from the ones leading into the focus to the
+//! ones finishing from it. Whatever time is left goes to positional sparring
+//! from the focus.
+
+use crate::ast::Sequence;
+use crate::semantic::MartialSystem;
+use crate::symbol::Symbol;
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Write;
+
+/// Teaching time per step for sequences without a `@duration`
+pub const MINUTES_PER_STEP: u32 = 3;
+
+/// A timed part of a lesson
+#[derive(Debug, Clone, PartialEq)]
+pub struct LessonBlock {
+    /// Minutes from the start of the lesson
+    pub start: u32,
+    pub minutes: u32,
+    /// Sequence taught in this block, or `None` for sparring
+    pub sequence: Option<String>,
+}
+
+/// A lesson outline around a focus state
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lesson {
+    pub focus: String,
+    pub minutes: u32,
+    pub blocks: Vec<LessonBlock>,
+}
+
+/// Time needed to teach a sequence
+pub fn sequence_minutes(sequence: &Sequence) -> u32 {
+    sequence
+        .duration()
+        .unwrap_or(sequence.steps.len() as u32 * MINUTES_PER_STEP)
+}
+
+/// Number of transitions from `focus` to every state, following edges
+/// forwards or backwards
+fn distances(system: &MartialSystem, focus: &str, forward: bool) -> HashMap<Symbol, usize> {
+    let focus = Symbol::new(focus);
+    let mut distances = HashMap::from([(focus.clone(), 0)]);
+    let mut queue = VecDeque::from([focus]);
+
+    while let Some(state) = queue.pop_front() {
+        let distance = distances[&state];
+        for step in system.sequences.values().flat_map(|s| &s.steps) {
+            let (from, to) = if forward { (&step.from.state, &step.to.state) } else { (&step.to.state, &step.from.state) };
+            if *from == state && !distances.contains_key(to) {
+                distances.insert(to.clone(), distance + 1);
+                queue.push_back(to.clone());
+            }
+        }
+    }
+    distances
+}
+
+/// Plan a lesson of `minutes` around `focus`
+///
+/// Returns `None` if `focus` is not a state of the system.
+pub fn plan(system: &MartialSystem, focus: &str, minutes: u32) -> Option<Lesson> {
+    if !system.states.contains_key(focus) {
+        return None;
+    }
+    let to_focus = distances(system, focus, false);
+    let from_focus = distances(system, focus, true);
+
+    // Signed place relative to the focus (negative leads into it) and
+    // closeness of every sequence connected to the focus
+    let mut candidates = Vec::new();
+    for (name, sequence) in &system.sequences {
+        let start = &sequence.steps[0].from.state;
+        let place = match (to_focus.get(start), from_focus.get(start)) {
+            (Some(&before), Some(&after)) if before < after => -(before as i64),
+            (_, Some(&after)) => after as i64,
+            (Some(&before), None) => -(before as i64),
+            (None, None) => continue,
+        };
+        let closeness = sequence
+            .steps
+            .iter()
+            .flat_map(|step| [&step.from.state, &step.to.state])
+            .filter_map(|state| to_focus.get(state).into_iter().chain(from_focus.get(state)).min())
+            .min()
+            .copied()
+            .unwrap_or(usize::MAX);
+        candidates.push((closeness, place, name));
+    }
+    candidates.sort();
+
+    let mut remaining = minutes;
+    let mut selected = Vec::new();
+    for (_, place, name) in candidates {
+        let needed = sequence_minutes(&system.sequences[name]);
+        if needed <= remaining {
+            remaining -= needed;
+            selected.push((place, name));
+        }
+    }
+    selected.sort();
+
+    let mut blocks = Vec::new();
+    let mut start = 0;
+    for (_, name) in selected {
+        let minutes = sequence_minutes(&system.sequences[name]);
+        blocks.push(LessonBlock {
+            start,
+            minutes,
+            sequence: Some(name.clone()),
+        });
+        start += minutes;
+    }
+    if remaining > 0 {
+        blocks.push(LessonBlock {
+            start,
+            minutes: remaining,
+            sequence: None,
+        });
+    }
+
+    Some(Lesson {
+        focus: focus.to_string(),
+        minutes,
+        blocks,
+    })
+}
+
+/// Render a lesson as a Markdown outline
+pub fn to_markdown(system: &MartialSystem, lesson: &Lesson) -> String {
+    let mut out = format!("# Lesson: {}\n\n{} minutes\n\n", lesson.focus, lesson.minutes);
+
+    for (i, block) in lesson.blocks.iter().enumerate() {
+        let time = format!("{}:{:02}", block.start / 60, block.start % 60);
+        match &block.sequence {
+            Some(name) => {
+                let _ = writeln!(out, "{}. **{}** {} ({} min)", i + 1, time, name, block.minutes);
+                for step in &system.sequences[name].steps {
+                    let _ = writeln!(
+                        out,
+                        "   - {}: {} → {}",
+                        step.action_name, step.from, step.to
+                    );
+                }
+            }
+            None => {
+                let _ = writeln!(
+                    out,
+                    "{}. **{}** Positional sparring from {} ({} min)",
+                    i + 1,
+                    time,
+                    lesson.focus,
+                    block.minutes
+                );
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loader::{load_sources, SourceFile};
+
+    fn system() -> MartialSystem {
+        let source = "roles { Top, Bottom }\n\
+                      state Guard\nstate HalfGuard\nstate Side\nstate Mount\nstate Standing\n\
+                      sequence Pass @duration(10):\n    Knee: HalfGuard[Top] -> Side[Top]\n\
+                      sequence Flatten:\n    Underhook: Guard[Top] -> HalfGuard[Top]\n\
+                      sequence Climb:\n    Step: Side[Top] -> Mount[Top]\n    Settle: Mount[Top] -> Mount[Top]\n\
+                      sequence Reset:\n    Stand: Standing[Top] -> Standing[Bottom]";
+        load_sources("test", &[SourceFile::new("a.martial", source)]).unwrap()
+    }
+
+    fn sequences(lesson: &Lesson) -> Vec<&str> {
+        lesson.blocks.iter().filter_map(|b| b.sequence.as_deref()).collect()
+    }
+
+    #[test]
+    fn test_plan_orders_prerequisites_first() {
+        let system = system();
+        let lesson = plan(&system, "Side", 30).unwrap();
+
+        assert_eq!(sequences(&lesson), vec!["Flatten", "Pass", "Climb"]);
+        let starts: Vec<u32> = lesson.blocks.iter().map(|b| b.start).collect();
+        assert_eq!(starts, vec![0, 3, 13, 19]);
+        assert_eq!(lesson.blocks[3].sequence, None);
+        assert_eq!(lesson.blocks[3].minutes, 11);
+    }
+
+    #[test]
+    fn test_plan_fits_time_budget() {
+        let system = system();
+        let lesson = plan(&system, "Side", 16).unwrap();
+        assert_eq!(sequences(&lesson), vec!["Pass", "Climb"]);
+        assert_eq!(lesson.blocks.iter().map(|b| b.minutes).sum::<u32>(), 16);
+
+        assert!(plan(&system, "Nowhere", 60).is_none());
+    }
+
+    #[test]
+    fn test_markdown_outline() {
+        let system = system();
+        let markdown = to_markdown(&system, &plan(&system, "Side", 14).unwrap());
+        assert!(markdown.starts_with("# Lesson: Side\n\n14 minutes\n\n"));
+        assert!(markdown.contains("1. **0:00** Flatten (3 min)\n"));
+        assert!(markdown.contains("2. **0:03** Pass (10 min)\n   - Knee: HalfGuard[Top] → Side[Top]\n"));
+        assert!(markdown.contains("3. **0:13** Positional sparring from Side (1 min)"));
+    }
+}
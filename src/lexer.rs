@@ -1,20 +1,41 @@
 //! Lexer for the Martial DSL
 //!
-//! Tokenizes `.martial` files into a stream of tokens.
+//! Tokenizes `.martial` files into a stream of tokens. Tokens borrow their
+//! text from the source and carry the byte span they were read from.
 
-use std::fmt;
+use crate::prelude::*;
+use schemars::JsonSchema;
+use serde::Serialize;
+use alloc::borrow::Cow;
+use core::fmt;
 
 /// A token in the Martial DSL
 #[derive(Debug, Clone, PartialEq)]
-pub enum Token {
+pub enum Token<'a> {
     // Keywords
     Roles,
     State,
     Sequence,
     Group,
+    Levels,
+    Term,
+    Ruleset,
+    Forbid,
+    Template,
+    Mirror,
+    Mirrored,
+    Alternating,
+    Suffixes,
+    Assert,
+    OnFail,
     
     // Identifiers
-    Identifier(String),
+    Identifier(&'a str),
+    
+    // Literals, used in annotation arguments and term definitions
+    Number(&'a str),
+    /// Unescaped contents; borrowed unless the string has escapes
+    Str(Cow<'a, str>),
     
     // Symbols
     LeftBrace,      // {
@@ -23,34 +44,59 @@ pub enum Token {
     RightBracket,   // ]
     Colon,          // :
     Arrow,          // ->
+    DoubleArrow,    // <->
+    Slash,          // /
     Comma,          // ,
+    At,             // @
+    LeftParen,      // (
+    RightParen,     // )
+    Equals,         // =
     
     // End of file
     Eof,
 }
 
-impl fmt::Display for Token {
+impl fmt::Display for Token<'_> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             Token::Roles => write!(f, "roles"),
             Token::State => write!(f, "state"),
             Token::Sequence => write!(f, "sequence"),
             Token::Group => write!(f, "group"),
+            Token::Levels => write!(f, "levels"),
+            Token::Term => write!(f, "term"),
+            Token::Ruleset => write!(f, "ruleset"),
+            Token::Forbid => write!(f, "forbid"),
+            Token::Template => write!(f, "template"),
+            Token::Mirror => write!(f, "mirror"),
+            Token::Mirrored => write!(f, "mirrored"),
+            Token::Alternating => write!(f, "alternating"),
+            Token::Suffixes => write!(f, "suffixes"),
+            Token::Assert => write!(f, "assert"),
+            Token::OnFail => write!(f, "onfail"),
             Token::Identifier(s) => write!(f, "{}", s),
+            Token::Number(n) => write!(f, "{}", n),
+            Token::Str(s) => write!(f, "{:?}", s),
             Token::LeftBrace => write!(f, "{{"),
             Token::RightBrace => write!(f, "}}"),
             Token::LeftBracket => write!(f, "["),
             Token::RightBracket => write!(f, "]"),
             Token::Colon => write!(f, ":"),
             Token::Arrow => write!(f, "->"),
+            Token::DoubleArrow => write!(f, "<->"),
+            Token::Slash => write!(f, "/"),
             Token::Comma => write!(f, ","),
+            Token::At => write!(f, "@"),
+            Token::LeftParen => write!(f, "("),
+            Token::RightParen => write!(f, ")"),
+            Token::Equals => write!(f, "="),
             Token::Eof => write!(f, "EOF"),
         }
     }
 }
 
 /// Position in source code for error reporting
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, JsonSchema)]
 pub struct Position {
     pub line: usize,
     pub column: usize,
@@ -62,15 +108,30 @@ impl fmt::Display for Position {
     }
 }
 
+/// Byte range of a token in the source
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    /// Text of the span in `source`
+    pub fn text<'a>(&self, source: &'a str) -> &'a str {
+        &source[self.start..self.end]
+    }
+}
+
 /// A token with its position in the source
 #[derive(Debug, Clone, PartialEq)]
-pub struct PositionedToken {
-    pub token: Token,
+pub struct PositionedToken<'a> {
+    pub token: Token<'a>,
     pub position: Position,
+    pub span: Span,
 }
 
 /// Lexer error
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct LexError {
     pub message: String,
     pub position: Position,
@@ -82,23 +143,62 @@ impl fmt::Display for LexError {
     }
 }
 
+impl core::error::Error for LexError {}
+
+/// The words [`keyword`] reads as keywords
+pub const KEYWORDS: [&str; 15] = [
+    "roles", "state", "sequence", "group", "levels", "term", "ruleset", "forbid", "template", "mirror",
+    "mirrored", "alternating", "suffixes", "assert", "onfail",
+];
+
+/// The keyword token spelled `word`, if it is one
+pub fn keyword(word: &str) -> Option<Token<'static>> {
+    Some(match word {
+        "roles" => Token::Roles,
+        "state" => Token::State,
+        "sequence" => Token::Sequence,
+        "group" => Token::Group,
+        "levels" => Token::Levels,
+        "term" => Token::Term,
+        "ruleset" => Token::Ruleset,
+        "forbid" => Token::Forbid,
+        "template" => Token::Template,
+        "mirror" => Token::Mirror,
+        "mirrored" => Token::Mirrored,
+        "alternating" => Token::Alternating,
+        "suffixes" => Token::Suffixes,
+        "assert" => Token::Assert,
+        "onfail" => Token::OnFail,
+        _ => return None,
+    })
+}
+
 /// Lexer for the Martial DSL
-pub struct Lexer {
-    input: Vec<char>,
+pub struct Lexer<'a> {
+    input: &'a str,
+    /// Byte offset of the next character
     position: usize,
     line: usize,
     column: usize,
 }
 
-impl Lexer {
+impl<'a> Lexer<'a> {
     /// Create a new lexer from input string
-    pub fn new(input: &str) -> Self {
-        Lexer {
-            input: input.chars().collect(),
+    ///
+    /// A leading front-matter block is skipped, see [`crate::metadata`].
+    pub fn new(input: &'a str) -> Self {
+        let mut lexer = Lexer {
+            input,
             position: 0,
             line: 1,
             column: 1,
+        };
+        if let Some(block) = crate::metadata::front_matter(input) {
+            while lexer.position < block.raw.len() {
+                lexer.advance();
+            }
         }
+        lexer
     }
     
     /// Get current position
@@ -111,26 +211,18 @@ impl Lexer {
     
     /// Peek at current character without consuming
     fn peek(&self) -> Option<char> {
-        if self.position < self.input.len() {
-            Some(self.input[self.position])
-        } else {
-            None
-        }
+        self.input[self.position..].chars().next()
     }
     
     /// Peek at next character without consuming
     fn peek_next(&self) -> Option<char> {
-        if self.position + 1 < self.input.len() {
-            Some(self.input[self.position + 1])
-        } else {
-            None
-        }
+        self.input[self.position..].chars().nth(1)
     }
     
     /// Consume and return current character
     fn advance(&mut self) -> Option<char> {
         if let Some(ch) = self.peek() {
-            self.position += 1;
+            self.position += ch.len_utf8();
             if ch == '\n' {
                 self.line += 1;
                 self.column = 1;
@@ -142,66 +234,104 @@ impl Lexer {
             None
         }
     }
+
+    /// Consume characters while `predicate` holds and return them
+    fn take_while(&mut self, predicate: impl Fn(char) -> bool) -> &'a str {
+        let start = self.position;
+        while self.peek().is_some_and(&predicate) {
+            self.advance();
+        }
+        &self.input[start..self.position]
+    }
     
     /// Skip whitespace
     fn skip_whitespace(&mut self) {
-        while let Some(ch) = self.peek() {
-            if ch.is_whitespace() {
-                self.advance();
-            } else {
-                break;
-            }
-        }
+        self.take_while(char::is_whitespace);
     }
     
+    /// Whether only whitespace precedes the current character on its line
+    fn at_line_start(&self) -> bool {
+        let before = &self.input[..self.position];
+        before[before.rfind('\n').map_or(0, |i| i + 1)..].trim().is_empty()
+    }
+
     /// Skip single-line comment
     fn skip_comment(&mut self) {
-        // Skip the //
-        self.advance();
-        self.advance();
-        
-        // Skip until end of line
-        while let Some(ch) = self.peek() {
-            if ch == '\n' {
-                break;
-            }
-            self.advance();
-        }
+        self.take_while(|ch| ch != '\n');
     }
     
     /// Lex an identifier or keyword
-    fn lex_identifier(&mut self) -> Result<Token, LexError> {
-        let mut result = String::new();
-        
-        while let Some(ch) = self.peek() {
-            if ch.is_alphanumeric() || ch == '_' {
-                result.push(ch);
-                self.advance();
-            } else {
-                break;
-            }
+    ///
+    /// Words joined by dots, as in `wrestling.SingleLeg`, make up a single
+    /// identifier, naming a declaration of a dependency.
+    fn lex_identifier(&mut self) -> Result<Token<'a>, LexError> {
+        let start = self.position;
+        self.take_while(|ch| ch.is_alphanumeric() || ch == '_');
+        while self.peek() == Some('.') && self.peek_next().is_some_and(|ch| ch.is_alphabetic() || ch == '_') {
+            self.advance();
+            self.take_while(|ch| ch.is_alphanumeric() || ch == '_');
         }
+        let word = &self.input[start..self.position];
         
-        // Check if it's a keyword
-        let token = match result.as_str() {
-            "roles" => Token::Roles,
-            "state" => Token::State,
-            "sequence" => Token::Sequence,
-            "group" => Token::Group,
-            _ => Token::Identifier(result),
-        };
-        
-        Ok(token)
+        Ok(keyword(word).unwrap_or(Token::Identifier(word)))
     }
     
+    /// Lex a double-quoted string, with `\"` and `\\` escapes
+    ///
+    /// The contents are borrowed from the source until an escape is met.
+    fn lex_string(&mut self, position: Position) -> Result<Token<'a>, LexError> {
+        self.advance(); // opening quote
+        let start = self.position;
+        let mut owned: Option<String> = None;
+        loop {
+            match self.peek() {
+                Some('"') => {
+                    let borrowed = &self.input[start..self.position];
+                    self.advance();
+                    return Ok(Token::Str(owned.map_or(Cow::Borrowed(borrowed), Cow::Owned)));
+                }
+                Some('\\') => {
+                    let result = owned.get_or_insert_with(|| self.input[start..self.position].to_string());
+                    self.advance();
+                    match self.peek() {
+                        Some(ch @ ('"' | '\\')) => {
+                            result.push(ch);
+                            self.advance();
+                        }
+                        other => {
+                            return Err(LexError {
+                                message: format!("Unknown escape sequence: \\{}", other.map(String::from).unwrap_or_default()),
+                                position: self.current_position(),
+                            })
+                        }
+                    }
+                }
+                Some('\n') | None => {
+                    return Err(LexError {
+                        message: "Unterminated string".to_string(),
+                        position,
+                    })
+                }
+                Some(ch) => {
+                    if let Some(result) = &mut owned {
+                        result.push(ch);
+                    }
+                    self.advance();
+                }
+            }
+        }
+    }
+
     /// Get the next token
-    pub fn next_token(&mut self) -> Result<PositionedToken, LexError> {
+    pub fn next_token(&mut self) -> Result<PositionedToken<'a>, LexError> {
         // Skip whitespace and comments
         loop {
             self.skip_whitespace();
             
-            // Check for comment
-            if self.peek() == Some('/') && self.peek_next() == Some('/') {
+            // Check for comment, or a directive of a conditional section,
+            // which the loader has evaluated, see `crate::conditional`
+            let comment = self.peek() == Some('/') && self.peek_next() == Some('/');
+            if comment || (self.peek() == Some('#') && self.at_line_start()) {
                 self.skip_comment();
             } else {
                 break;
@@ -209,6 +339,7 @@ impl Lexer {
         }
         
         let position = self.current_position();
+        let start = self.position;
         
         // Check for EOF
         let ch = match self.peek() {
@@ -216,6 +347,7 @@ impl Lexer {
             None => return Ok(PositionedToken {
                 token: Token::Eof,
                 position,
+                span: Span { start, end: start },
             }),
         };
         
@@ -245,6 +377,39 @@ impl Lexer {
                 self.advance();
                 Token::Comma
             }
+            '/' => {
+                self.advance();
+                Token::Slash
+            }
+            '@' => {
+                self.advance();
+                Token::At
+            }
+            '(' => {
+                self.advance();
+                Token::LeftParen
+            }
+            ')' => {
+                self.advance();
+                Token::RightParen
+            }
+            '=' => {
+                self.advance();
+                Token::Equals
+            }
+            '<' => {
+                self.advance();
+                if self.peek() == Some('-') && self.peek_next() == Some('>') {
+                    self.advance();
+                    self.advance();
+                    Token::DoubleArrow
+                } else {
+                    return Err(LexError {
+                        message: format!("Expected '->' after '<', got {:?}", self.peek()),
+                        position,
+                    });
+                }
+            }
             '-' => {
                 self.advance();
                 if self.peek() == Some('>') {
@@ -260,6 +425,14 @@ impl Lexer {
             _ if ch.is_alphabetic() || ch == '_' => {
                 self.lex_identifier()?
             }
+            '"' => self.lex_string(position)?,
+            _ if ch.is_ascii_digit() => {
+                let start = self.position;
+                self.take_while(|c| c.is_ascii_digit());
+                // A unit or side may follow, as in `@turn(90L)`
+                self.take_while(|c| c.is_ascii_alphabetic());
+                Token::Number(&self.input[start..self.position])
+            }
             _ => {
                 return Err(LexError {
                     message: format!("Unexpected character: '{}'", ch),
@@ -268,11 +441,15 @@ impl Lexer {
             }
         };
         
-        Ok(PositionedToken { token, position })
+        Ok(PositionedToken {
+            token,
+            position,
+            span: Span { start, end: self.position },
+        })
     }
     
     /// Tokenize the entire input
-    pub fn tokenize(&mut self) -> Result<Vec<PositionedToken>, LexError> {
+    pub fn tokenize(&mut self) -> Result<Vec<PositionedToken<'a>>, LexError> {
         let mut tokens = Vec::new();
         
         loop {
@@ -302,6 +479,9 @@ mod tests {
         assert_eq!(tokens[1].token, Token::State);
         assert_eq!(tokens[2].token, Token::Sequence);
         assert_eq!(tokens[3].token, Token::Eof);
+        for word in KEYWORDS {
+            assert_eq!(keyword(word).map(|token| token.to_string()).as_deref(), Some(word));
+        }
     }
     
     #[test]
@@ -309,15 +489,20 @@ mod tests {
         let mut lexer = Lexer::new("Top Bottom Mount123 _private");
         let tokens = lexer.tokenize().unwrap();
         
-        assert_eq!(tokens[0].token, Token::Identifier("Top".to_string()));
-        assert_eq!(tokens[1].token, Token::Identifier("Bottom".to_string()));
-        assert_eq!(tokens[2].token, Token::Identifier("Mount123".to_string()));
-        assert_eq!(tokens[3].token, Token::Identifier("_private".to_string()));
+        assert_eq!(tokens[0].token, Token::Identifier("Top"));
+        assert_eq!(tokens[1].token, Token::Identifier("Bottom"));
+        assert_eq!(tokens[2].token, Token::Identifier("Mount123"));
+        assert_eq!(tokens[3].token, Token::Identifier("_private"));
+
+        let tokens = Lexer::new("wrestling.SingleLeg[Top]").tokenize().unwrap();
+        assert_eq!(tokens[0].token, Token::Identifier("wrestling.SingleLeg"));
+        assert_eq!(tokens[1].token, Token::LeftBracket);
+        assert!(Lexer::new("wrestling.").tokenize().is_err());
     }
     
     #[test]
     fn test_symbols() {
-        let mut lexer = Lexer::new("{ } [ ] : -> ,");
+        let mut lexer = Lexer::new("{ } [ ] : -> , = <->");
         let tokens = lexer.tokenize().unwrap();
         
         assert_eq!(tokens[0].token, Token::LeftBrace);
@@ -327,6 +512,8 @@ mod tests {
         assert_eq!(tokens[4].token, Token::Colon);
         assert_eq!(tokens[5].token, Token::Arrow);
         assert_eq!(tokens[6].token, Token::Comma);
+        assert_eq!(tokens[7].token, Token::Equals);
+        assert_eq!(tokens[8].token, Token::DoubleArrow);
     }
     
     #[test]
@@ -337,6 +524,13 @@ mod tests {
         assert_eq!(tokens[0].token, Token::Roles);
         assert_eq!(tokens[1].token, Token::State);
         assert_eq!(tokens[2].token, Token::Eof);
+
+        // Directives of conditional sections are skipped, but only at the
+        // start of a line
+        let tokens = Lexer::new("  #if nogi\nstate\n#endif").tokenize().unwrap();
+        assert_eq!(tokens[0].token, Token::State);
+        assert_eq!(tokens[1].token, Token::Eof);
+        assert!(Lexer::new("state #if").tokenize().is_err());
     }
     
     #[test]
@@ -350,12 +544,12 @@ state Mount roles {
         let tokens = lexer.tokenize().unwrap();
         
         assert_eq!(tokens[0].token, Token::State);
-        assert_eq!(tokens[1].token, Token::Identifier("Mount".to_string()));
+        assert_eq!(tokens[1].token, Token::Identifier("Mount"));
         assert_eq!(tokens[2].token, Token::Roles);
         assert_eq!(tokens[3].token, Token::LeftBrace);
-        assert_eq!(tokens[4].token, Token::Identifier("Top".to_string()));
+        assert_eq!(tokens[4].token, Token::Identifier("Top"));
         assert_eq!(tokens[5].token, Token::Comma);
-        assert_eq!(tokens[6].token, Token::Identifier("Bottom".to_string()));
+        assert_eq!(tokens[6].token, Token::Identifier("Bottom"));
         assert_eq!(tokens[7].token, Token::RightBrace);
     }
     
@@ -366,13 +560,13 @@ state Mount roles {
         let tokens = lexer.tokenize().unwrap();
         
         assert_eq!(tokens[0].token, Token::Sequence);
-        assert_eq!(tokens[1].token, Token::Identifier("Test".to_string()));
+        assert_eq!(tokens[1].token, Token::Identifier("Test"));
         assert_eq!(tokens[2].token, Token::Colon);
-        assert_eq!(tokens[3].token, Token::Identifier("Action".to_string()));
+        assert_eq!(tokens[3].token, Token::Identifier("Action"));
         assert_eq!(tokens[4].token, Token::Colon);
-        assert_eq!(tokens[5].token, Token::Identifier("State".to_string()));
+        assert_eq!(tokens[5].token, Token::Identifier("State"));
         assert_eq!(tokens[6].token, Token::LeftBracket);
-        assert_eq!(tokens[7].token, Token::Identifier("Role".to_string()));
+        assert_eq!(tokens[7].token, Token::Identifier("Role"));
         assert_eq!(tokens[8].token, Token::RightBracket);
         assert_eq!(tokens[9].token, Token::Arrow);
     }
@@ -384,11 +578,59 @@ state Mount roles {
         let tokens = lexer.tokenize().unwrap();
 
         assert_eq!(tokens[0].token, Token::Group);
-        assert_eq!(tokens[1].token, Token::Identifier("GuardFamily".to_string()));
+        assert_eq!(tokens[1].token, Token::Identifier("GuardFamily"));
         assert_eq!(tokens[2].token, Token::LeftBrace);
-        assert_eq!(tokens[3].token, Token::Identifier("ClosedGuard".to_string()));
+        assert_eq!(tokens[3].token, Token::Identifier("ClosedGuard"));
         assert_eq!(tokens[4].token, Token::Comma);
-        assert_eq!(tokens[5].token, Token::Identifier("OpenGuard".to_string()));
+        assert_eq!(tokens[5].token, Token::Identifier("OpenGuard"));
         assert_eq!(tokens[6].token, Token::RightBrace);
     }
+
+    #[test]
+    fn test_annotation() {
+        let input = "levels { White } state Mount @level(White)";
+        let tokens = Lexer::new(input).tokenize().unwrap();
+
+        assert_eq!(tokens[0].token, Token::Levels);
+        assert_eq!(tokens[6].token, Token::At);
+        assert_eq!(tokens[7].token, Token::Identifier("level"));
+        assert_eq!(tokens[8].token, Token::LeftParen);
+        assert_eq!(tokens[10].token, Token::RightParen);
+
+        let tokens = Lexer::new("@duration(15)").tokenize().unwrap();
+        assert_eq!(tokens[3].token, Token::Number("15"));
+
+        let tokens = Lexer::new("@turn(90L)").tokenize().unwrap();
+        assert_eq!(tokens[3].token, Token::Number("90L"));
+    }
+
+    #[test]
+    fn test_string() {
+        let tokens = Lexer::new(r#"@video("https://x.test/a b" "say \"hi\" \\")"#).tokenize().unwrap();
+        assert_eq!(tokens[3].token, Token::Str("https://x.test/a b".into()));
+        assert_eq!(tokens[4].token, Token::Str(r#"say "hi" \"#.into()));
+
+        let error = Lexer::new("\"open\nstate Mount").tokenize().unwrap_err();
+        assert_eq!(error.message, "Unterminated string");
+        assert_eq!(error.position, Position { line: 1, column: 1 });
+        assert!(Lexer::new(r#""\n""#).tokenize().is_err());
+    }
+
+    #[test]
+    fn test_spans() {
+        let input = "state Montée @label(fr: \"Montée\") // ok\n-> \"a\\\"b\"";
+        let tokens = Lexer::new(input).tokenize().unwrap();
+
+        let texts: Vec<&str> = tokens.iter().map(|t| t.span.text(input)).collect();
+        assert_eq!(
+            texts,
+            vec!["state", "Montée", "@", "label", "(", "fr", ":", "\"Montée\"", ")", "->", "\"a\\\"b\"", ""]
+        );
+        assert!(matches!(tokens[7].token, Token::Str(Cow::Borrowed("Montée"))));
+        assert!(matches!(&tokens[10].token, Token::Str(Cow::Owned(s)) if s == "a\"b"));
+        // Columns count characters, spans count bytes
+        assert_eq!(tokens[2].position, Position { line: 1, column: 14 });
+        assert_eq!(tokens[2].span, Span { start: 14, end: 15 });
+        assert_eq!(tokens[9].position, Position { line: 2, column: 1 });
+    }
 }
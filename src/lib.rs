@@ -2,9 +2,116 @@
 //!
 //! This library provides parsing, validation, and graph generation
 //! for martial arts systems defined in .martial files.
+//!
+//! The lexer, parser, validator and graph only need an allocator: build
+//! with `--no-default-features --features alloc` to use them without std.
+//! Everything that touches files, threads or the terminal needs the `std`
+//! feature, which is on by default.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(not(any(feature = "std", feature = "alloc")))]
+compile_error!("martial-lang needs either the `std` or the `alloc` feature");
+
+extern crate alloc;
+
+/// Items of the std prelude that core modules import from `alloc`
+mod prelude {
+    pub(crate) use alloc::boxed::Box;
+    pub(crate) use alloc::format;
+    pub(crate) use alloc::string::{String, ToString};
+    pub(crate) use alloc::vec;
+    pub(crate) use alloc::vec::Vec;
+}
 
+/// Hash maps and sets
+///
+/// Without std there is no randomly seeded hasher, so ordered maps stand in.
+mod collections {
+    #[cfg(not(feature = "std"))]
+    pub(crate) use alloc::collections::{BTreeMap as HashMap, BTreeSet as HashSet};
+    #[cfg(feature = "std")]
+    pub(crate) use std::collections::{HashMap, HashSet};
+}
+
+pub mod error;
 pub mod ast;
+pub mod symbol;
 pub mod lexer;
+pub mod metadata;
+pub mod conditional;
 pub mod parser;
+pub mod grammar;
 pub mod semantic;
 pub mod graph;
+pub mod selector;
+pub mod lint;
+pub mod export;
+pub mod diagram;
+pub mod handle;
+pub mod loader;
+pub mod migrate;
+pub mod fix;
+pub mod snapshot;
+pub mod compare;
+pub mod balance;
+pub mod matrix;
+pub mod bottleneck;
+pub mod impact;
+pub mod annotate;
+pub mod storyboard;
+pub mod embusen;
+
+#[cfg(feature = "std")]
+pub mod build;
+#[cfg(feature = "std")]
+pub mod package;
+#[cfg(feature = "std")]
+pub mod printer;
+#[cfg(feature = "std")]
+pub mod refactor;
+#[cfg(feature = "std")]
+pub mod import;
+#[cfg(feature = "std")]
+pub mod completion;
+#[cfg(feature = "std")]
+pub mod highlight;
+#[cfg(feature = "std")]
+pub mod config;
+#[cfg(feature = "std")]
+pub mod lock;
+#[cfg(feature = "std")]
+pub mod lsp;
+#[cfg(feature = "std")]
+pub mod doc;
+#[cfg(feature = "std")]
+pub mod help;
+#[cfg(feature = "std")]
+pub mod fixture;
+#[cfg(feature = "graphql")]
+pub mod graphql;
+#[cfg(feature = "std")]
+pub mod lesson;
+#[cfg(feature = "std")]
+pub mod curriculum;
+#[cfg(feature = "tui")]
+pub mod tui;
+#[cfg(feature = "std")]
+pub mod schema;
+#[cfg(feature = "std")]
+pub mod serve;
+#[cfg(feature = "std")]
+pub mod site;
+#[cfg(feature = "std")]
+pub mod sparring;
+#[cfg(feature = "std")]
+pub mod synthetic;
+#[cfg(feature = "std")]
+pub mod train;
+#[cfg(feature = "std")]
+pub mod wasm;
+
+#[cfg(feature = "ffi")]
+pub mod ffi;
+
+pub use error::{Error, Result};
@@ -0,0 +1,526 @@
+//! Lints: non-fatal checks run on a validated system
+//!
+//! Every check implements [`Lint`] and is run through a [`LintRegistry`].
+//! The built-in checks ship in [`LintRegistry::builtin`]; applications add
+//! their own rules with [`LintRegistry::register`], and projects pick the
+//! ones they want with the `lints` setting of their `martial.toml`.
+//! A [`Profile`] bundles the lints a style of practice requires.
+//!
+//! ```
+//! use martial_lang::lint::{Level, Lint, LintRegistry};
+//! use martial_lang::semantic::{Diagnostic, MartialSystem};
+//!
+//! /// Every sequence ends in Zanshin
+//! struct EndsInZanshin;
+//!
+//! impl Lint for EndsInZanshin {
+//!     fn name(&self) -> &'static str {
+//!         "ends-in-zanshin"
+//!     }
+//!
+//!     fn level(&self) -> Level {
+//!         Level::Deny
+//!     }
+//!
+//!     fn check(&self, system: &MartialSystem) -> Vec<Diagnostic> {
+//!         system
+//!             .sequences
+//!             .values()
+//!             .filter(|s| s.steps.last().is_some_and(|step| step.to.state != "Zanshin"))
+//!             .map(|s| Diagnostic::new(self.name(), format!("Sequence {} does not end in Zanshin", s.name)))
+//!             .collect()
+//!     }
+//! }
+//!
+//! let mut registry = LintRegistry::builtin();
+//! registry.register(Box::new(EndsInZanshin));
+//! assert!(registry.get("ends-in-zanshin").is_some());
+//! ```
+
+use crate::graph::{MartialGraph, Node};
+use crate::prelude::*;
+use crate::semantic::{Diagnostic, MartialSystem};
+use alloc::collections::{BTreeMap, BTreeSet};
+use core::fmt;
+use serde::Serialize;
+
+/// How seriously a lint's findings are taken
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    /// Only run when a project enables it by name
+    Allow,
+    /// Reported, but the system is still valid
+    Warn,
+    /// Fails validation
+    Deny,
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Level::Allow => "allow",
+            Level::Warn => "warn",
+            Level::Deny => "deny",
+        })
+    }
+}
+
+/// A check run on a validated system
+pub trait Lint: Send + Sync {
+    /// Kebab-case name, used in `martial.toml` and as the code of its
+    /// diagnostics
+    fn name(&self) -> &'static str;
+
+    /// How seriously its findings are taken
+    fn level(&self) -> Level {
+        Level::Warn
+    }
+
+    /// Findings on `system`, each with the lint's name as code
+    fn check(&self, system: &MartialSystem) -> Vec<Diagnostic>;
+}
+
+/// The lints a project can enable, by name
+pub struct LintRegistry {
+    lints: Vec<Box<dyn Lint>>,
+    /// Levels set by the project, overriding those of the lints
+    levels: BTreeMap<&'static str, Level>,
+}
+
+impl LintRegistry {
+    /// A registry without any lint
+    pub fn new() -> Self {
+        LintRegistry {
+            lints: Vec::new(),
+            levels: BTreeMap::new(),
+        }
+    }
+
+    /// A registry with the built-in lints
+    pub fn builtin() -> Self {
+        let mut registry = LintRegistry::new();
+        registry.register(Box::new(UnusedRole));
+        registry.register(Box::new(UnusedState));
+        registry.register(Box::new(DominanceDrop));
+        registry.register(Box::new(ForbiddenReach));
+        registry.register(Box::new(LinearSequence));
+        registry.register(Box::new(FinalState));
+        registry.register(Box::new(CoreConnectivity));
+        registry
+    }
+
+    /// Add `lint`, replacing any lint of the same name
+    pub fn register(&mut self, lint: Box<dyn Lint>) {
+        match self.lints.iter_mut().find(|l| l.name() == lint.name()) {
+            Some(slot) => *slot = lint,
+            None => self.lints.push(lint),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&dyn Lint> {
+        self.lints.iter().find(|lint| lint.name() == name).map(|lint| lint.as_ref())
+    }
+
+    /// Names of the lints, in registration order
+    pub fn names(&self) -> Vec<&'static str> {
+        self.lints.iter().map(|lint| lint.name()).collect()
+    }
+
+    /// Run the lint named `name` at `level` rather than its own; false if
+    /// no lint has that name
+    pub fn set_level(&mut self, name: &str, level: Level) -> bool {
+        match self.get(name).map(|lint| lint.name()) {
+            Some(name) => {
+                self.levels.insert(name, level);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Level of `lint`, as set by the project or else its own
+    pub fn level(&self, lint: &dyn Lint) -> Level {
+        self.levels.get(lint.name()).copied().unwrap_or_else(|| lint.level())
+    }
+
+    /// Names of the lints run when a project does not pick any
+    pub fn default_names(&self) -> Vec<&'static str> {
+        self.lints.iter().filter(|lint| self.level(lint.as_ref()) != Level::Allow).map(|lint| lint.name()).collect()
+    }
+
+    /// Those of `names` that no lint has
+    pub fn unknown<'a>(&self, names: &'a [String]) -> Vec<&'a str> {
+        names.iter().map(String::as_str).filter(|name| self.get(name).is_none()).collect()
+    }
+
+    /// Findings of the lints named in `enabled`, or of the default ones
+    /// if `None`, in registration order
+    pub fn check(&self, system: &MartialSystem, enabled: Option<&[String]>) -> Vec<Diagnostic> {
+        let enabled: BTreeSet<&str> = match enabled {
+            Some(names) => names.iter().map(String::as_str).collect(),
+            None => self.default_names().into_iter().collect(),
+        };
+        self.run(system, |lint| enabled.contains(lint.name()).then(|| self.level(lint).max(Level::Warn)))
+    }
+
+    /// Findings of the lints `profile` requires, all at [`Level::Deny`]
+    pub fn check_profile(&self, system: &MartialSystem, profile: &Profile) -> Vec<Diagnostic> {
+        self.run(system, |lint| profile.lints.contains(&lint.name()).then_some(Level::Deny))
+    }
+
+    /// Findings of the lints `level` gives a level to, at that level
+    fn run(&self, system: &MartialSystem, level: impl Fn(&dyn Lint) -> Option<Level>) -> Vec<Diagnostic> {
+        self.lints
+            .iter()
+            .filter_map(|lint| Some((lint, level(lint.as_ref())?)))
+            .flat_map(|(lint, level)| lint.check(system).into_iter().map(move |diagnostic| diagnostic.with_level(level)))
+            .collect()
+    }
+}
+
+impl Default for LintRegistry {
+    fn default() -> Self {
+        LintRegistry::builtin()
+    }
+}
+
+/// Lints required by a style of practice, selected with
+/// `mat validate --profile`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Profile {
+    pub name: &'static str,
+    pub description: &'static str,
+    /// Names of the required lints
+    pub lints: &'static [&'static str],
+}
+
+impl Profile {
+    pub const ALL: [Profile; 2] = [
+        Profile {
+            name: "kata",
+            description: "linear sequences ending in a @final state",
+            lints: &["linear-sequence", "final-state"],
+        },
+        Profile {
+            name: "sparring",
+            description: "every @core position reaches every other one",
+            lints: &["core-connectivity"],
+        },
+    ];
+
+    pub fn from_name(name: &str) -> Option<Profile> {
+        Profile::ALL.into_iter().find(|profile| profile.name == name)
+    }
+}
+
+/// Declared roles that no step uses
+pub struct UnusedRole;
+
+impl Lint for UnusedRole {
+    fn name(&self) -> &'static str {
+        "unused-role"
+    }
+
+    fn check(&self, system: &MartialSystem) -> Vec<Diagnostic> {
+        let used: BTreeSet<&str> = system.state_refs().flat_map(|r| r.roles()).map(|role| role.as_str()).collect();
+        system
+            .roles
+            .iter()
+            .filter(|role| !used.contains(role.as_str()))
+            .map(|role| {
+                let warning = Diagnostic::new(self.name(), format!("Role {} is not used by any step", role));
+                match system.role_positions.get(role) {
+                    Some(position) => warning.at(system.origin("role", role), *position),
+                    None => warning,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Declared states that no step uses
+pub struct UnusedState;
+
+impl Lint for UnusedState {
+    fn name(&self) -> &'static str {
+        "unused-state"
+    }
+
+    fn check(&self, system: &MartialSystem) -> Vec<Diagnostic> {
+        let used: BTreeSet<&str> = system.state_refs().flat_map(|r| system.states_of(&r.state)).collect();
+        system
+            .states
+            .iter()
+            .filter(|(name, _)| !used.contains(name.as_str()))
+            .map(|(name, state)| {
+                Diagnostic::new(self.name(), format!("State {} is not used by any step", name))
+                    .at(system.origin("state", name), state.position)
+            })
+            .collect()
+    }
+}
+
+/// Attack steps leaving a position for a less dominant one
+pub struct DominanceDrop;
+
+impl Lint for DominanceDrop {
+    fn name(&self) -> &'static str {
+        "dominance-drop"
+    }
+
+    fn check(&self, system: &MartialSystem) -> Vec<Diagnostic> {
+        system
+            .dominance_drops()
+            .into_iter()
+            .map(|drop| {
+                let warning = Diagnostic::new(self.name(), drop.to_string());
+                match system.sequences[&drop.sequence].steps.get(drop.step - 1) {
+                    Some(step) => warning.at(system.origin("sequence", &drop.sequence), step.position),
+                    None => warning,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Positions only reached through actions a ruleset forbids
+pub struct ForbiddenReach;
+
+impl Lint for ForbiddenReach {
+    fn name(&self) -> &'static str {
+        "forbidden-reach"
+    }
+
+    fn check(&self, system: &MartialSystem) -> Vec<Diagnostic> {
+        let mut warnings = Vec::new();
+        for ruleset in system.rulesets.keys() {
+            for position in system.reached_only_through_forbidden(ruleset) {
+                warnings.push(Diagnostic::new(
+                    self.name(),
+                    format!("Under ruleset {}, {} is only reached through forbidden actions", ruleset, position),
+                ));
+            }
+        }
+        warnings
+    }
+}
+
+/// Sequences passing through the same position twice, off by default
+pub struct LinearSequence;
+
+impl Lint for LinearSequence {
+    fn name(&self) -> &'static str {
+        "linear-sequence"
+    }
+
+    fn level(&self) -> Level {
+        Level::Allow
+    }
+
+    fn check(&self, system: &MartialSystem) -> Vec<Diagnostic> {
+        let mut diagnostics = Vec::new();
+        for sequence in system.sequences.values() {
+            let mut visited: BTreeSet<String> = sequence.steps.first().map(|step| step.from.to_string()).into_iter().collect();
+            let repeated = sequence.steps.iter().enumerate().find(|(_, step)| !visited.insert(step.to.to_string()));
+            if let Some((i, step)) = repeated {
+                diagnostics.push(
+                    Diagnostic::new(
+                        self.name(),
+                        format!(
+                            "Sequence {} is not linear: step {} ({}) returns to {}",
+                            sequence.name,
+                            i + 1,
+                            step.action_name,
+                            step.to
+                        ),
+                    )
+                    .at(system.origin("sequence", &sequence.name), step.position),
+                );
+            }
+        }
+        diagnostics
+    }
+}
+
+/// Sequences not ending in a state marked `@final`, off by default
+pub struct FinalState;
+
+impl Lint for FinalState {
+    fn name(&self) -> &'static str {
+        "final-state"
+    }
+
+    fn level(&self) -> Level {
+        Level::Allow
+    }
+
+    fn check(&self, system: &MartialSystem) -> Vec<Diagnostic> {
+        if !system.states.values().any(|state| state.is_final()) {
+            return vec![Diagnostic::new(self.name(), "No state is marked @final")];
+        }
+        let mut diagnostics = Vec::new();
+        for sequence in system.sequences.values() {
+            let Some(last) = sequence.steps.last() else { continue };
+            if system.states.get(last.to.state.as_str()).is_some_and(|state| !state.is_final()) {
+                diagnostics.push(
+                    Diagnostic::new(
+                        self.name(),
+                        format!("Sequence {} ends in {}, which is not marked @final", sequence.name, last.to.state),
+                    )
+                    .at(system.origin("sequence", &sequence.name), last.position),
+                );
+            }
+        }
+        diagnostics
+    }
+}
+
+/// `@core` positions that cannot reach every other one, off by default
+///
+/// Without any `@core` state, every position of the graph is core.
+pub struct CoreConnectivity;
+
+impl Lint for CoreConnectivity {
+    fn name(&self) -> &'static str {
+        "core-connectivity"
+    }
+
+    fn level(&self) -> Level {
+        Level::Allow
+    }
+
+    fn check(&self, system: &MartialSystem) -> Vec<Diagnostic> {
+        let graph = MartialGraph::from_system(system);
+        let marked = system.states.values().any(|state| state.is_core());
+        let core: Vec<&Node> = graph
+            .nodes
+            .iter()
+            .filter(|node| !marked || system.states.get(node.state.as_str()).is_some_and(|state| state.is_core()))
+            .collect();
+        let mut diagnostics = Vec::new();
+        for node in &core {
+            let reachable = graph.reachable_from(node);
+            let missing: Vec<String> = core
+                .iter()
+                .filter(|other| *other != node && !reachable.contains(**other))
+                .map(|other| other.id())
+                .collect();
+            if missing.is_empty() {
+                continue;
+            }
+            let diagnostic = Diagnostic::new(
+                self.name(),
+                format!("Core position {} cannot reach {}", node.id(), missing.join(", ")),
+            );
+            diagnostics.push(match system.states.get(node.state.as_str()) {
+                Some(state) => diagnostic.at(system.origin("state", &state.name), state.position),
+                None => diagnostic,
+            });
+        }
+        diagnostics
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loader::{load_sources, SourceFile};
+
+    /// Sequences must not end in Guard
+    struct NoGuardFinish;
+
+    impl Lint for NoGuardFinish {
+        fn name(&self) -> &'static str {
+            "no-guard-finish"
+        }
+
+        fn level(&self) -> Level {
+            Level::Allow
+        }
+
+        fn check(&self, system: &MartialSystem) -> Vec<Diagnostic> {
+            system
+                .sequences
+                .values()
+                .filter(|s| s.steps.last().is_some_and(|step| step.to.state == "Guard"))
+                .map(|s| Diagnostic::new(self.name(), format!("Sequence {} ends in Guard", s.name)))
+                .collect()
+        }
+    }
+
+    fn system() -> MartialSystem {
+        let source = "roles { Top, Bottom, Referee }\nstate Mount\nstate Guard\nstate Turtle\n\
+                      sequence Sweep:\n    Roll: Guard[Bottom] -> Mount[Top]\n\
+                      sequence Lose:\n    Slip: Mount[Top] -> Guard[Top]";
+        load_sources("test", &[SourceFile::new("a.martial", source)]).unwrap()
+    }
+
+    #[test]
+    fn test_custom_lint() {
+        let mut registry = LintRegistry::builtin();
+        registry.register(Box::new(NoGuardFinish));
+        assert_eq!(registry.names().last(), Some(&"no-guard-finish"));
+        assert!(!registry.default_names().contains(&"no-guard-finish"));
+
+        let codes = |diagnostics: Vec<Diagnostic>| diagnostics.into_iter().map(|d| d.code).collect::<Vec<_>>();
+        let system = system();
+        assert_eq!(codes(registry.check(&system, None)), ["unused-role", "unused-state"]);
+
+        let enabled = ["no-guard-finish".to_string()];
+        let diagnostics = registry.check(&system, Some(&enabled));
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].to_string(), "Sequence Lose ends in Guard");
+        assert_eq!(diagnostics[0].level, Level::Warn);
+
+        let names = ["unused-role".to_string(), "everything".to_string()];
+        assert_eq!(registry.unknown(&names), ["everything"]);
+
+        assert!(registry.set_level("no-guard-finish", Level::Deny));
+        assert!(!registry.set_level("everything", Level::Deny));
+        assert!(registry.default_names().contains(&"no-guard-finish"));
+        let diagnostics = registry.check(&system, None);
+        assert_eq!(diagnostics.last().map(|d| d.level), Some(Level::Deny));
+    }
+
+    #[test]
+    fn test_builtin_lints() {
+        let system = system();
+        let diagnostics = LintRegistry::builtin().check(&system, None);
+        assert_eq!(diagnostics[0].to_string(), "Role Referee is not used by any step (a.martial, line 1, column 1)");
+        assert_eq!(diagnostics[1].message, "State Turtle is not used by any step");
+        assert_eq!(diagnostics[1].position.map(|p| p.line), Some(4));
+        assert_eq!(UnusedState.level(), Level::Warn);
+    }
+
+    #[test]
+    fn test_profiles() {
+        let source = "roles { Tori, Uke }\nstate Kamae\nstate Uchi\nstate Zanshin @final @core\nstate Yoi @core\n\
+                      sequence Ippon:\n    Step: Kamae[Tori] -> Uchi[Tori]\n    Strike: Uchi[Tori] -> Zanshin[Tori]\n\
+                      sequence Back:\n    Step: Kamae[Tori] -> Uchi[Tori]\n    Return: Uchi[Tori] -> Kamae[Tori]\n\
+                      sequence Bow:\n    Rei: Zanshin[Tori] -> Yoi[Tori]";
+        let system = load_sources("test", &[SourceFile::new("a.martial", source)]).unwrap();
+        let registry = LintRegistry::builtin();
+        let messages = |diagnostics: Vec<Diagnostic>| diagnostics.into_iter().map(|d| d.message).collect::<Vec<_>>();
+
+        let kata = Profile::from_name("kata").unwrap();
+        let diagnostics = registry.check_profile(&system, &kata);
+        assert!(diagnostics.iter().all(|d| d.level == Level::Deny));
+        assert_eq!(
+            messages(diagnostics),
+            [
+                "Sequence Back is not linear: step 2 (Return) returns to Kamae[Tori]",
+                "Sequence Back ends in Kamae, which is not marked @final",
+                "Sequence Bow ends in Yoi, which is not marked @final",
+            ]
+        );
+
+        let sparring = Profile::from_name("sparring").unwrap();
+        assert_eq!(
+            messages(registry.check_profile(&system, &sparring)),
+            ["Core position Yoi[Tori] cannot reach Zanshin[Tori]"]
+        );
+        assert!(Profile::from_name("randori").is_none());
+        assert!(registry.check(&system, None).iter().all(|d| d.code.starts_with("unused")));
+    }
+}
@@ -0,0 +1,1010 @@
+//! Loading of martial systems from source files
+//!
+//! Runs the lexer, parser and semantic validator over a set of `.martial`
+//! sources. The CLI and editor integrations share this pipeline so that
+//! diagnostics are attributed to files the same way everywhere.
+//!
+//! With the `parallel` feature (on by default), files are lexed and parsed
+//! on a thread pool. Results are kept in source order, so diagnostics are
+//! the same as when parsing serially.
+//!
+//! Reading directories and the [`ValidationCache`] need the `std` feature;
+//! parsing and validation of in-memory sources do not.
+
+use crate::ast::{Declaration, MartialFile};
+use crate::conditional::{self, ConditionalError};
+#[cfg(feature = "std")]
+use crate::config::{Config, Layer, LayerKind, CONFIG_FILES};
+use crate::prelude::*;
+use crate::lexer::{LexError, Lexer, Position};
+use crate::metadata::{self, MetadataError};
+use crate::parser::{ParseError, Parser};
+use crate::semantic::{MartialSystem, SemanticError, SemanticValidator, ValidationReport};
+#[cfg(feature = "std")]
+use crate::lint::Level;
+#[cfg(feature = "std")]
+use crate::semantic::Diagnostic;
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+use core::fmt;
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "std")]
+use std::collections::hash_map::DefaultHasher;
+#[cfg(feature = "std")]
+use std::collections::HashMap;
+#[cfg(feature = "std")]
+use std::fs;
+#[cfg(feature = "std")]
+use std::hash::{Hash, Hasher};
+#[cfg(feature = "std")]
+use std::path::Path;
+
+/// A `.martial` source held in memory
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SourceFile {
+    pub path: String,
+    pub content: String,
+    /// Namespace its declarations are qualified with, for a file of a
+    /// dependency, see [`MartialFile::qualify`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub namespace: Option<String>,
+}
+
+impl SourceFile {
+    pub fn new(path: impl Into<String>, content: impl Into<String>) -> Self {
+        SourceFile {
+            path: path.into(),
+            content: content.into(),
+            namespace: None,
+        }
+    }
+}
+
+/// Error raised while loading a system
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum LoadError {
+    /// A file or directory could not be read
+    Io { path: String, message: String },
+    /// The directory contains no `.martial` files
+    NoSources { path: String },
+    Metadata { file: String, error: MetadataError },
+    Conditional { file: String, error: ConditionalError },
+    Lex { file: String, error: LexError },
+    Parse { file: String, error: ParseError },
+    Semantic(SemanticError),
+}
+
+impl LoadError {
+    /// File the error refers to, if it can be attributed to one
+    pub fn file(&self) -> Option<&str> {
+        match self {
+            LoadError::Io { path, .. } => Some(path),
+            LoadError::NoSources { .. } => None,
+            LoadError::Metadata { file, .. }
+            | LoadError::Conditional { file, .. }
+            | LoadError::Lex { file, .. }
+            | LoadError::Parse { file, .. } => Some(file),
+            LoadError::Semantic(error) => error.file.as_deref(),
+        }
+    }
+
+    /// Position the error refers to, if known
+    pub fn position(&self) -> Option<Position> {
+        match self {
+            LoadError::Metadata { error, .. } => Some(Position {
+                line: error.line,
+                column: 1,
+            }),
+            LoadError::Conditional { error, .. } => Some(Position {
+                line: error.line,
+                column: 1,
+            }),
+            LoadError::Lex { error, .. } => Some(error.position),
+            LoadError::Parse { error, .. } => Some(error.position),
+            LoadError::Semantic(error) => error.position,
+            _ => None,
+        }
+    }
+
+    /// Description of the error without its location
+    pub fn message(&self) -> String {
+        match self {
+            LoadError::Metadata { error, .. } => error.message.clone(),
+            LoadError::Conditional { error, .. } => error.message.clone(),
+            LoadError::Lex { error, .. } => error.message.clone(),
+            LoadError::Parse { error, .. } => error.message.clone(),
+            LoadError::Semantic(error) => format!("{}: {}", error.context, error.message),
+            other => other.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Io { path, message } => write!(f, "Error reading {}: {}", path, message),
+            LoadError::NoSources { path } => write!(f, "No .martial files found in {}", path),
+            LoadError::Metadata { file, error } => write!(f, "Metadata error in {}: {}", file, error),
+            LoadError::Conditional { file, error } => write!(f, "Conditional section error in {}: {}", file, error),
+            LoadError::Lex { file, error } => write!(f, "Lexer error in {}: {}", file, error),
+            LoadError::Parse { file, error } => write!(f, "Parse error in {}: {}", file, error),
+            LoadError::Semantic(error) => match &error.file {
+                Some(file) => write!(f, "{} ({})", error, file),
+                None => write!(f, "{}", error),
+            },
+        }
+    }
+}
+
+impl core::error::Error for LoadError {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            LoadError::Metadata { error, .. } => Some(error),
+            LoadError::Conditional { error, .. } => Some(error),
+            LoadError::Lex { error, .. } => Some(error),
+            LoadError::Parse { error, .. } => Some(error),
+            LoadError::Io { .. } | LoadError::NoSources { .. } | LoadError::Semantic(_) => None,
+        }
+    }
+}
+
+/// Find all `.martial` files in a directory, sorted by path
+#[cfg(feature = "std")]
+pub fn find_martial_files(dir_path: &str) -> Result<Vec<String>, std::io::Error> {
+    let mut files = Vec::new();
+
+    for entry in fs::read_dir(dir_path)? {
+        let entry = entry?;
+        let path = entry.path();
+
+        if path.is_file() {
+            if let Some(ext) = path.extension() {
+                if ext == "martial" {
+                    if let Some(path_str) = path.to_str() {
+                        files.push(path_str.to_string());
+                    }
+                }
+            }
+        }
+    }
+
+    files.sort();
+    Ok(files)
+}
+
+/// Derive a system name from its directory
+#[cfg(feature = "std")]
+pub fn system_name(dir_path: &str) -> String {
+    Path::new(dir_path)
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or("unknown")
+        .to_string()
+}
+
+/// Read every `.martial` file of a directory into memory
+#[cfg(feature = "std")]
+pub fn read_directory(dir_path: &str) -> Result<Vec<SourceFile>, LoadError> {
+    let files = find_martial_files(dir_path).map_err(|e| LoadError::Io {
+        path: dir_path.to_string(),
+        message: e.to_string(),
+    })?;
+    read_files(files)
+}
+
+/// Read the files of the system in a directory, as selected by the
+/// include and exclude globs of `config`, after the `.martial` files of its
+/// shared libraries and the files of its dependencies
+///
+/// Files of a dependency are tagged with its namespace, and left out of the
+/// system's own files.
+#[cfg(feature = "std")]
+pub fn read_configured(dir_path: &str, config: &Config) -> Result<Vec<SourceFile>, LoadError> {
+    let layers = config.layers(dir_path);
+    let mut sources = Vec::new();
+    for layer in &layers {
+        let found = match layer.kind {
+            LayerKind::Shared => find_martial_files(&layer.dir),
+            LayerKind::Dependency => {
+                if !Path::new(&layer.dir).is_dir() {
+                    return Err(LoadError::Io {
+                        path: layer.dir.clone(),
+                        message: format!("dependency '{}' is not fetched, run `mat add` first", layer.name),
+                    });
+                }
+                Config::load(&layer.dir)
+                    .map_err(|e| LoadError::Io {
+                        path: layer.dir.clone(),
+                        message: e.to_string(),
+                    })
+                    .map(|dependency| dependency.find_files(&layer.dir))?
+            }
+            LayerKind::System => config.find_files(&layer.dir).map(|files| {
+                files
+                    .into_iter()
+                    .filter(|file| Layer::of(&layers, file) == Some(layer))
+                    .collect()
+            }),
+        };
+        let found = found.map_err(|e| LoadError::Io {
+            path: layer.dir.clone(),
+            message: e.to_string(),
+        })?;
+        for mut source in read_files(found)? {
+            source.namespace = layer.namespace().map(str::to_string);
+            sources.push(source);
+        }
+    }
+    Ok(sources)
+}
+
+#[cfg(feature = "std")]
+fn read_files(files: Vec<String>) -> Result<Vec<SourceFile>, LoadError> {
+    let mut sources = Vec::new();
+    for path in files {
+        let content = fs::read_to_string(&path).map_err(|e| LoadError::Io {
+            path: path.clone(),
+            message: e.to_string(),
+        })?;
+        sources.push(SourceFile::new(path, content));
+    }
+    Ok(sources)
+}
+
+/// Lex and parse a single source, tagging the AST with its path and metadata
+pub fn parse_source(source: &SourceFile) -> Result<MartialFile, LoadError> {
+    parse_source_with(source, false)
+}
+
+/// Like [`parse_source`], accepting constructs removed from the version of
+/// the file if `compat` is set, see [`Parser::compat`]
+pub fn parse_source_with(source: &SourceFile, compat: bool) -> Result<MartialFile, LoadError> {
+    parse_source_defining(source, compat, &[])
+}
+
+/// Like [`parse_source_with`], keeping the conditional sections of the
+/// flags in `defines`, see [`crate::conditional`]
+pub fn parse_source_defining(source: &SourceFile, compat: bool, defines: &[String]) -> Result<MartialFile, LoadError> {
+    let metadata = metadata::parse(&source.content).map_err(|error| LoadError::Metadata {
+        file: source.path.clone(),
+        error,
+    })?;
+    let content = conditional::evaluate(&source.content, defines).map_err(|error| LoadError::Conditional {
+        file: source.path.clone(),
+        error,
+    })?;
+
+    let mut lexer = Lexer::new(&content);
+    let tokens = lexer.tokenize().map_err(|error| LoadError::Lex {
+        file: source.path.clone(),
+        error,
+    })?;
+
+    let mut parser = Parser::new(tokens).compat(compat);
+    let mut file = parser.parse().map_err(|error| LoadError::Parse {
+        file: source.path.clone(),
+        error,
+    })?;
+    file.source = Some(source.path.clone());
+    file.metadata = metadata;
+    if let Some(namespace) = &source.namespace {
+        file.qualify(namespace);
+    }
+    Ok(file)
+}
+
+/// Parse every source, in parallel if enabled, returning results in source order
+pub fn parse_sources(sources: &[SourceFile]) -> Vec<Result<MartialFile, LoadError>> {
+    #[cfg(feature = "parallel")]
+    let results = sources.par_iter().map(parse_source).collect();
+    #[cfg(not(feature = "parallel"))]
+    let results = sources.iter().map(parse_source).collect();
+    results
+}
+
+/// A state or sequence and the file that declares it
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Origin {
+    /// `state` or `sequence`
+    pub kind: &'static str,
+    pub name: String,
+    pub file: String,
+    pub position: Position,
+}
+
+/// States and sequences of parsed files, in file and declaration order
+pub fn origins(files: &[MartialFile]) -> Vec<Origin> {
+    let mut origins = Vec::new();
+    for file in files {
+        let path = file.source.clone().unwrap_or_default();
+        for declaration in &file.declarations {
+            let (kind, name, position) = match declaration {
+                Declaration::State(state) => ("state", &state.name, state.position),
+                Declaration::Sequence(sequence) => ("sequence", &sequence.name, sequence.position),
+                _ => continue,
+            };
+            origins.push(Origin {
+                kind,
+                name: name.clone(),
+                file: path.clone(),
+                position,
+            });
+        }
+    }
+    origins
+}
+
+/// Validate a system from files that have already been parsed
+pub fn validate_files(system_name: &str, files: &[MartialFile]) -> Result<MartialSystem, LoadError> {
+    validate_report(system_name, files).map(|report| report.system)
+}
+
+/// Validate a system from files that have already been parsed, keeping the
+/// warnings found along the way
+pub fn validate_report(system_name: &str, files: &[MartialFile]) -> Result<ValidationReport, LoadError> {
+    validate_report_with(system_name, files, SemanticValidator::new())
+}
+
+/// Like [`validate_report`], with a validator configured beforehand, such as
+/// by [`SemanticValidator::duplicates`]
+pub fn validate_report_with(
+    system_name: &str,
+    files: &[MartialFile],
+    mut validator: SemanticValidator,
+) -> Result<ValidationReport, LoadError> {
+    for file in files {
+        validator.add_file(file.clone()).map_err(LoadError::Semantic)?;
+    }
+    validator
+        .validate(system_name.to_string())
+        .map_err(LoadError::Semantic)
+}
+
+/// Parse and validate a system from in-memory sources
+///
+/// Fails with the error of the first file, in source order, that does not parse.
+pub fn load_sources(system_name: &str, sources: &[SourceFile]) -> Result<MartialSystem, LoadError> {
+    let files = parse_sources(sources).into_iter().collect::<Result<Vec<_>, _>>()?;
+    validate_files(system_name, &files)
+}
+
+/// Parse every source and, if they all parse, validate the system
+///
+/// Unlike [`load_sources`], keeps going after a file fails to parse so that
+/// every syntax error is reported. Returns the parsed files with the errors.
+pub fn check_sources(system_name: &str, sources: &[SourceFile]) -> (Vec<MartialFile>, Vec<LoadError>) {
+    let mut files = Vec::new();
+    let mut errors = Vec::new();
+
+    for result in parse_sources(sources) {
+        match result {
+            Ok(file) => files.push(file),
+            Err(error) => errors.push(error),
+        }
+    }
+
+    // Cross-file checks only make sense once every file parses
+    if errors.is_empty() {
+        if let Err(error) = validate_files(system_name, &files) {
+            errors.push(error);
+        }
+    }
+
+    (files, errors)
+}
+
+/// Parse results of previous runs, keyed on file path and content hash
+///
+/// Meant for long-running tools such as the language server, which
+/// re-validate a system after every edit: files whose content is unchanged
+/// skip lexing and parsing, and only the cross-file semantic pass re-runs.
+/// Results are dropped when the parse settings of the [`Config`] change.
+#[cfg(feature = "std")]
+#[derive(Debug, Default)]
+pub struct ValidationCache {
+    entries: HashMap<String, (u64, Result<MartialFile, LoadError>)>,
+    /// Hash of the settings the entries were parsed with
+    settings: u64,
+}
+
+#[cfg(feature = "std")]
+impl ValidationCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of files with a cached parse result
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Whether `source` would be served from the cache
+    pub fn contains(&self, source: &SourceFile) -> bool {
+        matches!(self.entries.get(&source.path), Some((hash, _)) if *hash == content_hash(source))
+    }
+
+    /// Drop the cached result of a file, forcing it to be parsed again
+    pub fn invalidate(&mut self, path: &str) {
+        self.entries.remove(path);
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Same as [`check_sources`] with the settings of `config`, parsing only
+    /// files that changed since the last call
+    ///
+    /// Files that are no longer among `sources` are evicted.
+    pub fn check(
+        &mut self,
+        system_name: &str,
+        sources: &[SourceFile],
+        config: &Config,
+    ) -> (Vec<MartialFile>, Vec<LoadError>) {
+        let settings = settings_hash(config);
+        if settings != self.settings {
+            self.entries.clear();
+            self.settings = settings;
+        }
+        let stale: Vec<SourceFile> = sources
+            .iter()
+            .filter(|source| !self.contains(source))
+            .cloned()
+            .collect();
+        #[cfg(feature = "parallel")]
+        let results: Vec<_> = stale
+            .par_iter()
+            .map(|source| parse_source_defining(source, config.compat, &config.defines))
+            .collect();
+        #[cfg(not(feature = "parallel"))]
+        let results: Vec<_> = stale
+            .iter()
+            .map(|source| parse_source_defining(source, config.compat, &config.defines))
+            .collect();
+        for (source, result) in stale.iter().zip(results) {
+            self.entries.insert(source.path.clone(), (content_hash(source), result));
+        }
+        self.entries.retain(|path, _| sources.iter().any(|s| &s.path == path));
+
+        let mut files = Vec::new();
+        let mut errors = Vec::new();
+        for source in sources {
+            match &self.entries[&source.path].1 {
+                Ok(file) => files.push(file.clone()),
+                Err(error) => errors.push(error.clone()),
+            }
+        }
+        if errors.is_empty() {
+            if let Err(error) = validate_report_with(system_name, &files, config.validator()) {
+                errors.push(error);
+            }
+        }
+        (files, errors)
+    }
+}
+
+/// Hash of what a source parses from: its content and namespace
+#[cfg(feature = "std")]
+fn content_hash(source: &SourceFile) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    source.content.hash(&mut hasher);
+    source.namespace.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Hash of the settings of `config` that change how sources parse
+#[cfg(feature = "std")]
+fn settings_hash(config: &Config) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    config.compat.hash(&mut hasher);
+    config.defines.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Directories under `root`, itself included, that hold a system: one with
+/// `.martial` files or a project file; sorted by path
+///
+/// Directories within a system are part of it, as its config may include
+/// them, and are not searched for systems of their own. Neither are the
+/// shared libraries of the systems found.
+#[cfg(feature = "std")]
+pub fn find_systems(root: &str) -> Result<Vec<String>, LoadError> {
+    fn walk(dir: &Path, systems: &mut Vec<String>) -> std::io::Result<()> {
+        let mut entries: Vec<std::path::PathBuf> = fs::read_dir(dir)?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<Result<_, _>>()?;
+        entries.sort();
+        let is_system = entries.iter().any(|path| {
+            path.is_file()
+                && (path.extension().is_some_and(|ext| ext == "martial")
+                    || path.file_name().and_then(|n| n.to_str()).is_some_and(|n| CONFIG_FILES.contains(&n)))
+        });
+        if is_system {
+            systems.push(dir.to_string_lossy().into_owned());
+            return Ok(());
+        }
+        for path in entries.iter().filter(|path| path.is_dir()) {
+            walk(path, systems)?;
+        }
+        Ok(())
+    }
+
+    let mut systems = Vec::new();
+    walk(Path::new(root), &mut systems).map_err(|e| LoadError::Io {
+        path: root.to_string(),
+        message: e.to_string(),
+    })?;
+    let shared: Vec<std::path::PathBuf> = systems
+        .iter()
+        .filter_map(|dir| Some(Config::load(dir).ok()?.layers(dir)))
+        .flatten()
+        .filter(|layer| layer.is_external())
+        .filter_map(|layer| fs::canonicalize(&layer.dir).ok())
+        .collect();
+    systems.retain(|dir| fs::canonicalize(dir).map_or(true, |dir| !shared.contains(&dir)));
+    Ok(systems)
+}
+
+/// The outcome of checking one system, see [`check_systems`]
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SystemCheck {
+    /// Directory of the system
+    pub path: String,
+    pub name: String,
+    pub files: usize,
+    /// States and sequences of the system, 0 if it does not validate
+    pub states: usize,
+    pub sequences: usize,
+    /// Every parse error, or else the validation error
+    pub errors: Vec<LoadError>,
+    /// Findings of the lints its project config enables, failing the
+    /// check at [`Level::Deny`]
+    pub warnings: Vec<Diagnostic>,
+}
+
+#[cfg(feature = "std")]
+impl SystemCheck {
+    /// Findings of the lints run at [`Level::Deny`]
+    pub fn denied(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.warnings.iter().filter(|warning| warning.level == Level::Deny)
+    }
+
+    /// Whether the system validates without any denied finding
+    pub fn passed(&self) -> bool {
+        self.errors.is_empty() && self.denied().next().is_none()
+    }
+}
+
+/// Check every system under `root`, each with its own project config, in
+/// parallel if enabled; results are in path order
+#[cfg(feature = "std")]
+pub fn check_systems(root: &str) -> Result<Vec<SystemCheck>, LoadError> {
+    let systems = find_systems(root)?;
+    #[cfg(feature = "parallel")]
+    let checks = systems.par_iter().map(|dir| check_system(dir)).collect();
+    #[cfg(not(feature = "parallel"))]
+    let checks = systems.iter().map(|dir| check_system(dir)).collect();
+    Ok(checks)
+}
+
+#[cfg(feature = "std")]
+fn check_system(dir: &str) -> SystemCheck {
+    let mut check = SystemCheck {
+        path: dir.to_string(),
+        name: system_name(dir),
+        files: 0,
+        states: 0,
+        sequences: 0,
+        errors: Vec::new(),
+        warnings: Vec::new(),
+    };
+    let config = match Config::load(dir) {
+        Ok(config) => config,
+        Err(e) => {
+            let path = Config::find(dir).unwrap_or_else(|| dir.to_string());
+            check.errors.push(LoadError::Io { path, message: e.to_string() });
+            return check;
+        }
+    };
+    let sources = match read_configured(dir, &config) {
+        Ok(sources) if sources.is_empty() => {
+            check.errors.push(LoadError::NoSources { path: dir.to_string() });
+            return check;
+        }
+        Ok(sources) => sources,
+        Err(e) => {
+            check.errors.push(e);
+            return check;
+        }
+    };
+    check.files = sources.len();
+
+    let mut files = Vec::new();
+    for source in &sources {
+        match parse_source_defining(source, config.compat, &config.defines) {
+            Ok(file) => files.push(file),
+            Err(error) => check.errors.push(error),
+        }
+    }
+    if !check.errors.is_empty() {
+        return check;
+    }
+    match validate_report_with(&check.name, &files, config.validator()) {
+        Ok(ValidationReport { system, warnings }) => {
+            check.states = system.states.len();
+            check.sequences = system.sequences.len();
+            check.warnings = warnings;
+        }
+        Err(error) => check.errors.push(error),
+    }
+    check
+}
+
+/// Parse and validate the system stored in a directory
+#[cfg(feature = "std")]
+pub fn load_directory(dir_path: &str) -> Result<MartialSystem, LoadError> {
+    let sources = read_directory(dir_path)?;
+    if sources.is_empty() {
+        return Err(LoadError::NoSources {
+            path: dir_path.to_string(),
+        });
+    }
+    load_sources(&system_name(dir_path), &sources)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::semantic::DuplicatePolicy;
+
+    #[test]
+    fn test_load_sources() {
+        let sources = vec![
+            SourceFile::new("roles.martial", "roles { Top, Bottom }"),
+            SourceFile::new("states.martial", "state Mount\nstate Guard"),
+            SourceFile::new(
+                "sequences.martial",
+                "sequence Escape:\n    Shrimp: Mount[Bottom] -> Guard[Bottom]",
+            ),
+        ];
+
+        let system = load_sources("test", &sources).unwrap();
+        assert_eq!(system.states.len(), 2);
+        assert_eq!(system.sequences.len(), 1);
+    }
+
+    #[test]
+    fn test_sequences_in_declaration_order() {
+        let sources = vec![
+            SourceFile::new("a.martial", "roles { Top, Bottom }\nstate Mount\nstate Guard"),
+            SourceFile::new(
+                "b.martial",
+                "sequence Sweep:\n    Hip: Guard[Bottom] -> Mount[Top]\n\
+                 sequence Escape:\n    Shrimp: Mount[Bottom] -> Guard[Bottom]",
+            ),
+            SourceFile::new("c.martial", "sequence Bridge:\n    Upa: Mount[Bottom] -> Guard[Top]"),
+        ];
+
+        let mut system = load_sources("test", &sources).unwrap();
+        let names = |system: &MartialSystem| -> Vec<String> {
+            system.sequences_ordered().iter().map(|s| s.name.clone()).collect()
+        };
+        assert_eq!(names(&system), ["Sweep", "Escape", "Bridge"]);
+
+        // Sequences added by hand come last, by name
+        let extra = system.sequences["Sweep"].clone();
+        system.sequences.insert("Armbar".to_string(), crate::ast::Sequence { name: "Armbar".to_string(), ..extra });
+        system.sequences.remove("Escape");
+        assert_eq!(names(&system), ["Sweep", "Bridge", "Armbar"]);
+    }
+
+    #[test]
+    fn test_semantic_error_is_attributed_to_file() {
+        let sources = vec![
+            SourceFile::new("roles.martial", "roles { Top }"),
+            SourceFile::new("states.martial", "state Mount"),
+            SourceFile::new(
+                "sequences.martial",
+                "sequence Escape:\n    Shrimp: Mount[Top] -> Guard[Top]",
+            ),
+        ];
+
+        let error = load_sources("test", &sources).unwrap_err();
+        assert_eq!(error.file(), Some("sequences.martial"));
+        match error {
+            LoadError::Semantic(e) => {
+                assert_eq!(e.position.map(|p| p.line), Some(2));
+            }
+            other => panic!("Expected semantic error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_check_sources_reports_every_parse_error() {
+        let sources = vec![
+            SourceFile::new("a.martial", "state"),
+            SourceFile::new("b.martial", "roles { Top }"),
+            SourceFile::new("c.martial", "group {"),
+        ];
+        let (files, errors) = check_sources("test", &sources);
+        assert_eq!(files.len(), 1);
+        let attributed: Vec<_> = errors.iter().map(|e| e.file()).collect();
+        assert_eq!(attributed, vec![Some("a.martial"), Some("c.martial")]);
+    }
+
+    #[test]
+    fn test_parse_sources_keeps_source_order() {
+        let sources: Vec<SourceFile> = (0..64)
+            .map(|i| {
+                let content = if i % 3 == 0 { format!("state S{}\nstate", i) } else { format!("state S{}", i) };
+                SourceFile::new(format!("{:02}.martial", i), content)
+            })
+            .collect();
+        let results = parse_sources(&sources);
+        assert_eq!(results.len(), sources.len());
+        for (i, result) in results.iter().enumerate() {
+            match result {
+                Ok(file) => assert_eq!(file.source.as_deref(), Some(sources[i].path.as_str())),
+                Err(error) => {
+                    assert_eq!(i % 3, 0);
+                    assert_eq!(error.file(), Some(sources[i].path.as_str()));
+                }
+            }
+        }
+        assert_eq!(load_sources("test", &sources).unwrap_err().file(), Some("00.martial"));
+    }
+
+    #[test]
+    fn test_validation_cache() {
+        let mut sources = vec![
+            SourceFile::new("roles.martial", "roles { Top }"),
+            SourceFile::new("states.martial", "state Mount"),
+            SourceFile::new("sequences.martial", "sequence Hold:\n    Settle: Mount[Top] -> Mount[Top]"),
+        ];
+        let config = Config::default();
+        let mut cache = ValidationCache::new();
+        let (files, errors) = cache.check("test", &sources, &config);
+        assert_eq!((files.len(), errors.len()), (3, 0));
+        assert_eq!(cache.len(), 3);
+
+        // Only the edited file is parsed again, but cross-file checks re-run
+        sources[1].content = "state Back".to_string();
+        assert!(cache.contains(&sources[0]) && !cache.contains(&sources[1]));
+        let (_, errors) = cache.check("test", &sources, &config);
+        assert_eq!(errors[0].file(), Some("sequences.martial"));
+
+        sources[1].content = "state".to_string();
+        let (files, errors) = cache.check("test", &sources, &config);
+        assert_eq!((files.len(), errors[0].file()), (2, Some("states.martial")));
+
+        cache.invalidate("roles.martial");
+        assert!(!cache.contains(&sources[0]));
+        sources.remove(2);
+        cache.check("test", &sources, &config);
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.check("test", &sources, &config), check_sources("test", &sources));
+    }
+
+    #[test]
+    fn test_validation_cache_settings() {
+        let sources = vec![
+            SourceFile::new("roles.martial", "roles { Top }"),
+            SourceFile::new("states.martial", "martial 2\nstate Mount roles { Top }\nstate Mount[Top]"),
+        ];
+        let mut config = Config::default();
+        let mut cache = ValidationCache::new();
+        let (_, errors) = cache.check("test", &sources, &config);
+        assert!(errors[0].message().contains("martial 1 syntax"), "{:?}", errors);
+
+        // Files parsed without compat are parsed again with it
+        config.compat = true;
+        let (_, errors) = cache.check("test", &sources, &config);
+        assert!(errors[0].message().contains("Mount"), "{:?}", errors);
+
+        config.duplicates = DuplicatePolicy::FirstWins;
+        let (files, errors) = cache.check("test", &sources, &config);
+        assert_eq!((files.len(), errors.len()), (2, 0));
+    }
+
+    #[test]
+    fn test_validation_cache_defines() {
+        let sources = vec![
+            SourceFile::new("roles.martial", "roles { Top }"),
+            SourceFile::new("states.martial", "state Mount\n#if gi\nstate Lapel\n#endif"),
+            SourceFile::new("sequences.martial", "sequence Grip:\n    Grab: Mount[Top] -> Lapel[Top]"),
+        ];
+        let mut config = Config::default();
+        let mut cache = ValidationCache::new();
+        let (_, errors) = cache.check("test", &sources, &config);
+        assert!(errors[0].message().contains("'Lapel'"), "{:?}", errors);
+
+        config.defines = vec!["gi".to_string()];
+        let (files, errors) = cache.check("test", &sources, &config);
+        assert_eq!((files.len(), errors.len()), (3, 0));
+    }
+
+    #[test]
+    fn test_origins() {
+        let files: Vec<MartialFile> = [
+            ("roles.martial", "roles { Top }"),
+            ("states.martial", "state Mount\nstate Back"),
+            ("sequences.martial", "sequence TakeBack:\n    Roll: Mount[Top] -> Back[Top]"),
+        ]
+        .iter()
+        .map(|(path, content)| parse_source(&SourceFile::new(*path, *content)).unwrap())
+        .collect();
+
+        let found: Vec<_> = origins(&files)
+            .into_iter()
+            .map(|o| (o.kind, o.name, o.file, o.position.line))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                ("state", "Mount".to_string(), "states.martial".to_string(), 1),
+                ("state", "Back".to_string(), "states.martial".to_string(), 2),
+                ("sequence", "TakeBack".to_string(), "sequences.martial".to_string(), 1),
+            ]
+        );
+        assert!(validate_files("test", &files).is_ok());
+    }
+
+    #[test]
+    fn test_front_matter_metadata() {
+        let source = SourceFile::new("states.martial", "---\nauthor: Coach\n---\nstate Mount\nstate");
+        match parse_source(&source).unwrap_err() {
+            LoadError::Parse { error, .. } => assert_eq!(error.position.line, 5),
+            other => panic!("Expected parse error, got {:?}", other),
+        }
+
+        let sources = vec![
+            SourceFile::new("roles.martial", "roles { Top }"),
+            SourceFile::new("states.martial", "---\nauthor: Coach\nlevel: BlueBelt\n---\nstate Mount"),
+        ];
+        let system = load_sources("test", &sources).unwrap();
+        assert_eq!(system.metadata.len(), 1);
+        assert_eq!(system.metadata["states.martial"].level.as_deref(), Some("BlueBelt"));
+
+        let broken = vec![SourceFile::new("roles.martial", "+++\nauthor =\n+++\nroles { Top }")];
+        let error = load_sources("test", &broken).unwrap_err();
+        assert!(matches!(error, LoadError::Metadata { .. }));
+        assert_eq!(error.position().map(|p| p.line), Some(2));
+    }
+
+    #[test]
+    fn test_check_systems() {
+        let root = std::env::temp_dir().join(format!("martial-systems-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let write = |path: &str, content: &str| {
+            let path = root.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        };
+        write("bjj/roles.martial", "roles { Top, Bottom }\nstate Mount\nstate Guard");
+        write("bjj/drills/sweep.martial", "sequence Sweep:\n    Hip: Guard[Bottom] -> Mount[Top]");
+        write("bjj/martial.toml", "include = [\"**/*.martial\"]");
+        write("striking/judo/broken.martial", "roles { Top }\nstate");
+        write("striking/judo/throws.martial", "state");
+        write("notes/readme.txt", "");
+        write("shared/roles.martial", "roles { Tori, Uke }");
+        write("aikido/martial.toml", "shared = [\"../shared\"]");
+        write("aikido/kata.martial", "state Seiza roles { Tori }\nstate Tachi");
+
+        let root_path = root.to_str().unwrap();
+        let systems = find_systems(root_path).unwrap();
+        let names: Vec<String> = systems.iter().map(|dir| system_name(dir)).collect();
+        assert_eq!(names, ["aikido", "bjj", "judo"]);
+
+        let checks = check_systems(root_path).unwrap();
+        // The roles come from the shared library
+        assert_eq!((checks[0].files, checks[0].states), (2, 2));
+        assert!(checks[0].errors.is_empty(), "{:?}", checks[0].errors);
+        assert_eq!((checks[1].files, checks[1].states, checks[1].sequences), (2, 2, 1));
+        assert!(checks[1].errors.is_empty());
+        // Every file that does not parse is reported
+        assert_eq!((checks[2].files, checks[2].errors.len(), checks[2].states), (2, 2, 0));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_shared_libraries() {
+        let root = std::env::temp_dir().join(format!("martial-shared-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let write = |path: &str, content: &str| {
+            let path = root.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        };
+        write("shared/common.martial", "roles { Top, Bottom }\nstate Standing");
+        write("bjj/martial.toml", "shared = [\"../shared\"]");
+        write("bjj/guard.martial", "state Guard roles { Bottom }\nstate Standing");
+
+        let dir = root.join("bjj").to_string_lossy().into_owned();
+        let config = Config::load(&dir).unwrap();
+        let layers = config.layers(&dir);
+        let names: Vec<&str> = layers.iter().map(|layer| layer.name.as_str()).collect();
+        assert_eq!(names, ["shared", "bjj"]);
+
+        // Shared files come first, so the system is the one redeclaring
+        let sources = read_configured(&dir, &config).unwrap();
+        assert_eq!(Layer::of(&layers, &sources[0].path).map(|l| l.kind), Some(LayerKind::Shared));
+        assert_eq!(Layer::of(&layers, &sources[1].path).map(|l| l.kind), Some(LayerKind::System));
+        let error = load_sources("bjj", &sources).unwrap_err();
+        assert_eq!(error.file(), Some(sources[1].path.as_str()));
+        assert!(error.message().ends_with(&format!("(first declared in {})", sources[0].path)), "{}", error);
+        assert!(Layer::of(&layers, &sources[0].path).unwrap().to_string().starts_with("shared library 'shared'"));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_dependencies() {
+        let root = std::env::temp_dir().join(format!("martial-dependencies-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let write = |path: &str, content: &str| {
+            let path = root.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        };
+        write("bjj/martial.toml", "include = [\"**/*.martial\"]\n\n[dependencies]\nwrestling = \"w.martialpkg\"");
+        write(
+            "bjj/vendor/wrestling/takedowns.martial",
+            "roles { Top, Bottom }\nstate Standing\nstate SingleLeg\n\
+             sequence Shot:\n    Shoot: Standing[Top] -> SingleLeg[Top]",
+        );
+        write("bjj/vendor/wrestling/martial.toml", "include = [\"**/*.martial\"]");
+        write("bjj/guard.martial", "state Standing\nsequence Finish:\n    Lift: wrestling.SingleLeg[Top] -> Standing[Top]");
+
+        let dir = root.join("bjj").to_string_lossy().into_owned();
+        let config = Config::load(&dir).unwrap();
+        let sources = read_configured(&dir, &config).unwrap();
+        let namespaces: Vec<Option<&str>> = sources.iter().map(|s| s.namespace.as_deref()).collect();
+        assert_eq!(namespaces, [Some("wrestling"), None]);
+
+        // Both declare Standing, one of them under the namespace
+        let system = load_sources("bjj", &sources).unwrap();
+        let mut states: Vec<&str> = system.states.keys().map(|s| s.as_str()).collect();
+        states.sort();
+        assert_eq!(states, ["Standing", "wrestling.SingleLeg", "wrestling.Standing"]);
+        assert!(system.sequences.contains_key("wrestling.Shot"));
+
+        fs::remove_dir_all(root.join("bjj/vendor")).unwrap();
+        let error = read_configured(&dir, &config).unwrap_err();
+        assert!(error.to_string().contains("run `mat add` first"), "{}", error);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_conditional_sections() {
+        let source = SourceFile::new(
+            "guard.martial",
+            "roles { Top, Bottom }\n#if nogi\nstate Ashi\n#endif\nstate Guard\nstate",
+        );
+        // Lines left out keep the positions of the rest
+        let error = parse_source(&source).unwrap_err();
+        assert_eq!(error.position().map(|p| p.line), Some(6));
+        let source = SourceFile::new("guard.martial", "#if nogi\nstate Ashi\n#endif\nstate Guard");
+        let names = |file: MartialFile| -> Vec<String> { origins(&[file]).into_iter().map(|o| o.name).collect() };
+        assert_eq!(names(parse_source(&source).unwrap()), ["Guard"]);
+        let defined = parse_source_defining(&source, false, &["nogi".to_string()]).unwrap();
+        assert_eq!(names(defined), ["Ashi", "Guard"]);
+
+        let error = parse_source(&SourceFile::new("guard.martial", "#if nogi\nstate Ashi")).unwrap_err();
+        assert!(matches!(error, LoadError::Conditional { .. }));
+        assert_eq!((error.file(), error.position().map(|p| p.line)), (Some("guard.martial"), Some(1)));
+    }
+
+    #[test]
+    fn test_parse_error_is_attributed_to_file() {
+        let sources = vec![SourceFile::new("broken.martial", "state")];
+        let error = load_sources("test", &sources).unwrap_err();
+        assert!(matches!(error, LoadError::Parse { .. }));
+        assert_eq!(error.file(), Some("broken.martial"));
+    }
+}
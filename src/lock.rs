@@ -0,0 +1,209 @@
+//! Lockfiles of validated systems
+//!
+//! `mat lock` writes a `martial.lock` next to the sources of a system, with
+//! the SHA-256 hash of every file and a fingerprint of the system they
+//! resolve to:
+//!
+//! ```toml
+//! version = 1
+//! fingerprint = "9f2c…"
+//!
+//! [[file]]
+//! path = "guard.martial"
+//! sha256 = "4be1…"
+//! ```
+//!
+//! Commands given `--locked`, or run in a project with `locked = true`,
+//! check the sources against it first, so an artifact built from them is
+//! known to come from exactly the locked files.
+
+use crate::loader::SourceFile;
+use crate::semantic::MartialSystem;
+use crate::snapshot;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::path::Path;
+
+/// Name of the lockfile inside a system directory
+pub const LOCK_FILE: &str = "martial.lock";
+
+/// Format version of the lockfile
+pub const LOCK_VERSION: u32 = 1;
+
+/// Contents of a lockfile
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Lockfile {
+    pub version: u32,
+    /// Hash of the validated system, see [`fingerprint`]
+    pub fingerprint: String,
+    /// Files of the system, sorted by path
+    #[serde(rename = "file", default)]
+    pub files: Vec<LockedFile>,
+}
+
+/// A source file and the hash of its content
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LockedFile {
+    /// Path relative to the system directory, `/`-separated
+    pub path: String,
+    pub sha256: String,
+}
+
+/// A difference between a lockfile and the sources
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Mismatch {
+    /// A file whose content changed since it was locked
+    Changed(String),
+    /// A file that is not in the lockfile
+    Added(String),
+    /// A locked file that is no longer part of the system
+    Removed(String),
+    /// The system resolves differently, as under other settings
+    Fingerprint { locked: String, actual: String },
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Mismatch::Changed(path) => write!(f, "{} changed", path),
+            Mismatch::Added(path) => write!(f, "{} is not locked", path),
+            Mismatch::Removed(path) => write!(f, "{} is locked but missing", path),
+            Mismatch::Fingerprint { locked, actual } => {
+                write!(f, "system fingerprint is {}, locked {}", short(actual), short(locked))
+            }
+        }
+    }
+}
+
+fn short(hash: &str) -> &str {
+    &hash[..hash.len().min(12)]
+}
+
+/// Hex-encoded SHA-256 of `bytes`
+pub fn sha256(bytes: &[u8]) -> String {
+    Sha256::digest(bytes).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Hash of the declarations of a validated system, in canonical order
+///
+/// Its name and per-file metadata are left out, as they depend on where the
+/// files are rather than what they declare.
+pub fn fingerprint(system: &MartialSystem) -> String {
+    let mut value = snapshot::normalize_system(system);
+    if let Some(object) = value.as_object_mut() {
+        object.remove("name");
+        object.remove("metadata");
+    }
+    sha256(value.to_string().as_bytes())
+}
+
+/// Path of `path` relative to the system directory `dir`, `/`-separated
+fn relative(dir: &str, path: &str) -> String {
+    let relative = Path::new(path).strip_prefix(dir).unwrap_or(Path::new(path));
+    relative.to_string_lossy().replace('\\', "/")
+}
+
+impl Lockfile {
+    /// Lock the sources of the system in `dir` and the system they resolve to
+    pub fn new(dir: &str, sources: &[SourceFile], system: &MartialSystem) -> Self {
+        let mut files: Vec<LockedFile> = sources
+            .iter()
+            .map(|source| LockedFile {
+                path: relative(dir, &source.path),
+                sha256: sha256(source.content.as_bytes()),
+            })
+            .collect();
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        Lockfile {
+            version: LOCK_VERSION,
+            fingerprint: fingerprint(system),
+            files,
+        }
+    }
+
+    pub fn parse(content: &str) -> Result<Self, String> {
+        let lockfile: Lockfile = toml::from_str(content).map_err(|e: toml::de::Error| e.message().to_string())?;
+        if lockfile.version != LOCK_VERSION {
+            return Err(format!("unsupported lockfile version {}", lockfile.version));
+        }
+        Ok(lockfile)
+    }
+
+    pub fn to_toml(&self) -> String {
+        toml::to_string(self).unwrap_or_default()
+    }
+
+    /// How the sources of the system in `dir` and the system they resolve to
+    /// differ from the lockfile, in file order then the fingerprint
+    pub fn verify(&self, dir: &str, sources: &[SourceFile], system: &MartialSystem) -> Vec<Mismatch> {
+        let current = Lockfile::new(dir, sources, system);
+        let mut mismatches = Vec::new();
+        for file in &current.files {
+            match self.files.iter().find(|locked| locked.path == file.path) {
+                Some(locked) if locked.sha256 != file.sha256 => mismatches.push(Mismatch::Changed(file.path.clone())),
+                Some(_) => {}
+                None => mismatches.push(Mismatch::Added(file.path.clone())),
+            }
+        }
+        for locked in &self.files {
+            if !current.files.iter().any(|file| file.path == locked.path) {
+                mismatches.push(Mismatch::Removed(locked.path.clone()));
+            }
+        }
+        if current.fingerprint != self.fingerprint {
+            mismatches.push(Mismatch::Fingerprint {
+                locked: self.fingerprint.clone(),
+                actual: current.fingerprint,
+            });
+        }
+        mismatches
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loader;
+
+    fn sources(guard: &str) -> Vec<SourceFile> {
+        vec![
+            SourceFile::new("bjj/roles.martial", "roles { Top, Bottom }\nstate Mount"),
+            SourceFile::new("bjj/guard.martial", guard),
+        ]
+    }
+
+    #[test]
+    fn test_sha256() {
+        assert_eq!(sha256(b"abc"), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+    }
+
+    #[test]
+    fn test_lock_and_verify() {
+        let locked_sources = sources("state Guard");
+        let system = loader::load_sources("bjj", &locked_sources).unwrap();
+        let lockfile = Lockfile::new("bjj", &locked_sources, &system);
+        let paths: Vec<&str> = lockfile.files.iter().map(|f| f.path.as_str()).collect();
+        assert_eq!(paths, ["guard.martial", "roles.martial"]);
+        assert_eq!(Lockfile::parse(&lockfile.to_toml()).unwrap(), lockfile);
+        assert!(lockfile.verify("bjj", &locked_sources, &system).is_empty());
+
+        // The same system elsewhere has the same fingerprint
+        let moved = loader::load_sources("club", &locked_sources).unwrap();
+        assert_eq!(fingerprint(&moved), lockfile.fingerprint);
+
+        // A comment changes the file but not the system
+        let commented = sources("// closed guard\nstate Guard");
+        let mismatches = lockfile.verify("bjj", &commented, &system);
+        assert_eq!(mismatches, [Mismatch::Changed("guard.martial".to_string())]);
+
+        let changed = sources("state Guard\nstate Back");
+        let system = loader::load_sources("bjj", &changed).unwrap();
+        let mut mismatches = lockfile.verify("bjj", &changed[..1], &system);
+        assert_eq!(mismatches.remove(0), Mismatch::Removed("guard.martial".to_string()));
+        assert!(matches!(mismatches[0], Mismatch::Fingerprint { .. }));
+        assert!(mismatches[0].to_string().starts_with("system fingerprint is "));
+
+        assert!(Lockfile::parse("version = 2\nfingerprint = \"\"").is_err());
+    }
+}
@@ -0,0 +1,747 @@
+//! Language server for the Martial DSL
+//!
+//! Implements the Language Server Protocol over stdio:
+//! - Diagnostics for the whole system directory on open and save
+//! - Go-to-definition for states, roles, sequences and groups
+//! - Hover showing allowed roles and incident transitions
+//! - Completion of keywords and known identifiers
+//!
+//! A document is analysed together with every other `.martial` file in its
+//! directory; unsaved buffers take precedence over the contents on disk.
+//! Parse results are cached, so only edited files are parsed again.
+//!
+//! Columns are counted in characters inside the crate and in UTF-16 code
+//! units on the wire, as the protocol does by default; they are converted
+//! where positions are read from and written into messages.
+
+use crate::ast::{Declaration, MartialFile};
+use crate::completion::{self, CompletionKind};
+use crate::config::Config;
+use crate::lexer::Position;
+use crate::loader::{self, LoadError, SourceFile, ValidationCache};
+use crate::semantic::{Description, IdentifierKind, ReferenceKind};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+
+// CompletionItemKind values from the LSP specification
+const KIND_METHOD: u32 = 2;
+const KIND_FUNCTION: u32 = 3;
+const KIND_CLASS: u32 = 7;
+const KIND_MODULE: u32 = 9;
+const KIND_KEYWORD: u32 = 14;
+const KIND_ENUM_MEMBER: u32 = 20;
+
+/// Run the language server on stdin/stdout until the client exits
+pub fn run_stdio() -> io::Result<()> {
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    run(stdin.lock(), stdout.lock())
+}
+
+/// Run the language server over arbitrary streams
+pub fn run<R: BufRead, W: Write>(mut reader: R, mut writer: W) -> io::Result<()> {
+    let mut server = Server::default();
+
+    while let Some(message) = read_message(&mut reader)? {
+        for reply in server.handle(&message) {
+            write_message(&mut writer, &reply)?;
+        }
+        if server.exit {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// Read one `Content-Length` framed JSON-RPC message
+fn read_message<R: BufRead>(reader: &mut R) -> io::Result<Option<Value>> {
+    let mut content_length = None;
+
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(value) = line.strip_prefix("Content-Length:") {
+            content_length = value.trim().parse::<usize>().ok();
+        }
+    }
+
+    let length = content_length.ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData, "missing Content-Length header")
+    })?;
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Write one `Content-Length` framed JSON-RPC message
+fn write_message<W: Write>(writer: &mut W, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(writer, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    writer.flush()
+}
+
+/// Language server state
+#[derive(Default)]
+struct Server {
+    /// Open documents by file path
+    documents: HashMap<String, String>,
+    /// Parse results by system directory
+    caches: HashMap<String, ValidationCache>,
+    exit: bool,
+}
+
+/// Parsed view of one system directory
+struct Analysis {
+    sources: Vec<SourceFile>,
+    files: Vec<MartialFile>,
+    diagnostics: HashMap<String, Vec<Value>>,
+}
+
+impl Server {
+    /// Handle a message and return the messages to send back
+    fn handle(&mut self, message: &Value) -> Vec<Value> {
+        let method = message["method"].as_str().unwrap_or("");
+        let params = &message["params"];
+        let id = message.get("id").cloned();
+
+        let result = match method {
+            "initialize" => Some(json!({
+                "capabilities": {
+                    "textDocumentSync": { "openClose": true, "change": 1, "save": { "includeText": true } },
+                    "definitionProvider": true,
+                    "hoverProvider": true,
+                    "referencesProvider": true,
+                    "completionProvider": { "triggerCharacters": ["["] }
+                },
+                "serverInfo": { "name": "mat", "version": env!("CARGO_PKG_VERSION") }
+            })),
+            "shutdown" => Some(Value::Null),
+            "exit" => {
+                self.exit = true;
+                None
+            }
+            "textDocument/didOpen" => {
+                let path = document_path(params);
+                let text = params["textDocument"]["text"].as_str().unwrap_or("").to_string();
+                self.documents.insert(path.clone(), text);
+                return self.publish_diagnostics(&path);
+            }
+            "textDocument/didChange" => {
+                let path = document_path(params);
+                if let Some(change) = params["contentChanges"].as_array().and_then(|c| c.last()) {
+                    if let Some(text) = change["text"].as_str() {
+                        self.documents.insert(path, text.to_string());
+                    }
+                }
+                None
+            }
+            "textDocument/didSave" => {
+                let path = document_path(params);
+                if let Some(text) = params["text"].as_str() {
+                    self.documents.insert(path.clone(), text.to_string());
+                }
+                return self.publish_diagnostics(&path);
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(&document_path(params));
+                None
+            }
+            "textDocument/definition" => Some(self.definition(params)),
+            "textDocument/hover" => Some(self.hover(params)),
+            "textDocument/references" => Some(self.references(params)),
+            "textDocument/completion" => Some(self.completion(params)),
+            _ => {
+                // Unknown requests get an error, unknown notifications are ignored
+                return match id {
+                    Some(id) => vec![json!({
+                        "jsonrpc": "2.0",
+                        "id": id,
+                        "error": { "code": -32601, "message": format!("Method not found: {}", method) }
+                    })],
+                    None => Vec::new(),
+                };
+            }
+        };
+
+        match (id, result) {
+            (Some(id), Some(result)) => vec![json!({ "jsonrpc": "2.0", "id": id, "result": result })],
+            _ => Vec::new(),
+        }
+    }
+
+    /// Lex, parse and validate every file in the directory of `path`
+    fn analyze(&mut self, path: &str) -> Analysis {
+        let dir = Path::new(path)
+            .parent()
+            .and_then(|p| p.to_str())
+            .unwrap_or(".")
+            .to_string();
+
+        // Files on disk, overridden by open buffers from the same directory
+        let config = Config::load(&dir).unwrap_or_default();
+        let mut sources = loader::read_configured(&dir, &config).unwrap_or_default();
+        for (doc_path, text) in &self.documents {
+            let doc_dir = Path::new(doc_path).parent().and_then(|p| p.to_str()).unwrap_or(".");
+            if doc_dir != dir {
+                continue;
+            }
+            match sources.iter_mut().find(|s| &s.path == doc_path) {
+                Some(source) => source.content = text.clone(),
+                None => sources.push(SourceFile::new(doc_path.clone(), text.clone())),
+            }
+        }
+        sources.sort_by(|a, b| a.path.cmp(&b.path));
+
+        let mut diagnostics: HashMap<String, Vec<Value>> = sources
+            .iter()
+            .map(|s| (s.path.clone(), Vec::new()))
+            .collect();
+        let cache = self.caches.entry(dir.clone()).or_default();
+        let (files, errors) = cache.check(&loader::system_name(&dir), &sources, &config);
+        for error in &errors {
+            let file = error.file().unwrap_or(path).to_string();
+            let diagnostic = self.diagnostic(&sources, error);
+            diagnostics.entry(file).or_default().push(diagnostic);
+        }
+
+        Analysis {
+            sources,
+            files,
+            diagnostics,
+        }
+    }
+
+    /// Convert a load error into an LSP diagnostic
+    fn diagnostic(&self, sources: &[SourceFile], error: &LoadError) -> Value {
+        let content = error
+            .file()
+            .and_then(|file| sources.iter().find(|s| s.path == file))
+            .map(|s| s.content.as_str())
+            .unwrap_or("");
+
+        json!({
+            "range": word_range(content, error.position().unwrap_or(Position { line: 1, column: 1 })),
+            "severity": 1,
+            "source": "mat",
+            "message": error.message()
+        })
+    }
+
+    /// Build publishDiagnostics notifications for every file next to `path`
+    fn publish_diagnostics(&mut self, path: &str) -> Vec<Value> {
+        let analysis = self.analyze(path);
+        let mut files: Vec<_> = analysis.diagnostics.into_iter().collect();
+        files.sort_by(|a, b| a.0.cmp(&b.0));
+
+        files
+            .into_iter()
+            .map(|(file, diagnostics)| {
+                json!({
+                    "jsonrpc": "2.0",
+                    "method": "textDocument/publishDiagnostics",
+                    "params": { "uri": path_to_uri(&file), "diagnostics": diagnostics }
+                })
+            })
+            .collect()
+    }
+
+    /// Identifier under the cursor of a textDocument/position request
+    fn word_at(&self, params: &Value) -> Option<String> {
+        let path = document_path(params);
+        let content = match self.documents.get(&path) {
+            Some(text) => text.clone(),
+            None => std::fs::read_to_string(&path).ok()?,
+        };
+        let position = request_position(&content, params)?;
+        let character = position.column - 1;
+
+        let chars: Vec<char> = content.lines().nth(position.line - 1)?.chars().collect();
+        let is_ident = |c: &char| c.is_alphanumeric() || *c == '_';
+
+        let mut start = character.min(chars.len());
+        while start > 0 && is_ident(&chars[start - 1]) {
+            start -= 1;
+        }
+        let mut end = character.min(chars.len());
+        while end < chars.len() && is_ident(&chars[end]) {
+            end += 1;
+        }
+
+        if start == end {
+            None
+        } else {
+            Some(chars[start..end].iter().collect())
+        }
+    }
+
+    /// textDocument/definition
+    fn definition(&mut self, params: &Value) -> Value {
+        let word = match self.word_at(params) {
+            Some(word) => word,
+            None => return Value::Null,
+        };
+        let analysis = self.analyze(&document_path(params));
+
+        for file in &analysis.files {
+            for declaration in &file.declarations {
+                let position = match declaration {
+                    Declaration::State(state) if state.name == word => state.position,
+                    Declaration::Sequence(sequence) if sequence.name == word => sequence.position,
+                    Declaration::Group(group) if group.name == word => group.position,
+                    Declaration::Roles(roles) if roles.roles.contains(&word) => roles.position,
+                    _ => continue,
+                };
+                let source = file.source.as_deref().unwrap_or("");
+                let content = analysis
+                    .sources
+                    .iter()
+                    .find(|s| s.path == source)
+                    .map(|s| s.content.as_str())
+                    .unwrap_or("");
+                return json!({
+                    "uri": path_to_uri(source),
+                    "range": word_range(content, position)
+                });
+            }
+        }
+
+        Value::Null
+    }
+
+    /// textDocument/hover
+    fn hover(&mut self, params: &Value) -> Value {
+        let word = match self.word_at(params) {
+            Some(word) => word,
+            None => return Value::Null,
+        };
+        let analysis = self.analyze(&document_path(params));
+
+        // The declarations alone still describe what they can while the
+        // system does not validate
+        let text = match loader::validate_files("", &analysis.files) {
+            Ok(system) => system.describe(&word).map(|description| hover_text(&description)),
+            Err(_) => describe_declarations(&analysis.files, &word),
+        };
+        match text {
+            Some(text) => json!({ "contents": { "kind": "markdown", "value": text } }),
+            None => Value::Null,
+        }
+    }
+
+    /// textDocument/references, once the system validates
+    fn references(&mut self, params: &Value) -> Value {
+        let word = match self.word_at(params) {
+            Some(word) => word,
+            None => return Value::Null,
+        };
+        let analysis = self.analyze(&document_path(params));
+        let Ok(system) = loader::validate_files("", &analysis.files) else {
+            return Value::Null;
+        };
+        let include_declaration = params["context"]["includeDeclaration"].as_bool().unwrap_or(true);
+
+        let mut locations = Vec::new();
+        for reference in system.references(&word) {
+            if reference.kind == ReferenceKind::Declaration && !include_declaration {
+                continue;
+            }
+            let (Some(file), Some(position)) = (&reference.file, reference.position) else {
+                continue;
+            };
+            let content = analysis.sources.iter().find(|s| &s.path == file).map(|s| s.content.as_str()).unwrap_or("");
+            for range in occurrences(content, &word, position) {
+                let location = json!({ "uri": path_to_uri(file), "range": range });
+                // A step using the name twice is referenced twice
+                if !locations.contains(&location) {
+                    locations.push(location);
+                }
+            }
+        }
+        Value::Array(locations)
+    }
+
+    /// textDocument/completion
+    fn completion(&mut self, params: &Value) -> Value {
+        let path = document_path(params);
+        let analysis = self.analyze(&path);
+        let content = analysis.sources.iter().find(|s| s.path == path).map(|s| s.content.as_str()).unwrap_or("");
+        let position = request_position(content, params).unwrap_or(Position { line: 1, column: 1 });
+
+        let items: Vec<Value> = completion::completions(&analysis.sources, &path, position)
+            .into_iter()
+            .map(|item| {
+                let kind = match item.kind {
+                    CompletionKind::Keyword => KIND_KEYWORD,
+                    CompletionKind::Role => KIND_ENUM_MEMBER,
+                    CompletionKind::State => KIND_CLASS,
+                    CompletionKind::Sequence => KIND_FUNCTION,
+                    CompletionKind::Group => KIND_MODULE,
+                    CompletionKind::Action => KIND_METHOD,
+                };
+                json!({ "label": item.label, "kind": kind, "detail": item.kind })
+            })
+            .collect();
+
+        Value::Array(items)
+    }
+}
+
+/// Markdown rendering of a [`Description`]
+fn hover_text(description: &Description) -> String {
+    let mut text = format!("**{}** `{}`\n\n", description.kind, description.name);
+    if !description.roles.is_empty() {
+        text.push_str(&format!("Allowed roles: {}\n", description.roles.join(", ")));
+    }
+    if !description.states.is_empty() {
+        let label = match description.kind {
+            IdentifierKind::State => "Groups",
+            _ => "States",
+        };
+        text.push_str(&format!("{}: {}\n", label, description.states.join(", ")));
+    }
+    if let Some(degree) = description.degree {
+        text.push_str(&format!("Transitions: {} in, {} out\n", degree.incoming, degree.outgoing));
+    }
+    if !description.steps.is_empty() {
+        text.push('\n');
+        for (sequence, step) in &description.steps {
+            text.push_str(&format!("- `{}`: {} -> {} ({})\n", step.action_name, step.from, step.to, sequence));
+        }
+    }
+    text
+}
+
+/// Markdown description of an identifier, looked up across parsed files
+fn describe_declarations(files: &[MartialFile], word: &str) -> Option<String> {
+    let declarations: Vec<&Declaration> = files.iter().flat_map(|f| &f.declarations).collect();
+    let steps = || {
+        declarations.iter().filter_map(|d| match d {
+            Declaration::Sequence(sequence) => Some(sequence),
+            _ => None,
+        })
+        .flat_map(|sequence| sequence.steps.iter().map(move |step| (sequence, step)))
+    };
+    let format_step = |sequence: &str, step: &crate::ast::SequenceStep| {
+        format!(
+            "- `{}`: {} -> {} ({})\n",
+            step.action_name, step.from, step.to, sequence
+        )
+    };
+
+    for declaration in &declarations {
+        match declaration {
+            Declaration::State(state) if state.name == word => {
+                let mut text = format!("**state** `{}`\n\n", state.name);
+                match &state.allowed_roles {
+                    Some(roles) => text.push_str(&format!("Allowed roles: {}\n", roles.join(", "))),
+                    None => text.push_str("Allowed roles: all\n"),
+                }
+
+                let incoming: Vec<String> = steps()
+                    .filter(|(_, step)| step.to.state == word)
+                    .map(|(sequence, step)| format_step(&sequence.name, step))
+                    .collect();
+                let outgoing: Vec<String> = steps()
+                    .filter(|(_, step)| step.from.state == word)
+                    .map(|(sequence, step)| format_step(&sequence.name, step))
+                    .collect();
+
+                if !incoming.is_empty() {
+                    text.push_str(&format!("\nIncoming:\n{}", incoming.concat()));
+                }
+                if !outgoing.is_empty() {
+                    text.push_str(&format!("\nOutgoing:\n{}", outgoing.concat()));
+                }
+                return Some(text);
+            }
+            Declaration::Sequence(sequence) if sequence.name == word => {
+                let mut text = format!("**sequence** `{}` ({} steps)\n\n", sequence.name, sequence.steps.len());
+                for step in &sequence.steps {
+                    text.push_str(&format_step(&sequence.name, step));
+                }
+                return Some(text);
+            }
+            Declaration::Group(group) if group.name == word => {
+                return Some(format!("**group** `{}`\n\nStates: {}\n", group.name, group.states.join(", ")));
+            }
+            Declaration::Roles(roles) if roles.roles.iter().any(|r| r == word) => {
+                return Some(format!("**role** `{}`\n", word));
+            }
+            _ => {}
+        }
+    }
+
+    // Action names are not declared on their own, describe their uses
+    let uses: Vec<String> = steps()
+        .filter(|(_, step)| step.action_name == word)
+        .map(|(sequence, step)| format_step(&sequence.name, step))
+        .collect();
+    if uses.is_empty() {
+        None
+    } else {
+        Some(format!("**action** `{}`\n\n{}", word, uses.concat()))
+    }
+}
+
+/// Range covering the identifier starting at `position` (1-based) in `content`
+fn word_range(content: &str, position: Position) -> Value {
+    let line = position.line.saturating_sub(1);
+    let start = position.column.saturating_sub(1);
+    let length = content
+        .lines()
+        .nth(line)
+        .map(|l| {
+            l.chars()
+                .skip(start)
+                .take_while(|c| c.is_alphanumeric() || *c == '_')
+                .count()
+        })
+        .unwrap_or(0)
+        .max(1);
+
+    range(content, line, start, start + length)
+}
+
+/// Ranges of `word` on the first line from `position` (1-based) that has it
+fn occurrences(content: &str, word: &str, position: Position) -> Vec<Value> {
+    let is_ident = |c: char| c.is_alphanumeric() || c == '_';
+    let word: Vec<char> = word.chars().collect();
+    for (line, text) in content.lines().enumerate().skip(position.line.saturating_sub(1)) {
+        let chars: Vec<char> = text.chars().collect();
+        let from = if line + 1 == position.line { position.column.saturating_sub(1) } else { 0 };
+        let ranges: Vec<Value> = (from..chars.len())
+            .filter(|&start| chars[start..].starts_with(&word))
+            .filter(|&start| start == 0 || !is_ident(chars[start - 1]))
+            .filter(|&start| chars.get(start + word.len()).is_none_or(|c| !is_ident(*c)))
+            .map(|start| range(content, line, start, start + word.len()))
+            .collect();
+        if !ranges.is_empty() {
+            return ranges;
+        }
+    }
+    Vec::new()
+}
+
+/// The `position` of a request as a 1-based position counting characters,
+/// converted from the UTF-16 columns of the protocol
+fn request_position(content: &str, params: &Value) -> Option<Position> {
+    let line = params["position"]["line"].as_u64()? as usize;
+    let character = params["position"]["character"].as_u64()? as usize;
+    let text = content.lines().nth(line).unwrap_or("");
+    let mut units = 0;
+    let column = text
+        .chars()
+        .position(|c| {
+            units += c.len_utf16();
+            units > character
+        })
+        .unwrap_or_else(|| text.chars().count());
+    Some(Position {
+        line: line + 1,
+        column: column + 1,
+    })
+}
+
+/// LSP range of the characters `start..end` (0-based) of line `line` of
+/// `content`, in UTF-16 columns
+fn range(content: &str, line: usize, start: usize, end: usize) -> Value {
+    let text = content.lines().nth(line).unwrap_or("");
+    // Columns past the end of the line count one unit each
+    let column = |chars: usize| -> usize {
+        text.chars().take(chars).map(char::len_utf16).sum::<usize>() + chars.saturating_sub(text.chars().count())
+    };
+    json!({
+        "start": { "line": line, "character": column(start) },
+        "end": { "line": line, "character": column(end) }
+    })
+}
+
+/// File path of the `textDocument` in a request
+fn document_path(params: &Value) -> String {
+    uri_to_path(params["textDocument"]["uri"].as_str().unwrap_or(""))
+}
+
+/// Convert a `file://` URI into a filesystem path
+fn uri_to_path(uri: &str) -> String {
+    let path = uri.strip_prefix("file://").unwrap_or(uri);
+    let hex = |byte: Option<&u8>| byte.and_then(|b| (*b as char).to_digit(16));
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match (bytes[i], hex(bytes.get(i + 1)), hex(bytes.get(i + 2))) {
+            (b'%', Some(high), Some(low)) => {
+                decoded.push((high * 16 + low) as u8);
+                i += 2;
+            }
+            (byte, _, _) => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Convert a filesystem path into a `file://` URI
+fn path_to_uri(path: &str) -> String {
+    let mut uri = String::from("file://");
+    for c in path.chars() {
+        match c {
+            ' ' => uri.push_str("%20"),
+            '%' => uri.push_str("%25"),
+            '#' => uri.push_str("%23"),
+            '?' => uri.push_str("%3F"),
+            _ => uri.push(c),
+        }
+    }
+    uri
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(message: Value) -> String {
+        let body = message.to_string();
+        format!("Content-Length: {}\r\n\r\n{}", body.len(), body)
+    }
+
+    fn run_session(messages: Vec<Value>) -> Vec<Value> {
+        let input: String = messages.into_iter().map(frame).collect();
+        let mut output = Vec::new();
+        run(input.as_bytes(), &mut output).unwrap();
+
+        let mut reader = io::BufReader::new(output.as_slice());
+        let mut replies = Vec::new();
+        while let Some(message) = read_message(&mut reader).unwrap() {
+            replies.push(message);
+        }
+        replies
+    }
+
+    const URI: &str = "file:///nonexistent-martial-lsp-test/system.martial";
+
+    fn open(text: &str) -> Value {
+        json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didOpen",
+            "params": { "textDocument": { "uri": URI, "languageId": "martial", "version": 1, "text": text } }
+        })
+    }
+
+    #[test]
+    fn test_uri_to_path() {
+        assert_eq!(uri_to_path("file:///my%20system/s.martial"), "/my system/s.martial");
+        assert_eq!(uri_to_path("file:///caf%C3%A9/a%20"), "/café/a ");
+        assert_eq!(uri_to_path("file:///50%/%é%zz/%"), "/50%/%é%zz/%");
+        assert_eq!(uri_to_path(&path_to_uri("/a b/100%#1")), "/a b/100%#1");
+    }
+
+    #[test]
+    fn test_diagnostics_on_open() {
+        let text = "roles { Top }\nstate A\nsequence S:\n    Go: A[Top] -> B[Top]\n";
+        let replies = run_session(vec![
+            json!({ "jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {} }),
+            open(text),
+        ]);
+
+        assert_eq!(replies.len(), 2);
+        assert!(replies[0]["result"]["capabilities"]["hoverProvider"].as_bool().unwrap());
+
+        let diagnostics = &replies[1]["params"]["diagnostics"];
+        assert_eq!(replies[1]["method"], "textDocument/publishDiagnostics");
+        assert_eq!(diagnostics.as_array().unwrap().len(), 1);
+        assert!(diagnostics[0]["message"].as_str().unwrap().contains("State 'B' is not defined"));
+        assert_eq!(diagnostics[0]["range"]["start"]["line"], 3);
+    }
+
+    #[test]
+    fn test_definition_and_hover() {
+        let text = "roles { Top, Bottom }\nstate Mount\nstate Guard\nsequence Escape:\n    Shrimp: Mount[Bottom] -> Guard[Bottom]\n";
+        let position = json!({ "textDocument": { "uri": URI }, "position": { "line": 4, "character": 14 } });
+        let replies = run_session(vec![
+            open(text),
+            json!({ "jsonrpc": "2.0", "id": 2, "method": "textDocument/definition", "params": position }),
+            json!({ "jsonrpc": "2.0", "id": 3, "method": "textDocument/hover", "params": position }),
+        ]);
+
+        let definition = &replies[1]["result"];
+        assert_eq!(definition["uri"], URI);
+        assert_eq!(definition["range"]["start"]["line"], 1);
+        assert_eq!(definition["range"]["start"]["character"], 6);
+
+        let hover = replies[2]["result"]["contents"]["value"].as_str().unwrap();
+        assert!(hover.contains("**state** `Mount`"));
+        assert!(hover.contains("Shrimp"));
+    }
+
+    #[test]
+    fn test_references() {
+        let text = "roles { Top, Bottom }\nstate Mount\nstate Guard\nsequence Escape:\n    Shrimp: Mount[Bottom] -> Guard[Bottom]\n    Stay: Guard[Bottom] -> Guard[Bottom]\n";
+        let position = json!({ "textDocument": { "uri": URI }, "position": { "line": 2, "character": 7 }, "context": { "includeDeclaration": false } });
+        let replies = run_session(vec![
+            open(text),
+            json!({ "jsonrpc": "2.0", "id": 2, "method": "textDocument/references", "params": position }),
+        ]);
+
+        let ranges: Vec<(u64, u64)> = replies[1]["result"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|location| {
+                assert_eq!(location["uri"], URI);
+                let start = &location["range"]["start"];
+                (start["line"].as_u64().unwrap(), start["character"].as_u64().unwrap())
+            })
+            .collect();
+        assert_eq!(ranges, [(4, 29), (5, 10), (5, 27)]);
+    }
+
+    #[test]
+    fn test_utf16_columns() {
+        // U+1D54A takes two UTF-16 code units
+        let text = "roles { Top, Bottom }\nstate Mount\nstate Guard\nsequence Escape:\n    \u{1D54A}hrimp: Mount[Bottom] -> Guard[Bottom]\n";
+        let references = json!({ "textDocument": { "uri": URI }, "position": { "line": 4, "character": 31 }, "context": { "includeDeclaration": false } });
+        let replies = run_session(vec![
+            open(text),
+            json!({ "jsonrpc": "2.0", "id": 2, "method": "textDocument/references", "params": references }),
+            open(&text.replace("-> Guard", "-> -> Guard")),
+        ]);
+
+        let range = &replies[1]["result"][0]["range"];
+        assert_eq!((&range["start"]["character"], &range["end"]["character"]), (&json!(30), &json!(35)));
+        let diagnostic = &replies[2]["params"]["diagnostics"][0]["range"]["start"];
+        assert_eq!((&diagnostic["line"], &diagnostic["character"]), (&json!(4), &json!(30)));
+    }
+
+    #[test]
+    fn test_completion_in_role_slot() {
+        let text = "roles { Top, Bottom }\nstate Mount\nsequence S:\n    Go: Mount[";
+        let replies = run_session(vec![
+            open(text),
+            json!({
+                "jsonrpc": "2.0", "id": 4, "method": "textDocument/completion",
+                "params": { "textDocument": { "uri": URI }, "position": { "line": 3, "character": 14 } }
+            }),
+        ]);
+
+        let labels: Vec<&str> = replies[1]["result"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|i| i["label"].as_str().unwrap())
+            .collect();
+        assert_eq!(labels, vec!["Top", "Bottom"]);
+    }
+}
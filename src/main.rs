@@ -1,11 +1,7 @@
-mod ast;
-mod lexer;
-mod parser;
-mod semantic;
-mod graph;
-
-use std::env;
+use martial_lang::{annotate, ast, balance, bottleneck, build, compare, conditional, config, curriculum, diagram, doc, embusen, export, fix, fixture, grammar, graph, handle, help, impact, matrix, highlight, selector, import, lesson, lint, loader, lock, lsp, package, migrate, refactor, schema, semantic, serve, site, sparring, storyboard, synthetic, train};
 use std::fs;
+use std::io;
+use std::env;
 use std::path::Path;
 use std::process;
 
@@ -19,15 +15,35 @@ fn main() {
     }
     
     let command = &args[1];
+    if args[2..].iter().any(|a| a == "--help") {
+        if let Some(command) = help::command(command) {
+            print!("{}", help::render(command));
+            return;
+        }
+    }
     
     match command.as_str() {
         "validate" => {
-            if args.len() < 3 {
-                eprintln!("Error: validate requires a path argument");
-                print_usage();
-                process::exit(1);
+            let flags = &args[2..];
+            let profile = option_arg(flags, &["--profile"]);
+            let path = match flags
+                .iter()
+                .enumerate()
+                .find(|(i, a)| !a.starts_with('-') && (*i == 0 || flags[i - 1] != "--profile"))
+                .map(|(_, a)| a)
+            {
+                Some(path) => path,
+                None => {
+                    eprintln!("Error: validate requires a path argument");
+                    print_usage();
+                    process::exit(1);
+                }
+            };
+            if flags.iter().any(|a| a == "--per-file") {
+                validate_per_file_command(path);
+            } else {
+                validate_command(path, flags.iter().any(|a| a == "--strict"), profile);
             }
-            validate_command(&args[2]);
         }
         "graph" => {
             if args.len() < 3 {
@@ -35,7 +51,7 @@ fn main() {
                 print_usage();
                 process::exit(1);
             }
-            graph_command(&args[2]);
+            graph_command(&args[2], &args[3..]);
         }
         "dot" => {
             if args.len() < 3 {
@@ -43,7 +59,23 @@ fn main() {
                 print_usage();
                 process::exit(1);
             }
-            dot_command(&args[2]);
+            dot_command(&args[2], &args[3..]);
+        }
+        "tui" => {
+            if args.len() < 3 {
+                eprintln!("Error: tui requires a path argument");
+                print_usage();
+                process::exit(1);
+            }
+            tui_command(&args[2]);
+        }
+        "show" => {
+            if args.len() < 3 {
+                eprintln!("Error: show requires a path argument");
+                print_usage();
+                process::exit(1);
+            }
+            show_command(&args[2], &args[3..]);
         }
         "stats" => {
             if args.len() < 3 {
@@ -51,11 +83,504 @@ fn main() {
                 print_usage();
                 process::exit(1);
             }
-            stats_command(&args[2]);
+            stats_command(&args[2], &repeated_arg(&args[3..], "--start"), args[3..].iter().any(|a| a == "--used-only"));
+        }
+        "system" => {
+            if args.len() < 3 {
+                eprintln!("Error: system requires a path argument");
+                print_usage();
+                process::exit(1);
+            }
+            system_command(&args[2], option_arg(&args[3..], &["--lang"]));
+        }
+        "schema" => {
+            if args.len() < 3 {
+                eprintln!("Error: schema requires a format argument ({})", schema::FORMATS.join(", "));
+                print_usage();
+                process::exit(1);
+            }
+            schema_command(&args[2]);
+        }
+        "doc" => {
+            if args.len() < 3 {
+                eprintln!("Error: doc requires a path argument");
+                print_usage();
+                process::exit(1);
+            }
+            doc_command(&args[2], output_arg(&args[3..]), option_arg(&args[3..], &["--lang"]));
+        }
+        "flashcards" => {
+            if args.len() < 3 {
+                eprintln!("Error: flashcards requires a path argument");
+                print_usage();
+                process::exit(1);
+            }
+            flashcards_command(&args[2], output_arg(&args[3..]));
+        }
+        "site" => {
+            if args.len() < 3 {
+                eprintln!("Error: site requires a path argument");
+                print_usage();
+                process::exit(1);
+            }
+            site_command(&args[2], output_arg(&args[3..]).unwrap_or("site"), option_arg(&args[3..], &["--lang"]));
+        }
+        "import" => {
+            // --force takes no value, so keep it out of the positional scan
+            let rest: Vec<String> = args[2..].iter().filter(|a| *a != "--force").cloned().collect();
+            let force = rest.len() < args.len() - 2;
+            let rest = &rest[..];
+            let input = match positional_args(rest).first() {
+                Some(input) => input.to_string(),
+                None => {
+                    eprintln!("Error: import requires an input file");
+                    print_usage();
+                    process::exit(1);
+                }
+            };
+            let output = match output_arg(rest) {
+                Some(output) => output,
+                None => {
+                    eprintln!("Error: import requires an output directory (-o)");
+                    print_usage();
+                    process::exit(1);
+                }
+            };
+            import_command(&input, option_arg(rest, &["-f", "--format"]), output, force);
+        }
+        "gen-synthetic" => {
+            let rest = &args[2..];
+            let output = match output_arg(rest) {
+                Some(output) => output,
+                None => {
+                    eprintln!("Error: gen-synthetic requires an output directory (-o)");
+                    print_usage();
+                    process::exit(1);
+                }
+            };
+            let defaults = synthetic::SyntheticOptions::default();
+            let options = synthetic::SyntheticOptions {
+                states: number_arg(rest, "--states", defaults.states),
+                sequences: number_arg(rest, "--sequences", defaults.sequences),
+                seed: number_arg(rest, "--seed", defaults.seed as usize) as u64,
+                ..defaults
+            };
+            gen_synthetic_command(output, &options);
+        }
+        "rename" => {
+            if args.len() < 6 {
+                eprintln!("Error: rename requires a path, a kind, the old name and the new name");
+                print_usage();
+                process::exit(1);
+            }
+            rename_command(&args[2], &args[3], &args[4], &args[5]);
+        }
+        "migrate" => {
+            let flags = &args[2..];
+            let path = match flags.iter().find(|a| !a.starts_with('-')) {
+                Some(path) => path,
+                None => {
+                    eprintln!("Error: migrate requires a path argument");
+                    print_usage();
+                    process::exit(1);
+                }
+            };
+            migrate_command(path, flags.iter().any(|a| a == "--check"));
+        }
+        "fix" => {
+            let flags = &args[2..];
+            let path = match flags.iter().find(|a| !a.starts_with('-')) {
+                Some(path) => path,
+                None => {
+                    eprintln!("Error: fix requires a path argument");
+                    print_usage();
+                    process::exit(1);
+                }
+            };
+            fix_command(path, flags.iter().any(|a| a == "--yes" || a == "-y"));
+        }
+        "extract-group" => {
+            if args.len() < 4 {
+                eprintln!("Error: extract-group requires a path and a group name");
+                print_usage();
+                process::exit(1);
+            }
+            extract_group_command(&args[2], &args[3], output_arg(&args[4..]));
+        }
+        "lesson" => {
+            if args.len() < 3 {
+                eprintln!("Error: lesson requires a path argument");
+                print_usage();
+                process::exit(1);
+            }
+            let rest = &args[3..];
+            let focus = match option_arg(rest, &["-f", "--focus"]) {
+                Some(focus) => focus,
+                None => {
+                    eprintln!("Error: lesson requires a focus state (--focus State)");
+                    print_usage();
+                    process::exit(1);
+                }
+            };
+            let minutes = match option_arg(rest, &["-m", "--minutes"]).map(str::parse::<u32>) {
+                None => 60,
+                Some(Ok(minutes)) if minutes > 0 => minutes,
+                Some(_) => {
+                    eprintln!("Error: --minutes expects a positive number");
+                    process::exit(1);
+                }
+            };
+            lesson_command(&args[2], focus, minutes, output_arg(rest), option_arg(rest, &["--lang"]));
+        }
+        "order" => {
+            if args.len() < 3 {
+                eprintln!("Error: order requires a path argument");
+                print_usage();
+                process::exit(1);
+            }
+            order_command(&args[2], output_arg(&args[3..]));
+        }
+        "log" => {
+            let rest = args.get(3..).unwrap_or_default();
+            let positional = positional_args(rest);
+            let (path, entry) = match (args.get(2).map(String::as_str), positional.as_slice()) {
+                (Some("add"), [entry]) => (".", *entry),
+                (Some("add"), [path, entry]) => (*path, *entry),
+                _ => {
+                    eprintln!("Error: usage is mat log add [directory] \"From[Role] -> To[Role] via Action\"");
+                    print_usage();
+                    process::exit(1);
+                }
+            };
+            log_add_command(path, entry);
+        }
+        "heatmap" => {
+            if args.len() < 3 {
+                eprintln!("Error: heatmap requires a path argument");
+                print_usage();
+                process::exit(1);
+            }
+            let rest = &args[3..];
+            heatmap_command(
+                &args[2],
+                option_arg(rest, &["-f", "--format"]).unwrap_or("dot"),
+                option_arg(rest, &["--log"]),
+                output_arg(rest),
+            );
+        }
+        "balance" => {
+            let Some(path) = positional_args(&args[2..]).first().copied() else {
+                eprintln!("Error: balance requires a path argument");
+                print_usage();
+                process::exit(1);
+            };
+            balance_command(path, option_arg(&args[2..], &["-f", "--format"]).unwrap_or("text"));
+        }
+        "matrix" => {
+            let Some(path) = positional_args(&args[2..]).first().copied() else {
+                eprintln!("Error: matrix requires a path argument");
+                print_usage();
+                process::exit(1);
+            };
+            let by = option_arg(&args[2..], &["--by"]).unwrap_or("group");
+            let Some(axis) = matrix::Axis::from_name(by) else {
+                eprintln!("Error: Unknown --by '{}' (expected group or state)", by);
+                process::exit(1);
+            };
+            matrix_command(
+                path,
+                axis,
+                option_arg(&args[2..], &["-f", "--format"]).unwrap_or("markdown"),
+                output_arg(&args[2..]),
+            );
+        }
+        "bottlenecks" => {
+            let Some(path) = positional_args(&args[2..]).first().copied() else {
+                eprintln!("Error: bottlenecks requires a path argument");
+                print_usage();
+                process::exit(1);
+            };
+            let between = match (option_arg(&args[2..], &["--from"]), option_arg(&args[2..], &["--to"])) {
+                (Some(from), Some(to)) => Some((from, to)),
+                (None, None) => None,
+                _ => {
+                    eprintln!("Error: --from and --to go together");
+                    process::exit(1);
+                }
+            };
+            check_selectors(between.into_iter().flat_map(|(from, to)| [from, to]));
+            bottlenecks_command(path, between, option_arg(&args[2..], &["-f", "--format"]).unwrap_or("text"));
+        }
+        "compare" => {
+            let [left, right] = positional_args(&args[2..])[..] else {
+                eprintln!("Error: compare requires two paths");
+                print_usage();
+                process::exit(1);
+            };
+            compare_command(
+                left,
+                right,
+                option_arg(&args[2..], &["-f", "--format"]).unwrap_or("text"),
+                output_arg(&args[2..]),
+            );
+        }
+        "next" => {
+            if args.len() < 4 {
+                eprintln!("Error: next requires a path and a position (State[Role])");
+                print_usage();
+                process::exit(1);
+            }
+            let rest = &args[4..];
+            let limit = match option_arg(rest, &["-n", "--limit"]).map(str::parse::<usize>) {
+                None => 5,
+                Some(Ok(limit)) if limit > 0 => limit,
+                Some(_) => {
+                    eprintln!("Error: --limit expects a positive number");
+                    process::exit(1);
+                }
+            };
+            next_command(&args[2], &args[3], option_arg(rest, &["--log"]), limit);
+        }
+        "path" | "paths" => {
+            let rest = &args[2..];
+            let positional: Vec<&String> = rest
+                .iter()
+                .enumerate()
+                .filter(|(i, a)| !a.starts_with('-') && (*i == 0 || !["--k", "--via", "--avoid"].contains(&rest[i - 1].as_str())))
+                .map(|(_, a)| a)
+                .collect();
+            let [path, from, to] = positional[..] else {
+                eprintln!("Error: path requires a path and two positions");
+                print_usage();
+                process::exit(1);
+            };
+            let canonical = rest.iter().any(|a| a == "--canonical");
+            let constraints = graph::PathConstraints {
+                via: repeated_arg(rest, "--via").into_iter().map(String::from).collect(),
+                avoid: repeated_arg(rest, "--avoid").into_iter().map(String::from).collect(),
+            };
+            let constrained = constraints != graph::PathConstraints::default();
+            check_selectors([from.as_str(), to.as_str()].into_iter().chain(constraints.via.iter().chain(&constraints.avoid).map(String::as_str)));
+            match option_arg(rest, &["--k"]).map(str::parse::<usize>) {
+                None if constrained && !canonical => paths_command(path, from, to, &constraints, 1),
+                None if !constrained => path_command(path, from, to, canonical),
+                None => {
+                    eprintln!("Error: --via and --avoid cannot be combined with --canonical");
+                    process::exit(1);
+                }
+                Some(Ok(k)) if k > 0 && !canonical => paths_command(path, from, to, &constraints, k),
+                Some(Ok(_)) if canonical => {
+                    eprintln!("Error: --k cannot be combined with --canonical");
+                    process::exit(1);
+                }
+                Some(_) => {
+                    eprintln!("Error: --k expects a positive number");
+                    process::exit(1);
+                }
+            }
+        }
+        "reachable" => {
+            let [path, from] = positional_args(&args[2..])[..] else {
+                eprintln!("Error: reachable requires a path and a position");
+                print_usage();
+                process::exit(1);
+            };
+            reachable_command(path, from, option_arg(&args[2..], &["--kind"]));
+        }
+        "validate-all" => {
+            let Some(root) = positional_args(&args[2..]).first().copied() else {
+                eprintln!("Error: validate-all requires a path argument");
+                print_usage();
+                process::exit(1);
+            };
+            validate_all_command(root);
+        }
+        "package" | "unpack" | "info" => {
+            let command = args[1].as_str();
+            let Some(path) = positional_args(&args[2..]).first().copied() else {
+                eprintln!("Error: {} requires a path argument", command);
+                print_usage();
+                process::exit(1);
+            };
+            let output = output_arg(&args[2..]);
+            match command {
+                "package" => package_command(path, output),
+                "unpack" => unpack_command(path, output),
+                _ => info_command(path),
+            }
+        }
+        "add" => {
+            let [path, source] = positional_args(&args[2..])[..] else {
+                eprintln!("Error: add requires a directory and a package path or URL");
+                print_usage();
+                process::exit(1);
+            };
+            add_command(path, source, option_arg(&args[2..], &["--as"]));
+        }
+        "lock" => {
+            let Some(path) = positional_args(&args[2..]).first().copied() else {
+                eprintln!("Error: lock requires a path argument");
+                print_usage();
+                process::exit(1);
+            };
+            lock_command(path, args.iter().any(|a| a == "--check"));
+        }
+        "position" => {
+            let [path, state] = positional_args(&args[2..])[..] else {
+                eprintln!("Error: position requires a path and a state");
+                print_usage();
+                process::exit(1);
+            };
+            position_command(path, state);
+        }
+        "flows" => {
+            let Some(path) = positional_args(&args[2..]).first().copied() else {
+                eprintln!("Error: flows requires a path argument");
+                print_usage();
+                process::exit(1);
+            };
+            flows_command(path);
+        }
+        "describe" => {
+            let [path, name] = positional_args(&args[2..])[..] else {
+                eprintln!("Error: describe requires a path and a name");
+                print_usage();
+                process::exit(1);
+            };
+            describe_command(path, name);
+        }
+        "refs" => {
+            let [path, name] = positional_args(&args[2..])[..] else {
+                eprintln!("Error: refs requires a path and a name");
+                print_usage();
+                process::exit(1);
+            };
+            refs_command(path, name);
+        }
+        "impact" => {
+            let [path, "remove", target, name] = positional_args(&args[2..])[..] else {
+                eprintln!("Error: impact requires a path, then remove, a kind and a name");
+                print_usage();
+                process::exit(1);
+            };
+            let Some(target) = impact::Target::from_name(target) else {
+                eprintln!("Error: Unknown kind '{}' (expected state or sequence)", target);
+                process::exit(1);
+            };
+            impact_command(path, target, name, option_arg(&args[2..], &["-f", "--format"]).unwrap_or("text"));
+        }
+        "storyboard" => {
+            let rest = &args[2..];
+            let [path, sequence] = positional_args(rest)[..] else {
+                eprintln!("Error: storyboard requires a path and a sequence name");
+                print_usage();
+                process::exit(1);
+            };
+            let output = output_arg(rest);
+            let format = match option_arg(rest, &["-f", "--format"]) {
+                Some(name) => storyboard::StoryboardFormat::from_name(name).ok_or(name),
+                None => Ok(output.and_then(storyboard::StoryboardFormat::from_path).unwrap_or(storyboard::StoryboardFormat::Svg)),
+            };
+            let format = format.unwrap_or_else(|name| {
+                eprintln!("Error: Unknown storyboard format '{}' (expected svg or md)", name);
+                process::exit(1);
+            });
+            storyboard_command(path, sequence, format, output, option_arg(rest, &["--lang"]));
+        }
+        "embusen" => {
+            let rest = &args[2..];
+            let positional = positional_args(rest);
+            let [path, ref sequences @ ..] = positional[..] else {
+                eprintln!("Error: embusen requires a path argument");
+                print_usage();
+                process::exit(1);
+            };
+            if sequences.is_empty() {
+                eprintln!("Error: embusen requires at least one sequence");
+                print_usage();
+                process::exit(1);
+            }
+            check_selectors(sequences.iter().copied());
+            embusen_command(path, sequences, option_arg(rest, &["-f", "--format"]).unwrap_or("text"));
+        }
+        "train" => {
+            if args.len() < 3 {
+                eprintln!("Error: train requires a path argument");
+                print_usage();
+                process::exit(1);
+            }
+            let rest = &args[3..];
+            let start = match option_arg(rest, &["-s", "--start"]) {
+                Some(start) => start,
+                None => {
+                    eprintln!("Error: train requires a starting position (--start State[Role])");
+                    print_usage();
+                    process::exit(1);
+                }
+            };
+            train_command(&args[2], start, rest);
+        }
+        "build" => {
+            let positional = positional_args(&args[2..]);
+            build_command(positional.first().copied().unwrap_or("."));
+        }
+        "export" => {
+            // --all takes no value, so keep it out of the positional scan
+            let rest: Vec<String> = args[2..].iter().filter(|a| *a != "--all").cloned().collect();
+            let all = rest.len() < args.len() - 2;
+            let positional = positional_args(&rest);
+            let Some((path, names)) = positional.split_first() else {
+                eprintln!("Error: export requires a path argument");
+                print_usage();
+                process::exit(1);
+            };
+            if names.is_empty() && !all {
+                eprintln!("Error: export requires preset names or --all");
+                print_usage();
+                process::exit(1);
+            }
+            export_command(path, names, all);
+        }
+        "test" => {
+            let Some(path) = args[2..].iter().find(|a| !a.starts_with('-')) else {
+                eprintln!("Error: test requires a path argument");
+                print_usage();
+                process::exit(1);
+            };
+            test_command(path, args.iter().any(|a| a == "--update"));
+        }
+        "config" => {
+            let positional = positional_args(&args[2..]);
+            if positional.first() != Some(&"show") {
+                eprintln!("Error: usage: mat config show [directory]");
+                process::exit(1);
+            }
+            let path = positional.get(1).copied().unwrap_or(".");
+            print!("{}", resolve_config(path, &args[2..]).show());
+        }
+        "serve" => {
+            if args.len() < 3 {
+                eprintln!("Error: serve requires a path argument");
+                print_usage();
+                process::exit(1);
+            }
+            serve_command(
+                &args[2],
+                option_arg(&args[3..], &["--host"]).unwrap_or("127.0.0.1"),
+                number_arg(&args[3..], "--port", 8080),
+            );
+        }
+        "help" => help_command(&args[2..]),
+        "grammar" => grammar_command(&args[2..]),
+        "lsp" => {
+            if let Err(e) = lsp::run_stdio() {
+                eprintln!("Error: language server failed: {}", e);
+                process::exit(1);
+            }
         }
         path if Path::new(path).exists() => {
             // Backwards compatibility: treat as validate
-            validate_command(path);
+            validate_command(path, false, None);
         }
         _ => {
             eprintln!("Error: Unknown command '{}'", command);
@@ -66,17 +591,96 @@ fn main() {
 }
 
 fn print_usage() {
-    eprintln!("\nUsage:");
-    eprintln!("  mat validate <directory>     # Validate a martial system");
-    eprintln!("  mat graph <directory>        # Export graph as JSON");
-    eprintln!("  mat dot <directory>          # Export graph as DOT (Graphviz)");
-    eprintln!("  mat stats <directory>        # Show graph statistics");
+    eprint!("\n{}", help::usage());
 }
 
-fn validate_command(path: &str) {
-    let system = load_and_validate_system(path);
-    
+fn grammar_command(args: &[String]) {
+    let content = match option_arg(args, &["--format"]).unwrap_or("ebnf") {
+        "ebnf" => grammar::to_ebnf(),
+        "textmate" => highlight::textmate(),
+        "tree-sitter" => highlight::tree_sitter(),
+        other => {
+            eprintln!("Error: Unknown grammar format '{}', expected ebnf, textmate or tree-sitter", other);
+            process::exit(1);
+        }
+    };
+    write_output(output_arg(args), &content);
+}
+
+fn help_command(args: &[String]) {
+    let output = output_arg(args);
+    let flags: Vec<String> = args.iter().filter(|a| *a != "--man").cloned().collect();
+    let topics = positional_args(&flags);
+    let content = if args.iter().any(|a| a == "--man") {
+        help::man_page()
+    } else {
+        match topics.first().copied() {
+            None => help::usage(),
+            Some("syntax") => help::syntax(),
+            Some(name) => match help::command(name) {
+                Some(command) => help::render(command),
+                None => {
+                    let names: Vec<&str> = help::COMMANDS.iter().map(|c| c.name).collect();
+                    eprintln!("Error: Unknown command '{}'. Available help topics: syntax, {}", name, names.join(", "));
+                    process::exit(1);
+                }
+            },
+        }
+    };
+    write_output(output, &content);
+}
+
+fn validate_command(path: &str, strict: bool, profile: Option<&str>) {
+    let config = load_config(path);
+    let registry = lint::LintRegistry::builtin();
+    let profile = profile.map(|name| match lint::Profile::from_name(name) {
+        Some(profile) => profile,
+        None => {
+            let names: Vec<&str> = lint::Profile::ALL.iter().map(|p| p.name).collect();
+            eprintln!("Error: Unknown profile '{}'. Available profiles: {}", name, names.join(", "));
+            process::exit(1);
+        }
+    });
+    let named: Vec<String> = config.lints.iter().flatten().chain(&config.deny).cloned().collect();
+    let unknown = registry.unknown(&named);
+    if !unknown.is_empty() {
+        eprintln!("Error: Unknown lint(s): {}. Available lints: {}", unknown.join(", "), registry.names().join(", "));
+        process::exit(1);
+    }
+    let semantic::ValidationReport { system, warnings } = load_and_validate(path);
+    if strict || config.strict {
+        let missing = system.actions_without_terms();
+        if !missing.is_empty() {
+            eprintln!("\n✗ Strict mode: {} action(s) have no glossary entry:", missing.len());
+            for action in missing {
+                eprintln!("  - {}", action);
+            }
+            process::exit(1);
+        }
+    }
+    if let Some(profile) = profile {
+        let problems = registry.check_profile(&system, &profile);
+        if !problems.is_empty() {
+            eprintln!("\n✗ Profile {} ({}): {} problem(s):", profile.name, profile.description, problems.len());
+            for problem in problems {
+                eprintln!("  - {}", problem);
+            }
+            process::exit(1);
+        }
+    }
+    let (denied, warnings): (Vec<_>, Vec<_>) = warnings.into_iter().partition(|warning| warning.level == lint::Level::Deny);
+    if !denied.is_empty() {
+        eprintln!("\n✗ {} denied lint finding(s):", denied.len());
+        for finding in denied {
+            eprintln!("  - {}", finding);
+        }
+        process::exit(1);
+    }
+
     println!("\n✓ System '{}' is valid!", system.name);
+    for warning in &warnings {
+        eprintln!("⚠ {}", warning);
+    }
     println!("\nSystem summary:");
     println!("  Roles: {}", system.roles.len());
     for role in &system.roles {
@@ -86,43 +690,1345 @@ fn validate_command(path: &str) {
     for state_name in system.states.keys() {
         println!("    - {}", state_name);
     }
-    println!("  Sequences: {}", system.sequences.len());
-    for seq_name in system.sequences.keys() {
-        println!("    - {}", seq_name);
+    println!("  Sequences: {}", system.sequences.len());
+    for seq_name in system.sequences.keys() {
+        println!("    - {}", seq_name);
+    }
+    if !system.groups.is_empty() {
+        println!("  Groups: {}", system.groups.len());
+        for (group_name, states) in &system.groups {
+            println!("    - {} ({})", group_name, states.join(", "));
+        }
+    }
+}
+
+fn validate_all_command(root: &str) {
+    let checks = match loader::check_systems(root) {
+        Ok(checks) if checks.is_empty() => {
+            eprintln!("Error: No .martial files found under {}", root);
+            process::exit(1);
+        }
+        Ok(checks) => checks,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+
+    let width = checks.iter().map(|c| c.name.len()).max().unwrap_or(0).max("System".len());
+    println!("{:<width$}  Files  States  Sequences  Errors  Warnings", "System", width = width);
+    for check in &checks {
+        println!(
+            "{:<width$}  {:>5}  {:>6}  {:>9}  {:>6}  {:>8}",
+            check.name,
+            check.files,
+            check.states,
+            check.sequences,
+            check.errors.len(),
+            check.warnings.len(),
+            width = width
+        );
+    }
+
+    let failed: Vec<&loader::SystemCheck> = checks.iter().filter(|c| !c.passed()).collect();
+    for check in &failed {
+        println!("\n✗ {} ({}):", check.name, check.path);
+        for error in &check.errors {
+            println!("  {}", error);
+        }
+        for finding in check.denied() {
+            println!("  {}", finding);
+        }
+    }
+    if failed.is_empty() {
+        println!("\n✓ All {} systems are valid", checks.len());
+    } else {
+        println!("\n✗ {} of {} systems are invalid", failed.len(), checks.len());
+        process::exit(1);
+    }
+}
+
+/// Write the lockfile of the system of `path`, or check it if `check`
+fn lock_command(path: &str, check: bool) {
+    let (sources, report) = load_sources_and_validate(path);
+    if check {
+        check_lockfile(path, &sources, &report.system);
+        return;
+    }
+    let lockfile = lock::Lockfile::new(path, &sources, &report.system);
+    let lock_path = Path::new(path).join(lock::LOCK_FILE);
+    if let Err(e) = fs::write(&lock_path, lockfile.to_toml()) {
+        eprintln!("Error writing {}: {}", lock_path.display(), e);
+        process::exit(1);
+    }
+    println!("\n✓ Locked {} file(s) of system '{}' in {}", lockfile.files.len(), report.system.name, lock_path.display());
+    println!("  fingerprint {}", lockfile.fingerprint);
+}
+
+/// Bundle the system of `path` into a package, `<name>.martialpkg` unless
+/// `output` is given
+fn package_command(path: &str, output: Option<&str>) {
+    let config = load_config(path);
+    let (sources, report) = load_sources_and_validate(path);
+    if config.locked {
+        check_lockfile(path, &sources, &report.system);
+    }
+    let package = package::Package::new(path, &config, &sources, &report.system);
+    let output = output.map(String::from).unwrap_or_else(|| format!("{}.{}", report.system.name, package::PACKAGE_EXTENSION));
+    let json = match package.to_json() {
+        Ok(json) => json,
+        Err(e) => {
+            eprintln!("Error serializing package: {}", e);
+            process::exit(1);
+        }
+    };
+    if let Err(e) = fs::write(&output, json) {
+        eprintln!("Error writing {}: {}", output, e);
+        process::exit(1);
+    }
+    println!("\n✓ Packaged system '{}' ({} files) into {}", package.manifest.name, package.sources.len(), output);
+    println!("  fingerprint {}", package.manifest.fingerprint);
+}
+
+/// Read the package at `path`, exiting if it cannot be
+fn read_package(path: &str) -> package::Package {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) => {
+            eprintln!("Error reading {}: {}", path, e);
+            process::exit(1);
+        }
+    };
+    match package::Package::parse(&content) {
+        Ok(package) => package,
+        Err(e) => {
+            eprintln!("Error in {}: {}", path, e);
+            process::exit(1);
+        }
+    }
+}
+
+fn info_command(path: &str) {
+    let package = read_package(path);
+    let manifest = &package.manifest;
+    println!("\nPackage: {} (written by {})", manifest.name, manifest.tool);
+    println!("  Fingerprint: {}", manifest.fingerprint);
+    println!("  Roles: {}, States: {}, Sequences: {}", manifest.roles, manifest.states, manifest.sequences);
+    println!("  Graph: {} nodes, {} edges", package.graph.nodes.len(), package.graph.edges.len());
+    println!("  Files:");
+    for file in &manifest.files {
+        let size = package.sources.iter().find(|s| s.path == file.path).map_or(0, |s| s.content.len());
+        println!("    - {} ({} bytes, sha256 {})", file.path, size, &file.sha256[..12.min(file.sha256.len())]);
+        if let Some(metadata) = manifest.metadata.get(&file.path) {
+            let fields = [("author", &metadata.author), ("level", &metadata.level), ("reviewed", &metadata.reviewed)];
+            for (key, value) in fields.iter().filter_map(|(key, value)| Some((key, value.as_ref()?))) {
+                println!("        {}: {}", key, value);
+            }
+        }
+    }
+    let tampered = package.tampered();
+    if tampered.is_empty() {
+        println!("\n✓ Every file matches the manifest");
+    } else {
+        println!("\n✗ The package does not match its manifest:");
+        for mismatch in &tampered {
+            println!("  - {}", mismatch);
+        }
+        process::exit(1);
+    }
+}
+
+/// Write the sources of the package at `path` into `output`, a directory
+/// named after the system by default
+fn unpack_command(path: &str, output: Option<&str>) {
+    let package = read_package(path);
+    let output = output.unwrap_or(&package.manifest.name);
+    if fs::read_dir(output).is_ok_and(|mut entries| entries.next().is_some()) {
+        eprintln!("Error: {} already exists and is not empty", output);
+        process::exit(1);
+    }
+    match package.unpack(output) {
+        Ok(written) => {
+            for file in &written {
+                println!("  {}", file);
+            }
+            println!("\n✓ Unpacked system '{}' into {}", package.manifest.name, output);
+            println!("  Check it with: mat validate {} --locked", output);
+        }
+        Err(e) => {
+            eprintln!("Error unpacking {}: {}", path, e);
+            process::exit(1);
+        }
+    }
+}
+
+/// Fetch the package at `source`, a path or an http(s) URL, into the
+/// vendor directory of the system of `path`, and record it as a dependency
+fn add_command(path: &str, source: &str, name: Option<&str>) {
+    let content = if source.starts_with("http://") || source.starts_with("https://") {
+        let output = process::Command::new("curl").args(["-fsSL", source]).output();
+        match output {
+            Ok(output) if output.status.success() => String::from_utf8_lossy(&output.stdout).into_owned(),
+            Ok(output) => {
+                eprintln!("Error fetching {}: {}", source, String::from_utf8_lossy(&output.stderr).trim());
+                process::exit(1);
+            }
+            Err(e) => {
+                eprintln!("Error fetching {}: curl could not be run: {}", source, e);
+                process::exit(1);
+            }
+        }
+    } else {
+        match fs::read_to_string(source) {
+            Ok(content) => content,
+            Err(e) => {
+                eprintln!("Error reading {}: {}", source, e);
+                process::exit(1);
+            }
+        }
+    };
+    let package = match package::Package::parse(&content) {
+        Ok(package) => package,
+        Err(e) => {
+            eprintln!("Error in {}: {}", source, e);
+            process::exit(1);
+        }
+    };
+    let name = name.map(String::from).unwrap_or_else(|| package::namespace(&package.manifest.name));
+    if package::namespace(&name) != name {
+        eprintln!("Error: '{}' is not a valid namespace, pass another with --as", name);
+        process::exit(1);
+    }
+
+    let vendor = Path::new(path).join(config::VENDOR_DIR).join(&name);
+    let vendor = vendor.to_string_lossy();
+    if let Err(e) = fs::remove_dir_all(vendor.as_ref()).or_else(|e| match e.kind() {
+        std::io::ErrorKind::NotFound => Ok(()),
+        _ => Err(e),
+    }) {
+        eprintln!("Error removing {}: {}", vendor, e);
+        process::exit(1);
+    }
+    if let Err(e) = package.unpack(&vendor) {
+        eprintln!("Error unpacking {}: {}", source, e);
+        process::exit(1);
+    }
+
+    let config_path = config::Config::find(path).unwrap_or_else(|| {
+        Path::new(path).join(config::CONFIG_FILES[0]).to_string_lossy().into_owned()
+    });
+    let current = fs::read_to_string(&config_path).unwrap_or_default();
+    if let Err(e) = fs::write(&config_path, config::with_dependency(&current, &name, source)) {
+        eprintln!("Error writing {}: {}", config_path, e);
+        process::exit(1);
+    }
+    println!("\n✓ Added system '{}' ({} files) as '{}' in {}", package.manifest.name, package.sources.len(), name, vendor);
+    println!("  Recorded in {}; refer to its states as {}.<State>", config_path, name);
+}
+
+fn validate_per_file_command(path: &str) {
+    let config = load_config(path);
+    let sources = match loader::read_configured(path, &config) {
+        Ok(sources) if !sources.is_empty() => sources,
+        Ok(_) => {
+            eprintln!("Error: No .martial files found in directory");
+            process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+
+    println!("Files:");
+    let mut files = Vec::new();
+    let mut failed = 0;
+    for source in &sources {
+        match loader::parse_source_defining(source, config.compat, &config.defines) {
+            Ok(file) => {
+                println!("  ✓ {}", source.path);
+                files.push(file);
+            }
+            Err(e) => {
+                println!("  ✗ {}", source.path);
+                println!("      {}", e);
+                failed += 1;
+            }
+        }
+    }
+
+    let origins = loader::origins(&files);
+    if !origins.is_empty() {
+        println!("\nDeclared in:");
+        for origin in &origins {
+            println!(
+                "  {} {} — {}:{}",
+                origin.kind, origin.name, origin.file, origin.position.line
+            );
+        }
+    }
+
+    if failed > 0 {
+        eprintln!("\n✗ {} file(s) have syntax errors; skipping cross-file validation", failed);
+        process::exit(1);
+    }
+    match loader::validate_report_with(&loader::system_name(path), &files, config.validator()) {
+        Ok(report) => println!("\n✓ System '{}' is valid across {} file(s)", report.system.name, files.len()),
+        Err(e) => {
+            eprintln!("\n✗ {}", e);
+            process::exit(1);
+        }
+    }
+}
+
+/// The system and the graph exported by `mat graph` and `mat dot`, as their
+/// options say; `--annotate` adds the positions no sequence uses
+fn exported_graph(path: &str, args: &[String]) -> (semantic::MartialSystem, graph::MartialGraph) {
+    let config = load_config(path);
+    let level = option_arg(args, &["-l", "--level"]);
+    let ruleset = option_arg(args, &["--ruleset"]).or(config.ruleset.as_deref());
+    let lang = option_arg(args, &["--lang"]);
+    let system = localized(under_ruleset(at_level(load_and_validate_system(path), level), ruleset), lang);
+    let mut graph = perspective(&system, option_arg(args, &["--role"]));
+    if args.iter().any(|a| a == "--annotate") {
+        graph = graph.with_declared_nodes(&system);
+    }
+    check_selectors(option_arg(args, &["--start"]));
+
+    let depth = option_arg(args, &["--depth"]).map(|depth| match depth.parse::<usize>() {
+        Ok(depth) => depth,
+        Err(_) => {
+            eprintln!("Error: --depth expects a number of transitions");
+            process::exit(1);
+        }
+    });
+    match (option_arg(args, &["--start"]), depth) {
+        (Some(start), depth) if graph.nodes.iter().any(|n| n.matches(start)) => graph = graph.within(start, depth),
+        (Some(start), _) => {
+            eprintln!("Error: '{}' is not a state or position of the graph", start);
+            process::exit(1);
+        }
+        (None, Some(_)) => {
+            eprintln!("Error: --depth requires --start");
+            process::exit(1);
+        }
+        (None, None) => {}
+    }
+    if args.iter().any(|a| a == "--merge-edges") {
+        graph = graph.merge_parallel_edges();
+    }
+    (system, graph)
+}
+
+fn graph_command(path: &str, args: &[String]) {
+    let format = option_arg(args, &["-f", "--format"]).unwrap_or("json");
+    let exporters = export::GraphExporterRegistry::builtin();
+    let Some(exporter) = exporters.get(format) else {
+        eprintln!("Error: Unknown graph format '{}'. Available formats: {}", format, exporters.names().join(", "));
+        process::exit(1);
+    };
+    let (system, graph) = exported_graph(path, args);
+    if !args.iter().any(|a| a == "--annotate") {
+        return print_export(exporter, &graph);
+    }
+    let annotations = annotate::Annotations::new(&system, &graph);
+    match format {
+        "dot" => println!("{}", annotations.to_dot(&graph)),
+        "html" => println!("{}", annotations.to_html(&graph)),
+        other => {
+            eprintln!("Error: --annotate applies to the dot and html formats, not '{}'", other);
+            process::exit(1);
+        }
+    }
+}
+
+fn print_export(exporter: &dyn export::GraphExporter, graph: &graph::MartialGraph) {
+    match exporter.export(graph, &export::ExportOptions::default()) {
+        Ok(content) => println!("{}", content),
+        Err(e) => {
+            eprintln!("Error exporting to {}: {}", exporter.name(), e);
+            process::exit(1);
+        }
+    }
+}
+
+fn system_command(path: &str, lang: Option<&str>) {
+    let system = localized(load_and_validate_system(path), lang);
+    
+    match system.to_json() {
+        Ok(json) => {
+            println!("{}", json);
+        }
+        Err(e) => {
+            eprintln!("Error exporting to JSON: {}", e);
+            process::exit(1);
+        }
+    }
+}
+
+fn schema_command(format: &str) {
+    match schema::schema(format) {
+        Some(schema) => {
+            println!("{}", serde_json::to_string_pretty(&schema).unwrap_or_default());
+        }
+        None => {
+            eprintln!("Error: Unknown schema format '{}' (expected {})", format, schema::FORMATS.join(", "));
+            process::exit(1);
+        }
+    }
+}
+
+fn doc_command(path: &str, output: Option<&str>, lang: Option<&str>) {
+    let system = localized(load_and_validate_system(path), lang);
+    write_output(output, &doc::to_markdown(&system));
+}
+
+fn flashcards_command(path: &str, output: Option<&str>) {
+    let system = load_and_validate_system(path);
+    write_output(output, &doc::flashcards(&system));
+}
+
+fn site_command(path: &str, output: &str, lang: Option<&str>) {
+    let system = localized(load_and_validate_system(path), lang);
+    
+    if let Err(e) = fs::create_dir_all(output) {
+        eprintln!("Error creating {}: {}", output, e);
+        process::exit(1);
+    }
+    let pages = site::generate(&system);
+    for page in &pages {
+        let page_path = Path::new(output).join(&page.path);
+        if let Err(e) = fs::write(&page_path, &page.html) {
+            eprintln!("Error writing {}: {}", page_path.display(), e);
+            process::exit(1);
+        }
+    }
+    eprintln!("Wrote {} pages to {}", pages.len(), output);
+}
+
+fn import_command(input: &str, format: Option<&str>, output: &str, force: bool) {
+    let format = match format {
+        Some(name) => import::ImportFormat::from_name(name),
+        None => import::ImportFormat::from_path(input),
+    };
+    let format = match format {
+        Some(format) => format,
+        None => {
+            eprintln!(
+                "Error: Unknown import format (use --format with one of: {})",
+                import::ImportFormat::NAMES.join(", ")
+            );
+            process::exit(1);
+        }
+    };
+    
+    let content = match fs::read_to_string(input) {
+        Ok(content) => content,
+        Err(e) => {
+            eprintln!("Error reading {}: {}", input, e);
+            process::exit(1);
+        }
+    };
+    let declarations = match import::import(format, &content) {
+        Ok(declarations) => declarations,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+    
+    // Validate before writing anything
+    let sources = import::to_sources(output, declarations);
+    if let Err(e) = loader::load_sources(&loader::system_name(output), &sources) {
+        eprintln!("Imported system is invalid: {}", e);
+        process::exit(1);
+    }
+    let existing: Vec<&str> =
+        sources.iter().map(|source| source.path.as_str()).filter(|path| Path::new(path).exists()).collect();
+    if !existing.is_empty() && !force {
+        eprintln!("Error: {} already exist(s); use --force to overwrite", existing.join(", "));
+        process::exit(1);
+    }
+    
+    if let Err(e) = fs::create_dir_all(output) {
+        eprintln!("Error creating {}: {}", output, e);
+        process::exit(1);
+    }
+    for source in &sources {
+        if let Err(e) = fs::write(&source.path, &source.content) {
+            eprintln!("Error writing {}: {}", source.path, e);
+            process::exit(1);
+        }
+        eprintln!("Wrote {}", source.path);
+    }
+    eprintln!("✓ Imported system is valid");
+}
+
+fn gen_synthetic_command(output: &str, options: &synthetic::SyntheticOptions) {
+    let sources = synthetic::generate(output, options);
+    if let Err(e) = fs::create_dir_all(output) {
+        eprintln!("Error creating {}: {}", output, e);
+        process::exit(1);
+    }
+    for source in &sources {
+        if let Err(e) = fs::write(&source.path, &source.content) {
+            eprintln!("Error writing {}: {}", source.path, e);
+            process::exit(1);
+        }
+    }
+    eprintln!(
+        "Wrote {} states and {} sequences to {} files in {}",
+        options.states,
+        options.sequences,
+        sources.len(),
+        output
+    );
+}
+
+fn rename_command(path: &str, kind: &str, old: &str, new: &str) {
+    let kind = match refactor::SymbolKind::from_name(kind) {
+        Some(kind) => kind,
+        None => {
+            eprintln!(
+                "Error: Unknown kind '{}' (expected one of: {})",
+                kind,
+                refactor::SymbolKind::NAMES.join(", ")
+            );
+            process::exit(1);
+        }
+    };
+    
+    let (mut sources, mut files) = parse_directory(path);
+    
+    let counts = match refactor::rename(&mut files, kind, old, new) {
+        Ok(counts) => counts,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+    
+    // Edit changed files and check the result before touching the disk
+    for (source, count) in sources.iter_mut().zip(&counts) {
+        if *count > 0 {
+            match refactor::rename_source(&source.content, kind, old, new) {
+                Ok(content) => source.content = content,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    process::exit(1);
+                }
+            }
+        }
+    }
+    if let Err(e) = validate_sources(path, &sources) {
+        eprintln!("Rename would make the system invalid: {}", e);
+        process::exit(1);
+    }
+    
+    for (source, count) in sources.iter().zip(&counts) {
+        if *count > 0 {
+            if let Err(e) = fs::write(&source.path, &source.content) {
+                eprintln!("Error writing {}: {}", source.path, e);
+                process::exit(1);
+            }
+            eprintln!("  {} ({} occurrences)", source.path, count);
+        }
+    }
+    let changed = counts.iter().filter(|c| **c > 0).count();
+    eprintln!("✓ Renamed {} '{}' to '{}' in {} files", kind, old, new, changed);
+}
+
+fn migrate_command(path: &str, check: bool) {
+    let sources = match loader::read_configured(path, &load_config(path)) {
+        Ok(sources) => sources,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+
+    // Migrate every file before touching the disk
+    let mut migrations = Vec::new();
+    for source in &sources {
+        match migrate::migrate(&source.content) {
+            Ok(Some(migration)) => migrations.push((source, migration)),
+            Ok(None) => {}
+            Err(e) => {
+                eprintln!("Error in {}: {}", source.path, e);
+                process::exit(1);
+            }
+        }
+    }
+
+    for (source, migration) in &migrations {
+        if !check {
+            if let Err(e) = fs::write(&source.path, &migration.content) {
+                eprintln!("Error writing {}: {}", source.path, e);
+                process::exit(1);
+            }
+        }
+        eprintln!("  {} (martial {} → {})", source.path, migration.from, ast::LATEST_VERSION);
+    }
+    match (migrations.len(), check) {
+        (0, _) => eprintln!("✓ All {} files use martial {}", sources.len(), ast::LATEST_VERSION),
+        (n, true) => {
+            eprintln!("✗ {} of {} files need migrating", n, sources.len());
+            process::exit(1);
+        }
+        (n, false) => eprintln!("✓ Migrated {} of {} files to martial {}", n, sources.len(), ast::LATEST_VERSION),
+    }
+}
+
+/// Validate the system of `path` and apply a suggestion of its error, on
+/// confirmation unless `yes`, until it is valid or an error has none
+fn fix_command(path: &str, yes: bool) {
+    let config = load_config(path);
+    let mut sources = match loader::read_configured(path, &config) {
+        Ok(sources) => sources,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+    let system_name = loader::system_name(path);
+    let mut lines = io::stdin().lines();
+    let mut applied = 0;
+    let mut previous: Option<semantic::SemanticError> = None;
+    loop {
+        let error = match validate_sources(path, &sources) {
+            Ok(_) => {
+                eprintln!("✓ Applied {} fix(es); system '{}' is valid", applied, system_name);
+                return;
+            }
+            Err(loader::LoadError::Semantic(error)) => error,
+            Err(e) => {
+                eprintln!("{}", e);
+                eprintln!("✗ Applied {} fix(es); parse errors cannot be fixed automatically", applied);
+                process::exit(1);
+            }
+        };
+        eprintln!("\n{}", error);
+        if let (Some(file), Some(position)) = (&error.file, error.position) {
+            eprintln!("  --> {}, {}", file, position);
+        }
+        if previous.as_ref().is_some_and(|p| p.message == error.message && p.position == error.position) {
+            eprintln!("✗ Applied {} fix(es); the last one did not fix the error", applied);
+            process::exit(1);
+        }
+
+        // Suggestions whose edits can be located, with the sources they change
+        let fixes: Vec<_> = error
+            .suggestions
+            .iter()
+            .filter_map(|suggestion| Some((suggestion, fix::apply(&sources, &error, suggestion)?)))
+            .collect();
+        if fixes.is_empty() {
+            eprintln!("✗ Applied {} fix(es); this error has no automatic fix", applied);
+            process::exit(1);
+        }
+        for (i, (suggestion, changed)) in fixes.iter().enumerate() {
+            eprintln!("  [{}] {}", i + 1, suggestion.message);
+            for new in changed {
+                let old = sources.iter().find(|s| s.path == new.path).map_or("", |s| s.content.as_str());
+                let (line, removed, added) = fix::changed_lines(old, &new.content);
+                eprintln!("      {}, line {}", new.path, line);
+                for text in removed {
+                    eprintln!("      - {}", text);
+                }
+                for text in added {
+                    eprintln!("      + {}", text);
+                }
+            }
+        }
+
+        let choice = if yes {
+            0
+        } else {
+            eprint!("Apply [1-{}], or stop [n]? ", fixes.len());
+            // Stop at the end of the input rather than apply everything
+            let answer = lines.next().and_then(Result::ok);
+            match answer.as_deref().map(str::trim) {
+                Some("" | "y" | "yes") => Some(0),
+                Some(answer) => answer.parse::<usize>().ok().filter(|n| (1..=fixes.len()).contains(n)).map(|n| n - 1),
+                None => None,
+            }
+            .unwrap_or_else(|| {
+                eprintln!("Stopped after {} fix(es)", applied);
+                process::exit(1);
+            })
+        };
+        for new in &fixes[choice].1 {
+            if let Err(e) = fs::write(&new.path, &new.content) {
+                eprintln!("Error writing {}: {}", new.path, e);
+                process::exit(1);
+            }
+            if let Some(source) = sources.iter_mut().find(|s| s.path == new.path) {
+                source.content = new.content.clone();
+            }
+        }
+        applied += 1;
+        previous = Some(error);
+    }
+}
+
+fn extract_group_command(path: &str, group: &str, output: Option<&str>) {
+    let (sources, _) = parse_directory(path);
+    let output = match output {
+        Some(output) => output.to_string(),
+        None => Path::new(path)
+            .join(format!("{}.martial", group.to_lowercase()))
+            .to_string_lossy()
+            .into_owned(),
+    };
+    if Path::new(&output).exists() {
+        eprintln!("Error: {} already exists", output);
+        process::exit(1);
+    }
+    
+    let extraction = match refactor::extract_group(&sources, group, &output) {
+        Ok(extraction) => extraction,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+    
+    // Files left without declarations are removed rather than kept empty
+    let mut unchanged = Vec::new();
+    let mut updated = Vec::new();
+    let mut emptied = Vec::new();
+    for (source, remainder) in sources.into_iter().zip(extraction.remainders) {
+        match remainder {
+            refactor::Remainder::Unchanged => unchanged.push(source),
+            refactor::Remainder::Emptied => emptied.push(source.path),
+            refactor::Remainder::Edited(content) => updated.push(loader::SourceFile { content, ..source }),
+        }
+    }
+    let new_source = extraction.file;
+    
+    let check: Vec<_> = unchanged.into_iter().chain(updated.clone()).chain([new_source.clone()]).collect();
+    if let Err(e) = validate_sources(path, &check) {
+        eprintln!("Extraction would make the system invalid: {}", e);
+        process::exit(1);
+    }
+    
+    for source in [&new_source].into_iter().chain(&updated) {
+        if let Err(e) = fs::write(&source.path, &source.content) {
+            eprintln!("Error writing {}: {}", source.path, e);
+            process::exit(1);
+        }
+    }
+    for source in &updated {
+        eprintln!("  {} (updated)", source.path);
+    }
+    for path in &emptied {
+        if let Err(e) = fs::remove_file(path) {
+            eprintln!("Error removing {}: {}", path, e);
+            process::exit(1);
+        }
+        eprintln!("  {} (removed)", path);
+    }
+    eprintln!("✓ Extracted group '{}' ({} declarations) to {}", group, extraction.moved, output);
+}
+
+fn lesson_command(path: &str, focus: &str, minutes: u32, output: Option<&str>, lang: Option<&str>) {
+    let system = load_and_validate_system(path);
+    
+    let mut lesson = match lesson::plan(&system, focus, minutes) {
+        Some(lesson) => lesson,
+        None => {
+            eprintln!("Error: '{}' is not a state of the system", focus);
+            process::exit(1);
+        }
+    };
+    if let Some(label) = lang.and_then(|lang| system.states[focus].label(lang)) {
+        lesson.focus = label.to_string();
+    }
+    write_output(output, &lesson::to_markdown(&localized(system, lang), &lesson));
+}
+
+fn order_command(path: &str, output: Option<&str>) {
+    let system = load_and_validate_system(path);
+    write_output(output, &curriculum::to_markdown(&system, &curriculum::order(&system)));
+}
+
+fn log_add_command(path: &str, entry: &str) {
+    let mut entry = match sparring::parse_entry(entry) {
+        Ok(entry) => entry,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+    if entry.date.is_none() {
+        entry.date = Some(sparring::today());
+    }
+    
+    let system = load_and_validate_system(path);
+    let graph = graph::MartialGraph::from_system(&system);
+    if !sparring::is_declared(&graph, &entry) {
+        eprintln!("⚠ {} -> {} via {} is not declared in the system", entry.from.id(), entry.to.id(), entry.action);
+    }
+    
+    let log_path = Path::new(path).join(sparring::LOG_FILE);
+    let mut content = fs::read_to_string(&log_path).unwrap_or_default();
+    if !content.is_empty() && !content.ends_with('\n') {
+        content.push('\n');
+    }
+    content.push_str(&format!("{}\n", entry));
+    if let Err(e) = fs::write(&log_path, content) {
+        eprintln!("Error writing {}: {}", log_path.display(), e);
+        process::exit(1);
+    }
+    eprintln!("✓ Logged to {}", log_path.display());
+}
+
+fn heatmap_command(path: &str, format: &str, log: Option<&str>, output: Option<&str>) {
+    let system = load_and_validate_system(path);
+    let graph = graph::MartialGraph::from_system(&system);
+    
+    let entries = read_log(path, log, true);
+    
+    let heatmap = sparring::Heatmap::new(&graph, &entries);
+    for usage in &heatmap.undeclared {
+        eprintln!(
+            "⚠ Undeclared transition used {} times: {} -> {} via {}",
+            usage.count,
+            usage.from.id(),
+            usage.to.id(),
+            usage.action
+        );
+    }
+    let rendered = match format {
+        "dot" => heatmap.to_dot(&graph),
+        "json" => match heatmap.to_json() {
+            Ok(json) => json,
+            Err(e) => {
+                eprintln!("Error exporting to JSON: {}", e);
+                process::exit(1);
+            }
+        },
+        other => {
+            eprintln!("Error: Unknown heatmap format '{}' (expected dot or json)", other);
+            process::exit(1);
+        }
+    };
+    write_output(output, &rendered);
+}
+
+fn balance_command(path: &str, format: &str) {
+    let report = balance::BalanceReport::new(&load_and_validate_system(path));
+    match format {
+        "text" => print!("{}", report.to_text()),
+        "json" => match report.to_json() {
+            Ok(json) => println!("{}", json),
+            Err(e) => {
+                eprintln!("Error exporting to JSON: {}", e);
+                process::exit(1);
+            }
+        },
+        other => {
+            eprintln!("Error: Unknown balance format '{}' (expected text or json)", other);
+            process::exit(1);
+        }
+    }
+}
+
+fn matrix_command(path: &str, axis: matrix::Axis, format: &str, output: Option<&str>) {
+    let graph = graph::MartialGraph::from_system(&load_and_validate_system(path));
+    if axis == matrix::Axis::Group && graph.groups.is_empty() {
+        eprintln!("Error: the system declares no groups, use --by state");
+        process::exit(1);
+    }
+    let matrix = matrix::ReachabilityMatrix::new(&graph, axis);
+    let rendered = match format {
+        "markdown" | "md" => matrix.to_markdown(),
+        "csv" => matrix.to_csv(),
+        "json" => match matrix.to_json() {
+            Ok(json) => json,
+            Err(e) => {
+                eprintln!("Error exporting to JSON: {}", e);
+                process::exit(1);
+            }
+        },
+        other => {
+            eprintln!("Error: Unknown matrix format '{}' (expected markdown, csv or json)", other);
+            process::exit(1);
+        }
+    };
+    write_output(output, &rendered);
+}
+
+/// Print the bridges of the graph of `path`, or the minimum cut `between`
+/// two positions
+fn bottlenecks_command(path: &str, between: Option<(&str, &str)>, format: &str) {
+    let system = load_and_validate_system(path);
+    let graph = graph::MartialGraph::from_system(&system);
+    let describe = |edge: &graph::Edge| {
+        format!("{} -> {} via {} ({})", edge.from.id(), edge.to.id(), edge.action, edge.sequences.join(", "))
+    };
+    let json = |value: Result<String, serde_json::Error>| match value {
+        Ok(json) => println!("{}", json),
+        Err(e) => {
+            eprintln!("Error exporting to JSON: {}", e);
+            process::exit(1);
+        }
+    };
+    if !matches!(format, "text" | "json") {
+        eprintln!("Error: Unknown bottlenecks format '{}' (expected text or json)", format);
+        process::exit(1);
+    }
+
+    let Some((from, to)) = between else {
+        let bridges = bottleneck::bridges(&graph);
+        if format == "json" {
+            return json(serde_json::to_string_pretty(&bridges));
+        }
+        if bridges.is_empty() {
+            println!("\n✓ No transition of '{}' is a bridge", system.name);
+            return;
+        }
+        println!("\nBridges of '{}' ({}):", system.name, bridges.len());
+        for bridge in &bridges {
+            let separated: Vec<String> = bridge.separated.iter().map(|node| node.id()).collect();
+            println!("  - {}", describe(&bridge.edge));
+            println!("      separates {}", separated.join(", "));
+        }
+        return;
+    };
+    for endpoint in [from, to] {
+        if !graph.nodes.iter().any(|node| node.matches(endpoint)) {
+            eprintln!("Error: '{}' is not a state or position of the graph", endpoint);
+            process::exit(1);
+        }
+    }
+    let Some(cut) = bottleneck::min_cut(&graph, from, to) else {
+        eprintln!("Error: {} and {} share a position, no cut separates them", from, to);
+        process::exit(1);
+    };
+    if format == "json" {
+        return json(serde_json::to_string_pretty(&cut));
+    }
+    if cut.is_empty() {
+        println!("\nNo path leads from {} to {}", from, to);
+        return;
+    }
+    println!("\nMinimum cut from {} to {} ({} transition(s)):", from, to, cut.len());
+    for edge in &cut {
+        println!("  - {}", describe(edge));
+    }
+}
+
+fn compare_command(left: &str, right: &str, format: &str, output: Option<&str>) {
+    let left = graph::MartialGraph::from_system(&load_and_validate_system(left));
+    let right = graph::MartialGraph::from_system(&load_and_validate_system(right));
+    let comparison = compare::Comparison::new(&left, &right);
+    let rendered = match format {
+        "text" => comparison.to_text(),
+        "dot" => comparison.to_dot(&left, &right),
+        "json" => match comparison.to_json() {
+            Ok(json) => json,
+            Err(e) => {
+                eprintln!("Error exporting to JSON: {}", e);
+                process::exit(1);
+            }
+        },
+        other => {
+            eprintln!("Error: Unknown compare format '{}' (expected text, dot or json)", other);
+            process::exit(1);
+        }
+    };
+    write_output(output, &rendered);
+}
+
+fn next_command(path: &str, position: &str, log: Option<&str>, limit: usize) {
+    let system = load_and_validate_system(path);
+    let graph = graph::MartialGraph::from_system(&system);
+    
+    let node = match train::parse_node(position) {
+        Some(node) if graph.nodes.contains(&node) => node,
+        Some(_) => {
+            eprintln!("Error: '{}' is not a position of the graph", position);
+            process::exit(1);
+        }
+        None => {
+            eprintln!("Error: expected a position as State[Role], got '{}'", position);
+            process::exit(1);
+        }
+    };
+    // Without a log every declared action is equally likely
+    let entries = read_log(path, log, log.is_some());
+    let suggestions = sparring::suggestions(&graph, &entries, &node);
+    
+    println!("\nFrom {} ({} logged transitions):", node.id(), suggestions.iter().map(|s| s.count).sum::<usize>());
+    if suggestions.is_empty() {
+        println!("  No actions from here.");
+    }
+    for (i, suggestion) in suggestions.iter().take(limit).enumerate() {
+        let source = if suggestion.sequences.is_empty() {
+            "not declared".to_string()
+        } else {
+            suggestion.sequences.join(", ")
+        };
+        println!(
+            "  {}. {} -> {}  {:.0}%  ({} logged; {})",
+            i + 1,
+            suggestion.action,
+            suggestion.to.id(),
+            suggestion.probability * 100.0,
+            suggestion.count,
+            source
+        );
+    }
+}
+
+fn path_command(path: &str, from: &str, to: &str, canonical: bool) {
+    let system = load_and_validate_system(path);
+    let graph = graph::MartialGraph::from_system(&system);
+
+    let mut found = if canonical { graph.canonical_path(from, to) } else { graph.shortest_path(from, to) };
+    if canonical && found.is_none() {
+        found = graph.shortest_path(from, to);
+        if found.is_some() {
+            println!("\nNo path joins sequences only at their entry points; showing the shortest path.");
+        }
+    }
+    let Some(edges) = found else {
+        eprintln!("Error: no path from {} to {}", from, to);
+        process::exit(1);
+    };
+
+    println!("\nFrom {} to {} in {} actions:", from, to, edges.len());
+    for (i, edge) in edges.iter().enumerate() {
+        println!("  {}. {} ({}): {} -> {}", i + 1, edge.action, edge.sequence, edge.from.id(), edge.to.id());
+    }
+}
+
+/// List up to `k` routes from `from` to `to` meeting `constraints`, then the
+/// transitions every one of them takes
+fn paths_command(path: &str, from: &str, to: &str, constraints: &graph::PathConstraints, k: usize) {
+    let system = load_and_validate_system(path);
+    let graph = graph::MartialGraph::from_system(&system).merge_parallel_edges();
+    for pattern in constraints.via.iter().chain(&constraints.avoid) {
+        if !graph.nodes.iter().any(|n| n.matches(pattern)) {
+            eprintln!("Warning: '{}' matches no position of the graph", pattern);
+        }
+    }
+    let routes = graph.find_paths_with_constraints(from, to, constraints, k);
+    if routes.is_empty() {
+        let mut conditions = Vec::new();
+        if !constraints.via.is_empty() {
+            conditions.push(format!("via {}", constraints.via.join(", ")));
+        }
+        if !constraints.avoid.is_empty() {
+            conditions.push(format!("avoiding {}", constraints.avoid.join(", ")));
+        }
+        let conditions = if conditions.is_empty() { String::new() } else { format!(" {}", conditions.join(" ")) };
+        eprintln!("Error: no path from {} to {}{}", from, to, conditions);
+        process::exit(1);
+    }
+
+    println!("\n{} route(s) from {} to {}:", routes.len(), from, to);
+    for (n, route) in routes.iter().enumerate() {
+        println!("\n  Route {}, {} actions:", n + 1, route.len());
+        for (i, edge) in route.iter().enumerate() {
+            println!("    {}. {} ({}): {} -> {}", i + 1, edge.action, edge.sequences.join(", "), edge.from.id(), edge.to.id());
+        }
+    }
+    if routes.len() > 1 {
+        let shared: Vec<&&graph::Edge> = routes[0].iter().filter(|edge| routes.iter().all(|route| route.contains(edge))).collect();
+        if shared.is_empty() {
+            println!("\n✓ No action is on every route");
+        } else {
+            let actions: Vec<String> = shared.iter().map(|edge| format!("{} ({} -> {})", edge.action, edge.from.id(), edge.to.id())).collect();
+            println!("\n⚠ Every route takes {}", actions.join(", "));
+        }
+    }
+}
+
+fn reachable_command(path: &str, from: &str, kind: Option<&str>) {
+    check_selectors([from]);
+    let system = load_and_validate_system(path);
+    let graph = graph::MartialGraph::from_system(&system);
+    if !graph.nodes.iter().any(|n| n.matches(from)) {
+        eprintln!("Error: '{}' is not a state or position of the graph", from);
+        process::exit(1);
+    }
+
+    let kind_of = |name: &str| system.sequences.get(name).and_then(|s| s.kind());
+    let sequences: Vec<&str> = graph
+        .reachable_sequences(from)
+        .into_iter()
+        .map(|name| name.as_ref())
+        .filter(|name| kind.is_none() || kind_of(name) == kind)
+        .collect();
+
+    println!("\n{} reachable from {} ({}):", kind.map_or("Sequences".to_string(), |k| format!("'{}' sequences", k)), from, sequences.len());
+    for name in sequences {
+        match kind_of(name).filter(|_| kind.is_none()) {
+            Some(kind) => println!("  - {} ({})", name, kind),
+            None => println!("  - {}", name),
+        }
+    }
+}
+
+fn position_command(path: &str, state: &str) {
+    let system = load_and_validate_system(path);
+    let Some(description) = system.describe(state).filter(|d| d.kind == semantic::IdentifierKind::State) else {
+        eprintln!("Error: '{}' is not a state", state);
+        process::exit(1);
+    };
+    let graph = graph::MartialGraph::from_system(&system);
+
+    println!("\nPosition {}", state);
+    println!("  Roles: {}", description.roles.join(", "));
+    if !description.states.is_empty() {
+        println!("  Groups: {}", description.states.join(", "));
+    }
+    if system.states[state].is_final() {
+        println!("  Final (@final)");
+    }
+
+    let initial = graph.initial_nodes();
+    let action = |edge: &graph::Edge| {
+        let side = if edge.reaction { ", reaction" } else if edge.failure { ", failed" } else { "" };
+        format!("{} ({}{})", edge.action, edge.sequence, side)
+    };
+    for (node, centrality) in graph.centrality().into_iter().filter(|(node, _)| node.state == state) {
+        let incoming: Vec<&graph::Edge> = graph.edges.iter().filter(|e| &e.to == node).collect();
+        let outgoing: Vec<&graph::Edge> = graph.edges.iter().filter(|e| &e.from == node).collect();
+        let mut flags = Vec::new();
+        if initial.contains(&node) {
+            flags.push("initial");
+        }
+        if outgoing.iter().all(|e| e.to == *node) {
+            flags.push("terminal");
+        }
+        match flags.is_empty() {
+            true => println!("\n{}", node.id()),
+            false => println!("\n{} ({})", node.id(), flags.join(", ")),
+        }
+        println!("  Incoming ({}):", incoming.len());
+        for edge in incoming {
+            println!("    {} from {}", action(edge), edge.from.id());
+        }
+        println!("  Outgoing ({}):", outgoing.len());
+        for edge in outgoing {
+            println!("    {} to {}", action(edge), edge.to.id());
+        }
+        println!(
+            "  Centrality: betweenness {:.2}, closeness {:.2}",
+            centrality.betweenness, centrality.closeness
+        );
+    }
+}
+
+fn flows_command(path: &str) {
+    let system = load_and_validate_system(path);
+    let flows = graph::MartialGraph::from_system(&system).flows();
+    println!("\nSequence flows ({}):", flows.len());
+    for flow in flows {
+        let passing = if flow.ends { "" } else { " (passing through)" };
+        println!("  {} -> {} at {}{}", flow.from, flow.to, flow.at.id(), passing);
+    }
+}
+
+fn describe_command(path: &str, name: &str) {
+    let system = load_and_validate_system(path);
+    match system.describe(name) {
+        Some(description) => print!("\n{}", description),
+        None => {
+            eprintln!("Error: '{}' is not a state, role, sequence, group or action", name);
+            process::exit(1);
+        }
+    }
+}
+
+fn refs_command(path: &str, name: &str) {
+    let system = load_and_validate_system(path);
+    let references = system.references(name);
+    if references.is_empty() {
+        eprintln!("Error: '{}' is not a state, role, group, sequence or action", name);
+        process::exit(1);
+    }
+    println!("\nReferences to {} ({}):", name, references.len());
+    for reference in references {
+        println!("  {}", reference);
+    }
+}
+
+/// Report what removing a state or sequence would break, without editing
+/// any file
+fn impact_command(path: &str, target: impact::Target, name: &str, format: &str) {
+    let system = load_and_validate_system(path);
+    let Some(impact) = impact::Impact::new(&system, target, name) else {
+        eprintln!("Error: '{}' is not a {} of '{}'", name, target.name(), system.name);
+        process::exit(1);
+    };
+    match format {
+        "text" => print!("{}", impact.to_text()),
+        "json" => match impact.to_json() {
+            Ok(json) => println!("{}", json),
+            Err(e) => {
+                eprintln!("Error exporting to JSON: {}", e);
+                process::exit(1);
+            }
+        },
+        other => {
+            eprintln!("Error: Unknown impact format '{}' (expected text or json)", other);
+            process::exit(1);
+        }
+    }
+}
+
+fn storyboard_command(path: &str, sequence: &str, format: storyboard::StoryboardFormat, output: Option<&str>, lang: Option<&str>) {
+    let system = localized(load_and_validate_system(path), lang);
+    let Some(storyboard) = storyboard::Storyboard::new(&system, sequence) else {
+        eprintln!("Error: '{}' is not a sequence of '{}'", sequence, system.name);
+        process::exit(1);
+    };
+    write_output(output, &storyboard.render(format));
+}
+
+fn embusen_command(path: &str, selectors: &[&str], format: &str) {
+    let system = load_and_validate_system(path);
+    let mut names: Vec<&str> = Vec::new();
+    for text in selectors {
+        let Ok(selector) = selector::Selector::parse(text) else { continue };
+        let matching: Vec<&str> = system
+            .sequences_ordered()
+            .into_iter()
+            .map(|sequence| sequence.name.as_str())
+            .filter(|name| selector.matches_name(name))
+            .collect();
+        if matching.is_empty() {
+            eprintln!("Error: '{}' is not a sequence of '{}'", text, system.name);
+            process::exit(1);
+        }
+        names.extend(matching);
+    }
+    if !names.iter().flat_map(|name| &system.sequences[*name].steps).any(embusen::has_direction) {
+        eprintln!("Error: No step of {} gives a direction with @turn or @advance", names.join(", "));
+        process::exit(1);
     }
-    if !system.groups.is_empty() {
-        println!("  Groups: {}", system.groups.len());
-        for (group_name, states) in &system.groups {
-            println!("    - {} ({})", group_name, states.join(", "));
+
+    let embusen = embusen::Embusen::new(&system, &names);
+    match format {
+        "text" => print!("{}", embusen.to_text()),
+        "json" => match embusen.to_json() {
+            Ok(json) => println!("{}", json),
+            Err(e) => {
+                eprintln!("Error exporting to JSON: {}", e);
+                process::exit(1);
+            }
+        },
+        other => {
+            eprintln!("Error: Unknown embusen format '{}' (expected text or json)", other);
+            process::exit(1);
         }
     }
+    if !embusen.returns {
+        process::exit(1);
+    }
 }
 
-fn graph_command(path: &str) {
+fn train_command(path: &str, start: &str, args: &[String]) {
     let system = load_and_validate_system(path);
     let graph = graph::MartialGraph::from_system(&system);
     
-    match graph.to_json() {
-        Ok(json) => {
-            println!("{}", json);
+    let start = match train::parse_node(start) {
+        Some(start) => start,
+        None => {
+            eprintln!("Error: Invalid starting position '{}' (expected State[Role])", start);
+            process::exit(1);
         }
-        Err(e) => {
-            eprintln!("Error exporting to JSON: {}", e);
+    };
+    let mut trainer = match train::Trainer::new(&graph, start.clone()) {
+        Some(trainer) => trainer,
+        None => {
+            eprintln!("Error: {} is not a position of the graph", start.id());
             process::exit(1);
         }
+    };
+    let number = |names: &[&str]| {
+        option_arg(args, names).map(|value| {
+            value.parse::<u64>().unwrap_or_else(|_| {
+                eprintln!("Error: {} expects a number, got '{}'", names[0], value);
+                process::exit(1);
+            })
+        })
+    };
+    let mut rng = match number(&["--seed"]) {
+        Some(seed) => train::Rng::new(seed),
+        None => train::Rng::from_time(),
+    };
+    
+    let result = if args.iter().any(|a| a == "--random") {
+        let steps = number(&["--steps"]).unwrap_or(10) as usize;
+        train::random_walk(&mut trainer, &mut rng, steps, io::stdout())
+    } else {
+        train::interactive(&mut trainer, &mut rng, io::stdin().lock(), io::stdout())
+    };
+    if let Err(e) = result {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    }
+}
+
+fn dot_command(path: &str, args: &[String]) {
+    let (system, graph) = exported_graph(path, args);
+    if args.iter().any(|a| a == "--annotate") {
+        println!("{}", annotate::Annotations::new(&system, &graph).to_dot(&graph));
+    } else {
+        print_export(&export::DotExporter, &graph);
     }
 }
 
-fn dot_command(path: &str) {
+#[cfg(feature = "tui")]
+fn tui_command(path: &str) {
     let system = load_and_validate_system(path);
-    let graph = graph::MartialGraph::from_system(&system);
-    
-    println!("{}", graph.to_dot());
+    if let Err(e) = martial_lang::tui::run(&system) {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    }
+}
+
+#[cfg(not(feature = "tui"))]
+fn tui_command(_path: &str) {
+    eprintln!("Error: mat was built without the `tui` feature");
+    process::exit(1);
 }
 
-fn stats_command(path: &str) {
+/// Positions past which `mat show` suggests narrowing the graph down
+const SHOW_LIMIT: usize = 30;
+
+fn show_command(path: &str, args: &[String]) {
+    let mut graph = exported_graph(path, args).1;
+    if let Some(sequence) = option_arg(args, &["-s", "--sequence"]) {
+        check_selectors([sequence]);
+        graph = graph.sequence(sequence);
+        if graph.edges.is_empty() {
+            eprintln!("Error: '{}' is not a sequence of the graph", sequence);
+            process::exit(1);
+        }
+    }
+    if graph.nodes.len() > SHOW_LIMIT {
+        eprintln!(
+            "Note: {} positions; narrow the drawing down with --sequence or --start and --depth",
+            graph.nodes.len()
+        );
+    }
+    let charset = if args.iter().any(|a| a == "--ascii") { diagram::Charset::Ascii } else { diagram::Charset::Unicode };
+    print!("{}", diagram::render(&graph, charset));
+}
+
+fn stats_command(path: &str, starts: &[&str], used_only: bool) {
+    check_selectors(starts.iter().copied());
     let system = load_and_validate_system(path);
-    let graph = graph::MartialGraph::from_system(&system);
+    let mut graph = graph::MartialGraph::from_system(&system);
+    if !used_only {
+        graph = graph.with_declared_nodes(&system);
+    }
     let stats = graph.statistics();
     
     println!("\nGraph Statistics for '{}':", system.name);
@@ -130,6 +2036,23 @@ fn stats_command(path: &str) {
     println!("  Edges: {}", stats.edge_count);
     println!("  Self-loops: {}", stats.self_loops);
     
+    if !system.metadata.is_empty() {
+        println!("\n  Files:");
+        for (file, metadata) in &system.metadata {
+            let mut details = Vec::new();
+            if let Some(author) = &metadata.author {
+                details.push(format!("author: {}", author));
+            }
+            if let Some(level) = &metadata.level {
+                details.push(format!("level: {}", level));
+            }
+            if let Some(reviewed) = &metadata.reviewed {
+                details.push(format!("reviewed: {}", reviewed));
+            }
+            println!("    - {} ({})", file, details.join(", "));
+        }
+    }
+    
     if !stats.source_nodes.is_empty() {
         println!("\n  Source nodes (no incoming edges):");
         for node in &stats.source_nodes {
@@ -151,17 +2074,524 @@ fn stats_command(path: &str) {
         }
     }
     
-    // Check for unreachable nodes
-    let unreachable = graph.find_unreachable_nodes();
+    // Check for unreachable nodes, from the given starts or else from every
+    // node with an outgoing edge; isolated ones are listed above
+    let unreachable = if starts.is_empty() {
+        graph.find_unreachable_nodes()
+    } else {
+        graph.unreachable_from(&start_nodes(&graph, starts))
+    };
+    let unreachable: Vec<&graph::Node> = unreachable.iter().filter(|node| !stats.isolated_nodes.contains(node)).collect();
     if !unreachable.is_empty() {
-        println!("\n  ⚠ Unreachable nodes:");
+        match starts {
+            [] => println!("\n  ⚠ Unreachable nodes:"),
+            _ => println!("\n  ⚠ Unreachable from {}:", starts.join(", ")),
+        }
         for node in &unreachable {
             println!("    - {}", node.id());
         }
     }
+    
+    // How deep the curriculum goes
+    let chain = graph.longest_chain();
+    if let Some(first) = chain.first() {
+        println!("\n  Longest chain: {} actions", chain.len());
+        let mut line = first.from.id();
+        for edge in &chain {
+            line.push_str(&format!(" -{}-> {}", edge.action, edge.to.id()));
+        }
+        println!("    {}", line);
+    }
+    let depths = graph.depths();
+    if !depths.is_empty() {
+        println!("\n  Depth from initial positions:");
+        for (node, depth) in &depths {
+            println!("    {:>3}  {}", depth, node.id());
+        }
+    }
+    
+    // Positions by dominance
+    let hierarchy = system.hierarchy();
+    if !hierarchy.is_empty() {
+        println!("\n  Position hierarchy (by dominance):");
+        for (state, dominance) in &hierarchy {
+            println!("    {:>3}  {}", dominance, state);
+        }
+    }
+    let drops = system.dominance_drops();
+    if !drops.is_empty() {
+        println!("\n  ⚠ Attacks losing dominance:");
+        for drop in &drops {
+            println!("    - {}", drop);
+        }
+    }
+    
+    // States that may be the same position under different names
+    let equivalent = graph.equivalent_states();
+    if !equivalent.is_empty() {
+        println!("\n  ⚠ Equivalent states (same transitions in and out; consider merging):");
+        for class in &equivalent {
+            println!("    - {}", class.join(", "));
+        }
+    }
+}
+
+/// Read and parse every file of a directory, to be rewritten, exiting on
+/// the first error
+fn parse_directory(path: &str) -> (Vec<loader::SourceFile>, Vec<ast::MartialFile>) {
+    let config = load_config(path);
+    let sources = match loader::read_configured(path, &config) {
+        Ok(sources) => sources,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+    let mut files = Vec::new();
+    for source in &sources {
+        if conditional::has_sections(&source.content) {
+            eprintln!("Error: {} has conditional sections, which refactorings do not support", source.path);
+            process::exit(1);
+        }
+        match loader::parse_source_with(source, config.compat) {
+            Ok(file) => files.push(file),
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(1);
+            }
+        }
+    }
+    (sources, files)
+}
+
+fn build_command(path: &str) {
+    let config = project_config(path);
+    run_exports(path, &config, &config.exports);
+}
+
+fn export_command(path: &str, names: &[&str], all: bool) {
+    let config = project_config(path);
+    let targets: Vec<build::ExportTarget> = if all {
+        config.exports.iter().filter(|e| e.name.is_some()).cloned().collect()
+    } else {
+        names
+            .iter()
+            .map(|name| match config.preset(name) {
+                Some(preset) => preset.clone(),
+                None => {
+                    let presets: Vec<&str> = config.exports.iter().filter_map(|e| e.name.as_deref()).collect();
+                    eprintln!("Error: No export preset named '{}'. Available presets: {}", name, presets.join(", "));
+                    process::exit(1);
+                }
+            })
+            .collect()
+    };
+    if targets.is_empty() {
+        eprintln!("Error: No named [[export]] presets in the project file");
+        process::exit(1);
+    }
+    run_exports(path, &config, &targets);
+}
+
+/// The project file of `path`, which must have one
+fn project_config(path: &str) -> config::Config {
+    if config::Config::find(path).is_none() {
+        eprintln!("Error: No {} found in {}", config::CONFIG_FILES.join(" or "), path);
+        process::exit(1);
+    }
+    load_config(path)
+}
+
+fn run_exports(path: &str, config: &config::Config, targets: &[build::ExportTarget]) {
+    let root = Path::new(path);
+    let system = load_and_validate_system(&root.join(&config.source).to_string_lossy());
+    let artifacts = build::build_with(root, targets, &system, &build::ExporterRegistry::builtin()).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(1);
+    });
+    for artifact in artifacts {
+        if !artifact.changed {
+            eprintln!("Unchanged {}", artifact.path.display());
+        } else if let Err(e) = artifact.write() {
+            eprintln!("Error writing {}: {}", artifact.path.display(), e);
+            process::exit(1);
+        } else {
+            eprintln!("Wrote {}", artifact.path.display());
+        }
+    }
+}
+
+fn test_command(path: &str, update: bool) {
+    let fixtures = fixture::discover(Path::new(path)).unwrap_or_else(|e| {
+        eprintln!("Error: cannot read {}: {}", path, e);
+        process::exit(1);
+    });
+    if fixtures.is_empty() {
+        eprintln!("Error: No *{} files found in {}", fixture::EXPECT_SUFFIX, path);
+        process::exit(1);
+    }
+
+    if update {
+        for fixture in &fixtures {
+            match fixture::update_snapshot(fixture) {
+                Ok(true) => eprintln!("Wrote {}", fixture.snapshot.display()),
+                Ok(false) => {}
+                Err(e) => eprintln!("Skipped snapshot: {}", e),
+            }
+        }
+    }
+
+    let mut failed = 0;
+    for fixture in &fixtures {
+        let result = fixture::run(fixture);
+        if result.passed() {
+            println!("  ✓ {}", result.name);
+        } else {
+            failed += 1;
+            println!("  ✗ {}", result.name);
+            for failure in &result.failures {
+                println!("      {}", failure);
+            }
+        }
+    }
+
+    if failed > 0 {
+        eprintln!("\n✗ {} of {} fixture(s) failed", failed, fixtures.len());
+        process::exit(1);
+    }
+    println!("\n✓ All {} fixture(s) passed", fixtures.len());
+}
+
+fn serve_command(path: &str, host: &str, port: usize) {
+    let Ok(port) = u16::try_from(port) else {
+        eprintln!("Error: --port expects a number up to 65535");
+        process::exit(1);
+    };
+    let system = load_and_validate_system(path);
+    let listener = match std::net::TcpListener::bind((host, port)) {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("Error: cannot listen on {}:{}: {}", host, port, e);
+            process::exit(1);
+        }
+    };
+    eprintln!("Serving '{}' on http://{}:{}", system.name, host, port);
+    if let Err(e) = serve::serve(listener, handle::SystemHandle::new(system)) {
+        eprintln!("Error: server failed: {}", e);
+        process::exit(1);
+    }
+}
+
+/// Value of an option given by any of `names`, if present
+fn option_arg<'a>(args: &'a [String], names: &[&str]) -> Option<&'a str> {
+    let index = args.iter().position(|a| names.contains(&a.as_str()))?;
+    match args.get(index + 1) {
+        Some(value) => Some(value),
+        None => {
+            eprintln!("Error: {} requires a value", args[index]);
+            process::exit(1);
+        }
+    }
+}
+
+/// Every value given with `name`, for options that may be repeated
+fn repeated_arg<'a>(args: &'a [String], name: &str) -> Vec<&'a str> {
+    let mut values = Vec::new();
+    for (i, arg) in args.iter().enumerate() {
+        if arg == name {
+            match args.get(i + 1) {
+                Some(value) => values.push(value.as_str()),
+                None => {
+                    eprintln!("Error: {} requires a value", name);
+                    process::exit(1);
+                }
+            }
+        }
+    }
+    values
+}
+
+/// Exits if one of `selectors` is malformed, such as a regular expression
+/// that does not compile
+fn check_selectors<'a>(selectors: impl IntoIterator<Item = &'a str>) {
+    for text in selectors {
+        if let Err(e) = selector::Selector::parse(text) {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        }
+    }
+}
+
+/// Graph nodes matching the starting positions, given as selectors such as
+/// `State[Role]` or a state in any role; exits if one matches nothing
+fn start_nodes(graph: &graph::MartialGraph, starts: &[&str]) -> Vec<graph::Node> {
+    let mut nodes = Vec::new();
+    for start in starts {
+        let matching: Vec<graph::Node> = graph.nodes.iter().filter(|n| n.matches(start)).cloned().collect();
+        if matching.is_empty() {
+            eprintln!("Error: '{}' is not a position of the graph", start);
+            process::exit(1);
+        }
+        nodes.extend(matching);
+    }
+    nodes
+}
+
+/// Positive number given with `name`, or `default`
+fn number_arg(args: &[String], name: &str, default: usize) -> usize {
+    match option_arg(args, &[name]).map(str::parse::<usize>) {
+        None => default,
+        Some(Ok(value)) if value > 0 => value,
+        Some(_) => {
+            eprintln!("Error: {} expects a positive number", name);
+            process::exit(1);
+        }
+    }
+}
+
+/// Value of a `-o`/`--output` option, if present
+fn output_arg(args: &[String]) -> Option<&str> {
+    option_arg(args, &["-o", "--output"])
+}
+
+/// Arguments that are neither options nor option values
+fn positional_args(args: &[String]) -> Vec<&str> {
+    let mut positional = Vec::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if arg.starts_with('-') {
+            iter.next();
+        } else {
+            positional.push(arg.as_str());
+        }
+    }
+    positional
+}
+
+/// Read the sparring log given by `log`, or the one in the system directory
+///
+/// A missing log is an error only if `required`; otherwise it has no entries.
+fn read_log(path: &str, log: Option<&str>, required: bool) -> Vec<sparring::LogEntry> {
+    let log_path = match log {
+        Some(log) => Path::new(log).to_path_buf(),
+        None => Path::new(path).join(sparring::LOG_FILE),
+    };
+    let content = match fs::read_to_string(&log_path) {
+        Ok(content) => content,
+        Err(_) if !required && !log_path.exists() => return Vec::new(),
+        Err(e) => {
+            eprintln!("Error reading {}: {}", log_path.display(), e);
+            process::exit(1);
+        }
+    };
+    match sparring::parse_log(&content) {
+        Ok(entries) => entries,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    }
+}
+
+/// Write command output to a file, or stdout if none is given
+fn write_output(output: Option<&str>, content: &str) {
+    match output {
+        Some(path) => {
+            if let Err(e) = fs::write(path, content) {
+                eprintln!("Error writing {}: {}", path, e);
+                process::exit(1);
+            }
+            eprintln!("Wrote {}", path);
+        }
+        None => print!("{}", content),
+    }
+}
+
+/// Restrict a system to what is known at `level`, if one is given
+fn at_level(system: semantic::MartialSystem, level: Option<&str>) -> semantic::MartialSystem {
+    let level = match level {
+        Some(level) => level,
+        None => return system,
+    };
+    match system.at_level(level) {
+        Some(system) => system,
+        None => {
+            eprintln!(
+                "Error: Unknown level '{}' (declared levels: {})",
+                level,
+                system.levels.join(", ")
+            );
+            process::exit(1);
+        }
+    }
+}
+
+/// Build the graph, from the point of view of `role` if one is given
+fn perspective(system: &semantic::MartialSystem, role: Option<&str>) -> graph::MartialGraph {
+    let graph = graph::MartialGraph::from_system(system);
+    match role {
+        None => graph,
+        Some(role) if system.roles.contains(role) => graph.project_role_perspective(role),
+        Some(role) => {
+            let roles: Vec<&str> = system.roles.iter().map(String::as_str).collect();
+            eprintln!("Error: Unknown role '{}' (declared roles: {})", role, roles.join(", "));
+            process::exit(1);
+        }
+    }
+}
+
+/// Drop what `ruleset` forbids, if one is given
+fn under_ruleset(system: semantic::MartialSystem, ruleset: Option<&str>) -> semantic::MartialSystem {
+    let ruleset = match ruleset {
+        Some(ruleset) => ruleset,
+        None => return system,
+    };
+    match system.under_ruleset(ruleset) {
+        Some(system) => system,
+        None => {
+            let names: Vec<&str> = system.rulesets.keys().map(String::as_str).collect();
+            eprintln!("Error: Unknown ruleset '{}' (declared rulesets: {})", ruleset, names.join(", "));
+            process::exit(1);
+        }
+    }
+}
+
+/// Replace state and action names by their labels in `lang`, if one is given
+fn localized(system: semantic::MartialSystem, lang: Option<&str>) -> semantic::MartialSystem {
+    let lang = match lang {
+        Some(lang) => lang,
+        None => return system,
+    };
+    let languages = system.languages();
+    if !languages.contains(&lang) {
+        eprintln!(
+            "Error: No labels in language '{}' (labelled languages: {})",
+            lang,
+            languages.join(", ")
+        );
+        process::exit(1);
+    }
+    system.localized(lang)
+}
+
+/// The project config of a system directory under `MAT_*` variables and
+/// the flags every command reading files accepts, `--compat`,
+/// `--duplicates`, `--kinds`, `--define` and `--locked`
+fn load_config(path: &str) -> config::Config {
+    let args: Vec<String> = env::args().collect();
+    let global: Vec<String> = args
+        .iter()
+        .enumerate()
+        .filter(|&(i, a)| {
+            let takes_value = |flag: &str| ["--duplicates", "--kinds", "--define"].contains(&flag);
+            a == "--compat" || a == "--locked" || takes_value(a) || (i > 0 && takes_value(&args[i - 1]))
+        })
+        .map(|(_, a)| a.clone())
+        .collect();
+    resolve_config(path, &global).config
+}
+
+/// Flags overriding settings of the project config
+const CONFIG_FLAGS: [(&str, &str); 8] = [
+    ("--ruleset", "ruleset"),
+    ("--duplicates", "duplicates"),
+    ("--kinds", "kinds"),
+    ("--lints", "lints"),
+    ("--deny", "deny"),
+    ("--include", "include"),
+    ("--exclude", "exclude"),
+    ("--shared", "shared"),
+];
+
+/// The project config of a system directory, under `MAT_*` variables and
+/// then the flags among `args`
+fn resolve_config(path: &str, args: &[String]) -> config::Resolved {
+    let flag = |name: &str| config::Origin::Flag(name.to_string());
+    let result = config::Resolved::load(path).and_then(|mut resolved| {
+        resolved.apply_env(env::vars())?;
+        if args.iter().any(|a| a == "--strict") {
+            resolved.set("strict", "true", flag("--strict"))?;
+        }
+        if args.iter().any(|a| a == "--compat") {
+            resolved.set("compat", "true", flag("--compat"))?;
+        }
+        if args.iter().any(|a| a == "--locked") {
+            resolved.set("locked", "true", flag("--locked"))?;
+        }
+        for (name, key) in CONFIG_FLAGS {
+            if let Some(value) = option_arg(args, &[name]) {
+                resolved.set(key, value, flag(name))?;
+            }
+        }
+        // --define may be given once per flag
+        let defines: Vec<&str> = args
+            .windows(2)
+            .filter(|pair| pair[0] == "--define")
+            .map(|pair| pair[1].as_str())
+            .collect();
+        if !defines.is_empty() {
+            resolved.set("defines", &defines.join(","), flag("--define"))?;
+        }
+        Ok(resolved)
+    });
+    match result {
+        Ok(resolved) => resolved,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    }
+}
+
+/// Parse and validate the sources of the system of `path` as its project
+/// config says
+fn validate_sources(path: &str, sources: &[loader::SourceFile]) -> Result<semantic::ValidationReport, loader::LoadError> {
+    let config = load_config(path);
+    let files = sources
+        .iter()
+        .map(|source| loader::parse_source_defining(source, config.compat, &config.defines))
+        .collect::<Result<Vec<_>, _>>()?;
+    loader::validate_report_with(&loader::system_name(path), &files, config.validator())
 }
 
 fn load_and_validate_system(path: &str) -> semantic::MartialSystem {
+    load_and_validate(path).system
+}
+
+/// Load and validate the system of `path`, keeping its warnings, and check
+/// its lockfile if the project config or `--locked` asks to
+fn load_and_validate(path: &str) -> semantic::ValidationReport {
+    let (sources, report) = load_sources_and_validate(path);
+    if load_config(path).locked {
+        check_lockfile(path, &sources, &report.system);
+    }
+    report
+}
+
+/// Exit unless the lockfile of `path` matches its sources and system
+fn check_lockfile(path: &str, sources: &[loader::SourceFile], system: &semantic::MartialSystem) {
+    let lock_path = Path::new(path).join(lock::LOCK_FILE);
+    let lockfile = match fs::read_to_string(&lock_path).map_err(|e| e.to_string()).and_then(|c| lock::Lockfile::parse(&c)) {
+        Ok(lockfile) => lockfile,
+        Err(e) => {
+            eprintln!("Error reading {}: {}", lock_path.display(), e);
+            eprintln!("Run mat lock {} to create it", path);
+            process::exit(1);
+        }
+    };
+    let mismatches = lockfile.verify(path, sources, system);
+    if !mismatches.is_empty() {
+        eprintln!("\n✗ {} does not match the sources:", lock_path.display());
+        for mismatch in &mismatches {
+            eprintln!("  - {}", mismatch);
+        }
+        process::exit(1);
+    }
+    eprintln!("✓ Sources match {}", lock_path.display());
+}
+
+/// Load and validate the system of `path`, with the sources it was read from
+fn load_sources_and_validate(path: &str) -> (Vec<loader::SourceFile>, semantic::ValidationReport) {
     let path_obj = Path::new(path);
     
     if !path_obj.is_dir() {
@@ -172,68 +2602,53 @@ fn load_and_validate_system(path: &str) -> semantic::MartialSystem {
     eprintln!("\nValidating martial system: {}", path);
     
     // Get system name from directory
-    let system_name = path_obj
-        .file_name()
-        .and_then(|n| n.to_str())
-        .unwrap_or("unknown")
-        .to_string();
-    
-    // Find all .martial files
-    let martial_files = match find_martial_files(path) {
-        Ok(files) => files,
+    let system_name = loader::system_name(path);
+    
+    // Find and read the .martial files selected by the project config
+    let config = load_config(path);
+    let sources = match loader::read_configured(path, &config) {
+        Ok(sources) => sources,
         Err(e) => {
             eprintln!("Error finding .martial files: {}", e);
             process::exit(1);
         }
     };
     
-    if martial_files.is_empty() {
+    if sources.is_empty() {
         eprintln!("Error: No .martial files found in directory");
         process::exit(1);
     }
     
-    eprintln!("Found {} .martial files:", martial_files.len());
-    for file in &martial_files {
-        eprintln!("  - {}", file);
+    eprintln!("Found {} .martial files:", sources.len());
+    let layers = config.layers(path);
+    let shared = |file: &str| config::Layer::of(&layers, file).filter(|layer| layer.is_external());
+    for source in &sources {
+        match shared(&source.path) {
+            Some(layer) => eprintln!("  - {} (from {})", source.path, layer),
+            None => eprintln!("  - {}", source.path),
+        }
     }
     
     // Parse all files
-    let mut validator = semantic::SemanticValidator::new();
+    let mut validator = config.validator();
     
-    for file_path in &martial_files {
-        eprintln!("\nParsing {}...", file_path);
-        
-        let content = match fs::read_to_string(file_path) {
-            Ok(c) => c,
-            Err(e) => {
-                eprintln!("Error reading {}: {}", file_path, e);
-                process::exit(1);
-            }
-        };
-        
-        // Lex
-        let mut lexer = lexer::Lexer::new(&content);
-        let tokens = match lexer.tokenize() {
-            Ok(t) => t,
-            Err(e) => {
-                eprintln!("Lexer error in {}: {}", file_path, e);
-                process::exit(1);
-            }
-        };
+    for source in &sources {
+        eprintln!("\nParsing {}...", source.path);
         
-        // Parse
-        let mut parser = parser::Parser::new(tokens);
-        let martial_file = match parser.parse() {
+        let martial_file = match loader::parse_source_defining(source, config.compat, &config.defines) {
             Ok(f) => f,
             Err(e) => {
-                eprintln!("Parse error in {}: {}", file_path, e);
+                eprintln!("{}", e);
                 process::exit(1);
             }
         };
         
         // Add to validator
         if let Err(e) = validator.add_file(martial_file) {
-            eprintln!("Semantic error in {}: {}", file_path, e);
+            eprintln!("Semantic error in {}: {}", source.path, e);
+            if let Some(layer) = shared(&source.path) {
+                eprintln!("  declared in {}", layer);
+            }
             process::exit(1);
         }
         
@@ -243,32 +2658,13 @@ fn load_and_validate_system(path: &str) -> semantic::MartialSystem {
     // Validate the complete system
     eprintln!("\nValidating system semantics...");
     match validator.validate(system_name.clone()) {
-        Ok(system) => system,
+        Ok(report) => (sources, report),
         Err(e) => {
             eprintln!("\nValidation error: {}", e);
-            process::exit(1);
-        }
-    }
-}
-
-fn find_martial_files(dir_path: &str) -> Result<Vec<String>, std::io::Error> {
-    let mut files = Vec::new();
-    
-    for entry in fs::read_dir(dir_path)? {
-        let entry = entry?;
-        let path = entry.path();
-        
-        if path.is_file() {
-            if let Some(ext) = path.extension() {
-                if ext == "martial" {
-                    if let Some(path_str) = path.to_str() {
-                        files.push(path_str.to_string());
-                    }
-                }
+            if let Some(layer) = e.file.as_deref().and_then(shared) {
+                eprintln!("  declared in {}", layer);
             }
+            process::exit(1);
         }
     }
-    
-    files.sort();
-    Ok(files)
 }
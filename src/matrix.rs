@@ -0,0 +1,179 @@
+//! Reachability matrices between groups or states
+//!
+//! `mat matrix` tells, for every pair of groups, whether a position of the
+//! first leads to one of the second and in how few actions: a strategic
+//! overview answering "can I get from any guard to back control?". Groups
+//! sharing a state are at distance 0. Rows and columns can also be single
+//! states, in any role.
+
+use crate::graph::{MartialGraph, Node};
+use crate::prelude::*;
+use alloc::collections::BTreeSet;
+use serde::Serialize;
+
+/// What the rows and columns of a matrix are
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Axis {
+    Group,
+    State,
+}
+
+impl Axis {
+    pub const ALL: [Axis; 2] = [Axis::Group, Axis::State];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Axis::Group => "group",
+            Axis::State => "state",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Axis::ALL.into_iter().find(|axis| axis.name() == name)
+    }
+}
+
+/// Fewest actions from each label to each other, in the same order for
+/// rows and columns
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ReachabilityMatrix {
+    pub labels: Vec<String>,
+    /// `distances[i][j]` leads from `labels[i]` to `labels[j]`; `None` if
+    /// no path does
+    pub distances: Vec<Vec<Option<usize>>>,
+}
+
+impl ReachabilityMatrix {
+    /// The matrix of the groups of `graph`, or of its states, sorted
+    pub fn new(graph: &MartialGraph, axis: Axis) -> Self {
+        let members: Vec<(String, Vec<&str>)> = match axis {
+            Axis::Group => graph
+                .groups
+                .iter()
+                .map(|(group, states)| (group.clone(), states.iter().map(String::as_str).collect()))
+                .collect(),
+            Axis::State => graph
+                .nodes
+                .iter()
+                .map(|node| node.state.as_str())
+                .collect::<BTreeSet<&str>>()
+                .into_iter()
+                .map(|state| (state.to_string(), vec![state]))
+                .collect(),
+        };
+        let nodes_of = |states: &[&str]| -> Vec<&Node> {
+            graph.nodes.iter().filter(|node| states.contains(&node.state.as_str())).collect()
+        };
+        let distances = members
+            .iter()
+            .map(|(_, from)| {
+                let distances = graph.distances_from(nodes_of(from));
+                members
+                    .iter()
+                    .map(|(_, to)| nodes_of(to).into_iter().filter_map(|node| distances.get(node).copied()).min())
+                    .collect()
+            })
+            .collect();
+        ReachabilityMatrix {
+            labels: members.into_iter().map(|(label, _)| label).collect(),
+            distances,
+        }
+    }
+
+    /// Export as JSON
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string_pretty(self)
+    }
+
+    /// Export as CSV: a header of the targets, then a row per source with
+    /// its distances, empty where there is no path
+    pub fn to_csv(&self) -> String {
+        let quote = |field: &str| {
+            if field.contains([',', '"', '\n']) {
+                format!("\"{}\"", field.replace('"', "\"\""))
+            } else {
+                field.to_string()
+            }
+        };
+        let mut csv = String::from("from");
+        for label in &self.labels {
+            csv.push(',');
+            csv.push_str(&quote(label));
+        }
+        csv.push('\n');
+        for (label, row) in self.labels.iter().zip(&self.distances) {
+            csv.push_str(&quote(label));
+            for distance in row {
+                csv.push(',');
+                if let Some(distance) = distance {
+                    csv.push_str(&distance.to_string());
+                }
+            }
+            csv.push('\n');
+        }
+        csv
+    }
+
+    /// Export as a Markdown table, with `—` where there is no path
+    pub fn to_markdown(&self) -> String {
+        let mut markdown = String::from("| from \\ to |");
+        for label in &self.labels {
+            markdown.push_str(&format!(" {} |", label));
+        }
+        markdown.push_str("\n|---|");
+        markdown.push_str(&"---:|".repeat(self.labels.len()));
+        markdown.push('\n');
+        for (label, row) in self.labels.iter().zip(&self.distances) {
+            markdown.push_str(&format!("| **{}** |", label));
+            for distance in row {
+                match distance {
+                    Some(distance) => markdown.push_str(&format!(" {} |", distance)),
+                    None => markdown.push_str(" — |"),
+                }
+            }
+            markdown.push('\n');
+        }
+        markdown
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loader::{self, SourceFile};
+
+    fn graph() -> MartialGraph {
+        let source = "roles { Top, Bottom }\nstate ClosedGuard\nstate HalfGuard\nstate Mount\nstate Back\n\
+                      group Guards { ClosedGuard, HalfGuard }\ngroup Control { Mount, Back }\ngroup Pinned { Mount }\n\
+                      sequence Sweep:\n    Hip: HalfGuard[Bottom] -> Mount[Top]\n    Take: Mount[Top] -> Back[Top]";
+        let system = loader::load_sources("club", &[SourceFile::new("club.martial", source)]).unwrap();
+        MartialGraph::from_system(&system)
+    }
+
+    #[test]
+    fn test_group_matrix() {
+        let matrix = ReachabilityMatrix::new(&graph(), Axis::Group);
+        assert_eq!(matrix.labels, ["Control", "Guards", "Pinned"]);
+        assert_eq!(
+            matrix.distances,
+            [
+                [Some(0), None, Some(0)],
+                [Some(1), Some(0), Some(1)],
+                // Mount is in both groups
+                [Some(0), None, Some(0)],
+            ]
+        );
+        assert_eq!(matrix.to_csv(), "from,Control,Guards,Pinned\nControl,0,,0\nGuards,1,0,1\nPinned,0,,0\n");
+        let markdown = matrix.to_markdown();
+        assert!(markdown.starts_with("| from \\ to | Control | Guards | Pinned |\n|---|---:|---:|---:|\n"));
+        assert!(markdown.ends_with("| **Pinned** | 0 | — | 0 |\n"), "{}", markdown);
+    }
+
+    #[test]
+    fn test_state_matrix() {
+        let matrix = ReachabilityMatrix::new(&graph(), Axis::State);
+        assert_eq!(matrix.labels, ["Back", "HalfGuard", "Mount"]);
+        assert_eq!(matrix.distances[1], [Some(2), Some(0), Some(1)]);
+        assert_eq!(Axis::from_name("group"), Some(Axis::Group));
+    }
+}
@@ -0,0 +1,222 @@
+//! Per-file metadata from front-matter blocks
+//!
+//! A `.martial` file may start with a front-matter block, either TOML
+//! between `+++` lines or flat YAML (`key: value`) between `---` lines:
+//!
+//! ```text
+//! +++
+//! author = "Coach Silva"
+//! level = "BlueBelt"
+//! reviewed = 2024-03-01
+//! +++
+//! state Mount
+//! ```
+//!
+//! The lexer skips the block; the loader parses it into [`FileMetadata`].
+
+use crate::prelude::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use alloc::collections::BTreeMap;
+use core::fmt;
+
+/// Syntax of a front-matter block
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrontMatterFormat {
+    Toml,
+    Yaml,
+}
+
+impl FrontMatterFormat {
+    fn delimiter(self) -> &'static str {
+        match self {
+            FrontMatterFormat::Toml => "+++",
+            FrontMatterFormat::Yaml => "---",
+        }
+    }
+}
+
+/// A front-matter block found at the start of a file
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrontMatter<'a> {
+    pub format: FrontMatterFormat,
+    /// Content between the delimiters
+    pub body: &'a str,
+    /// The whole block including both delimiter lines
+    pub raw: &'a str,
+}
+
+/// Metadata of a single file
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct FileMetadata {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+    /// Belt or curriculum level the file belongs to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub level: Option<String>,
+    /// Date the content was last reviewed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reviewed: Option<String>,
+    /// Any other keys, as text
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, String>,
+}
+
+impl FileMetadata {
+    fn set(&mut self, key: &str, value: String) {
+        match key {
+            "author" => self.author = Some(value),
+            "level" | "belt" | "belt_level" => self.level = Some(value),
+            "reviewed" | "last_reviewed" | "last-reviewed" => self.reviewed = Some(value),
+            _ => {
+                self.extra.insert(key.to_string(), value);
+            }
+        }
+    }
+}
+
+/// Error in a front-matter block
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MetadataError {
+    pub message: String,
+    pub line: usize,
+}
+
+impl fmt::Display for MetadataError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Front matter error at line {}: {}", self.line, self.message)
+    }
+}
+
+impl core::error::Error for MetadataError {}
+
+/// Format of the block opening on the first line, if any
+fn opening(content: &str) -> Option<FrontMatterFormat> {
+    let first = content.lines().next()?.trim_end();
+    [FrontMatterFormat::Toml, FrontMatterFormat::Yaml]
+        .into_iter()
+        .find(|format| first == format.delimiter())
+}
+
+/// Locate a complete front-matter block at the start of `content`
+pub fn front_matter(content: &str) -> Option<FrontMatter<'_>> {
+    let format = opening(content)?;
+    let body_start = content.find('\n')? + 1;
+
+    let mut offset = body_start;
+    for line in content[body_start..].split_inclusive('\n') {
+        if line.trim_end() == format.delimiter() {
+            return Some(FrontMatter {
+                format,
+                body: &content[body_start..offset],
+                raw: &content[..offset + line.len()],
+            });
+        }
+        offset += line.len();
+    }
+    None
+}
+
+fn parse_toml(body: &str) -> Result<FileMetadata, MetadataError> {
+    let table: toml::Table = toml::from_str(body).map_err(|e: toml::de::Error| {
+        // Lines are relative to the body, which starts on line 2
+        let line = e
+            .span()
+            .map(|span| body[..span.start].matches('\n').count() + 2)
+            .unwrap_or(1);
+        MetadataError {
+            message: e.message().to_string(),
+            line,
+        }
+    })?;
+
+    let mut metadata = FileMetadata::default();
+    for (key, value) in table {
+        let text = match value {
+            toml::Value::String(s) => s,
+            other => other.to_string(),
+        };
+        metadata.set(&key, text);
+    }
+    Ok(metadata)
+}
+
+fn parse_yaml(body: &str) -> Result<FileMetadata, MetadataError> {
+    let mut metadata = FileMetadata::default();
+    for (i, line) in body.lines().enumerate() {
+        let line_number = i + 2;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (key, value) = line.split_once(':').ok_or_else(|| MetadataError {
+            message: format!("Expected 'key: value', got '{}'", line),
+            line: line_number,
+        })?;
+        let value = value.trim();
+        let value = value
+            .strip_prefix('"')
+            .and_then(|v| v.strip_suffix('"'))
+            .or_else(|| value.strip_prefix('\'').and_then(|v| v.strip_suffix('\'')))
+            .unwrap_or(value);
+        metadata.set(key.trim(), value.to_string());
+    }
+    Ok(metadata)
+}
+
+/// Parse the front matter of a file, if it has one
+pub fn parse(content: &str) -> Result<Option<FileMetadata>, MetadataError> {
+    let block = match front_matter(content) {
+        Some(block) => block,
+        None if opening(content).is_some() => {
+            return Err(MetadataError {
+                message: "Unterminated front matter".to_string(),
+                line: 1,
+            })
+        }
+        None => return Ok(None),
+    };
+
+    let metadata = match block.format {
+        FrontMatterFormat::Toml => parse_toml(block.body)?,
+        FrontMatterFormat::Yaml => parse_yaml(block.body)?,
+    };
+    Ok(Some(metadata))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_toml_front_matter() {
+        let content = "+++\nauthor = \"Coach\"\nbelt = \"BlueBelt\"\nreviewed = 2024-03-01\nclub = \"North\"\n+++\nstate Mount";
+        let metadata = parse(content).unwrap().unwrap();
+        assert_eq!(metadata.author.as_deref(), Some("Coach"));
+        assert_eq!(metadata.level.as_deref(), Some("BlueBelt"));
+        assert_eq!(metadata.reviewed.as_deref(), Some("2024-03-01"));
+        assert_eq!(metadata.extra["club"], "North");
+        assert_eq!(front_matter(content).unwrap().raw.lines().count(), 6);
+    }
+
+    #[test]
+    fn test_yaml_front_matter() {
+        let content = "---\n# reviewed yearly\nauthor: 'Coach'\nlast_reviewed: 2024-03-01\n---\nstate Mount";
+        let metadata = parse(content).unwrap().unwrap();
+        assert_eq!(metadata.author.as_deref(), Some("Coach"));
+        assert_eq!(metadata.reviewed.as_deref(), Some("2024-03-01"));
+    }
+
+    #[test]
+    fn test_no_front_matter() {
+        assert_eq!(parse("state Mount").unwrap(), None);
+        assert!(front_matter("state Mount\n---\n").is_none());
+    }
+
+    #[test]
+    fn test_front_matter_errors() {
+        assert_eq!(parse("---\nauthor: Coach\nstate Mount").unwrap_err().message, "Unterminated front matter");
+        assert_eq!(parse("---\nauthor: Coach\nbroken\n---\n").unwrap_err().line, 3);
+        assert_eq!(parse("+++\nauthor = \n+++\n").unwrap_err().line, 2);
+    }
+}
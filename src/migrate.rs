@@ -0,0 +1,141 @@
+//! Migration of `.martial` files to the latest file format
+//!
+//! Each version after the first has a step rewriting the syntax of the one
+//! before it. Steps only edit the source text of the constructs they
+//! rewrite, so the rest of the file keeps its comments, front matter and
+//! layout.
+
+use crate::ast::{MartialFile, LATEST_VERSION};
+use crate::lexer::{Lexer, PositionedToken, Span, Token};
+use crate::parser::{ParseError, Parser};
+use crate::prelude::*;
+
+/// A file rewritten to the latest version
+#[derive(Debug, Clone, PartialEq)]
+pub struct Migration {
+    /// Version the file was written in; syntax of older versions it used is
+    /// rewritten too, see [`Parser::compat`]
+    pub from: u32,
+    pub content: String,
+}
+
+/// Rewrites a file of version `n + 1` into version `n + 2`, as step `n`
+type Step = fn(&[PositionedToken]) -> Vec<(Span, String)>;
+
+const STEPS: [Step; LATEST_VERSION as usize - 1] = [roles_in_brackets];
+
+/// The file `source` rewritten to [`LATEST_VERSION`], or `None` if it is
+/// already written in it
+///
+/// Fails if the file does not parse in the version it declares, even in
+/// compatibility mode.
+pub fn migrate(source: &str) -> Result<Option<Migration>, ParseError> {
+    let file = parse(source)?;
+    let from = file.version;
+    if from == LATEST_VERSION && file.deprecations.is_empty() {
+        return Ok(None);
+    }
+    // Deprecated syntax may be that of any older version
+    let first = if file.deprecations.is_empty() { from as usize - 1 } else { 0 };
+    let mut content = source.to_string();
+    for step in &STEPS[first..] {
+        let tokens = Lexer::new(&content).tokenize()?;
+        let edits = step(&tokens);
+        content = apply(&content, edits);
+    }
+    let tokens = Lexer::new(&content).tokenize()?;
+    content = apply(&content, vec![pragma(&content, &tokens)]);
+    Ok(Some(Migration { from, content }))
+}
+
+/// The file, checking that it parses
+fn parse(source: &str) -> Result<MartialFile, ParseError> {
+    let tokens = Lexer::new(source).tokenize()?;
+    Parser::new(tokens).compat(true).parse()
+}
+
+/// `source` with the text of each span replaced
+fn apply(source: &str, mut edits: Vec<(Span, String)>) -> String {
+    edits.sort_by_key(|(span, _)| core::cmp::Reverse(span.start));
+    let mut out = source.to_string();
+    for (span, text) in edits {
+        out.replace_range(span.start..span.end, &text);
+    }
+    out
+}
+
+/// Edit setting the version pragma to the latest version, inserting it after
+/// any front matter if the file has none
+fn pragma(source: &str, tokens: &[PositionedToken]) -> (Span, String) {
+    if let [first, second, ..] = tokens {
+        if first.token == Token::Identifier("martial") && matches!(second.token, Token::Number(_)) {
+            return (second.span, LATEST_VERSION.to_string());
+        }
+    }
+    let start = crate::metadata::front_matter(source).map_or(0, |block| block.raw.len());
+    (Span { start, end: start }, format!("martial {}\n\n", LATEST_VERSION))
+}
+
+/// Version 1 to 2: `state Guard roles { Top, Bottom }` becomes
+/// `state Guard[Top, Bottom]`
+fn roles_in_brackets(tokens: &[PositionedToken]) -> Vec<(Span, String)> {
+    let mut edits = Vec::new();
+    for (i, window) in tokens.windows(4).enumerate() {
+        let [state, name, roles, brace] = window else { continue };
+        let same_line = roles.position.line == name.position.line;
+        if state.token != Token::State || roles.token != Token::Roles || brace.token != Token::LeftBrace || !same_line {
+            continue;
+        }
+        let Some(close) = tokens[i + 4..].iter().position(|t| t.token == Token::RightBrace) else { continue };
+        let names: Vec<String> = tokens[i + 4..i + 4 + close]
+            .iter()
+            .filter_map(|t| match t.token {
+                Token::Identifier(role) => Some(role.to_string()),
+                _ => None,
+            })
+            .collect();
+        let end = tokens[i + 4 + close].span.end;
+        edits.push((Span { start: name.span.end, end }, format!("[{}]", names.join(", "))));
+    }
+    edits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_file(source: &str) -> MartialFile {
+        Parser::new(Lexer::new(source).tokenize().unwrap()).parse().unwrap()
+    }
+
+    #[test]
+    fn test_migrate_v1() {
+        let source = "---\nauthor: Coach\n---\n// Positions\nroles { Top, Bottom }\n\n\
+                      state ClosedGuard roles {\n    Top, Bottom // both\n}\nstate Mount roles { Top } @level(White)\n\
+                      state Back\nroles { Referee }\n";
+        let migration = migrate(source).unwrap().unwrap();
+        assert_eq!(migration.from, 1);
+        assert_eq!(
+            migration.content,
+            "---\nauthor: Coach\n---\nmartial 2\n\n// Positions\nroles { Top, Bottom }\n\n\
+             state ClosedGuard[Top, Bottom]\nstate Mount[Top] @level(White)\n\
+             state Back\nroles { Referee }\n"
+        );
+
+        let migrated = parse_file(&migration.content);
+        assert_eq!(migrated.version, 2);
+        assert_eq!(migrated.normalized().declarations, parse_file(source).normalized().declarations);
+        assert_eq!(migrate(&migration.content).unwrap(), None);
+    }
+
+    #[test]
+    fn test_migrate_pragma() {
+        let migration = migrate("martial 1\nstate Guard roles { Bottom }\n").unwrap().unwrap();
+        assert_eq!(migration.content, "martial 2\nstate Guard[Bottom]\n");
+
+        let error = migrate("martial 3\nstate Guard\n").unwrap_err();
+        assert_eq!(error.message, "Unsupported file format version 3; this tool reads versions 1 to 2");
+        let migration = migrate("martial 2\nstate Guard roles { Bottom }\n").unwrap().unwrap();
+        assert_eq!((migration.from, migration.content.as_str()), (2, "martial 2\nstate Guard[Bottom]\n"));
+    }
+}
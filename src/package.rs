@@ -0,0 +1,309 @@
+//! Packages bundling a system into a single file
+//!
+//! `mat package` writes a `.martialpkg` file, a JSON document holding the
+//! sources of a validated system, its graph, and a manifest naming the
+//! system with the hash of every file, see [`crate::lock`]. Gyms share a
+//! system as one file; `mat info` describes a package and `mat unpack`
+//! writes its sources back, checking them against the manifest.
+//!
+//! Files of shared libraries are packaged under `shared/<library>/` and those
+//! of dependencies stay under `vendor/<name>/`; the
+//! unpacked system has a project file including them, with the settings the
+//! system was validated under.
+
+use crate::config::{Config, Layer, LayerKind};
+use crate::graph::MartialGraph;
+use crate::loader::SourceFile;
+use crate::lock::{self, LockedFile, Lockfile, Mismatch};
+use crate::metadata::FileMetadata;
+use crate::semantic::MartialSystem;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Component, Path};
+
+/// Extension of package files
+pub const PACKAGE_EXTENSION: &str = "martialpkg";
+
+/// Format version of packages
+pub const PACKAGE_FORMAT: u32 = 1;
+
+/// A system bundled with its graph
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Package {
+    pub format: u32,
+    pub manifest: Manifest,
+    /// Sources, with paths relative to the system directory
+    pub sources: Vec<SourceFile>,
+    pub graph: MartialGraph,
+}
+
+/// What a package holds
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    /// Name of the system
+    pub name: String,
+    /// Version of the tool that wrote the package
+    pub tool: String,
+    /// Fingerprint of the system, see [`lock::fingerprint`]
+    pub fingerprint: String,
+    /// Every source with its hash, sorted by path
+    pub files: Vec<LockedFile>,
+    pub roles: usize,
+    pub states: usize,
+    pub sequences: usize,
+    /// Front-matter metadata by source path
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub metadata: BTreeMap<String, FileMetadata>,
+    /// Project file of the unpacked system
+    pub config: String,
+}
+
+/// Error raised while reading or unpacking a package
+#[derive(Debug, Clone, PartialEq)]
+pub struct PackageError {
+    pub message: String,
+}
+
+impl fmt::Display for PackageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Package error: {}", self.message)
+    }
+}
+
+impl core::error::Error for PackageError {}
+
+fn error(message: impl Into<String>) -> PackageError {
+    PackageError { message: message.into() }
+}
+
+/// Path of a source in a package: relative to the system directory `dir`,
+/// or under `shared/<library>/` for a file of a shared library
+fn packaged_path(dir: &str, layers: &[Layer], path: &str) -> String {
+    let (base, prefix) = match Layer::of(layers, path) {
+        Some(layer) if layer.kind == LayerKind::Shared => (layer.dir.as_str(), format!("shared/{}/", layer.name)),
+        _ => (dir, String::new()),
+    };
+    let relative = Path::new(path).strip_prefix(base).unwrap_or(Path::new(path));
+    format!("{}{}", prefix, relative.to_string_lossy().replace('\\', "/"))
+}
+
+/// The project file of an unpacked system: its files wherever they are,
+/// under the settings that change how they resolve
+fn unpacked_config(config: &Config) -> String {
+    let strings = |items: &[String]| toml::Value::Array(items.iter().cloned().map(toml::Value::String).collect());
+    let mut table = toml::Table::new();
+    table.insert("include".to_string(), strings(&["**/*.martial".to_string()]));
+    if config.compat {
+        table.insert("compat".to_string(), toml::Value::Boolean(true));
+    }
+    if config.duplicates != Config::default().duplicates {
+        table.insert("duplicates".to_string(), toml::Value::String(config.duplicates.name().to_string()));
+    }
+    if !config.kinds.is_empty() {
+        table.insert("kinds".to_string(), strings(&config.kinds));
+    }
+    if !config.defines.is_empty() {
+        table.insert("defines".to_string(), strings(&config.defines));
+    }
+    if !config.dependencies.is_empty() {
+        let dependencies = config.dependencies.iter().map(|(name, source)| (name.clone(), toml::Value::String(source.clone())));
+        table.insert("dependencies".to_string(), toml::Value::Table(dependencies.collect()));
+    }
+    table.to_string()
+}
+
+/// Whether `path` stays within the directory it is relative to
+fn is_contained(path: &str) -> bool {
+    Path::new(path).components().all(|component| matches!(component, Component::Normal(_)))
+}
+
+/// Namespace a package is added under by default: the name of its system,
+/// made an identifier
+pub fn namespace(name: &str) -> String {
+    let namespace: String = name
+        .chars()
+        .map(|ch| if ch.is_alphanumeric() || ch == '_' { ch } else { '_' })
+        .collect();
+    match namespace.chars().next() {
+        Some(first) if first.is_alphabetic() || first == '_' => namespace,
+        _ => format!("_{}", namespace),
+    }
+}
+
+impl Package {
+    /// Bundle the system of `dir`, validated from `sources` under `config`
+    pub fn new(dir: &str, config: &Config, sources: &[SourceFile], system: &MartialSystem) -> Self {
+        let layers = config.layers(dir);
+        let mut sources: Vec<SourceFile> = sources
+            .iter()
+            .map(|source| SourceFile::new(packaged_path(dir, &layers, &source.path), source.content.clone()))
+            .collect();
+        sources.sort_by(|a, b| a.path.cmp(&b.path));
+        let metadata = system
+            .metadata
+            .iter()
+            .map(|(path, metadata)| (packaged_path(dir, &layers, path), metadata.clone()))
+            .collect();
+        let lockfile = Lockfile::new("", &sources, system);
+        Package {
+            format: PACKAGE_FORMAT,
+            manifest: Manifest {
+                name: system.name.clone(),
+                tool: format!("mat {}", env!("CARGO_PKG_VERSION")),
+                fingerprint: lockfile.fingerprint,
+                files: lockfile.files,
+                roles: system.roles.len(),
+                states: system.states.len(),
+                sequences: system.sequences.len(),
+                metadata,
+                config: unpacked_config(config),
+            },
+            sources,
+            graph: MartialGraph::from_system(system),
+        }
+    }
+
+    pub fn parse(content: &str) -> Result<Self, PackageError> {
+        let package: Package =
+            serde_json::from_str(content).map_err(|e| error(format!("not a package: {}", e)))?;
+        if package.format != PACKAGE_FORMAT {
+            return Err(error(format!("unsupported package format {}", package.format)));
+        }
+        Ok(package)
+    }
+
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string_pretty(self)
+    }
+
+    /// The lockfile of the unpacked system
+    pub fn lockfile(&self) -> Lockfile {
+        Lockfile {
+            version: lock::LOCK_VERSION,
+            fingerprint: self.manifest.fingerprint.clone(),
+            files: self.manifest.files.clone(),
+        }
+    }
+
+    /// Sources whose content does not match the manifest, or that it does
+    /// not list
+    pub fn tampered(&self) -> Vec<Mismatch> {
+        let mut mismatches = Vec::new();
+        for source in &self.sources {
+            let hash = lock::sha256(source.content.as_bytes());
+            match self.manifest.files.iter().find(|file| file.path == source.path) {
+                Some(file) if file.sha256 != hash => mismatches.push(Mismatch::Changed(source.path.clone())),
+                Some(_) => {}
+                None => mismatches.push(Mismatch::Added(source.path.clone())),
+            }
+        }
+        for file in &self.manifest.files {
+            if !self.sources.iter().any(|source| source.path == file.path) {
+                mismatches.push(Mismatch::Removed(file.path.clone()));
+            }
+        }
+        mismatches
+    }
+
+    /// Write the sources, the project file and the lockfile into `dir`,
+    /// returning the paths written
+    ///
+    /// Fails before writing anything if the sources do not match the
+    /// manifest or a path leaves `dir`.
+    pub fn unpack(&self, dir: &str) -> Result<Vec<String>, PackageError> {
+        if let Some(mismatch) = self.tampered().first() {
+            return Err(error(format!("{} does not match the manifest", mismatch)));
+        }
+        if let Some(source) = self.sources.iter().find(|source| !is_contained(&source.path)) {
+            return Err(error(format!("{} is outside the package", source.path)));
+        }
+        let mut files: Vec<(String, &str)> = self
+            .sources
+            .iter()
+            .map(|source| (source.path.clone(), source.content.as_str()))
+            .collect();
+        let lockfile = self.lockfile().to_toml();
+        files.push(("martial.toml".to_string(), &self.manifest.config));
+        files.push((lock::LOCK_FILE.to_string(), &lockfile));
+
+        let mut written = Vec::new();
+        for (path, content) in files {
+            let path = Path::new(dir).join(path);
+            let write = || -> std::io::Result<()> {
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::write(&path, content)
+            };
+            write().map_err(|e| error(format!("cannot write {}: {}", path.display(), e)))?;
+            written.push(path.to_string_lossy().into_owned());
+        }
+        Ok(written)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loader;
+
+    #[test]
+    fn test_package_round_trip() {
+        let root = std::env::temp_dir().join(format!("martial-package-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let write = |path: &str, content: &str| {
+            let path = root.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        };
+        write("shared/roles.martial", "roles { Top, Bottom }");
+        write("bjj/martial.toml", "shared = [\"../shared\"]\nduplicates = \"first-wins\"");
+        write("bjj/guard.martial", "+++\nauthor = \"Coach\"\n+++\nstate Guard\nstate Guard\nstate Mount");
+        write("bjj/sweeps.martial", "sequence Sweep:\n    Hip: Guard[Bottom] -> Mount[Top]");
+
+        let dir = root.join("bjj").to_string_lossy().into_owned();
+        let config = Config::load(&dir).unwrap();
+        let sources = loader::read_configured(&dir, &config).unwrap();
+        let files = sources.iter().map(|s| loader::parse_source(s).unwrap()).collect::<Vec<_>>();
+        let system = loader::validate_report_with("bjj", &files, config.validator()).unwrap().system;
+
+        let package = Package::new(&dir, &config, &sources, &system);
+        let paths: Vec<&str> = package.sources.iter().map(|s| s.path.as_str()).collect();
+        assert_eq!(paths, ["guard.martial", "shared/shared/roles.martial", "sweeps.martial"]);
+        assert_eq!(package.manifest.metadata["guard.martial"].author.as_deref(), Some("Coach"));
+        assert_eq!((package.manifest.states, package.graph.edges.len()), (2, 1));
+
+        let package = Package::parse(&package.to_json().unwrap()).unwrap();
+        assert!(package.tampered().is_empty());
+        let out = root.join("unpacked").to_string_lossy().into_owned();
+        assert_eq!(package.unpack(&out).unwrap().len(), 5);
+
+        // The unpacked system resolves the same, under the same settings
+        let config = Config::load(&out).unwrap();
+        assert_eq!(config.duplicates, crate::semantic::DuplicatePolicy::FirstWins);
+        let sources = loader::read_configured(&out, &config).unwrap();
+        let files = sources.iter().map(|s| loader::parse_source(s).unwrap()).collect::<Vec<_>>();
+        let system = loader::validate_report_with("unpacked", &files, config.validator()).unwrap().system;
+        let lockfile = Lockfile::parse(&fs::read_to_string(root.join("unpacked/martial.lock")).unwrap()).unwrap();
+        assert!(lockfile.verify(&out, &sources, &system).is_empty());
+
+        let mut tampered = package.clone();
+        tampered.sources[0].content.push_str("\nstate Back");
+        assert_eq!(tampered.tampered(), [Mismatch::Changed("guard.martial".to_string())]);
+        assert!(tampered.unpack(&out).is_err());
+        let mut escaping = package;
+        escaping.sources[0].path = "../guard.martial".to_string();
+        escaping.manifest.files[0].path = "../guard.martial".to_string();
+        assert!(escaping.unpack(&out).unwrap_err().message.contains("outside the package"));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_namespace() {
+        assert_eq!(namespace("wrestling-core"), "wrestling_core");
+        assert_eq!(namespace("bjj"), "bjj");
+        assert_eq!(namespace("10th planet"), "_10th_planet");
+    }
+}
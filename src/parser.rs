@@ -1,13 +1,21 @@
 //! Parser for the Martial DSL
 //!
 //! Builds an Abstract Syntax Tree from a token stream.
+//!
+//! Malformed input is always reported as an error, never a panic; the
+//! `fuzz/` targets check this for the lexer and parser.
 
 use crate::ast::*;
-use crate::lexer::{LexError, Position, PositionedToken, Token};
-use std::fmt;
+use crate::grammar;
+use crate::prelude::*;
+use crate::lexer::{LexError, Position, PositionedToken, Span, Token};
+use crate::symbol::Symbol;
+use serde::Serialize;
+use alloc::collections::BTreeMap;
+use core::fmt;
 
 /// Parser error
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct ParseError {
     pub message: String,
     pub position: Position,
@@ -19,6 +27,8 @@ impl fmt::Display for ParseError {
     }
 }
 
+impl core::error::Error for ParseError {}
+
 impl From<LexError> for ParseError {
     fn from(err: LexError) -> Self {
         ParseError {
@@ -29,20 +39,47 @@ impl From<LexError> for ParseError {
 }
 
 /// Parser for the Martial DSL
-pub struct Parser {
-    tokens: Vec<PositionedToken>,
+pub struct Parser<'a> {
+    tokens: Vec<PositionedToken<'a>>,
     position: usize,
+    /// Parameters of the template whose steps are being parsed
+    params: Vec<String>,
+    /// Version of the file format, from the `martial N` pragma
+    version: u32,
+    /// Whether constructs removed from the file's version are accepted
+    compat: bool,
+    deprecations: Vec<Deprecation>,
+    /// Text of each declaration parsed, from its first token to its last
+    spans: Vec<Span>,
 }
 
-impl Parser {
+impl<'a> Parser<'a> {
     /// Create a new parser from a token stream
-    pub fn new(tokens: Vec<PositionedToken>) -> Self {
+    pub fn new(tokens: Vec<PositionedToken<'a>>) -> Self {
         Parser {
             tokens,
             position: 0,
+            params: Vec::new(),
+            version: 1,
+            compat: false,
+            deprecations: Vec::new(),
+            spans: Vec::new(),
         }
     }
 
+    /// Accept constructs removed from the version a file declares, recording
+    /// a [`Deprecation`] for each instead of failing
+    pub fn compat(mut self, compat: bool) -> Self {
+        self.compat = compat;
+        self
+    }
+
+    /// Where the declarations parsed so far are in the source text, from
+    /// their first token to their last, for editing it in place
+    pub fn spans(&self) -> &[Span] {
+        &self.spans
+    }
+
     /// Get current position for error reporting
     fn current_position(&self) -> Position {
         if self.position < self.tokens.len() {
@@ -55,7 +92,7 @@ impl Parser {
     }
 
     /// Peek at current token without consuming
-    fn peek(&self) -> &Token {
+    fn peek(&self) -> &Token<'a> {
         if self.position < self.tokens.len() {
             &self.tokens[self.position].token
         } else {
@@ -63,8 +100,13 @@ impl Parser {
         }
     }
 
+    /// Peek at the token after the current one
+    fn peek_next(&self) -> &Token<'a> {
+        self.tokens.get(self.position + 1).map_or(&Token::Eof, |t| &t.token)
+    }
+
     /// Consume and return current token
-    fn advance(&mut self) -> &Token {
+    fn advance(&mut self) -> &Token<'a> {
         if self.position < self.tokens.len() {
             let token = &self.tokens[self.position].token;
             self.position += 1;
@@ -76,13 +118,12 @@ impl Parser {
 
     /// Expect a specific token and consume it
     fn expect(&mut self, expected: Token) -> Result<(), ParseError> {
-        let current = self.peek().clone();
-        if current == expected {
+        if self.peek() == &expected {
             self.advance();
             Ok(())
         } else {
             Err(ParseError {
-                message: format!("Expected {}, got {}", expected, current),
+                message: format!("Expected {}, got {}", expected, self.peek()),
                 position: self.current_position(),
             })
         }
@@ -90,10 +131,40 @@ impl Parser {
 
     /// Expect an identifier and return it
     fn expect_identifier(&mut self) -> Result<String, ParseError> {
-        match self.peek().clone() {
-            Token::Identifier(name) => {
+        match self.peek() {
+            &Token::Identifier(name) => {
                 self.advance();
-                Ok(name)
+                Ok(name.to_string())
+            }
+            other => Err(ParseError {
+                message: format!("Expected identifier, got {}", other),
+                position: self.current_position(),
+            }),
+        }
+    }
+
+    /// Expect an identifier, add where it is written to `spans` and return it
+    fn expect_name(&mut self, spans: &mut Spans) -> Result<String, ParseError> {
+        let name = self.expect_identifier()?;
+        spans.push(self.last_span());
+        Ok(name)
+    }
+
+    /// Where the token just consumed is written
+    fn last_span(&self) -> NameSpan {
+        let token = &self.tokens[self.position - 1];
+        NameSpan {
+            position: token.position,
+            span: token.span,
+        }
+    }
+
+    /// Expect an identifier and return it interned
+    fn expect_symbol(&mut self) -> Result<Symbol, ParseError> {
+        match self.peek() {
+            &Token::Identifier(name) => {
+                self.advance();
+                Ok(Symbol::new(name))
             }
             other => Err(ParseError {
                 message: format!("Expected identifier, got {}", other),
@@ -104,31 +175,72 @@ impl Parser {
 
     /// Parse a complete martial file
     ///
-    /// Grammar: program ::= declaration+
+    /// Grammar: `program`, see [`crate::grammar`]
     pub fn parse(&mut self) -> Result<MartialFile, ParseError> {
+        self.version = self.parse_version_pragma()?;
         let mut declarations = Vec::new();
 
         while self.peek() != &Token::Eof {
+            let start = self.tokens[self.position].span.start;
             declarations.push(self.parse_declaration()?);
+            let end = self.tokens[self.position - 1].span.end;
+            self.spans.push(Span { start, end });
         }
 
-        Ok(MartialFile { declarations })
+        Ok(MartialFile {
+            source: None,
+            metadata: None,
+            version: self.version,
+            declarations,
+            deprecations: core::mem::take(&mut self.deprecations),
+        })
+    }
+
+    /// Parse the version pragma, if the file starts with one
+    ///
+    /// Grammar: `version_pragma`, see [`crate::grammar`]
+    ///
+    /// `martial` is not a keyword: it only introduces the pragma as the
+    /// first token of the file.
+    fn parse_version_pragma(&mut self) -> Result<u32, ParseError> {
+        if !(self.peek() == &Token::Identifier("martial") && matches!(self.peek_next(), Token::Number(_))) {
+            return Ok(1);
+        }
+        self.advance(); // consume "martial"
+        let position = self.current_position();
+        let number = match self.advance() {
+            Token::Number(number) => *number,
+            _ => "",
+        };
+        match number.parse() {
+            Ok(version) if (1..=LATEST_VERSION).contains(&version) => Ok(version),
+            _ => Err(ParseError {
+                message: format!(
+                    "Unsupported file format version {}; this tool reads versions 1 to {}",
+                    number, LATEST_VERSION
+                ),
+                position,
+            }),
+        }
     }
 
     /// Parse a declaration
     ///
-    /// Grammar: declaration ::= roles_decl | state_decl | sequence_decl | group_decl
+    /// Grammar: `declaration`, see [`crate::grammar`]
     fn parse_declaration(&mut self) -> Result<Declaration, ParseError> {
         match self.peek() {
             Token::Roles => Ok(Declaration::Roles(self.parse_roles_decl()?)),
             Token::State => Ok(Declaration::State(self.parse_state_decl()?)),
-            Token::Sequence => Ok(Declaration::Sequence(self.parse_sequence_decl()?)),
+            Token::Sequence | Token::Mirrored | Token::Alternating => Ok(Declaration::Sequence(self.parse_sequence_decl()?)),
             Token::Group => Ok(Declaration::Group(self.parse_group_decl()?)),
+            Token::Levels => Ok(Declaration::Levels(self.parse_levels_decl()?)),
+            Token::Term => Ok(Declaration::Term(self.parse_term_decl()?)),
+            Token::Ruleset => Ok(Declaration::Ruleset(self.parse_ruleset_decl()?)),
+            Token::Template => Ok(Declaration::Template(self.parse_template_decl()?)),
+            Token::Mirror => Ok(Declaration::Mirror(self.parse_mirror_decl()?)),
+            Token::Assert => Ok(Declaration::Assert(self.parse_assert_decl()?)),
             other => Err(ParseError {
-                message: format!(
-                    "Expected declaration (roles, state, sequence, or group), got {}",
-                    other
-                ),
+                message: format!("Expected declaration ({}), got {}", grammar::one_of(&grammar::declarations()), other),
                 position: self.current_position(),
             }),
         }
@@ -136,130 +248,610 @@ impl Parser {
 
     /// Parse a roles declaration
     ///
-    /// Grammar: roles_decl ::= "roles" "{" IDENTIFIER { "," IDENTIFIER } "}"
+    /// Grammar: `roles_decl`, see [`crate::grammar`]
     fn parse_roles_decl(&mut self) -> Result<RolesDecl, ParseError> {
+        let position = self.current_position();
         self.expect(Token::Roles)?;
         self.expect(Token::LeftBrace)?;
 
+        let mut spans = Spans::default();
         let mut roles = Vec::new();
-        roles.push(self.expect_identifier()?);
+        roles.push(self.expect_name(&mut spans)?);
 
         while self.peek() == &Token::Comma {
             self.advance(); // consume comma
-            roles.push(self.expect_identifier()?);
+            roles.push(self.expect_name(&mut spans)?);
         }
 
         self.expect(Token::RightBrace)?;
 
-        Ok(RolesDecl { roles })
+        Ok(RolesDecl { roles, position, spans })
     }
 
-    /// Parse a state declaration
+    /// Parse a levels declaration
     ///
-    /// Grammar: state_decl ::= "state" IDENTIFIER [ state_roles ]
-    ///          state_roles ::= "roles" "{" IDENTIFIER { "," IDENTIFIER } "}"
-    fn parse_state_decl(&mut self) -> Result<State, ParseError> {
-        self.expect(Token::State)?;
-        let name = self.expect_identifier()?;
+    /// Grammar: `levels_decl`, see [`crate::grammar`]
+    fn parse_levels_decl(&mut self) -> Result<LevelsDecl, ParseError> {
+        let position = self.current_position();
+        self.expect(Token::Levels)?;
+        self.expect(Token::LeftBrace)?;
 
-        let allowed_roles = if self.peek() == &Token::Roles {
-            self.advance(); // consume "roles"
-            self.expect(Token::LeftBrace)?;
+        let mut levels = vec![self.expect_identifier()?];
+        while self.peek() == &Token::Comma {
+            self.advance(); // consume comma
+            levels.push(self.expect_identifier()?);
+        }
 
-            let mut roles = Vec::new();
-            roles.push(self.expect_identifier()?);
+        self.expect(Token::RightBrace)?;
+
+        Ok(LevelsDecl { levels, position })
+    }
+
+    /// Expect an annotation argument (identifier, number or string) and return it
+    fn expect_annotation_arg(&mut self) -> Result<String, ParseError> {
+        let value = match self.peek() {
+            Token::Identifier(value) | Token::Number(value) => value.to_string(),
+            Token::Str(value) => value.to_string(),
+            other => {
+                return Err(ParseError {
+                    message: format!("Expected annotation argument, got {}", other),
+                    position: self.current_position(),
+                })
+            }
+        };
+        self.advance();
+        Ok(value)
+    }
+
+    /// Parse a positional or named annotation argument
+    ///
+    /// Grammar: `annotation_arg`, see [`crate::grammar`]
+    fn parse_annotation_arg(
+        &mut self,
+        args: &mut Vec<String>,
+        named: &mut BTreeMap<String, String>,
+    ) -> Result<(), ParseError> {
+        let position = self.current_position();
+        let is_identifier = matches!(self.peek(), Token::Identifier(_));
+        let value = self.expect_annotation_arg()?;
+        if !(is_identifier && self.peek() == &Token::Colon) {
+            args.push(value);
+            return Ok(());
+        }
 
+        self.advance(); // consume ":"
+        if named.contains_key(&value) {
+            return Err(ParseError {
+                message: format!("Duplicate annotation argument '{}'", value),
+                position,
+            });
+        }
+        let argument = self.expect_annotation_arg()?;
+        named.insert(value, argument);
+        Ok(())
+    }
+
+    /// Parse a glossary term
+    ///
+    /// Grammar: `term_decl`, see [`crate::grammar`]
+    fn parse_term_decl(&mut self) -> Result<TermDecl, ParseError> {
+        self.expect(Token::Term)?;
+        let position = self.current_position();
+        let mut spans = Spans::default();
+        let name = self.expect_name(&mut spans)?;
+
+        let definition = match self.peek() {
+            Token::Str(definition) => {
+                let definition = definition.to_string();
+                self.advance();
+                definition
+            }
+            other => {
+                return Err(ParseError {
+                    message: format!("Expected quoted definition, got {}", other),
+                    position: self.current_position(),
+                })
+            }
+        };
+
+        Ok(TermDecl {
+            name,
+            definition,
+            position,
+            spans,
+        })
+    }
+
+    /// Parse a ruleset
+    ///
+    /// Grammar: `ruleset_decl`, `forbid_rule`, see [`crate::grammar`]
+    fn parse_ruleset_decl(&mut self) -> Result<RulesetDecl, ParseError> {
+        self.expect(Token::Ruleset)?;
+        let position = self.current_position();
+        let mut spans = Spans::default();
+        let name = self.expect_name(&mut spans)?;
+        self.expect(Token::LeftBrace)?;
+
+        let mut forbidden = Vec::new();
+        while self.peek() == &Token::Forbid {
+            self.advance(); // consume "forbid"
+            forbidden.push(self.expect_name(&mut spans)?);
             while self.peek() == &Token::Comma {
                 self.advance(); // consume comma
-                roles.push(self.expect_identifier()?);
+                forbidden.push(self.expect_name(&mut spans)?);
+            }
+        }
+
+        self.expect(Token::RightBrace)?;
+
+        Ok(RulesetDecl {
+            name,
+            forbidden,
+            position,
+            spans,
+        })
+    }
+
+    /// Parse a mirror declaration
+    ///
+    /// Grammar: `mirror_decl`, `pair`, see [`crate::grammar`]
+    fn parse_mirror_decl(&mut self) -> Result<MirrorDecl, ParseError> {
+        let position = self.current_position();
+        self.expect(Token::Mirror)?;
+        let kind = match self.peek() {
+            Token::Roles => MirrorKind::Roles,
+            Token::Suffixes => MirrorKind::Suffixes,
+            other => {
+                return Err(ParseError {
+                    message: format!("Expected {} after mirror, got {}", grammar::one_of(&grammar::choices("mirror_kind")), other),
+                    position: self.current_position(),
+                })
             }
+        };
+        self.advance();
+        self.expect(Token::LeftBrace)?;
 
-            self.expect(Token::RightBrace)?;
-            Some(roles)
-        } else {
-            None
+        let mut spans = Spans::default();
+        let mut pairs = Vec::new();
+        loop {
+            let left = self.expect_name(&mut spans)?;
+            self.expect(Token::DoubleArrow)?;
+            pairs.push((left, self.expect_name(&mut spans)?));
+            if self.peek() != &Token::Comma {
+                break;
+            }
+            self.advance(); // consume comma
+        }
+
+        self.expect(Token::RightBrace)?;
+
+        Ok(MirrorDecl {
+            kind,
+            pairs,
+            position,
+            spans,
+        })
+    }
+
+    /// Parse an assertion
+    ///
+    /// Grammar: `assert_decl`, see [`crate::grammar`]
+    fn parse_assert_decl(&mut self) -> Result<AssertDecl, ParseError> {
+        let position = self.current_position();
+        self.expect(Token::Assert)?;
+        let kind_position = self.current_position();
+        let name = self.expect_identifier()?;
+        let kind = AssertKind::from_name(&name).ok_or_else(|| ParseError {
+            message: format!(
+                "Unknown assertion '{}', expected {}",
+                name,
+                grammar::one_of(&grammar::choices("assertion"))
+            ),
+            position: kind_position,
+        })?;
+        let from = self.parse_state_ref()?;
+        self.expect(Token::Arrow)?;
+        let to = self.parse_state_ref()?;
+
+        Ok(AssertDecl {
+            kind,
+            from,
+            to,
+            position,
+        })
+    }
+
+    /// Parse trailing annotations
+    ///
+    /// Grammar: `annotation`, `annotation_arg`, see [`crate::grammar`]
+    fn parse_annotations(&mut self) -> Result<Vec<Annotation>, ParseError> {
+        let mut annotations = Vec::new();
+        while self.peek() == &Token::At {
+            let position = self.current_position();
+            self.advance(); // consume "@"
+            let name = self.expect_identifier()?;
+
+            let mut args = Vec::new();
+            let mut named = BTreeMap::new();
+            if self.peek() == &Token::LeftParen {
+                self.advance(); // consume "("
+                if self.peek() != &Token::RightParen {
+                    self.parse_annotation_arg(&mut args, &mut named)?;
+                    while self.peek() == &Token::Comma {
+                        self.advance(); // consume comma
+                        self.parse_annotation_arg(&mut args, &mut named)?;
+                    }
+                }
+                self.expect(Token::RightParen)?;
+            }
+
+            annotations.push(Annotation {
+                name,
+                args,
+                named,
+                position,
+            });
+        }
+        Ok(annotations)
+    }
+
+    /// Parse a state declaration
+    ///
+    /// Grammar: `state_decl`, `state_roles`, see [`crate::grammar`]
+    ///
+    /// In version 1, `state_roles` must start on the line of the state name;
+    /// a `roles` on a later line begins a roles declaration instead.
+    fn parse_state_decl(&mut self) -> Result<State, ParseError> {
+        self.expect(Token::State)?;
+        let position = self.current_position();
+        let mut spans = Spans::default();
+        let name = self.expect_name(&mut spans)?;
+
+        let same_line = self.current_position().line == position.line;
+        let allowed_roles = match self.peek() {
+            Token::Roles if same_line && self.version == 1 => {
+                self.advance(); // consume "roles"
+                Some(self.parse_role_list(Token::LeftBrace, Token::RightBrace, &mut spans)?)
+            }
+            Token::Roles if same_line && self.peek_next() == &Token::LeftBrace => {
+                let message = format!(
+                    "'state {} roles {{ .. }}' is martial 1 syntax; write 'state {}[..]', or run mat migrate",
+                    name, name
+                );
+                if !self.compat {
+                    return Err(ParseError {
+                        message,
+                        position: self.current_position(),
+                    });
+                }
+                self.deprecations.push(Deprecation {
+                    message,
+                    position: self.current_position(),
+                });
+                self.advance(); // consume "roles"
+                Some(self.parse_role_list(Token::LeftBrace, Token::RightBrace, &mut spans)?)
+            }
+            Token::LeftBracket if self.version >= 2 => Some(self.parse_role_list(Token::LeftBracket, Token::RightBracket, &mut spans)?),
+            _ => None,
         };
 
+        let annotations = self.parse_annotations()?;
+
         Ok(State {
             name,
             allowed_roles,
+            annotations,
+            position,
+            spans,
         })
     }
 
+    /// Parse a comma-separated list of roles between `open` and `close`
+    fn parse_role_list(&mut self, open: Token, close: Token, spans: &mut Spans) -> Result<Vec<String>, ParseError> {
+        self.expect(open)?;
+
+        let mut roles = Vec::new();
+        roles.push(self.expect_name(spans)?);
+
+        while self.peek() == &Token::Comma {
+            self.advance(); // consume comma
+            roles.push(self.expect_name(spans)?);
+        }
+
+        self.expect(close)?;
+        Ok(roles)
+    }
+
     /// Parse a sequence declaration
     ///
-    /// Grammar: sequence_decl ::= "sequence" IDENTIFIER ":" sequence_step+
-    ///          sequence_step ::= IDENTIFIER ":" state_ref "->" state_ref
+    /// Grammar: `sequence_decl`, `alternating`, `sequence_item`, see [`crate::grammar`]
     fn parse_sequence_decl(&mut self) -> Result<Sequence, ParseError> {
+        let mirrored = self.peek() == &Token::Mirrored;
+        if mirrored {
+            self.advance(); // consume "mirrored"
+        }
+        let mut alternating_spans = Spans::default();
+        let alternating = if self.peek() == &Token::Alternating {
+            self.advance(); // consume "alternating"
+            self.expect(Token::LeftParen)?;
+            let first = self.expect_name(&mut alternating_spans)?;
+            self.expect(Token::Comma)?;
+            let second = self.expect_name(&mut alternating_spans)?;
+            self.expect(Token::RightParen)?;
+            Some((first, second))
+        } else {
+            None
+        };
         self.expect(Token::Sequence)?;
-        let name = self.expect_identifier()?;
+        let position = self.current_position();
+        let mut spans = Spans::default();
+        let name = self.expect_name(&mut spans)?;
+        spans.0.extend(alternating_spans.0);
+        let annotations = self.parse_annotations()?;
         self.expect(Token::Colon)?;
 
         let mut steps = Vec::new();
+        let mut invocations = Vec::new();
+
+        // Parse at least one item, then keep parsing while we see
+        // identifiers (start of the next step or invocation)
+        loop {
+            if self.peek_next() == &Token::LeftParen {
+                self.parse_invocations(steps.len(), &mut invocations)?;
+            } else {
+                steps.push(self.parse_sequence_step()?);
+            }
+            if !matches!(self.peek(), Token::Identifier(_)) {
+                break;
+            }
+        }
 
-        // Parse at least one step
-        steps.push(self.parse_sequence_step()?);
+        Ok(Sequence {
+            name,
+            steps,
+            invocations,
+            annotations,
+            mirrored,
+            alternating,
+            mirror_of: None,
+            position,
+            spans,
+        })
+    }
 
-        // Parse additional steps
-        // Keep parsing while we see identifiers (start of next step)
-        while matches!(self.peek(), Token::Identifier(_)) {
-            steps.push(self.parse_sequence_step()?);
+    /// Parse the invocations of a template, one per argument list
+    ///
+    /// Grammar: `invocation`, `arguments`, `template_arg`, see [`crate::grammar`]
+    fn parse_invocations(&mut self, index: usize, invocations: &mut Vec<Invocation>) -> Result<(), ParseError> {
+        let mut template_spans = Spans::default();
+        let template = self.expect_name(&mut template_spans)?;
+        loop {
+            let position = self.current_position();
+            let mut spans = template_spans.clone();
+            self.expect(Token::LeftParen)?;
+            let mut args = Vec::new();
+            if self.peek() != &Token::RightParen {
+                args.push(self.parse_template_arg(&mut spans)?);
+                while self.peek() == &Token::Comma {
+                    self.advance(); // consume comma
+                    args.push(self.parse_template_arg(&mut spans)?);
+                }
+            }
+            self.expect(Token::RightParen)?;
+            invocations.push(Invocation {
+                template: template.clone(),
+                args,
+                index,
+                position,
+                spans,
+            });
+
+            if !(self.peek() == &Token::Comma && self.peek_next() == &Token::LeftParen) {
+                return Ok(());
+            }
+            self.advance(); // consume comma
         }
+    }
 
-        Ok(Sequence { name, steps })
+    fn parse_template_arg(&mut self, spans: &mut Spans) -> Result<TemplateArg, ParseError> {
+        if let Some(token) = self.tokens.get(self.position) {
+            spans.push(NameSpan {
+                position: token.position,
+                span: token.span,
+            });
+        }
+        if self.peek_next() == &Token::LeftBracket {
+            Ok(TemplateArg::Ref(self.parse_state_ref()?))
+        } else {
+            Ok(TemplateArg::Name(self.expect_symbol()?))
+        }
+    }
+
+    /// Parse a template declaration
+    ///
+    /// Grammar: `template_decl`, see [`crate::grammar`]
+    ///
+    /// In the steps, a parameter may stand alone where a state reference is
+    /// expected.
+    fn parse_template_decl(&mut self) -> Result<TemplateDecl, ParseError> {
+        self.expect(Token::Template)?;
+        let position = self.current_position();
+        let name = self.expect_identifier()?;
+        self.expect(Token::LeftParen)?;
+
+        let mut params: Vec<String> = Vec::new();
+        if self.peek() != &Token::RightParen {
+            loop {
+                let param_position = self.current_position();
+                let param = self.expect_identifier()?;
+                if params.contains(&param) {
+                    return Err(ParseError {
+                        message: format!("Duplicate template parameter '{}'", param),
+                        position: param_position,
+                    });
+                }
+                params.push(param);
+                if self.peek() != &Token::Comma {
+                    break;
+                }
+                self.advance(); // consume comma
+            }
+        }
+        self.expect(Token::RightParen)?;
+        self.expect(Token::Equals)?;
+
+        self.params = params;
+        let mut steps = Vec::new();
+        let result = loop {
+            match self.parse_sequence_step() {
+                Ok(step) => steps.push(step),
+                Err(e) => break Err(e),
+            }
+            if !matches!(self.peek(), Token::Identifier(_)) {
+                break Ok(());
+            }
+        };
+        let params = core::mem::take(&mut self.params);
+        result?;
+
+        Ok(TemplateDecl {
+            name,
+            params,
+            steps,
+            position,
+        })
     }
 
     /// Parse a sequence step
     ///
-    /// Grammar: sequence_step ::= IDENTIFIER ":" state_ref "->" state_ref
+    /// Grammar: `sequence_step`, `on_fail`, `reaction`, see [`crate::grammar`]
     fn parse_sequence_step(&mut self) -> Result<SequenceStep, ParseError> {
-        let action_name = self.expect_identifier()?;
+        let position = self.current_position();
+        let action_name = self.expect_symbol()?;
+        let mut spans = Spans::default();
+        spans.push(self.last_span());
         self.expect(Token::Colon)?;
         let from = self.parse_state_ref()?;
         self.expect(Token::Arrow)?;
         let to = self.parse_state_ref()?;
 
+        let on_fail = if self.peek() == &Token::OnFail {
+            self.advance(); // consume "onfail"
+            self.expect(Token::Arrow)?;
+            Some(self.parse_state_ref()?)
+        } else {
+            None
+        };
+        let reaction = if self.peek() == &Token::Slash {
+            self.advance(); // consume "/"
+            let from = self.parse_state_ref()?;
+            self.expect(Token::Arrow)?;
+            let to = self.parse_state_ref()?;
+            Some(Reaction { from, to })
+        } else {
+            None
+        };
+        let repeat = match self.peek() {
+            // On the next line, a word starts the next step
+            Token::Identifier(word) if self.current_position().line == position.line && is_repetition(word) => {
+                let repeat_position = self.current_position();
+                // Expansion copies the step once per repetition
+                let count = word[1..].parse().ok().filter(|count| (1..=MAX_REPETITIONS).contains(count));
+                let count = count.ok_or_else(|| ParseError {
+                    message: format!("A step is repeated 1 to {} times, got {}", MAX_REPETITIONS, word),
+                    position: repeat_position,
+                })?;
+                self.advance(); // consume "xN"
+                Some(count)
+            }
+            _ => None,
+        };
+        let annotations = self.parse_annotations()?;
+
         Ok(SequenceStep {
             action_name,
             from,
             to,
+            on_fail,
+            reaction,
+            annotations,
+            template: None,
+            repeat,
+            repetition: None,
+            position,
+            spans,
         })
     }
 
     /// Parse a state reference
     ///
-    /// Grammar: state_ref ::= IDENTIFIER "[" IDENTIFIER "]"
+    /// Grammar: `state_ref`, see [`crate::grammar`]
     fn parse_state_ref(&mut self) -> Result<StateRef, ParseError> {
-        let state = self.expect_identifier()?;
+        let state = self.expect_symbol()?;
+        let mut spans = Spans::default();
+        spans.push(self.last_span());
+        if self.peek() != &Token::LeftBracket && self.params.iter().any(|p| *p == *state) {
+            return Ok(StateRef {
+                spans,
+                ..StateRef::placeholder(state)
+            });
+        }
         self.expect(Token::LeftBracket)?;
-        let role = self.expect_identifier()?;
+        let role = self.expect_symbol()?;
+        spans.push(self.last_span());
+        let mut extra_roles = Vec::new();
+        while self.peek() == &Token::Comma {
+            self.advance(); // consume comma
+            extra_roles.push(self.expect_symbol()?);
+            spans.push(self.last_span());
+        }
         self.expect(Token::RightBracket)?;
 
-        Ok(StateRef { state, role })
+        Ok(StateRef {
+            state,
+            role,
+            extra_roles,
+            spans,
+        })
     }
 
     /// Parse a group declaration
     ///
-    /// Grammar: group_decl ::= "group" IDENTIFIER "{" IDENTIFIER { "," IDENTIFIER } "}"
+    /// Grammar: `group_decl`, see [`crate::grammar`]
     fn parse_group_decl(&mut self) -> Result<GroupDecl, ParseError> {
         self.expect(Token::Group)?;
-        let name = self.expect_identifier()?;
+        let position = self.current_position();
+        let mut spans = Spans::default();
+        let name = self.expect_name(&mut spans)?;
         self.expect(Token::LeftBrace)?;
 
         let mut states = Vec::new();
-        states.push(self.expect_identifier()?);
+        states.push(self.expect_name(&mut spans)?);
 
         while self.peek() == &Token::Comma {
             self.advance(); // consume comma
-            states.push(self.expect_identifier()?);
+            states.push(self.expect_name(&mut spans)?);
         }
 
         self.expect(Token::RightBrace)?;
 
-        Ok(GroupDecl { name, states })
+        Ok(GroupDecl {
+            name,
+            states,
+            position,
+            spans,
+        })
     }
 }
 
+/// Most times a step can be repeated with an `xN` suffix
+pub const MAX_REPETITIONS: u32 = 1000;
+
+/// Whether `word` is a repetition suffix such as `x3`
+fn is_repetition(word: &str) -> bool {
+    word.len() > 1 && word.starts_with('x') && word[1..].bytes().all(|b| b.is_ascii_digit())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -319,6 +911,14 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_parse_roles_after_state() {
+        let result = parse_input("state Mount\nroles { Top, Bottom }").unwrap();
+        assert_eq!(result.declarations.len(), 2);
+        assert!(matches!(&result.declarations[0], Declaration::State(state) if state.allowed_roles.is_none()));
+        assert!(matches!(&result.declarations[1], Declaration::Roles(_)));
+    }
+
     #[test]
     fn test_parse_sequence() {
         let input = r#"
@@ -420,4 +1020,308 @@ group GuardFamily {
             _ => panic!("Expected Group declaration"),
         }
     }
+
+    #[test]
+    fn test_parse_levels_and_annotations() {
+        let input = r#"
+levels { WhiteBelt, BlueBelt }
+state Mount roles { Top } @level(BlueBelt)
+sequence Hold @level(BlueBelt) @drill:
+    Settle: Mount[Top] -> Mount[Top] @entry()
+"#;
+        let result = parse_input(input).unwrap();
+        match &result.declarations[0] {
+            Declaration::Levels(levels) => assert_eq!(levels.levels, vec!["WhiteBelt", "BlueBelt"]),
+            _ => panic!("Expected Levels declaration"),
+        }
+        match &result.declarations[1] {
+            Declaration::State(state) => assert_eq!(state.level(), Some("BlueBelt")),
+            _ => panic!("Expected State declaration"),
+        }
+        match &result.declarations[2] {
+            Declaration::Sequence(sequence) => {
+                assert_eq!(sequence.level(), Some("BlueBelt"));
+                assert_eq!(sequence.annotations[1].name, "drill");
+                assert!(sequence.annotations[1].args.is_empty());
+                assert_eq!(sequence.steps[0].annotations[0].name, "entry");
+            }
+            _ => panic!("Expected Sequence declaration"),
+        }
+
+        let result = parse_input(r#"state Stance @label(en: "Front Stance", ja: "前屈立ち") @tag(a, b)"#).unwrap();
+        match &result.declarations[0] {
+            Declaration::State(state) => {
+                assert_eq!(state.label("en"), Some("Front Stance"));
+                assert_eq!(state.label("ja"), Some("前屈立ち"));
+                assert_eq!(state.label("fr"), None);
+                assert_eq!(state.annotations[1].args, vec!["a", "b"]);
+            }
+            _ => panic!("Expected State declaration"),
+        }
+        assert!(parse_input(r#"state A @label(en: "A", en: "B")"#).is_err());
+        assert!(parse_input(r#"state A @label(en:)"#).is_err());
+
+        assert!(parse_input("state Mount @level(").is_err());
+        assert!(parse_input("state Mount @").is_err());
+    }
+
+    #[test]
+    fn test_parse_term() {
+        let result = parse_input(r#"term OGoshi "Major hip throw""#).unwrap();
+        match &result.declarations[0] {
+            Declaration::Term(term) => {
+                assert_eq!(term.name, "OGoshi");
+                assert_eq!(term.definition, "Major hip throw");
+            }
+            _ => panic!("Expected Term declaration"),
+        }
+        assert!(parse_input("term OGoshi MajorHipThrow").is_err());
+    }
+
+    #[test]
+    fn test_parse_reaction() {
+        let input = "sequence Sweep:\n    Scissor: Guard[Bottom] -> Mount[Top] / Guard[Top] -> Mount[Bottom] @drill\n    Post: Mount[Top] -> Mount[Top]";
+        let result = parse_input(input).unwrap();
+        match &result.declarations[0] {
+            Declaration::Sequence(sequence) => {
+                let reaction = sequence.steps[0].reaction.as_ref().unwrap();
+                assert_eq!((reaction.from.state.as_str(), reaction.from.role.as_str()), ("Guard", "Top"));
+                assert_eq!((reaction.to.state.as_str(), reaction.to.role.as_str()), ("Mount", "Bottom"));
+                assert_eq!(sequence.steps[0].annotations[0].name, "drill");
+                assert_eq!(sequence.steps[1].reaction, None);
+            }
+            _ => panic!("Expected Sequence declaration"),
+        }
+        assert!(parse_input("sequence S:\n    Go: A[X] -> B[X] / A[Y]").is_err());
+    }
+
+    #[test]
+    fn test_parse_on_fail() {
+        let input = "sequence Finish:\n    Armbar: Mount[Top] -> Armbar[Top] onfail -> Mount[Top] / Mount[Bottom] -> Armbar[Bottom] @drill";
+        let result = parse_input(input).unwrap();
+        match &result.declarations[0] {
+            Declaration::Sequence(sequence) => {
+                let step = &sequence.steps[0];
+                assert_eq!(step.on_fail.as_ref().map(ToString::to_string).as_deref(), Some("Mount[Top]"));
+                assert!(step.reaction.is_some());
+                assert_eq!(step.outcomes().count(), 2);
+                assert_eq!(step.annotations[0].name, "drill");
+            }
+            _ => panic!("Expected Sequence declaration"),
+        }
+        assert!(parse_input("sequence S:\n    Go: A[X] -> B[X] onfail A[X]").is_err());
+    }
+
+    #[test]
+    fn test_parse_repetition() {
+        let input = "sequence Drill:\n    AgeUke: Zenkutsu[Tori] -> Zenkutsu[Tori] x3 @entry\n    x2: Zenkutsu[Tori] -> Yoi[Tori]";
+        let result = parse_input(input).unwrap();
+        match &result.declarations[0] {
+            Declaration::Sequence(sequence) => {
+                assert_eq!(sequence.steps[0].repeat, Some(3));
+                assert_eq!(sequence.steps[0].annotations[0].name, "entry");
+                // A suffix-like word on the next line is the next step
+                assert_eq!((sequence.steps[1].action_name.as_str(), sequence.steps[1].repeat), ("x2", None));
+            }
+            _ => panic!("Expected Sequence declaration"),
+        }
+        let error = parse_input("sequence S:\n    Go: A[X] -> A[X] x0").unwrap_err();
+        assert_eq!((error.message.as_str(), error.position.column), ("A step is repeated 1 to 1000 times, got x0", 22));
+        assert!(parse_input("sequence S:\n    Go: A[X] -> A[X] x1000").is_ok());
+        let error = parse_input("sequence S:\n    Go: A[X] -> A[X] x4000000000").unwrap_err();
+        assert_eq!(error.message, "A step is repeated 1 to 1000 times, got x4000000000");
+    }
+
+    #[test]
+    fn test_parse_ruleset() {
+        let result = parse_input("ruleset IBJJF {\n    forbid HeelHook, Slam\n    forbid Scissor\n}").unwrap();
+        match &result.declarations[0] {
+            Declaration::Ruleset(ruleset) => {
+                assert_eq!(ruleset.name, "IBJJF");
+                assert_eq!(ruleset.forbidden, vec!["HeelHook", "Slam", "Scissor"]);
+            }
+            _ => panic!("Expected Ruleset declaration"),
+        }
+        assert!(parse_input("ruleset Open {}").is_ok());
+        assert!(parse_input("ruleset IBJJF { HeelHook }").is_err());
+        assert!(parse_input("ruleset IBJJF { forbid }").is_err());
+    }
+
+    #[test]
+    fn test_parse_template() {
+        let input = "template Step(action, from, to) = action: from -> to\n\
+                     template Punch(action, stance) =\n    \
+                     action: stance[Tori] -> stance[Tori] @level(White)\n    \
+                     Return: stance[Tori] -> Ready[Tori]";
+        let result = parse_input(input).unwrap();
+        match &result.declarations[..] {
+            [Declaration::Template(step), Declaration::Template(punch)] => {
+                assert_eq!(step.params, vec!["action", "from", "to"]);
+                assert!(step.steps[0].from.is_placeholder());
+                assert_eq!(step.steps[0].to, StateRef::placeholder("to"));
+                assert_eq!(punch.steps.len(), 2);
+                assert_eq!(punch.steps[0].from.role.as_str(), "Tori");
+                assert_eq!(punch.position, Position { line: 2, column: 10 });
+            }
+            other => panic!("Expected two templates, got {:?}", other),
+        }
+
+        assert!(parse_input("template T(a, a) = a: S[R] -> S[R]").is_err());
+        assert!(parse_input("template T(a) = a: b -> S[R]").is_err());
+        assert!(parse_input("template T(a) =").is_err());
+    }
+
+    #[test]
+    fn test_parse_invocations() {
+        let input = "sequence Kata:\n    \
+                     Bow: Ready[Tori] -> Ready[Tori]\n    \
+                     Punch(Oizuki, Zenkutsu), (Gyakuzuki, Kokutsu)\n    \
+                     Step(Turn, Zenkutsu[Tori], Ready[Tori])\n    \
+                     Bow: Ready[Tori] -> Ready[Tori]";
+        let result = parse_input(input).unwrap();
+        let Declaration::Sequence(sequence) = &result.declarations[0] else {
+            panic!("Expected Sequence declaration");
+        };
+        assert_eq!(sequence.steps.len(), 2);
+        let invocations: Vec<(&str, usize, usize)> = sequence
+            .invocations
+            .iter()
+            .map(|i| (i.template.as_str(), i.index, i.position.line))
+            .collect();
+        assert_eq!(invocations, vec![("Punch", 1, 3), ("Punch", 1, 3), ("Step", 1, 4)]);
+        assert_eq!(sequence.invocations[1].args[1], TemplateArg::Name("Kokutsu".into()));
+        assert_eq!(sequence.invocations[2].args[1].to_string(), "Zenkutsu[Tori]");
+
+        let result = parse_input("sequence Kata:\n    Punch()").unwrap();
+        let Declaration::Sequence(sequence) = &result.declarations[0] else {
+            panic!("Expected Sequence declaration");
+        };
+        assert!(sequence.steps.is_empty());
+        assert!(sequence.invocations[0].args.is_empty());
+        assert!(parse_input("sequence Kata:\n    Punch(A, B").is_err());
+    }
+
+    #[test]
+    fn test_parse_mirror() {
+        let input = "mirror roles { Orthodox <-> Southpaw, Tori <-> Uke }\n\
+                     mirror suffixes { Left <-> Right }\n\
+                     mirrored sequence JabLeft:\n    \
+                     JabLeft: Guard[Orthodox] -> Guard[Orthodox]";
+        let result = parse_input(input).unwrap();
+        match &result.declarations[..] {
+            [Declaration::Mirror(roles), Declaration::Mirror(suffixes), Declaration::Sequence(sequence)] => {
+                assert_eq!(roles.kind, MirrorKind::Roles);
+                assert_eq!(
+                    roles.pairs,
+                    vec![("Orthodox".to_string(), "Southpaw".to_string()), ("Tori".to_string(), "Uke".to_string())]
+                );
+                assert_eq!(suffixes.kind, MirrorKind::Suffixes);
+                assert!(sequence.mirrored);
+                assert_eq!(sequence.mirror_of, None);
+            }
+            other => panic!("Expected mirrors and a sequence, got {:?}", other),
+        }
+
+        assert!(parse_input("mirror states { A <-> B }").is_err());
+        assert!(parse_input("mirror roles { A -> B }").is_err());
+        assert!(parse_input("mirror roles { A <> B }").is_err());
+        assert!(parse_input("mirror roles {}").is_err());
+        assert!(parse_input("mirrored state A").is_err());
+
+        let sequence = "mirrored alternating(Tori, Uke) sequence Drill:\n    Go: A[Tori] -> A[Tori]";
+        match &parse_input(sequence).unwrap().declarations[..] {
+            [Declaration::Sequence(sequence)] => {
+                assert!(sequence.mirrored);
+                assert_eq!(sequence.alternating, Some(("Tori".to_string(), "Uke".to_string())));
+            }
+            other => panic!("Expected a sequence, got {:?}", other),
+        }
+        assert!(parse_input("alternating(Tori) sequence Drill:\n    Go: A[Tori] -> A[Tori]").is_err());
+        assert!(parse_input("alternating(Tori, Uke) mirrored sequence Drill:\n    Go: A[Tori] -> A[Tori]").is_err());
+    }
+
+    #[test]
+    fn test_parse_assert() {
+        let input = "assert reachable Standing[Neutral] -> Mount[Top]\n\
+                     assert no_path Mount[Bottom] -> RearMount[Bottom]";
+        let result = parse_input(input).unwrap();
+        match &result.declarations[..] {
+            [Declaration::Assert(reachable), Declaration::Assert(no_path)] => {
+                assert_eq!(reachable.kind, AssertKind::Reachable);
+                assert_eq!(reachable.from.to_string(), "Standing[Neutral]");
+                assert_eq!(reachable.to.to_string(), "Mount[Top]");
+                assert_eq!(no_path.kind, AssertKind::NoPath);
+                assert_eq!(no_path.position, Position { line: 2, column: 1 });
+            }
+            other => panic!("Expected two assertions, got {:?}", other),
+        }
+
+        let error = parse_input("assert connected A[R] -> B[R]").unwrap_err();
+        assert!(error.message.contains("Unknown assertion 'connected'"), "{}", error);
+        assert!(parse_input("assert reachable A -> B[R]").is_err());
+        assert!(parse_input("assert reachable A[R] B[R]").is_err());
+    }
+
+    #[test]
+    fn test_parse_version() {
+        assert_eq!(parse_input("// Kata\nmartial 2\nstate Back").unwrap().version, 2);
+        let file = parse_input("martial 2\nstate Guard[Top, Bottom] @core\nstate Back").unwrap();
+        assert_eq!(file.version, 2);
+        match &file.declarations[0] {
+            Declaration::State(state) => {
+                assert_eq!(state.allowed_roles, Some(vec!["Top".to_string(), "Bottom".to_string()]));
+                assert_eq!(state.annotations.len(), 1);
+            }
+            other => panic!("expected a state, got {:?}", other),
+        }
+
+        assert_eq!(parse_input("state Guard roles { Top }").unwrap().version, 1);
+        assert!(parse_input("state Guard[Top]").is_err());
+        assert!(parse_input("martial 1\nstate Guard[Top]").is_err());
+        let error = parse_input("martial 2\nstate Guard roles { Top }").unwrap_err();
+        assert!(error.message.contains("'state Guard[..]'"), "{}", error);
+        assert!(parse_input("martial 0").is_err());
+        assert!(parse_input("state A\nmartial 2").is_err());
+    }
+
+    #[test]
+    fn test_parse_compat() {
+        let source = "martial 2\nstate Guard roles { Top }\nstate Mount[Top]";
+        let tokens = Lexer::new(source).tokenize().unwrap();
+        let file = Parser::new(tokens).compat(true).parse().unwrap();
+        assert_eq!(file.normalized(), parse_input("martial 2\nstate Guard[Top]\nstate Mount[Top]").unwrap().normalized());
+        assert_eq!(file.deprecations.len(), 1);
+        assert_eq!(file.deprecations[0].position.line, 2);
+        assert!(file.deprecations[0].message.contains("'state Guard[..]'"), "{:?}", file.deprecations);
+        assert!(parse_input(source).is_err());
+    }
+
+    #[test]
+    fn test_malformed_input_is_an_error() {
+        for input in [
+            "\u{0}",
+            "\u{feff}roles { A }",
+            "\"",
+            "-",
+            "state A @",
+            "state A @level(",
+            "sequence S:\n    X: A[",
+            "sequence S:\n    X: A[B] -> A[B] /",
+            "roles { A, }",
+            "term A",
+            "ruleset R { forbid }",
+            "template T(",
+            "template T(a) = a: a",
+            "sequence S:\n    T(A[",
+            "sequence S:\n    T(A), (",
+            &"[".repeat(10_000),
+        ] {
+            assert!(parse_input(input).is_err(), "{:?}", input);
+        }
+        // A token stream without EOF reads as if it ended there
+        let tokens = Lexer::new("state A").tokenize().unwrap();
+        let mut parser = Parser::new(tokens[..2].to_vec());
+        assert_eq!(parser.parse().unwrap().declarations.len(), 1);
+        assert!(Parser::new(Vec::new()).parse().unwrap().declarations.is_empty());
+    }
 }
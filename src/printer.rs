@@ -0,0 +1,291 @@
+//! Source printer for the Martial DSL
+//!
+//! Turns an AST back into `.martial` source in the canonical layout used by
+//! the examples. Comments are not part of the AST and are therefore lost;
+//! front-matter metadata is re-emitted as TOML.
+
+use crate::ast::*;
+use crate::lexer;
+use crate::metadata::FileMetadata;
+use std::fmt::Write;
+
+fn state_ref(r: &StateRef) -> String {
+    r.to_string()
+}
+
+fn quote(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Print an annotation argument, quoting it unless it is an identifier or
+/// number, such as `90L`
+fn annotation_arg(arg: &str) -> String {
+    let mut chars = arg.chars();
+    let bare = match chars.next() {
+        Some(c) if c.is_alphabetic() || c == '_' => {
+            chars.all(|c| c.is_alphanumeric() || c == '_') && lexer::keyword(arg).is_none()
+        }
+        Some(c) if c.is_ascii_digit() => {
+            chars.as_str().trim_start_matches(|c: char| c.is_ascii_digit()).chars().all(|c| c.is_ascii_alphabetic())
+        }
+        _ => false,
+    };
+    if bare {
+        arg.to_string()
+    } else {
+        quote(arg)
+    }
+}
+
+/// Print annotations, each preceded by a space
+fn annotations(annotations: &[Annotation]) -> String {
+    let mut out = String::new();
+    for annotation in annotations {
+        let _ = write!(out, " @{}", annotation.name);
+        if !annotation.args.is_empty() || !annotation.named.is_empty() {
+            let args: Vec<String> = annotation
+                .args
+                .iter()
+                .map(|a| annotation_arg(a))
+                .chain(annotation.named.iter().map(|(k, v)| format!("{}: {}", k, annotation_arg(v))))
+                .collect();
+            let _ = write!(out, "({})", args.join(", "));
+        }
+    }
+    out
+}
+
+fn print_step(step: &SequenceStep) -> String {
+    let mut out = format!("{}: {} -> {}", step.action_name, state_ref(&step.from), state_ref(&step.to));
+    if let Some(on_fail) = &step.on_fail {
+        let _ = write!(out, " onfail -> {}", state_ref(on_fail));
+    }
+    if let Some(reaction) = &step.reaction {
+        let _ = write!(out, " / {} -> {}", state_ref(&reaction.from), state_ref(&reaction.to));
+    }
+    if let (Some(count), None) = (step.repeat, step.repetition) {
+        let _ = write!(out, " x{}", count);
+    }
+    out + &annotations(&step.annotations)
+}
+
+fn print_invocation(invocation: &Invocation) -> String {
+    let args: Vec<String> = invocation.args.iter().map(TemplateArg::to_string).collect();
+    format!("{}({})", invocation.template, args.join(", "))
+}
+
+/// Print a single declaration in the syntax of file format `version`,
+/// without a trailing newline
+pub fn print_declaration(declaration: &Declaration, version: u32) -> String {
+    match declaration {
+        Declaration::Roles(roles) => format!("roles {{ {} }}", roles.roles.join(", ")),
+        Declaration::State(state) => {
+            let mut out = format!("state {}", state.name);
+            match &state.allowed_roles {
+                Some(roles) if version >= 2 => {
+                    let _ = write!(out, "[{}]", roles.join(", "));
+                }
+                Some(roles) => {
+                    let _ = write!(out, " roles {{ {} }}", roles.join(", "));
+                }
+                None => {}
+            }
+            out + &annotations(&state.annotations)
+        }
+        Declaration::Sequence(sequence) => {
+            let mut out = String::new();
+            if sequence.mirrored {
+                out.push_str("mirrored ");
+            }
+            if let Some((first, second)) = &sequence.alternating {
+                let _ = write!(out, "alternating({}, {}) ", first, second);
+            }
+            let _ = write!(out, "sequence {}{}:", sequence.name, annotations(&sequence.annotations));
+            let mut invocations = sequence.invocations.iter().peekable();
+            for (i, step) in sequence.steps.iter().enumerate() {
+                while let Some(invocation) = invocations.next_if(|inv| inv.index <= i) {
+                    let _ = write!(out, "\n    {}", print_invocation(invocation));
+                }
+                let _ = write!(out, "\n    {}", print_step(step));
+            }
+            for invocation in invocations {
+                let _ = write!(out, "\n    {}", print_invocation(invocation));
+            }
+            out
+        }
+        Declaration::Template(template) => {
+            let mut out = format!("template {}({}) =", template.name, template.params.join(", "));
+            for step in &template.steps {
+                let _ = write!(out, "\n    {}", print_step(step));
+            }
+            out
+        }
+        Declaration::Group(group) => format!("group {} {{ {} }}", group.name, group.states.join(", ")),
+        Declaration::Levels(levels) => format!("levels {{ {} }}", levels.levels.join(", ")),
+        Declaration::Term(term) => format!("term {} {}", term.name, quote(&term.definition)),
+        Declaration::Ruleset(ruleset) if ruleset.forbidden.is_empty() => format!("ruleset {} {{}}", ruleset.name),
+        Declaration::Ruleset(ruleset) => {
+            format!("ruleset {} {{\n    forbid {}\n}}", ruleset.name, ruleset.forbidden.join(", "))
+        }
+        Declaration::Assert(assertion) => assertion.to_string(),
+        Declaration::Mirror(mirror) => {
+            let kind = match mirror.kind {
+                MirrorKind::Roles => "roles",
+                MirrorKind::Suffixes => "suffixes",
+            };
+            let pairs: Vec<String> = mirror.pairs.iter().map(|(left, right)| format!("{} <-> {}", left, right)).collect();
+            format!("mirror {} {{ {} }}", kind, pairs.join(", "))
+        }
+    }
+}
+
+/// Print metadata as a TOML front-matter block
+pub fn print_metadata(metadata: &FileMetadata) -> String {
+    let known = [
+        ("author", &metadata.author),
+        ("level", &metadata.level),
+        ("reviewed", &metadata.reviewed),
+    ];
+    let entries = known
+        .into_iter()
+        .filter_map(|(key, value)| value.as_ref().map(|v| (key, v)))
+        .chain(metadata.extra.iter().map(|(k, v)| (k.as_str(), v)));
+
+    let mut out = String::from("+++\n");
+    for (key, value) in entries {
+        let _ = writeln!(out, "{} = {}", key, toml::Value::String(value.clone()));
+    }
+    out.push_str("+++\n");
+    out
+}
+
+/// Print a whole file
+///
+/// Files of version 2 and later start with their version pragma.
+/// Consecutive state declarations are kept on adjacent lines; every other
+/// declaration is separated by a blank line.
+pub fn print_file(file: &MartialFile) -> String {
+    let mut out = file.metadata.as_ref().map(print_metadata).unwrap_or_default();
+    if file.version >= 2 {
+        let _ = writeln!(out, "martial {}", file.version);
+        if !file.declarations.is_empty() {
+            out.push('\n');
+        }
+    }
+    let mut previous: Option<&Declaration> = None;
+
+    for declaration in &file.declarations {
+        if let Some(previous) = previous {
+            let both_states = matches!(
+                (previous, declaration),
+                (Declaration::State(_), Declaration::State(_))
+            );
+            out.push_str(if both_states { "\n" } else { "\n\n" });
+        }
+        out.push_str(&print_declaration(declaration, file.version));
+        previous = Some(declaration);
+    }
+
+    if !file.declarations.is_empty() {
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn parse(input: &str) -> MartialFile {
+        let tokens = Lexer::new(input).tokenize().unwrap();
+        Parser::new(tokens).parse().unwrap()
+    }
+
+    #[test]
+    fn test_print_canonical_layout() {
+        let input = "roles { Top, Bottom }\n\n\
+                     state Mount roles { Top, Bottom }\n\
+                     state Back\n\n\
+                     sequence TakeBack:\n    \
+                         Roll: Mount[Top] -> Back[Top]\n    \
+                         Hook: Back[Top] -> Back[Top]\n\n\
+                     group Dominant { Mount, Back }\n";
+        assert_eq!(print_file(&parse(input)), input);
+    }
+
+    #[test]
+    fn test_print_annotations() {
+        let input = "levels { White, Blue }\n\n\
+                     state Mount roles { Top } @level(Blue)\n\n\
+                     sequence Hold @level(Blue) @drill:\n    \
+                         Settle: Mount[Top] -> Mount[Top] @level()\n";
+        let printed = print_file(&parse(input));
+        assert_eq!(printed, input.replace("@level()", "@level"));
+
+        let input = "term Kesa \"Scarf \\\"hold\\\"\"\n";
+        assert_eq!(print_file(&parse(input)), input);
+
+        let input = "sequence Hold @duration(5) @video(\"https://x.test/?q=\\\"a\\\"\"):\n    \
+                         Settle: Mount[Top] -> Mount[Top] @video(\"https://x.test\")\n";
+        assert_eq!(print_file(&parse(input)), input);
+
+        let input = "sequence Team:\n    Enter: Guard[Top, Bottom, Top] -> Mount[Top, Bottom, Bottom]\n";
+        assert_eq!(print_file(&parse(input)), input);
+
+        let input = "sequence Sweep:\n    Scissor: Guard[Bottom] -> Mount[Top] / Guard[Top] -> Mount[Bottom] @drill\n";
+        assert_eq!(print_file(&parse(input)), input);
+
+        let input = "sequence Finish:\n    Armbar: Mount[Top] -> Armbar[Top] onfail -> Mount[Top] / Mount[Bottom] -> Armbar[Bottom]\n";
+        assert_eq!(print_file(&parse(input)), input);
+
+        let input = "ruleset IBJJF {\n    forbid HeelHook, Slam\n}\n\nruleset Open {}\n";
+        assert_eq!(print_file(&parse(input)), input);
+
+        let input = "state Stance @label(en: \"Front Stance\", ja: Zenkutsu)\n";
+        assert_eq!(print_file(&parse(input)), input);
+
+        let input = "state Stance @note(\"state\", en: \"term\")\n";
+        assert_eq!(print_file(&parse(input)), input);
+    }
+
+    #[test]
+    fn test_print_templates() {
+        let input = "template Punch(action, stance, to) =\n    \
+                         action: stance[Tori] -> to @video(action)\n\n\
+                     sequence Kata:\n    \
+                         Punch(Oizuki, Zenkutsu, Ready[Tori])\n    \
+                         Punch(Gyakuzuki, Ready, Ready[Tori])\n    \
+                         Bow: Ready[Tori] -> Ready[Tori] x3 @turn(90L)\n    \
+                         Punch(Oizuki, Ready, Ready[Tori])\n";
+        assert_eq!(print_file(&parse(input)), input);
+    }
+
+    #[test]
+    fn test_print_metadata() {
+        let mut file = parse("state Mount");
+        file.metadata = crate::metadata::parse("---\nauthor: Coach\nclub: North\n---\n").unwrap();
+        assert_eq!(print_file(&file), "+++\nauthor = \"Coach\"\nclub = \"North\"\n+++\nstate Mount\n");
+    }
+
+    #[test]
+    fn test_print_versions() {
+        let input = "martial 2\n\nstate Guard[Top, Bottom] @level(White)\nstate Back\n";
+        let file = parse(input);
+        assert_eq!(file.version, 2);
+        assert_eq!(print_file(&file), input);
+
+        let v1 = MartialFile { version: 1, ..file };
+        assert_eq!(print_file(&v1), "state Guard roles { Top, Bottom } @level(White)\nstate Back\n");
+    }
+
+    #[test]
+    fn test_print_reparses() {
+        let file = parse("roles{A}state S sequence Q: Go: S[A] -> S[A] group G{S}");
+        let printed = print_file(&file);
+        let reparsed = parse(&printed);
+        assert_eq!(reparsed.declarations.len(), file.declarations.len());
+        assert_eq!(print_file(&reparsed), printed);
+    }
+}
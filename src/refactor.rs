@@ -0,0 +1,724 @@
+//! AST-based refactorings across the files of a system
+//!
+//! Refactorings work out what changes on the parsed files, then edit only
+//! that text of the sources, so comments and layout elsewhere are kept.
+//! Callers re-validate the result before writing.
+
+use crate::ast::*;
+use crate::lexer::{Lexer, Span};
+use crate::loader::SourceFile;
+use crate::parser::Parser;
+use crate::symbol::Symbol;
+use std::fmt;
+
+/// Kind of symbol a refactoring applies to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolKind {
+    Role,
+    State,
+    Sequence,
+    Action,
+    Group,
+}
+
+impl SymbolKind {
+    /// Names accepted by [`SymbolKind::from_name`]
+    pub const NAMES: [&'static str; 5] = ["role", "state", "sequence", "action", "group"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "role" => Some(SymbolKind::Role),
+            "state" => Some(SymbolKind::State),
+            "sequence" => Some(SymbolKind::Sequence),
+            "action" => Some(SymbolKind::Action),
+            "group" => Some(SymbolKind::Group),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for SymbolKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            SymbolKind::Role => "role",
+            SymbolKind::State => "state",
+            SymbolKind::Sequence => "sequence",
+            SymbolKind::Action => "action",
+            SymbolKind::Group => "group",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Error preventing a refactoring
+#[derive(Debug, Clone, PartialEq)]
+pub struct RefactorError {
+    pub message: String,
+}
+
+impl RefactorError {
+    fn new(message: impl Into<String>) -> Self {
+        RefactorError {
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for RefactorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Refactoring error: {}", self.message)
+    }
+}
+
+impl core::error::Error for RefactorError {}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_alphabetic() || c == '_')
+        && chars.all(|c| c.is_alphanumeric() || c == '_')
+        && !crate::completion::KEYWORDS.contains(&name)
+}
+
+/// Whether a symbol of `kind` named `name` is declared or used in `files`
+fn exists(files: &[MartialFile], kind: SymbolKind, name: &str) -> bool {
+    files.iter().flat_map(|f| &f.declarations).any(|d| match (kind, d) {
+        (SymbolKind::Role, Declaration::Roles(r)) => r.roles.iter().any(|r| r == name),
+        (SymbolKind::State, Declaration::State(s)) => s.name == name,
+        (SymbolKind::Sequence, Declaration::Sequence(s)) => s.name == name,
+        (SymbolKind::Action, Declaration::Sequence(s)) => s.steps.iter().any(|step| step.action_name == name),
+        (SymbolKind::Group, Declaration::Group(g)) => g.name == name,
+        _ => false,
+    })
+}
+
+/// A change to the text of a file: `span` replaced with `text`
+#[derive(Debug, Clone, PartialEq)]
+struct Edit {
+    span: Span,
+    text: String,
+}
+
+/// Replace the name written at `span`, if its span is known
+fn replace(edits: &mut Vec<Edit>, span: Option<NameSpan>, new: &str) {
+    if let Some(NameSpan { span, .. }) = span {
+        edits.push(Edit {
+            span,
+            text: new.to_string(),
+        });
+    }
+}
+
+/// Rename `old` in a list of names whose spans are those of `spans` from
+/// `offset` on
+///
+/// Renaming onto a name already in the list merges the two: the later one
+/// is removed, with the comma before it, from the list and its text.
+fn rename_in(
+    names: &mut Vec<String>,
+    spans: &mut Spans,
+    offset: usize,
+    old: &str,
+    new: &str,
+    edits: &mut Vec<Edit>,
+) -> usize {
+    let mut count = 0;
+    for (i, name) in names.iter_mut().enumerate().filter(|(_, n)| *n == old) {
+        *name = new.to_string();
+        replace(edits, spans.get(offset + i), new);
+        count += 1;
+    }
+
+    let listed: Vec<Span> = spans.0.iter().skip(offset).map(|name| name.span).collect();
+    let duplicates: Vec<usize> = (0..names.len()).filter(|&i| names[..i].contains(&names[i])).collect();
+    for &i in duplicates.iter().rev() {
+        // The first of equal names is kept, so a duplicate has one before it
+        if let (Some(before), Some(span)) = (listed.get(i - 1), listed.get(i)) {
+            edits.retain(|edit| edit.span != *span);
+            edits.push(Edit {
+                span: Span {
+                    start: before.end,
+                    end: span.end,
+                },
+                text: String::new(),
+            });
+        }
+        names.remove(i);
+        if offset + i < spans.0.len() {
+            spans.0.remove(offset + i);
+        }
+    }
+    count
+}
+
+fn rename_ref(r: &mut StateRef, kind: SymbolKind, old: &str, new: &str, edits: &mut Vec<Edit>) -> usize {
+    let fields: Vec<&mut Symbol> = match kind {
+        SymbolKind::State => vec![&mut r.state],
+        SymbolKind::Role => std::iter::once(&mut r.role).chain(&mut r.extra_roles).collect(),
+        _ => return 0,
+    };
+    // Roles follow the state in the spans
+    let first = if kind == SymbolKind::Role { 1 } else { 0 };
+    let mut count = 0;
+    for (i, field) in fields.into_iter().enumerate().filter(|(_, f)| **f == old) {
+        *field = Symbol::new(new);
+        replace(edits, r.spans.get(first + i), new);
+        count += 1;
+    }
+    count
+}
+
+fn rename_step(step: &mut SequenceStep, kind: SymbolKind, old: &str, new: &str, edits: &mut Vec<Edit>) -> usize {
+    let mut count = 0;
+    if kind == SymbolKind::Action && step.action_name == old {
+        step.action_name = Symbol::new(new);
+        replace(edits, step.spans.get(0), new);
+        count += 1;
+    }
+    count += rename_ref(&mut step.from, kind, old, new, edits);
+    // Only the start of a step can be a group
+    if kind == SymbolKind::Group && step.from.state == old {
+        step.from.state = Symbol::new(new);
+        replace(edits, step.from.spans.get(0), new);
+        count += 1;
+    }
+    count += rename_ref(&mut step.to, kind, old, new, edits);
+    if let Some(on_fail) = &mut step.on_fail {
+        count += rename_ref(on_fail, kind, old, new, edits);
+    }
+    if let Some(reaction) = &mut step.reaction {
+        count += rename_ref(&mut reaction.from, kind, old, new, edits);
+        count += rename_ref(&mut reaction.to, kind, old, new, edits);
+    }
+    count
+}
+
+/// Rename a symbol and every reference to it
+///
+/// Returns the number of occurrences changed in each file, in file order.
+/// States, sequences and groups cannot be renamed onto an existing name;
+/// roles and actions can, which merges them.
+pub fn rename(files: &mut [MartialFile], kind: SymbolKind, old: &str, new: &str) -> Result<Vec<usize>, RefactorError> {
+    if !is_identifier(new) {
+        return Err(RefactorError::new(format!("'{}' is not a valid identifier", new)));
+    }
+    if !exists(files, kind, old) {
+        return Err(RefactorError::new(format!("No {} named '{}'", kind, old)));
+    }
+    let merges = matches!(kind, SymbolKind::Role | SymbolKind::Action);
+    if old != new && !merges && exists(files, kind, new) {
+        return Err(RefactorError::new(format!("A {} named '{}' already exists", kind, new)));
+    }
+
+    Ok(files.iter_mut().map(|file| rename_file(file, kind, old, new, &mut Vec::new())).collect())
+}
+
+/// Rename a symbol in one file, returning the number of occurrences changed
+///
+/// Adds the text edits doing the same to `edits`, for the names whose spans
+/// are known.
+fn rename_file(file: &mut MartialFile, kind: SymbolKind, old: &str, new: &str, edits: &mut Vec<Edit>) -> usize {
+    let mut count = 0;
+    for declaration in &mut file.declarations {
+        match declaration {
+            Declaration::Roles(roles) if kind == SymbolKind::Role => {
+                count += rename_in(&mut roles.roles, &mut roles.spans, 0, old, new, edits);
+            }
+            Declaration::State(state) => {
+                if kind == SymbolKind::State && state.name == old {
+                    state.name = new.to_string();
+                    replace(edits, state.spans.get(0), new);
+                    count += 1;
+                }
+                if let (SymbolKind::Role, Some(roles)) = (kind, &mut state.allowed_roles) {
+                    count += rename_in(roles, &mut state.spans, 1, old, new, edits);
+                }
+            }
+            Declaration::Sequence(sequence) => {
+                if kind == SymbolKind::Sequence && sequence.name == old {
+                    sequence.name = new.to_string();
+                    replace(edits, sequence.spans.get(0), new);
+                    count += 1;
+                }
+                if let (SymbolKind::Role, Some((first, second))) = (kind, &mut sequence.alternating) {
+                    for (i, role) in [first, second].into_iter().enumerate().filter(|(_, role)| *role == old) {
+                        *role = new.to_string();
+                        replace(edits, sequence.spans.get(1 + i), new);
+                        count += 1;
+                    }
+                }
+                for step in &mut sequence.steps {
+                    count += rename_step(step, kind, old, new, edits);
+                }
+                for invocation in &mut sequence.invocations {
+                    for (i, arg) in invocation.args.iter_mut().enumerate() {
+                        match arg {
+                            TemplateArg::Name(name) if *name == old => {
+                                *name = Symbol::new(new);
+                                replace(edits, invocation.spans.get(1 + i), new);
+                                count += 1;
+                            }
+                            TemplateArg::Ref(state_ref) => count += rename_ref(state_ref, kind, old, new, edits),
+                            TemplateArg::Name(_) => {}
+                        }
+                    }
+                }
+            }
+            Declaration::Group(group) => {
+                if kind == SymbolKind::Group && group.name == old {
+                    group.name = new.to_string();
+                    replace(edits, group.spans.get(0), new);
+                    count += 1;
+                }
+                if kind == SymbolKind::State {
+                    count += rename_in(&mut group.states, &mut group.spans, 1, old, new, edits);
+                }
+            }
+            Declaration::Ruleset(ruleset) if matches!(kind, SymbolKind::Action | SymbolKind::Sequence) => {
+                count += rename_in(&mut ruleset.forbidden, &mut ruleset.spans, 1, old, new, edits);
+            }
+            Declaration::Term(term) => {
+                // Glossary entries follow the action or state they define
+                if matches!(kind, SymbolKind::Action | SymbolKind::State) && term.name == old {
+                    term.name = new.to_string();
+                    replace(edits, term.spans.get(0), new);
+                    count += 1;
+                }
+            }
+            Declaration::Template(template) if !template.params.iter().any(|p| p == old) => {
+                for step in &mut template.steps {
+                    count += rename_step(step, kind, old, new, edits);
+                }
+            }
+            Declaration::Mirror(mirror) if mirror.kind == MirrorKind::Roles && kind == SymbolKind::Role => {
+                let roles = mirror.pairs.iter_mut().flat_map(|(left, right)| [left, right]);
+                for (i, role) in roles.enumerate().filter(|(_, role)| *role == old) {
+                    *role = new.to_string();
+                    replace(edits, mirror.spans.get(i), new);
+                    count += 1;
+                }
+            }
+            Declaration::Assert(assertion) => {
+                count += rename_ref(&mut assertion.from, kind, old, new, edits);
+                count += rename_ref(&mut assertion.to, kind, old, new, edits);
+            }
+            Declaration::Roles(_)
+            | Declaration::Levels(_)
+            | Declaration::Ruleset(_)
+            | Declaration::Template(_)
+            | Declaration::Mirror(_) => {}
+        }
+    }
+    count
+}
+
+/// The file `content` parsed, with the span of each declaration
+fn parse_spans(content: &str) -> Result<(MartialFile, Vec<Span>), RefactorError> {
+    let tokens = Lexer::new(content).tokenize().map_err(|e| RefactorError::new(e.to_string()))?;
+    let mut parser = Parser::new(tokens).compat(true);
+    let file = parser.parse().map_err(|e| RefactorError::new(e.to_string()))?;
+    Ok((file, parser.spans().to_vec()))
+}
+
+/// `content` with the occurrences of a symbol [`rename`] changes renamed,
+/// and nothing else, so that comments and layout are kept
+///
+/// The file is parsed once; the rename edits the names at the spans the
+/// parser recorded for them.
+pub fn rename_source(content: &str, kind: SymbolKind, old: &str, new: &str) -> Result<String, RefactorError> {
+    let (mut file, _) = parse_spans(content)?;
+    let mut edits = Vec::new();
+    rename_file(&mut file, kind, old, new, &mut edits);
+    edits.sort_by_key(|edit| edit.span.start);
+
+    let mut out = String::with_capacity(content.len());
+    let mut at = 0;
+    for edit in &edits {
+        out.push_str(&content[at..edit.span.start]);
+        out.push_str(&edit.text);
+        at = edit.span.end;
+    }
+    out.push_str(&content[at..]);
+    Ok(out)
+}
+
+/// What extracting a group leaves of a source file
+#[derive(Debug, Clone, PartialEq)]
+pub enum Remainder {
+    /// None of its declarations moved
+    Unchanged,
+    /// Some moved; the text left, comments included
+    Edited(String),
+    /// All of its declarations moved
+    Emptied,
+}
+
+/// A group moved to a new file, see [`extract_group`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Extraction {
+    pub file: SourceFile,
+    /// Number of declarations moved to the new file
+    pub moved: usize,
+    /// What is left of each source, in order
+    pub remainders: Vec<Remainder>,
+}
+
+/// The text a declaration spanning `span` takes in `content`: its lines,
+/// with the comment lines right above it and a comment ending its last line
+fn declaration_text(content: &str, span: Span) -> Span {
+    let line_start = |at: usize| content[..at].rfind('\n').map_or(0, |i| i + 1);
+    let mut start = span.start;
+    if content[line_start(start)..start].trim().is_empty() {
+        start = line_start(start);
+        while start > 0 {
+            let above = line_start(start - 1);
+            if !content[above..start].trim_start().starts_with("//") {
+                break;
+            }
+            start = above;
+        }
+    }
+    let line_end = content[span.end..].find('\n').map_or(content.len(), |i| span.end + i + 1);
+    let rest = content[span.end..line_end].trim();
+    let end = if rest.is_empty() || rest.starts_with("//") { line_end } else { span.end };
+    Span { start, end }
+}
+
+/// Move a group, its states and the sequences that only touch them to a new file
+///
+/// Each declaration is cut from its source with the comment lines right
+/// above it and pasted into a new file with the given path, in their
+/// original order, so comments and layout are kept.
+pub fn extract_group(sources: &[SourceFile], group: &str, path: &str) -> Result<Extraction, RefactorError> {
+    let parsed = sources
+        .iter()
+        .map(|source| parse_spans(&source.content))
+        .collect::<Result<Vec<_>, _>>()?;
+    let states: Vec<String> = parsed
+        .iter()
+        .flat_map(|(f, _)| &f.declarations)
+        .find_map(|d| match d {
+            Declaration::Group(g) if g.name == group => Some(g.states.clone()),
+            _ => None,
+        })
+        .ok_or_else(|| RefactorError::new(format!("No group named '{}'", group)))?;
+
+    let moves = |declaration: &Declaration| match declaration {
+        Declaration::Group(g) => g.name == group,
+        Declaration::State(s) => states.contains(&s.name),
+        Declaration::Sequence(s) => s
+            .steps
+            .iter()
+            .all(|step| states.iter().any(|s| *s == step.from.state) && states.iter().any(|s| *s == step.to.state)),
+        Declaration::Roles(_)
+        | Declaration::Levels(_)
+        | Declaration::Term(_)
+        | Declaration::Ruleset(_)
+        | Declaration::Template(_)
+        | Declaration::Mirror(_)
+        | Declaration::Assert(_) => false,
+    };
+
+    let mut blocks = Vec::new();
+    let mut versions = Vec::new();
+    let mut remainders = Vec::new();
+    for (source, (file, spans)) in sources.iter().zip(&parsed) {
+        let cuts: Vec<Span> = file
+            .declarations
+            .iter()
+            .zip(spans)
+            .filter(|(declaration, _)| moves(declaration))
+            .map(|(_, span)| declaration_text(&source.content, *span))
+            .collect();
+        if cuts.is_empty() {
+            remainders.push(Remainder::Unchanged);
+            continue;
+        }
+        if !versions.contains(&file.version) {
+            versions.push(file.version);
+        }
+        blocks.extend(cuts.iter().map(|cut| source.content[cut.start..cut.end].trim_end().to_string()));
+        if cuts.len() == file.declarations.len() {
+            remainders.push(Remainder::Emptied);
+            continue;
+        }
+        let mut content = source.content.clone();
+        for cut in cuts.iter().rev() {
+            // A cut between blank lines takes the blank lines after it too
+            let mut end = cut.end;
+            if content[..cut.start].ends_with("\n\n") || cut.start == 0 {
+                end += content[end..].len() - content[end..].trim_start_matches('\n').len();
+            }
+            content.replace_range(cut.start..end, "");
+        }
+        remainders.push(Remainder::Edited(content));
+    }
+
+    // Declarations are copied as written, so they keep their file version
+    let pragma = match versions[..] {
+        [1] => String::new(),
+        [version] => format!("martial {}\n\n", version),
+        _ => {
+            return Err(RefactorError::new(format!(
+                "The declarations of group '{}' are written in different file format versions; migrate the files first",
+                group
+            )))
+        }
+    };
+    Ok(Extraction {
+        file: SourceFile::new(path, format!("{}{}\n", pragma, blocks.join("\n\n"))),
+        moved: blocks.len(),
+        remainders,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loader::{load_sources, parse_source, SourceFile};
+    use crate::printer::print_file;
+
+    fn files() -> Vec<MartialFile> {
+        [
+            ("roles.martial", "roles { Top, Bottom }"),
+            ("states.martial", "state Mount roles { Top, Bottom }\nstate Back\ngroup Dominant { Mount, Back }"),
+            ("sequences.martial", "sequence TakeBack:\n    Roll: Mount[Top] -> Back[Top]\n    Hook: Back[Top] -> Back[Top]"),
+        ]
+        .iter()
+        .map(|(path, content)| parse_source(&SourceFile::new(*path, *content)).unwrap())
+        .collect()
+    }
+
+    fn printed(files: &[MartialFile]) -> Vec<SourceFile> {
+        files
+            .iter()
+            .map(|f| SourceFile::new(f.source.clone().unwrap(), print_file(f)))
+            .collect()
+    }
+
+    #[test]
+    fn test_rename_state() {
+        let mut files = files();
+        let counts = rename(&mut files, SymbolKind::State, "Mount", "FullMount").unwrap();
+        assert_eq!(counts, vec![0, 2, 1]);
+
+        let sources = printed(&files);
+        assert!(sources[1].content.contains("state FullMount roles { Top, Bottom }"));
+        assert!(sources[1].content.contains("group Dominant { FullMount, Back }"));
+        assert!(sources[2].content.contains("Roll: FullMount[Top] -> Back[Top]"));
+        load_sources("test", &sources).unwrap();
+    }
+
+    #[test]
+    fn test_rename_source_matches_rename() {
+        let content = "roles { Top, Bottom }\nstate Guard roles { Top, Bottom }\nstate Mount\n\
+                       group Pins { Mount }\nterm Hip \"Hip bump\"\nmirror roles { Top <-> Bottom }\n\
+                       ruleset Kids { forbid Hip, Sweep }\nassert reachable Guard[Bottom] -> Mount[Top]\n\
+                       template Step(action, to) =\n    action: Guard[Bottom] -> to / Guard[Top] -> Guard[Bottom]\n\
+                       alternating(Bottom, Top) sequence Sweep:\n    Hip: Guard[Bottom] -> Mount[Top, Bottom] onfail -> Guard[Bottom]\n\
+                       \x20   Step(Hip, Mount[Top]), (Pin, Pins[Top])\n    Pin: Pins[Top] -> Mount[Top]\n";
+        let renames = [
+            (SymbolKind::Role, "Top"),
+            (SymbolKind::State, "Guard"),
+            (SymbolKind::State, "Mount"),
+            (SymbolKind::Group, "Pins"),
+            (SymbolKind::Action, "Hip"),
+            (SymbolKind::Sequence, "Sweep"),
+        ];
+        for (kind, old) in renames {
+            let (mut expected, _) = parse_spans(content).unwrap();
+            assert!(rename_file(&mut expected, kind, old, "Renamed", &mut Vec::new()) > 0);
+            let renamed = rename_source(content, kind, old, "Renamed").unwrap();
+            let (file, _) = parse_spans(&renamed).unwrap();
+            assert!(file.same_declarations(&expected), "{} {}:\n{}", kind, old, renamed);
+        }
+    }
+
+    #[test]
+    fn test_rename_source_merges() {
+        let sources = [
+            SourceFile::new("roles.martial", "roles { Top, Bottom, Neutral } // all of them\n"),
+            SourceFile::new(
+                "states.martial",
+                "state Stand roles { Neutral, Top }\nstate Mount roles { Top, Bottom }\n\
+                 ruleset Kids { forbid Slam, Spike\n    forbid Drop }\n",
+            ),
+            SourceFile::new(
+                "sequences.martial",
+                "sequence Takedown:\n    Drop: Stand[Neutral] -> Mount[Top]\n\
+                 sequence Finish:\n    Slam: Stand[Top] -> Mount[Top]\n    Spike: Mount[Top] -> Mount[Top]\n",
+            ),
+        ];
+        let rewrite = |kind: SymbolKind, old: &str, new: &str| -> Vec<SourceFile> {
+            sources
+                .iter()
+                .map(|source| SourceFile {
+                    content: rename_source(&source.content, kind, old, new).unwrap(),
+                    ..source.clone()
+                })
+                .collect()
+        };
+
+        let renamed = rewrite(SymbolKind::Role, "Neutral", "Top");
+        assert_eq!(renamed[0].content, "roles { Top, Bottom } // all of them\n");
+        assert!(renamed[1].content.starts_with("state Stand roles { Top }\n"), "{}", renamed[1].content);
+        load_sources("test", &renamed).unwrap();
+
+        let renamed = rewrite(SymbolKind::Role, "Top", "Neutral");
+        assert_eq!(renamed[0].content, "roles { Neutral, Bottom } // all of them\n");
+        assert!(renamed[1].content.starts_with("state Stand roles { Neutral }\n"), "{}", renamed[1].content);
+        load_sources("test", &renamed).unwrap();
+
+        let renamed = rewrite(SymbolKind::Action, "Drop", "Slam");
+        assert!(renamed[1].content.contains("ruleset Kids { forbid Slam, Spike }\n"), "{}", renamed[1].content);
+        load_sources("test", &renamed).unwrap();
+    }
+
+    #[test]
+    fn test_rename_source_keeps_comments() {
+        let content = "// Pins\nstate Mount roles { Top, Bottom } // on top\n\n\
+                       sequence Pin:\n    // Climb\n    Mount: Mount[Top] -> Mount[Top] @video(Mount)\n";
+        let renamed = rename_source(content, SymbolKind::State, "Mount", "FullMount").unwrap();
+        assert_eq!(
+            renamed,
+            "// Pins\nstate FullMount roles { Top, Bottom } // on top\n\n\
+             sequence Pin:\n    // Climb\n    Mount: FullMount[Top] -> FullMount[Top] @video(Mount)\n"
+        );
+        let renamed = rename_source(content, SymbolKind::Action, "Mount", "Climb").unwrap();
+        assert!(renamed.contains("    Climb: Mount[Top] -> Mount[Top] @video(Mount)\n"), "{}", renamed);
+        let renamed = rename_source(content, SymbolKind::Role, "Top", "T").unwrap();
+        assert!(renamed.contains("roles { T, Bottom } // on top") && renamed.contains("Mount[T] -> Mount[T]"), "{}", renamed);
+    }
+
+    #[test]
+    fn test_rename_role_and_action() {
+        let mut files = files();
+        assert_eq!(rename(&mut files, SymbolKind::Role, "Top", "Attacker").unwrap(), vec![1, 1, 4]);
+        assert_eq!(rename(&mut files, SymbolKind::Action, "Hook", "Hooks").unwrap(), vec![0, 0, 1]);
+
+        let sources = printed(&files);
+        assert!(sources[2].content.contains("Hooks: Back[Attacker] -> Back[Attacker]"));
+        load_sources("test", &sources).unwrap();
+    }
+
+    #[test]
+    fn test_rename_updates_reaction() {
+        let mut files = files();
+        files[2] = parse_source(&SourceFile::new(
+            "sequences.martial",
+            "sequence Roll:\n    Bridge: Mount[Bottom] -> Back[Top] / Mount[Top] -> Back[Bottom]\n\
+             template Flip(action) =\n    action: Back[Top] -> Mount[Top] / Back[Bottom] -> Mount[Bottom]",
+        ))
+        .unwrap();
+        assert_eq!(rename(&mut files, SymbolKind::State, "Mount", "FullMount").unwrap(), vec![0, 2, 4]);
+
+        let sources = printed(&files);
+        assert!(sources[2].content.contains("Bridge: FullMount[Bottom] -> Back[Top] / FullMount[Top] -> Back[Bottom]"));
+        assert!(sources[2].content.contains("action: Back[Top] -> FullMount[Top] / Back[Bottom] -> FullMount[Bottom]"));
+        load_sources("test", &sources).unwrap();
+    }
+
+    #[test]
+    fn test_rename_group() {
+        let mut files = files();
+        files[2] = parse_source(&SourceFile::new(
+            "sequences.martial",
+            "template Leave(from) = Bridge: from[Bottom] -> Back[Bottom]\n\
+             sequence Escape:\n    Shrimp: Dominant[Bottom] -> Back[Bottom]\n    Leave(Dominant)",
+        ))
+        .unwrap();
+        assert_eq!(rename(&mut files, SymbolKind::Group, "Dominant", "Pinned").unwrap(), vec![0, 1, 2]);
+
+        let sources = printed(&files);
+        assert!(sources[1].content.contains("group Pinned { Mount, Back }"));
+        assert!(sources[2].content.contains("Shrimp: Pinned[Bottom] -> Back[Bottom]\n    Leave(Pinned)"));
+        load_sources("test", &sources).unwrap();
+    }
+
+    #[test]
+    fn test_rename_updates_alternating() {
+        let mut files = files();
+        files[2] = parse_source(&SourceFile::new(
+            "sequences.martial",
+            "alternating(Top, Bottom) sequence Scramble:\n    Roll: Mount[Top] -> Back[Top]\n    Bridge: Mount[Bottom] -> Mount[Bottom]",
+        ))
+        .unwrap();
+        assert_eq!(rename(&mut files, SymbolKind::Role, "Top", "Attacker").unwrap(), vec![1, 1, 3]);
+        let sources = printed(&files);
+        assert!(sources[2].content.starts_with("alternating(Attacker, Bottom) sequence Scramble:"));
+        load_sources("test", &sources).unwrap();
+    }
+
+    #[test]
+    fn test_rename_updates_mirror() {
+        let mut files = files();
+        files.push(parse_source(&SourceFile::new("mirror.martial", "mirror roles { Top <-> Bottom }")).unwrap());
+        assert_eq!(rename(&mut files, SymbolKind::Role, "Top", "Attacker").unwrap(), vec![1, 1, 4, 1]);
+        assert_eq!(print_file(&files[3]), "mirror roles { Attacker <-> Bottom }\n");
+    }
+
+    #[test]
+    fn test_rename_updates_term() {
+        let mut files = files();
+        files.push(parse_source(&SourceFile::new("glossary.martial", "term Hook \"Insert the hooks\"")).unwrap());
+        assert_eq!(rename(&mut files, SymbolKind::Action, "Hook", "Hooks").unwrap(), vec![0, 0, 1, 1]);
+        assert_eq!(print_file(&files[3]), "term Hooks \"Insert the hooks\"\n");
+    }
+
+    #[test]
+    fn test_extract_group() {
+        let sources = vec![
+            SourceFile::new("roles.martial", "roles { Top, Bottom }\n"),
+            SourceFile::new(
+                "states.martial",
+                "// Top positions\nstate Mount roles { Top, Bottom }\nstate Back // taken\n\ngroup Dominant { Mount, Back }\n",
+            ),
+            SourceFile::new(
+                "sequences.martial",
+                "sequence TakeBack:\n    Roll: Mount[Top] -> Back[Top]\n\n\
+                 // Out from under\nsequence Escape:\n    Bridge: Mount[Bottom] -> Guard[Top]\n",
+            ),
+            SourceFile::new("guard.martial", "state Guard\n"),
+        ];
+
+        let extraction = extract_group(&sources, "Dominant", "dominant.martial").unwrap();
+        assert_eq!(extraction.moved, 4);
+        assert_eq!(
+            extraction.file.content,
+            "// Top positions\nstate Mount roles { Top, Bottom }\n\nstate Back // taken\n\n\
+             group Dominant { Mount, Back }\n\nsequence TakeBack:\n    Roll: Mount[Top] -> Back[Top]\n"
+        );
+        assert_eq!(
+            extraction.remainders,
+            vec![
+                Remainder::Unchanged,
+                Remainder::Emptied,
+                Remainder::Edited("// Out from under\nsequence Escape:\n    Bridge: Mount[Bottom] -> Guard[Top]\n".into()),
+                Remainder::Unchanged,
+            ]
+        );
+
+        let mut remaining: Vec<SourceFile> = sources
+            .into_iter()
+            .zip(extraction.remainders)
+            .filter_map(|(source, remainder)| match remainder {
+                Remainder::Unchanged => Some(source),
+                Remainder::Edited(content) => Some(SourceFile { content, ..source }),
+                Remainder::Emptied => None,
+            })
+            .collect();
+        remaining.push(extraction.file);
+        load_sources("test", &remaining).unwrap();
+
+        assert!(extract_group(&remaining, "Missing", "x.martial").is_err());
+    }
+
+    #[test]
+    fn test_rename_errors() {
+        let mut files = files();
+        let error = rename(&mut files, SymbolKind::State, "Mount", "Back").unwrap_err();
+        assert!(error.message.contains("already exists"));
+        assert!(rename(&mut files, SymbolKind::Sequence, "Missing", "Other").is_err());
+        assert!(rename(&mut files, SymbolKind::Group, "Dominant", "not valid").is_err());
+        assert!(rename(&mut files, SymbolKind::State, "Mount", "state").is_err());
+    }
+}
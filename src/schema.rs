@@ -0,0 +1,170 @@
+//! JSON Schema documents for the exported formats
+//!
+//! Generated from the Rust types so that they cannot drift from what
+//! `mat graph` and `mat system` actually emit.
+
+use crate::graph::MartialGraph;
+use crate::semantic::MartialSystem;
+use schemars::generate::SchemaSettings;
+use schemars::JsonSchema;
+use serde_json::Value;
+
+/// Names of the formats a schema is available for
+pub const FORMATS: [&str; 2] = ["graph", "system"];
+
+/// JSON Schema of `T` as serialized, so that fields skipped when empty are
+/// not required
+fn schema_of<T: JsonSchema>() -> Value {
+    SchemaSettings::default().for_serialize().into_generator().into_root_schema_for::<T>().to_value()
+}
+
+/// JSON Schema of the graph export
+pub fn graph_schema() -> Value {
+    schema_of::<MartialGraph>()
+}
+
+/// JSON Schema of the system export
+pub fn system_schema() -> Value {
+    schema_of::<MartialSystem>()
+}
+
+/// JSON Schema of a format by name
+pub fn schema(format: &str) -> Option<Value> {
+    match format {
+        "graph" => Some(graph_schema()),
+        "system" => Some(system_schema()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Errors of `value` against `schema`, for the keywords the generated
+    /// schemas use
+    fn check(root: &Value, schema: &Value, value: &Value, path: &str, errors: &mut Vec<String>) {
+        if let Some(reference) = schema["$ref"].as_str() {
+            let name = reference.trim_start_matches("#/$defs/");
+            return check(root, &root["$defs"][name], value, path, errors);
+        }
+        if let Some(options) = schema["anyOf"].as_array() {
+            let matches = |option: &Value| {
+                let mut option_errors = Vec::new();
+                check(root, option, value, path, &mut option_errors);
+                option_errors.is_empty()
+            };
+            if !options.iter().any(matches) {
+                errors.push(format!("{}: matches no option", path));
+            }
+        }
+        if let Some(types) = schema.get("type") {
+            let types: Vec<&str> = match types {
+                Value::Array(types) => types.iter().filter_map(Value::as_str).collect(),
+                other => other.as_str().into_iter().collect(),
+            };
+            let fits = |kind: &&str| match *kind {
+                "object" => value.is_object(),
+                "array" => value.is_array(),
+                "string" => value.is_string(),
+                "integer" => value.is_i64() || value.is_u64(),
+                "number" => value.is_number(),
+                "boolean" => value.is_boolean(),
+                "null" => value.is_null(),
+                _ => false,
+            };
+            if !types.iter().any(fits) {
+                errors.push(format!("{}: expected {}, got {}", path, types.join(" or "), value));
+                return;
+            }
+        }
+        if let (Some(minimum), Some(number)) = (schema["minimum"].as_f64(), value.as_f64()) {
+            if number < minimum {
+                errors.push(format!("{}: {} is below {}", path, number, minimum));
+            }
+        }
+        if let Some(object) = value.as_object() {
+            for field in schema["required"].as_array().into_iter().flatten().filter_map(Value::as_str) {
+                if !object.contains_key(field) {
+                    errors.push(format!("{}: missing required {}", path, field));
+                }
+            }
+            for (key, field) in object {
+                let field_path = format!("{}.{}", path, key);
+                match schema["properties"].get(key) {
+                    Some(property) => check(root, property, field, &field_path, errors),
+                    None => match &schema["additionalProperties"] {
+                        Value::Bool(false) => errors.push(format!("{}: unexpected field", field_path)),
+                        Value::Object(_) => check(root, &schema["additionalProperties"], field, &field_path, errors),
+                        _ => {}
+                    },
+                }
+            }
+        }
+        if let Some(items) = value.as_array() {
+            let prefix = schema["prefixItems"].as_array().map_or(&[][..], Vec::as_slice);
+            for (i, item) in items.iter().enumerate() {
+                let item_schema = prefix.get(i).unwrap_or(&schema["items"]);
+                if item_schema.is_object() {
+                    check(root, item_schema, item, &format!("{}[{}]", path, i), errors);
+                }
+            }
+            let length = items.len() as u64;
+            if schema["minItems"].as_u64().is_some_and(|min| length < min)
+                || schema["maxItems"].as_u64().is_some_and(|max| length > max)
+            {
+                errors.push(format!("{}: {} items is out of bounds", path, length));
+            }
+        }
+    }
+
+    fn errors(schema: &Value, value: &Value) -> Vec<String> {
+        let mut errors = Vec::new();
+        check(schema, schema, value, "$", &mut errors);
+        errors
+    }
+
+    #[test]
+    fn test_exports_match_schemas() {
+        for example in ["bjj-basic", "karate-heian", "judo-newaza"] {
+            let path = format!("{}/examples/{}", env!("CARGO_MANIFEST_DIR"), example);
+            let system = crate::loader::load_directory(&path).unwrap();
+            let json: Value = serde_json::from_str(&system.to_json().unwrap()).unwrap();
+            assert_eq!(errors(&system_schema(), &json), Vec::<String>::new(), "{}", example);
+
+            let graph = MartialGraph::from_system(&system);
+            let json = serde_json::to_value(&graph).unwrap();
+            assert_eq!(errors(&graph_schema(), &json), Vec::<String>::new(), "{}", example);
+        }
+
+        // The checks catch a missing field
+        let json = serde_json::json!({ "system_name": "Empty", "edges": [] });
+        assert_eq!(errors(&graph_schema(), &json), ["$: missing required nodes"]);
+    }
+
+    #[test]
+    fn test_graph_schema() {
+        let schema = graph_schema();
+        assert_eq!(schema["title"], "MartialGraph");
+        assert!(schema["properties"]["nodes"].is_object());
+        assert!(schema["properties"]["edges"].is_object());
+    }
+
+    #[test]
+    fn test_optional_fields() {
+        // Fields left out when empty are not required
+        let required = |schema: &Value| schema["required"].as_array().cloned().unwrap_or_default();
+        let system = required(&system_schema());
+        assert!(system.contains(&"states".into()));
+        for field in ["levels", "terms", "rulesets"] {
+            assert!(!system.contains(&field.into()), "{} is required", field);
+        }
+        assert!(!required(&graph_schema()).contains(&"groups".into()));
+    }
+
+    #[test]
+    fn test_schema_by_name() {
+        assert_eq!(schema("system").unwrap()["title"], "MartialSystem");
+        assert!(schema("unknown").is_none());
+    }
+}
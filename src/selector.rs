@@ -0,0 +1,156 @@
+//! Selectors of positions and sequences
+//!
+//! Commands taking a position or a sequence to filter on accept a selector
+//! rather than an exact name, in the same syntax everywhere:
+//!
+//! - a glob, where `*` stands for any text and `?` for one character. A glob
+//!   with brackets matches position ids, such as `Guard*[Bottom]`; without,
+//!   it matches the state in any role, such as `Turtle*`. A name without
+//!   wildcards is a glob matching only itself.
+//! - a regular expression between slashes, such as `/^Heian/`, found
+//!   anywhere in the state or the position id. Regular expressions need the
+//!   `std` feature.
+//!
+//! Sequence selectors match sequence names the same way.
+
+use crate::graph::Node;
+use crate::prelude::*;
+use core::fmt;
+use core::str::FromStr;
+
+/// Error raised by a malformed selector
+#[derive(Debug, Clone, PartialEq)]
+pub struct SelectorError {
+    pub selector: String,
+    pub message: String,
+}
+
+impl fmt::Display for SelectorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid selector '{}': {}", self.selector, self.message)
+    }
+}
+
+impl core::error::Error for SelectorError {}
+
+/// A selector of positions or sequences
+#[derive(Debug, Clone)]
+pub enum Selector {
+    Glob(String),
+    #[cfg(feature = "std")]
+    Regex(regex::Regex),
+}
+
+impl Selector {
+    pub fn parse(text: &str) -> Result<Self, SelectorError> {
+        let error = |message: String| SelectorError {
+            selector: text.to_string(),
+            message,
+        };
+        let Some(pattern) = text.strip_prefix('/').and_then(|rest| rest.strip_suffix('/')) else {
+            return if text.is_empty() { Err(error("empty selector".to_string())) } else { Ok(Selector::Glob(text.to_string())) };
+        };
+        #[cfg(feature = "std")]
+        return regex::Regex::new(pattern).map(Selector::Regex).map_err(|e| error(e.to_string()));
+        #[cfg(not(feature = "std"))]
+        {
+            let _ = pattern;
+            Err(error("regular expressions need the `std` feature".to_string()))
+        }
+    }
+
+    /// Whether the selector matches `node`
+    pub fn matches_node(&self, node: &Node) -> bool {
+        match self {
+            Selector::Glob(glob) if glob.contains('[') => glob_match(glob, &node.id()),
+            Selector::Glob(glob) => glob_match(glob, &node.state),
+            #[cfg(feature = "std")]
+            Selector::Regex(regex) => regex.is_match(&node.state) || regex.is_match(&node.id()),
+        }
+    }
+
+    /// Whether the selector matches a name, such as a sequence's
+    pub fn matches_name(&self, name: &str) -> bool {
+        match self {
+            Selector::Glob(glob) => glob_match(glob, name),
+            #[cfg(feature = "std")]
+            Selector::Regex(regex) => regex.is_match(name),
+        }
+    }
+}
+
+impl FromStr for Selector {
+    type Err = SelectorError;
+
+    fn from_str(text: &str) -> Result<Self, SelectorError> {
+        Selector::parse(text)
+    }
+}
+
+/// Whether `text` matches `pattern`, where `*` stands for any text and `?`
+/// for one character
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let (pattern, text): (Vec<char>, Vec<char>) = (pattern.chars().collect(), text.chars().collect());
+    // Position after the last `*` and the text it was matched up to
+    let (mut p, mut t, mut star): (usize, usize, Option<(usize, usize)>) = (0, 0, None);
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p + 1, t));
+                p += 1;
+            }
+            Some(&ch) if ch == '?' || ch == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                Some((after, matched)) => {
+                    p = after;
+                    t = matched + 1;
+                    star = Some((after, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&ch| ch == '*')
+}
+
+/// Whether `node` matches the selector `text`; an invalid selector matches
+/// nothing
+pub fn node_filter(text: &str) -> impl Fn(&Node) -> bool {
+    let selector = Selector::parse(text).ok();
+    move |node| selector.as_ref().is_some_and(|selector| selector.matches_node(node))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_selectors() {
+        let (guard, half) = (Node::new("ClosedGuard", "Bottom"), Node::new("HalfGuard", "Top"));
+        let matching = |text: &str| -> Vec<String> {
+            let selector = Selector::parse(text).unwrap();
+            [&guard, &half].into_iter().filter(|n| selector.matches_node(n)).map(Node::id).collect()
+        };
+        assert_eq!(matching("ClosedGuard"), ["ClosedGuard[Bottom]"]);
+        assert_eq!(matching("*Guard"), ["ClosedGuard[Bottom]", "HalfGuard[Top]"]);
+        assert_eq!(matching("*Guard[Bottom]"), ["ClosedGuard[Bottom]"]);
+        assert_eq!(matching("Half?uard[*]"), ["HalfGuard[Top]"]);
+        assert!(matching("Guard").is_empty());
+        assert_eq!(matching("/^Half/"), ["HalfGuard[Top]"]);
+        assert_eq!(matching("/Guard$/"), ["ClosedGuard[Bottom]", "HalfGuard[Top]"]);
+        assert_eq!(matching(r"/\[Bottom\]/"), ["ClosedGuard[Bottom]"]);
+
+        let heian = Selector::parse("/^Heian(Shodan|Nidan)$/").unwrap();
+        assert!(heian.matches_name("HeianNidan") && !heian.matches_name("HeianSandan"));
+        assert!(Selector::parse("Heian*").unwrap().matches_name("HeianSandan"));
+
+        let error = Selector::parse("/(/").unwrap_err();
+        assert!(error.to_string().starts_with("Invalid selector '/(/': "), "{}", error);
+        assert!(Selector::parse("").is_err());
+        assert!(!node_filter("/(/")(&guard));
+        assert!(glob_match("a*b*c", "aXbYbc") && !glob_match("a*b", "ab c"));
+    }
+}
@@ -4,16 +4,186 @@
 //! - Collects and merges roles from all files
 //! - Validates state-role combinations
 //! - Validates sequence step connectivity
+//! - Validates annotations and curriculum levels
 
 use crate::ast::*;
-use std::collections::{HashMap, HashSet};
-use std::fmt;
+use crate::collections::{HashMap, HashSet};
+use crate::graph::MartialGraph;
+use crate::lexer::Position;
+use crate::lint::{Level, LintRegistry};
+use crate::metadata::FileMetadata;
+use crate::prelude::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use alloc::collections::{BTreeMap, BTreeSet};
+use core::fmt::{self, Write};
 
 /// Semantic validation error
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct SemanticError {
     pub message: String,
     pub context: String,
+    /// What the error is about, for callers matching on it
+    #[serde(rename = "details", skip_serializing_if = "SemanticErrorKind::is_other")]
+    pub kind: Box<SemanticErrorKind>,
+    /// Source file of the offending declaration, if known
+    pub file: Option<String>,
+    /// Position of the offending declaration or step, if known
+    pub position: Option<Position>,
+    /// Corrections that would fix the error, most likely first
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    pub suggestions: Box<[Suggestion]>,
+}
+
+/// A machine-applicable correction of a [`SemanticError`], applied by
+/// [`crate::fix`]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Suggestion {
+    /// What the correction does, such as "Replace 'Mout' with 'Mount'"
+    pub message: String,
+    pub edits: Vec<Edit>,
+}
+
+/// A change to the source of a file
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum Edit {
+    /// Replace the identifier `target` in the construct starting at
+    /// `position` of `file` by `replacement`; the location of the error if
+    /// unset
+    Replace {
+        file: Option<String>,
+        position: Option<Position>,
+        target: String,
+        replacement: String,
+    },
+    /// Insert `text` as a line before the line of `position` in `file`,
+    /// indented like it; the location of the error if unset
+    Insert {
+        file: Option<String>,
+        position: Option<Position>,
+        text: String,
+    },
+}
+
+impl Edit {
+    /// Replace `target` where the error is
+    pub fn replace(target: impl Into<String>, replacement: impl Into<String>) -> Self {
+        Edit::Replace {
+            file: None,
+            position: None,
+            target: target.into(),
+            replacement: replacement.into(),
+        }
+    }
+
+    /// Insert the line `text` before the line of the error
+    pub fn insert(text: impl Into<String>) -> Self {
+        Edit::Insert {
+            file: None,
+            position: None,
+            text: text.into(),
+        }
+    }
+
+    /// Make the edit apply in the construct at `position` of `file`
+    pub fn at(mut self, file: Option<&String>, at: Position) -> Self {
+        match &mut self {
+            Edit::Replace { file: f, position, .. } | Edit::Insert { file: f, position, .. } => {
+                *f = file.cloned();
+                *position = Some(at);
+            }
+        }
+        self
+    }
+}
+
+/// What a [`SemanticError`] is about
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum SemanticErrorKind {
+    /// A reference to a state that is not declared; `suggestions` are the
+    /// declared states with the closest names
+    UndefinedState { name: String, suggestions: Vec<String> },
+    /// A reference to a role that is not declared; `suggestions` are the
+    /// declared roles with the closest names
+    UndefinedRole { name: String, suggestions: Vec<String> },
+    /// A role the state does not allow
+    RoleNotAllowed { state: String, role: String, allowed: Vec<String> },
+    /// A level that is not declared
+    UndefinedLevel { name: String },
+    /// A second declaration of a name; `kind` is the declaration keyword,
+    /// such as `state` or `sequence`
+    Duplicate { kind: String, name: String },
+    /// A step that does not start where the previous one ends; `step_index`
+    /// counts from 0, and `opponent` is set for the opponent's transitions
+    BrokenChain {
+        sequence: String,
+        step_index: usize,
+        expected: String,
+        found: String,
+        opponent: bool,
+    },
+    /// A state referenced with a different number of role slots than before
+    ArityMismatch { state: String, expected: usize, found: usize },
+    /// An assertion the graph does not satisfy; `path` holds the actions
+    /// of a path that `no_path` forbids
+    AssertionFailed { assertion: String, path: Vec<String> },
+    /// Any other error, described by the message
+    Other,
+}
+
+impl SemanticErrorKind {
+    pub fn is_other(&self) -> bool {
+        *self == SemanticErrorKind::Other
+    }
+}
+
+impl SemanticError {
+    /// Create an error without location information
+    pub fn new(message: impl Into<String>, context: impl Into<String>) -> Self {
+        SemanticError {
+            message: message.into(),
+            context: context.into(),
+            kind: Box::new(SemanticErrorKind::Other),
+            file: None,
+            position: None,
+            suggestions: Box::default(),
+        }
+    }
+
+    /// Set what the error is about, suggesting the close names of an
+    /// undefined one as replacements
+    pub fn with_kind(mut self, kind: SemanticErrorKind) -> Self {
+        if let SemanticErrorKind::UndefinedState { name, suggestions }
+        | SemanticErrorKind::UndefinedRole { name, suggestions } = &kind
+        {
+            let replacements = suggestions.iter().map(|candidate| Suggestion {
+                message: format!("Replace '{}' with '{}'", name, candidate),
+                edits: vec![Edit::replace(name.clone(), candidate.clone())],
+            });
+            self.suggestions = self.suggestions.iter().cloned().chain(replacements).collect();
+        }
+        self.kind = Box::new(kind);
+        self
+    }
+
+    /// Add a correction, after those already suggested
+    pub fn with_suggestion(mut self, suggestion: Suggestion) -> Self {
+        let mut suggestions = self.suggestions.into_vec();
+        suggestions.push(suggestion);
+        self.suggestions = suggestions.into_boxed_slice();
+        self
+    }
+
+    /// Attach the file and position the error refers to
+    pub fn at(mut self, file: Option<&String>, position: Position) -> Self {
+        self.file = file.cloned();
+        self.position = Some(position);
+        self
+    }
 }
 
 impl fmt::Display for SemanticError {
@@ -22,54 +192,1064 @@ impl fmt::Display for SemanticError {
     }
 }
 
-/// A validated martial system
+impl core::error::Error for SemanticError {}
+
+/// A non-fatal finding of the validation, such as a declared role that no
+/// step uses
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Diagnostic {
+    /// Name of the lint that raised it, such as `unused-role`
+    pub code: String,
+    pub level: Level,
+    pub message: String,
+    /// Source file of the declaration it is about, if known
+    pub file: Option<String>,
+    /// Position of the declaration or step it is about, if known
+    pub position: Option<Position>,
+}
+
+impl Diagnostic {
+    pub fn new(code: impl Into<String>, message: impl Into<String>) -> Self {
+        Diagnostic {
+            code: code.into(),
+            level: Level::Warn,
+            message: message.into(),
+            file: None,
+            position: None,
+        }
+    }
+
+    pub fn with_level(mut self, level: Level) -> Self {
+        self.level = level;
+        self
+    }
+
+    /// Attach the source file and position the diagnostic refers to
+    pub fn at(mut self, file: Option<&String>, position: Position) -> Self {
+        self.file = file.cloned();
+        self.position = Some(position);
+        self
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+        match (&self.file, self.position) {
+            (Some(file), Some(position)) => write!(f, " ({}, {})", file, position),
+            (None, Some(position)) => write!(f, " ({})", position),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// What the validator does with a second declaration of a state
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DuplicatePolicy {
+    /// Reject the system
+    #[default]
+    Error,
+    /// Keep the first declaration, in file order
+    FirstWins,
+    /// Keep the first declaration, allowing the roles of every declaration
+    MergeRoles,
+}
+
+impl DuplicatePolicy {
+    pub const ALL: [DuplicatePolicy; 3] = [DuplicatePolicy::Error, DuplicatePolicy::FirstWins, DuplicatePolicy::MergeRoles];
+
+    /// Name of the policy in configuration files
+    pub fn name(self) -> &'static str {
+        match self {
+            DuplicatePolicy::Error => "error",
+            DuplicatePolicy::FirstWins => "first-wins",
+            DuplicatePolicy::MergeRoles => "merge-roles",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|policy| policy.name() == name)
+    }
+}
+
+/// Outcomes a sequence can be classified as with `@kind`, before those added
+/// with [`SemanticValidator::kinds`]
+pub const SEQUENCE_KINDS: [&str; 4] = ["sweep", "submission", "pass", "escape"];
+
+/// Code of the warnings about deprecated syntax accepted in compatibility
+/// mode, see [`Parser::compat`](crate::parser::Parser::compat)
+pub const DEPRECATED: &str = "deprecated";
+
+/// A validated system, with the deprecations of its files and the findings
+/// of the lints the validator runs
 #[derive(Debug, Clone)]
+pub struct ValidationReport {
+    pub system: MartialSystem,
+    /// Non-fatal findings, in the order they were found
+    pub warnings: Vec<Diagnostic>,
+}
+
+/// Each of `names` with where `spans` says it is written, leaving out those
+/// without a span
+fn name_positions<'a>(names: impl Iterator<Item = &'a String>, spans: &Spans) -> Vec<(String, Position)> {
+    names.enumerate().filter_map(|(i, name)| Some((name.clone(), spans.get(i)?.position))).collect()
+}
+
+/// Context of errors about the `i`th step of a sequence, naming the
+/// template it was expanded from, if any
+fn step_context(sequence: &Sequence, i: usize, step: &SequenceStep) -> String {
+    let mut context = format!("sequence {} step {} ({})", sequence.name, i + 1, step.action_name);
+    if let Some(template) = &step.template {
+        let _ = write!(context, ", expanded from template {}", template);
+    }
+    if let (Some(i), Some(count)) = (step.repetition, step.repeat) {
+        let _ = write!(context, ", repetition {} of {}", i, count);
+    }
+    if let Some(original) = &sequence.mirror_of {
+        let _ = write!(context, ", mirrored from {}", original);
+    }
+    context
+}
+
+/// States a reference to `name` stands for: the members of a group if it
+/// names one rather than a state, as the start of a step may, else the
+/// state itself
+fn referenced_states<'a>(
+    states: &BTreeMap<String, State>,
+    groups: &'a BTreeMap<String, Vec<String>>,
+    name: &'a str,
+) -> Vec<&'a str> {
+    match groups.get(name) {
+        Some(members) if !states.contains_key(name) => members.iter().map(String::as_str).collect(),
+        _ => vec![name],
+    }
+}
+
+/// Declared names close to `name`, closest first, at most three
+fn suggestions<'a>(name: &str, candidates: impl IntoIterator<Item = &'a String>) -> Vec<String> {
+    let name = name.to_lowercase();
+    let max = (name.chars().count() / 3).max(1);
+    let mut close: Vec<(usize, &String)> = candidates
+        .into_iter()
+        .map(|candidate| (edit_distance(&name, &candidate.to_lowercase()), candidate))
+        .filter(|(distance, _)| *distance <= max)
+        .collect();
+    close.sort();
+    close.into_iter().take(3).map(|(_, candidate)| candidate.clone()).collect()
+}
+
+/// Levenshtein distance between `a` and `b`, by character
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a != *b);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// Check assertions against the graph of `system`
+fn check_assertions(system: &MartialSystem, assertions: &[(AssertDecl, Option<String>)]) -> Result<(), SemanticError> {
+    if assertions.is_empty() {
+        return Ok(());
+    }
+    let graph = MartialGraph::from_system(system);
+    for (assertion, source) in assertions {
+        let (from, to) = (assertion.from.to_string(), assertion.to.to_string());
+        let path = graph.shortest_path(&from, &to);
+        let actions: Vec<String> = path.iter().flatten().map(|edge| edge.action.to_string()).collect();
+        let message = match (assertion.kind, path) {
+            (AssertKind::Reachable, None) => format!("Assertion failed: {} cannot be reached from {}", to, from),
+            (AssertKind::NoPath, Some(path)) if path.is_empty() => {
+                format!("Assertion failed: {} and {} are the same position", from, to)
+            }
+            (AssertKind::NoPath, Some(_)) => {
+                format!("Assertion failed: {} reaches {} through {}", from, to, actions.join(", "))
+            }
+            _ => continue,
+        };
+        return Err(SemanticError::new(message, assertion.to_string())
+            .with_kind(SemanticErrorKind::AssertionFailed {
+                assertion: assertion.to_string(),
+                path: actions,
+            })
+            .at(source.as_ref(), assertion.position));
+    }
+    Ok(())
+}
+
+/// Whether `text` is an absolute http(s) URL with a host
+fn is_url(text: &str) -> bool {
+    let rest = match text.strip_prefix("https://").or_else(|| text.strip_prefix("http://")) {
+        Some(rest) => rest,
+        None => return false,
+    };
+    let host = rest.split(['/', '?', '#']).next().unwrap_or("");
+    !host.is_empty() && !text.chars().any(char::is_whitespace)
+}
+
+/// A step of an attack sequence that loses dominance
+#[derive(Debug, Clone, PartialEq)]
+pub struct DominanceDrop {
+    pub sequence: String,
+    /// 1-based step number
+    pub step: usize,
+    pub action: String,
+    /// Last rated state before the step, and its dominance
+    pub from: (String, u32),
+    pub to: (String, u32),
+}
+
+impl fmt::Display for DominanceDrop {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Attack '{}' step {} ({}) goes from {} ({}) down to {} ({})",
+            self.sequence, self.step, self.action, self.from.0, self.from.1, self.to.0, self.to.1
+        )
+    }
+}
+
+/// What kind of identifier a [`Description`] is about
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IdentifierKind {
+    State,
+    Role,
+    Sequence,
+    Group,
+    Action,
+}
+
+impl IdentifierKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            IdentifierKind::State => "state",
+            IdentifierKind::Role => "role",
+            IdentifierKind::Sequence => "sequence",
+            IdentifierKind::Group => "group",
+            IdentifierKind::Action => "action",
+        }
+    }
+}
+
+impl fmt::Display for IdentifierKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Transitions of the graph into and out of the positions of a state, role
+/// or group
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Degree {
+    pub incoming: usize,
+    pub outgoing: usize,
+}
+
+/// What an identifier is, from [`MartialSystem::describe`]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Description {
+    pub name: String,
+    pub kind: IdentifierKind,
+    /// Source file of the declaration; for an action, of its first use
+    pub file: Option<String>,
+    pub position: Option<Position>,
+    /// Roles a state allows, every role if it declares none
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub roles: Vec<String>,
+    /// Members of a group, the groups a state belongs to, or the states a
+    /// role is allowed in
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub states: Vec<String>,
+    /// Steps of a sequence, steps of an action, or steps from or to a
+    /// state, with the sequence of each
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub steps: Vec<(String, SequenceStep)>,
+    /// Unset for sequences and actions
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub degree: Option<Degree>,
+}
+
+impl fmt::Display for Description {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.kind, self.name)?;
+        match (&self.file, self.position) {
+            (Some(file), Some(position)) => writeln!(f, " ({}:{})", file, position.line)?,
+            (Some(file), None) => writeln!(f, " ({})", file)?,
+            (None, _) => writeln!(f)?,
+        }
+        if !self.roles.is_empty() {
+            writeln!(f, "  Roles: {}", self.roles.join(", "))?;
+        }
+        if !self.states.is_empty() {
+            let label = match self.kind {
+                IdentifierKind::State => "Groups",
+                _ => "States",
+            };
+            writeln!(f, "  {}: {}", label, self.states.join(", "))?;
+        }
+        if let Some(degree) = self.degree {
+            writeln!(f, "  Transitions: {} in, {} out", degree.incoming, degree.outgoing)?;
+        }
+        if !self.steps.is_empty() {
+            writeln!(f, "  Steps:")?;
+            for (sequence, step) in &self.steps {
+                writeln!(f, "    {}: {} -> {} ({})", step.action_name, step.from, step.to, sequence)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// How a [`Reference`] uses a name
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReferenceKind {
+    /// Where the name is declared
+    Declaration,
+    /// A step performing the action
+    Action,
+    /// Where a step starts
+    From,
+    /// Where a step ends
+    To,
+    /// The `onfail` outcome of a step
+    OnFail,
+    /// The opponent's side of a step
+    Reaction,
+    /// The roles a state declaration allows
+    AllowedRole,
+    /// A state listed in a group
+    Member,
+    /// An action or sequence a ruleset forbids
+    Forbidden,
+}
+
+impl ReferenceKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            ReferenceKind::Declaration => "declaration",
+            ReferenceKind::Action => "action",
+            ReferenceKind::From => "from",
+            ReferenceKind::To => "to",
+            ReferenceKind::OnFail => "onfail",
+            ReferenceKind::Reaction => "reaction",
+            ReferenceKind::AllowedRole => "allowed role",
+            ReferenceKind::Member => "member",
+            ReferenceKind::Forbidden => "forbidden",
+        }
+    }
+}
+
+/// A use of a name, from [`MartialSystem::references`]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Reference {
+    pub file: Option<String>,
+    /// Where the name is written, or the start of the step it appears in if
+    /// that step was generated, as from a template
+    pub position: Option<Position>,
+    pub kind: ReferenceKind,
+    /// The declaration the name appears in, such as `sequence Sweep`
+    pub within: String,
+}
+
+impl fmt::Display for Reference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (&self.file, self.position) {
+            (Some(file), Some(position)) => write!(f, "{}:{}:{}: ", file, position.line, position.column)?,
+            (Some(file), None) => write!(f, "{}: ", file)?,
+            (None, _) => {}
+        }
+        write!(f, "{} in {}", self.kind.name(), self.within)
+    }
+}
+
+/// A validated martial system
+#[derive(Debug, Clone, Serialize, JsonSchema)]
 pub struct MartialSystem {
     pub name: String,
-    pub roles: HashSet<String>,
-    pub states: HashMap<String, State>,
-    pub sequences: HashMap<String, Sequence>,
-    pub groups: HashMap<String, Vec<String>>,
+    pub roles: BTreeSet<String>,
+    pub states: BTreeMap<String, State>,
+    pub sequences: BTreeMap<String, Sequence>,
+    pub groups: BTreeMap<String, Vec<String>>,
+    /// Curriculum levels, lowest first
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub levels: Vec<String>,
+    /// Glossary definitions by term
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub terms: BTreeMap<String, String>,
+    /// Forbidden action and sequence names by ruleset
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub rulesets: BTreeMap<String, Vec<String>>,
+    /// Front-matter metadata by source file
+    pub metadata: BTreeMap<String, FileMetadata>,
+    /// Sequence names in declaration order, file by file; see
+    /// [`MartialSystem::sequences_ordered`]
+    #[serde(skip)]
+    pub sequence_order: Vec<String>,
+    /// Source file of each named declaration, keyed by (kind, name); see
+    /// [`MartialSystem::origin`]
+    #[serde(skip)]
+    pub origins: BTreeMap<(String, String), String>,
+    /// Position of the first declaration of each role
+    #[serde(skip)]
+    pub role_positions: BTreeMap<String, Position>,
+    /// Where the names of each group and ruleset are written, keyed by
+    /// (kind, name): its own name, then those it lists
+    #[serde(skip)]
+    pub name_positions: BTreeMap<(String, String), Vec<(String, Position)>>,
+}
+
+impl MartialSystem {
+    /// Source file of the declaration of `name`, a `state`, `sequence`,
+    /// `role` or other declaration keyword, if known
+    pub fn origin(&self, kind: &str, name: &str) -> Option<&String> {
+        self.origins.get(&(kind.to_string(), name.to_string()))
+    }
+
+    /// States a step starting at `name` may start from: the members of a
+    /// group, or the state itself
+    pub fn states_of<'a>(&'a self, name: &'a str) -> Vec<&'a str> {
+        referenced_states(&self.states, &self.groups, name)
+    }
+
+    /// Every state reference of the steps, their failure outcomes and their
+    /// reactions
+    pub fn state_refs(&self) -> impl Iterator<Item = &StateRef> {
+        self.sequences.values().flat_map(|sequence| &sequence.steps).flat_map(|step| {
+            let reaction = step.reaction.iter().flat_map(|reaction| [&reaction.from, &reaction.to]);
+            core::iter::once(&step.from).chain(step.outcomes()).chain(reaction)
+        })
+    }
+
+    /// Sequences in the order they were declared, file by file, with each
+    /// generated mirror right after its original
+    ///
+    /// Sequences missing from `sequence_order` follow in name order.
+    pub fn sequences_ordered(&self) -> Vec<&Sequence> {
+        let declared: BTreeSet<&String> = self.sequence_order.iter().collect();
+        self.sequence_order
+            .iter()
+            .filter_map(|name| self.sequences.get(name))
+            .chain(self.sequences.values().filter(|s| !declared.contains(&s.name)))
+            .collect()
+    }
+
+    /// Actions used in sequences that have no glossary entry, sorted
+    pub fn actions_without_terms(&self) -> Vec<&str> {
+        let actions: BTreeSet<&str> = self
+            .sequences
+            .values()
+            .flat_map(|s| &s.steps)
+            .map(|step| step.action_name.as_str())
+            .filter(|action| !self.terms.contains_key(*action))
+            .collect();
+        actions.into_iter().collect()
+    }
+
+    /// Steps of `@attack` sequences that go to a less dominant position
+    /// than the last rated one before them, sorted by sequence and step
+    ///
+    /// States without a `@dominance` are skipped over.
+    pub fn dominance_drops(&self) -> Vec<DominanceDrop> {
+        let mut drops = Vec::new();
+        let names: Vec<&String> = self.sequences.keys().collect();
+        for name in names {
+            let sequence = &self.sequences[name];
+            if !sequence.is_attack() {
+                continue;
+            }
+            let dominance = |state: &str| self.states.get(state).and_then(|s| s.dominance());
+            let mut last = sequence.steps.first().and_then(|step| {
+                dominance(&step.from.state).map(|d| (step.from.state.to_string(), d))
+            });
+            for (i, step) in sequence.steps.iter().enumerate() {
+                let to = match dominance(&step.to.state) {
+                    Some(d) => (step.to.state.to_string(), d),
+                    None => continue,
+                };
+                if let Some(from) = last.filter(|(_, d)| *d > to.1) {
+                    drops.push(DominanceDrop {
+                        sequence: name.clone(),
+                        step: i + 1,
+                        action: step.action_name.to_string(),
+                        from,
+                        to: to.clone(),
+                    });
+                }
+                last = Some(to);
+            }
+        }
+        drops
+    }
+
+    /// States with a `@dominance`, most dominant first
+    pub fn hierarchy(&self) -> Vec<(&str, u32)> {
+        let mut ranked: Vec<(&str, u32)> = self
+            .states
+            .iter()
+            .filter_map(|(name, state)| Some((name.as_str(), state.dominance()?)))
+            .collect();
+        ranked.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+        ranked
+    }
+
+    /// Rank of a level in the declared order, lowest first
+    pub fn level_rank(&self, level: &str) -> Option<usize> {
+        self.levels.iter().position(|l| l == level)
+    }
+
+    /// Level a sequence is taught at: its own `@level`, or else the highest
+    /// level of the states it goes through
+    pub fn sequence_level<'a>(&'a self, sequence: &'a Sequence) -> Option<&'a str> {
+        sequence.level().or_else(|| {
+            sequence
+                .steps
+                .iter()
+                .flat_map(|step| core::iter::once(&step.from).chain(step.outcomes()))
+                .filter_map(|state_ref| self.states.get(state_ref.state.as_str())?.level())
+                .max_by_key(|level| self.level_rank(level))
+        })
+    }
+
+    /// The part of the system known at `level`: states and sequences without
+    /// a level or at a level up to and including it
+    ///
+    /// Returns `None` if the level is not declared.
+    pub fn at_level(&self, level: &str) -> Option<MartialSystem> {
+        let rank = self.level_rank(level)?;
+        let known = |l: Option<&str>| l.and_then(|l| self.level_rank(l)).is_none_or(|r| r <= rank);
+
+        let states: BTreeMap<String, State> = self
+            .states
+            .iter()
+            .filter(|(_, state)| known(state.level()))
+            .map(|(name, state)| (name.clone(), state.clone()))
+            .collect();
+        let sequences = self
+            .sequences
+            .iter()
+            .filter(|(_, sequence)| known(self.sequence_level(sequence)))
+            .map(|(name, sequence)| (name.clone(), sequence.clone()))
+            .collect();
+        let groups = self
+            .groups
+            .iter()
+            .map(|(name, members)| {
+                let members: Vec<String> = members.iter().filter(|s| states.contains_key(*s)).cloned().collect();
+                (name.clone(), members)
+            })
+            .filter(|(_, members)| !members.is_empty())
+            .collect();
+
+        Some(MartialSystem {
+            name: self.name.clone(),
+            roles: self.roles.clone(),
+            states,
+            sequences,
+            groups,
+            levels: self.levels[..=rank].to_vec(),
+            terms: self.terms.clone(),
+            rulesets: self.rulesets.clone(),
+            metadata: self.metadata.clone(),
+            sequence_order: self.sequence_order.clone(),
+            origins: self.origins.clone(),
+            role_positions: self.role_positions.clone(),
+            name_positions: self.name_positions.clone(),
+        })
+    }
+
+    /// The system without what `ruleset` forbids: its sequences, and the
+    /// steps of its actions; sequences left without steps are dropped
+    ///
+    /// Returns `None` if the ruleset is not declared.
+    pub fn under_ruleset(&self, ruleset: &str) -> Option<MartialSystem> {
+        let forbidden = self.rulesets.get(ruleset)?;
+        let sequences = self
+            .sequences
+            .iter()
+            .filter(|(name, _)| !forbidden.contains(name))
+            .map(|(name, sequence)| {
+                let steps = sequence
+                    .steps
+                    .iter()
+                    .filter(|step| !forbidden.iter().any(|f| *f == step.action_name))
+                    .cloned()
+                    .collect();
+                (name.clone(), Sequence { steps, ..sequence.clone() })
+            })
+            .filter(|(_, sequence)| !sequence.steps.is_empty())
+            .collect();
+
+        Some(MartialSystem {
+            sequences,
+            ..self.clone()
+        })
+    }
+
+    /// Positions entered by some transition, but only by ones `ruleset`
+    /// forbids, as sorted `State[Role]` names
+    pub fn reached_only_through_forbidden(&self, ruleset: &str) -> Vec<String> {
+        let targets = |system: &MartialSystem| -> BTreeSet<String> {
+            system
+                .sequences
+                .values()
+                .flat_map(|s| &s.steps)
+                .map(|step| step.to.to_string())
+                .collect()
+        };
+        let legal = match self.under_ruleset(ruleset) {
+            Some(system) => targets(&system),
+            None => return Vec::new(),
+        };
+        targets(self).difference(&legal).cloned().collect()
+    }
+
+    /// Languages used by `@label` annotations, sorted
+    pub fn languages(&self) -> Vec<&str> {
+        let states = self.states.values().flat_map(|s| &s.annotations);
+        let steps = self.sequences.values().flat_map(|s| &s.steps).flat_map(|s| &s.annotations);
+        let languages: BTreeSet<&str> = states
+            .chain(steps)
+            .filter(|a| a.name == "label")
+            .flat_map(|a| a.named.keys())
+            .map(String::as_str)
+            .collect();
+        languages.into_iter().collect()
+    }
+
+    /// The system with state and action names replaced by their labels in
+    /// `lang`, for rendering; names without a label are kept
+    pub fn localized(&self, lang: &str) -> MartialSystem {
+        let state_name = |name: &str| {
+            self.states
+                .get(name)
+                .and_then(|s| s.label(lang))
+                .unwrap_or(name)
+                .to_string()
+        };
+        let state_ref = |r: &StateRef| StateRef {
+            state: state_name(&r.state).into(),
+            role: r.role.clone(),
+            extra_roles: r.extra_roles.clone(),
+            spans: r.spans.clone(),
+        };
+
+        let states = self
+            .states
+            .iter()
+            .map(|(name, state)| {
+                let name = state_name(name);
+                (name.clone(), State { name, ..state.clone() })
+            })
+            .collect();
+        let sequences = self
+            .sequences
+            .iter()
+            .map(|(name, sequence)| {
+                let steps = sequence
+                    .steps
+                    .iter()
+                    .map(|step| SequenceStep {
+                        action_name: step.label(lang).unwrap_or(&step.action_name).into(),
+                        from: state_ref(&step.from),
+                        to: state_ref(&step.to),
+                        on_fail: step.on_fail.as_ref().map(state_ref),
+                        reaction: step.reaction.as_ref().map(|r| Reaction {
+                            from: state_ref(&r.from),
+                            to: state_ref(&r.to),
+                        }),
+                        ..step.clone()
+                    })
+                    .collect();
+                (name.clone(), Sequence { steps, ..sequence.clone() })
+            })
+            .collect();
+        let groups = self
+            .groups
+            .iter()
+            .map(|(name, members)| (name.clone(), members.iter().map(|m| state_name(m)).collect()))
+            .collect();
+
+        MartialSystem {
+            states,
+            sequences,
+            groups,
+            ..self.clone()
+        }
+    }
+
+    /// What `name` is: a state, role, sequence, group or action, tried in
+    /// that order, with where it is declared and how it is used
+    pub fn describe(&self, name: &str) -> Option<Description> {
+        let steps = |keep: &dyn Fn(&SequenceStep) -> bool| -> Vec<(String, SequenceStep)> {
+            self.sequences_ordered()
+                .into_iter()
+                .flat_map(|sequence| sequence.steps.iter().map(move |step| (sequence, step)))
+                .filter(|(_, step)| keep(step))
+                .map(|(sequence, step)| (sequence.name.clone(), step.clone()))
+                .collect()
+        };
+        let degree = |is_node: &dyn Fn(&crate::graph::Node) -> bool| {
+            let graph = MartialGraph::from_system(self);
+            Degree {
+                incoming: graph.edges.iter().filter(|edge| is_node(&edge.to)).count(),
+                outgoing: graph.edges.iter().filter(|edge| is_node(&edge.from)).count(),
+            }
+        };
+        let description = |kind: IdentifierKind, position: Option<Position>| Description {
+            name: name.to_string(),
+            kind,
+            file: self.origin(kind.name(), name).cloned(),
+            position,
+            roles: Vec::new(),
+            states: Vec::new(),
+            steps: Vec::new(),
+            degree: None,
+        };
+
+        if let Some(state) = self.states.get(name) {
+            let roles = match &state.allowed_roles {
+                Some(roles) => roles.clone(),
+                None => self.roles.iter().cloned().collect(),
+            };
+            let groups = self
+                .groups
+                .iter()
+                .filter(|(_, members)| members.iter().any(|m| m == name))
+                .map(|(group, _)| group.clone())
+                .collect();
+            return Some(Description {
+                roles,
+                states: groups,
+                steps: steps(&|step| step.from.state == name || step.outcomes().any(|to| to.state == name)),
+                degree: Some(degree(&|node| node.state == name)),
+                ..description(IdentifierKind::State, Some(state.position))
+            });
+        }
+        if self.roles.contains(name) {
+            let allowed = |state: &State| state.allowed_roles.as_ref().is_none_or(|roles| roles.iter().any(|r| r == name));
+            return Some(Description {
+                states: self.states.values().filter(|state| allowed(state)).map(|state| state.name.clone()).collect(),
+                degree: Some(degree(&|node| node.role.split(", ").any(|role| role == name))),
+                ..description(IdentifierKind::Role, self.role_positions.get(name).copied())
+            });
+        }
+        if let Some(sequence) = self.sequences.get(name) {
+            return Some(Description {
+                steps: sequence.steps.iter().map(|step| (name.to_string(), step.clone())).collect(),
+                ..description(IdentifierKind::Sequence, Some(sequence.position))
+            });
+        }
+        if let Some(members) = self.groups.get(name) {
+            return Some(Description {
+                states: members.clone(),
+                degree: Some(degree(&|node| members.iter().any(|m| node.state == m.as_str()))),
+                ..description(IdentifierKind::Group, None)
+            });
+        }
+
+        // Actions are not declared on their own: they are where first used
+        let uses = steps(&|step| step.action_name == name);
+        let (sequence, first) = uses.first()?;
+        Some(Description {
+            file: self.origin("sequence", sequence).cloned(),
+            position: Some(first.position),
+            steps: uses.clone(),
+            ..description(IdentifierKind::Action, None)
+        })
+    }
+
+    /// Every use of `name` as a state, role, group, sequence or action:
+    /// declarations first, then steps in declaration order, then group
+    /// members and rulesets
+    ///
+    /// Generated mirrors are skipped, as their steps are not in any file;
+    /// steps expanded from a template are at their invocation.
+    pub fn references(&self, name: &str) -> Vec<Reference> {
+        let mut references = Vec::new();
+        let mut push = |file: Option<&String>, position: Option<Position>, kind: ReferenceKind, within: String| {
+            references.push(Reference {
+                file: file.cloned(),
+                position,
+                kind,
+                within,
+            })
+        };
+        // Where `name` is written in a group or ruleset, its own name first
+        let listed = |kind: &str, declared: &str, skip: usize| {
+            let positions = self.name_positions.get(&(kind.to_string(), declared.to_string()))?;
+            positions.iter().skip(skip).find(|(listed, _)| listed == name).map(|&(_, position)| position)
+        };
+
+        if let Some(state) = self.states.get(name) {
+            let position = state.spans.get(0).map_or(state.position, |span| span.position);
+            push(self.origin("state", name), Some(position), ReferenceKind::Declaration, format!("state {}", name));
+        }
+        if self.roles.contains(name) {
+            let position = self.role_positions.get(name).copied();
+            push(self.origin("role", name), position, ReferenceKind::Declaration, "roles".to_string());
+        }
+        if self.groups.contains_key(name) {
+            let position = listed("group", name, 0);
+            push(self.origin("group", name), position, ReferenceKind::Declaration, format!("group {}", name));
+        }
+        let sequences: Vec<&Sequence> =
+            self.sequences_ordered().into_iter().filter(|sequence| sequence.mirror_of.is_none()).collect();
+        if let Some(sequence) = sequences.iter().find(|sequence| sequence.name == name) {
+            let file = self.origin("sequence", name);
+            let position = sequence.spans.get(0).map_or(sequence.position, |span| span.position);
+            push(file, Some(position), ReferenceKind::Declaration, format!("sequence {}", name));
+        }
+        for state in self.states.values() {
+            if let Some(i) = state.allowed_roles.iter().flatten().position(|role| role == name) {
+                let file = self.origin("state", &state.name);
+                let position = state.spans.get(1 + i).map_or(state.position, |span| span.position);
+                push(file, Some(position), ReferenceKind::AllowedRole, format!("state {}", state.name));
+            }
+        }
+
+        for sequence in sequences {
+            let file = self.origin("sequence", &sequence.name);
+            for step in &sequence.steps {
+                // Steps expanded from a template are reported at the invocation
+                let at = |position: Option<Position>| match step.template {
+                    None => position.or(Some(step.position)),
+                    Some(_) => Some(step.position),
+                };
+                let within = || format!("sequence {}", sequence.name);
+                if step.action_name == name {
+                    push(file, at(step.spans.get(0).map(|span| span.position)), ReferenceKind::Action, within());
+                }
+                let uses = |state_ref: &StateRef| {
+                    let used = state_ref.state == name || state_ref.roles().any(|role| *role == name);
+                    used.then(|| at(state_ref.position_of(name)))
+                };
+                if let Some(position) = uses(&step.from) {
+                    push(file, position, ReferenceKind::From, within());
+                }
+                if let Some(position) = uses(&step.to) {
+                    push(file, position, ReferenceKind::To, within());
+                }
+                if let Some(position) = step.on_fail.as_ref().and_then(uses) {
+                    push(file, position, ReferenceKind::OnFail, within());
+                }
+                if let Some(position) = step.reaction.as_ref().and_then(|reaction| uses(&reaction.from).or_else(|| uses(&reaction.to))) {
+                    push(file, position, ReferenceKind::Reaction, within());
+                }
+            }
+        }
+
+        for (group, members) in &self.groups {
+            if members.iter().any(|member| member == name) {
+                let position = listed("group", group, 1);
+                push(self.origin("group", group), position, ReferenceKind::Member, format!("group {}", group));
+            }
+        }
+        for (ruleset, forbidden) in &self.rulesets {
+            if forbidden.iter().any(|forbidden| forbidden == name) {
+                let file = self.origin("ruleset", ruleset);
+                push(file, listed("ruleset", ruleset, 1), ReferenceKind::Forbidden, format!("ruleset {}", ruleset));
+            }
+        }
+        references
+    }
+
+    /// Export the system as JSON
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string_pretty(self)
+    }
 }
 
 /// Semantic validator
+#[derive(Default)]
 pub struct SemanticValidator {
     /// All declared roles (merged from all files)
-    roles: HashSet<String>,
+    roles: BTreeSet<String>,
     /// All declared states
-    states: HashMap<String, State>,
+    states: BTreeMap<String, State>,
     /// All declared sequences
-    sequences: HashMap<String, Sequence>,
+    sequences: BTreeMap<String, Sequence>,
+    /// Sequence names in declaration order
+    sequence_order: Vec<String>,
     /// All declared groups
-    groups: HashMap<String, Vec<String>>,
+    groups: BTreeMap<String, Vec<String>>,
+    /// Group declaration positions
+    group_positions: BTreeMap<String, Position>,
+    /// Position of the first declaration of each role
+    role_positions: BTreeMap<String, Position>,
+    /// Where the names of each group and ruleset are written
+    name_positions: BTreeMap<(String, String), Vec<(String, Position)>>,
+    /// Curriculum levels, lowest first
+    levels: Vec<String>,
+    /// Whether a levels declaration has been seen
+    levels_declared: bool,
+    /// Glossary definitions by term
+    terms: BTreeMap<String, String>,
+    /// All declared rulesets
+    rulesets: BTreeMap<String, RulesetDecl>,
+    /// All declared templates
+    templates: BTreeMap<String, TemplateDecl>,
+    /// Mirror declarations, with their source file
+    mirrors: Vec<(MirrorDecl, Option<String>)>,
+    /// Assertions, with their source file
+    assertions: Vec<(AssertDecl, Option<String>)>,
+    /// Source file of each named declaration, keyed by (kind, name)
+    origins: HashMap<(&'static str, String), String>,
+    /// Front-matter metadata by source file
+    metadata: BTreeMap<String, FileMetadata>,
+    /// Deprecated constructs accepted in compatibility mode
+    deprecations: Vec<Diagnostic>,
+    /// What to do with states declared more than once
+    duplicates: DuplicatePolicy,
+    /// Outcomes accepted by `@kind`
+    kinds: Vec<String>,
+    /// Names of the lints to run, the default ones if `None`
+    lints: Option<Vec<String>>,
+    /// Names of the lints run at [`Level::Deny`]
+    deny: Vec<String>,
 }
 
 impl SemanticValidator {
     /// Create a new validator
     pub fn new() -> Self {
         SemanticValidator {
-            roles: HashSet::new(),
-            states: HashMap::new(),
-            sequences: HashMap::new(),
-            groups: HashMap::new(),
+            roles: BTreeSet::new(),
+            states: BTreeMap::new(),
+            sequences: BTreeMap::new(),
+            sequence_order: Vec::new(),
+            groups: BTreeMap::new(),
+            group_positions: BTreeMap::new(),
+            role_positions: BTreeMap::new(),
+            name_positions: BTreeMap::new(),
+            levels: Vec::new(),
+            levels_declared: false,
+            terms: BTreeMap::new(),
+            rulesets: BTreeMap::new(),
+            templates: BTreeMap::new(),
+            mirrors: Vec::new(),
+            assertions: Vec::new(),
+            origins: HashMap::new(),
+            metadata: BTreeMap::new(),
+            deprecations: Vec::new(),
+            duplicates: DuplicatePolicy::Error,
+            kinds: SEQUENCE_KINDS.iter().map(|kind| kind.to_string()).collect(),
+            lints: None,
+            deny: Vec::new(),
+        }
+    }
+
+    /// Resolve states declared more than once with `policy` rather than
+    /// rejecting the system
+    pub fn duplicates(mut self, policy: DuplicatePolicy) -> Self {
+        self.duplicates = policy;
+        self
+    }
+
+    /// Accept `kinds` in `@kind` annotations, besides [`SEQUENCE_KINDS`]
+    pub fn kinds(mut self, kinds: impl IntoIterator<Item = String>) -> Self {
+        for kind in kinds {
+            if !self.kinds.contains(&kind) {
+                self.kinds.push(kind);
+            }
+        }
+        self
+    }
+
+    /// Run the lints named in `lints` rather than the default ones
+    pub fn lints(mut self, lints: Option<Vec<String>>) -> Self {
+        self.lints = lints;
+        self
+    }
+
+    /// Run the lints named in `deny` at [`Level::Deny`], whether or not
+    /// they are among the lints run
+    pub fn deny(mut self, deny: impl IntoIterator<Item = String>) -> Self {
+        self.deny.extend(deny);
+        self
+    }
+
+    /// Source file a declaration came from
+    fn origin(&self, kind: &'static str, name: &str) -> Option<&String> {
+        self.origins.get(&(kind, name.to_string()))
+    }
+
+    /// Name the file of the first declaration in a duplicate error, which
+    /// may come from another layer such as a shared library
+    fn first_declared(&self, mut error: SemanticError) -> SemanticError {
+        if let SemanticErrorKind::Duplicate { kind, name } = &*error.kind {
+            let kind = ["state", "sequence", "group", "term", "ruleset", "template"]
+                .into_iter()
+                .find(|k| k == kind);
+            if let Some(first) = kind.and_then(|kind| self.origin(kind, name)) {
+                error.message = format!("{} (first declared in {})", error.message, first);
+            }
         }
+        error
     }
 
     /// Add declarations from a parsed file
     pub fn add_file(&mut self, file: MartialFile) -> Result<(), SemanticError> {
+        let source = file.source;
+        for deprecation in file.deprecations {
+            self.deprecations
+                .push(Diagnostic::new(DEPRECATED, deprecation.message).at(source.as_ref(), deprecation.position));
+        }
+        if let (Some(source), Some(metadata)) = (&source, file.metadata) {
+            self.metadata.insert(source.clone(), metadata);
+        }
         for declaration in file.declarations {
-            match declaration {
-                Declaration::Roles(roles_decl) => {
-                    self.add_roles(roles_decl)?;
-                }
-                Declaration::State(state) => {
-                    self.add_state(state)?;
+            let (kind, name, position) = match &declaration {
+                Declaration::Roles(roles_decl) => ("roles", None, roles_decl.position),
+                Declaration::State(state) => ("state", Some(state.name.clone()), state.position),
+                Declaration::Sequence(sequence) => ("sequence", Some(sequence.name.clone()), sequence.position),
+                Declaration::Group(group) => ("group", Some(group.name.clone()), group.position),
+                Declaration::Levels(levels_decl) => ("levels", None, levels_decl.position),
+                Declaration::Term(term) => ("term", Some(term.name.clone()), term.position),
+                Declaration::Ruleset(ruleset) => ("ruleset", Some(ruleset.name.clone()), ruleset.position),
+                Declaration::Template(template) => ("template", Some(template.name.clone()), template.position),
+                Declaration::Mirror(mirror) => ("mirror", None, mirror.position),
+                Declaration::Assert(assertion) => ("assert", None, assertion.position),
+            };
+
+            let roles = match &declaration {
+                Declaration::Roles(roles_decl) => roles_decl.roles.clone(),
+                _ => Vec::new(),
+            };
+
+            let result = match declaration {
+                Declaration::Roles(roles_decl) => self.add_roles(roles_decl),
+                Declaration::State(state) => self.add_state(state),
+                Declaration::Sequence(sequence) => self.add_sequence(sequence),
+                Declaration::Group(group) => self.add_group(group),
+                Declaration::Levels(levels_decl) => self.add_levels(levels_decl),
+                Declaration::Term(term) => self.add_term(term),
+                Declaration::Ruleset(ruleset) => self.add_ruleset(ruleset),
+                Declaration::Template(template) => self.add_template(template),
+                Declaration::Mirror(mirror) => {
+                    self.mirrors.push((mirror, source.clone()));
+                    Ok(())
                 }
-                Declaration::Sequence(sequence) => {
-                    self.add_sequence(sequence)?;
+                Declaration::Assert(assertion) => {
+                    self.assertions.push((assertion, source.clone()));
+                    Ok(())
                 }
-                Declaration::Group(group) => {
-                    self.add_group(group)?;
+            };
+            result.map_err(|e| self.first_declared(e).at(source.as_ref(), position))?;
+
+            if let (Some(name), Some(source)) = (name, &source) {
+                self.origins.entry((kind, name)).or_insert_with(|| source.clone());
+            }
+            if let Some(source) = &source {
+                for role in roles {
+                    self.origins.entry(("role", role)).or_insert_with(|| source.clone());
                 }
             }
         }
@@ -80,30 +1260,132 @@ impl SemanticValidator {
     fn add_roles(&mut self, roles_decl: RolesDecl) -> Result<(), SemanticError> {
         for role in roles_decl.roles {
             if role.is_empty() {
-                return Err(SemanticError {
-                    message: "Role name cannot be empty".to_string(),
-                    context: "roles declaration".to_string(),
-                });
+                return Err(SemanticError::new(
+                    "Role name cannot be empty",
+                    "roles declaration",
+                ));
             }
+            self.role_positions.entry(role.clone()).or_insert(roles_decl.position);
             self.roles.insert(role);
         }
         Ok(())
     }
 
+    /// Add the levels (only one declaration is allowed, as it defines an order)
+    fn add_levels(&mut self, levels_decl: LevelsDecl) -> Result<(), SemanticError> {
+        if self.levels_declared {
+            return Err(SemanticError::new(
+                "Levels are already declared; list them all in a single declaration",
+                "levels declaration",
+            ));
+        }
+        self.levels_declared = true;
+
+        for level in levels_decl.levels {
+            if self.levels.contains(&level) {
+                return Err(SemanticError::new(
+                    format!("Level '{}' appears multiple times", level),
+                    "levels declaration",
+                ));
+            }
+            self.levels.push(level);
+        }
+        Ok(())
+    }
+
+    /// Add a glossary term
+    fn add_term(&mut self, term: TermDecl) -> Result<(), SemanticError> {
+        if self.terms.contains_key(&term.name) {
+            return Err(SemanticError::new(
+                format!("Term '{}' is already defined", term.name),
+                format!("term {}", term.name),
+            )
+            .with_kind(SemanticErrorKind::Duplicate {
+                kind: "term".to_string(),
+                name: term.name.clone(),
+            }));
+        }
+        if term.definition.trim().is_empty() {
+            return Err(SemanticError::new(
+                "Term definition cannot be empty",
+                format!("term {}", term.name),
+            ));
+        }
+        self.terms.insert(term.name, term.definition);
+        Ok(())
+    }
+
+    /// Add a ruleset
+    fn add_ruleset(&mut self, ruleset: RulesetDecl) -> Result<(), SemanticError> {
+        if self.rulesets.contains_key(&ruleset.name) {
+            return Err(SemanticError::new(
+                format!("Ruleset '{}' is already defined", ruleset.name),
+                format!("ruleset {}", ruleset.name),
+            )
+            .with_kind(SemanticErrorKind::Duplicate {
+                kind: "ruleset".to_string(),
+                name: ruleset.name.clone(),
+            }));
+        }
+        let names = core::iter::once(&ruleset.name).chain(&ruleset.forbidden);
+        let positions = name_positions(names, &ruleset.spans);
+        self.name_positions.insert(("ruleset".to_string(), ruleset.name.clone()), positions);
+        self.rulesets.insert(ruleset.name.clone(), ruleset);
+        Ok(())
+    }
+
+    /// Add a template
+    fn add_template(&mut self, template: TemplateDecl) -> Result<(), SemanticError> {
+        if self.templates.contains_key(&template.name) {
+            return Err(SemanticError::new(
+                format!("Template '{}' is already defined", template.name),
+                format!("template {}", template.name),
+            )
+            .with_kind(SemanticErrorKind::Duplicate {
+                kind: "template".to_string(),
+                name: template.name.clone(),
+            }));
+        }
+        self.templates.insert(template.name.clone(), template);
+        Ok(())
+    }
+
     /// Add a state
     fn add_state(&mut self, state: State) -> Result<(), SemanticError> {
         if state.name.is_empty() {
-            return Err(SemanticError {
-                message: "State name cannot be empty".to_string(),
-                context: "state declaration".to_string(),
-            });
+            return Err(SemanticError::new(
+                "State name cannot be empty",
+                "state declaration",
+            ));
         }
 
-        if self.states.contains_key(&state.name) {
-            return Err(SemanticError {
-                message: format!("State '{}' is already defined", state.name),
-                context: format!("state {}", state.name),
-            });
+        if let Some(first) = self.states.get_mut(&state.name) {
+            match self.duplicates {
+                DuplicatePolicy::Error => {
+                    return Err(SemanticError::new(
+                        format!("State '{}' is already defined", state.name),
+                        format!("state {}", state.name),
+                    )
+                    .with_kind(SemanticErrorKind::Duplicate {
+                        kind: "state".to_string(),
+                        name: state.name.clone(),
+                    }));
+                }
+                DuplicatePolicy::FirstWins => {}
+                // A declaration without roles allows them all
+                DuplicatePolicy::MergeRoles => match (&mut first.allowed_roles, state.allowed_roles) {
+                    (Some(roles), Some(more)) => {
+                        for role in more {
+                            if !roles.contains(&role) {
+                                roles.push(role);
+                            }
+                        }
+                    }
+                    (roles, None) => *roles = None,
+                    (None, Some(_)) => {}
+                },
+            }
+            return Ok(());
         }
 
         self.states.insert(state.name.clone(), state);
@@ -113,19 +1395,24 @@ impl SemanticValidator {
     /// Add a sequence
     fn add_sequence(&mut self, sequence: Sequence) -> Result<(), SemanticError> {
         if sequence.name.is_empty() {
-            return Err(SemanticError {
-                message: "Sequence name cannot be empty".to_string(),
-                context: "sequence declaration".to_string(),
-            });
+            return Err(SemanticError::new(
+                "Sequence name cannot be empty",
+                "sequence declaration",
+            ));
         }
 
         if self.sequences.contains_key(&sequence.name) {
-            return Err(SemanticError {
-                message: format!("Sequence '{}' is already defined", sequence.name),
-                context: format!("sequence {}", sequence.name),
-            });
+            return Err(SemanticError::new(
+                format!("Sequence '{}' is already defined", sequence.name),
+                format!("sequence {}", sequence.name),
+            )
+            .with_kind(SemanticErrorKind::Duplicate {
+                kind: "sequence".to_string(),
+                name: sequence.name.clone(),
+            }));
         }
 
+        self.sequence_order.push(sequence.name.clone());
         self.sequences.insert(sequence.name.clone(), sequence);
         Ok(())
     }
@@ -133,49 +1420,204 @@ impl SemanticValidator {
     /// Add a group
     fn add_group(&mut self, group: GroupDecl) -> Result<(), SemanticError> {
         if group.name.is_empty() {
-            return Err(SemanticError {
-                message: "Group name cannot be empty".to_string(),
-                context: "group declaration".to_string(),
-            });
+            return Err(SemanticError::new(
+                "Group name cannot be empty",
+                "group declaration",
+            ));
         }
 
         if self.groups.contains_key(&group.name) {
-            return Err(SemanticError {
-                message: format!("Group '{}' is already defined", group.name),
-                context: format!("group {}", group.name),
-            });
+            return Err(SemanticError::new(
+                format!("Group '{}' is already defined", group.name),
+                format!("group {}", group.name),
+            )
+            .with_kind(SemanticErrorKind::Duplicate {
+                kind: "group".to_string(),
+                name: group.name.clone(),
+            }));
         }
 
+        self.group_positions.insert(group.name.clone(), group.position);
+        let positions = name_positions(core::iter::once(&group.name).chain(&group.states), &group.spans);
+        self.name_positions.insert(("group".to_string(), group.name.clone()), positions);
         self.groups.insert(group.name, group.states);
         Ok(())
     }
 
     /// Validate the entire system
-    pub fn validate(self, system_name: String) -> Result<MartialSystem, SemanticError> {
+    pub fn validate(mut self, system_name: String) -> Result<ValidationReport, SemanticError> {
         // Check that we have at least one role
         if self.roles.is_empty() {
-            return Err(SemanticError {
-                message: "No roles defined. At least one role declaration is required.".to_string(),
-                context: system_name,
-            });
+            return Err(SemanticError::new(
+                "No roles defined. At least one role declaration is required.",
+                system_name,
+            ));
         }
 
+        // Expand templates before anything looks at the steps
+        self.expand_templates()?;
+        let mirror = self.validate_mirrors()?;
+        self.expand_mirrors(&mirror)?;
+
         // Validate states
         self.validate_states()?;
 
+        // Validate groups, before the sequences whose steps may start from them
+        self.validate_groups()?;
+
         // Validate sequences
         self.validate_sequences()?;
+        self.validate_arity()?;
 
-        // Validate groups
-        self.validate_groups()?;
+        // Validate annotations and levels
+        self.validate_annotations()?;
+        self.validate_levels()?;
+
+        // Validate rulesets
+        self.validate_rulesets()?;
+
+        // Assertions are checked on the graph of the validated system
+        let assertions = core::mem::take(&mut self.assertions);
+        for (assertion, source) in &assertions {
+            let context = assertion.to_string();
+            self.validate_state_ref(&assertion.from, &context)
+                .and_then(|_| self.validate_state_ref(&assertion.to, &context))
+                .map_err(|e| e.at(source.as_ref(), assertion.position))?;
+        }
 
-        Ok(MartialSystem {
+        let system = MartialSystem {
             name: system_name,
             roles: self.roles,
             states: self.states,
             sequences: self.sequences,
             groups: self.groups,
-        })
+            levels: self.levels,
+            terms: self.terms,
+            rulesets: self
+                .rulesets
+                .into_iter()
+                .map(|(name, ruleset)| (name, ruleset.forbidden))
+                .collect(),
+            metadata: self.metadata,
+            sequence_order: self.sequence_order,
+            origins: self
+                .origins
+                .into_iter()
+                .map(|((kind, name), file)| ((kind.to_string(), name), file))
+                .collect(),
+            role_positions: self.role_positions,
+            name_positions: self.name_positions,
+        };
+        check_assertions(&system, &assertions)?;
+        let mut registry = LintRegistry::builtin();
+        for name in &self.deny {
+            registry.set_level(name, Level::Deny);
+        }
+        let enabled = self.lints.map(|mut lints| {
+            for name in &self.deny {
+                if !lints.contains(name) {
+                    lints.push(name.clone());
+                }
+            }
+            lints
+        });
+        let mut warnings = self.deprecations;
+        warnings.extend(registry.check(&system, enabled.as_deref()));
+        Ok(ValidationReport { system, warnings })
+    }
+
+    /// Replace template invocations by the steps they expand to
+    fn expand_templates(&mut self) -> Result<(), SemanticError> {
+        let seq_names: Vec<String> = self.sequences.keys().cloned().collect();
+        for seq_name in seq_names {
+            let Some(sequence) = self.sequences.get_mut(&seq_name) else {
+                continue;
+            };
+            if let Err((position, message)) = sequence.expand(&self.templates) {
+                return Err(SemanticError::new(message, format!("sequence {}", seq_name))
+                    .at(self.origin("sequence", &seq_name), position));
+            }
+        }
+        Ok(())
+    }
+
+    /// Combine the mirror declarations, checking that each role and
+    /// suffix has a single mirror
+    fn validate_mirrors(&self) -> Result<Mirror, SemanticError> {
+        let mut mirror = Mirror::default();
+        for (declaration, source) in &self.mirrors {
+            let error = |message: String| {
+                SemanticError::new(message, "mirror declaration").at(source.as_ref(), declaration.position)
+            };
+            for (left, right) in &declaration.pairs {
+                if left == right {
+                    return Err(error(format!("'{}' cannot be its own mirror", left)));
+                }
+                match declaration.kind {
+                    MirrorKind::Roles => {
+                        for role in [left, right] {
+                            if !self.roles.contains(role.as_str()) {
+                                return Err(error(format!("Role '{}' is not defined", role)).with_kind(
+                                    SemanticErrorKind::UndefinedRole {
+                                        name: role.clone(),
+                                        suggestions: suggestions(role, &self.roles),
+                                    },
+                                ));
+                            }
+                            if let Some(other) = mirror.roles.get(role) {
+                                return Err(error(format!("Role '{}' is already mirrored by '{}'", role, other)));
+                            }
+                        }
+                        mirror.roles.insert(left.clone(), right.clone());
+                        mirror.roles.insert(right.clone(), left.clone());
+                    }
+                    MirrorKind::Suffixes => {
+                        let suffixes = mirror.suffixes.iter().flat_map(|(l, r)| [l, r]);
+                        if let Some(suffix) = suffixes.into_iter().find(|s| *s == left || *s == right) {
+                            return Err(error(format!("Suffix '{}' is already mirrored", suffix)));
+                        }
+                        mirror.suffixes.push((left.clone(), right.clone()));
+                    }
+                }
+            }
+        }
+        Ok(mirror)
+    }
+
+    /// Add the generated mirror of every sequence marked `mirrored`
+    fn expand_mirrors(&mut self, mirror: &Mirror) -> Result<(), SemanticError> {
+        let seq_names: Vec<String> = self.sequences.iter().filter(|(_, s)| s.mirrored).map(|(n, _)| n.clone()).collect();
+        for seq_name in seq_names {
+            let sequence = &self.sequences[&seq_name];
+            let origin = self.origin("sequence", &seq_name).cloned();
+            let error = |message: String| {
+                SemanticError::new(message, format!("sequence {}", seq_name)).at(origin.as_ref(), sequence.position)
+            };
+            if mirror.roles.is_empty() && mirror.suffixes.is_empty() {
+                return Err(error(format!(
+                    "Sequence '{}' is mirrored, but no mirror roles or suffixes are declared",
+                    seq_name
+                )));
+            }
+            let mirrored = mirror.sequence(sequence);
+            if self.sequences.contains_key(&mirrored.name) {
+                return Err(error(format!(
+                    "The mirror of sequence '{}' would be named '{}', which is already defined",
+                    seq_name, mirrored.name
+                ))
+                .with_kind(SemanticErrorKind::Duplicate {
+                    kind: "sequence".to_string(),
+                    name: mirrored.name.clone(),
+                }));
+            }
+            if let Some(origin) = origin {
+                self.origins.insert(("sequence", mirrored.name.clone()), origin);
+            }
+            let after = self.sequence_order.iter().position(|name| *name == seq_name);
+            self.sequence_order.insert(after.map_or(self.sequence_order.len(), |i| i + 1), mirrored.name.clone());
+            self.sequences.insert(mirrored.name.clone(), mirrored);
+        }
+        Ok(())
     }
 
     /// Validate all states
@@ -184,26 +1626,207 @@ impl SemanticValidator {
             if let Some(allowed_roles) = &state.allowed_roles {
                 // Check that all allowed roles exist
                 for role in allowed_roles {
-                    if !self.roles.contains(role) {
-                        return Err(SemanticError {
-                            message: format!(
+                    if !self.roles.contains(role.as_str()) {
+                        return Err(SemanticError::new(
+                            format!(
                                 "Role '{}' is not defined. Available roles: {}",
                                 role,
                                 self.roles.iter().cloned().collect::<Vec<_>>().join(", ")
                             ),
-                            context: format!("state {}", state_name),
-                        });
+                            format!("state {}", state_name),
+                        )
+                        .with_kind(SemanticErrorKind::UndefinedRole {
+                            name: role.clone(),
+                            suggestions: suggestions(role, &self.roles),
+                        })
+                        .at(self.origin("state", state_name), state.position));
+                    }
+                }
+
+                // Check for duplicate roles
+                let mut seen = HashSet::new();
+                for role in allowed_roles {
+                    if !seen.insert(role) {
+                        return Err(SemanticError::new(
+                            format!("Role '{}' appears multiple times", role),
+                            format!("state {}", state_name),
+                        )
+                        .at(self.origin("state", state_name), state.position));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Validate all groups
+    fn validate_groups(&self) -> Result<(), SemanticError> {
+        for (group_name, states) in &self.groups {
+            let position = self.group_positions.get(group_name).copied().unwrap_or_default();
+            let origin = self.origin("group", group_name);
+
+            if states.is_empty() {
+                return Err(SemanticError::new(
+                    "Group must contain at least one state",
+                    format!("group {}", group_name),
+                )
+                .at(origin, position));
+            }
+
+            for state_name in states {
+                if !self.states.contains_key(state_name) {
+                    return Err(SemanticError::new(
+                        format!(
+                            "State '{}' is not defined. Available states: {}",
+                            state_name,
+                            self.states.keys().cloned().collect::<Vec<_>>().join(", ")
+                        ),
+                        format!("group {}", group_name),
+                    )
+                    .with_kind(SemanticErrorKind::UndefinedState {
+                        name: state_name.clone(),
+                        suggestions: suggestions(state_name, self.states.keys()),
+                    })
+                    .at(origin, position));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Validate all sequences
+    fn validate_sequences(&self) -> Result<(), SemanticError> {
+        for (seq_name, sequence) in &self.sequences {
+            let origin = self.origin("sequence", seq_name);
+
+            if sequence.steps.is_empty() {
+                return Err(SemanticError::new(
+                    "Sequence must have at least one step",
+                    format!("sequence {}", seq_name),
+                )
+                .at(origin, sequence.position));
+            }
+
+            if let Some((first, second)) = &sequence.alternating {
+                let context = format!("sequence {}", seq_name);
+                for role in [first, second] {
+                    if !self.roles.contains(role) {
+                        return Err(SemanticError::new(format!("Role '{}' is not defined", role), context)
+                            .with_kind(SemanticErrorKind::UndefinedRole {
+                                name: role.clone(),
+                                suggestions: suggestions(role, &self.roles),
+                            })
+                            .at(origin, sequence.position));
+                    }
+                }
+                if first == second {
+                    return Err(SemanticError::new(
+                        format!("Alternating roles must differ, got '{}' twice", first),
+                        context,
+                    )
+                    .at(origin, sequence.position));
+                }
+            }
+
+            // Validate each step
+            for (i, step) in sequence.steps.iter().enumerate() {
+                let step_context = step_context(sequence, i, step);
+
+                // In an alternating sequence, each step starts in the role
+                // whose turn it is
+                if let Some(turn) = sequence.turn(i) {
+                    if step.from.role != turn {
+                        return Err(SemanticError::new(
+                            format!("It is {}'s turn, but the step starts in role {}", turn, step.from.role),
+                            step_context,
+                        )
+                        .at(origin, step.position));
+                    }
+                }
+
+                // Validate 'from' state reference, for each state of a group
+                for state in referenced_states(&self.states, &self.groups, &step.from.state) {
+                    let from = StateRef { state: state.into(), ..step.from.clone() };
+                    self.validate_state_ref(&from, &step_context)
+                        .map_err(|e| e.at(origin, step.position))?;
+                }
+
+                // Validate 'to' state reference, and the failure outcome
+                for outcome in step.outcomes() {
+                    self.validate_state_ref(outcome, &step_context)
+                        .map_err(|e| e.at(origin, step.position))?;
+                }
+
+                // Validate the opponent's transition
+                if let Some(reaction) = &step.reaction {
+                    let context = format!("{} opponent", step_context);
+                    self.validate_state_ref(&reaction.from, &context)
+                        .and_then(|_| self.validate_state_ref(&reaction.to, &context))
+                        .map_err(|e| e.at(origin, step.position))?;
+                }
+
+                // Validate chain connectivity (step N's 'to', or its failure
+                // outcome, must equal step N+1's 'from', or be one of its
+                // states if it is a group); partners taking turns each
+                // continue from their own previous step
+                let stride = if sequence.alternating.is_some() { 2 } else { 1 };
+                if i >= stride {
+                    let prev_step = &sequence.steps[i - stride];
+                    let starts = referenced_states(&self.states, &self.groups, &step.from.state);
+                    let continues = |end: &StateRef| {
+                        starts.contains(&end.state.as_str()) && end.roles().eq(step.from.roles())
+                    };
+                    if !prev_step.outcomes().any(continues) {
+                        let ends = match &prev_step.on_fail {
+                            Some(on_fail) => format!("{} (or {} on failure)", prev_step.to, on_fail),
+                            None => prev_step.to.to_string(),
+                        };
+                        let previous = match sequence.turn(i) {
+                            Some(turn) => format!("{}'s previous step", turn),
+                            None => "previous step".to_string(),
+                        };
+                        let error = SemanticError::new(
+                            format!(
+                                "Step chain is broken: {} ends at {}, but this step starts at {}",
+                                previous, ends, step.from
+                            ),
+                            step_context,
+                        )
+                        .with_kind(SemanticErrorKind::BrokenChain {
+                            sequence: seq_name.clone(),
+                            step_index: i,
+                            expected: prev_step.to.to_string(),
+                            found: step.from.to_string(),
+                            opponent: false,
+                        })
+                        .at(origin, step.position);
+                        // Expanded and mirrored steps have no source of their own to edit
+                        if step.template.is_some() || prev_step.template.is_some() || sequence.mirror_of.is_some() {
+                            return Err(error);
+                        }
+                        let repairs = self.chain_repairs(&prev_step.to, step);
+                        return Err(repairs.into_iter().fold(error, SemanticError::with_suggestion));
                     }
-                }
 
-                // Check for duplicate roles
-                let mut seen = HashSet::new();
-                for role in allowed_roles {
-                    if !seen.insert(role) {
-                        return Err(SemanticError {
-                            message: format!("Role '{}' appears multiple times", role),
-                            context: format!("state {}", state_name),
-                        });
+                    // Consecutive opponent transitions chain the same way
+                    if let (Some(prev), Some(reaction)) = (&prev_step.reaction, &step.reaction) {
+                        if prev.to != reaction.from {
+                            return Err(SemanticError::new(
+                                format!(
+                                    "Opponent chain is broken: previous step leaves the opponent at {}, but this step starts them at {}",
+                                    prev.to, reaction.from
+                                ),
+                                step_context,
+                            )
+                            .with_kind(SemanticErrorKind::BrokenChain {
+                                sequence: seq_name.clone(),
+                                step_index: i,
+                                expected: prev.to.to_string(),
+                                found: reaction.from.to_string(),
+                                opponent: true,
+                            })
+                            .at(origin, step.position));
+                        }
                     }
                 }
             }
@@ -211,66 +1834,317 @@ impl SemanticValidator {
         Ok(())
     }
 
-    /// Validate all groups
-    fn validate_groups(&self) -> Result<(), SemanticError> {
-        for (group_name, states) in &self.groups {
-            if states.is_empty() {
-                return Err(SemanticError {
-                    message: "Group must contain at least one state".to_string(),
-                    context: format!("group {}", group_name),
-                });
+    /// Corrections of a `step` that should start at `expected`: inserting a
+    /// step of another sequence going from `expected` to its start, then
+    /// starting it at `expected` instead
+    fn chain_repairs(&self, expected: &StateRef, step: &SequenceStep) -> Vec<Suggestion> {
+        let mut repairs: Vec<Suggestion> = Vec::new();
+        for (name, sequence) in &self.sequences {
+            for other in &sequence.steps {
+                if other.from != *expected || other.to != step.from {
+                    continue;
+                }
+                let edits = vec![Edit::insert(format!("{}: {} -> {}", other.action_name, other.from, other.to))];
+                if repairs.iter().all(|repair| repair.edits != edits) {
+                    repairs.push(Suggestion {
+                        message: format!("Insert step '{}' from sequence {}", other.action_name, name),
+                        edits,
+                    });
+                }
             }
+        }
+        repairs.truncate(3);
 
-            for state_name in states {
-                if !self.states.contains_key(state_name) {
-                    return Err(SemanticError {
-                        message: format!(
+        // Replace the names that differ, unless a role to replace also fills
+        // an earlier slot, which the edit could not tell apart
+        let found: Vec<_> = step.from.roles().collect();
+        let wanted: Vec<_> = expected.roles().collect();
+        let ambiguous = found.iter().enumerate().any(|(i, role)| wanted.get(i) != Some(role) && found[..i].contains(role));
+        if found.len() == wanted.len() && !ambiguous {
+            let mut edits = Vec::new();
+            if step.from.state != expected.state {
+                edits.push(Edit::replace(step.from.state.to_string(), expected.state.to_string()));
+            }
+            for (role, replacement) in found.into_iter().zip(wanted) {
+                if role != replacement {
+                    edits.push(Edit::replace(role.to_string(), replacement.to_string()));
+                }
+            }
+            repairs.push(Suggestion {
+                message: format!("Start the step at {}", expected),
+                edits,
+            });
+        }
+        repairs
+    }
+
+    /// Validate that every reference to a state has the same number of role
+    /// slots, one per participant
+    fn validate_arity(&self) -> Result<(), SemanticError> {
+        let mut arities: HashMap<&str, usize> = HashMap::new();
+        let seq_names: Vec<&String> = self.sequences.keys().collect();
+
+        for seq_name in seq_names {
+            let sequence = &self.sequences[seq_name];
+            for (i, step) in sequence.steps.iter().enumerate() {
+                let reaction_refs = step.reaction.iter().flat_map(|r| [&r.from, &r.to]);
+                for state_ref in core::iter::once(&step.from).chain(step.outcomes()).chain(reaction_refs) {
+                    for state in referenced_states(&self.states, &self.groups, &state_ref.state) {
+                        let arity = *arities.entry(state).or_insert(state_ref.arity());
+                        if arity != state_ref.arity() {
+                            return Err(SemanticError::new(
+                                format!(
+                                    "State '{}' has {} role slot(s) here but {} in earlier references",
+                                    state,
+                                    state_ref.arity(),
+                                    arity
+                                ),
+                                step_context(sequence, i, step),
+                            )
+                            .with_kind(SemanticErrorKind::ArityMismatch {
+                                state: state.to_string(),
+                                expected: arity,
+                                found: state_ref.arity(),
+                            })
+                            .at(self.origin("sequence", seq_name), step.position));
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Validate annotations on a state, sequence or step
+    fn validate_annotation(&self, annotation: &Annotation, target: &str, context: &str) -> Result<(), SemanticError> {
+        if !annotation.named.is_empty() && annotation.name != "label" {
+            return Err(SemanticError::new(
+                format!("Annotation '@{}' does not take named arguments", annotation.name),
+                context.to_string(),
+            ));
+        }
+        let no_args = || {
+            if annotation.args.is_empty() {
+                Ok(())
+            } else {
+                Err(SemanticError::new(
+                    format!("Annotation '@{}' takes no arguments", annotation.name),
+                    context.to_string(),
+                ))
+            }
+        };
+        match annotation.name.as_str() {
+            "level" if target != "step" => {
+                let level = match annotation.args.as_slice() {
+                    [level] => level,
+                    _ => {
+                        return Err(SemanticError::new(
+                            "Annotation '@level' takes exactly one level",
+                            context.to_string(),
+                        ))
+                    }
+                };
+                if !self.levels.contains(level) {
+                    return Err(SemanticError::new(
+                        format!(
+                            "Level '{}' is not defined. Available levels: {}",
+                            level,
+                            self.levels.join(", ")
+                        ),
+                        context.to_string(),
+                    )
+                    .with_kind(SemanticErrorKind::UndefinedLevel { name: level.clone() }));
+                }
+                Ok(())
+            }
+            "kind" if target == "sequence" => match annotation.args.as_slice() {
+                [kind] if self.kinds.contains(kind) => Ok(()),
+                [kind] => Err(SemanticError::new(
+                    format!("Kind '{}' is not defined. Available kinds: {}", kind, self.kinds.join(", ")),
+                    context.to_string(),
+                )),
+                _ => Err(SemanticError::new("Annotation '@kind' takes exactly one kind", context.to_string())),
+            },
+            "duration" if target == "sequence" => match annotation.args.as_slice() {
+                [minutes] if minutes.parse::<u32>().is_ok_and(|m| m > 0) => Ok(()),
+                _ => Err(SemanticError::new(
+                    "Annotation '@duration' takes a positive number of minutes",
+                    context.to_string(),
+                )),
+            },
+            "video" if target != "state" => match annotation.args.as_slice() {
+                [url] if is_url(url) => Ok(()),
+                [url] => Err(SemanticError::new(
+                    format!("'{}' is not a valid http(s) URL", url),
+                    context.to_string(),
+                )),
+                _ => Err(SemanticError::new(
+                    "Annotation '@video' takes exactly one URL",
+                    context.to_string(),
+                )),
+            },
+            "dominance" if target == "state" => match annotation.args.as_slice() {
+                [score] if score.parse::<u32>().is_ok() => Ok(()),
+                _ => Err(SemanticError::new(
+                    "Annotation '@dominance' takes a non-negative whole number",
+                    context.to_string(),
+                )),
+            },
+            "weight" if target == "step" => match annotation.args.as_slice() {
+                [weight] if weight.parse::<u32>().is_ok() => Ok(()),
+                _ => Err(SemanticError::new(
+                    "Annotation '@weight' takes a non-negative whole number",
+                    context.to_string(),
+                )),
+            },
+            "turn" if target == "step" => match annotation.args.as_slice() {
+                [turn] if parse_turn(turn).is_some() => Ok(()),
+                _ => Err(SemanticError::new(
+                    "Annotation '@turn' takes an angle in multiples of 45 degrees and a side, as in @turn(90L)",
+                    context.to_string(),
+                )),
+            },
+            "advance" if target == "step" => match annotation.args.as_slice() {
+                [] => Ok(()),
+                [steps] if steps.parse::<u32>().is_ok_and(|n| n > 0) => Ok(()),
+                _ => Err(SemanticError::new(
+                    "Annotation '@advance' takes a positive number of steps, or none for one",
+                    context.to_string(),
+                )),
+            },
+            "tag" => {
+                if annotation.args.is_empty() {
+                    Err(SemanticError::new("Annotation '@tag' takes at least one tag", context.to_string()))
+                } else {
+                    Ok(())
+                }
+            }
+            "requires" if target == "state" => {
+                if annotation.args.is_empty() {
+                    return Err(SemanticError::new(
+                        "Annotation '@requires' takes at least one state",
+                        context.to_string(),
+                    ));
+                }
+                match annotation.args.iter().find(|state| !self.states.contains_key(*state)) {
+                    Some(state) => Err(SemanticError::new(
+                        format!(
                             "State '{}' is not defined. Available states: {}",
-                            state_name,
+                            state,
                             self.states.keys().cloned().collect::<Vec<_>>().join(", ")
                         ),
-                        context: format!("group {}", group_name),
-                    });
+                        context.to_string(),
+                    )
+                    .with_kind(SemanticErrorKind::UndefinedState {
+                        name: state.clone(),
+                        suggestions: suggestions(state, self.states.keys()),
+                    })),
+                    None => Ok(()),
                 }
             }
+            "attack" if target == "sequence" => no_args(),
+            "final" | "core" if target == "state" => no_args(),
+            "entry" if target == "step" => no_args(),
+            "label" if target != "sequence" => {
+                if !annotation.args.is_empty() || annotation.named.is_empty() {
+                    return Err(SemanticError::new(
+                        "Annotation '@label' takes display names by language, as in @label(en: \"Mount\")",
+                        context.to_string(),
+                    ));
+                }
+                match annotation.named.iter().find(|(_, label)| label.trim().is_empty()) {
+                    Some((lang, _)) => Err(SemanticError::new(
+                        format!("Label for language '{}' cannot be empty", lang),
+                        context.to_string(),
+                    )),
+                    None => Ok(()),
+                }
+            }
+            name => Err(SemanticError::new(
+                format!("Annotation '@{}' is not allowed on a {}", name, target),
+                context.to_string(),
+            )),
+        }
+    }
+
+    /// Validate that the labels of a state name no other state in their
+    /// language, which would merge the two in [`MartialSystem::localized`]
+    fn validate_state_label(&self, annotation: &Annotation, state_name: &str, context: &str) -> Result<(), SemanticError> {
+        if annotation.name != "label" {
+            return Ok(());
+        }
+        for (lang, label) in &annotation.named {
+            let other = self
+                .states
+                .iter()
+                .find(|(name, state)| *name != state_name && state.label(lang).unwrap_or(name) == label);
+            if let Some((other, _)) = other {
+                return Err(SemanticError::new(
+                    format!("Label '{}' for language '{}' is already the name of state {} in that language", label, lang, other),
+                    context.to_string(),
+                ));
+            }
         }
         Ok(())
     }
 
-    /// Validate all sequences
-    fn validate_sequences(&self) -> Result<(), SemanticError> {
+    /// Validate all annotations
+    fn validate_annotations(&self) -> Result<(), SemanticError> {
+        for (state_name, state) in &self.states {
+            let context = format!("state {}", state_name);
+            for annotation in &state.annotations {
+                self.validate_annotation(annotation, "state", &context)
+                    .and_then(|()| self.validate_state_label(annotation, state_name, &context))
+                    .map_err(|e| e.at(self.origin("state", state_name), annotation.position))?;
+            }
+        }
         for (seq_name, sequence) in &self.sequences {
-            if sequence.steps.is_empty() {
-                return Err(SemanticError {
-                    message: "Sequence must have at least one step".to_string(),
-                    context: format!("sequence {}", seq_name),
-                });
+            let origin = self.origin("sequence", seq_name);
+            let context = format!("sequence {}", seq_name);
+            for annotation in &sequence.annotations {
+                self.validate_annotation(annotation, "sequence", &context)
+                    .map_err(|e| e.at(origin, annotation.position))?;
+            }
+            for (i, step) in sequence.steps.iter().enumerate() {
+                let context = step_context(sequence, i, step);
+                for annotation in &step.annotations {
+                    self.validate_annotation(annotation, "step", &context)
+                        .map_err(|e| e.at(origin, annotation.position))?;
+                }
             }
+        }
+        Ok(())
+    }
 
-            // Validate each step
+    /// Validate that sequences only go through states introduced at or
+    /// below their own level
+    fn validate_levels(&self) -> Result<(), SemanticError> {
+        let rank = |level: &str| self.levels.iter().position(|l| l == level);
+
+        for (seq_name, sequence) in &self.sequences {
+            let level = match sequence.level() {
+                Some(level) => level,
+                None => continue,
+            };
             for (i, step) in sequence.steps.iter().enumerate() {
-                let step_context = format!("sequence {} step {} ({})", seq_name, i + 1, step.action_name);
-
-                // Validate 'from' state reference
-                self.validate_state_ref(&step.from, &step_context)?;
-
-                // Validate 'to' state reference
-                self.validate_state_ref(&step.to, &step_context)?;
-
-                // Validate chain connectivity (step N's 'to' must equal step N+1's 'from')
-                if i > 0 {
-                    let prev_step = &sequence.steps[i - 1];
-                    if prev_step.to.state != step.from.state || prev_step.to.role != step.from.role {
-                        return Err(SemanticError {
-                            message: format!(
-                                "Step chain is broken: previous step ends at {}[{}], but this step starts at {}[{}]",
-                                prev_step.to.state,
-                                prev_step.to.role,
-                                step.from.state,
-                                step.from.role
+                let reaction_states = step.reaction.iter().flat_map(|r| [&r.from.state, &r.to.state]);
+                let starts = referenced_states(&self.states, &self.groups, &step.from.state);
+                let ends = step.outcomes().map(|end| end.state.as_str());
+                let names = ends.chain(reaction_states.map(|s| s.as_str()));
+                for state_name in starts.into_iter().chain(names) {
+                    let state_level = match self.states.get(state_name).and_then(|s| s.level()) {
+                        Some(state_level) => state_level,
+                        None => continue,
+                    };
+                    if rank(state_level) > rank(level) {
+                        return Err(SemanticError::new(
+                            format!(
+                                "State '{}' is introduced at level '{}', above the sequence level '{}'",
+                                state_name, state_level, level
                             ),
-                            context: step_context,
-                        });
+                            step_context(sequence, i, step),
+                        )
+                        .at(self.origin("sequence", seq_name), step.position));
                     }
                 }
             }
@@ -278,45 +2152,106 @@ impl SemanticValidator {
         Ok(())
     }
 
+    /// Validate that rulesets only forbid declared actions and sequences
+    fn validate_rulesets(&self) -> Result<(), SemanticError> {
+        for (name, ruleset) in &self.rulesets {
+            for forbidden in &ruleset.forbidden {
+                let is_action = self
+                    .sequences
+                    .values()
+                    .flat_map(|s| &s.steps)
+                    .any(|step| step.action_name == *forbidden);
+                if !is_action && !self.sequences.contains_key(forbidden) {
+                    return Err(SemanticError::new(
+                        format!("'{}' is neither an action nor a sequence", forbidden),
+                        format!("ruleset {}", name),
+                    )
+                    .at(self.origin("ruleset", name), ruleset.position));
+                }
+            }
+        }
+        Ok(())
+    }
+
     /// Validate a state reference
     fn validate_state_ref(&self, state_ref: &StateRef, context: &str) -> Result<(), SemanticError> {
+        if self.groups.contains_key(state_ref.state.as_str()) && !self.states.contains_key(state_ref.state.as_str()) {
+            return Err(SemanticError::new(
+                format!("'{}' is a group; only the start of a step can be a group", state_ref.state),
+                context.to_string(),
+            ));
+        }
+
         // Check that state exists
-        let state = self.states.get(&state_ref.state).ok_or_else(|| SemanticError {
-            message: format!(
+        let state = self.states.get(state_ref.state.as_str()).ok_or_else(|| SemanticError::new(
+            format!(
                 "State '{}' is not defined. Available states: {}",
                 state_ref.state,
                 self.states.keys().cloned().collect::<Vec<_>>().join(", ")
             ),
-            context: context.to_string(),
-        })?;
-
-        // Check that role exists
-        if !self.roles.contains(&state_ref.role) {
-            return Err(SemanticError {
-                message: format!(
-                    "Role '{}' is not defined. Available roles: {}",
-                    state_ref.role,
-                    self.roles.iter().cloned().collect::<Vec<_>>().join(", ")
-                ),
-                context: context.to_string(),
-            });
-        }
+            context.to_string(),
+        )
+        .with_kind(SemanticErrorKind::UndefinedState {
+            name: state_ref.state.to_string(),
+            suggestions: suggestions(&state_ref.state, self.states.keys()),
+        }))?;
 
-        // Check that role is allowed for this state
-        if let Some(allowed_roles) = &state.allowed_roles {
-            if !allowed_roles.contains(&state_ref.role) {
-                return Err(SemanticError {
-                    message: format!(
-                        "Role '{}' is not allowed for state '{}'. Allowed roles: {}",
-                        state_ref.role,
-                        state_ref.state,
-                        allowed_roles.join(", ")
+        for role in state_ref.roles() {
+            // Check that role exists
+            if !self.roles.contains(role.as_str()) {
+                let mut error = SemanticError::new(
+                    format!(
+                        "Role '{}' is not defined. Available roles: {}",
+                        role,
+                        self.roles.iter().cloned().collect::<Vec<_>>().join(", ")
                     ),
-                    context: context.to_string(),
+                    context.to_string(),
+                )
+                .with_kind(SemanticErrorKind::UndefinedRole {
+                    name: role.to_string(),
+                    suggestions: suggestions(role, &self.roles),
                 });
+                // Declare it after the role declared last
+                let last = self.role_positions.iter().max_by_key(|(_, p)| (p.line, p.column));
+                if let Some((last, &position)) = last.filter(|_| error.suggestions.is_empty()) {
+                    error = error.with_suggestion(Suggestion {
+                        message: format!("Declare role '{}'", role),
+                        edits: vec![Edit::replace(last.clone(), format!("{}, {}", last, role))
+                            .at(self.origin("role", last), position)],
+                    });
+                }
+                return Err(error);
+            }
+
+            // Check that role is allowed for this state
+            if let Some(allowed_roles) = &state.allowed_roles {
+                if !allowed_roles.iter().any(|r| r == role) {
+                    let error = SemanticError::new(
+                        format!(
+                            "Role '{}' is not allowed for state '{}'. Allowed roles: {}",
+                            role,
+                            state_ref.state,
+                            allowed_roles.join(", ")
+                        ),
+                        context.to_string(),
+                    )
+                    .with_kind(SemanticErrorKind::RoleNotAllowed {
+                        state: state_ref.state.to_string(),
+                        role: role.to_string(),
+                        allowed: allowed_roles.clone(),
+                    });
+                    let Some(last) = allowed_roles.last() else {
+                        return Err(error);
+                    };
+                    return Err(error.with_suggestion(Suggestion {
+                        message: format!("Allow role '{}' in state '{}'", role, state_ref.state),
+                        edits: vec![Edit::replace(last.clone(), format!("{}, {}", last, role))
+                            .at(self.origin("state", &state_ref.state), state.position)],
+                    }));
+                }
             }
+            // If no allowed_roles, all roles are valid (per spec)
         }
-        // If no allowed_roles, all roles are valid (per spec)
 
         Ok(())
     }
@@ -325,10 +2260,13 @@ impl SemanticValidator {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::lexer::Position;
 
     fn make_roles(roles: Vec<&str>) -> RolesDecl {
         RolesDecl {
             roles: roles.into_iter().map(|s| s.to_string()).collect(),
+            position: Position::default(),
+            spans: Spans::default(),
         }
     }
 
@@ -336,13 +2274,18 @@ mod tests {
         State {
             name: name.to_string(),
             allowed_roles: allowed_roles.map(|r| r.into_iter().map(|s| s.to_string()).collect()),
+            annotations: Vec::new(),
+            position: Position::default(),
+            spans: Spans::default(),
         }
     }
 
     fn make_state_ref(state: &str, role: &str) -> StateRef {
         StateRef {
-            state: state.to_string(),
-            role: role.to_string(),
+            state: state.into(),
+            role: role.into(),
+            extra_roles: Vec::new(),
+            spans: Spans::default(),
         }
     }
 
@@ -358,6 +2301,21 @@ mod tests {
         assert!(validator.roles.contains("Neutral"));
     }
 
+    #[test]
+    fn test_system_is_sorted() {
+        let mut validator = SemanticValidator::new();
+        validator.add_roles(make_roles(vec!["Top", "Bottom", "Neutral"])).unwrap();
+        for name in ["Mount", "Back", "Guard"] {
+            validator.add_state(make_state(name, None)).unwrap();
+        }
+        let system = validator.validate("test".to_string()).unwrap().system;
+
+        assert_eq!(system.roles.iter().collect::<Vec<_>>(), ["Bottom", "Neutral", "Top"]);
+        assert_eq!(system.states.keys().collect::<Vec<_>>(), ["Back", "Guard", "Mount"]);
+        let json = system.to_json().unwrap();
+        assert!(json.find("\"Back\"").unwrap() < json.find("\"Mount\"").unwrap());
+    }
+
     #[test]
     fn test_duplicate_state() {
         let mut validator = SemanticValidator::new();
@@ -388,10 +2346,25 @@ mod tests {
         let sequence = Sequence {
             name: "Test".to_string(),
             steps: vec![SequenceStep {
-                action_name: "Move".to_string(),
+                action_name: "Move".into(),
                 from: make_state_ref("Mount", "Top"),
                 to: make_state_ref("Guard", "Top"),
+                on_fail: None,
+                reaction: None,
+                annotations: Vec::new(),
+                template: None,
+                repeat: None,
+                repetition: None,
+                position: Position::default(),
+                spans: Spans::default(),
             }],
+            invocations: Vec::new(),
+            annotations: Vec::new(),
+            mirrored: false,
+            alternating: None,
+            mirror_of: None,
+            position: Position::default(),
+            spans: Spans::default(),
         };
         validator.add_sequence(sequence).unwrap();
 
@@ -413,16 +2386,39 @@ mod tests {
             name: "Test".to_string(),
             steps: vec![
                 SequenceStep {
-                    action_name: "Move1".to_string(),
+                    action_name: "Move1".into(),
                     from: make_state_ref("A", "Top"),
                     to: make_state_ref("B", "Top"),
+                    on_fail: None,
+                    reaction: None,
+                    annotations: Vec::new(),
+                    template: None,
+                    repeat: None,
+                    repetition: None,
+                    position: Position::default(),
+                    spans: Spans::default(),
                 },
                 SequenceStep {
-                    action_name: "Move2".to_string(),
+                    action_name: "Move2".into(),
                     from: make_state_ref("C", "Top"), // Should be B[Top]
                     to: make_state_ref("A", "Top"),
+                    on_fail: None,
+                    reaction: None,
+                    annotations: Vec::new(),
+                    template: None,
+                    repeat: None,
+                    repetition: None,
+                    position: Position::default(),
+                    spans: Spans::default(),
                 },
             ],
+            invocations: Vec::new(),
+            annotations: Vec::new(),
+            mirrored: false,
+            alternating: None,
+            mirror_of: None,
+            position: Position::default(),
+            spans: Spans::default(),
         };
         validator.add_sequence(sequence).unwrap();
 
@@ -442,17 +2438,32 @@ mod tests {
             name: "Escape".to_string(),
             steps: vec![
                 SequenceStep {
-                    action_name: "Shrimp".to_string(),
+                    action_name: "Shrimp".into(),
                     from: make_state_ref("Mount", "Bottom"),
                     to: make_state_ref("Guard", "Bottom"),
+                    on_fail: None,
+                    reaction: None,
+                    annotations: Vec::new(),
+                    template: None,
+                    repeat: None,
+                    repetition: None,
+                    position: Position::default(),
+                    spans: Spans::default(),
                 },
             ],
+            invocations: Vec::new(),
+            annotations: Vec::new(),
+            mirrored: false,
+            alternating: None,
+            mirror_of: None,
+            position: Position::default(),
+            spans: Spans::default(),
         };
         validator.add_sequence(sequence).unwrap();
 
         let result = validator.validate("BJJ".to_string());
         assert!(result.is_ok());
-        let system = result.unwrap();
+        let system = result.unwrap().system;
         assert_eq!(system.name, "BJJ");
         assert_eq!(system.roles.len(), 2);
         assert_eq!(system.states.len(), 2);
@@ -470,12 +2481,14 @@ mod tests {
         let group = GroupDecl {
             name: "TopPositions".to_string(),
             states: vec!["Mount".to_string(), "SideControl".to_string()],
+            position: Position::default(),
+            spans: Spans::default(),
         };
         validator.add_group(group).unwrap();
 
         let result = validator.validate("Test".to_string());
         assert!(result.is_ok());
-        let system = result.unwrap();
+        let system = result.unwrap().system;
         assert_eq!(system.groups.len(), 1);
         assert!(system.groups.contains_key("TopPositions"));
         assert_eq!(system.groups["TopPositions"], vec!["Mount", "SideControl"]);
@@ -490,6 +2503,8 @@ mod tests {
         let group = GroupDecl {
             name: "Bad".to_string(),
             states: vec!["Mount".to_string(), "NonExistent".to_string()],
+            position: Position::default(),
+            spans: Spans::default(),
         };
         validator.add_group(group).unwrap();
 
@@ -505,14 +2520,785 @@ mod tests {
         let group1 = GroupDecl {
             name: "Guards".to_string(),
             states: vec!["A".to_string()],
+            position: Position::default(),
+            spans: Spans::default(),
         };
         let group2 = GroupDecl {
             name: "Guards".to_string(),
             states: vec!["B".to_string()],
+            position: Position::default(),
+            spans: Spans::default(),
         };
         validator.add_group(group1).unwrap();
         let result = validator.add_group(group2);
         assert!(result.is_err());
         assert!(result.unwrap_err().message.contains("already defined"));
     }
+
+    fn load(source: &str) -> Result<MartialSystem, String> {
+        crate::loader::load_sources("test", &[crate::loader::SourceFile::new("a.martial", source)])
+            .map_err(|e| e.to_string())
+    }
+
+    fn semantic_error(source: &str) -> SemanticError {
+        match crate::loader::load_sources("test", &[crate::loader::SourceFile::new("a.martial", source)]) {
+            Err(crate::loader::LoadError::Semantic(error)) => error,
+            other => panic!("expected a semantic error, got {:?}", other.map(|s| s.name)),
+        }
+    }
+
+    fn error_kind(source: &str) -> SemanticErrorKind {
+        *semantic_error(source).kind
+    }
+
+    #[test]
+    fn test_error_kinds() {
+        let base = "roles { Top, Bottom }\nstate Mount\nstate Guard roles { Bottom }\n";
+        let kind = |body: &str| error_kind(&format!("{}{}", base, body));
+
+        assert_eq!(
+            kind("sequence A:\n    Go: Mout[Top] -> Mount[Top]"),
+            SemanticErrorKind::UndefinedState { name: "Mout".to_string(), suggestions: vec!["Mount".to_string()] }
+        );
+        assert_eq!(
+            kind("sequence A:\n    Go: Mount[Tp] -> Mount[Top]"),
+            SemanticErrorKind::UndefinedRole { name: "Tp".to_string(), suggestions: vec!["Top".to_string()] }
+        );
+        assert_eq!(
+            kind("group G { Back }"),
+            SemanticErrorKind::UndefinedState { name: "Back".to_string(), suggestions: vec![] }
+        );
+        assert_eq!(
+            kind("sequence A:\n    Go: Guard[Top] -> Mount[Top]"),
+            SemanticErrorKind::RoleNotAllowed {
+                state: "Guard".to_string(),
+                role: "Top".to_string(),
+                allowed: vec!["Bottom".to_string()],
+            }
+        );
+        assert_eq!(
+            kind("state Mount"),
+            SemanticErrorKind::Duplicate { kind: "state".to_string(), name: "Mount".to_string() }
+        );
+        assert_eq!(
+            kind("sequence A:\n    Go: Mount[Top] -> Mount[Top]\n    Back: Guard[Bottom] -> Mount[Top]"),
+            SemanticErrorKind::BrokenChain {
+                sequence: "A".to_string(),
+                step_index: 1,
+                expected: "Mount[Top]".to_string(),
+                found: "Guard[Bottom]".to_string(),
+                opponent: false,
+            }
+        );
+        assert_eq!(
+            kind("sequence A:\n    Go: Mount[Top] -> Mount[Top, Bottom]"),
+            SemanticErrorKind::ArityMismatch { state: "Mount".to_string(), expected: 1, found: 2 }
+        );
+        assert_eq!(
+            kind("sequence A:\n    Go: Mount[Top] -> Mount[Top]\nassert no_path Mount[Top] -> Mount[Bottom]\n\
+                  sequence B:\n    Roll: Mount[Top] -> Mount[Bottom]"),
+            SemanticErrorKind::AssertionFailed {
+                assertion: "assert no_path Mount[Top] -> Mount[Bottom]".to_string(),
+                path: vec!["Roll".to_string()],
+            }
+        );
+        assert_eq!(kind("sequence A:\n    Go: Mount[Top] -> Mount[Top]\nstate Back roles { Top, Top }"), SemanticErrorKind::Other);
+    }
+
+    #[test]
+    fn test_suggestions() {
+        let names: Vec<String> = ["Mount", "Guard", "HalfGuard", "Back"].iter().map(|s| s.to_string()).collect();
+        assert_eq!(suggestions("guard", &names), ["Guard"]);
+        assert_eq!(suggestions("HalfGaurd", &names), ["HalfGuard"]);
+        assert!(suggestions("Turtle", &names).is_empty());
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+    }
+
+    #[test]
+    fn test_warnings() {
+        let mut validator = SemanticValidator::new();
+        let source = "roles { Top, Bottom, Referee }\nstate Mount @dominance(9)\nstate Side @dominance(6)\nstate Turtle\n\
+                      sequence Slip @attack:\n    Lose: Mount[Top] -> Side[Top]";
+        let file = crate::loader::parse_source(&crate::loader::SourceFile::new("a.martial", source)).unwrap();
+        validator.add_file(file).unwrap();
+        let report = validator.validate("test".to_string()).unwrap();
+
+        let codes: Vec<&str> = report.warnings.iter().map(|w| w.code.as_str()).collect();
+        assert_eq!(codes, ["unused-role", "unused-role", "unused-state", "dominance-drop"]);
+        assert_eq!(report.warnings[0].to_string(), "Role Bottom is not used by any step (a.martial, line 1, column 1)");
+        assert_eq!(report.warnings[2].message, "State Turtle is not used by any step");
+        assert_eq!(report.warnings[2].position.map(|p| p.line), Some(4));
+        assert_eq!(report.warnings[3].position.map(|p| p.line), Some(6));
+        assert_eq!(report.system.states.len(), 3);
+    }
+
+    #[test]
+    fn test_chain_repairs() {
+        let source = "roles { Top, Bottom }\nstate Guard\nstate Mount\n\
+                      sequence S:\n    Pull: Guard[Bottom] -> Guard[Top]\n    Sweep: Mount[Bottom] -> Mount[Top]";
+        let error = semantic_error(source);
+        assert_eq!(error.suggestions.len(), 1);
+        assert_eq!(error.suggestions[0].message, "Start the step at Guard[Top]");
+        assert_eq!(error.suggestions[0].edits, [Edit::replace("Mount", "Guard"), Edit::replace("Bottom", "Top")]);
+
+        // Expanded steps are not repaired
+        let error = semantic_error(&format!("{}sequence Kata:\n    Step(Go, Ready[Tori], Zenkutsu[Tori])\n    Punch(Oizuki, Kokutsu)", KATA));
+        assert!(matches!(*error.kind, SemanticErrorKind::BrokenChain { .. }), "{:?}", error);
+        assert!(error.suggestions.is_empty());
+    }
+
+    #[test]
+    fn test_duplicate_policies() {
+        let sources = [
+            crate::loader::SourceFile::new("a.martial", "roles { Top, Bottom, Referee }\nstate Guard roles { Top }\nstate Mount roles { Top }"),
+            crate::loader::SourceFile::new("b.martial", "state Guard roles { Bottom, Top } @level(White)\nstate Mount"),
+        ];
+        let files: Vec<MartialFile> = sources.iter().map(|s| crate::loader::parse_source(s).unwrap()).collect();
+        let validate = |policy| {
+            let mut validator = SemanticValidator::new().duplicates(policy);
+            for file in &files {
+                validator.add_file(file.clone())?;
+            }
+            validator.validate("test".to_string()).map(|report| report.system)
+        };
+
+        let error = validate(DuplicatePolicy::Error).unwrap_err();
+        assert_eq!(*error.kind, SemanticErrorKind::Duplicate { kind: "state".to_string(), name: "Guard".to_string() });
+        assert_eq!(error.file.as_deref(), Some("b.martial"));
+
+        let system = validate(DuplicatePolicy::FirstWins).unwrap();
+        assert_eq!(system.states["Guard"].allowed_roles, Some(vec!["Top".to_string()]));
+        assert!(system.states["Guard"].annotations.is_empty());
+        assert_eq!(system.origin("state", "Guard").map(String::as_str), Some("a.martial"));
+
+        let system = validate(DuplicatePolicy::MergeRoles).unwrap();
+        assert_eq!(system.states["Guard"].allowed_roles, Some(vec!["Top".to_string(), "Bottom".to_string()]));
+        assert_eq!(system.states["Mount"].allowed_roles, None);
+        assert_eq!(DuplicatePolicy::from_name("merge-roles"), Some(DuplicatePolicy::MergeRoles));
+    }
+
+    #[test]
+    fn test_on_fail() {
+        let source = "roles { Top, Bottom }\nstate Mount\nstate Armbar\nstate Back\n\
+                      sequence Finish:\n    Armbar: Mount[Top] -> Armbar[Top] onfail -> Mount[Top]\n    \
+                      Take: Mount[Top] -> Back[Top]\n";
+        assert!(load(source).is_ok());
+        assert!(load(&source.replace("Take: Mount[Top]", "Take: Armbar[Top]")).is_ok());
+
+        let error = load(&source.replace("Take: Mount[Top]", "Take: Back[Top]")).unwrap_err();
+        assert!(
+            error.contains("previous step ends at Armbar[Top] (or Mount[Top] on failure), but this step starts at Back[Top]"),
+            "{}",
+            error
+        );
+        assert!(load(&source.replace("onfail -> Mount", "onfail -> Nowhere")).unwrap_err().contains("Nowhere"));
+        let source = source.replace("Take: Mount[Top]", "Take: Armbar[Top]");
+        let kind = error_kind(&source.replace("onfail -> Mount[Top]", "onfail -> Mount[Top, Top]"));
+        assert!(matches!(kind, SemanticErrorKind::ArityMismatch { .. }), "{:?}", kind);
+    }
+
+    #[test]
+    fn test_sequence_kinds() {
+        let source = "roles { Top }\nstate Mount\nsequence Armbar @kind(submission):\n    Lock: Mount[Top] -> Mount[Top]\n";
+        let system = load(source).unwrap();
+        assert_eq!(system.sequences["Armbar"].kind(), Some("submission"));
+
+        let file = crate::loader::parse_source(&crate::loader::SourceFile::new("a.martial", source.replace("submission", "takedown"))).unwrap();
+        let validate = |mut validator: SemanticValidator| {
+            validator.add_file(file.clone())?;
+            validator.validate("test".to_string())
+        };
+        let error = validate(SemanticValidator::new()).unwrap_err();
+        assert_eq!(error.message, "Kind 'takedown' is not defined. Available kinds: sweep, submission, pass, escape");
+        assert!(validate(SemanticValidator::new().kinds(["takedown".to_string()])).is_ok());
+
+        let error = load(&source.replace("@kind(submission)", "@kind(sweep, pass)")).unwrap_err();
+        assert!(error.contains("takes exactly one kind"), "{}", error);
+    }
+
+    #[test]
+    fn test_group_steps() {
+        let declarations = "roles { Top, Bottom }\nstate ClosedGuard\nstate OpenGuard roles { Bottom }\nstate Mount\n\
+                            group AnyGuard { ClosedGuard, OpenGuard }\n";
+        let system = load(&format!(
+            "{}sequence Recover:\n    Open: ClosedGuard[Bottom] -> OpenGuard[Bottom]\n    \
+             Sweep: AnyGuard[Bottom] -> Mount[Top]",
+            declarations
+        ))
+        .unwrap();
+        assert_eq!(system.states_of("AnyGuard"), ["ClosedGuard", "OpenGuard"]);
+        assert_eq!(system.states_of("Mount"), ["Mount"]);
+
+        // The previous step must end at a state of the group, in the same roles
+        let kind = error_kind(&format!("{}sequence S:\n    Stay: Mount[Bottom] -> Mount[Bottom]\n    Go: AnyGuard[Bottom] -> Mount[Top]", declarations));
+        assert!(matches!(kind, SemanticErrorKind::BrokenChain { .. }), "{:?}", kind);
+        let kind = error_kind(&format!("{}sequence S:\n    Stay: ClosedGuard[Top] -> ClosedGuard[Top]\n    Go: AnyGuard[Top] -> Mount[Top]", declarations));
+        assert_eq!(kind, SemanticErrorKind::RoleNotAllowed { state: "OpenGuard".to_string(), role: "Top".to_string(), allowed: vec!["Bottom".to_string()] });
+
+        let error = load(&format!("{}sequence S:\n    Pull: Mount[Top] -> AnyGuard[Top]", declarations)).unwrap_err();
+        assert!(error.contains("'AnyGuard' is a group; only the start of a step can be a group"), "{}", error);
+    }
+
+    #[test]
+    fn test_deprecations() {
+        let source = "martial 2\nroles { Top }\nstate Mount roles { Top }\nsequence Hold:\n    Stay: Mount[Top] -> Mount[Top]";
+        let source = crate::loader::SourceFile::new("a.martial", source);
+        assert!(crate::loader::parse_source(&source).is_err());
+        let file = crate::loader::parse_source_with(&source, true).unwrap();
+        let mut validator = SemanticValidator::new();
+        validator.add_file(file).unwrap();
+        let report = validator.validate("test".to_string()).unwrap();
+
+        assert_eq!(report.warnings.len(), 1);
+        assert_eq!(report.warnings[0].code, DEPRECATED);
+        assert_eq!(report.warnings[0].file.as_deref(), Some("a.martial"));
+        assert_eq!(report.warnings[0].position.map(|p| p.line), Some(3));
+    }
+
+    const LEVELS: &str = "roles { Top, Bottom }\n\
+                          levels { White, Blue, Purple }\n\
+                          state Guard\n\
+                          state Mount @level(White)\n\
+                          state Back @level(Blue)\n";
+
+    const KATA: &str = "roles { Tori }\n\
+                        state Ready\nstate Zenkutsu\nstate Kokutsu\n\
+                        template Step(action, from, to) = action: from -> to\n\
+                        template Punch(action, stance) =\n    \
+                            action: Ready[Tori] -> stance[Tori] @video(\"https://videos.test/action\")\n    \
+                            Return: stance[Tori] -> Ready[Tori]\n";
+
+    #[test]
+    fn test_templates() {
+        let system = load(&format!(
+            "{}sequence Kata:\n    \
+                 Bow: Ready[Tori] -> Ready[Tori]\n    \
+                 Punch(Oizuki, Zenkutsu), (Gyakuzuki, Kokutsu)\n    \
+                 Step(Turn, Ready[Tori], Ready[Tori])",
+            KATA
+        ))
+        .unwrap();
+        let kata = &system.sequences["Kata"];
+        let steps: Vec<String> = kata.steps.iter().map(|s| format!("{}: {} -> {}", s.action_name, s.from, s.to)).collect();
+        assert_eq!(
+            steps,
+            [
+                "Bow: Ready[Tori] -> Ready[Tori]",
+                "Oizuki: Ready[Tori] -> Zenkutsu[Tori]",
+                "Return: Zenkutsu[Tori] -> Ready[Tori]",
+                "Gyakuzuki: Ready[Tori] -> Kokutsu[Tori]",
+                "Return: Kokutsu[Tori] -> Ready[Tori]",
+                "Turn: Ready[Tori] -> Ready[Tori]",
+            ]
+        );
+        assert!(kata.invocations.is_empty());
+        assert_eq!(kata.steps[1].template.as_deref(), Some("Punch"));
+        assert_eq!(kata.steps[1].position.line, 11);
+        assert_eq!(kata.steps[1].videos(), ["https://videos.test/action"]);
+        assert_eq!(kata.steps[0].template, None);
+    }
+
+    #[test]
+    fn test_template_errors() {
+        let error = |body: &str| {
+            crate::loader::load_sources("test", &[crate::loader::SourceFile::new("a.martial", format!("{}{}", KATA, body))])
+                .unwrap_err()
+        };
+
+        // Errors inside an expansion point at the invocation
+        let e = error("sequence Kata:\n    Punch(Oizuki, Zenkutsu)\n    Punch(Gyakuzuki, Nowhere)");
+        assert!(e.message().contains("State 'Nowhere' is not defined"), "{}", e);
+        assert!(e.message().contains("step 3 (Gyakuzuki), expanded from template Punch"), "{}", e);
+        assert_eq!(e.position().map(|p| p.line), Some(11));
+
+        let e = error("sequence Kata:\n    Step(Go, Ready[Tori], Zenkutsu[Tori])\n    Step(Go, Ready[Tori], Ready[Tori])");
+        assert!(e.message().contains("Step chain is broken"), "{}", e);
+        assert_eq!(e.position().map(|p| p.line), Some(11));
+
+        let e = error("sequence Kata:\n    Kick(Maegeri, Zenkutsu)");
+        assert!(e.message().contains("Template 'Kick' is not defined"), "{}", e);
+        assert_eq!(e.position().map(|p| p.line), Some(10));
+        let e = error("sequence Kata:\n    Punch(Oizuki)");
+        assert!(e.message().contains("takes 2 argument(s) (action, stance), got 1"), "{}", e);
+        let e = error("sequence Kata:\n    Punch(Oizuki, Zenkutsu[Tori])");
+        assert!(e.message().contains("'stance' of template 'Punch' stands for a name"), "{}", e);
+        let e = error("sequence Kata:\n    Step(Go, Ready, Ready)");
+        assert!(e.message().contains("stands for a state like State[Role]"), "{}", e);
+        let e = error("template Step(a) = a: Ready[Tori] -> Ready[Tori]");
+        assert!(e.message().contains("Template 'Step' is already defined"), "{}", e);
+    }
+
+    #[test]
+    fn test_repetitions() {
+        let system = load(&format!(
+            "{}sequence Kata:\n    \
+                 Step(Go, Ready[Tori], Zenkutsu[Tori])\n    \
+                 Oizuki: Zenkutsu[Tori] -> Zenkutsu[Tori] x3\n    \
+                 Back: Zenkutsu[Tori] -> Ready[Tori]",
+            KATA
+        ))
+        .unwrap();
+        let kata = &system.sequences["Kata"];
+        let steps: Vec<(&str, Option<u32>)> = kata.steps.iter().map(|s| (s.action_name.as_str(), s.repetition)).collect();
+        assert_eq!(steps, [("Go", None), ("Oizuki", Some(1)), ("Oizuki", Some(2)), ("Oizuki", Some(3)), ("Back", None)]);
+        assert_eq!(kata.steps[3].position.line, 11);
+
+        // Errors inside a repetition name it
+        let error = |body: &str| {
+            crate::loader::load_sources("test", &[crate::loader::SourceFile::new("a.martial", format!("{}{}", KATA, body))])
+                .unwrap_err()
+        };
+        let e = error("sequence Kata:\n    Oizuki: Ready[Tori] -> Ready[Tori] x2\n    Back: Zenkutsu[Tori] -> Ready[Tori]");
+        assert!(e.message().contains("step 3 (Back)"), "{}", e);
+        let e = error("sequence Kata:\n    Oizuki: Ready[Tori] -> Ready[Tori] x2 @level(Black)");
+        assert!(e.message().contains("step 1 (Oizuki), repetition 1 of 2"), "{}", e);
+        let e = error("sequence Kata:\n    Go: Ready[Tori] -> Zenkutsu[Tori] x2");
+        assert!(
+            e.message().contains("Step 'Go' cannot be repeated: it ends at Zenkutsu[Tori] rather than where it starts, Ready[Tori]"),
+            "{}",
+            e
+        );
+        assert_eq!(e.position().map(|p| (p.line, p.column)), Some((10, 5)));
+    }
+
+    const BOXING: &str = "roles { Orthodox, Southpaw, Coach }\n\
+                          levels { White, Blue }\n\
+                          mirror roles { Orthodox <-> Southpaw }\n\
+                          state Guard\nstate Clinch roles { Orthodox, Southpaw }\nstate Lead roles { Orthodox, Coach }\n";
+
+    #[test]
+    fn test_mirrored_sequences() {
+        let system = load(&format!(
+            "{}mirror suffixes {{ Left <-> Right }}\n\
+             mirrored sequence HookLeft @level(Blue):\n    \
+                 HookLeft: Guard[Orthodox] -> Clinch[Orthodox] / Guard[Southpaw] -> Clinch[Southpaw]\n    \
+                 Hold: Clinch[Orthodox] -> Clinch[Orthodox]\n\
+             mirrored sequence Pivot:\n    \
+                 StepRight: Guard[Coach] -> Guard[Orthodox]",
+            BOXING
+        ))
+        .unwrap();
+        assert_eq!(system.sequences.len(), 4);
+
+        let right = &system.sequences["HookRight"];
+        assert_eq!(right.mirror_of.as_deref(), Some("HookLeft"));
+        assert!(!right.mirrored);
+        assert_eq!(right.level(), Some("Blue"));
+        let step = &right.steps[0];
+        assert_eq!(step.action_name.as_str(), "HookRight");
+        assert_eq!(step.from.to_string(), "Guard[Southpaw]");
+        assert_eq!(step.to.to_string(), "Clinch[Southpaw]");
+        let reaction = step.reaction.as_ref().unwrap();
+        assert_eq!(reaction.from.to_string(), "Guard[Orthodox]");
+        assert_eq!(right.steps[1].action_name.as_str(), "Hold");
+
+        // Without a suffix, the name gets one; unpaired roles stay
+        let pivot = &system.sequences["PivotMirrored"];
+        assert_eq!(pivot.steps[0].action_name.as_str(), "StepLeft");
+        assert_eq!(pivot.steps[0].from.to_string(), "Guard[Coach]");
+        assert_eq!(pivot.steps[0].to.to_string(), "Guard[Southpaw]");
+
+        let order: Vec<&str> = system.sequences_ordered().iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(order, ["HookLeft", "HookRight", "Pivot", "PivotMirrored"]);
+    }
+
+    #[test]
+    fn test_mirror_errors() {
+        let error = |body: &str| load(&format!("{}{}", BOXING, body)).unwrap_err();
+
+        // Errors in a generated sequence name where it comes from
+        let e = error("mirrored sequence Feed:\n    Hold: Lead[Orthodox] -> Lead[Orthodox]");
+        assert!(e.contains("sequence FeedMirrored step 1 (Hold), mirrored from Feed"), "{}", e);
+        let e = error("mirrored sequence Jab:\n    Go: Guard[Orthodox] -> Guard[Orthodox]\n\
+                       sequence JabMirrored:\n    Go: Guard[Orthodox] -> Guard[Orthodox]");
+        assert!(e.contains("would be named 'JabMirrored', which is already defined"), "{}", e);
+
+        assert!(error("mirror roles { Coach <-> Referee }").contains("Role 'Referee' is not defined"));
+        assert!(error("mirror roles { Coach <-> Orthodox }").contains("Role 'Orthodox' is already mirrored by 'Southpaw'"));
+        assert!(error("mirror roles { Coach <-> Coach }").contains("'Coach' cannot be its own mirror"));
+        assert!(error("mirror suffixes { L <-> R, Left <-> R }").contains("Suffix 'R' is already mirrored"));
+
+        let e = load("roles { A }\nstate S\nmirrored sequence Go:\n    Go: S[A] -> S[A]").unwrap_err();
+        assert!(e.contains("no mirror roles or suffixes are declared"), "{}", e);
+    }
+
+    #[test]
+    fn test_alternating_sequences() {
+        let system = load(&format!(
+            "{}mirror suffixes {{ Left <-> Right }}\n\
+             mirrored alternating(Orthodox, Coach) sequence PadsLeft:\n    \
+                 JabLeft: Guard[Orthodox] -> Clinch[Orthodox]\n    \
+                 Catch: Lead[Coach] -> Guard[Coach]\n    \
+                 Break: Clinch[Orthodox] -> Guard[Orthodox]",
+            BOXING
+        ))
+        .unwrap();
+        let right = &system.sequences["PadsRight"];
+        assert_eq!(right.alternating, Some(("Southpaw".to_string(), "Coach".to_string())));
+        assert_eq!(right.turn(2), Some("Southpaw"));
+
+        let graph = crate::graph::MartialGraph::from_system(&system);
+        let turns: Vec<(&str, Option<&str>)> = graph
+            .edges
+            .iter()
+            .filter(|edge| edge.sequence == "PadsLeft")
+            .map(|edge| (edge.action.as_str(), edge.turn.as_deref()))
+            .collect();
+        assert_eq!(turns, [("JabLeft", Some("Orthodox")), ("Catch", Some("Coach")), ("Break", Some("Orthodox"))]);
+
+        let error = |body: &str| load(&format!("{}{}", BOXING, body)).unwrap_err();
+        let e = error("alternating(Orthodox, Coach) sequence Pads:\n    \
+                       Jab: Guard[Orthodox] -> Guard[Orthodox]\n    Cross: Guard[Orthodox] -> Guard[Orthodox]");
+        assert!(e.contains("It is Coach's turn, but the step starts in role Orthodox"), "{}", e);
+        let e = error("alternating(Orthodox, Coach) sequence Pads:\n    Jab: Guard[Orthodox] -> Clinch[Orthodox]\n    \
+                       Catch: Guard[Coach] -> Guard[Coach]\n    Cross: Guard[Orthodox] -> Guard[Orthodox]");
+        assert!(
+            e.contains("Orthodox's previous step ends at Clinch[Orthodox], but this step starts at Guard[Orthodox]"),
+            "{}",
+            e
+        );
+        let e = error("alternating(Orthodox, Referee) sequence Pads:\n    Jab: Guard[Orthodox] -> Guard[Orthodox]");
+        assert!(e.contains("Role 'Referee' is not defined"), "{}", e);
+        let e = error("alternating(Coach, Coach) sequence Pads:\n    Jab: Guard[Coach] -> Guard[Coach]");
+        assert!(e.contains("Alternating roles must differ, got 'Coach' twice"), "{}", e);
+    }
+
+    #[test]
+    fn test_assertions() {
+        let system = format!(
+            "{}state SideControl\n\
+             sequence Escape:\n    Bridge: Mount[Bottom] -> Guard[Bottom]\n\
+             sequence Pass:\n    Cut: Guard[Top] -> SideControl[Top]\n    Mount: SideControl[Top] -> Mount[Top]\n",
+            LEVELS
+        );
+        let check = |assertions: &str| load(&format!("{}{}", system, assertions));
+        check("assert reachable Guard[Top] -> Mount[Top]\nassert no_path Mount[Bottom] -> Mount[Top]").unwrap();
+
+        let error = check("assert reachable Mount[Top] -> Guard[Top]").unwrap_err();
+        assert!(error.contains("Assertion failed: Guard[Top] cannot be reached from Mount[Top]"), "{}", error);
+        let error = crate::loader::load_sources(
+            "test",
+            &[crate::loader::SourceFile::new("a.martial", format!("{}assert reachable Mount[Top] -> Guard[Top]", system))],
+        )
+        .unwrap_err();
+        assert_eq!(error.position().map(|p| (p.line, p.column)), Some((12, 1)));
+        let error = check("assert no_path Guard[Top] -> Mount[Top]").unwrap_err();
+        assert!(error.contains("Guard[Top] reaches Mount[Top] through Cut, Mount"), "{}", error);
+        let error = check("assert no_path Guard[Top] -> Guard[Top]").unwrap_err();
+        assert!(error.contains("are the same position"), "{}", error);
+        let error = check("assert reachable Guard[Top] -> Back[Referee]").unwrap_err();
+        assert!(error.contains("Role 'Referee' is not defined"), "{}", error);
+        assert!(error.contains("assert reachable Guard[Top] -> Back[Referee]"), "{}", error);
+    }
+
+    #[test]
+    fn test_levels() {
+        let system = load(&format!(
+            "{}sequence Take @level(Blue):\n    Roll: Mount[Top] -> Back[Top]\n\
+             sequence Sweep:\n    Scissor: Guard[Bottom] -> Mount[Top]\n\
+             sequence Chain:\n    Step: Guard[Top] -> Back[Top]\n\
+             group Control {{ Mount, Back }}",
+            LEVELS
+        ))
+        .unwrap();
+        assert_eq!(system.levels, vec!["White", "Blue", "Purple"]);
+        assert_eq!(system.sequence_level(&system.sequences["Sweep"]), Some("White"));
+        assert_eq!(system.sequence_level(&system.sequences["Chain"]), Some("Blue"));
+
+        let white = system.at_level("White").unwrap();
+        let states: Vec<&String> = white.states.keys().collect();
+        assert_eq!(states, vec!["Guard", "Mount"]);
+        assert_eq!(white.sequences.keys().collect::<Vec<_>>(), vec!["Sweep"]);
+        assert_eq!(white.groups["Control"], vec!["Mount"]);
+
+        assert_eq!(system.at_level("Purple").unwrap().sequences.len(), 3);
+        assert!(system.at_level("Black").is_none());
+    }
+
+    #[test]
+    fn test_level_errors() {
+        let error = load(&format!("{}sequence Take @level(White):\n    Roll: Mount[Top] -> Back[Top]", LEVELS)).unwrap_err();
+        assert!(error.contains("introduced at level 'Blue', above the sequence level 'White'"));
+
+        let error = load(&format!("{}state Side @level(Black)", LEVELS)).unwrap_err();
+        assert!(error.contains("Level 'Black' is not defined"));
+
+        let error = load(&format!("{}state Side @level(White, Blue)", LEVELS)).unwrap_err();
+        assert!(error.contains("takes exactly one level"));
+
+        let error = load(&format!("{}state Side @video", LEVELS)).unwrap_err();
+        assert!(error.contains("'@video' is not allowed on a state"));
+
+        let error = load(&format!("{}sequence Hold @duration(0):\n    Settle: Mount[Top] -> Mount[Top]", LEVELS)).unwrap_err();
+        assert!(error.contains("positive number of minutes"));
+    }
+
+    #[test]
+    fn test_terms() {
+        let system = load(&format!(
+            "{}term Settle \"Sink the hips\"\nterm Kesa \"Scarf hold\"\n\
+             sequence Hold:\n    Settle: Mount[Top] -> Mount[Top]\n    Post: Mount[Top] -> Mount[Top]",
+            LEVELS
+        ))
+        .unwrap();
+        assert_eq!(system.terms["Settle"], "Sink the hips");
+        assert_eq!(system.actions_without_terms(), vec!["Post"]);
+
+        let error = load(&format!("{}term A \"x\"\nterm A \"y\"", LEVELS)).unwrap_err();
+        assert!(error.contains("Term 'A' is already defined"));
+        assert!(load(&format!("{}term A \" \"", LEVELS)).unwrap_err().contains("cannot be empty"));
+    }
+
+    #[test]
+    fn test_labels() {
+        let system = load(&format!(
+            "{}state Stance @label(en: \"Front Stance\", ja: \"Zenkutsu Dachi\")\ngroup Stances {{ Stance }}\n\
+             sequence Drill:\n    Step: Stance[Top] -> Mount[Top] @label(ja: \"Ayumi Ashi\")",
+            LEVELS
+        ))
+        .unwrap();
+        assert_eq!(system.languages(), vec!["en", "ja"]);
+
+        let ja = system.localized("ja");
+        assert!(ja.states.contains_key("Zenkutsu Dachi") && ja.states.contains_key("Mount"));
+        assert_eq!(ja.groups["Stances"], vec!["Zenkutsu Dachi"]);
+        let step = &ja.sequences["Drill"].steps[0];
+        assert_eq!((step.action_name.as_str(), step.from.state.as_str()), ("Ayumi Ashi", "Zenkutsu Dachi"));
+        assert_eq!(system.localized("en").sequences["Drill"].steps[0].action_name, "Step");
+
+        for (source, message) in [
+            ("state A @label(\"A\")", "takes display names by language"),
+            ("state A @label(en: \" \")", "Label for language 'en' cannot be empty"),
+            ("state A @level(en: White)", "does not take named arguments"),
+            ("sequence S @label(en: \"S\"):\n    Go: Mount[Top] -> Mount[Top]", "not allowed on a sequence"),
+            (
+                "state A @label(ja: \"Kamae\")\nstate B @label(ja: \"Kamae\")",
+                "Label 'Kamae' for language 'ja' is already the name of state B in that language",
+            ),
+            ("state A @label(ja: \"Mount\")", "already the name of state Mount"),
+        ] {
+            let error = load(&format!("{}{}", LEVELS, source)).unwrap_err();
+            assert!(error.contains(message), "{}", error);
+        }
+    }
+
+    #[test]
+    fn test_dominance() {
+        let system = load(&format!(
+            "{}state Side @dominance(6)\nstate Knee @dominance(7)\nstate Top @dominance(8)\n\
+             sequence Attack @attack:\n    Pass: Guard[Top] -> Side[Top]\n    Pop: Side[Top] -> Top[Top]\n    \
+             Slip: Top[Top] -> Knee[Top]\n    Back: Knee[Top] -> Top[Top]\n\
+             sequence Escape:\n    Drop: Top[Bottom] -> Side[Bottom]",
+            LEVELS
+        ))
+        .unwrap();
+        let drops = system.dominance_drops();
+        assert_eq!(drops.len(), 1);
+        assert_eq!(drops[0].to_string(), "Attack 'Attack' step 3 (Slip) goes from Top (8) down to Knee (7)");
+        assert_eq!(system.hierarchy(), vec![("Top", 8), ("Knee", 7), ("Side", 6)]);
+
+        for (source, message) in [
+            ("state A @dominance(high)", "non-negative whole number"),
+            ("state A @attack", "not allowed on a state"),
+            ("sequence S @attack(yes):\n    Go: Mount[Top] -> Mount[Top]", "takes no arguments"),
+            ("sequence S:\n    Go: Mount[Top] -> Mount[Top] @entry(1)", "takes no arguments"),
+            ("state A @requires", "takes at least one state"),
+            ("state A @requires(Mout)", "State 'Mout' is not defined"),
+            ("sequence S @entry:\n    Go: Mount[Top] -> Mount[Top]", "not allowed on a sequence"),
+        ] {
+            let error = load(&format!("{}{}", LEVELS, source)).unwrap_err();
+            assert!(error.contains(message), "{}", error);
+        }
+    }
+
+    #[test]
+    fn test_multiple_participants() {
+        let source = "state Triangle roles { Top, Bottom }\n\
+                      sequence Team:\n    \
+                      Enter: Guard[Top, Bottom, Top] -> Triangle[Top, Bottom, Bottom]\n    \
+                      Hold: Triangle[Top, Bottom, Bottom] -> Triangle[Bottom, Bottom, Top]";
+        let system = load(&format!("{}{}", LEVELS, source)).unwrap();
+        let graph = crate::graph::MartialGraph::from_system(&system);
+        assert!(graph.nodes.iter().any(|n| n.id() == "Triangle[Top, Bottom, Bottom]"));
+
+        let error = load(&format!("{}{}\nsequence Solo:\n    Go: Triangle[Top] -> Mount[Top]", LEVELS, source)).unwrap_err();
+        assert!(error.contains("State 'Triangle' has 3 role slot(s) here but 1"), "{}", error);
+
+        let error = load(&format!("{}{}", LEVELS, source.replace("[Top, Bottom, Bottom] ->", "[Top, Neutral, Bottom] ->"))).unwrap_err();
+        assert!(error.contains("Role 'Neutral' is not defined"), "{}", error);
+    }
+
+    #[test]
+    fn test_reactions() {
+        let source = "sequence Sweep:\n    \
+                      Scissor: Guard[Bottom] -> Mount[Top] / Guard[Top] -> Mount[Bottom]\n    \
+                      Post: Mount[Top] -> Mount[Top] / Mount[Bottom] -> Back[Bottom]";
+        assert!(load(&format!("{}{}", LEVELS, source)).is_ok());
+
+        let broken = source.replace("/ Mount[Bottom] -> Back", "/ Guard[Top] -> Back");
+        let error = load(&format!("{}{}", LEVELS, broken)).unwrap_err();
+        assert!(error.contains("Opponent chain is broken"), "{}", error);
+
+        let undefined = source.replace("Back[Bottom]", "Turtle[Bottom]");
+        let error = load(&format!("{}{}", LEVELS, undefined)).unwrap_err();
+        assert!(error.contains("step 2 (Post) opponent"), "{}", error);
+    }
+
+    #[test]
+    fn test_rulesets() {
+        let system = load(&format!(
+            "{}ruleset Kids {{ forbid Slam, Choke }}\nruleset Open {{}}\n\
+             sequence Takedown:\n    Slam: Guard[Top] -> Mount[Top]\n    Settle: Mount[Top] -> Mount[Top]\n\
+             sequence Choke:\n    Squeeze: Mount[Top] -> Back[Bottom]\n\
+             sequence Pass:\n    Knee: Guard[Top] -> Mount[Top]\n\
+             sequence Throw:\n    Slam: Guard[Bottom] -> Back[Top]",
+            LEVELS
+        ))
+        .unwrap();
+
+        let kids = system.under_ruleset("Kids").unwrap();
+        let names: Vec<&String> = kids.sequences.keys().collect();
+        assert_eq!(names, vec!["Pass", "Takedown"]);
+        assert_eq!(kids.sequences["Takedown"].steps.len(), 1);
+        assert!(system.under_ruleset("Pro").is_none());
+        assert_eq!(system.under_ruleset("Open").unwrap().sequences.len(), 4);
+
+        assert_eq!(system.reached_only_through_forbidden("Kids"), vec!["Back[Bottom]", "Back[Top]"]);
+        assert!(system.reached_only_through_forbidden("Open").is_empty());
+
+        let error = load(&format!("{}ruleset Kids {{ forbid Nothing }}", LEVELS)).unwrap_err();
+        assert!(error.contains("'Nothing' is neither an action nor a sequence"), "{}", error);
+        let error = load(&format!("{}ruleset A {{}}\nruleset A {{}}", LEVELS)).unwrap_err();
+        assert!(error.contains("Ruleset 'A' is already defined"));
+    }
+
+    #[test]
+    fn test_video_annotations() {
+        let system = load(&format!(
+            "{}sequence Hold @video(\"https://videos.test/hold\"):\n    \
+             Settle: Mount[Top] -> Mount[Top] @video(\"http://videos.test/settle?t=30\")",
+            LEVELS
+        ))
+        .unwrap();
+        assert_eq!(system.sequences["Hold"].videos(), vec!["https://videos.test/hold"]);
+
+        for url in ["ftp://videos.test", "https://", "https://videos.test/a b", "videos.test"] {
+            let source = format!("{}sequence Hold @video(\"{}\"):\n    Settle: Mount[Top] -> Mount[Top]", LEVELS, url);
+            assert!(load(&source).unwrap_err().contains("not a valid http(s) URL"), "{}", url);
+        }
+        let error = load(&format!("{}state Side @video(\"https://videos.test\")", LEVELS)).unwrap_err();
+        assert!(error.contains("'@video' is not allowed on a state"));
+
+        let error = load(&format!("{}levels {{ Black }}", LEVELS)).unwrap_err();
+        assert!(error.contains("already declared"));
+
+        assert!(load("roles { Top }\nlevels { White, White }").unwrap_err().contains("appears multiple times"));
+    }
+
+    #[test]
+    fn test_tags_and_weights() {
+        let system = load(&format!(
+            "{}state Side @tag(top, pin)\n\
+             sequence Hold @tag(control) @tag(drill):\n    \
+             Settle: Mount[Top] -> Mount[Top] @weight(3) @tag(basic)",
+            LEVELS
+        ))
+        .unwrap();
+        assert_eq!(system.sequences["Hold"].tags(), ["control", "drill"]);
+        assert_eq!(system.sequences["Hold"].steps[0].weight(), Some(3));
+        assert_eq!(system.states["Side"].attributes()["tag"], "top, pin");
+
+        let error = load(&format!("{}sequence Hold:\n    Settle: Mount[Top] -> Mount[Top] @weight(heavy)", LEVELS)).unwrap_err();
+        assert!(error.contains("'@weight' takes a non-negative whole number"));
+        let error = load(&format!("{}state Side @weight(2)", LEVELS)).unwrap_err();
+        assert!(error.contains("'@weight' is not allowed on a state"));
+        let error = load(&format!("{}state Side @tag", LEVELS)).unwrap_err();
+        assert!(error.contains("'@tag' takes at least one tag"));
+    }
+
+    #[test]
+    fn test_describe() {
+        let system = load(
+            "roles { Top, Bottom }\n\
+             state Mount\n\
+             state Guard roles { Bottom }\n\
+             group Dominant { Mount }\n\
+             sequence Sweep:\n    \
+             Scissor: Guard[Bottom] -> Mount[Top]\n    \
+             Post: Mount[Top] -> Mount[Top]",
+        )
+        .unwrap();
+
+        let mount = system.describe("Mount").unwrap();
+        assert_eq!(mount.kind, IdentifierKind::State);
+        assert_eq!((mount.file.as_deref(), mount.position.map(|p| p.line)), (Some("a.martial"), Some(2)));
+        assert_eq!(mount.roles, ["Bottom", "Top"]);
+        assert_eq!(mount.states, ["Dominant"]);
+        assert_eq!(mount.steps.len(), 2);
+        assert_eq!(mount.degree, Some(Degree { incoming: 2, outgoing: 1 }));
+
+        let bottom = system.describe("Bottom").unwrap();
+        assert_eq!(bottom.kind, IdentifierKind::Role);
+        assert_eq!(bottom.states, ["Guard", "Mount"]);
+        assert_eq!(bottom.degree, Some(Degree { incoming: 0, outgoing: 1 }));
+
+        let sweep = system.describe("Sweep").unwrap();
+        assert_eq!((sweep.kind, sweep.steps.len(), sweep.degree), (IdentifierKind::Sequence, 2, None));
+        assert_eq!(system.describe("Dominant").unwrap().states, ["Mount"]);
+
+        let post = system.describe("Post").unwrap();
+        assert_eq!(post.kind, IdentifierKind::Action);
+        assert_eq!((post.file.as_deref(), post.position.map(|p| p.line)), (Some("a.martial"), Some(7)));
+        assert_eq!(
+            post.to_string(),
+            "action Post (a.martial:7)\n  Steps:\n    Post: Mount[Top] -> Mount[Top] (Sweep)\n"
+        );
+        assert!(system.describe("Kimura").is_none());
+    }
+
+    #[test]
+    fn test_references() {
+        let system = load(
+            "roles { Top, Bottom }\n\
+             state Mount\n\
+             state Guard roles { Bottom }\n\
+             group Dominant { Mount }\n\
+             ruleset Kids { forbid Post }\n\
+             sequence Sweep:\n    \
+             Scissor: Guard[Bottom] -> Mount[Top] onfail -> Guard[Bottom]\n    \
+             Post: Mount[Top] -> Mount[Top]",
+        )
+        .unwrap();
+        let kinds = |name: &str| -> Vec<(Option<(usize, usize)>, ReferenceKind)> {
+            system.references(name).iter().map(|r| (r.position.map(|p| (p.line, p.column)), r.kind)).collect()
+        };
+
+        assert_eq!(
+            kinds("Mount"),
+            [
+                (Some((2, 7)), ReferenceKind::Declaration),
+                (Some((7, 31)), ReferenceKind::To),
+                (Some((8, 11)), ReferenceKind::From),
+                (Some((8, 25)), ReferenceKind::To),
+                (Some((4, 18)), ReferenceKind::Member),
+            ]
+        );
+        assert_eq!(
+            kinds("Bottom"),
+            [
+                (Some((1, 1)), ReferenceKind::Declaration),
+                (Some((3, 21)), ReferenceKind::AllowedRole),
+                (Some((7, 20)), ReferenceKind::From),
+                (Some((7, 58)), ReferenceKind::OnFail),
+            ]
+        );
+        assert_eq!(
+            kinds("Post"),
+            [(Some((8, 5)), ReferenceKind::Action), (Some((5, 23)), ReferenceKind::Forbidden)]
+        );
+        assert_eq!(kinds("Sweep"), [(Some((6, 10)), ReferenceKind::Declaration)]);
+        assert_eq!(kinds("Dominant"), [(Some((4, 7)), ReferenceKind::Declaration)]);
+        assert_eq!(system.references("Post")[0].to_string(), "a.martial:8:5: action in sequence Sweep");
+        assert!(system.references("Kimura").is_empty());
+    }
 }
@@ -0,0 +1,424 @@
+//! HTTP API over a validated system
+//!
+//! Answers JSON queries so that web apps need not run the CLI for every
+//! request:
+//! - `GET /graph`: the graph, as exported by `mat graph`
+//! - `GET /stats`: node and edge counts, source, sink and isolated nodes
+//! - `GET /paths?from=Guard&to=Back[Top]`: the fewest actions between two
+//!   states or nodes; with `&canonical`, joining sequences only at their
+//!   entry points
+//! - `POST /validate`: validate the `.martial` source in the body on its own
+//! - `GET|POST /graphql`: GraphQL queries, see [`crate::graphql`]; the schema
+//!   itself is at `GET /graphql/schema`
+//!
+//! Each connection is served on its own thread from a shared
+//! [`SystemHandle`] and carries a single request.
+
+use crate::handle::SystemHandle;
+use crate::loader::{self, SourceFile};
+use serde_json::{json, Value};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+/// Largest request body accepted, in bytes
+pub const MAX_BODY: usize = 1 << 20;
+
+/// How long a client may take to send its request
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// An HTTP request
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    /// Decoded query parameters, in order
+    pub query: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl Request {
+    /// First value of query parameter `name`
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.query.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str())
+    }
+}
+
+/// A response, JSON unless stated otherwise
+#[derive(Debug, Clone, PartialEq)]
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+impl Response {
+    fn json(status: u16, value: &Value) -> Self {
+        Response {
+            status,
+            content_type: "application/json",
+            body: value.to_string(),
+        }
+    }
+
+    #[cfg_attr(not(feature = "graphql"), allow(dead_code))]
+    fn text(status: u16, body: String) -> Self {
+        Response {
+            status,
+            content_type: "text/plain; charset=utf-8",
+            body,
+        }
+    }
+
+    fn error(status: u16, message: impl Into<String>) -> Self {
+        Response::json(status, &json!({ "error": message.into() }))
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        413 => "Payload Too Large",
+        _ => "Internal Server Error",
+    }
+}
+
+/// Serve connections accepted on `listener` until it fails
+pub fn serve(listener: TcpListener, handle: SystemHandle) -> io::Result<()> {
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("Warning: failed to accept a connection: {}", e);
+                continue;
+            }
+        };
+        let handle = handle.clone();
+        thread::spawn(move || {
+            if let Err(e) = handle_connection(stream, &handle) {
+                eprintln!("Warning: connection failed: {}", e);
+            }
+        });
+    }
+    Ok(())
+}
+
+fn handle_connection(stream: TcpStream, handle: &SystemHandle) -> io::Result<()> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let mut writer = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+    let response = match read_request(&mut reader, &mut writer) {
+        Ok(request) => respond(handle, &request),
+        Err(e) if e.kind() == io::ErrorKind::InvalidData => Response::error(400, e.to_string()),
+        Err(e) if e.kind() == io::ErrorKind::OutOfMemory => Response::error(413, e.to_string()),
+        Err(e) => return Err(e),
+    };
+    write_response(&mut writer, &response)
+}
+
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+/// Read one request
+///
+/// Bodies are read according to `Content-Length`; a client expecting
+/// `100 Continue` gets it on `writer` before the body is read.
+pub fn read_request<R: BufRead, W: Write>(reader: &mut R, writer: &mut W) -> io::Result<Request> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut parts = line.split_whitespace();
+    let (method, target) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(method), Some(target), Some(version)) if version.starts_with("HTTP/") => (method, target),
+        _ => return Err(invalid(format!("Malformed request line: {}", line.trim_end()))),
+    };
+
+    let mut content_length = 0;
+    let mut expect_continue = false;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 {
+            return Err(invalid("Unexpected end of headers"));
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        let Some((name, value)) = header.split_once(':') else {
+            return Err(invalid(format!("Malformed header: {}", header)));
+        };
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("content-length") {
+            content_length = value.parse().map_err(|_| invalid(format!("Invalid Content-Length: {}", value)))?;
+        } else if name.eq_ignore_ascii_case("expect") {
+            expect_continue = value.eq_ignore_ascii_case("100-continue");
+        }
+    }
+
+    if content_length > MAX_BODY {
+        return Err(io::Error::new(
+            io::ErrorKind::OutOfMemory,
+            format!("Body exceeds {} bytes", MAX_BODY),
+        ));
+    }
+    if expect_continue && content_length > 0 {
+        writer.write_all(b"HTTP/1.1 100 Continue\r\n\r\n")?;
+        writer.flush()?;
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    Ok(Request {
+        method: method.to_string(),
+        path: decode(path),
+        query: query
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+                (decode(key), decode(value))
+            })
+            .collect(),
+        body: String::from_utf8(body).map_err(|_| invalid("Body is not UTF-8"))?,
+    })
+}
+
+/// Percent-decode a URL component, reading `+` as a space
+fn decode(component: &str) -> String {
+    let hex = |byte: Option<&u8>| byte.and_then(|b| (*b as char).to_digit(16));
+    let bytes = component.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match (bytes[i], hex(bytes.get(i + 1)), hex(bytes.get(i + 2))) {
+            (b'%', Some(high), Some(low)) => {
+                decoded.push((high * 16 + low) as u8);
+                i += 2;
+            }
+            (b'+', _, _) => decoded.push(b' '),
+            (byte, _, _) => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Write `response` and close the exchange
+pub fn write_response<W: Write>(writer: &mut W, response: &Response) -> io::Result<()> {
+    write!(
+        writer,
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        response.status,
+        reason(response.status),
+        response.content_type,
+        response.body.len(),
+        response.body
+    )?;
+    writer.flush()
+}
+
+/// Answer a request from the shared system
+pub fn respond(handle: &SystemHandle, request: &Request) -> Response {
+    let method = request.method.as_str();
+    match (method, request.path.trim_end_matches('/')) {
+        ("GET", "/graph") => match serde_json::to_value(handle.graph()) {
+            Ok(graph) => Response::json(200, &graph),
+            Err(e) => Response::error(500, e.to_string()),
+        },
+        ("GET", "/stats") => stats(handle),
+        ("GET", "/paths") => paths(handle, request),
+        ("POST", "/validate") => validate(&request.body),
+        #[cfg(feature = "graphql")]
+        ("GET" | "POST", "/graphql") => graphql(handle, request),
+        #[cfg(feature = "graphql")]
+        ("GET", "/graphql/schema") => Response::text(200, crate::graphql::schema(handle.clone()).sdl()),
+        (_, "/graph" | "/stats" | "/paths" | "/validate") => {
+            Response::error(405, format!("{} is not allowed on {}", method, request.path))
+        }
+        _ => Response::error(404, format!("No endpoint at {}", request.path)),
+    }
+}
+
+fn stats(handle: &SystemHandle) -> Response {
+    let system = handle.system();
+    let stats = handle.statistics();
+    let ids = |nodes: &[crate::graph::Node]| nodes.iter().map(|n| n.id()).collect::<Vec<_>>();
+    Response::json(
+        200,
+        &json!({
+            "system": system.name,
+            "states": system.states.len(),
+            "sequences": system.sequences.len(),
+            "nodes": stats.node_count,
+            "edges": stats.edge_count,
+            "self_loops": stats.self_loops,
+            "source_nodes": ids(&stats.source_nodes),
+            "sink_nodes": ids(&stats.sink_nodes),
+            "isolated_nodes": ids(&stats.isolated_nodes),
+        }),
+    )
+}
+
+fn paths(handle: &SystemHandle, request: &Request) -> Response {
+    let (Some(from), Some(to)) = (request.param("from"), request.param("to")) else {
+        return Response::error(400, "Both 'from' and 'to' are required");
+    };
+    let path = if request.param("canonical").is_some() {
+        handle.canonical_path(from, to)
+    } else {
+        handle.shortest_path(from, to)
+    };
+    match path {
+        Some(path) => Response::json(200, &json!({ "from": from, "to": to, "path": path })),
+        None => Response::error(404, format!("No path from {} to {}", from, to)),
+    }
+}
+
+fn validate(source: &str) -> Response {
+    let (_, errors) = loader::check_sources("request", &[SourceFile::new("request.martial", source)]);
+    Response::json(200, &json!({ "valid": errors.is_empty(), "errors": errors }))
+}
+
+/// Run a GraphQL request given as a JSON body or as `query` and
+/// `variables` parameters
+#[cfg(feature = "graphql")]
+fn graphql(handle: &SystemHandle, request: &Request) -> Response {
+    let query = if request.method == "POST" {
+        match serde_json::from_str::<async_graphql::Request>(&request.body) {
+            Ok(query) => query,
+            Err(e) => return Response::error(400, format!("Invalid GraphQL request: {}", e)),
+        }
+    } else {
+        let Some(query) = request.param("query") else {
+            return Response::error(400, "'query' is required");
+        };
+        let variables = match request.param("variables").map(serde_json::from_str).transpose() {
+            Ok(variables) => variables.unwrap_or_default(),
+            Err(e) => return Response::error(400, format!("Invalid variables: {}", e)),
+        };
+        async_graphql::Request::new(query).variables(async_graphql::Variables::from_json(variables))
+    };
+    let response = crate::graphql::execute(&crate::graphql::schema(handle.clone()), query);
+    match serde_json::to_value(&response) {
+        Ok(value) => Response::json(200, &value),
+        Err(e) => Response::error(500, e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loader::load_sources;
+    use std::io::Read;
+
+    fn handle() -> SystemHandle {
+        let source = "roles { Top, Bottom }\nstate Guard\nstate Mount\nstate Back\nstate Lost\n\
+                      sequence Pass:\n    Sweep: Guard[Bottom] -> Mount[Top]\n    Roll: Mount[Top] -> Back[Top]";
+        SystemHandle::new(load_sources("club", &[SourceFile::new("a.martial", source)]).unwrap())
+    }
+
+    fn get(target: &str) -> Request {
+        let raw = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", target);
+        read_request(&mut raw.as_bytes(), &mut Vec::new()).unwrap()
+    }
+
+    fn body(response: &Response) -> Value {
+        serde_json::from_str(&response.body).unwrap()
+    }
+
+    #[test]
+    fn test_read_request() {
+        let request = get("/paths?from=Guard%5BBottom%5D&to=Back+Top&flag");
+        assert_eq!((request.method.as_str(), request.path.as_str()), ("GET", "/paths"));
+        assert_eq!(request.param("from"), Some("Guard[Bottom]"));
+        assert_eq!(request.param("to"), Some("Back Top"));
+        assert_eq!(request.param("flag"), Some(""));
+        assert_eq!(decode("100%"), "100%");
+
+        let raw = "POST /validate HTTP/1.1\r\ncontent-length: 11\r\nExpect: 100-continue\r\n\r\nstate Mount";
+        let mut interim = Vec::new();
+        let request = read_request(&mut raw.as_bytes(), &mut interim).unwrap();
+        assert_eq!(request.body, "state Mount");
+        assert_eq!(interim, b"HTTP/1.1 100 Continue\r\n\r\n");
+
+        let error = read_request(&mut "nonsense\r\n\r\n".as_bytes(), &mut Vec::new()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        let raw = format!("POST /validate HTTP/1.1\r\nContent-Length: {}\r\n\r\n", MAX_BODY + 1);
+        let error = read_request(&mut raw.as_bytes(), &mut Vec::new()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::OutOfMemory);
+    }
+
+    #[test]
+    fn test_endpoints() {
+        let handle = handle();
+
+        let response = respond(&handle, &get("/paths?from=Guard&to=Back"));
+        assert_eq!(response.status, 200);
+        let actions: Vec<Value> = body(&response)["path"].as_array().unwrap().iter().map(|e| e["action"].clone()).collect();
+        assert_eq!(actions, vec![json!("Sweep"), json!("Roll")]);
+        assert_eq!(respond(&handle, &get("/paths?from=Back&to=Guard")).status, 404);
+        // Roll only follows Sweep
+        assert_eq!(respond(&handle, &get("/paths?from=Mount&to=Back")).status, 200);
+        assert_eq!(respond(&handle, &get("/paths?from=Mount&to=Back&canonical")).status, 404);
+        assert_eq!(respond(&handle, &get("/paths?from=Back")).status, 400);
+
+        let stats = body(&respond(&handle, &get("/stats")));
+        assert_eq!((stats["system"].clone(), stats["edges"].clone()), (json!("club"), json!(2)));
+        assert_eq!(body(&respond(&handle, &get("/graph/")))["edges"].as_array().unwrap().len(), 2);
+        assert_eq!(respond(&handle, &get("/nowhere")).status, 404);
+        assert_eq!(respond(&handle, &get("/validate")).status, 405);
+
+        let post = |source: &str| Request {
+            method: "POST".to_string(),
+            path: "/validate".to_string(),
+            body: source.to_string(),
+            ..Request::default()
+        };
+        assert_eq!(body(&respond(&handle, &post("roles { Top }\nstate Mount")))["valid"], json!(true));
+        let invalid = body(&respond(&handle, &post("sequence S:\n    Go: Nowhere[Top] -> Mount[Top]")));
+        assert_eq!(invalid["valid"], json!(false));
+        assert_eq!(invalid["errors"].as_array().unwrap().len(), 1);
+    }
+
+    #[cfg(feature = "graphql")]
+    #[test]
+    fn test_graphql() {
+        let handle = handle();
+        let data = body(&respond(&handle, &get("/graphql?query=%7B+path(from:%22Guard%22,to:%22Back%22)+%7B+action+%7D+%7D")));
+        assert_eq!(data["data"]["path"], json!([{ "action": "Sweep" }, { "action": "Roll" }]));
+
+        let post = Request {
+            method: "POST".to_string(),
+            path: "/graphql".to_string(),
+            body: r#"{"query": "query($s: String!) { state(name: $s) { name } }", "variables": {"s": "Mount"}}"#.to_string(),
+            ..Request::default()
+        };
+        assert_eq!(body(&respond(&handle, &post))["data"]["state"]["name"], "Mount");
+        assert_eq!(respond(&handle, &get("/graphql")).status, 400);
+
+        let schema = respond(&handle, &get("/graphql/schema"));
+        assert!(schema.content_type.starts_with("text/plain"));
+        assert!(schema.body.contains("type Transition"), "{}", schema.body);
+    }
+
+    #[test]
+    fn test_serve_over_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let handle = handle();
+        thread::spawn(move || serve(listener, handle));
+
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream.write_all(b"GET /stats HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+        let (_, json) = response.split_once("\r\n\r\n").unwrap();
+        assert_eq!(serde_json::from_str::<Value>(json).unwrap()["states"], json!(4));
+    }
+}
@@ -0,0 +1,369 @@
+//! Static HTML site generation
+//!
+//! Companion of the Markdown handbook in [`crate::doc`] for publishing a
+//! curriculum online: an index page, one page per state and one per
+//! sequence, cross-linked, each with an inline SVG diagram. No external
+//! tools are needed to render the diagrams.
+
+use crate::ast::{SequenceStep, Spans, StateRef};
+use crate::doc::all_steps;
+use crate::export::escape;
+use crate::semantic::MartialSystem;
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+/// A generated page: path relative to the site root and its HTML
+#[derive(Debug, Clone, PartialEq)]
+pub struct Page {
+    pub path: String,
+    pub html: String,
+}
+
+const STYLE: &str = "body{font-family:sans-serif;max-width:60em;margin:2em auto;padding:0 1em}\
+table{border-collapse:collapse}td,th{border:1px solid #ccc;padding:.3em .6em}\
+svg text{font-size:12px}nav{margin-bottom:1em}";
+
+// Diagram layout
+const BOX_WIDTH: usize = 160;
+const BOX_HEIGHT: usize = 30;
+const ROW_GAP: usize = 50;
+
+/// File name part for a state or sequence; localized names may contain spaces
+fn slug(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_alphanumeric() || c == '_' { c } else { '-' })
+        .collect()
+}
+
+fn state_page(name: &str) -> String {
+    format!("state-{}.html", slug(name))
+}
+
+fn sequence_page(name: &str) -> String {
+    format!("sequence-{}.html", slug(name))
+}
+
+fn state_link(r: &StateRef) -> String {
+    format!(
+        "<a href=\"{}\">{}</a>[{}]",
+        state_page(&r.state),
+        escape(&r.state),
+        escape(&r.role_list())
+    )
+}
+
+fn layout(system: &MartialSystem, title: &str, body: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{} - {}</title>\n\
+         <style>{}</style>\n</head>\n<body>\n<nav><a href=\"index.html\">{}</a></nav>\n{}</body>\n</html>\n",
+        escape(title),
+        escape(&system.name),
+        STYLE,
+        escape(&system.name),
+        body
+    )
+}
+
+/// Box with a centered label, linking to the state page
+fn svg_box(out: &mut String, x: usize, y: usize, r: &StateRef) {
+    let _ = write!(
+        out,
+        "<a href=\"{}\"><rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" rx=\"4\" fill=\"#eef\" stroke=\"#336\"/>\
+         <text x=\"{}\" y=\"{}\" text-anchor=\"middle\">{}[{}]</text></a>",
+        state_page(&r.state),
+        x,
+        y,
+        BOX_WIDTH,
+        BOX_HEIGHT,
+        x + BOX_WIDTH / 2,
+        y + BOX_HEIGHT / 2 + 4,
+        escape(&r.state),
+        escape(&r.role_list())
+    );
+}
+
+fn svg_arrow(out: &mut String, (x1, y1): (usize, usize), (x2, y2): (usize, usize), label: &str) {
+    let _ = write!(
+        out,
+        "<line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\" stroke=\"#333\" marker-end=\"url(#arrow)\"/>\
+         <text x=\"{}\" y=\"{}\">{}</text>",
+        x1,
+        y1,
+        x2,
+        y2,
+        (x1 + x2) / 2 + 6,
+        (y1 + y2) / 2 + 4,
+        escape(label)
+    );
+}
+
+fn svg(width: usize, height: usize, content: &str) -> String {
+    format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\">\
+         <defs><marker id=\"arrow\" viewBox=\"0 0 10 10\" refX=\"10\" refY=\"5\" markerWidth=\"6\" markerHeight=\"6\" orient=\"auto\">\
+         <path d=\"M0,0 L10,5 L0,10 z\"/></marker></defs>{}</svg>\n",
+        width, height, content
+    )
+}
+
+/// Vertical chain of the steps of a sequence
+fn sequence_diagram(steps: &[SequenceStep]) -> String {
+    let mut content = String::new();
+    let x = 10;
+    let row = BOX_HEIGHT + ROW_GAP;
+    for (i, step) in steps.iter().enumerate() {
+        let y = 10 + i * row;
+        if i == 0 {
+            svg_box(&mut content, x, y, &step.from);
+        }
+        svg_box(&mut content, x, y + row, &step.to);
+        let center = x + BOX_WIDTH / 2;
+        svg_arrow(&mut content, (center, y + BOX_HEIGHT), (center, y + row), &step.action_name);
+    }
+    svg(BOX_WIDTH + 200, 20 + BOX_HEIGHT + steps.len() * row, &content)
+}
+
+/// Sources on the left, the state in the middle, targets on the right
+fn state_diagram(name: &str, incoming: &[&(&str, &SequenceStep)], outgoing: &[&(&str, &SequenceStep)]) -> String {
+    let mut content = String::new();
+    let column = BOX_WIDTH + 120;
+    let row = BOX_HEIGHT + 20;
+    let rows = incoming.len().max(outgoing.len()).max(1);
+    let middle = 10 + (rows - 1) * row / 2;
+
+    let center = StateRef {
+        state: name.into(),
+        role: "…".into(),
+        extra_roles: Vec::new(),
+        spans: Spans::default(),
+    };
+    svg_box(&mut content, 10 + column, middle, &center);
+
+    for (i, (_, step)) in incoming.iter().enumerate() {
+        let y = 10 + i * row;
+        svg_box(&mut content, 10, y, &step.from);
+        svg_arrow(
+            &mut content,
+            (10 + BOX_WIDTH, y + BOX_HEIGHT / 2),
+            (10 + column, middle + BOX_HEIGHT / 2),
+            &step.action_name,
+        );
+    }
+    for (i, (_, step)) in outgoing.iter().enumerate() {
+        let y = 10 + i * row;
+        svg_box(&mut content, 10 + 2 * column, y, &step.to);
+        svg_arrow(
+            &mut content,
+            (10 + column + BOX_WIDTH, middle + BOX_HEIGHT / 2),
+            (10 + 2 * column, y + BOX_HEIGHT / 2),
+            &step.action_name,
+        );
+    }
+    svg(20 + 2 * column + BOX_WIDTH, 20 + rows * row, &content)
+}
+
+/// Links to the videos of a step, each preceded by a space
+fn video_links(step: &SequenceStep) -> String {
+    step.videos()
+        .iter()
+        .map(|url| format!(" <a class=\"video\" href=\"{}\">▶ video</a>", escape(url)))
+        .collect()
+}
+
+fn step_list(out: &mut String, title: &str, steps: &[&(&str, &SequenceStep)]) {
+    if steps.is_empty() {
+        return;
+    }
+    let _ = writeln!(out, "<h2>{}</h2>\n<ul>", title);
+    for (sequence, step) in steps {
+        let _ = writeln!(
+            out,
+            "<li><strong>{}</strong> from {} to {} (<a href=\"{}\">{}</a>){}</li>",
+            escape(&step.action_name),
+            state_link(&step.from),
+            state_link(&step.to),
+            sequence_page(sequence),
+            escape(sequence),
+            video_links(step)
+        );
+    }
+    out.push_str("</ul>\n");
+}
+
+fn index(system: &MartialSystem) -> String {
+    let mut body = String::new();
+    let _ = writeln!(body, "<h1>{}</h1>", escape(&system.name));
+
+    let roles: BTreeSet<&String> = system.roles.iter().collect();
+    let roles: Vec<String> = roles.into_iter().map(|r| escape(r)).collect();
+    let _ = writeln!(body, "<h2>Roles</h2>\n<p>{}</p>", roles.join(", "));
+
+    let groups: Vec<&String> = system.groups.keys().collect();
+    for group in groups {
+        let _ = writeln!(body, "<h2>Group: {}</h2>\n<ul>", escape(group));
+        for state in &system.groups[group] {
+            let _ = writeln!(body, "<li><a href=\"{}\">{}</a></li>", state_page(state), escape(state));
+        }
+        body.push_str("</ul>\n");
+    }
+
+    let states: Vec<&String> = system.states.keys().collect();
+    body.push_str("<h2>States</h2>\n<ul>\n");
+    for state in states {
+        let _ = writeln!(body, "<li><a href=\"{}\">{}</a></li>", state_page(state), escape(state));
+    }
+    body.push_str("</ul>\n");
+
+    let sequences: Vec<&String> = system.sequences.keys().collect();
+    body.push_str("<h2>Sequences</h2>\n<ul>\n");
+    for sequence in sequences {
+        let _ = writeln!(
+            body,
+            "<li><a href=\"{}\">{}</a> ({} steps)</li>",
+            sequence_page(sequence),
+            escape(sequence),
+            system.sequences[sequence].steps.len()
+        );
+    }
+    body.push_str("</ul>\n");
+
+    layout(system, "Index", &body)
+}
+
+/// Generate every page of the site
+pub fn generate(system: &MartialSystem) -> Vec<Page> {
+    let mut pages = vec![Page {
+        path: "index.html".to_string(),
+        html: index(system),
+    }];
+    let steps = all_steps(system);
+
+    let states: Vec<&String> = system.states.keys().collect();
+    for name in states {
+        let state = &system.states[name];
+        let incoming: Vec<_> = steps.iter().filter(|(_, s)| &s.to.state == name).collect();
+        let outgoing: Vec<_> = steps.iter().filter(|(_, s)| &s.from.state == name).collect();
+
+        let mut body = String::new();
+        let _ = writeln!(body, "<h1>{}</h1>", escape(name));
+        if let Some(roles) = &state.allowed_roles {
+            let _ = writeln!(body, "<p>Roles: {}</p>", escape(&roles.join(", ")));
+        }
+        let groups: Vec<&String> = system
+            .groups
+            .iter()
+            .filter(|(_, members)| members.contains(name))
+            .map(|(group, _)| group)
+            .collect();
+        if !groups.is_empty() {
+            let groups: Vec<String> = groups.into_iter().map(|g| escape(g)).collect();
+            let _ = writeln!(body, "<p>Groups: {}</p>", groups.join(", "));
+        }
+        if incoming.is_empty() && outgoing.is_empty() {
+            body.push_str("<p>No techniques lead to or from this state.</p>\n");
+        } else {
+            body.push_str(&state_diagram(name, &incoming, &outgoing));
+        }
+        step_list(&mut body, "Entered by", &incoming);
+        step_list(&mut body, "Leads to", &outgoing);
+
+        pages.push(Page {
+            path: state_page(name),
+            html: layout(system, name, &body),
+        });
+    }
+
+    let sequences: Vec<&String> = system.sequences.keys().collect();
+    for name in sequences {
+        let sequence = &system.sequences[name];
+        let mut body = String::new();
+        let _ = writeln!(body, "<h1>{}</h1>", escape(name));
+        for url in sequence.videos() {
+            let _ = writeln!(body, "<p>Video: <a class=\"video\" href=\"{0}\">{0}</a></p>", escape(url));
+        }
+        body.push_str(&sequence_diagram(&sequence.steps));
+        body.push_str("<table>\n<tr><th>#</th><th>Action</th><th>From</th><th>To</th></tr>\n");
+        for (i, step) in sequence.steps.iter().enumerate() {
+            let _ = writeln!(
+                body,
+                "<tr><td>{}</td><td>{}{}</td><td>{}</td><td>{}</td></tr>",
+                i + 1,
+                escape(&step.action_name),
+                video_links(step),
+                state_link(&step.from),
+                state_link(&step.to)
+            );
+        }
+        body.push_str("</table>\n");
+
+        pages.push(Page {
+            path: sequence_page(name),
+            html: layout(system, name, &body),
+        });
+    }
+
+    pages
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loader::{load_sources, SourceFile};
+
+    fn system() -> MartialSystem {
+        let sources = vec![SourceFile::new(
+            "system.martial",
+            "roles { Top, Bottom }\n\
+             state Mount\n\
+             state Back\n\
+             state Guard\n\
+             sequence TakeBack:\n    \
+                 Roll: Mount[Top] -> Back[Top]",
+        )];
+        load_sources("club", &sources).unwrap()
+    }
+
+    #[test]
+    fn test_pages() {
+        let pages = generate(&system());
+        let paths: Vec<&str> = pages.iter().map(|p| p.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            vec![
+                "index.html",
+                "state-Back.html",
+                "state-Guard.html",
+                "state-Mount.html",
+                "sequence-TakeBack.html"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_cross_links_and_diagrams() {
+        let pages = generate(&system());
+        let page = |path: &str| &pages.iter().find(|p| p.path == path).unwrap().html;
+
+        assert!(page("index.html").contains("<a href=\"sequence-TakeBack.html\">TakeBack</a>"));
+        assert!(page("sequence-TakeBack.html").contains("<svg"));
+        assert!(page("sequence-TakeBack.html").contains("<a href=\"state-Back.html\">Back</a>[Top]"));
+        assert!(page("state-Mount.html").contains("<h2>Leads to</h2>"));
+        assert!(!page("state-Guard.html").contains("<svg"));
+    }
+
+    #[test]
+    fn test_video_links() {
+        let sources = vec![SourceFile::new(
+            "system.martial",
+            "roles { Top }\nstate Mount\n\
+             sequence Hold @video(\"https://videos.test/hold?a=1&b=2\"):\n    \
+             Settle: Mount[Top] -> Mount[Top] @video(\"https://videos.test/settle\")",
+        )];
+        let pages = generate(&load_sources("club", &sources).unwrap());
+        let page = |path: &str| &pages.iter().find(|p| p.path == path).unwrap().html;
+
+        assert!(page("sequence-Hold.html").contains("href=\"https://videos.test/hold?a=1&amp;b=2\""));
+        assert!(page("sequence-Hold.html").contains("Settle <a class=\"video\" href=\"https://videos.test/settle\">"));
+        assert!(page("state-Mount.html").contains("(<a href=\"sequence-Hold.html\">Hold</a>) <a class=\"video\""));
+    }
+}
@@ -0,0 +1,109 @@
+//! Stable serializations for golden-file tests
+//!
+//! [`MartialSystem::to_json`] and [`MartialGraph::to_json`] are already
+//! deterministic, but their layout follows the struct definitions. The
+//! snapshots here also put every object's keys in alphabetical order and
+//! group edges by sequence, so a field moved in the source does not churn
+//! every golden file. Ordered data, such as the steps of a sequence or the
+//! levels, keeps its order.
+
+use crate::graph::MartialGraph;
+use crate::prelude::*;
+use crate::semantic::MartialSystem;
+use serde_json::{Map, Value};
+
+/// Rebuild `value` with the keys of every object in sorted order
+fn canonical(value: Value) -> Value {
+    match value {
+        Value::Object(map) => {
+            let mut entries: Vec<(String, Value)> = map.into_iter().collect();
+            entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+            Value::Object(entries.into_iter().map(|(key, value)| (key, canonical(value))).collect::<Map<_, _>>())
+        }
+        Value::Array(values) => Value::Array(values.into_iter().map(canonical).collect()),
+        other => other,
+    }
+}
+
+/// The system as a JSON value in canonical order
+pub fn normalize_system(system: &MartialSystem) -> Value {
+    canonical(serde_json::to_value(system).unwrap_or(Value::Null))
+}
+
+/// The graph as a JSON value in canonical order
+///
+/// Edges are grouped by sequence, in the order of its steps.
+pub fn normalize_graph(graph: &MartialGraph) -> Value {
+    let mut graph = graph.clone();
+    graph.edges.sort_by(|a, b| a.sequence.cmp(&b.sequence));
+    graph.nodes.sort();
+    canonical(serde_json::to_value(&graph).unwrap_or(Value::Null))
+}
+
+fn pretty(value: &Value) -> String {
+    serde_json::to_string_pretty(value).unwrap_or_default() + "\n"
+}
+
+/// Snapshot of a system: pretty-printed JSON in canonical order
+pub fn system(system: &MartialSystem) -> String {
+    pretty(&normalize_system(system))
+}
+
+/// Snapshot of a graph: pretty-printed JSON in canonical order
+pub fn graph(graph: &MartialGraph) -> String {
+    pretty(&normalize_graph(graph))
+}
+
+/// The first line at which `actual` differs from the `expected` snapshot,
+/// as `line N: expected "..." but got "..."`
+pub fn diff(expected: &str, actual: &str) -> Option<String> {
+    let mut expected_lines = expected.lines();
+    let mut actual_lines = actual.lines();
+    for line in 1.. {
+        match (expected_lines.next(), actual_lines.next()) {
+            (None, None) => return None,
+            (expected, actual) if expected == actual => continue,
+            (expected, actual) => {
+                let show = |text: Option<&str>| text.map_or("end of snapshot".to_string(), |t| format!("{:?}", t.trim()));
+                return Some(format!("line {}: expected {} but got {}", line, show(expected), show(actual)));
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loader::{load_sources, SourceFile};
+
+    const SOURCE: &str = "roles { Top, Bottom, Neutral }\nstate Guard\nstate Mount\nstate Back\n\
+                          group Dominant { Mount, Back }\ngroup Bottom { Guard }\n\
+                          sequence Sweep:\n    Hip: Guard[Bottom] -> Mount[Top]\n    Take: Mount[Top] -> Back[Top]\n\
+                          sequence Pass:\n    Cut: Guard[Top] -> Mount[Top]\n";
+
+    #[test]
+    fn test_snapshots_are_stable() {
+        let snapshots: Vec<(String, String)> = (0..8)
+            .map(|_| {
+                let system = load_sources("club", &[SourceFile::new("a.martial", SOURCE)]).unwrap();
+                (super::system(&system), graph(&MartialGraph::from_system(&system)))
+            })
+            .collect();
+        assert!(snapshots.iter().all(|s| *s == snapshots[0]));
+
+        let (system, graph) = &snapshots[0];
+        assert!(system.contains("\"roles\": [\n    \"Bottom\",\n    \"Neutral\",\n    \"Top\"\n  ]"), "{}", system);
+        assert!(system.find("\"Back\": {").unwrap() < system.find("\"Guard\": {").unwrap());
+        let actions: Vec<&str> = graph.lines().filter_map(|l| l.trim().strip_prefix("\"action\": ")).collect();
+        assert_eq!(actions, ["\"Cut\",", "\"Hip\",", "\"Take\","]);
+        assert!(graph.ends_with("}\n"));
+    }
+
+    #[test]
+    fn test_diff() {
+        assert_eq!(diff("a\nb\n", "a\nb\n"), None);
+        assert_eq!(diff("a\nb\n", "a\nc\n").unwrap(), "line 2: expected \"b\" but got \"c\"");
+        assert_eq!(diff("a\n", "a\nb\n").unwrap(), "line 2: expected end of snapshot but got \"b\"");
+    }
+}
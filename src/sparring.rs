@@ -0,0 +1,380 @@
+//! Sparring log and usage heat map
+//!
+//! Live transitions are recorded one per line in a `sparring.log` file next
+//! to the `.martial` sources:
+//!
+//! ```text
+//! # date       from          ->  to           via action
+//! 2024-03-01 SideControl[Top] -> Mount[Top] via KneeSlide
+//! ```
+//!
+//! The date is optional; blank lines and `#` comments are ignored. The heat
+//! map counts how often each declared transition was used, and lists logged
+//! transitions that the system does not declare. The same counts rank the
+//! likely next actions from a position.
+
+use crate::graph::{Edge, MartialGraph, Node};
+use crate::train::parse_node;
+use crate::symbol::Symbol;
+use serde::Serialize;
+use std::fmt;
+
+/// Name of the log file inside a system directory
+pub const LOG_FILE: &str = "sparring.log";
+
+/// A transition recorded during sparring
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogEntry {
+    /// Day of the roll, as `YYYY-MM-DD`
+    pub date: Option<String>,
+    pub from: Node,
+    pub to: Node,
+    pub action: String,
+}
+
+impl fmt::Display for LogEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(date) = &self.date {
+            write!(f, "{} ", date)?;
+        }
+        write!(f, "{} -> {} via {}", self.from.id(), self.to.id(), self.action)
+    }
+}
+
+/// Error in a log line
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogError {
+    pub message: String,
+    /// 1-based line in the log, if read from one
+    pub line: Option<usize>,
+}
+
+impl fmt::Display for LogError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "Sparring log error at line {}: {}", line, self.message),
+            None => write!(f, "Sparring log error: {}", self.message),
+        }
+    }
+}
+
+impl core::error::Error for LogError {}
+
+fn is_date(text: &str) -> bool {
+    let parts: Vec<&str> = text.split('-').collect();
+    parts.len() == 3
+        && [4, 2, 2].iter().zip(&parts).all(|(len, part)| part.len() == *len && part.chars().all(|c| c.is_ascii_digit()))
+}
+
+/// Parse a single entry: `[YYYY-MM-DD] From[Role] -> To[Role] via Action`
+pub fn parse_entry(text: &str) -> Result<LogEntry, LogError> {
+    let error = |message: String| LogError { message, line: None };
+    let text = text.trim();
+
+    let (date, rest) = match text.split_once(char::is_whitespace) {
+        Some((first, rest)) if is_date(first) => (Some(first.to_string()), rest),
+        _ => (None, text),
+    };
+    let (transition, action) = rest
+        .rsplit_once(" via ")
+        .ok_or_else(|| error(format!("Expected 'From[Role] -> To[Role] via Action', got '{}'", text)))?;
+    let (from, to) = transition
+        .split_once("->")
+        .ok_or_else(|| error(format!("Expected '->' in '{}'", transition.trim())))?;
+
+    let node = |text: &str| parse_node(text).ok_or_else(|| error(format!("Expected State[Role], got '{}'", text.trim())));
+    let action = action.trim();
+    if action.is_empty() || !action.chars().all(|c| c.is_alphanumeric() || c == '_') {
+        return Err(error(format!("Invalid action name '{}'", action)));
+    }
+
+    Ok(LogEntry {
+        date,
+        from: node(from)?,
+        to: node(to)?,
+        action: action.to_string(),
+    })
+}
+
+/// Parse a whole log
+pub fn parse_log(content: &str) -> Result<Vec<LogEntry>, LogError> {
+    let mut entries = Vec::new();
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let entry = parse_entry(line).map_err(|e| LogError {
+            line: Some(i + 1),
+            ..e
+        })?;
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// Today's date as `YYYY-MM-DD` (UTC)
+pub fn today() -> String {
+    let days = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs() / 86_400)
+        .unwrap_or(0) as i64;
+
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+fn matches(edge: &Edge, entry: &LogEntry) -> bool {
+    edge.from == entry.from && edge.to == entry.to && edge.action == entry.action
+}
+
+/// Whether the system declares the logged transition
+pub fn is_declared(graph: &MartialGraph, entry: &LogEntry) -> bool {
+    graph.edges.iter().any(|edge| matches(edge, entry))
+}
+
+/// A declared edge and how often it was used
+#[derive(Debug, Clone, Serialize)]
+pub struct EdgeUsage {
+    #[serde(flatten)]
+    pub edge: Edge,
+    pub count: usize,
+}
+
+/// A logged transition the system does not declare
+#[derive(Debug, Clone, Serialize)]
+pub struct UndeclaredUsage {
+    pub from: Node,
+    pub to: Node,
+    pub action: String,
+    pub count: usize,
+}
+
+/// Usage counts of a system's transitions
+#[derive(Debug, Clone, Serialize)]
+pub struct Heatmap {
+    pub system_name: String,
+    pub edges: Vec<EdgeUsage>,
+    pub undeclared: Vec<UndeclaredUsage>,
+}
+
+impl Heatmap {
+    /// Count the log entries against the graph
+    ///
+    /// An edge declared by several sequences gets the full count for each.
+    pub fn new(graph: &MartialGraph, entries: &[LogEntry]) -> Self {
+        let edges = graph
+            .edges
+            .iter()
+            .map(|edge| EdgeUsage {
+                edge: edge.clone(),
+                count: entries.iter().filter(|entry| matches(edge, entry)).count(),
+            })
+            .collect();
+
+        let mut undeclared: Vec<UndeclaredUsage> = Vec::new();
+        for entry in entries.iter().filter(|entry| !is_declared(graph, entry)) {
+            match undeclared
+                .iter_mut()
+                .find(|u| u.from == entry.from && u.to == entry.to && u.action == entry.action)
+            {
+                Some(usage) => usage.count += 1,
+                None => undeclared.push(UndeclaredUsage {
+                    from: entry.from.clone(),
+                    to: entry.to.clone(),
+                    action: entry.action.clone(),
+                    count: 1,
+                }),
+            }
+        }
+        undeclared.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.action.cmp(&b.action)));
+
+        Heatmap {
+            system_name: graph.system_name.clone(),
+            edges,
+            undeclared,
+        }
+    }
+
+    /// Export as JSON
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string_pretty(self)
+    }
+
+    /// Export the graph as DOT with edges colored from yellow (rarely used)
+    /// to red (most used); unused edges are grey and dashed
+    pub fn to_dot(&self, graph: &MartialGraph) -> String {
+        let max = self.edges.iter().map(|u| u.count).max().unwrap_or(0);
+        graph.to_dot_with(|edge| {
+            let count = self
+                .edges
+                .iter()
+                .find(|u| u.edge == *edge)
+                .map(|u| u.count)
+                .unwrap_or(0);
+            if count == 0 {
+                return format!("label=\"{}\", color=grey, fontcolor=grey, style=dashed", edge.action);
+            }
+            let ratio = count as f64 / max as f64;
+            format!(
+                "label=\"{} ({})\", color=\"{:.3} 1.000 1.000\", penwidth={:.1}",
+                edge.action,
+                count,
+                0.15 * (1.0 - ratio),
+                1.0 + 4.0 * ratio
+            )
+        })
+    }
+}
+
+/// A possible next action from a position
+#[derive(Debug, Clone, Serialize)]
+pub struct Suggestion {
+    pub action: Symbol,
+    pub to: Node,
+    /// Sequences declaring the transition; empty if it is only logged
+    pub sequences: Vec<Symbol>,
+    /// Times the transition was logged
+    pub count: usize,
+    /// Estimated chance of taking it from the position
+    pub probability: f64,
+}
+
+/// Rank the actions from `from` by how often they were logged, most likely first
+///
+/// Declared actions and logged but undeclared ones are both candidates. The
+/// estimate adds one to every count, so that actions never logged yet keep
+/// a small chance.
+pub fn suggestions(graph: &MartialGraph, entries: &[LogEntry], from: &Node) -> Vec<Suggestion> {
+    fn candidate<'a>(suggestions: &'a mut Vec<Suggestion>, action: &str, to: &Node) -> &'a mut Suggestion {
+        let index = match suggestions.iter().position(|s| s.action == action && s.to == *to) {
+            Some(index) => index,
+            None => {
+                suggestions.push(Suggestion {
+                    action: action.into(),
+                    to: to.clone(),
+                    sequences: Vec::new(),
+                    count: 0,
+                    probability: 0.0,
+                });
+                suggestions.len() - 1
+            }
+        };
+        &mut suggestions[index]
+    }
+
+    let mut suggestions = Vec::new();
+    for edge in graph.edges.iter().filter(|edge| edge.from == *from && !edge.reaction) {
+        candidate(&mut suggestions, &edge.action, &edge.to)
+            .sequences
+            .push(edge.sequence.clone());
+    }
+    for entry in entries.iter().filter(|entry| entry.from == *from) {
+        candidate(&mut suggestions, &entry.action, &entry.to).count += 1;
+    }
+
+    let total: usize = suggestions.iter().map(|s| s.count + 1).sum();
+    for suggestion in &mut suggestions {
+        suggestion.sequences.sort();
+        suggestion.probability = (suggestion.count + 1) as f64 / total as f64;
+    }
+    suggestions.sort_by(|a, b| {
+        b.count
+            .cmp(&a.count)
+            .then_with(|| a.action.cmp(&b.action))
+            .then_with(|| a.to.id().cmp(&b.to.id()))
+    });
+    suggestions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loader::{load_sources, SourceFile};
+
+    fn graph() -> MartialGraph {
+        let source = "roles { Top, Bottom }\nstate Side\nstate Mount\n\
+                      sequence Climb:\n    KneeSlide: Side[Top] -> Mount[Top]\n\
+                      sequence Escape:\n    Bridge: Mount[Bottom] -> Side[Bottom]";
+        let system = load_sources("test", &[SourceFile::new("a.martial", source)]).unwrap();
+        MartialGraph::from_system(&system)
+    }
+
+    #[test]
+    fn test_parse_entry() {
+        let entry = parse_entry("2024-03-01 SideControl[Top] -> Mount[Top] via KneeSlide").unwrap();
+        assert_eq!(entry.date.as_deref(), Some("2024-03-01"));
+        assert_eq!(entry.from.id(), "SideControl[Top]");
+        assert_eq!(entry.action, "KneeSlide");
+        assert_eq!(entry.to_string(), "2024-03-01 SideControl[Top] -> Mount[Top] via KneeSlide");
+
+        assert_eq!(parse_entry("A[X]->B[Y] via Go").unwrap().date, None);
+        assert!(parse_entry("SideControl[Top] -> Mount[Top]").is_err());
+        assert!(parse_entry("SideControl -> Mount[Top] via KneeSlide").is_err());
+        assert!(parse_entry("A[X] -> B[Y] via two words").is_err());
+    }
+
+    #[test]
+    fn test_parse_log() {
+        let log = "# Tuesday class\n\nA[X] -> B[Y] via Go\nbroken\n";
+        assert_eq!(parse_log(log).unwrap_err().line, Some(4));
+        assert_eq!(parse_log("# only comments\n").unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_today_format() {
+        let today = today();
+        assert!(is_date(&today), "{}", today);
+        assert!(today.as_str() > "2024-01-01");
+    }
+
+    #[test]
+    fn test_heatmap() {
+        let graph = graph();
+        let log = "Side[Top] -> Mount[Top] via KneeSlide\n\
+                   2024-03-01 Side[Top] -> Mount[Top] via KneeSlide\n\
+                   Mount[Top] -> Mount[Top] via Armbar\n";
+        let heatmap = Heatmap::new(&graph, &parse_log(log).unwrap());
+
+        let counts: Vec<(&str, usize)> = heatmap.edges.iter().map(|u| (u.edge.action.as_str(), u.count)).collect();
+        assert!(counts.contains(&("KneeSlide", 2)));
+        assert!(counts.contains(&("Bridge", 0)));
+        assert_eq!(heatmap.undeclared.len(), 1);
+        assert_eq!(heatmap.undeclared[0].action, "Armbar");
+
+        let dot = heatmap.to_dot(&graph);
+        assert!(dot.contains("label=\"KneeSlide (2)\", color=\"0.000 1.000 1.000\", penwidth=5.0"));
+        assert!(dot.contains("label=\"Bridge\", color=grey"));
+        assert!(heatmap.to_json().unwrap().contains("\"count\": 2"));
+    }
+
+    #[test]
+    fn test_suggestions() {
+        let graph = graph();
+        let log = "Side[Top] -> Mount[Top] via KneeSlide\n\
+                   Side[Top] -> Mount[Top] via KneeSlide\n\
+                   Side[Top] -> Back[Top] via Roll\n\
+                   Mount[Top] -> Side[Top] via Dismount\n";
+        let side = parse_node("Side[Top]").unwrap();
+        let ranked = suggestions(&graph, &parse_log(log).unwrap(), &side);
+
+        let summary: Vec<(&str, usize, &[Symbol])> =
+            ranked.iter().map(|s| (s.action.as_str(), s.count, s.sequences.as_slice())).collect();
+        assert_eq!(summary, vec![("KneeSlide", 2, &["Climb".into()][..]), ("Roll", 1, &[][..])]);
+        assert_eq!(ranked[0].probability, 0.6);
+
+        let unlogged = suggestions(&graph, &[], &parse_node("Mount[Bottom]").unwrap());
+        assert_eq!(unlogged.len(), 1);
+        assert_eq!(unlogged[0].probability, 1.0);
+        assert!(suggestions(&graph, &[], &parse_node("Nowhere[Top]").unwrap()).is_empty());
+    }
+}
@@ -0,0 +1,292 @@
+//! Storyboards of single sequences
+//!
+//! A linear sequence such as a line of a kata reads best as a timeline: the
+//! stances in order from left to right, each action on the arrow between
+//! the stance it starts from and the one it ends in. `mat storyboard` draws
+//! it as SVG, or as a Markdown table for handouts. When the steps give
+//! their direction with `@turn` and `@advance`, the storyboard also shows
+//! the floor pattern, see [`crate::embusen`].
+
+use crate::ast::StateRef;
+use crate::embusen::{self, Place};
+use crate::export::escape;
+use crate::prelude::*;
+use alloc::collections::BTreeMap;
+use crate::semantic::MartialSystem;
+use core::fmt::Write;
+
+/// Width of a character of a label in the SVG drawing
+const CHAR_WIDTH: usize = 8;
+const BOX_HEIGHT: usize = 44;
+const MARGIN: usize = 20;
+/// Height of the title above the timeline
+const TITLE_HEIGHT: usize = 40;
+/// Length of a step in the drawing of the floor pattern
+const FLOOR_STEP: i32 = 50;
+
+/// Format of a storyboard
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StoryboardFormat {
+    Svg,
+    Markdown,
+}
+
+impl StoryboardFormat {
+    pub const ALL: [StoryboardFormat; 2] = [StoryboardFormat::Svg, StoryboardFormat::Markdown];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            StoryboardFormat::Svg => "svg",
+            StoryboardFormat::Markdown => "md",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        StoryboardFormat::ALL.into_iter().find(|format| format.name() == name.to_lowercase())
+    }
+
+    /// Guess the format from a file extension
+    pub fn from_path(path: &str) -> Option<Self> {
+        path.rsplit_once('.').and_then(|(_, extension)| Self::from_name(extension))
+    }
+}
+
+/// A stance of the timeline
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    /// Action leading to the stance from the previous frame, `None` for the
+    /// starting stance
+    pub action: Option<String>,
+    pub stance: StateRef,
+    /// Where the performer stands, if the steps give their direction
+    pub place: Option<Place>,
+}
+
+/// The timeline of a sequence
+#[derive(Debug, Clone, PartialEq)]
+pub struct Storyboard {
+    pub system_name: String,
+    pub sequence: String,
+    pub frames: Vec<Frame>,
+}
+
+impl Storyboard {
+    /// The storyboard of the sequence `name` of `system`, `None` if it
+    /// declares no such sequence
+    pub fn new(system: &MartialSystem, name: &str) -> Option<Self> {
+        let sequence = system.sequences.get(name)?;
+        let places = if sequence.steps.iter().any(embusen::has_direction) {
+            embusen::walk(&sequence.steps)
+        } else {
+            Vec::new()
+        };
+        // Steps are chained, each starting where the previous one ended
+        let start = sequence.steps.first().map(|step| (None, &step.from));
+        let frames = start
+            .into_iter()
+            .chain(sequence.steps.iter().map(|step| (Some(step.action_name.to_string()), &step.to)))
+            .enumerate()
+            .map(|(i, (action, stance))| Frame {
+                action,
+                stance: stance.clone(),
+                place: places.get(i).copied(),
+            })
+            .collect();
+        Some(Storyboard {
+            system_name: system.name.clone(),
+            sequence: name.to_string(),
+            frames,
+        })
+    }
+
+    /// The storyboard in `format`
+    pub fn render(&self, format: StoryboardFormat) -> String {
+        match format {
+            StoryboardFormat::Svg => self.to_svg(),
+            StoryboardFormat::Markdown => self.to_markdown(),
+        }
+    }
+
+    /// A standalone SVG drawing, stances left to right with the actions
+    /// numbered above the arrows between them, and the floor pattern below
+    /// if the steps give their direction
+    pub fn to_svg(&self) -> String {
+        let width = self
+            .frames
+            .iter()
+            .map(|frame| frame.stance.state.len().max(frame.stance.role_list().len() + 2))
+            .max()
+            .unwrap_or(0)
+            * CHAR_WIDTH
+            + 20;
+        let y = TITLE_HEIGHT + MARGIN;
+        let mut content = String::new();
+        let mut x = MARGIN;
+        // The n-th action leads to the n-th frame after the start
+        for (step, frame) in self.frames.iter().enumerate() {
+            if let Some(action) = &frame.action {
+                let label = format!("{}. {}", step, action);
+                let gap = (label.len() * CHAR_WIDTH).max(40) + 20;
+                let _ = writeln!(
+                    content,
+                    "<line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\" stroke=\"#333\" marker-end=\"url(#arrow)\"/>\
+                     <text x=\"{}\" y=\"{}\" text-anchor=\"middle\" font-size=\"12\">{}</text>",
+                    x,
+                    y + BOX_HEIGHT / 2,
+                    x + gap,
+                    y + BOX_HEIGHT / 2,
+                    x + gap / 2,
+                    y + BOX_HEIGHT / 2 - 6,
+                    escape(&label)
+                );
+                x += gap;
+            }
+            let _ = writeln!(
+                content,
+                "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" rx=\"6\" fill=\"#eef\" stroke=\"#336\"/>\
+                 <text x=\"{}\" y=\"{}\" text-anchor=\"middle\">{}</text>\
+                 <text x=\"{}\" y=\"{}\" text-anchor=\"middle\" font-size=\"11\">[{}]</text>",
+                x,
+                y,
+                width,
+                BOX_HEIGHT,
+                x + width / 2,
+                y + 19,
+                escape(&frame.stance.state),
+                x + width / 2,
+                y + 35,
+                escape(&frame.stance.role_list())
+            );
+            x += width;
+        }
+        let mut height = y + BOX_HEIGHT + MARGIN;
+        let floor = self.floor_svg(height + MARGIN);
+        if let Some((floor, width, floor_height)) = floor {
+            content.push_str(&floor);
+            x = x.max(width);
+            height += MARGIN + floor_height;
+        }
+
+        format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" font-family=\"sans-serif\" font-size=\"14\">\n\
+             <defs><marker id=\"arrow\" viewBox=\"0 0 10 10\" refX=\"10\" refY=\"5\" markerWidth=\"6\" markerHeight=\"6\" orient=\"auto\">\
+             <path d=\"M0,0 L10,5 L0,10 z\"/></marker></defs>\n\
+             <text x=\"{}\" y=\"{}\" font-size=\"18\" font-weight=\"bold\">{}</text>\n{}</svg>\n",
+            (x + MARGIN).max(self.sequence.len() * 11 + 2 * MARGIN),
+            height,
+            MARGIN,
+            MARGIN + 18,
+            escape(&self.sequence),
+            content
+        )
+    }
+
+    /// The floor pattern drawn from the top at `top`, each point numbered
+    /// with the actions ending there, with the width and height it takes;
+    /// `None` if the steps give no direction
+    fn floor_svg(&self, top: usize) -> Option<(String, usize, usize)> {
+        let places: Vec<Place> = self.frames.iter().map(|frame| frame.place).collect::<Option<_>>()?;
+        let xs = places.iter().map(|place| place.point.x);
+        let ys = places.iter().map(|place| place.point.y);
+        let (left, right) = (xs.clone().min()?, xs.max()?);
+        let (bottom, front) = (ys.clone().min()?, ys.max()?);
+        // Forward is up, as seen from behind the performer at the start
+        let origin_x = MARGIN as i32 + FLOOR_STEP / 2;
+        let origin_y = (top + 30) as i32 + FLOOR_STEP / 2;
+        let at = |place: &Place| {
+            (origin_x + (place.point.x - left) * FLOOR_STEP, origin_y + (front - place.point.y) * FLOOR_STEP)
+        };
+
+        let mut out = format!(
+            "<text x=\"{}\" y=\"{}\" font-size=\"14\" font-weight=\"bold\">Floor pattern</text>\n",
+            MARGIN,
+            top + 14
+        );
+        let points: Vec<String> = places.iter().map(&at).map(|(x, y)| format!("{},{}", x, y)).collect();
+        let _ = writeln!(out, "<polyline points=\"{}\" fill=\"none\" stroke=\"#336\" stroke-width=\"2\"/>", points.join(" "));
+        let mut labels: BTreeMap<(i32, i32), Vec<String>> = BTreeMap::new();
+        for (step, place) in places.iter().enumerate() {
+            labels.entry(at(place)).or_default().push(if step == 0 { "start".to_string() } else { step.to_string() });
+        }
+        for ((x, y), label) in &labels {
+            let _ = writeln!(
+                out,
+                "<circle cx=\"{}\" cy=\"{}\" r=\"5\" fill=\"#336\"/><text x=\"{}\" y=\"{}\" font-size=\"11\">{}</text>",
+                x,
+                y,
+                x + 8,
+                y - 8,
+                label.join(", ")
+            );
+        }
+        let width = origin_x + (right - left) * FLOOR_STEP + FLOOR_STEP / 2 + 2 * MARGIN as i32;
+        let height = 30 + (front - bottom + 1) * FLOOR_STEP;
+        Some((out, width as usize, height as usize))
+    }
+
+    /// A Markdown table of the actions, each with the stance it ends in and,
+    /// if the steps give their direction, where the performer then stands
+    pub fn to_markdown(&self) -> String {
+        let floor = self.frames.iter().any(|frame| frame.place.is_some());
+        let mut out = format!("# {}\n\n| # | Action | Stance |", self.sequence);
+        out.push_str(if floor { " Floor |\n|---|---|---|---|\n" } else { "\n|---|---|---|\n" });
+        for (step, frame) in self.frames.iter().enumerate() {
+            let number = if step == 0 { String::new() } else { step.to_string() };
+            let _ = write!(out, "| {} | {} | {} |", number, frame.action.as_deref().unwrap_or(""), frame.stance);
+            match frame.place {
+                Some(place) => {
+                    let _ = writeln!(out, " {} ({}, {}) |", place.arrow(), place.point.x, place.point.y);
+                }
+                None => out.push('\n'),
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loader::{self, SourceFile};
+
+    #[test]
+    fn test_storyboard() {
+        let source = "roles { Migi, Hidari }\nstate Yoi\nstate Zenkutsu\n\
+                      sequence FirstLine:\n    GedanBarai: Yoi[Migi] -> Zenkutsu[Hidari]\n    OiZuki: Zenkutsu[Hidari] -> Zenkutsu[Migi]";
+        let system = loader::load_sources("heian", &[SourceFile::new("heian.martial", source)]).unwrap();
+        let storyboard = Storyboard::new(&system, "FirstLine").unwrap();
+        let frames: Vec<(Option<&str>, String)> =
+            storyboard.frames.iter().map(|frame| (frame.action.as_deref(), frame.stance.to_string())).collect();
+        assert_eq!(
+            frames,
+            [
+                (None, "Yoi[Migi]".to_string()),
+                (Some("GedanBarai"), "Zenkutsu[Hidari]".to_string()),
+                (Some("OiZuki"), "Zenkutsu[Migi]".to_string()),
+            ]
+        );
+
+        let markdown = storyboard.to_markdown();
+        assert!(markdown.starts_with("# FirstLine\n\n| # | Action | Stance |\n|---|---|---|\n|  |  | Yoi[Migi] |\n"), "{}", markdown);
+        assert!(markdown.ends_with("| 1 | GedanBarai | Zenkutsu[Hidari] |\n| 2 | OiZuki | Zenkutsu[Migi] |\n"), "{}", markdown);
+
+        let svg = storyboard.to_svg();
+        assert!(svg.starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\""), "{}", svg);
+        assert_eq!(svg.matches("<rect ").count(), 3);
+        assert_eq!(svg.matches("<line ").count(), 2);
+        assert!(svg.contains(">1. GedanBarai</text>") && svg.contains(">[Hidari]</text>"), "{}", svg);
+
+        assert!(Storyboard::new(&system, "SecondLine").is_none());
+
+        let source = source.replace("Zenkutsu[Hidari]\n", "Zenkutsu[Hidari] @turn(90L) @advance\n");
+        let system = loader::load_sources("heian", &[SourceFile::new("heian.martial", &source)]).unwrap();
+        let storyboard = Storyboard::new(&system, "FirstLine").unwrap();
+        let markdown = storyboard.to_markdown();
+        assert!(markdown.contains("| Stance | Floor |\n|---|---|---|---|\n|  |  | Yoi[Migi] | ↑ (0, 0) |\n"), "{}", markdown);
+        assert!(markdown.ends_with("| 2 | OiZuki | Zenkutsu[Migi] | ← (-1, 0) |\n"), "{}", markdown);
+        let svg = storyboard.to_svg();
+        assert!(svg.contains(">Floor pattern</text>") && svg.contains(">1, 2</text>"), "{}", svg);
+        assert_eq!(StoryboardFormat::from_path("out/line.MD"), Some(StoryboardFormat::Markdown));
+        assert_eq!(StoryboardFormat::from_path("line.png"), None);
+    }
+}
@@ -0,0 +1,242 @@
+//! Interned identifiers
+//!
+//! State, role and action names are repeated in every step that mentions
+//! them and in every node and edge of the graph. A [`Symbol`] stores each
+//! distinct name once, so copies are reference-count bumps and equality is
+//! a pointer comparison. Interned names live for the rest of the process.
+//!
+//! Without the `std` feature there is no global lock to guard the interner:
+//! every symbol owns its name and equality compares the text.
+
+use crate::prelude::*;
+use alloc::borrow::Cow;
+use alloc::sync::Arc;
+use core::borrow::Borrow;
+use core::fmt;
+use core::hash::{Hash, Hasher};
+use core::ops::Deref;
+use schemars::{JsonSchema, Schema, SchemaGenerator};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+#[cfg(feature = "std")]
+use std::collections::HashSet;
+#[cfg(feature = "std")]
+use std::sync::{Mutex, OnceLock};
+
+/// An interned name
+///
+/// Behaves like a `&str`: it derefs to one, displays as one and compares
+/// with `str` and `String`.
+#[derive(Clone, Eq)]
+pub struct Symbol(Arc<str>);
+
+#[cfg(feature = "std")]
+fn interner() -> &'static Mutex<HashSet<Arc<str>>> {
+    static INTERNER: OnceLock<Mutex<HashSet<Arc<str>>>> = OnceLock::new();
+    INTERNER.get_or_init(Default::default)
+}
+
+impl Symbol {
+    /// Intern `name`, reusing the stored copy if it was seen before
+    #[cfg(feature = "std")]
+    pub fn new(name: &str) -> Self {
+        let mut names = interner().lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(existing) = names.get(name) {
+            return Symbol(existing.clone());
+        }
+        let interned: Arc<str> = Arc::from(name);
+        names.insert(interned.clone());
+        Symbol(interned)
+    }
+
+    /// Wrap `name`; without std, symbols are not interned
+    #[cfg(not(feature = "std"))]
+    pub fn new(name: &str) -> Self {
+        Symbol(Arc::from(name))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Default for Symbol {
+    fn default() -> Self {
+        Symbol::new("")
+    }
+}
+
+impl Deref for Symbol {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl AsRef<str> for Symbol {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Borrow<str> for Symbol {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+impl PartialEq for Symbol {
+    fn eq(&self, other: &Self) -> bool {
+        // Equal interned names share the same allocation
+        if cfg!(feature = "std") {
+            Arc::ptr_eq(&self.0, &other.0)
+        } else {
+            self.0 == other.0
+        }
+    }
+}
+
+// Hashes like the name itself, as required by `Borrow<str>`
+impl Hash for Symbol {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.as_str().hash(state)
+    }
+}
+
+impl PartialOrd for Symbol {
+    fn partial_cmp(&self, other: &Self) -> Option<core::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Symbol {
+    fn cmp(&self, other: &Self) -> core::cmp::Ordering {
+        self.as_str().cmp(other.as_str())
+    }
+}
+
+impl PartialEq<str> for Symbol {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for Symbol {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl PartialEq<String> for Symbol {
+    fn eq(&self, other: &String) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<Symbol> for str {
+    fn eq(&self, other: &Symbol) -> bool {
+        self == other.as_str()
+    }
+}
+
+impl PartialEq<Symbol> for &str {
+    fn eq(&self, other: &Symbol) -> bool {
+        *self == other.as_str()
+    }
+}
+
+impl PartialEq<Symbol> for String {
+    fn eq(&self, other: &Symbol) -> bool {
+        self == other.as_str()
+    }
+}
+
+impl From<&str> for Symbol {
+    fn from(name: &str) -> Self {
+        Symbol::new(name)
+    }
+}
+
+impl From<String> for Symbol {
+    fn from(name: String) -> Self {
+        Symbol::new(&name)
+    }
+}
+
+impl From<&String> for Symbol {
+    fn from(name: &String) -> Self {
+        Symbol::new(name)
+    }
+}
+
+impl From<Symbol> for String {
+    fn from(symbol: Symbol) -> Self {
+        symbol.as_str().to_string()
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self)
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl Serialize for Symbol {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Symbol {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = Cow::<str>::deserialize(deserializer)?;
+        Ok(Symbol::new(&name))
+    }
+}
+
+impl JsonSchema for Symbol {
+    fn inline_schema() -> bool {
+        true
+    }
+
+    fn schema_name() -> Cow<'static, str> {
+        String::schema_name()
+    }
+
+    fn json_schema(generator: &mut SchemaGenerator) -> Schema {
+        String::json_schema(generator)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_interning() {
+        let a = Symbol::new("Mount");
+        let b = Symbol::from(String::from("Mount"));
+        assert!(Arc::ptr_eq(&a.0, &b.0));
+        assert_eq!(a, b);
+        assert_ne!(a, Symbol::new("Guard"));
+        assert_eq!(a, "Mount");
+        assert_eq!("Mount", a);
+        assert!(Symbol::new("Guard") < a);
+
+        let mut counts: HashMap<Symbol, usize> = HashMap::new();
+        *counts.entry(a.clone()).or_default() += 1;
+        assert_eq!(counts.get("Mount"), Some(&1));
+
+        assert_eq!(format!("{}[{:?}]", a, b), "Mount[\"Mount\"]");
+        assert_eq!(serde_json::to_string(&a).unwrap(), "\"Mount\"");
+        let parsed: Symbol = serde_json::from_str("\"Mount\"").unwrap();
+        assert_eq!(parsed, a);
+    }
+}
@@ -0,0 +1,114 @@
+//! Generation of large synthetic systems
+//!
+//! Produces valid systems of arbitrary size for benchmarks and load tests.
+//! Sequences are random walks over numbered states, so the graph has the
+//! branching and cycles of a real system. The same seed always gives the
+//! same sources.
+
+use crate::loader::SourceFile;
+use std::fmt::Write;
+use std::path::Path;
+
+/// Size and shape of a synthetic system
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SyntheticOptions {
+    pub states: usize,
+    pub sequences: usize,
+    /// Upper bound on the steps of a sequence
+    pub max_steps: usize,
+    /// Distinct action names
+    pub actions: usize,
+    /// Sequences per `sequences_NNN.martial` file
+    pub sequences_per_file: usize,
+    pub seed: u64,
+}
+
+impl Default for SyntheticOptions {
+    fn default() -> Self {
+        SyntheticOptions {
+            states: 1000,
+            sequences: 5000,
+            max_steps: 4,
+            actions: 200,
+            sequences_per_file: 250,
+            seed: 1,
+        }
+    }
+}
+
+/// Deterministic xorshift generator, enough to spread names around
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Rng(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    fn below(&mut self, bound: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % bound.max(1) as u64) as usize
+    }
+}
+
+/// Generate the files of a synthetic system under `dir`
+///
+/// Roles go to `roles.martial`, states to `states.martial` and sequences
+/// to numbered files of [`SyntheticOptions::sequences_per_file`] each.
+pub fn generate(dir: &str, options: &SyntheticOptions) -> Vec<SourceFile> {
+    let path = |name: &str| Path::new(dir).join(name).to_string_lossy().into_owned();
+    let states = options.states.max(1);
+    let mut rng = Rng::new(options.seed);
+
+    let mut sources = vec![SourceFile::new(path("roles.martial"), "roles { Top, Bottom }\n")];
+    let mut content = String::new();
+    for s in 0..states {
+        let _ = writeln!(content, "state S{}", s);
+    }
+    sources.push(SourceFile::new(path("states.martial"), content));
+
+    let per_file = options.sequences_per_file.max(1);
+    for (file, first) in (0..options.sequences).step_by(per_file).enumerate() {
+        let mut content = String::new();
+        for q in first..(first + per_file).min(options.sequences) {
+            let role = if rng.below(2) == 0 { "Top" } else { "Bottom" };
+            let mut from = rng.below(states);
+            let _ = writeln!(content, "sequence Q{}:", q);
+            for _ in 0..1 + rng.below(options.max_steps.max(1)) {
+                let to = rng.below(states);
+                let action = rng.below(options.actions);
+                let _ = writeln!(content, "    A{}: S{}[{}] -> S{}[{}]", action, from, role, to, role);
+                from = to;
+            }
+            content.push('\n');
+        }
+        sources.push(SourceFile::new(path(&format!("sequences_{:03}.martial", file)), content));
+    }
+    sources
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loader::load_sources;
+
+    #[test]
+    fn test_generate() {
+        let options = SyntheticOptions {
+            states: 50,
+            sequences: 120,
+            sequences_per_file: 50,
+            ..SyntheticOptions::default()
+        };
+        let sources = generate("synthetic", &options);
+        let names: Vec<&str> = sources.iter().map(|s| s.path.as_str()).collect();
+        assert_eq!(names.len(), 5);
+        assert_eq!(names[4], Path::new("synthetic").join("sequences_002.martial").to_str().unwrap());
+
+        let system = load_sources("synthetic", &sources).unwrap();
+        assert_eq!((system.states.len(), system.sequences.len()), (50, 120));
+        assert_eq!(generate("synthetic", &options), sources);
+        assert_ne!(generate("synthetic", &SyntheticOptions { seed: 2, ..options }), sources);
+    }
+}
@@ -0,0 +1,231 @@
+//! Interactive flow trainer
+//!
+//! Walks the graph from a starting position: at each step the current
+//! position and its outgoing actions are shown, and the next action is
+//! either chosen by the user or drawn at random. Dead ends end the round.
+
+use crate::graph::{Edge, MartialGraph, Node};
+use std::io::{self, BufRead, Write};
+
+/// Parse a `State[Role]` node reference
+pub fn parse_node(text: &str) -> Option<Node> {
+    let text = text.trim();
+    let open = text.find('[')?;
+    let role = text[open + 1..].strip_suffix(']')?;
+    let state = &text[..open];
+    if state.is_empty() || role.is_empty() {
+        return None;
+    }
+    Some(Node::new(state.trim(), role.trim()))
+}
+
+/// Small xorshift generator, so that random rounds can be replayed from a seed
+#[derive(Debug, Clone)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // Zero is a fixed point of xorshift
+        Rng(seed.max(1))
+    }
+
+    /// Seed from the current time
+    pub fn from_time() -> Self {
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(1);
+        Rng::new(nanos)
+    }
+
+    /// Next index below `bound`, which must be non-zero
+    pub fn below(&mut self, bound: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % bound as u64) as usize
+    }
+}
+
+/// Position of a training round in the graph
+#[derive(Debug, Clone)]
+pub struct Trainer<'a> {
+    graph: &'a MartialGraph,
+    current: Node,
+    /// Edges taken so far, in order
+    pub history: Vec<&'a Edge>,
+}
+
+impl<'a> Trainer<'a> {
+    /// Start at `start`, or `None` if the node is not part of the graph
+    pub fn new(graph: &'a MartialGraph, start: Node) -> Option<Self> {
+        if !graph.nodes.contains(&start) {
+            return None;
+        }
+        Some(Trainer {
+            graph,
+            current: start,
+            history: Vec::new(),
+        })
+    }
+
+    pub fn current(&self) -> &Node {
+        &self.current
+    }
+
+    /// Outgoing actions of the current position, sorted by action and target
+    ///
+    /// The opponent's side of a step is not an option, as it is not an action
+    /// taken from the position.
+    pub fn options(&self) -> Vec<&'a Edge> {
+        let mut options: Vec<&Edge> = self
+            .graph
+            .edges
+            .iter()
+            .filter(|e| e.from == self.current && !e.reaction)
+            .collect();
+        options.sort_by_key(|e| (e.action.clone(), e.to.id(), e.sequence.clone()));
+        options
+    }
+
+    /// Take the `index`-th option, returning the edge followed
+    pub fn choose(&mut self, index: usize) -> Option<&'a Edge> {
+        let edge = *self.options().get(index)?;
+        self.current = edge.to.clone();
+        self.history.push(edge);
+        Some(edge)
+    }
+
+    /// Go back to the previous position, if any
+    pub fn back(&mut self) -> bool {
+        match self.history.pop() {
+            Some(edge) => {
+                self.current = edge.from.clone();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+fn print_options(output: &mut impl Write, trainer: &Trainer) -> io::Result<()> {
+    writeln!(output, "\nPosition: {}", trainer.current().id())?;
+    for (i, edge) in trainer.options().iter().enumerate() {
+        match &edge.turn {
+            Some(turn) => writeln!(output, "  {}. {} -> {} ({}, {}'s turn)", i + 1, edge.action, edge.to.id(), edge.sequence, turn)?,
+            None => writeln!(output, "  {}. {} -> {} ({})", i + 1, edge.action, edge.to.id(), edge.sequence)?,
+        }
+    }
+    Ok(())
+}
+
+fn print_summary(output: &mut impl Write, trainer: &Trainer) -> io::Result<()> {
+    writeln!(output, "\nFlow ({} actions):", trainer.history.len())?;
+    for edge in &trainer.history {
+        writeln!(output, "  {} -> {}: {}", edge.from.id(), edge.to.id(), edge.action)?;
+    }
+    Ok(())
+}
+
+/// Run an interactive round
+///
+/// Reads one command per line: an option number, `r` for a random option,
+/// `b` to go back and `q` to quit. Ends at a dead end or end of input.
+pub fn interactive(trainer: &mut Trainer, rng: &mut Rng, input: impl BufRead, mut output: impl Write) -> io::Result<()> {
+    let mut lines = input.lines();
+    loop {
+        print_options(&mut output, trainer)?;
+        let count = trainer.options().len();
+        if count == 0 {
+            writeln!(output, "  No actions from here.")?;
+            break;
+        }
+        write!(output, "Choose [1-{}, r=random, b=back, q=quit]: ", count)?;
+        output.flush()?;
+
+        let line = match lines.next() {
+            Some(line) => line?,
+            None => break,
+        };
+        match line.trim() {
+            "q" => break,
+            "r" => {
+                trainer.choose(rng.below(count));
+            }
+            "b" => {
+                if !trainer.back() {
+                    writeln!(output, "  Already at the start.")?;
+                }
+            }
+            choice => match choice.parse::<usize>() {
+                Ok(n) if (1..=count).contains(&n) => {
+                    trainer.choose(n - 1);
+                }
+                _ => writeln!(output, "  Unknown choice '{}'", choice)?,
+            },
+        }
+    }
+    print_summary(&mut output, trainer)
+}
+
+/// Walk up to `steps` random actions without prompting
+pub fn random_walk(trainer: &mut Trainer, rng: &mut Rng, steps: usize, mut output: impl Write) -> io::Result<()> {
+    for _ in 0..steps {
+        let count = trainer.options().len();
+        if count == 0 {
+            break;
+        }
+        trainer.choose(rng.below(count));
+    }
+    print_summary(&mut output, trainer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loader::{load_sources, SourceFile};
+
+    fn graph() -> MartialGraph {
+        let source = "roles { Top, Bottom }\n\
+                      state Guard\nstate Mount\nstate Back\n\
+                      sequence Sweep:\n    Scissor: Guard[Bottom] -> Mount[Top]\n\
+                      sequence Take:\n    Roll: Mount[Top] -> Back[Top]\n\
+                      sequence Pass:\n    Knee: Guard[Top] -> Mount[Top]";
+        let system = load_sources("test", &[SourceFile::new("a.martial", source)]).unwrap();
+        MartialGraph::from_system(&system)
+    }
+
+    #[test]
+    fn test_parse_node() {
+        assert_eq!(parse_node("Guard[Bottom]"), Some(Node::new("Guard", "Bottom")));
+        assert_eq!(parse_node("Guard"), None);
+        assert_eq!(parse_node("[Top]"), None);
+    }
+
+    #[test]
+    fn test_interactive_round() {
+        let graph = graph();
+        let start = parse_node("Guard[Bottom]").unwrap();
+        let mut trainer = Trainer::new(&graph, start).unwrap();
+        let mut output = Vec::new();
+        interactive(&mut trainer, &mut Rng::new(1), "9\n1\nb\n1\n1\n".as_bytes(), &mut output).unwrap();
+
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("1. Scissor -> Mount[Top] (Sweep)"));
+        assert!(output.contains("Unknown choice '9'"));
+        assert!(output.contains("No actions from here."));
+        assert_eq!(trainer.current().id(), "Back[Top]");
+        assert_eq!(trainer.history.len(), 2);
+    }
+
+    #[test]
+    fn test_random_walk() {
+        let graph = graph();
+        assert!(Trainer::new(&graph, parse_node("Nowhere[Top]").unwrap()).is_none());
+
+        let mut trainer = Trainer::new(&graph, parse_node("Guard[Top]").unwrap()).unwrap();
+        random_walk(&mut trainer, &mut Rng::new(7), 10, io::sink()).unwrap();
+        let actions: Vec<&str> = trainer.history.iter().map(|e| e.action.as_str()).collect();
+        assert_eq!(actions, vec!["Knee", "Roll"]);
+    }
+}
@@ -0,0 +1,508 @@
+//! Terminal explorer of `mat tui`
+//!
+//! Three panes list the positions of the graph, the sequences, and the
+//! transitions into and out of the selected position. A filter typed after
+//! `/` narrows the panes as it is typed: `role:`, `group:` and `tag:` terms
+//! match on roles, groups and sequence tags, and other words on names.
+//! Opening a sequence marks the positions it goes through, which `n` and `p`
+//! step through, and opening a transition selects the position at its other
+//! end.
+//!
+//! [`Explorer`] holds the state of the interface and is driven by [`Key`]s,
+//! so it runs without a terminal; [`run`] draws it with ratatui.
+
+use crate::ast::Sequence;
+use crate::graph::{Edge, MartialGraph, Node};
+use crate::semantic::MartialSystem;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, List, ListItem, ListState, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+use std::io;
+
+/// A pane of the explorer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pane {
+    States,
+    Sequences,
+    Adjacency,
+}
+
+impl Pane {
+    const ALL: [Pane; 3] = [Pane::States, Pane::Sequences, Pane::Adjacency];
+}
+
+/// Keys the explorer responds to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    Up,
+    Down,
+    Tab,
+    BackTab,
+    Enter,
+    Esc,
+    Backspace,
+    Char(char),
+}
+
+/// State of the explorer
+pub struct Explorer<'a> {
+    system: &'a MartialSystem,
+    graph: MartialGraph,
+    pub focus: Pane,
+    /// Selected row of each pane, in the order of [`Pane::ALL`]
+    selected: [usize; 3],
+    /// The filter, as typed
+    pub filter: String,
+    /// Whether keys are typed into the filter
+    pub editing: bool,
+    /// Sequence opened last, and the positions it goes through in order
+    pub opened: Option<(String, Vec<Node>)>,
+    /// Message of the last action, if it has one
+    pub status: Option<String>,
+}
+
+impl<'a> Explorer<'a> {
+    pub fn new(system: &'a MartialSystem) -> Self {
+        Explorer {
+            system,
+            graph: MartialGraph::from_system(system),
+            focus: Pane::States,
+            selected: [0; 3],
+            filter: String::new(),
+            editing: false,
+            opened: None,
+            status: None,
+        }
+    }
+
+    /// Positions matching the filter
+    pub fn states(&self) -> Vec<&Node> {
+        self.graph.nodes.iter().filter(|node| self.terms().all(|term| self.node_matches(node, term))).collect()
+    }
+
+    /// Sequences matching the filter
+    pub fn sequences(&self) -> Vec<&'a Sequence> {
+        self.system
+            .sequences
+            .values()
+            .filter(|sequence| self.terms().all(|term| self.sequence_matches(sequence, term)))
+            .collect()
+    }
+
+    /// Transitions out of the selected position, then into it, each with
+    /// whether it leaves the position
+    pub fn adjacency(&self) -> Vec<(&Edge, bool)> {
+        let Some(node) = self.selected_state() else {
+            return Vec::new();
+        };
+        let outgoing = self.graph.edges.iter().filter(|e| e.from == *node).map(|e| (e, true));
+        let incoming = self.graph.edges.iter().filter(|e| e.to == *node).map(|e| (e, false));
+        outgoing.chain(incoming).collect()
+    }
+
+    pub fn selected_state(&self) -> Option<&Node> {
+        self.states().get(self.selected[0]).copied()
+    }
+
+    pub fn selected_sequence(&self) -> Option<&'a Sequence> {
+        self.sequences().get(self.selected[1]).copied()
+    }
+
+    /// Selected row of `pane`
+    pub fn selected(&self, pane: Pane) -> usize {
+        self.selected[pane as usize]
+    }
+
+    /// Handle `key`; `false` once the explorer should close
+    pub fn handle(&mut self, key: Key) -> bool {
+        self.status = None;
+        if self.editing {
+            match key {
+                Key::Char(c) => self.filter.push(c),
+                Key::Backspace => {
+                    self.filter.pop();
+                }
+                Key::Enter => self.editing = false,
+                Key::Esc => {
+                    self.filter.clear();
+                    self.editing = false;
+                }
+                _ => return true,
+            }
+            self.selected = [0; 3];
+            return true;
+        }
+
+        match key {
+            Key::Char('q') => return false,
+            Key::Char('/') => self.editing = true,
+            Key::Esc => {
+                self.filter.clear();
+                self.opened = None;
+                self.selected = [0; 3];
+            }
+            Key::Tab => self.focus = Pane::ALL[(self.focus as usize + 1) % 3],
+            Key::BackTab => self.focus = Pane::ALL[(self.focus as usize + 2) % 3],
+            Key::Up | Key::Char('k') => self.move_by(-1),
+            Key::Down | Key::Char('j') => self.move_by(1),
+            Key::Char('n') => self.step_opened(1),
+            Key::Char('p') => self.step_opened(-1),
+            Key::Enter => self.open(),
+            _ => {}
+        }
+        true
+    }
+
+    fn rows(&self, pane: Pane) -> usize {
+        match pane {
+            Pane::States => self.states().len(),
+            Pane::Sequences => self.sequences().len(),
+            Pane::Adjacency => self.adjacency().len(),
+        }
+    }
+
+    fn move_by(&mut self, delta: isize) {
+        let rows = self.rows(self.focus);
+        let selected = &mut self.selected[self.focus as usize];
+        *selected = selected.saturating_add_signed(delta).min(rows.saturating_sub(1));
+        if self.focus == Pane::States {
+            self.selected[Pane::Adjacency as usize] = 0;
+        }
+    }
+
+    /// Open the selection of the focused pane
+    fn open(&mut self) {
+        match self.focus {
+            Pane::States => self.focus = Pane::Adjacency,
+            Pane::Sequences => {
+                let Some(sequence) = self.selected_sequence() else {
+                    return;
+                };
+                let mut positions: Vec<Node> = Vec::new();
+                for edge in self.graph.edges.iter().filter(|e| e.sequence == sequence.name.as_str() && !e.reaction) {
+                    for node in [&edge.from, &edge.to] {
+                        if !positions.contains(node) {
+                            positions.push(node.clone());
+                        }
+                    }
+                }
+                self.opened = Some((sequence.name.clone(), positions));
+                self.focus = Pane::States;
+                self.step_opened(0);
+            }
+            Pane::Adjacency => {
+                let Some(&(edge, outgoing)) = self.adjacency().get(self.selected[2]) else {
+                    return;
+                };
+                let target = if outgoing { edge.to.clone() } else { edge.from.clone() };
+                if self.select(&target) {
+                    self.focus = Pane::States;
+                }
+            }
+        }
+    }
+
+    /// Select the position of the opened sequence `delta` after the selected
+    /// one, or its first position if the selection is not one of them
+    fn step_opened(&mut self, delta: isize) {
+        let Some((_, positions)) = &self.opened else {
+            self.status = Some("Open a sequence first".to_string());
+            return;
+        };
+        let current = self.selected_state().and_then(|node| positions.iter().position(|p| p == node));
+        let next = match current {
+            Some(i) if delta != 0 => (i as isize + delta).rem_euclid(positions.len() as isize) as usize,
+            _ => 0,
+        };
+        if let Some(target) = positions.get(next).cloned() {
+            self.select(&target);
+        }
+    }
+
+    /// Select `node` in the states pane, if the filter shows it
+    fn select(&mut self, node: &Node) -> bool {
+        match self.states().iter().position(|n| *n == node) {
+            Some(i) => {
+                self.selected[0] = i;
+                self.selected[2] = 0;
+                true
+            }
+            None => {
+                self.status = Some(format!("{} is hidden by the filter", node.id()));
+                false
+            }
+        }
+    }
+
+    /// Lowercase terms of the filter
+    fn terms(&self) -> impl Iterator<Item = String> + '_ {
+        self.filter.split_whitespace().map(str::to_lowercase)
+    }
+
+    fn node_matches(&self, node: &Node, term: String) -> bool {
+        let contains = |text: &str, part: &str| text.to_lowercase().contains(part);
+        match term.split_once(':') {
+            Some(("role", role)) => contains(&node.role, role),
+            Some(("group", group)) => self
+                .system
+                .groups
+                .iter()
+                .any(|(name, states)| contains(name, group) && states.iter().any(|s| *s == node.state.as_str())),
+            Some(("tag", tag)) => self.system.sequences.values().any(|sequence| {
+                sequence.tags().iter().any(|t| contains(t, tag))
+                    && self.graph.edges.iter().any(|e| e.sequence == sequence.name.as_str() && (e.from == *node || e.to == *node))
+            }),
+            _ => contains(&node.state, &term),
+        }
+    }
+
+    fn sequence_matches(&self, sequence: &Sequence, term: String) -> bool {
+        let contains = |text: &str, part: &str| text.to_lowercase().contains(part);
+        let refs = || sequence.steps.iter().flat_map(|step| [&step.from, &step.to]);
+        match term.split_once(':') {
+            Some(("role", role)) => refs().any(|r| contains(&r.role, role)),
+            Some(("group", group)) => self
+                .system
+                .groups
+                .iter()
+                .any(|(name, states)| contains(name, group) && refs().any(|r| states.iter().any(|s| *s == r.state.as_str()))),
+            Some(("tag", tag)) => sequence.tags().iter().any(|t| contains(t, tag)),
+            _ => contains(&sequence.name, &term),
+        }
+    }
+}
+
+/// Draw `explorer` on `frame`
+pub fn draw(frame: &mut Frame, explorer: &Explorer) {
+    let [main, footer] = Layout::vertical([Constraint::Min(3), Constraint::Length(1)]).areas(frame.area());
+    let panes: [_; 3] = Layout::horizontal([Constraint::Percentage(30), Constraint::Percentage(30), Constraint::Percentage(40)])
+        .areas(main);
+
+    let opened = explorer.opened.as_ref();
+    let states: Vec<ListItem> = explorer
+        .states()
+        .into_iter()
+        .map(|node| {
+            let item = ListItem::new(node.id());
+            match opened {
+                Some((_, positions)) if positions.contains(node) => item.style(Style::new().add_modifier(Modifier::BOLD)),
+                _ => item,
+            }
+        })
+        .collect();
+    let sequences: Vec<ListItem> = explorer
+        .sequences()
+        .into_iter()
+        .map(|sequence| match sequence.kind() {
+            Some(kind) => ListItem::new(format!("{} ({})", sequence.name, kind)),
+            None => ListItem::new(sequence.name.clone()),
+        })
+        .collect();
+    let adjacency: Vec<ListItem> = explorer
+        .adjacency()
+        .into_iter()
+        .map(|(edge, outgoing)| {
+            let text = if outgoing {
+                format!("→ {} → {}", edge.action, edge.to.id())
+            } else {
+                format!("← {} ← {}", edge.from.id(), edge.action)
+            };
+            ListItem::new(format!("{}  ({})", text, edge.sequence))
+        })
+        .collect();
+
+    let adjacency_title = match explorer.selected_state() {
+        Some(node) => format!(" {} ", node.id()),
+        None => " Transitions ".to_string(),
+    };
+    let states_title = match opened {
+        Some((name, _)) => format!(" States · {} ", name),
+        None => " States ".to_string(),
+    };
+    let titles = [states_title, " Sequences ".to_string(), adjacency_title];
+    for (((pane, area), items), title) in Pane::ALL.into_iter().zip(panes).zip([states, sequences, adjacency]).zip(titles) {
+        let mut block = Block::bordered().title(title);
+        if explorer.focus == pane {
+            block = block.border_style(Style::new().add_modifier(Modifier::BOLD));
+        }
+        let list = List::new(items).block(block).highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+        let mut state = ListState::default().with_selected(Some(explorer.selected(pane)));
+        frame.render_stateful_widget(list, area, &mut state);
+    }
+
+    let footer_text = if explorer.editing {
+        format!("/{}█", explorer.filter)
+    } else if let Some(status) = &explorer.status {
+        status.clone()
+    } else if !explorer.filter.is_empty() {
+        format!("Filter: {}  (Esc clears)", explorer.filter)
+    } else {
+        "Tab pane · ↑↓ move · Enter open · / filter (role: group: tag:) · n/p opened sequence · q quit".to_string()
+    };
+    frame.render_widget(Paragraph::new(Line::from(footer_text)), footer);
+}
+
+/// Run the explorer in the terminal until it is closed
+pub fn run(system: &MartialSystem) -> io::Result<()> {
+    let mut terminal = ratatui::init();
+    let result = event_loop(&mut terminal, Explorer::new(system));
+    ratatui::restore();
+    result
+}
+
+fn event_loop(terminal: &mut DefaultTerminal, mut explorer: Explorer) -> io::Result<()> {
+    loop {
+        terminal.draw(|frame| draw(frame, &explorer))?;
+        let Event::Key(event) = event::read()? else {
+            continue;
+        };
+        if event.kind != KeyEventKind::Press {
+            continue;
+        }
+        if event.code == KeyCode::Char('c') && event.modifiers.contains(KeyModifiers::CONTROL) {
+            return Ok(());
+        }
+        let key = match event.code {
+            KeyCode::Up => Key::Up,
+            KeyCode::Down => Key::Down,
+            KeyCode::Tab => Key::Tab,
+            KeyCode::BackTab => Key::BackTab,
+            KeyCode::Enter => Key::Enter,
+            KeyCode::Esc => Key::Esc,
+            KeyCode::Backspace => Key::Backspace,
+            KeyCode::Char(c) => Key::Char(c),
+            _ => continue,
+        };
+        if !explorer.handle(key) {
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loader::{load_sources, SourceFile};
+    use ratatui::backend::TestBackend;
+    use ratatui::Terminal;
+
+    fn system() -> MartialSystem {
+        let source = "roles { Top, Bottom }\nstate Guard\nstate Mount\nstate Back\n\
+                      group Ground { Guard, Mount }\n\
+                      sequence Sweep @tag(sweep):\n    Hip: Guard[Bottom] -> Mount[Top]\n\
+                      sequence Take:\n    Roll: Mount[Top] -> Back[Top]";
+        load_sources("club", &[SourceFile::new("a.martial", source)]).unwrap()
+    }
+
+    fn ids(nodes: Vec<&Node>) -> Vec<String> {
+        nodes.into_iter().map(Node::id).collect()
+    }
+
+    fn typed(explorer: &mut Explorer, text: &str) {
+        explorer.handle(Key::Char('/'));
+        for c in text.chars() {
+            explorer.handle(Key::Char(c));
+        }
+    }
+
+    #[test]
+    fn test_filter() {
+        let system = system();
+        let mut explorer = Explorer::new(&system);
+        assert_eq!(ids(explorer.states()), ["Back[Top]", "Guard[Bottom]", "Mount[Top]"]);
+
+        typed(&mut explorer, "role:bot");
+        assert_eq!(ids(explorer.states()), ["Guard[Bottom]"]);
+        assert_eq!(explorer.sequences().len(), 1);
+        explorer.handle(Key::Esc);
+        assert!(explorer.filter.is_empty() && !explorer.editing);
+
+        typed(&mut explorer, "group:ground");
+        assert_eq!(ids(explorer.states()), ["Guard[Bottom]", "Mount[Top]"]);
+        assert_eq!(explorer.sequences().len(), 2);
+        explorer.handle(Key::Backspace);
+        assert_eq!(explorer.filter, "group:groun");
+        explorer.handle(Key::Esc);
+
+        typed(&mut explorer, "tag:sweep");
+        explorer.handle(Key::Enter);
+        assert!(!explorer.editing);
+        assert_eq!(ids(explorer.states()), ["Guard[Bottom]", "Mount[Top]"]);
+        assert_eq!(explorer.sequences()[0].name, "Sweep");
+        assert_eq!(explorer.sequences().len(), 1);
+    }
+
+    #[test]
+    fn test_navigation() {
+        let system = system();
+        let mut explorer = Explorer::new(&system);
+        explorer.handle(Key::Down);
+        explorer.handle(Key::Down);
+        explorer.handle(Key::Down);
+        assert_eq!(explorer.selected_state().unwrap().id(), "Mount[Top]");
+        let adjacency: Vec<(String, bool)> = explorer.adjacency().iter().map(|(e, out)| (e.action.to_string(), *out)).collect();
+        assert_eq!(adjacency, [("Roll".to_string(), true), ("Hip".to_string(), false)]);
+
+        // Following the incoming transition selects where it comes from
+        explorer.handle(Key::Enter);
+        assert_eq!(explorer.focus, Pane::Adjacency);
+        explorer.handle(Key::Down);
+        explorer.handle(Key::Enter);
+        assert_eq!(explorer.focus, Pane::States);
+        assert_eq!(explorer.selected_state().unwrap().id(), "Guard[Bottom]");
+
+        explorer.handle(Key::BackTab);
+        assert_eq!(explorer.focus, Pane::Adjacency);
+        explorer.handle(Key::Tab);
+        explorer.handle(Key::Char('n'));
+        assert_eq!(explorer.status.as_deref(), Some("Open a sequence first"));
+        assert!(!explorer.handle(Key::Char('q')));
+    }
+
+    #[test]
+    fn test_open_sequence() {
+        let system = system();
+        let mut explorer = Explorer::new(&system);
+        explorer.handle(Key::Tab);
+        explorer.handle(Key::Down);
+        assert_eq!(explorer.selected_sequence().unwrap().name, "Take");
+        explorer.handle(Key::Enter);
+        assert_eq!(explorer.focus, Pane::States);
+        assert_eq!(explorer.selected_state().unwrap().id(), "Mount[Top]");
+        explorer.handle(Key::Char('n'));
+        assert_eq!(explorer.selected_state().unwrap().id(), "Back[Top]");
+        explorer.handle(Key::Char('n'));
+        assert_eq!(explorer.selected_state().unwrap().id(), "Mount[Top]");
+        explorer.handle(Key::Char('p'));
+        assert_eq!(explorer.selected_state().unwrap().id(), "Back[Top]");
+
+        typed(&mut explorer, "role:bottom");
+        explorer.handle(Key::Enter);
+        explorer.handle(Key::Char('n'));
+        assert_eq!(explorer.status.as_deref(), Some("Mount[Top] is hidden by the filter"));
+    }
+
+    #[test]
+    fn test_draw() {
+        let system = system();
+        let mut explorer = Explorer::new(&system);
+        explorer.handle(Key::Down);
+        let mut terminal = Terminal::new(TestBackend::new(100, 10)).unwrap();
+        terminal.draw(|frame| draw(frame, &explorer)).unwrap();
+        let screen: String = terminal
+            .backend()
+            .buffer()
+            .content()
+            .chunks(100)
+            .map(|row| row.iter().map(|cell| cell.symbol()).collect::<String>() + "\n")
+            .collect();
+        assert!(screen.contains(" States "), "{}", screen);
+        assert!(screen.contains("Sweep"), "{}", screen);
+        assert!(screen.contains(" Guard[Bottom] "), "{}", screen);
+        assert!(screen.contains("→ Hip → Mount[Top]  (Sweep)"), "{}", screen);
+        assert!(screen.contains("q quit"), "{}", screen);
+    }
+}
@@ -0,0 +1,111 @@
+//! Browser bindings for the Martial DSL
+//!
+//! Exposes validation and graph generation over JSON strings so that a web
+//! playground can check `.martial` content client-side. Files are passed as
+//! a JSON array of `{ "path": ..., "content": ... }` objects; nothing here
+//! touches the filesystem.
+//!
+//! The functions are plain Rust and are exported to JavaScript with
+//! `wasm-bindgen` when the `wasm` feature is enabled.
+
+use crate::graph::MartialGraph;
+use crate::lexer::Position;
+use crate::loader::{self, LoadError, SourceFile};
+use serde::Serialize;
+
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+/// Name given to systems validated in the browser
+const SYSTEM_NAME: &str = "playground";
+
+/// A problem reported back to the browser
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Diagnostic {
+    pub file: Option<String>,
+    pub position: Option<Position>,
+    pub message: String,
+    /// Full error, tagged with its `kind` (lex, parse, semantic...)
+    pub error: LoadError,
+}
+
+impl From<LoadError> for Diagnostic {
+    fn from(error: LoadError) -> Self {
+        Diagnostic {
+            file: error.file().map(|f| f.to_string()),
+            position: error.position(),
+            message: error.message(),
+            error,
+        }
+    }
+}
+
+fn parse_files(files_json: &str) -> Result<Vec<SourceFile>, String> {
+    serde_json::from_str(files_json).map_err(|e| format!("Invalid files JSON: {}", e))
+}
+
+/// Validate a set of files and return a JSON array of diagnostics
+///
+/// An empty array means the system is valid.
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+pub fn validate_source(files_json: &str) -> Result<String, String> {
+    let sources = parse_files(files_json)?;
+    let (_, errors) = loader::check_sources(SYSTEM_NAME, &sources);
+    let diagnostics: Vec<Diagnostic> = errors.into_iter().map(Diagnostic::from).collect();
+    serde_json::to_string(&diagnostics).map_err(|e| e.to_string())
+}
+
+/// Build the transition graph of a set of files as JSON
+///
+/// Fails with a JSON array of diagnostics if the system is invalid.
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+pub fn graph_json(files_json: &str) -> Result<String, String> {
+    let sources = parse_files(files_json)?;
+    let (_, errors) = loader::check_sources(SYSTEM_NAME, &sources);
+    if !errors.is_empty() {
+        let diagnostics: Vec<Diagnostic> = errors.into_iter().map(Diagnostic::from).collect();
+        return Err(serde_json::to_string(&diagnostics).map_err(|e| e.to_string())?);
+    }
+
+    let system = loader::load_sources(SYSTEM_NAME, &sources).map_err(|e| e.to_string())?;
+    MartialGraph::from_system(&system)
+        .to_json()
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    fn files(sequence: &str) -> String {
+        json!([
+            { "path": "roles.martial", "content": "roles { Top, Bottom }" },
+            { "path": "states.martial", "content": "state Mount\nstate Guard" },
+            { "path": "sequences.martial", "content": sequence }
+        ])
+        .to_string()
+    }
+
+    #[test]
+    fn test_validate_source_reports_diagnostics() {
+        let valid = validate_source(&files("sequence S:\n    Shrimp: Mount[Bottom] -> Guard[Bottom]")).unwrap();
+        assert_eq!(valid, "[]");
+
+        let invalid = validate_source(&files("sequence S:\n    Shrimp: Mount[Bottom] -> Side[Bottom]")).unwrap();
+        let diagnostics: Value = serde_json::from_str(&invalid).unwrap();
+        assert_eq!(diagnostics[0]["file"], "sequences.martial");
+        assert_eq!(diagnostics[0]["position"]["line"], 2);
+        assert_eq!(diagnostics[0]["error"]["kind"], "semantic");
+    }
+
+    #[test]
+    fn test_graph_json() {
+        let graph = graph_json(&files("sequence S:\n    Shrimp: Mount[Bottom] -> Guard[Bottom]")).unwrap();
+        let graph: Value = serde_json::from_str(&graph).unwrap();
+        assert_eq!(graph["edges"].as_array().unwrap().len(), 1);
+
+        assert!(graph_json(&files("sequence S:\n    Shrimp")).is_err());
+        assert!(graph_json("not json").unwrap_err().starts_with("Invalid files JSON"));
+    }
+}
@@ -56,6 +56,7 @@ fn parse_martial_system(dir_path: &str) -> Result<martial_lang::semantic::Martia
     // Validate the complete system
     validator
         .validate(system_name)
+        .map(|report| report.system)
         .map_err(|e| format!("Validation error: {}", e))
 }
 
@@ -860,3 +861,92 @@ fn test_empty_directory() {
     // Clean up
     fs::remove_dir(temp_dir).ok();
 }
+
+#[test]
+fn test_levels_fixture() {
+    let system = parse_martial_system("tests/fixtures/valid_levels").unwrap();
+    assert_eq!(system.levels, vec!["WhiteBelt", "BlueBelt", "PurpleBelt"]);
+    assert_eq!(system.states["BackControl"].level(), Some("BlueBelt"));
+
+    let blue = system.at_level("BlueBelt").unwrap();
+    assert_eq!(blue.states.len(), 3);
+    assert!(!blue.states.contains_key("Berimbolo"));
+    assert_eq!(blue.sequences.len(), 2);
+    assert!(!blue.sequences.contains_key("InvertToBack"));
+
+    let graph = martial_lang::graph::MartialGraph::from_system(&blue);
+    assert_eq!(graph.edges.len(), 2);
+}
+
+#[test]
+fn test_fixture_expectations() {
+    let fixtures = martial_lang::fixture::discover(Path::new("tests/fixtures")).unwrap();
+    assert!(fixtures.len() >= 4);
+    for fixture in &fixtures {
+        let result = martial_lang::fixture::run(fixture);
+        assert!(result.passed(), "{}: {:?}", result.name, result.failures);
+    }
+}
+
+#[test]
+fn test_validate_command_lints() {
+    let root = std::env::temp_dir().join(format!("martial-cli-lints-{}", std::process::id()));
+    fs::create_dir_all(&root).unwrap();
+    fs::write(
+        root.join("loop.martial"),
+        "roles { Tori, Uke }\nstate Kamae\nstate Uchi\nstate Idle\n\
+         sequence Drill:\n    Step: Kamae[Tori] -> Uchi[Tori]\n    Return: Uchi[Tori] -> Kamae[Tori]\n",
+    )
+    .unwrap();
+    let validate = || std::process::Command::new(env!("CARGO_BIN_EXE_mat")).arg("validate").arg(&root).output().unwrap();
+
+    // Opt-in lints run when the project enables them
+    fs::write(root.join("martial.toml"), "lints = [\"linear-sequence\", \"final-state\"]\n").unwrap();
+    let output = validate();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{}", stderr);
+    assert!(stderr.contains("Sequence Drill is not linear"), "{}", stderr);
+    assert!(stderr.contains("No state is marked @final"), "{}", stderr);
+    assert!(!stderr.contains("State Idle is not used"), "{}", stderr);
+
+    // Denied findings fail validation
+    fs::write(root.join("martial.toml"), "lints = [\"final-state\"]\ndeny = [\"unused-state\"]\n").unwrap();
+    let output = validate();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(1), "{}", stderr);
+    assert!(stderr.contains("✗ 1 denied lint finding(s)"), "{}", stderr);
+    assert!(stderr.contains("State Idle is not used by any step"), "{}", stderr);
+    assert!(!String::from_utf8_lossy(&output.stdout).contains("is valid"));
+
+    fs::remove_dir_all(&root).ok();
+}
+
+#[test]
+fn test_import_command_keeps_existing_files() {
+    let root = std::env::temp_dir().join(format!("martial-cli-import-{}", std::process::id()));
+    let output = root.join("club");
+    fs::create_dir_all(&output).unwrap();
+    let csv = root.join("sweeps.csv");
+    fs::write(&csv, "sequence,action,from_state,from_role,to_state,to_role\nSweep,hip bump,closed guard,Bottom,mount,Top\n")
+        .unwrap();
+    fs::write(output.join("states.martial"), "state Kept\n").unwrap();
+    let import = |force: bool| {
+        let mut command = std::process::Command::new(env!("CARGO_BIN_EXE_mat"));
+        command.arg("import").arg(&csv).arg("-o").arg(&output);
+        if force {
+            command.arg("--force");
+        }
+        command.output().unwrap()
+    };
+
+    let refused = import(false);
+    assert_eq!(refused.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&refused.stderr).contains("use --force to overwrite"));
+    assert_eq!(fs::read_to_string(output.join("states.martial")).unwrap(), "state Kept\n");
+    assert!(!output.join("roles.martial").exists());
+
+    assert!(import(true).status.success());
+    assert!(fs::read_to_string(output.join("states.martial")).unwrap().contains("ClosedGuard"));
+
+    fs::remove_dir_all(&root).ok();
+}
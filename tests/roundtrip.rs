@@ -0,0 +1,183 @@
+//! Property-based round trip of the printer and parser
+//!
+//! Random ASTs are printed with [`printer::print_file`], parsed back and
+//! compared with [`MartialFile::same_declarations`]. Only syntax is
+//! exercised: the generated files need not be semantically valid.
+
+use martial_lang::ast::*;
+use martial_lang::lexer::{self, Position};
+use martial_lang::loader::{self, SourceFile};
+use martial_lang::metadata::FileMetadata;
+use martial_lang::printer;
+use martial_lang::symbol::Symbol;
+use proptest::collection::{btree_map, vec};
+use proptest::option;
+use proptest::prelude::*;
+
+fn identifier() -> impl Strategy<Value = String> {
+    "[A-Za-z_é][A-Za-z0-9_é]{0,8}".prop_filter("keywords are not identifiers", |word| lexer::keyword(word).is_none())
+}
+
+fn identifiers(max: usize) -> impl Strategy<Value = Vec<String>> {
+    vec(identifier(), 1..=max)
+}
+
+/// Any single-line text, as allowed in string literals
+fn text() -> impl Strategy<Value = String> {
+    "[^\\p{Cc}]{0,16}"
+}
+
+fn annotation() -> impl Strategy<Value = Annotation> {
+    let arg = prop_oneof![identifier(), "[0-9]{1,4}", text()];
+    (identifier(), vec(arg, 0..3), btree_map(identifier(), text(), 0..3)).prop_map(|(name, args, named)| Annotation {
+        name,
+        args,
+        named,
+        position: Position::default(),
+    })
+}
+
+fn annotations() -> impl Strategy<Value = Vec<Annotation>> {
+    vec(annotation(), 0..3)
+}
+
+fn state_ref() -> impl Strategy<Value = StateRef> {
+    (identifier(), identifier(), vec(identifier(), 0..3)).prop_map(|(state, role, extra_roles)| StateRef {
+        state: state.into(),
+        role: role.into(),
+        extra_roles: extra_roles.into_iter().map(Symbol::from).collect(),
+        spans: Spans::default(),
+    })
+}
+
+fn step() -> impl Strategy<Value = SequenceStep> {
+    let reaction = option::of((state_ref(), state_ref()).prop_map(|(from, to)| Reaction { from, to }));
+    (identifier(), state_ref(), state_ref(), option::of(state_ref()), reaction, annotations()).prop_map(
+        |(action_name, from, to, on_fail, reaction, annotations)| SequenceStep {
+            action_name: action_name.into(),
+            from,
+            to,
+            on_fail,
+            reaction,
+            annotations,
+            template: None,
+            repeat: None,
+            repetition: None,
+            position: Position::default(),
+            spans: Spans::default(),
+        },
+    )
+}
+
+fn declaration() -> impl Strategy<Value = Declaration> {
+    let position = Position::default();
+    prop_oneof![
+        identifiers(4).prop_map(move |roles| {
+            Declaration::Roles(RolesDecl {
+                roles,
+                position,
+                spans: Spans::default(),
+            })
+        }),
+        (identifier(), option::of(identifiers(3)), annotations()).prop_map(move |(name, allowed_roles, annotations)| {
+            Declaration::State(State {
+                name,
+                allowed_roles,
+                annotations,
+                position,
+                spans: Spans::default(),
+            })
+        }),
+        (identifier(), vec(step(), 1..4), annotations(), any::<bool>(), option::of((identifier(), identifier()))).prop_map(
+            move |(name, steps, annotations, mirrored, alternating)| {
+                Declaration::Sequence(Sequence {
+                    name,
+                    steps,
+                    invocations: Vec::new(),
+                    annotations,
+                    mirrored,
+                    alternating,
+                    mirror_of: None,
+                    position,
+                    spans: Spans::default(),
+                })
+            }
+        ),
+        (identifier(), identifiers(4)).prop_map(move |(name, states)| Declaration::Group(GroupDecl {
+            name,
+            states,
+            position,
+            spans: Spans::default(),
+        })),
+        identifiers(4).prop_map(move |levels| Declaration::Levels(LevelsDecl { levels, position })),
+        (identifier(), text()).prop_map(move |(name, definition)| Declaration::Term(TermDecl {
+            name,
+            definition,
+            position,
+            spans: Spans::default(),
+        })),
+        (identifier(), vec(identifier(), 0..4)).prop_map(move |(name, forbidden)| {
+            Declaration::Ruleset(RulesetDecl {
+                name,
+                forbidden,
+                position,
+                spans: Spans::default(),
+            })
+        }),
+        (prop_oneof![Just(MirrorKind::Roles), Just(MirrorKind::Suffixes)], vec((identifier(), identifier()), 1..3))
+            .prop_map(move |(kind, pairs)| {
+                Declaration::Mirror(MirrorDecl {
+                    kind,
+                    pairs,
+                    position,
+                    spans: Spans::default(),
+                })
+            }),
+        (prop_oneof![Just(AssertKind::Reachable), Just(AssertKind::NoPath)], state_ref(), state_ref()).prop_map(
+            move |(kind, from, to)| Declaration::Assert(AssertDecl {
+                kind,
+                from,
+                to,
+                position,
+            })
+        ),
+    ]
+}
+
+fn metadata() -> impl Strategy<Value = FileMetadata> {
+    // Extra keys must not be aliases of the known ones
+    let extra_key = "[a-z][a-z0-9_]{0,8}".prop_filter("known key", |key| {
+        !["author", "level", "belt", "belt_level", "reviewed", "last_reviewed"].contains(&key.as_str())
+    });
+    (option::of(text()), option::of(text()), option::of(text()), btree_map(extra_key, text(), 0..3)).prop_map(
+        |(author, level, reviewed, extra)| FileMetadata {
+            author,
+            level,
+            reviewed,
+            extra,
+        },
+    )
+}
+
+fn martial_file() -> impl Strategy<Value = MartialFile> {
+    (option::of(metadata()), 1..=LATEST_VERSION, vec(declaration(), 0..8)).prop_map(|(metadata, version, declarations)| {
+        MartialFile {
+            source: None,
+            metadata,
+            version,
+            declarations,
+            deprecations: Vec::new(),
+        }
+    })
+}
+
+proptest! {
+    #[test]
+    fn printed_files_parse_back(file in martial_file()) {
+        let printed = printer::print_file(&file);
+        let reparsed = loader::parse_source(&SourceFile::new("roundtrip.martial", printed.clone()))
+            .map_err(|e| TestCaseError::fail(format!("{}\n{}", e, printed)))?;
+        prop_assert!(reparsed.same_declarations(&file), "{}\n{:#?}", printed, reparsed);
+        prop_assert_eq!(printer::print_file(&reparsed), printed);
+    }
+}